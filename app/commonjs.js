@@ -0,0 +1,3 @@
+const { slugify } = require("./util");
+
+module.exports = (ctx) => ({ slug: slugify(ctx.headers["user-agent"] || "Hello World") });
@@ -0,0 +1,5 @@
+const res = await fetch("https://httpbin.org/get", { headers: { Accept: "application/json" } });
+const body = await res.json();
+console.log(res.status, body);
+
+export default { url: body.url };
@@ -0,0 +1,9 @@
+interface Greeting {
+    message: string;
+    length: number;
+}
+
+const message: string = "Hello from TypeScript";
+const greeting: Greeting = { message, length: message.length };
+
+export default greeting;
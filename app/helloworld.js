@@ -0,0 +1,2 @@
+log("serving", ctx.headers["accept"]);
+({ hello: "world", engine: "js" })
\ No newline at end of file
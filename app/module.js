@@ -0,0 +1,3 @@
+import { greet } from "./greeting.js";
+
+export default (ctx) => ({ message: greet(ctx.headers["user-agent"] || "stranger") });
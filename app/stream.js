@@ -0,0 +1,21 @@
+// Renders a page while the data for it arrives, try it with
+// `curl -N localhost:8080/js/stream.js`.
+const wait = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+export default {
+  fetch(request) {
+    const encoder = new TextEncoder();
+    const body = new ReadableStream({
+      async start(controller) {
+        controller.enqueue(encoder.encode("<!doctype html><ul>\n"));
+        for (const item of ["one", "two", "three"]) {
+          await wait(500);
+          controller.enqueue(encoder.encode(`<li>${item}</li>\n`));
+        }
+        controller.enqueue(encoder.encode("</ul>\n"));
+        controller.close();
+      },
+    });
+    return new Response(body, { headers: { "content-type": "text/html;charset=UTF-8" } });
+  },
+};
@@ -0,0 +1 @@
+exports.slugify = (s) => String(s).toLowerCase().replace(/[^a-z0-9]+/g, "-");
@@ -0,0 +1,8 @@
+export default {
+  async fetch(request, ctx) {
+    const agent = request.headers.get("user-agent") || "stranger";
+    return new Response(`Hello ${agent}, you sent a ${request.method} to ${request.url}\n`, {
+      headers: { "content-type": "text/plain;charset=UTF-8" },
+    });
+  },
+};
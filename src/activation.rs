@@ -0,0 +1,107 @@
+//! Sockets passed by systemd to a socket-activated service: `LISTEN_FDS`
+//! of them, from file descriptor 3 on, bound before the server started.
+//! With them systemd holds on to connections while the server restarts, and
+//! the server needs no privileges for low ports.
+
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+
+/// The first descriptor systemd passes, after stdin, stdout and stderr.
+const FIRST_FD: RawFd = 3;
+
+pub enum Activated {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// The sockets systemd passed to this process, none if it was not socket
+/// activated. The variables are removed so children don't take them too.
+pub fn sockets() -> io::Result<Vec<Activated>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+    // Sockets meant for another process, which exec'd this one.
+    if pid.parse() != Ok(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let fds: RawFd = fds.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("LISTEN_FDS is not a number: {}", fds),
+        )
+    })?;
+    (FIRST_FD..FIRST_FD + fds).map(socket).collect()
+}
+
+/// The listener of `fd`, which systemd bound to a TCP address or a path.
+fn socket(fd: RawFd) -> io::Result<Activated> {
+    // Safety: systemd passed `fd` to this process, nothing else owns it.
+    let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+    let activated = match tcp.local_addr() {
+        Ok(_) => Activated::Tcp(tcp),
+        Err(_) => {
+            let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+            unix.local_addr()?;
+            Activated::Unix(unix)
+        }
+    };
+    Ok(activated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn takes_the_passed_sockets() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let Activated::Tcp(tcp) = socket(tcp.into_raw_fd()).unwrap() else {
+            panic!("not a TCP socket");
+        };
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut got = [0; 2];
+        tcp.accept().unwrap().0.read_exact(&mut got).unwrap();
+        assert_eq!(&got, b"hi");
+
+        let path = std::env::temp_dir().join("rustvm_activated.sock");
+        let _ = std::fs::remove_file(&path);
+        let unix = UnixListener::bind(&path).unwrap();
+        let Activated::Unix(unix) = socket(unix.into_raw_fd()).unwrap() else {
+            panic!("not a Unix socket");
+        };
+        assert_eq!(
+            unix.local_addr().unwrap().as_pathname(),
+            Some(path.as_path())
+        );
+    }
+
+    #[test]
+    fn reads_the_variables_once() {
+        std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        std::env::set_var("LISTEN_FDS", "0");
+        assert!(sockets().unwrap().is_empty());
+        assert!(std::env::var("LISTEN_PID").is_err());
+
+        std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        std::env::set_var("LISTEN_FDS", "many");
+        let e = sockets().err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(std::env::var("LISTEN_FDS").is_err());
+
+        // Meant for the process that exec'd this one.
+        std::env::set_var("LISTEN_PID", "1");
+        std::env::set_var("LISTEN_FDS", "2");
+        assert!(sockets().unwrap().is_empty());
+        assert!(sockets().unwrap().is_empty());
+    }
+}
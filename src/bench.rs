@@ -0,0 +1,441 @@
+//! `rustvm bench`: load on a running app from a number of keep-alive
+//! connections, and the latency of each route it got. The compile cache hit
+//! rate comes from the dashboard of the app, `/admin?format=json` read with
+//! the token of `[admin]` before and after.
+//!
+//! Requests are plain HTTP/1.1 written by hand, so every connection is one
+//! socket kept for the whole run, like browsers and proxies keep theirs.
+
+use async_std::io::BufReader;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+use tide::http::Url;
+
+pub struct Options {
+    /// Where the app runs, `http://host:port`.
+    pub url: Url,
+    /// Routes requested in turn by every connection.
+    pub paths: Vec<String>,
+    pub connections: usize,
+    pub duration: Duration,
+    /// Token of the dashboard to read the compile cache from.
+    pub admin_token: Option<String>,
+}
+
+/// `30s`, `500ms`, `2m`, or seconds without a unit.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(at) => s.split_at(at),
+        None => (s, "s"),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {}", s))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("Unknown unit {} of {}, use ms, s or m", unit, s)),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// One keep-alive connection to the app, opened again after the app
+/// closes it.
+struct Connection {
+    /// `host:port`, connected to and sent as the `Host` header.
+    host: String,
+    stream: Option<BufReader<TcpStream>>,
+}
+
+impl Connection {
+    fn new(url: &Url) -> io::Result<Self> {
+        if url.scheme() != "http" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Only http:// can be benchmarked, not {}", url),
+            ));
+        }
+        let host = url.host_str().unwrap_or("127.0.0.1");
+        let port = url.port_or_known_default().unwrap_or(80);
+        Ok(Self {
+            host: format!("{}:{}", host, port),
+            stream: None,
+        })
+    }
+
+    /// The status and body `GET path` is answered with.
+    async fn get(&mut self, path: &str) -> io::Result<(u16, Vec<u8>)> {
+        self.send(path, "").await
+    }
+
+    /// `get` with the header lines of `headers`, each ending in CRLF.
+    async fn send(&mut self, path: &str, headers: &str) -> io::Result<(u16, Vec<u8>)> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => BufReader::new(TcpStream::connect(&self.host).await?),
+        };
+        let head = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rustvm-bench\r\n{}\r\n",
+            path, self.host, headers
+        );
+        let (status, body, keep_alive) = exchange(&mut stream, head.as_bytes()).await?;
+        if keep_alive {
+            self.stream = Some(stream);
+        }
+        Ok((status, body))
+    }
+}
+
+/// Sends `head` and reads the answer: its status, body and whether the
+/// connection stays open.
+async fn exchange(
+    stream: &mut BufReader<TcpStream>,
+    head: &[u8],
+) -> io::Result<(u16, Vec<u8>, bool)> {
+    stream.get_mut().write_all(head).await?;
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid(format!("Invalid status line {:?}", line)))?;
+    let (mut length, mut chunked, mut keep_alive) = (None, false, true);
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(invalid("The connection closed in the head".into()));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await?;
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| invalid(format!("Invalid chunk size {:?}", line)))?;
+            let start = body.len();
+            body.resize(start + size + 2, 0);
+            stream.read_exact(&mut body[start..]).await?;
+            body.truncate(start + size);
+            if size == 0 {
+                break;
+            }
+        }
+    } else if let Some(length) = length {
+        body.resize(length, 0);
+        stream.read_exact(&mut body).await?;
+    } else {
+        stream.read_to_end(&mut body).await?;
+        keep_alive = false;
+    }
+    Ok((status, body, keep_alive))
+}
+
+/// The latencies of one route.
+#[derive(Debug, Default)]
+pub struct Route {
+    pub path: String,
+    pub latencies: Vec<Duration>,
+    /// Answers with a status of 500 and above, and requests without one.
+    pub errors: usize,
+}
+
+impl Route {
+    /// The latency `p` percent of the requests were faster than, of sorted
+    /// latencies.
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+/// The counters of an engine bench reads from the dashboard.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Counters {
+    engine: String,
+    prefix: String,
+    runs: u64,
+    compiles: u64,
+}
+
+/// How often the scripts of an engine came from the cache during the run.
+#[derive(Debug)]
+pub struct CacheHits {
+    pub engine: String,
+    pub prefix: String,
+    pub runs: u64,
+    pub compiles: u64,
+}
+
+impl CacheHits {
+    pub fn rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        self.runs.saturating_sub(self.compiles) as f64 / self.runs as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub routes: Vec<Route>,
+    pub elapsed: Duration,
+    /// Empty without the token of the dashboard.
+    pub cache: Vec<CacheHits>,
+}
+
+/// What bench reads of the report of the dashboard.
+#[derive(Debug, Default, Deserialize)]
+struct AdminReport {
+    dirs: Vec<DirCounters>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DirCounters {
+    stats: Counters,
+}
+
+async fn counters(url: &Url, token: Option<&str>) -> Vec<Counters> {
+    let (Some(token), Ok(mut connection)) = (token, Connection::new(url)) else {
+        return Vec::new();
+    };
+    let authorization = format!("Authorization: Bearer {}\r\n", token);
+    match connection.send("/admin?format=json", &authorization).await {
+        Ok((200, body)) => serde_json::from_slice::<AdminReport>(&body)
+            .unwrap_or_default()
+            .dirs
+            .into_iter()
+            .map(|d| d.stats)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Requests the routes of `options` until its duration is over.
+pub async fn run(options: &Options) -> io::Result<Report> {
+    if options.paths.is_empty() || options.connections == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Nothing to request, give a route and at least one connection",
+        ));
+    }
+    let token = options.admin_token.as_deref();
+    let before = counters(&options.url, token).await;
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    let mut tasks = Vec::new();
+    for n in 0..options.connections {
+        let mut connection = Connection::new(&options.url)?;
+        let paths = options.paths.clone();
+        tasks.push(tide_rhai::rt::spawn(async move {
+            let mut routes: Vec<Route> = paths.iter().map(|_| Route::default()).collect();
+            // Connections start on different routes, so each gets its share.
+            let mut i = n;
+            while Instant::now() < deadline {
+                let route = i % paths.len();
+                i += 1;
+                let sent = Instant::now();
+                let answered = connection.get(&paths[route]).await;
+                let took = sent.elapsed();
+                match answered {
+                    Ok((status, _)) if status < 500 => routes[route].latencies.push(took),
+                    Ok(_) => routes[route].errors += 1,
+                    Err(e) => {
+                        routes[route].errors += 1;
+                        log::debug!("{}: {}", paths[route], e);
+                        // Not to spin on a refused connection.
+                        tide_rhai::rt::sleep(Duration::from_millis(10)).await;
+                    }
+                }
+            }
+            routes
+        }));
+    }
+
+    let mut routes: Vec<Route> = options
+        .paths
+        .iter()
+        .map(|p| Route {
+            path: p.clone(),
+            ..Route::default()
+        })
+        .collect();
+    for task in tasks {
+        for (total, route) in routes.iter_mut().zip(task.await) {
+            total.latencies.extend(route.latencies);
+            total.errors += route.errors;
+        }
+    }
+    let elapsed = start.elapsed();
+    for route in &mut routes {
+        route.latencies.sort();
+    }
+
+    let after = counters(&options.url, token).await;
+    let cache = after
+        .into_iter()
+        .map(|a| {
+            let b = before
+                .iter()
+                .find(|b| b.engine == a.engine && b.prefix == a.prefix)
+                .cloned()
+                .unwrap_or_default();
+            CacheHits {
+                runs: a.runs.saturating_sub(b.runs),
+                compiles: a.compiles.saturating_sub(b.compiles),
+                engine: a.engine,
+                prefix: a.prefix,
+            }
+        })
+        .filter(|c| c.runs > 0)
+        .collect();
+    Ok(Report {
+        routes,
+        elapsed,
+        cache,
+    })
+}
+
+fn ms(d: Duration) -> String {
+    format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.routes.iter().map(|r| r.path.len()).max().unwrap_or(0);
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            f,
+            "{:width$}  {:>8}  {:>8}  {:>6}  {:>9}  {:>9}  {:>9}  {:>9}",
+            "route", "requests", "req/s", "errors", "p50", "p90", "p99", "max",
+        )?;
+        for r in &self.routes {
+            let max = r.latencies.last().copied().unwrap_or_default();
+            writeln!(
+                f,
+                "{:width$}  {:>8}  {:>8.1}  {:>6}  {:>9}  {:>9}  {:>9}  {:>9}",
+                r.path,
+                r.latencies.len(),
+                r.latencies.len() as f64 / seconds,
+                r.errors,
+                ms(r.percentile(50.0)),
+                ms(r.percentile(90.0)),
+                ms(r.percentile(99.0)),
+                ms(max),
+            )?;
+        }
+        for c in &self.cache {
+            writeln!(
+                f,
+                "{} {}: {} runs, {} compiles, {:.1}% compile cache hits",
+                c.engine,
+                c.prefix,
+                c.runs,
+                c.compiles,
+                c.rate() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tide::{Body, Response};
+
+    #[test]
+    fn durations_and_percentiles() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+        assert!(parse_duration("5h").is_err());
+
+        let route = Route {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            ..Route::default()
+        };
+        assert_eq!(route.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(route.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(route.percentile(100.0), Duration::from_millis(100));
+    }
+
+    #[async_std::test]
+    async fn benchmarks_a_server() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let mut app = tide::new();
+        let r = runs.clone();
+        app.at("/hello").get(move |_| {
+            r.fetch_add(1, Ordering::Relaxed);
+            async { Ok::<_, tide::Error>("hello") }
+        });
+        app.at("/broken")
+            .get(|_| async { Ok::<_, tide::Error>(Response::new(500)) });
+        let r = runs.clone();
+        app.at("/admin").get(move |req: tide::Request<()>| {
+            let runs = r.load(Ordering::Relaxed);
+            let authorized = req.header("authorization").map(|h| h.as_str()) == Some("Bearer t0k");
+            let report = serde_json::json!({ "dirs": [
+                { "stats": {"engine": "rhai", "prefix": "/*", "runs": runs, "compiles": 1} }
+            ] });
+            async move {
+                match authorized {
+                    true => Ok::<Response, tide::Error>(Body::from_json(&report)?.into()),
+                    false => Ok(Response::new(401)),
+                }
+            }
+        });
+        let mut listener = app.bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&listener.info()[0].connection().to_string()).unwrap();
+        tide_rhai::rt::spawn(async move { listener.accept().await });
+
+        let report = run(&Options {
+            url,
+            paths: vec!["/hello".into(), "/broken".into()],
+            connections: 4,
+            duration: Duration::from_millis(300),
+            admin_token: Some("t0k".into()),
+        })
+        .await
+        .unwrap();
+        let hello = &report.routes[0];
+        assert!(!hello.latencies.is_empty());
+        assert_eq!(hello.errors, 0);
+        assert!(report.routes[1].latencies.is_empty());
+        assert!(report.routes[1].errors > 0);
+        assert_eq!(report.cache.len(), 1);
+        assert_eq!(report.cache[0].runs, hello.latencies.len() as u64);
+        assert!(report.to_string().contains("compile cache hits"));
+    }
+}
@@ -0,0 +1,1334 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use sha2::{Digest, Sha256};
+use tide_rhai::boa_engine::object::builtins::{JsArray, JsMap, JsUint8Array};
+use tide_rhai::boa_engine::object::ObjectInitializer;
+use tide_rhai::boa_engine::property::Attribute;
+use tide_rhai::boa_engine::{
+    Context, JsArgs, JsBigInt, JsNativeError, JsObject, JsResult, JsString, JsValue, NativeFunction,
+};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take},
+    character::complete::digit1,
+    combinator::{map, map_res},
+    multi::many0,
+    sequence::{delimited, pair, terminated},
+    IResult,
+}; // 7.1.1
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Bencode {
+    Number(i64),
+    ByteString(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+// examples:
+//  "4:spam" -> spam
+//  "5:hello" -> hello
+//  "10:technology" -> technology
+//  "2:hello" -> he
+// Bencode strings are not necessarily utf-8
+fn parse_string(bencode_bytes: &[u8]) -> IResult<&[u8], Vec<u8>> {
+    let (remaining, num_characters) = terminated(
+        map_res(digit1, |digits| {
+            String::from_utf8_lossy(digits).parse::<usize>()
+        }),
+        tag(":"),
+    )(bencode_bytes)?;
+
+    map(take(num_characters), |bytes: &[u8]| bytes.to_vec())(remaining)
+}
+
+// examples:
+//  "i3e" -> 3,
+//  "i-3e" -> -3
+//  "i10e" -> 10
+//  "i2562e" -> 2562
+fn parse_number(bencode_bytes: &[u8]) -> IResult<&[u8], i64> {
+    delimited(
+        tag("i"),
+        map_res(is_not("e"), |bytes| {
+            String::from_utf8_lossy(bytes).parse::<i64>()
+        }),
+        tag("e"),
+    )(bencode_bytes)
+}
+
+// examples:
+//  "l4:spam5:helloi3ee" -> [spam, hello, 3]
+//  "l2:hei3eli4ei5eee -> [he, 3, [4, 5]]
+fn parse_list(bencode_bytes: &[u8]) -> IResult<&[u8], Vec<Bencode>> {
+    delimited(tag("l"), many0(parse_bencode), tag("e"))(bencode_bytes)
+}
+
+// examples:
+//  "d 3:cow 3:moo 4:spam 4:eggs e" -> {cow: moo, spam: eggs}
+//  "d 4:spam l 1:a 1:b e e" -> {spam: [a, b]}
+//  "d 4:spam d 3:cow l 1:a 1:b e e e" -> {spam: {cow: [a, b]}}
+fn parse_dictionary(bencode_bytes: &[u8]) -> IResult<&[u8], BTreeMap<Vec<u8>, Bencode>> {
+    map(
+        delimited(
+            tag("d"),
+            many0(
+                // The `pair` combinator allows us
+                // to combine capturing the output
+                // of two parsers in succession
+                // into a tuple!
+                pair(parse_string, parse_bencode),
+            ),
+            tag("e"),
+        ),
+        // `pair` captures into tuples, and
+        // `many0` collects them into `Vec`s. We
+        // can simply collect these to a BTreeMap.
+        |elements| elements.into_iter().collect(),
+    )(bencode_bytes)
+}
+
+pub fn parse_bencode(bencode_bytes: &[u8]) -> IResult<&[u8], Bencode> {
+    // The `alt` combinator takes a tuple of parsers and keeps running them in
+    // succession until one of them succeeds, or until all of them fail.
+    alt((
+        // We use `map` in all four cases to wrap
+        // the result of the child parsers to the
+        // specific enum variant they correspond to.
+        map(parse_number, Bencode::Number),
+        map(parse_string, Bencode::ByteString),
+        map(parse_list, Bencode::List),
+        map(parse_dictionary, Bencode::Dict),
+    ))(bencode_bytes)
+}
+
+/// How strings that are not UTF-8, like the `pieces` of a torrent, are
+/// written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Binary {
+    Hex,
+    Base64,
+}
+
+impl Binary {
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Binary::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            Binary::Base64 => STANDARD.encode(bytes),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Binary::Hex => "hex",
+            Binary::Base64 => "base64",
+        }
+    }
+}
+
+/// The value in canonical bencode, as [`Bencode::encode`] writes it. Bytes
+/// that are not UTF-8, like those of the `pieces` of a torrent, are written
+/// `\xNN`, so the text parses back exactly when every string is UTF-8:
+/// `encode` has the bytes of the others.
+impl fmt::Display for Bencode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Lengths and structure are ASCII, so only strings are escaped.
+        let bytes = self.encode();
+        let mut rest = bytes.as_slice();
+        loop {
+            let e = match std::str::from_utf8(rest) {
+                Ok(s) => return f.write_str(s),
+                Err(e) => e,
+            };
+            let (valid, after) = rest.split_at(e.valid_up_to());
+            f.write_str(std::str::from_utf8(valid).unwrap_or_default())?;
+            let invalid = e.error_len().unwrap_or(after.len());
+            for b in &after[..invalid] {
+                write!(f, "\\x{:02x}", b)?;
+            }
+            rest = &after[invalid..];
+        }
+    }
+}
+
+/// Bencode in rhai: numbers are integers, which hold every number the parser
+/// reads, as it refuses those past 64 bits. Strings are strings if they are
+/// UTF-8 and blobs otherwise, lists arrays and dictionaries object maps,
+/// with keys that are not UTF-8, or that start with `hex:` or `base64:`
+/// themselves, written `hex:...`.
+impl From<Bencode> for rhai::Dynamic {
+    fn from(value: Bencode) -> Self {
+        match value {
+            Bencode::Number(n) => n.into(),
+            Bencode::ByteString(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => s.into(),
+                Err(e) => rhai::Dynamic::from_blob(e.into_bytes()),
+            },
+            Bencode::List(items) => {
+                rhai::Dynamic::from_array(items.into_iter().map(Into::into).collect())
+            }
+            Bencode::Dict(entries) => rhai::Dynamic::from_map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (text_key(&k, Binary::Hex).into(), v.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The value of rhai written the way `From<Bencode>` writes it, with blobs
+/// and characters as strings too, and `hex:...` and `base64:...` keys
+/// binary. Bencode has no floats, booleans or `()`.
+impl TryFrom<rhai::Dynamic> for Bencode {
+    type Error = String;
+
+    fn try_from(value: rhai::Dynamic) -> Result<Self, String> {
+        if let Ok(n) = value.as_int() {
+            return Ok(Bencode::Number(n));
+        }
+        if let Ok(c) = value.as_char() {
+            return Ok(Bencode::ByteString(c.to_string().into_bytes()));
+        }
+        if value.is_string() {
+            let s = value.into_immutable_string().unwrap_or_default();
+            return Ok(Bencode::ByteString(s.as_bytes().to_vec()));
+        }
+        if value.is::<rhai::Blob>() {
+            return Ok(Bencode::ByteString(value.cast::<rhai::Blob>()));
+        }
+        if value.is_array() {
+            return value
+                .cast::<rhai::Array>()
+                .into_iter()
+                .map(Bencode::try_from)
+                .collect::<Result<_, _>>()
+                .map(Bencode::List);
+        }
+        if value.is_map() {
+            let mut entries = BTreeMap::new();
+            for (key, value) in value.cast::<rhai::Map>() {
+                entries.insert(binary_key(&key)?, Bencode::try_from(value)?);
+            }
+            return Ok(Bencode::Dict(entries));
+        }
+        Err(format!("Bencode cannot hold {}", value.type_name()))
+    }
+}
+
+/// Registers the `bencode` module of rhai scripts: `bencode::decode` of a
+/// blob or a string, and `bencode::encode` of a value into a blob.
+pub fn register(engine: &mut rhai::Engine) {
+    use rhai::{Blob, Dynamic, EvalAltResult, ImmutableString};
+    type Out<T> = Result<T, Box<EvalAltResult>>;
+    let mut m = rhai::Module::new();
+    m.set_native_fn("decode", |bytes: Blob| -> Out<Dynamic> {
+        Ok(decode(&bytes)?.into())
+    });
+    m.set_native_fn("decode", |text: ImmutableString| -> Out<Dynamic> {
+        Ok(decode(text.as_bytes())?.into())
+    });
+    m.set_native_fn("encode", |value: Dynamic| -> Out<Blob> {
+        Ok(Bencode::try_from(value)?.encode())
+    });
+    engine.register_static_module("bencode", m.into());
+}
+
+/// The largest integer a JavaScript number holds exactly.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Bencode in JavaScript, like in rhai: strings are strings if they are
+/// UTF-8 and `Uint8Array`s otherwise, lists arrays and dictionaries objects,
+/// with keys written `hex:...` where rhai has them. Numbers past
+/// `Number.MAX_SAFE_INTEGER` are BigInts.
+fn to_js(value: &Bencode, context: &mut Context<'_>) -> JsResult<JsValue> {
+    Ok(match value {
+        Bencode::Number(n) if n.unsigned_abs() > MAX_SAFE_INTEGER => JsBigInt::from(*n).into(),
+        Bencode::Number(n) => (*n as f64).into(),
+        Bencode::ByteString(bytes) => match std::str::from_utf8(bytes) {
+            Ok(s) => JsString::from(s).into(),
+            Err(_) => JsUint8Array::from_iter(bytes.iter().copied(), context)?.into(),
+        },
+        Bencode::List(items) => {
+            let items = items
+                .iter()
+                .map(|item| to_js(item, context))
+                .collect::<JsResult<Vec<_>>>()?;
+            JsArray::from_iter(items, context).into()
+        }
+        Bencode::Dict(entries) => {
+            let mut properties = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                let key = JsString::from(text_key(key, Binary::Hex));
+                properties.push((key, to_js(value, context)?));
+            }
+            let mut object = ObjectInitializer::new(context);
+            for (key, value) in properties {
+                object.property(key, value, Attribute::all());
+            }
+            object.build().into()
+        }
+    })
+}
+
+/// The value of JavaScript written the way [`to_js`] writes it, with Maps
+/// as dictionaries too. Integers are numbers up to
+/// `Number.MAX_SAFE_INTEGER` and BigInts of 64 bits; bencode has no
+/// fractions, booleans, `null` or `undefined`.
+fn from_js(value: &JsValue, context: &mut Context<'_>) -> JsResult<Bencode> {
+    if let Some(s) = value.as_string() {
+        return Ok(Bencode::ByteString(
+            String::from_utf16_lossy(s).into_bytes(),
+        ));
+    }
+    if let Some(n) = value.as_number() {
+        if n.fract() != 0.0 || n.abs() > MAX_SAFE_INTEGER as f64 {
+            let message = format!("{} is not an integer bencode can hold, pass a BigInt", n);
+            return Err(JsNativeError::range().with_message(message).into());
+        }
+        return Ok(Bencode::Number(n as i64));
+    }
+    if let Some(big) = value.as_bigint() {
+        let big = big.to_string();
+        return big.parse().map(Bencode::Number).map_err(|_| {
+            let message = format!("{} does not fit in 64 bits", big);
+            JsNativeError::range().with_message(message).into()
+        });
+    }
+    let cannot = || {
+        let message = format!("Bencode cannot hold {}", value.display());
+        JsNativeError::typ().with_message(message)
+    };
+    let object = match value.as_object() {
+        Some(object) if !object.is_callable() => object,
+        _ => return Err(cannot().into()),
+    };
+    if JsUint8Array::from_object(object.clone()).is_ok() {
+        let len = object
+            .get(JsString::from("length"), context)?
+            .to_length(context)?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        for i in 0..len as usize {
+            bytes.push(object.get(i, context)?.to_uint8(context)?);
+        }
+        return Ok(Bencode::ByteString(bytes));
+    }
+    if object.is_array() {
+        let list = JsArray::from_object(object.clone())?;
+        let mut items = Vec::new();
+        for i in 0..list.length(context)? {
+            items.push(from_js(&list.get(i, context)?, context)?);
+        }
+        return Ok(Bencode::List(items));
+    }
+    let pairs = js_entries(object, context)?;
+    let mut entries = BTreeMap::new();
+    for i in 0..pairs.length(context)? {
+        let pair = pairs.get(i, context)?;
+        let pair = pair.as_object().ok_or_else(cannot)?;
+        let key = pair.get(0, context)?;
+        let key = match key.as_string() {
+            Some(s) => binary_key(&String::from_utf16_lossy(s))
+                .map_err(|e| JsNativeError::typ().with_message(e))?,
+            None => match from_js(&key, context)? {
+                Bencode::ByteString(bytes) => bytes,
+                _ => return Err(cannot().into()),
+            },
+        };
+        entries.insert(key, from_js(&pair.get(1, context)?, context)?);
+    }
+    Ok(Bencode::Dict(entries))
+}
+
+/// The `[key, value]` pairs of `object`, `Array.from` of a Map and
+/// `Object.entries` of anything else.
+fn js_entries(object: &JsObject, context: &mut Context<'_>) -> JsResult<JsArray> {
+    let (global, name) = match JsMap::from_object(object.clone()) {
+        Ok(_) => ("Array", "from"),
+        Err(_) => ("Object", "entries"),
+    };
+    let missing = || JsNativeError::typ().with_message(format!("{}.{} is missing", global, name));
+    let constructor = context
+        .global_object()
+        .get(JsString::from(global), context)?;
+    let method = constructor
+        .as_object()
+        .ok_or_else(missing)?
+        .get(JsString::from(name), context)?;
+    let method = method.as_callable().ok_or_else(missing)?;
+    let pairs = method.call(&constructor, &[object.clone().into()], context)?;
+    JsArray::from_object(pairs.as_object().cloned().ok_or_else(missing)?)
+}
+
+/// The bytes of a string, in UTF-8, or of a `Uint8Array`.
+fn js_bytes(value: &JsValue, context: &mut Context<'_>) -> JsResult<Vec<u8>> {
+    match from_js(value, context) {
+        Ok(Bencode::ByteString(bytes)) => Ok(bytes),
+        _ => Err(JsNativeError::typ()
+            .with_message("Expected a string or a Uint8Array")
+            .into()),
+    }
+}
+
+/// The `bencode` global of JavaScript handlers, for [`tide_rhai::JsDir::with_global`]:
+/// `bencode.decode` of a string or a `Uint8Array`, and `bencode.encode` of a
+/// value into a `Uint8Array`.
+pub fn js_global(context: &mut Context<'_>) -> JsResult<JsValue> {
+    let decoding = NativeFunction::from_fn_ptr(|_, args, context| {
+        let bytes = js_bytes(args.get_or_undefined(0), context)?;
+        let value = decode(&bytes).map_err(|e| JsNativeError::syntax().with_message(e))?;
+        to_js(&value, context)
+    });
+    let encoding = NativeFunction::from_fn_ptr(|_, args, context| {
+        let value = from_js(args.get_or_undefined(0), context)?;
+        Ok(JsUint8Array::from_iter(value.encode(), context)?.into())
+    });
+    Ok(ObjectInitializer::new(context)
+        .function(decoding, "decode", 1)
+        .function(encoding, "encode", 1)
+        .build()
+        .into())
+}
+
+/// What a [`Bencode`] can be indexed with: dictionary keys as text or bytes,
+/// list indices as `usize`.
+pub trait Key: fmt::Debug {
+    fn lookup<'a>(&self, value: &'a Bencode) -> Option<&'a Bencode>;
+
+    /// The key as the path of a [`Utf8PathError`] has it.
+    fn name(&self) -> String;
+}
+
+impl Key for &[u8] {
+    fn lookup<'a>(&self, value: &'a Bencode) -> Option<&'a Bencode> {
+        match value {
+            Bencode::Dict(entries) => entries.get(*self),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> String {
+        String::from_utf8_lossy(self).into_owned()
+    }
+}
+
+impl<const N: usize> Key for &[u8; N] {
+    fn lookup<'a>(&self, value: &'a Bencode) -> Option<&'a Bencode> {
+        self.as_slice().lookup(value)
+    }
+
+    fn name(&self) -> String {
+        self.as_slice().name()
+    }
+}
+
+impl Key for &str {
+    fn lookup<'a>(&self, value: &'a Bencode) -> Option<&'a Bencode> {
+        self.as_bytes().lookup(value)
+    }
+
+    fn name(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Key for usize {
+    fn lookup<'a>(&self, value: &'a Bencode) -> Option<&'a Bencode> {
+        match value {
+            Bencode::List(items) => items.get(*self),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// `value["info"]["name"]`, panicking where [`Bencode::get`] answers `None`.
+impl<K: Key> std::ops::Index<K> for Bencode {
+    type Output = Bencode;
+
+    fn index(&self, key: K) -> &Bencode {
+        match key.lookup(self) {
+            Some(value) => value,
+            None => panic!("No {:?} in {}", key, self.kind()),
+        }
+    }
+}
+
+/// A value read as a UTF-8 string that is not one, and where it is: the
+/// dictionary keys and list indices leading to it, from the outside in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utf8PathError {
+    pub path: Vec<String>,
+    /// Where the string stops being UTF-8, `None` if it is not a string.
+    pub error: Option<std::str::Utf8Error>,
+}
+
+impl Utf8PathError {
+    /// The error of the value under `key` of a dictionary, or at the index
+    /// `key` of a list, of the one this one is about.
+    pub fn within(mut self, key: impl fmt::Display) -> Self {
+        self.path.insert(0, key.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Utf8PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = match self.path.is_empty() {
+            true => "the value".to_string(),
+            false => self.path.join("."),
+        };
+        match &self.error {
+            Some(e) => write!(f, "{} is not UTF-8: {}", path, e),
+            None => write!(f, "{} is not a string", path),
+        }
+    }
+}
+
+impl std::error::Error for Utf8PathError {}
+
+/// Bytes of a binary string shown in a tree, the rest is cut.
+const TREE_BYTES: usize = 32;
+
+/// Parses `bytes` holding exactly one value.
+pub fn decode(bytes: &[u8]) -> Result<Bencode, String> {
+    match parse_bencode(bytes) {
+        Ok((rest, value)) if rest.is_empty() => Ok(value),
+        Ok((rest, _)) => Err(format!("{} bytes are left after the value", rest.len())),
+        Err(e) => {
+            let at = match &e {
+                nom::Err::Error(e) | nom::Err::Failure(e) => bytes.len() - e.input.len(),
+                nom::Err::Incomplete(_) => bytes.len(),
+            };
+            Err(format!("Invalid bencode at byte {}", at))
+        }
+    }
+}
+
+/// Documents parsed by [`BencodeCache::decode`] by the SHA-256 of their
+/// bytes, for payloads that come again and again, like the same torrent
+/// uploaded twice. Keeps the `capacity` most recently used ones.
+pub struct BencodeCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// The document and when it was last used.
+    documents: HashMap<[u8; 32], (u64, Arc<Bencode>)>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl BencodeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Like [`decode`], parsing `bytes` only if the same bytes were not
+    /// parsed before. Errors are not kept.
+    pub fn decode(&self, bytes: &[u8]) -> Result<Arc<Bencode>, String> {
+        let key: [u8; 32] = Sha256::digest(bytes).into();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let now = entries.clock;
+            if let Some((used, document)) = entries.documents.get_mut(&key) {
+                *used = now;
+                let document = document.clone();
+                entries.hits += 1;
+                return Ok(document);
+            }
+            entries.misses += 1;
+        }
+
+        let document = Arc::new(decode(bytes)?);
+        if self.capacity == 0 {
+            return Ok(document);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.documents.len() >= self.capacity && !entries.documents.contains_key(&key) {
+            let oldest = entries
+                .documents
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(k, _)| *k);
+            if let Some(oldest) = oldest {
+                entries.documents.remove(&oldest);
+            }
+        }
+        let now = entries.clock;
+        entries.documents.insert(key, (now, document.clone()));
+        Ok(document)
+    }
+
+    /// Calls of [`BencodeCache::decode`] answered from the cache, and those
+    /// that parsed.
+    pub fn hits(&self) -> (u64, u64) {
+        let entries = self.entries.lock().unwrap();
+        (entries.hits, entries.misses)
+    }
+}
+
+impl Bencode {
+    /// The value under `key` of a dictionary or at the index `key` of a
+    /// list, `None` if there is none or this is neither.
+    pub fn get<K: Key>(&self, key: K) -> Option<&Bencode> {
+        key.lookup(self)
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Bencode::Number(_) => "a number",
+            Bencode::ByteString(_) => "a string",
+            Bencode::List(_) => "a list",
+            Bencode::Dict(_) => "a dictionary",
+        }
+    }
+
+    /// The string this value is, if it is one in UTF-8. The error has an
+    /// empty path, for [`Utf8PathError::within`] to fill in on the way out;
+    /// [`Bencode::get_utf8_str`] has it filled in.
+    pub fn as_utf8_str(&self) -> Result<&str, Utf8PathError> {
+        match self {
+            Bencode::ByteString(bytes) => std::str::from_utf8(bytes).map_err(|e| Utf8PathError {
+                path: Vec::new(),
+                error: Some(e),
+            }),
+            _ => Err(Utf8PathError {
+                path: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    /// Like [`Bencode::as_utf8_str`], of the value `path` leads to, the
+    /// dictionary keys and list indices from this one in. The error has the
+    /// path, and no [`Utf8PathError::error`] if nothing is there either.
+    pub fn get_utf8_str(&self, path: &[&dyn Key]) -> Result<&str, Utf8PathError> {
+        let value = path.iter().try_fold(self, |value, key| key.lookup(value));
+        let missing = Utf8PathError {
+            path: Vec::new(),
+            error: None,
+        };
+        value
+            .map_or(Err(missing), Bencode::as_utf8_str)
+            .map_err(|mut e| {
+                e.path = path.iter().map(|key| key.name()).collect();
+                e
+            })
+    }
+
+    /// The value as JSON. Strings that are not UTF-8 become
+    /// `{"hex": "..."}` or `{"base64": "..."}`, and dictionary keys
+    /// `hex:...` or `base64:...`, as do keys that start with `hex:` or
+    /// `base64:` themselves.
+    pub fn to_json(&self, binary: Binary) -> serde_json::Value {
+        match self {
+            Bencode::Number(n) => (*n).into(),
+            Bencode::ByteString(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => s.into(),
+                Err(_) => serde_json::json!({ binary.name(): binary.encode(bytes) }),
+            },
+            Bencode::List(items) => items.iter().map(|i| i.to_json(binary)).collect(),
+            Bencode::Dict(entries) => entries
+                .iter()
+                .map(|(k, v)| (text_key(k, binary), v.to_json(binary)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        }
+    }
+
+    /// The value as an indented tree, one line per string or number, with
+    /// the length and the first bytes of binary strings.
+    pub fn tree(&self, binary: Binary) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, 0, binary);
+        out.trim_start().to_string()
+    }
+
+    fn write_tree(&self, out: &mut String, depth: usize, binary: Binary) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Bencode::List(items) if !items.is_empty() => {
+                for item in items {
+                    out.push_str(&format!("\n{}-", indent));
+                    item.write_tree(out, depth + 1, binary);
+                }
+            }
+            Bencode::Dict(entries) if !entries.is_empty() => {
+                for (key, value) in entries {
+                    let key = match std::str::from_utf8(key) {
+                        Ok(s) => s.to_string(),
+                        Err(_) => format!("<{}>", binary.encode(key)),
+                    };
+                    out.push_str(&format!("\n{}{}:", indent, key));
+                    value.write_tree(out, depth + 1, binary);
+                }
+            }
+            Bencode::List(_) => out.push_str(" []"),
+            Bencode::Dict(_) => out.push_str(" {}"),
+            Bencode::Number(n) => out.push_str(&format!(" {}", n)),
+            Bencode::ByteString(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => out.push_str(&format!(" {:?}", s)),
+                Err(_) => {
+                    let shown = &bytes[..bytes.len().min(TREE_BYTES)];
+                    let cut = if shown.len() < bytes.len() { "..." } else { "" };
+                    out.push_str(&format!(
+                        " <{} bytes> {}{}",
+                        bytes.len(),
+                        binary.encode(shown),
+                        cut
+                    ));
+                }
+            },
+        }
+    }
+
+    /// The value in canonical bencode: dictionary keys sorted by their
+    /// bytes, integers without leading zeros.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Bencode::Number(n) => out.extend(format!("i{}e", n).into_bytes()),
+            Bencode::ByteString(bytes) => write_string(out, bytes),
+            Bencode::List(items) => {
+                out.push(b'l');
+                for item in items {
+                    item.write(out);
+                }
+                out.push(b'e');
+            }
+            Bencode::Dict(entries) => {
+                out.push(b'd');
+                for (key, value) in entries {
+                    write_string(out, key);
+                    value.write(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+
+    /// The value of JSON written the way [`Bencode::to_json`] writes it:
+    /// `{"hex": "..."}` and `{"base64": "..."}` are binary strings, and so
+    /// are `hex:...` and `base64:...` keys. Bencode has no floats, booleans
+    /// or null.
+    pub fn from_json(json: &serde_json::Value) -> Result<Bencode, String> {
+        use serde_json::Value;
+        Ok(match json {
+            Value::Number(n) => Bencode::Number(
+                n.as_i64()
+                    .ok_or_else(|| format!("{} is not an integer bencode can hold", n))?,
+            ),
+            Value::String(s) => Bencode::ByteString(s.clone().into_bytes()),
+            Value::Array(items) => Bencode::List(
+                items
+                    .iter()
+                    .map(Bencode::from_json)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(map) => {
+                if let Some(bytes) = binary_object(map)? {
+                    return Ok(Bencode::ByteString(bytes));
+                }
+                let mut entries = BTreeMap::new();
+                for (key, value) in map {
+                    entries.insert(binary_key(key)?, Bencode::from_json(value)?);
+                }
+                Bencode::Dict(entries)
+            }
+            other => return Err(format!("Bencode cannot hold {}", other)),
+        })
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend(format!("{}:", bytes.len()).into_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Err(format!("{:?} is not hex", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("{:?} is not hex", s)))
+        .collect()
+}
+
+fn decode_binary(binary: &str, s: &str) -> Result<Vec<u8>, String> {
+    match binary {
+        "hex" => decode_hex(s),
+        _ => STANDARD
+            .decode(s)
+            .map_err(|e| format!("{:?} is not base64: {}", s, e)),
+    }
+}
+
+/// The bytes of `{"hex": "..."}` or `{"base64": "..."}`.
+fn binary_object(
+    map: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut entries = map.iter();
+    match (entries.next(), entries.next()) {
+        (Some((name, serde_json::Value::String(s))), None) if name == "hex" || name == "base64" => {
+            decode_binary(name, s).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The bytes of a dictionary key, `hex:...` and `base64:...` decoded.
+fn binary_key(key: &str) -> Result<Vec<u8>, String> {
+    match key.split_once(':') {
+        Some((binary @ ("hex" | "base64"), s)) => decode_binary(binary, s),
+        _ => Ok(key.as_bytes().to_vec()),
+    }
+}
+
+/// A dictionary key as text, the way [`binary_key`] reads it back: the key
+/// if it is UTF-8, and `hex:...` or `base64:...` of its bytes if it is not or
+/// if it starts with `hex:` or `base64:` itself.
+fn text_key(key: &[u8], binary: Binary) -> String {
+    match std::str::from_utf8(key) {
+        Ok(s) if !matches!(s.split_once(':'), Some(("hex" | "base64", _))) => s.to_string(),
+        _ => format!("{}:{}", binary.name(), binary.encode(key)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn basic_byte_string() {
+        assert_eq!(
+            parse_bencode(b"5:hello"),
+            Ok((b"" as &[u8], Bencode::ByteString("hello".into())))
+        );
+    }
+
+    #[test]
+    fn smaller_length() {
+        assert_eq!(
+            parse_bencode(b"5:helloworld"),
+            Ok((b"world" as &[u8], Bencode::ByteString("hello".into())))
+        );
+    }
+
+    #[test]
+    fn empty_string() {
+        assert_eq!(
+            parse_bencode(b"0:"),
+            Ok((b"" as &[u8], Bencode::ByteString("".into())))
+        );
+    }
+
+    #[test]
+    fn empty_string_smaller_len() {
+        assert_eq!(
+            parse_bencode(b"0:world"),
+            Ok((b"world" as &[u8], Bencode::ByteString("".into())))
+        );
+    }
+
+    #[test]
+    fn whitespace_in_string() {
+        assert_eq!(
+            parse_bencode(b"11:hello world"),
+            Ok((b"" as &[u8], Bencode::ByteString("hello world".into())))
+        );
+    }
+
+    #[test]
+    fn long_len() {
+        assert_eq!(
+            parse_bencode(b"42:helloworldprogrammedtothinkandnottofeeeeel"),
+            Ok((
+                b"" as &[u8],
+                Bencode::ByteString("helloworldprogrammedtothinkandnottofeeeeel".into())
+            ))
+        );
+    }
+
+    #[test]
+    fn long_len_multiple_whitespace_in_string() {
+        assert_eq!(
+            parse_bencode(b"50:hello world programmed to think and not to feeeeel"),
+            Ok((
+                b"" as &[u8],
+                Bencode::ByteString("hello world programmed to think and not to feeeeel".into())
+            ))
+        );
+    }
+
+    #[test]
+    fn negative_len_string() {
+        assert!(parse_bencode(b"-2:hello").is_err());
+    }
+
+    #[test]
+    fn incorrect_len_string() {
+        assert!(parse_bencode(b"5:worl").is_err());
+    }
+
+    #[test]
+    fn invalid_len_string() {
+        assert!(parse_bencode(b"5a:hello").is_err());
+    }
+
+    #[test]
+    fn positive_number() {
+        assert_eq!(
+            parse_bencode(b"i88e"),
+            Ok((b"" as &[u8], Bencode::Number(88)))
+        );
+    }
+
+    #[test]
+    fn zero() {
+        assert_eq!(
+            parse_bencode(b"i0e"),
+            Ok((b"" as &[u8], Bencode::Number(0)))
+        );
+    }
+
+    #[test]
+    fn negative_number() {
+        assert_eq!(
+            parse_bencode(b"i-88e"),
+            Ok((b"" as &[u8], Bencode::Number(-88)))
+        );
+    }
+
+    #[test]
+    fn empty_number() {
+        assert!(parse_bencode(b"ie").is_err());
+    }
+
+    #[test]
+    fn only_negative_sign() {
+        assert!(parse_bencode(b"i-e").is_err());
+    }
+
+    #[test]
+    fn basic_list() {
+        let bencode_hello = "5:hello";
+        let bencode_world = "5:world";
+
+        assert_eq!(
+            parse_bencode(format!("l{}{}e", bencode_hello, bencode_world).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::List(vec![
+                    Bencode::ByteString("hello".into()),
+                    Bencode::ByteString("world".into())
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn heterogenous_list() {
+        let bencode_string = "5:hello";
+        let bencode_number = "i8e";
+
+        assert_eq!(
+            parse_bencode(format!("l{}{}e", bencode_string, bencode_number).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::List(vec![
+                    Bencode::ByteString("hello".into()),
+                    Bencode::Number(8)
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn multiple_nested_list() {
+        let list_one = "l5:hello5:worlde";
+        let list_two = "l5:helloi8ee";
+
+        assert_eq!(
+            parse_bencode(format!("l{}{}e", list_one, list_two).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::List(vec![
+                    Bencode::List(vec![
+                        Bencode::ByteString("hello".into()),
+                        Bencode::ByteString("world".into())
+                    ]),
+                    Bencode::List(vec![
+                        Bencode::ByteString("hello".into()),
+                        Bencode::Number(8)
+                    ])
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_list() {
+        assert_eq!(
+            parse_bencode(b"le"),
+            Ok((b"" as &[u8], Bencode::List(vec![])))
+        );
+    }
+
+    #[test]
+    fn incomplete_list() {
+        let bencode_number = "i8e";
+        assert!(parse_bencode(format!("l{0}{0}{0}", bencode_number).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn list_with_invalid_element() {
+        let invalid_bencode_string = "-5:hello";
+
+        assert!(parse_bencode(format!("l{}e", invalid_bencode_string).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn basic_dict() {
+        let key_one = "3:bar";
+        let val_one = "4:spam";
+
+        let key_two = "3:foo";
+        let val_two = "i88e";
+
+        assert_eq!(
+            parse_bencode(format!("d{}{}{}{}e", key_one, val_one, key_two, val_two).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::Dict(
+                    vec![
+                        ("bar".into(), Bencode::ByteString("spam".into())),
+                        ("foo".into(), Bencode::Number(88)),
+                    ]
+                    .into_iter()
+                    .collect()
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_dict() {
+        assert_eq!(
+            parse_bencode(b"de"),
+            Ok((b"" as &[u8], Bencode::Dict(BTreeMap::new())))
+        );
+    }
+
+    #[test]
+    fn incomplete_dict() {
+        let key_one = "3:foo";
+
+        assert!(parse_bencode(format!("d{}e", key_one).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn dict_with_invalid_key() {
+        let key_one = "-3:foo";
+        let val_one = "i88e";
+
+        assert!(parse_bencode(format!("d{}{}e", key_one, val_one).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn dict_with_invalid_value() {
+        let key_one = "3:foo";
+        let val_one = "-3:bar";
+
+        assert!(parse_bencode(format!("d{}{}e", key_one, val_one).as_bytes()).is_err());
+    }
+
+    // Integration tests
+    // TODO: check if rust has clean way for test separation
+
+    #[test]
+    fn list_of_dict() {
+        let key_one = "3:foo";
+        let val_one = "3:bar";
+
+        let key_two = "3:baz";
+        let val_two = "3:baz";
+
+        let dict_str = format!("d{}{}{}{}e", key_one, val_one, key_two, val_two);
+        let (_, result_dict) = parse_dictionary(&dict_str.as_bytes()).unwrap();
+
+        assert_eq!(
+            parse_bencode(format!("l{0}{0}e", dict_str).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::List(vec![
+                    Bencode::Dict(result_dict.clone()),
+                    Bencode::Dict(result_dict)
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn dict_of_list() {
+        let bencode_hello = "5:hello";
+        let bencode_world = "5:world";
+
+        let list_str = format!("l{}{}e", bencode_hello, bencode_world);
+
+        let key_one = "3:foo";
+        let key_two = "3:bar";
+
+        let (_, result_list) = parse_list(&list_str.as_bytes()).unwrap();
+
+        assert_eq!(
+            parse_bencode(format!("d{}{2}{}{2}e", key_one, key_two, list_str).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::Dict(
+                    vec![
+                        ("foo".into(), Bencode::List(result_list.clone())),
+                        ("bar".into(), Bencode::List(result_list)),
+                    ]
+                    .into_iter()
+                    .collect()
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn multiple_nested_dicts() {
+        let key_one = "3:foo";
+        let val_one = "3:bar";
+
+        let key_two = "3:baz";
+        let val_two = "3:baz";
+
+        let nested_dict_str = format!("d{}{}{}{}e", key_one, val_one, key_two, val_two);
+        let (_, result_nested_dict) = parse_dictionary(&nested_dict_str.as_bytes()).unwrap();
+
+        assert_eq!(
+            parse_bencode(format!("d{}{2}{}{2}e", key_one, key_two, nested_dict_str).as_bytes()),
+            Ok((
+                b"" as &[u8],
+                Bencode::Dict(
+                    vec![
+                        ("foo".into(), Bencode::Dict(result_nested_dict.clone())),
+                        ("baz".into(), Bencode::Dict(result_nested_dict)),
+                    ]
+                    .into_iter()
+                    .collect()
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_whole_input() {
+        assert_eq!(decode(b"i3e"), Ok(Bencode::Number(3)));
+        assert!(decode(b"i3ei4e").is_err());
+        assert!(decode(b"l5:hello").is_err());
+    }
+
+    #[test]
+    fn json_and_tree() {
+        let torrent = decode(b"d8:announce4:http4:infod6:lengthi12e6:pieces2:\xff\x00ee").unwrap();
+        assert_eq!(
+            torrent.to_json(Binary::Hex),
+            serde_json::json!({"announce": "http", "info": {"length": 12, "pieces": {"hex": "ff00"}}})
+        );
+        assert_eq!(
+            torrent.to_json(Binary::Base64)["info"]["pieces"],
+            serde_json::json!({"base64": "/wA="})
+        );
+        assert_eq!(
+            torrent.tree(Binary::Hex),
+            "announce: \"http\"\ninfo:\n  length: 12\n  pieces: <2 bytes> ff00"
+        );
+        let list = decode(b"l1:ali1eee").unwrap();
+        assert_eq!(list.tree(Binary::Hex), "- \"a\"\n-\n  - 1");
+    }
+
+    #[test]
+    fn encode_json() {
+        let json = serde_json::json!({
+            "info": {"pieces": {"hex": "ff00"}, "length": 12},
+            "announce": "http",
+            "hex:ff": [1, {"base64": "/wA="}],
+        });
+        let value = Bencode::from_json(&json).unwrap();
+        assert_eq!(
+            value.encode(),
+            b"d8:announce4:http4:infod6:lengthi12e6:pieces2:\xff\x00e1:\xffli1e2:\xff\x00ee"
+        );
+        assert_eq!(decode(&value.encode()), Ok(value.clone()));
+        assert_eq!(
+            Bencode::from_json(&value.to_json(Binary::Base64)),
+            Ok(value)
+        );
+        let key = decode(b"d6:hex:ffi1ee").unwrap();
+        assert_eq!(
+            key.to_json(Binary::Hex),
+            serde_json::json!({"hex:6865783a6666": 1})
+        );
+        assert_eq!(Bencode::from_json(&key.to_json(Binary::Base64)), Ok(key));
+        assert!(Bencode::from_json(&serde_json::json!(1.5)).is_err());
+        assert!(Bencode::from_json(&serde_json::json!({"hex": "zz"})).is_err());
+        assert!(Bencode::from_json(&serde_json::json!([null])).is_err());
+    }
+
+    #[test]
+    fn cache() {
+        let cache = BencodeCache::new(2);
+        let a = cache.decode(b"i1e").unwrap();
+        assert!(Arc::ptr_eq(&a, &cache.decode(b"i1e").unwrap()));
+        cache.decode(b"i2e").unwrap();
+        cache.decode(b"i1e").unwrap();
+        // i2e is the least recently used and makes way.
+        cache.decode(b"i3e").unwrap();
+        assert!(Arc::ptr_eq(&a, &cache.decode(b"i1e").unwrap()));
+        assert_eq!(cache.hits(), (3, 3));
+        cache.decode(b"i2e").unwrap();
+        assert_eq!(cache.hits(), (3, 4));
+        assert!(cache.decode(b"i2").is_err());
+        cache.decode(b"i3e").unwrap();
+        assert_eq!(cache.hits(), (4, 5));
+    }
+
+    #[test]
+    fn utf8_strings() {
+        assert_eq!(Bencode::ByteString("hé".into()).as_utf8_str(), Ok("hé"));
+        let binary = Bencode::ByteString(b"ab\xff".to_vec()).as_utf8_str();
+        let e = binary.unwrap_err().within(0).within("path").within("info");
+        assert_eq!(e.path, ["info", "path", "0"]);
+        assert_eq!(e.error.unwrap().valid_up_to(), 2);
+        assert!(e.to_string().starts_with("info.path.0 is not UTF-8"));
+        let number = Bencode::Number(1).as_utf8_str().unwrap_err();
+        assert_eq!(number.to_string(), "the value is not a string");
+
+        let value = decode(b"d4:infod4:name2:h\xff5:filesl1:aeee").unwrap();
+        assert_eq!(value.get_utf8_str(&[&"info", &"files", &0usize]), Ok("a"));
+        let e = value.get_utf8_str(&[&"info", &"name"]).unwrap_err();
+        assert_eq!(e.path, ["info", "name"]);
+        assert_eq!(e.error.unwrap().valid_up_to(), 1);
+        let missing = value
+            .get_utf8_str(&[&b"info", &"files", &1usize])
+            .unwrap_err();
+        assert_eq!(missing.to_string(), "info.files.1 is not a string");
+    }
+
+    #[test]
+    fn index() {
+        let value = decode(b"d4:infod4:name1:a5:filesli1ei2eeee").unwrap();
+        assert_eq!(value["info"]["name"], Bencode::ByteString("a".into()));
+        assert_eq!(value[b"info"][b"files".as_slice()][1], Bencode::Number(2));
+        assert_eq!(
+            value
+                .get("info")
+                .and_then(|i| i.get("files"))
+                .and_then(|f| f.get(2)),
+            None
+        );
+        assert_eq!(value.get(0), None);
+        assert_eq!(value["info"]["name"].get("x"), None);
+        let missing = std::panic::catch_unwind(|| value["torrent"].clone());
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn display() {
+        let text = "d4:infod5:filesli1ei-2ee4:name3:étee";
+        let value = decode(text.as_bytes()).unwrap();
+        assert_eq!(value.to_string(), text);
+        assert_eq!(decode(format!("{}", value).as_bytes()), Ok(value));
+        let binary = Bencode::List(vec![Bencode::ByteString(b"a\xff\xfeb".to_vec())]);
+        assert_eq!(binary.to_string(), "l4:a\\xff\\xfebe");
+    }
+
+    #[test]
+    fn rhai_values() {
+        let mut value = decode(b"d4:infod4:name1:a6:pieces2:\xff\xfe5:filesli1eeee").unwrap();
+        if let Bencode::Dict(entries) = &mut value {
+            entries.insert(vec![0xff], Bencode::List(Vec::new()));
+        }
+        let dynamic: rhai::Dynamic = value.clone().into();
+        let map = dynamic.clone().cast::<rhai::Map>();
+        assert!(map.contains_key("hex:ff"));
+        assert!(map["info"].clone().cast::<rhai::Map>()["pieces"].is::<rhai::Blob>());
+        assert_eq!(Bencode::try_from(dynamic), Ok(value));
+        assert!(Bencode::try_from(rhai::Dynamic::from(1.5)).is_err());
+
+        let keys = decode(b"d6:base641:a6:hex:ffi1e9:base64:/wi2ee").unwrap();
+        let dynamic: rhai::Dynamic = keys.clone().into();
+        let map = dynamic.clone().cast::<rhai::Map>();
+        assert!(map.contains_key("base64"));
+        assert!(map.contains_key("hex:6865783a6666"));
+        assert!(map.contains_key("hex:6261736536343a2f77"));
+        assert_eq!(Bencode::try_from(dynamic), Ok(keys));
+
+        let mut engine = rhai::Engine::new();
+        register(&mut engine);
+        let name: String = engine
+            .eval(r#"let t = bencode::decode("d4:name1:ae"); t.name"#)
+            .unwrap();
+        assert_eq!(name, "a");
+        let blob: rhai::Blob = engine
+            .eval(r#"bencode::encode(#{ n: 1, l: [1, "x"] })"#)
+            .unwrap();
+        assert_eq!(blob, b"d1:lli1e1:xe1:ni1ee");
+    }
+
+    #[test]
+    fn js_values() {
+        use tide_rhai::boa_engine::Source;
+        let mut context = Context::default();
+        let script = b"d3:bigi9007199254740993e4:name1:a6:pieces2:\xff\xfe5:smalli-3ee";
+        let value = decode(script).unwrap();
+        let t = to_js(&value, &mut context).unwrap();
+        context
+            .register_global_property(JsString::from("t"), t, Attribute::all())
+            .unwrap();
+        let bencode = js_global(&mut context).unwrap();
+        context
+            .register_global_property(JsString::from("bencode"), bencode, Attribute::all())
+            .unwrap();
+        let mut eval = |code: &str| context.eval(Source::from_bytes(code));
+        let checks = "typeof t.big == 'bigint' && t.name == 'a' && t.pieces instanceof Uint8Array && t.small === -3";
+        assert_eq!(eval(checks).unwrap(), JsValue::from(true));
+        let t = eval("t").unwrap();
+        let encoded = eval(r#"bencode.encode(new Map([["b", [1n, "x"]], ["a", {}]]))"#).unwrap();
+        assert!(eval("bencode.encode(1.5)").is_err());
+        assert!(eval("bencode.decode('i1')").is_err());
+        let keys = "let k = bencode.decode('d6:hex:ffi1ee'); k['hex:6865783a6666'] === 1";
+        assert_eq!(eval(keys).unwrap(), JsValue::from(true));
+        let keys = eval("bencode.encode(k)").unwrap();
+        assert_eq!(from_js(&t, &mut context).unwrap(), value);
+        assert_eq!(
+            js_bytes(&encoded, &mut context).unwrap(),
+            b"d1:ade1:bli1e1:xee"
+        );
+        assert_eq!(js_bytes(&keys, &mut context).unwrap(), b"d6:hex:ffi1ee");
+    }
+}
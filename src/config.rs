@@ -0,0 +1,535 @@
+use crate::logging::LogFormat;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("could not read config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// Server configuration, read from `rustvm.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Addresses to serve the app on, all at once.
+    pub listen: Vec<Listen>,
+    /// Binds TCP addresses with `SO_REUSEPORT`, so a new binary can take
+    /// over while this one finishes its requests.
+    pub reuse_port: bool,
+    /// Serves the app once per CPU, each copy with its own engines, caches
+    /// and listening sockets bound with `SO_REUSEPORT`, for the kernel to
+    /// share connections between them. TCP addresses only.
+    pub thread_per_core: bool,
+    /// Seconds to wait for requests in flight when stopping.
+    pub drain_timeout: u64,
+    pub connections: Connections,
+    /// Directory of the scripts served.
+    pub dir: PathBuf,
+    /// Reloads, like SIGHUP, whenever a file of `dir` or the config changes.
+    /// Edits of the rhai and JavaScript modules the app keeps are picked up
+    /// without one, evaluating a rhai module again with those importing it
+    /// while the other modules stay as they are.
+    pub watch: bool,
+    /// Lowest level of the messages logged.
+    pub log_level: log::LevelFilter,
+    pub log_format: LogFormat,
+    /// Shows error pages with details for failing scripts.
+    pub dev: bool,
+    /// Directory scripts may read and write through the `file_*` bindings.
+    pub data_dir: PathBuf,
+    /// Directory of the store behind the `storage` object of JavaScript handlers.
+    pub storage_dir: PathBuf,
+    /// Snapshot written by `rustvm precompile` to serve scripts from.
+    pub snapshot: Option<PathBuf>,
+    /// Port an editor can attach to for debugging rhai scripts, only used with `dev`.
+    pub debug_port: Option<u16>,
+    /// Steps of the engine a rhai script may take, unlimited if unset.
+    pub rhai_max_operations: Option<u64>,
+    /// Seconds a JavaScript handler may run, timers and pending requests included.
+    pub js_timeout: u64,
+    /// Engine running JavaScript handlers.
+    pub js_engine: tide_rhai::JsEngine,
+    pub smtp: Option<tide_rhai::MailConfig>,
+    pub s3: Option<tide_rhai::S3Config>,
+    /// Lets JavaScript modules import others by URL.
+    pub remote_imports: Option<tide_rhai::RemoteImports>,
+    pub mirror: Option<Mirror>,
+    pub split: Option<Split>,
+    /// Rhai scripts rewriting every response, in order, see
+    /// [`tide_rhai::ResponseHooks`].
+    pub response_hooks: Vec<PathBuf>,
+    /// Rhai scripts run every so often with the bindings of the app, see
+    /// [`tide_rhai::Jobs`].
+    pub jobs: Vec<Job>,
+    /// Directory of a `schema.graphql` and its rhai resolvers, served at
+    /// `/graphql`.
+    pub graphql: Option<PathBuf>,
+    pub explorer: Explorer,
+    /// OAuth2 providers of the `oauth` object of rhai scripts, by name.
+    pub oauth: HashMap<String, tide_rhai::OAuthProvider>,
+    /// Requires signing in with an OpenID Connect provider for everything.
+    pub oidc: Option<tide_rhai::OidcConfig>,
+    pub secrets: Secrets,
+    pub integrity: Option<Integrity>,
+    pub audit: Option<Audit>,
+    pub slow_scripts: SlowScripts,
+    pub admin: Admin,
+    /// Apps served besides the one of `dir`, for the requests to their
+    /// domains.
+    pub tenants: Vec<Tenant>,
+    /// Directory of more tenants, one for every directory in it, named after
+    /// it and serving the domain of its name.
+    pub tenants_dir: Option<PathBuf>,
+    /// Directory of the data directories of the tenants, one for every
+    /// tenant named after it.
+    pub tenants_data_dir: PathBuf,
+    /// Quota of the tenants without one of their own.
+    pub tenant_quota: Quota,
+}
+
+/// Limits on the connections of TCP addresses, with TLS or not, enforced by
+/// the listener before the app sees a request. Unset limits are not enforced.
+/// They hold for every address, and every thread with `thread_per_core`,
+/// on its own.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Connections {
+    /// Connections served at once; the next ones wait in the accept queue.
+    pub max: Option<usize>,
+    /// Connections the kernel queues until they are accepted, 1024 if unset.
+    /// Clients past it wait in their connect, or are refused.
+    pub backlog: Option<i32>,
+    /// Seconds a connection may go without a byte read or written, outside
+    /// of the requests its scripts are answering.
+    pub idle_timeout: Option<u64>,
+    /// Seconds a client has to send the head of a request, the next one of
+    /// a kept-alive connection included, against slowloris clients. With
+    /// TLS they have as long for the handshake.
+    pub header_timeout: Option<u64>,
+}
+
+impl Connections {
+    /// Whether there is a limit to enforce.
+    pub fn is_set(&self) -> bool {
+        self.max.is_some()
+            || self.backlog.is_some()
+            || self.idle_timeout.is_some()
+            || self.header_timeout.is_some()
+    }
+
+    pub fn backlog(&self) -> i32 {
+        self.backlog.unwrap_or(1024)
+    }
+}
+
+/// The API explorer of `/openapi.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Explorer {
+    /// Where the explorer is served.
+    pub path: String,
+    /// Serves the explorer, by default only with `dev`.
+    pub enabled: Option<bool>,
+}
+
+impl Default for Explorer {
+    fn default() -> Self {
+        Self {
+            path: "/docs".into(),
+            enabled: None,
+        }
+    }
+}
+
+/// The secrets scripts get with `secret(name)`, read from the encrypted
+/// `file`, then `env_file` and then the environment, later ones overriding.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Secrets {
+    /// `.env` file of `NAME=value` lines.
+    pub env_file: Option<PathBuf>,
+    /// File written by `rustvm secrets seal`.
+    pub file: Option<PathBuf>,
+    /// Environment variable holding the key of `file`.
+    pub key_env: String,
+    /// Environment variables that are secrets, by name or by a prefix
+    /// ending in `*`.
+    pub env: Vec<String>,
+    /// Secrets scripts may get, the same way.
+    pub allow: Vec<String>,
+}
+
+impl Default for Secrets {
+    fn default() -> Self {
+        Self {
+            env_file: None,
+            file: None,
+            key_env: "RUSTVM_SECRETS_KEY".into(),
+            env: Vec::new(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl Secrets {
+    /// Reads the secrets, failing if a file cannot be read or the key of
+    /// `file` is not set.
+    pub fn load(&self) -> std::io::Result<tide_rhai::Secrets> {
+        let mut secrets = tide_rhai::Secrets::new();
+        if let Some(file) = &self.file {
+            let key = std::env::var(&self.key_env).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("set {} to the key of {}", self.key_env, file.display()),
+                )
+            })?;
+            secrets = secrets.with_encrypted_file(file, &key)?;
+        }
+        if let Some(file) = &self.env_file {
+            secrets = secrets.with_env_file(file)?;
+        }
+        let allow = tide_rhai::Allow::Only(self.allow.clone());
+        Ok(secrets.with_env(&self.env).allow(allow))
+    }
+}
+
+/// Refuses scripts that do not match the manifest `rustvm integrity sign`
+/// wrote into the app directory, see [`tide_rhai::Integrity`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct Integrity {
+    /// Key the manifest is signed with, from `rustvm integrity keys`.
+    pub public_key: String,
+}
+
+/// Records every outbound request of the scripts, see
+/// [`tide_rhai::AuditLog`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Audit {
+    /// File the json lines are appended to, the `audit` target of the log
+    /// if unset.
+    pub file: Option<PathBuf>,
+    /// Hosts scripts are expected to reach, as `host` or `host:port`.
+    pub known: Vec<String>,
+    /// Refuses requests to hosts that are not known.
+    pub deny_unknown: bool,
+}
+
+impl Audit {
+    /// Opens the log, failing if the file cannot be written.
+    pub fn open(&self) -> std::io::Result<tide_rhai::AuditLog> {
+        let mut audit = tide_rhai::AuditLog::new()
+            .known(tide_rhai::Allow::Only(self.known.clone()))
+            .deny_unknown(self.deny_unknown);
+        if let Some(file) = &self.file {
+            audit = audit.with_file(file)?;
+        }
+        Ok(audit)
+    }
+}
+
+/// When scripts are reported as slow, in the log and on the dashboard at
+/// `/admin`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SlowScripts {
+    /// p95 in milliseconds above which a script is slow.
+    pub threshold_ms: u64,
+    /// How many times its usual p95 a script must take to have regressed.
+    pub regression: f64,
+    /// Runs the percentiles are of, checked every time that many ran.
+    pub window: usize,
+}
+
+impl Default for SlowScripts {
+    fn default() -> Self {
+        Self {
+            threshold_ms: 1000,
+            regression: 2.0,
+            window: 100,
+        }
+    }
+}
+
+impl SlowScripts {
+    pub fn build(&self) -> tide_rhai::SlowScripts {
+        tide_rhai::SlowScripts::new()
+            .threshold(Duration::from_millis(self.threshold_ms))
+            .regression(self.regression)
+            .window(self.window)
+    }
+}
+
+/// A rhai script run every `every_secs` seconds, kept out of `dir` so it
+/// is not served.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Job {
+    pub script: PathBuf,
+    pub every_secs: u64,
+}
+
+/// The dashboard at `/admin`, see [`tide_rhai::Admin`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Admin {
+    /// Environment variable holding the token of the dashboard, which is
+    /// not served while it is unset.
+    pub token_env: String,
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self {
+            token_env: "RUSTVM_ADMIN_TOKEN".into(),
+        }
+    }
+}
+
+impl Admin {
+    /// The token, if one is set.
+    pub fn token(&self) -> Option<String> {
+        std::env::var(&self.token_env)
+            .ok()
+            .filter(|t| !t.is_empty())
+    }
+}
+
+/// An app of its own, with its scripts, engines, data directory and storage,
+/// see [`tide_rhai::Tenants`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tenant {
+    /// Name of the tenant, which its data and storage are kept under.
+    pub name: String,
+    /// Directory of its scripts.
+    pub dir: PathBuf,
+    /// Hosts it serves, `*.` matching any subdomain.
+    pub domains: Vec<String>,
+    /// What it may take of the server, `tenant_quota` if unset.
+    pub quota: Option<Quota>,
+}
+
+/// How much of the server a tenant may take, see [`tide_rhai::Quota`].
+/// Unset limits are not enforced.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    /// Requests it may get every minute.
+    pub requests_per_minute: Option<u64>,
+    /// Seconds its scripts may run every minute, all together.
+    pub script_seconds_per_minute: Option<f64>,
+    /// Sizes of the strings, arrays and maps its rhai scripts may build,
+    /// which bound the memory they take.
+    pub max_string_size: Option<usize>,
+    pub max_array_size: Option<usize>,
+    pub max_map_size: Option<usize>,
+    /// Bytes of its data directory.
+    pub data_bytes: Option<u64>,
+    /// Bytes of the `storage` of its JavaScript handlers.
+    pub storage_bytes: Option<u64>,
+}
+
+impl Quota {
+    /// The quota of requests and script time of the tenant `name`.
+    pub fn build(&self, name: &str) -> tide_rhai::Quota {
+        let mut quota = tide_rhai::Quota::new(name);
+        if let Some(requests) = self.requests_per_minute {
+            quota = quota.requests_per_minute(requests);
+        }
+        if let Some(seconds) = self.script_seconds_per_minute {
+            quota = quota.script_time_per_minute(Duration::from_secs_f64(seconds));
+        }
+        quota
+    }
+
+    pub fn limits(&self) -> tide_rhai::Limits {
+        tide_rhai::Limits {
+            max_string_size: self.max_string_size,
+            max_array_size: self.max_array_size,
+            max_map_size: self.max_map_size,
+            ..tide_rhai::Limits::default()
+        }
+    }
+
+    pub fn data(&self) -> tide_rhai::DataQuota {
+        let mut quota = tide_rhai::DataQuota::default();
+        if let Some(bytes) = self.data_bytes {
+            quota.max_total_size = bytes;
+        }
+        quota
+    }
+
+    pub fn storage(&self) -> tide_rhai::StorageQuota {
+        let mut quota = tide_rhai::StorageQuota::default();
+        if let Some(bytes) = self.storage_bytes {
+            quota.max_total_size = bytes;
+        }
+        quota
+    }
+}
+
+/// Sends some of the traffic to another app directory, for canary releases.
+/// Clients stay on the side they were put on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Split {
+    /// App directory of the canary, served with the same bindings.
+    pub dir: PathBuf,
+    /// Share of the clients put on the canary, from 0 to 100.
+    #[serde(default)]
+    pub percent: f64,
+    /// Requests with this header go to the canary.
+    pub header: Option<Match>,
+    /// Requests with this cookie go to the canary.
+    pub cookie: Option<Match>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Match {
+    pub name: String,
+    pub value: String,
+}
+
+impl Split {
+    /// `split` routing as configured.
+    pub fn apply<State>(&self, split: tide_rhai::Split<State>) -> tide_rhai::Split<State>
+    where
+        State: Clone + Send + Sync + 'static,
+    {
+        let mut split = split.percent(self.percent);
+        if let Some(m) = &self.header {
+            split = split.when_header(&m.name, &m.value);
+        }
+        if let Some(m) = &self.cookie {
+            split = split.when_cookie(&m.name, &m.value);
+        }
+        split
+    }
+}
+
+/// Copies some of the requests to a shadow to try a new version of the app
+/// on real traffic, dropping what it answers. Either `upstream` or `dir`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Mirror {
+    /// Share of the requests copied, from 0 to 100.
+    pub percent: f64,
+    /// Server the copies are sent to, under the same path.
+    pub upstream: Option<String>,
+    /// App directory serving the copies, without the data directory, mail
+    /// or S3, so it cannot change what the app serves.
+    pub dir: Option<PathBuf>,
+}
+
+/// An address the app is served on, `host:port` or `http+unix://path` for a
+/// Unix domain socket.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Listen {
+    pub addr: String,
+    /// Serves https instead of http, not for Unix domain sockets.
+    pub tls: Option<Tls>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tls {
+    /// PEM file of the certificate chain.
+    pub cert: PathBuf,
+    /// PEM file of the private key.
+    pub key: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![Listen {
+                addr: "127.0.0.1:8080".into(),
+                tls: None,
+            }],
+            reuse_port: false,
+            thread_per_core: false,
+            drain_timeout: 30,
+            connections: Connections::default(),
+            dir: PathBuf::from("./app/"),
+            watch: false,
+            log_level: log::LevelFilter::Info,
+            log_format: LogFormat::default(),
+            dev: false,
+            data_dir: PathBuf::from("./data/"),
+            storage_dir: PathBuf::from("./storage/"),
+            snapshot: None,
+            debug_port: None,
+            rhai_max_operations: None,
+            js_timeout: 30,
+            js_engine: tide_rhai::JsEngine::default(),
+            smtp: None,
+            s3: None,
+            remote_imports: None,
+            mirror: None,
+            split: None,
+            response_hooks: Vec::new(),
+            jobs: Vec::new(),
+            graphql: None,
+            explorer: Explorer::default(),
+            oauth: HashMap::new(),
+            oidc: None,
+            secrets: Secrets::default(),
+            integrity: None,
+            audit: None,
+            slow_scripts: SlowScripts::default(),
+            admin: Admin::default(),
+            tenants: Vec::new(),
+            tenants_dir: None,
+            tenants_data_dir: PathBuf::from("./tenant-data/"),
+            tenant_quota: Quota::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The tenants of `tenants` and those found in `tenants_dir`, except
+    /// those of a name already taken, with `tenant_quota` for those without
+    /// a quota.
+    pub fn tenants(&self) -> std::io::Result<Vec<Tenant>> {
+        let mut tenants = self.tenants.clone();
+        let mut found = Vec::new();
+        let entries = match &self.tenants_dir {
+            Some(dir) => std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type()?.is_dir() || name.starts_with('.') {
+                continue;
+            }
+            if tenants.iter().all(|t| t.name != name) {
+                found.push(Tenant {
+                    domains: vec![name.clone()],
+                    name,
+                    dir: entry.path(),
+                    quota: None,
+                });
+            }
+        }
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        tenants.extend(found);
+        for tenant in &mut tenants {
+            tenant
+                .quota
+                .get_or_insert_with(|| self.tenant_quota.clone());
+        }
+        Ok(tenants)
+    }
+
+    /// Loads the config file at `path`, falling back to defaults if it does not exist.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let s = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&s)?)
+    }
+}
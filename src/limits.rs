@@ -0,0 +1,491 @@
+//! A listener for TCP addresses holding back on clients before the app sees
+//! them, as `[connections]` configures: at most so many connections are
+//! served at once, the next ones waiting in the accept queue of the kernel,
+//! and connections gone idle or too slow to send their requests are closed.
+//!
+//! With TLS the handshake counts as the start of the first request: a
+//! client not done with it within the header timeout is closed, holding its
+//! permit until then like one sending half a head.
+
+use crate::config::Connections;
+use async_rustls::server::TlsStream;
+use async_rustls::TlsAcceptor;
+use async_std::channel::{bounded, Receiver, Sender};
+use async_std::io::{Read, Write};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use rustls::ServerConfig;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tide::listener::{ListenInfo, Listener, ToListener};
+use tide::{log, Server};
+
+/// Serves the connections of a TCP listener within [`Connections`], with
+/// TLS if it has a config.
+pub struct Limited<State> {
+    listener: Option<std::net::TcpListener>,
+    addr: SocketAddr,
+    connections: Connections,
+    tls: Option<TlsAcceptor>,
+    server: Option<Server<State>>,
+}
+
+impl<State> Limited<State> {
+    pub fn new(listener: std::net::TcpListener, connections: Connections) -> io::Result<Self> {
+        Ok(Self {
+            addr: listener.local_addr()?,
+            listener: Some(listener),
+            connections,
+            tls: None,
+            server: None,
+        })
+    }
+
+    /// Answers over TLS with `config`.
+    pub fn with_tls(mut self, config: ServerConfig) -> Self {
+        self.tls = Some(TlsAcceptor::from(Arc::new(config)));
+        self
+    }
+}
+
+impl<State> fmt::Debug for Limited<State> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Limited")
+            .field("addr", &self.addr)
+            .field("connections", &self.connections)
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+impl<State> fmt::Display for Limited<State> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        write!(f, "{}://{}", scheme, self.addr)
+    }
+}
+
+impl<State> ToListener<State> for Limited<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self> {
+        Ok(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl<State> Listener<State> for Limited<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn bind(&mut self, app: Server<State>) -> io::Result<()> {
+        self.server = Some(app);
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let server = self
+            .server
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+        let listener = self
+            .listener
+            .take()
+            .expect("`Listener::accept` may only be called once");
+        let listener = TcpListener::from(listener);
+        let permits = self.connections.max.map(Permits::new);
+        let mut incoming = listener.incoming();
+        loop {
+            // Without a permit nothing is accepted, so the kernel queues the
+            // next connections, up to the backlog.
+            let permit = match &permits {
+                Some(permits) => Some(permits.take().await),
+                None => None,
+            };
+            let stream = loop {
+                match incoming.next().await {
+                    Some(Ok(stream)) => break stream,
+                    Some(Err(e)) if transient(&e) => continue,
+                    Some(Err(e)) => {
+                        let delay = Duration::from_millis(500);
+                        log::error!("Error: {}. Pausing for {:?}.", e, delay);
+                        tide_rhai::rt::sleep(delay).await;
+                    }
+                    None => return Ok(()),
+                }
+            };
+            let server = server.clone();
+            let connections = self.connections.clone();
+            let tls = self.tls.clone();
+            tide_rhai::rt::spawn(async move {
+                if let Err(e) = serve(&server, stream, tls.as_ref(), &connections).await {
+                    log::debug!("Closing the connection: {}", e);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        vec![ListenInfo::new(
+            self.to_string(),
+            "tcp".into(),
+            self.tls.is_some(),
+        )]
+    }
+}
+
+fn transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// The connections that may be served at once, one permit each.
+struct Permits {
+    give: Sender<()>,
+    take: Receiver<()>,
+}
+
+/// Gives its permit back when the connection is closed.
+struct Permit(Sender<()>);
+
+impl Permits {
+    fn new(max: usize) -> Self {
+        let max = max.max(1);
+        let (give, take) = bounded(max);
+        for _ in 0..max {
+            let _ = give.try_send(());
+        }
+        Self { give, take }
+    }
+
+    async fn take(&self) -> Permit {
+        // The sender is kept, receiving never fails.
+        let _ = self.take.recv().await;
+        Permit(self.give.clone())
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let _ = self.0.try_send(());
+    }
+}
+
+/// Answers the requests of `stream`, over TLS with `tls`, until the client
+/// closes it, it times out or goes idle.
+async fn serve<State>(
+    server: &Server<State>,
+    stream: TcpStream,
+    tls: Option<&TlsAcceptor>,
+    connections: &Connections,
+) -> tide::http::Result<()>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    let done = match tls {
+        None => answer(server, stream.clone(), false, connections).await,
+        Some(tls) => match handshake(tls, stream.clone(), connections).await {
+            Ok(secured) => {
+                let secured = async_dup::Arc::new(async_dup::Mutex::new(secured));
+                answer(server, secured, true, connections).await
+            }
+            Err(e) => Err(e.into()),
+        },
+    };
+    // The clones async-h1 reads and writes with are gone with it.
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    done
+}
+
+/// The TLS stream of `stream`, if the client is done with the handshake
+/// within the header timeout.
+async fn handshake(
+    tls: &TlsAcceptor,
+    stream: TcpStream,
+    connections: &Connections,
+) -> io::Result<TlsStream<TcpStream>> {
+    let accepting = tls.accept(stream);
+    let Some(secs) = connections.header_timeout else {
+        return accepting.await;
+    };
+    match tide_rhai::rt::timeout(Duration::from_secs(secs), accepting).await {
+        Some(accepted) => accepted,
+        None => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "TLS handshake timed out",
+        )),
+    }
+}
+
+/// Answers the requests read from `stream`, a TCP stream or its TLS stream,
+/// without closing it.
+async fn answer<State, S>(
+    server: &Server<State>,
+    stream: S,
+    secure: bool,
+    connections: &Connections,
+) -> tide::http::Result<()>
+where
+    State: Clone + Send + Sync + 'static,
+    S: Peer + Read + Write + Clone + Send + Sync + Unpin + 'static,
+{
+    let local = stream.local_addr();
+    let peer = stream.peer_addr();
+    let activity = Arc::new(Activity::new());
+    let io = Tracked {
+        stream,
+        activity: activity.clone(),
+    };
+    let mut opts = async_h1::ServerOptions::new();
+    if let Some(secs) = connections.header_timeout {
+        opts = opts.with_headers_timeout(Duration::from_secs(secs));
+    }
+    let served = async_h1::accept_with_opts(
+        io,
+        |mut req| async {
+            if secure {
+                let _ = req.url_mut().set_scheme("https");
+            }
+            req.set_local_addr(local);
+            req.set_peer_addr(peer);
+            activity.busy.store(true, Ordering::Relaxed);
+            let res = server.respond(req).await;
+            activity.busy.store(false, Ordering::Relaxed);
+            activity.touch();
+            res
+        },
+        opts,
+    );
+    let Some(secs) = connections.idle_timeout else {
+        return served.await;
+    };
+    let idle = Duration::from_secs(secs);
+    let watched = async {
+        loop {
+            let left = idle.saturating_sub(activity.idle());
+            if left.is_zero() {
+                log::debug!("Closing the connection of {:?}, idle", peer);
+                return Ok(());
+            }
+            tide_rhai::rt::sleep(left).await;
+        }
+    };
+    served.race(watched).await
+}
+
+/// The addresses of a connection, whether TLS runs over it or not.
+trait Peer {
+    fn local_addr(&self) -> Option<SocketAddr>;
+    fn peer_addr(&self) -> Option<SocketAddr>;
+}
+
+impl Peer for TcpStream {
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpStream::local_addr(self).ok()
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+impl Peer for async_dup::Arc<async_dup::Mutex<TlsStream<TcpStream>>> {
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.lock().get_ref().0.local_addr().ok()
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.lock().get_ref().0.peer_addr().ok()
+    }
+}
+
+/// When a connection last read or wrote, and whether a request is answered.
+struct Activity {
+    start: Instant,
+    /// Milliseconds from `start`.
+    last: AtomicU64,
+    busy: AtomicBool,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    fn touch(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last.store(now, Ordering::Relaxed);
+    }
+
+    /// How long nothing happened, never while a request is answered.
+    fn idle(&self) -> Duration {
+        if self.busy.load(Ordering::Relaxed) {
+            return Duration::ZERO;
+        }
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+}
+
+/// A stream keeping its [`Activity`] up to date.
+#[derive(Clone)]
+struct Tracked<S> {
+    stream: S,
+    activity: Arc<Activity>,
+}
+
+impl<S: Read + Unpin> Read for Tracked<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(_)) = read {
+            self.activity.touch();
+        }
+        read
+    }
+}
+
+impl<S: Write + Unpin> Write for Tracked<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(_)) = written {
+            self.activity.touch();
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::reload::Certs;
+    use async_rustls::TlsConnector;
+
+    #[test]
+    fn holds_back_slow_clients() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let connections = Connections {
+            max: Some(1),
+            header_timeout: Some(1),
+            ..Connections::default()
+        };
+        let mut app = tide::new();
+        app.at("/").get(|_| async { Ok("ok") });
+        tide_rhai::rt::block_on(async move {
+            let mut listener = Limited::new(tcp, connections).unwrap();
+            listener.bind(app).await.unwrap();
+            tide_rhai::rt::spawn(async move { listener.accept().await });
+
+            // Half a head holds the only permit until the header timeout.
+            let mut slow = TcpStream::connect(addr).await.unwrap();
+            slow.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+            let waiting = Instant::now();
+            let mut next = TcpStream::connect(addr).await.unwrap();
+            next.write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut answer = String::new();
+            next.read_to_string(&mut answer).await.unwrap();
+            assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+            assert!(waiting.elapsed() >= Duration::from_millis(500));
+
+            let mut rest = Vec::new();
+            assert_eq!(slow.read_to_end(&mut rest).await.unwrap_or(0), 0);
+        });
+    }
+
+    /// The certificate of `localhost` in the temporary directory `name`, and
+    /// a client trusting it.
+    pub(crate) fn certificate(name: &str) -> (Arc<Certs>, TlsConnector) {
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        std::fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+        let certs = Certs::load(&dir.join("cert.pem"), &dir.join("key.pem")).unwrap();
+        let mut client = rustls::ClientConfig::new();
+        client
+            .root_store
+            .add(&rustls::Certificate(cert.serialize_der().unwrap()))
+            .unwrap();
+        (certs, TlsConnector::from(Arc::new(client)))
+    }
+
+    #[test]
+    fn holds_back_slow_handshakes() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let connections = Connections {
+            max: Some(1),
+            header_timeout: Some(1),
+            ..Connections::default()
+        };
+        let (certs, client) = certificate("rustvm_limits_tls");
+        let mut app = tide::new();
+        app.at("/")
+            .get(|req: tide::Request<()>| async move { Ok(req.url().scheme().to_string()) });
+        tide_rhai::rt::block_on(async move {
+            let mut listener = Limited::new(tcp, connections)
+                .unwrap()
+                .with_tls(certs.server_config());
+            assert_eq!(listener.to_string(), format!("https://{}", addr));
+            listener.bind(app).await.unwrap();
+            tide_rhai::rt::spawn(async move { listener.accept().await });
+
+            // A client never starting the handshake holds the only permit
+            // until the header timeout.
+            let mut slow = TcpStream::connect(addr).await.unwrap();
+            let waiting = Instant::now();
+            let next = TcpStream::connect(addr).await.unwrap();
+            let localhost = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+            let mut next = client.connect(localhost, next).await.unwrap();
+            next.write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut answer = Vec::new();
+            // The server closes without a close_notify.
+            let _ = next.read_to_end(&mut answer).await;
+            let answer = String::from_utf8_lossy(&answer);
+            assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+            assert!(answer.ends_with("https"), "{}", answer);
+            assert!(waiting.elapsed() >= Duration::from_millis(500));
+
+            let mut rest = Vec::new();
+            assert_eq!(slow.read_to_end(&mut rest).await.unwrap_or(0), 0);
+        });
+    }
+}
@@ -0,0 +1,61 @@
+//! The messages of the server and of scripts, written to stderr one per
+//! line, as text to read or as JSON for log collectors, with the values of
+//! secrets redacted.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `LEVEL target: message`
+    #[default]
+    Text,
+    /// `{"time":..,"level":..,"target":..,"message":..}`, time in milliseconds
+    Json,
+}
+
+struct Logger {
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let message = tide_rhai::redact(&message);
+        let line = match self.format {
+            LogFormat::Text => format!("{:<5} {}: {}", record.level(), record.target(), message),
+            LogFormat::Json => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis() as u64);
+                serde_json::json!({
+                    "time": time,
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message,
+                })
+                .to_string()
+            }
+        };
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs `level` and above in `format`, for the rest of the process.
+pub fn start(format: LogFormat, level: LevelFilter) {
+    // Only fails if a logger is set already, which then keeps logging.
+    let _ = log::set_boxed_logger(Box::new(Logger { format }));
+    log::set_max_level(level);
+}
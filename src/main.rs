@@ -14,11 +14,35 @@
 //    // Ok(())
 // }
 
+#[cfg(unix)]
+mod activation;
+mod bench;
+mod bencode;
+mod config;
+mod limits;
+mod logging;
+mod mmap;
+mod reload;
+mod scaffold;
+mod torrent;
 
-use tide_rhai::RhaiDir;
+use clap::{Parser, Subcommand};
+use config::{Config, Connections, Listen, Tls};
+use rustyline::error::ReadlineError;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tide_rhai::{
+    Allow, ApiExplorer, Debugger, EngineStats, Evaluated, GraphQl, Integrity, Jobs, JsDir, KvStore,
+    Metrics, Permissions, Profiler, ResponseHooks, RhaiDir, SlowScripts, Snapshot, Split, Tenants,
+};
 
-use tide::Request;
+use tide::listener::{ConcurrentListener, Listener};
 use tide::prelude::*;
+use tide::{Body, Endpoint, Request, Response, StatusCode};
+use tide_rustls::TlsListener;
 
 #[derive(Debug, Deserialize)]
 struct Animal {
@@ -26,17 +50,1737 @@ struct Animal {
     legs: u16,
 }
 
-#[async_std::main]
+#[derive(Parser)]
+#[command(
+    name = "rustvm",
+    about = "Serves rhai and JavaScript scripts over http"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    allow: AllowFlags,
+    /// Times scripts and the functions they call by route, writing folded stacks for flame graphs to FILE on exit
+    #[arg(long, value_name = "FILE", global = true)]
+    profile: Option<PathBuf>,
+}
+
+/// Deno-style permissions of the scripts. Without any of these flags scripts
+/// may reach any host and the whole data directory, but not the environment.
+/// Once one is given, scripts get only what the flags grant.
+#[derive(clap::Args)]
+struct AllowFlags {
+    /// Hosts, as `host` or `host:port`, scripts may connect to, all if no list is given
+    #[arg(long, value_name = "HOSTS", num_args = 0..=1, require_equals = true, value_delimiter = ',', global = true)]
+    allow_net: Option<Vec<String>>,
+    /// Paths scripts may read, all if no list is given
+    #[arg(long, value_name = "PATHS", num_args = 0..=1, require_equals = true, value_delimiter = ',', global = true)]
+    allow_read: Option<Vec<PathBuf>>,
+    /// Paths scripts may write, all if no list is given
+    #[arg(long, value_name = "PATHS", num_args = 0..=1, require_equals = true, value_delimiter = ',', global = true)]
+    allow_write: Option<Vec<PathBuf>>,
+    /// Environment variables, or prefixes like `APP_*`, scripts may read, all if no list is given
+    #[arg(long, value_name = "NAMES", num_args = 0..=1, require_equals = true, value_delimiter = ',', global = true)]
+    allow_env: Option<Vec<String>>,
+}
+
+fn allow<T>(flag: Option<Vec<T>>) -> Allow<T> {
+    match flag {
+        Some(list) if list.is_empty() => Allow::All,
+        Some(list) => Allow::Only(list),
+        None => Allow::none(),
+    }
+}
+
+impl AllowFlags {
+    fn permissions(self) -> Permissions {
+        let given = self.allow_net.is_some()
+            || self.allow_read.is_some()
+            || self.allow_write.is_some()
+            || self.allow_env.is_some();
+        if !given {
+            return Permissions::default();
+        }
+        Permissions {
+            net: allow(self.allow_net),
+            read: allow(self.allow_read),
+            write: allow(self.allow_write),
+            env: allow(self.allow_env),
+        }
+    }
+}
+
+/// The config file read unless `--config` names another.
+const CONFIG: &str = "rustvm.toml";
+
+/// The settings of `rustvm.toml`, each flag given overriding its setting,
+/// so an app can be served without a config file.
+#[derive(clap::Args, Default)]
+struct ServeArgs {
+    /// App directory to serve, `dir` of the config, ./app/ by default
+    dir: Option<PathBuf>,
+    /// Config file to read, skipped if it does not exist
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to serve on, `host:port` or `http+unix://path`, replacing those of the config; repeat for several
+    #[arg(long, value_name = "ADDR")]
+    listen: Vec<String>,
+    /// PEM certificate chain to serve https with, on every TCP address
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Binds TCP addresses with SO_REUSEPORT, --reuse-port=false to turn that of the config off
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    reuse_port: Option<bool>,
+    /// Serves the app once per CPU, each with its own engines and sockets, --thread-per-core=false to turn that of the config off
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    thread_per_core: Option<bool>,
+    /// Seconds to wait for requests in flight when stopping
+    #[arg(long, value_name = "SECS")]
+    drain_timeout: Option<u64>,
+    /// Connections served at once on every TCP address
+    #[arg(long, value_name = "N")]
+    max_connections: Option<usize>,
+    /// Connections the kernel queues until they are accepted
+    #[arg(long, value_name = "N")]
+    backlog: Option<i32>,
+    /// Seconds a connection may go without a byte read or written
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
+    /// Seconds a client has to send the head of a request, and for the TLS handshake
+    #[arg(long, value_name = "SECS")]
+    header_timeout: Option<u64>,
+    /// Reloads whenever a file of the app or the config changes, but for
+    /// the rhai and JavaScript modules the app reads again by itself;
+    /// --watch=false to turn that of the config off
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    watch: Option<bool>,
+    /// Shows error pages with details for failing scripts, --dev=false to turn that of the config off
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    dev: Option<bool>,
+    /// Port to debug rhai scripts on, with --dev
+    #[arg(long, value_name = "PORT")]
+    debug_port: Option<u16>,
+    /// Directory scripts may read and write through the `file_*` bindings, ./data/ by default
+    #[arg(long, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+    /// Directory of the store behind the `storage` object of JavaScript handlers, ./storage/ by default
+    #[arg(long, value_name = "DIR")]
+    storage_dir: Option<PathBuf>,
+    /// Snapshot built with `rustvm precompile` to serve scripts from
+    #[arg(long, value_name = "FILE")]
+    snapshot: Option<PathBuf>,
+    /// Rhai script rewriting every response, replacing those of the config; repeat for several, in order
+    #[arg(long, value_name = "FILE")]
+    response_hook: Vec<PathBuf>,
+    /// Directory of a `schema.graphql` and its resolvers to serve at /graphql
+    #[arg(long, value_name = "DIR")]
+    graphql: Option<PathBuf>,
+    /// Serves the API explorer, by default only with --dev
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    explorer: Option<bool>,
+    /// `.env` file of the secrets scripts get with `secret(name)`
+    #[arg(long, value_name = "FILE")]
+    env_file: Option<PathBuf>,
+    /// Directory of more tenants, one for every directory in it serving the domain of its name
+    #[arg(long, value_name = "DIR")]
+    tenants_dir: Option<PathBuf>,
+    /// Directory of the data directories of the tenants, ./tenant-data/ by default
+    #[arg(long, value_name = "DIR")]
+    tenants_data_dir: Option<PathBuf>,
+    /// Steps of the engine a rhai script may take
+    #[arg(long, value_name = "N")]
+    rhai_max_operations: Option<u64>,
+    /// Seconds a JavaScript handler may run
+    #[arg(long, value_name = "SECS")]
+    js_timeout: Option<u64>,
+    /// Engine running JavaScript handlers: boa, or v8 for builds with it
+    #[arg(long, value_name = "ENGINE", value_parser = js_engine)]
+    js_engine: Option<tide_rhai::JsEngine>,
+    /// error, warn, info, debug, trace or off
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<log::LevelFilter>,
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    log_format: Option<logging::LogFormat>,
+}
+
+fn js_engine(name: &str) -> Result<tide_rhai::JsEngine, String> {
+    use serde::de::{value, IntoDeserializer};
+    let name: value::StrDeserializer<'_, value::Error> = name.into_deserializer();
+    tide_rhai::JsEngine::deserialize(name).map_err(|e| e.to_string())
+}
+
+impl ServeArgs {
+    fn config_file(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(|| CONFIG.into())
+    }
+
+    /// The config file, with the flags given applied.
+    fn config(&self) -> Result<Config, config::ConfigError> {
+        let mut config = Config::load(self.config_file())?;
+        config.dir = self.dir.clone().unwrap_or(config.dir);
+        if !self.listen.is_empty() {
+            config.listen = self
+                .listen
+                .iter()
+                .map(|addr| Listen {
+                    addr: addr.clone(),
+                    tls: None,
+                })
+                .collect();
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            let tcp = config.listen.iter_mut();
+            for l in tcp.filter(|l| !l.addr.starts_with("http+unix://")) {
+                l.tls = Some(Tls {
+                    cert: cert.clone(),
+                    key: key.clone(),
+                });
+            }
+        }
+        config.reuse_port = self.reuse_port.unwrap_or(config.reuse_port);
+        config.thread_per_core = self.thread_per_core.unwrap_or(config.thread_per_core);
+        config.watch = self.watch.unwrap_or(config.watch);
+        config.dev = self.dev.unwrap_or(config.dev);
+        let connections = &mut config.connections;
+        connections.max = self.max_connections.or(connections.max);
+        connections.backlog = self.backlog.or(connections.backlog);
+        connections.idle_timeout = self.idle_timeout.or(connections.idle_timeout);
+        connections.header_timeout = self.header_timeout.or(connections.header_timeout);
+        config.data_dir = self.data_dir.clone().unwrap_or(config.data_dir);
+        config.storage_dir = self.storage_dir.clone().unwrap_or(config.storage_dir);
+        config.drain_timeout = self.drain_timeout.unwrap_or(config.drain_timeout);
+        config.js_timeout = self.js_timeout.unwrap_or(config.js_timeout);
+        config.js_engine = self.js_engine.unwrap_or(config.js_engine);
+        config.log_level = self.log_level.unwrap_or(config.log_level);
+        config.log_format = self.log_format.unwrap_or(config.log_format);
+        config.snapshot = self.snapshot.clone().or(config.snapshot);
+        config.debug_port = self.debug_port.or(config.debug_port);
+        config.rhai_max_operations = self.rhai_max_operations.or(config.rhai_max_operations);
+        if !self.response_hook.is_empty() {
+            config.response_hooks = self.response_hook.clone();
+        }
+        config.graphql = self.graphql.clone().or(config.graphql);
+        config.explorer.enabled = self.explorer.or(config.explorer.enabled);
+        config.secrets.env_file = self.env_file.clone().or(config.secrets.env_file);
+        config.tenants_dir = self.tenants_dir.clone().or(config.tenants_dir);
+        config.tenants_data_dir = self
+            .tenants_data_dir
+            .clone()
+            .unwrap_or(config.tenants_data_dir);
+        Ok(config)
+    }
+
+    /// What `--watch` looks at: the app and the config file.
+    fn watched(&self, config: &Config) -> Vec<PathBuf> {
+        if !config.watch {
+            return Vec::new();
+        }
+        let mut watched = vec![config.dir.clone(), self.config_file()];
+        watched.extend(config.tenants.iter().map(|t| t.dir.clone()));
+        watched.extend(config.tenants_dir.clone());
+        watched
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serves the app, the default without a command
+    Serve(ServeArgs),
+    /// Checks every script of an app and writes them to a snapshot to serve from
+    Precompile {
+        #[arg(default_value = "./app/")]
+        dir: PathBuf,
+        #[arg(short, long, default_value = "app.snapshot")]
+        output: PathBuf,
+    },
+    /// Creates a starter app in DIR: config, handlers, tests and a README of its routes
+    New {
+        dir: PathBuf,
+        #[arg(long, value_enum, default_value = "api")]
+        template: scaffold::Template,
+    },
+    /// Checks every script and import of an app and the files rustvm.toml names, failing on any problem
+    Check {
+        #[arg(default_value = "./app/")]
+        dir: PathBuf,
+        /// Config file of the app, the rustvm.toml of DIR or the closest directory above it by default
+        #[arg(long, value_name = "FILE")]
+        config: Option<PathBuf>,
+    },
+    /// Writes the handlers of an app and the modules they load to a snapshot, leaving out the rest
+    Bundle {
+        #[arg(default_value = "./app/")]
+        dir: PathBuf,
+        #[arg(short, long, default_value = "app.snapshot")]
+        output: PathBuf,
+        /// Handlers to start from, every script outside of node_modules by default
+        #[arg(short, long)]
+        entry: Vec<PathBuf>,
+    },
+    /// Evaluates rhai, or JavaScript with `--js`, line by line with the bindings of the app
+    Repl {
+        #[arg(long)]
+        js: bool,
+    },
+    /// Loads the running app with requests to PATHS and prints the latency of each and the compile cache hits of its dashboard
+    Bench {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Where the app runs
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        url: tide::http::Url,
+        /// Keep-alive connections sending requests at once
+        #[arg(long, default_value_t = 64)]
+        connections: usize,
+        /// How long to send requests, like 30s, 500ms or 2m
+        #[arg(long, default_value = "30s", value_parser = bench::parse_duration)]
+        duration: Duration,
+    },
+    /// Runs the `*_test.rhai`, `*.test.js` and `*.test.ts` files of the app, failing if any test fails
+    Test,
+    /// Reads bencoded files, like .torrent files
+    Bencode {
+        #[command(subcommand)]
+        command: BencodeCommand,
+    },
+    /// Reads .torrent files
+    Torrent {
+        #[command(subcommand)]
+        command: TorrentCommand,
+    },
+    /// Makes keys for and seals the encrypted secrets file of `[secrets]`
+    Secrets {
+        #[command(subcommand)]
+        command: SecretsCommand,
+    },
+    /// Makes keys for and signs the hashes of the scripts `[integrity]` checks
+    Integrity {
+        #[command(subcommand)]
+        command: IntegrityCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum IntegrityCommand {
+    /// Prints a new secret key to sign with and its public key for `[integrity]`
+    Keys,
+    /// Writes integrity.json and integrity.sig into DIR with the secret key in RUSTVM_SIGNING_KEY
+    Sign {
+        #[arg(default_value = "./app/")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretsCommand {
+    /// Prints a new random key, to keep in the variable of `key_env`
+    Key,
+    /// Encrypts a .env file with the key in the variable of `key_env`
+    Seal {
+        #[arg(default_value = ".env")]
+        file: PathBuf,
+        #[arg(short, long, default_value = "secrets.enc")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum TorrentCommand {
+    /// Prints the name, info hashes, trackers, pieces and files of torrents
+    Inspect {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Checks downloaded files against the pieces of a torrent
+    Verify {
+        torrent: PathBuf,
+        /// Directory the files of the torrent are in
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum BencodeCommand {
+    /// Prints a bencoded file as JSON, or as a tree with --pretty
+    Decode {
+        file: PathBuf,
+        #[arg(long, conflicts_with = "pretty")]
+        json: bool,
+        #[arg(long)]
+        pretty: bool,
+        /// How strings that are not UTF-8 are written
+        #[arg(long, value_enum, default_value = "hex")]
+        binary: bencode::Binary,
+    },
+    /// Writes JSON, in the form decode prints it, as canonical bencode
+    Encode {
+        /// JSON file to read, stdin if missing or `-`
+        file: Option<PathBuf>,
+        /// File to write the bencode to, stdout if missing
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[cfg_attr(not(feature = "tokio"), async_std::main)]
+#[cfg_attr(feature = "tokio", tokio::main)]
 async fn main() -> tide::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Precompile { dir, output }) => precompile(&dir, &output),
+        Some(Command::New { dir, template }) => new_app(&dir, template),
+        Some(Command::Check { dir, config }) => check(&dir, config),
+        Some(Command::Bundle { dir, output, entry }) => bundle(&dir, &output, &entry),
+        Some(Command::Repl { js }) => repl(js, cli.allow.permissions()),
+        Some(Command::Test) => test(cli.allow.permissions()),
+        Some(Command::Bench {
+            paths,
+            url,
+            connections,
+            duration,
+        }) => {
+            let rooted = |p: String| {
+                if p.starts_with('/') {
+                    p
+                } else {
+                    format!("/{}", p)
+                }
+            };
+            let options = bench::Options {
+                url,
+                paths: paths.into_iter().map(rooted).collect(),
+                connections,
+                duration,
+                admin_token: Config::load(CONFIG)
+                    .map(|c| c.admin)
+                    .unwrap_or_default()
+                    .token(),
+            };
+            run_bench(&options).await
+        }
+        Some(Command::Bencode { command }) => match command {
+            BencodeCommand::Decode {
+                file,
+                json: _,
+                pretty,
+                binary,
+            } => decode_bencode(&file, pretty, binary),
+            BencodeCommand::Encode { file, output } => {
+                encode_bencode(file.as_deref(), output.as_deref())
+            }
+        },
+        Some(Command::Torrent {
+            command: TorrentCommand::Inspect { files },
+        }) => inspect_torrents(&files),
+        Some(Command::Torrent {
+            command: TorrentCommand::Verify { torrent, dir },
+        }) => verify_torrent(&torrent, &dir),
+        Some(Command::Secrets { command }) => match command {
+            SecretsCommand::Key => {
+                println!("{}", tide_rhai::Secrets::generate_key());
+                Ok(())
+            }
+            SecretsCommand::Seal { file, output } => seal_secrets(&file, &output),
+        },
+        Some(Command::Integrity { command }) => match command {
+            IntegrityCommand::Keys => {
+                let (secret, public) = Integrity::generate_keys();
+                println!("secret key: {}\npublic key: {}", secret, public);
+                Ok(())
+            }
+            IntegrityCommand::Sign { dir } => sign(&dir),
+        },
+        Some(Command::Serve(args)) => serve(args, cli.allow.permissions(), cli.profile).await,
+        None => serve(ServeArgs::default(), cli.allow.permissions(), cli.profile).await,
+    }
+}
+
+/// Prints the bencoded `file` as JSON, or as a tree if `pretty`.
+fn decode_bencode(file: &Path, pretty: bool, binary: bencode::Binary) -> tide::Result<()> {
+    let bytes = mmap::open(file)?;
+    let value = bencode::decode(&bytes).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", file.display(), e),
+        )
+    })?;
+    if pretty {
+        println!("{}", value.tree(binary));
+    } else {
+        println!("{}", serde_json::to_string_pretty(&value.to_json(binary))?);
+    }
+    Ok(())
+}
+
+/// Writes the JSON of `file` as bencode to `output`.
+fn encode_bencode(file: Option<&Path>, output: Option<&Path>) -> tide::Result<()> {
+    let json: serde_json::Value = match file {
+        Some(path) if path != Path::new("-") => serde_json::from_slice(&std::fs::read(path)?)?,
+        _ => serde_json::from_reader(io::stdin().lock())?,
+    };
+    let value = bencode::Bencode::from_json(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let bytes = value.encode();
+    match output {
+        Some(path) => std::fs::write(path, bytes)?,
+        None => io::Write::write_all(&mut io::stdout().lock(), &bytes)?,
+    }
+    Ok(())
+}
+
+/// Prints every torrent of `files`, parsing copies of the same file once.
+fn inspect_torrents(files: &[PathBuf]) -> tide::Result<()> {
+    let cache = bencode::BencodeCache::new(files.len());
+    for (i, file) in files.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        inspect_torrent(file, &cache)?;
+    }
+    let (duplicates, _) = cache.hits();
+    if duplicates > 0 {
+        println!("\n{} of the files were duplicates", duplicates);
+    }
+    Ok(())
+}
+
+fn inspect_torrent(file: &Path, cache: &bencode::BencodeCache) -> tide::Result<()> {
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let t = torrent::Torrent::parse_cached(&mmap::open(file)?, cache).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", file.display(), e),
+        )
+    })?;
+    println!("name:          {}", t.name);
+    if let Some(hash) = t.info_hash_v1 {
+        println!("info hash v1:  {}", hex(&hash));
+    }
+    if let Some(hash) = t.info_hash_v2 {
+        println!("info hash v2:  {}", hex(&hash));
+    }
+    println!("piece length:  {}", t.piece_length);
+    if !t.pieces.is_empty() {
+        println!("pieces:        {}", t.pieces.len());
+    }
+    println!("total size:    {}", t.size());
+    println!("trackers:");
+    for tracker in &t.trackers {
+        println!("  {}", tracker);
+    }
+    println!("files:");
+    let width = t.files.iter().map(|f| f.length.to_string().len()).max();
+    for f in &t.files {
+        println!(
+            "  {:>width$}  {}",
+            f.length,
+            f.path,
+            width = width.unwrap_or(0)
+        );
+    }
+    Ok(())
+}
+
+/// Checks the files below `dir` against the pieces of `file`, failing if
+/// any piece does not match.
+fn verify_torrent(file: &Path, dir: &Path) -> tide::Result<()> {
+    let t = torrent::Torrent::parse(&mmap::open(file)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", file.display(), e),
+        )
+    })?;
+    let verification = t.verify(dir)?;
+    for missing in &verification.missing {
+        println!("missing: {}", missing);
+    }
+    for piece in &verification.failed {
+        println!("piece {} does not match", piece);
+    }
+    let ok = verification
+        .pieces
+        .saturating_sub(verification.failed.len());
+    println!("{} of {} pieces match", ok, verification.pieces);
+    if !verification.failed.is_empty() {
+        let message = format!("{} pieces do not match", verification.failed.len());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+    }
+    Ok(())
+}
+
+/// Writes the .env `file` to `output` encrypted with the key of the
+/// `[secrets]` of the config file.
+fn seal_secrets(file: &Path, output: &Path) -> tide::Result<()> {
+    let key_env = Config::load(CONFIG)?.secrets.key_env;
+    let key = std::env::var(&key_env).map_err(|_| {
+        let message = format!("set {} to a key from `rustvm secrets key`", key_env);
+        io::Error::new(io::ErrorKind::NotFound, message)
+    })?;
+    let sealed = tide_rhai::Secrets::seal(&std::fs::read_to_string(file)?, &key)?;
+    std::fs::write(output, sealed)?;
+    println!("Sealed {} into {}", file.display(), output.display());
+    Ok(())
+}
+
+/// Signs the hashes of the scripts of `dir`.
+fn sign(dir: &Path) -> tide::Result<()> {
+    let key = std::env::var("RUSTVM_SIGNING_KEY").map_err(|_| {
+        let message = "set RUSTVM_SIGNING_KEY to a secret key from `rustvm integrity keys`";
+        io::Error::new(io::ErrorKind::NotFound, message)
+    })?;
+    let count = Integrity::sign(dir, &key)?;
+    println!(
+        "Signed the hashes of {} scripts in {}",
+        count,
+        dir.display()
+    );
+    Ok(())
+}
+
+fn precompile(dir: &Path, output: &Path) -> tide::Result<()> {
+    let snapshot = Snapshot::build(dir)?;
+    snapshot.save(output)?;
+    println!("Wrote {} scripts to {}", snapshot.len(), output.display());
+    Ok(())
+}
+
+async fn run_bench(options: &bench::Options) -> tide::Result<()> {
+    println!(
+        "Requesting {} routes from {} connections for {:?}",
+        options.paths.len(),
+        options.connections,
+        options.duration
+    );
+    print!("{}", bench::run(options).await?);
+    Ok(())
+}
+
+fn new_app(dir: &Path, template: scaffold::Template) -> tide::Result<()> {
+    for file in scaffold::create(dir, template)? {
+        println!("Wrote {}", file.display());
+    }
+    println!(
+        "\nServe it with:\n\n    cd {}\n    rustvm serve --watch",
+        dir.display()
+    );
+    Ok(())
+}
+
+/// The config file of the app in `dir`: the `rustvm.toml` of `dir` or of
+/// the closest directory above it, which `serve` is run from.
+fn app_config(dir: &Path) -> PathBuf {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors()
+        .map(|d| d.join(CONFIG))
+        .find(|file| file.is_file())
+        .unwrap_or_else(|| CONFIG.into())
+}
+
+/// Prints every problem of the app in `dir`, of the apps its config file
+/// splits and mirrors traffic to and of the files it names, failing if
+/// there is one. The paths of the config are relative to its directory,
+/// as they are to the one `serve` is run from.
+fn check(dir: &Path, config: Option<PathBuf>) -> tide::Result<()> {
+    let file = config.unwrap_or_else(|| app_config(dir));
+    let root = file.parent().unwrap_or(Path::new("")).to_path_buf();
+    let at = |path: &Path| root.join(path);
+    let name = file.display();
+    let mut problems = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    match Config::load(&file) {
+        Ok(config) => {
+            dirs.extend(config.split.iter().map(|s| at(&s.dir)));
+            dirs.extend(
+                config
+                    .mirror
+                    .iter()
+                    .filter_map(|m| m.dir.as_deref().map(at)),
+            );
+            for script in config.response_hooks.iter().map(|s| at(s)) {
+                match ResponseHooks::new().script(&script) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => problems.push(format!(
+                        "{}: response hook {} does not exist",
+                        name,
+                        script.display()
+                    )),
+                    Err(e) => problems.push(format!("{}: response hook {}", name, e)),
+                    Ok(_) => {}
+                }
+            }
+            for job in config.jobs.iter().filter(|j| !at(&j.script).is_file()) {
+                problems.push(format!(
+                    "{}: job {} does not exist",
+                    name,
+                    job.script.display()
+                ));
+            }
+            if let Some(path) = &config.graphql {
+                let loaded =
+                    RhaiDir::new("/*", dir).and_then(|d| GraphQl::load(&d, at(path), "/graphql"));
+                if let Err(e) = loaded {
+                    problems.push(format!("{}: graphql {}: {}", name, path.display(), e));
+                }
+            }
+            if let Some(path) = &config.snapshot {
+                if let Err(e) = Snapshot::load(at(path)) {
+                    problems.push(format!("{}: snapshot {}: {}", name, path.display(), e));
+                }
+            }
+            for tls in config.listen.iter().filter_map(|l| l.tls.as_ref()) {
+                for f in [&tls.cert, &tls.key]
+                    .into_iter()
+                    .filter(|f| !at(f).is_file())
+                {
+                    problems.push(format!("{}: {} does not exist", name, f.display()));
+                }
+            }
+        }
+        Err(e) => problems.push(format!("{}: {}", name, e)),
+    }
+    for dir in &dirs {
+        match tide_rhai::check(dir) {
+            Ok(found) => problems.extend(found.iter().map(ToString::to_string)),
+            Err(e) => problems.push(format!("{}: {}", dir.display(), e)),
+        }
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if !problems.is_empty() {
+        let message = format!("{} problems found", problems.len());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+    }
+    println!("No problems found in {}", dir.display());
+    Ok(())
+}
+
+fn bundle(dir: &Path, output: &Path, entries: &[PathBuf]) -> tide::Result<()> {
+    let snapshot = Snapshot::bundle(dir, entries)?;
+    snapshot.save(output)?;
+    println!(
+        "Bundled {} scripts into {}",
+        snapshot.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// The debugger `config` asks for. It listens until the process exits, so
+/// it is made once and shared by every generation.
+fn debugger(config: &Config) -> io::Result<Option<Arc<Debugger>>> {
+    match (config.dev, config.debug_port) {
+        (true, Some(port)) => Debugger::listen(("127.0.0.1", port)).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The directories of the app of `config`, set up as it says, debugged by
+/// `debugger`.
+fn app(
+    config: &Config,
+    permissions: Permissions,
+    debugger: Option<&Arc<Debugger>>,
+) -> tide::Result<(RhaiDir, JsDir)> {
+    let (mut dir, mut js) = dirs(config, &config.dir, permissions, None)?;
+    if let Some(debugger) = debugger {
+        dir = dir.with_shared_debugger(debugger.clone());
+    }
+    if let Some(path) = &config.snapshot {
+        let snapshot = Arc::new(Snapshot::load(path)?);
+        dir = dir.with_snapshot(snapshot.clone());
+        js = js.with_snapshot(snapshot);
+    }
+    Ok((dir, js))
+}
+
+/// The directories serving the scripts of `root` with the bindings of
+/// `config`, with the data, storage and quota of `tenant` if it is one.
+fn dirs(
+    config: &Config,
+    root: &Path,
+    permissions: Permissions,
+    tenant: Option<&config::Tenant>,
+) -> tide::Result<(RhaiDir, JsDir)> {
+    let (data_dir, namespace) = match tenant {
+        Some(t) => (
+            config.tenants_data_dir.join(&t.name),
+            format!("tenant:{}", t.name),
+        ),
+        None => (config.data_dir.clone(), "app".to_string()),
+    };
+    let quota = tenant.and_then(|t| t.quota.clone()).unwrap_or_default();
+    let secrets = config.secrets.load()?;
+    let integrity = match &config.integrity {
+        Some(c) => Some(Arc::new(Integrity::load(root, &c.public_key)?)),
+        None => None,
+    };
+    // Copies serving a core each keep their compiled scripts to themselves.
+    let mut dir = RhaiDir::builder("/*", root)
+        .engine(bencode::register)
+        .shared_cache(!config.thread_per_core)
+        .limits(quota.limits())
+        .build()?
+        .with_dev_mode(config.dev)
+        .with_data_dir(&data_dir)?
+        .with_data_quota(quota.data())
+        .with_permissions(permissions.clone())
+        .with_secrets(secrets.clone());
+    if let Some(operations) = config.rhai_max_operations {
+        dir = dir.with_max_operations(operations);
+    }
+    if let Some(smtp) = &config.smtp {
+        dir = dir.with_mail(smtp.clone())?;
+    }
+    if let Some(s3) = &config.s3 {
+        dir = dir.with_s3(s3.clone())?;
+    }
+    if !config.oauth.is_empty() {
+        dir = dir.with_oauth(config.oauth.clone());
+    }
+    let mut js = JsDir::new("/js/*", root)?
+        .with_dev_mode(config.dev)
+        .with_timeout(Duration::from_secs(config.js_timeout))
+        .with_engine(config.js_engine)
+        .with_data_dir(&data_dir)?
+        .with_data_quota(quota.data())
+        .with_storage_quota(quota.storage())
+        .with_storage(&store(&config.storage_dir)?, &namespace)?
+        .with_permissions(permissions)
+        .with_secrets(secrets)
+        .with_global("bencode", bencode::js_global);
+    if let Some(remote) = &config.remote_imports {
+        js = js.with_remote_imports(remote.clone())?;
+    }
+    if config.thread_per_core {
+        js = js.with_pool_size(1);
+    }
+    if let Some(integrity) = integrity {
+        dir = dir.with_integrity(integrity.clone());
+        js = js.with_integrity(integrity);
+    }
+    if let Some(audit) = &config.audit {
+        let audit = Arc::new(audit.open()?);
+        dir = dir.with_audit(audit.clone());
+        js = js.with_audit(audit);
+    }
+    Ok((dir, js))
+}
+
+/// `dirs` reporting to the profiler and the slow scripts of every
+/// generation.
+fn observed(
+    (mut dir, mut js): (RhaiDir, JsDir),
+    profiler: Option<&Arc<Profiler>>,
+    slow: &Arc<SlowScripts>,
+) -> (RhaiDir, JsDir) {
+    if let Some(profiler) = profiler {
+        dir = dir.with_profiler(profiler.clone());
+        js = js.with_profiler(profiler.clone());
+    }
+    (
+        dir.with_slow_scripts(slow.clone()),
+        js.with_slow_scripts(slow.clone()),
+    )
+}
+
+/// The store in `dir`, opened once: it cannot be opened again while a
+/// reloaded app replaces the one using it.
+fn store(dir: &Path) -> std::io::Result<KvStore> {
+    static STORES: OnceLock<Mutex<HashMap<PathBuf, KvStore>>> = OnceLock::new();
+    let mut stores = STORES.get_or_init(Default::default).lock().unwrap();
+    if let Some(store) = stores.get(dir) {
+        return Ok(store.clone());
+    }
+    let store = KvStore::open(dir)?;
+    stores.insert(dir.to_path_buf(), store.clone());
+    Ok(store)
+}
+
+/// The app as `rustvm.toml` was when it was loaded, replaced by a reload.
+struct Generation {
+    dir: Arc<RhaiDir>,
+    js: Arc<JsDir>,
+    /// The directories of `[split]`, answering some of the traffic.
+    canary: Option<(Arc<RhaiDir>, Arc<JsDir>)>,
+    /// The directories of every tenant, answering the requests to its
+    /// domains within its quota.
+    tenants: Vec<(config::Tenant, Arc<RhaiDir>, Arc<JsDir>)>,
+    /// What answers the routes of `dir` and `js`, split with the canary and
+    /// the tenants.
+    rhai_routes: Arc<dyn Endpoint<()>>,
+    js_routes: Arc<dyn Endpoint<()>>,
+    graphql: Arc<dyn Endpoint<()>>,
+    admin: Arc<dyn Endpoint<()>>,
+    metrics: Arc<Metrics>,
+    jobs: Arc<Jobs>,
+    hooks: ResponseHooks,
+    /// The files compiled at start, the response hooks and the GraphQL
+    /// schema and resolvers, picked up only by a reload.
+    compiled: Vec<PathBuf>,
+}
+
+impl Generation {
+    async fn start(
+        config: &Config,
+        shard: usize,
+        permissions: Permissions,
+        profiler: Option<&Arc<Profiler>>,
+        slow: &Arc<SlowScripts>,
+        debugger: Option<&Arc<Debugger>>,
+    ) -> tide::Result<Self> {
+        let app = app(config, permissions.clone(), debugger)?;
+        let (dir, js) = observed(app, profiler, slow);
+        dir.start()?;
+        js.start().await?;
+        let dir = Arc::new(dir);
+        let js = Arc::new(js);
+        let mut jobs = Jobs::new();
+        // Once for the server, not for every copy serving a core.
+        if shard == 0 {
+            for job in &config.jobs {
+                let (d, script) = (dir.clone(), job.script.clone());
+                jobs = jobs.job(
+                    job.script.display().to_string(),
+                    Duration::from_secs(job.every_secs),
+                    move || d.run_script(&script).map_err(|e| e.to_string()),
+                );
+            }
+        }
+        let jobs = Arc::new(jobs);
+        jobs.start()?;
+        let mut tenants = Vec::new();
+        for tenant in config.tenants()? {
+            let dirs = dirs(config, &tenant.dir, permissions.clone(), Some(&tenant))?;
+            let (tenant_dir, tenant_js) = observed(dirs, profiler, slow);
+            tenant_dir.start()?;
+            tenant_js.start().await?;
+            tenants.push((tenant, Arc::new(tenant_dir), Arc::new(tenant_js)));
+        }
+        let (r, j) = (dir.clone(), js.clone());
+        let mut metrics = Metrics::new()
+            .watch(move || r.stats())
+            .watch(move || j.stats());
+        let quotas: Vec<_> = tenants
+            .iter()
+            .map(|(t, _, _)| Arc::new(t.quota.clone().unwrap_or_default().build(&t.name)))
+            .collect();
+        for quota in &quotas {
+            metrics = metrics.with_quota(quota.clone());
+        }
+        for (tenant, tenant_dir, tenant_js) in &tenants {
+            let (name, r) = (Some(tenant.name.clone()), tenant_dir.clone());
+            metrics = metrics.watch(move || EngineStats {
+                tenant: name.clone(),
+                ..r.stats()
+            });
+            let (name, j) = (Some(tenant.name.clone()), tenant_js.clone());
+            metrics = metrics.watch(move || EngineStats {
+                tenant: name.clone(),
+                ..j.stats()
+            });
+        }
+        let metrics = Arc::new(metrics);
+
+        let mut hooks = ResponseHooks::new();
+        for script in &config.response_hooks {
+            hooks = hooks.script(script)?;
+        }
+        let compiled = config
+            .response_hooks
+            .iter()
+            .chain(&config.graphql)
+            .filter_map(|p| p.canonicalize().ok())
+            .collect();
+        let graphql: Arc<dyn Endpoint<()>> = match &config.graphql {
+            Some(path) => Arc::new(GraphQl::load(&dir, path, "/graphql")?),
+            None => Arc::new(|_: Request<()>| async {
+                Ok::<_, tide::Error>(Response::new(StatusCode::NotFound))
+            }),
+        };
+        let admin: Arc<dyn Endpoint<()>> = match config.admin.token() {
+            Some(token) => {
+                let (r, j) = (dir.clone(), js.clone());
+                let mut admin = tide_rhai::Admin::new(token)
+                    .watch(move || r.report())
+                    .watch(move || j.report())
+                    .with_slow_scripts(slow.clone())
+                    .with_jobs(jobs.clone());
+                for (tenant, tenant_dir, tenant_js) in &tenants {
+                    let (name, r) = (Some(tenant.name.clone()), tenant_dir.clone());
+                    admin = admin.watch(move || {
+                        let mut report = r.report();
+                        report.stats.tenant = name.clone();
+                        report
+                    });
+                    let (name, j) = (Some(tenant.name.clone()), tenant_js.clone());
+                    admin = admin.watch(move || {
+                        let mut report = j.report();
+                        report.stats.tenant = name.clone();
+                        report
+                    });
+                }
+                Arc::new(admin)
+            }
+            None => Arc::new(|_: Request<()>| async {
+                Ok::<_, tide::Error>(Response::new(StatusCode::NotFound))
+            }),
+        };
+        let mut rhai_routes: Arc<dyn Endpoint<()>> = dir.clone();
+        let mut js_routes: Arc<dyn Endpoint<()>> = js.clone();
+        let mut canary = None;
+        if let Some(split) = &config.split {
+            let (canary_dir, canary_js) = dirs(config, &split.dir, permissions.clone(), None)?;
+            canary_dir.start()?;
+            canary_js.start().await?;
+            let (canary_dir, canary_js) = (Arc::new(canary_dir), Arc::new(canary_js));
+            rhai_routes =
+                Arc::new(split.apply(Split::new(shared(dir.clone()), shared(canary_dir.clone()))));
+            js_routes =
+                Arc::new(split.apply(Split::new(shared(js.clone()), shared(canary_js.clone()))));
+            canary = Some((canary_dir, canary_js));
+        }
+        if !tenants.is_empty() {
+            let mut rhai = Tenants::new().fallback(shared(rhai_routes));
+            let mut js = Tenants::new().fallback(shared(js_routes));
+            for ((tenant, tenant_dir, tenant_js), quota) in tenants.iter().zip(&quotas) {
+                rhai = rhai.tenant(&tenant.domains, quota.throttle(shared(tenant_dir.clone())));
+                js = js.tenant(&tenant.domains, quota.throttle(shared(tenant_js.clone())));
+            }
+            rhai_routes = Arc::new(rhai);
+            js_routes = Arc::new(js);
+        }
+        Ok(Self {
+            dir,
+            js,
+            canary,
+            tenants,
+            rhai_routes,
+            js_routes,
+            graphql,
+            admin,
+            metrics,
+            jobs,
+            hooks,
+            compiled,
+        })
+    }
+
+    /// Whether the directories of the generation pick up an edit of `path`
+    /// by themselves, like [`RhaiDir::picks_up_edits`], and nothing compiled
+    /// at start uses it.
+    fn picks_up_edits(&self, path: &Path) -> bool {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.compiled.iter().any(|c| canonical.starts_with(c)) {
+            return false;
+        }
+        let canary = self.canary.iter().map(|(d, j)| (d, j));
+        let tenants = self.tenants.iter().map(|(_, d, j)| (d, j));
+        std::iter::once((&self.dir, &self.js))
+            .chain(canary)
+            .chain(tenants)
+            .any(|(d, j)| d.picks_up_edits(&canonical) || j.picks_up_edits(&canonical))
+    }
+
+    async fn stop(&self) -> tide::Result<()> {
+        self.jobs.stop();
+        let stopped_js = self.js.shutdown().await;
+        self.dir.shutdown()?;
+        if let Some((dir, js)) = &self.canary {
+            js.shutdown().await?;
+            dir.shutdown()?;
+        }
+        for (_, dir, js) in &self.tenants {
+            js.shutdown().await?;
+            dir.shutdown()?;
+        }
+        Ok(stopped_js?)
+    }
+}
+
+/// The server answering with the live generation of `live`, its middleware
+/// and routes.
+fn server(
+    config: &Config,
+    live: &Arc<reload::Swap<Generation>>,
+    in_flight: &reload::InFlight,
+    permissions: &Permissions,
+) -> tide::Result<tide::Server<()>> {
     let mut app = tide::new();
+    app.with(in_flight.clone());
+    if let Some(config) = &config.oidc {
+        app.with(tide_rhai::Oidc::new(config.clone())?);
+    }
+    // Like the addresses, kept by reloads.
+    if let Some(config) = &config.mirror {
+        app.with(mirror(config, permissions.clone())?);
+    }
+    app.with(Hooks(live.clone()));
+    app.at("/metrics").get(current(live, |g| &g.metrics));
+    app.at("/admin").get(current(live, |g| &g.admin));
+    let l = live.clone();
+    app.at("/openapi.json").get(move |_| {
+        let dir = l.current().dir.clone();
+        async move { Body::from_json(&dir.openapi("rustvm", env!("CARGO_PKG_VERSION"))) }
+    });
+    if config.explorer.enabled.unwrap_or(config.dev) {
+        app.at(&config.explorer.path)
+            .get(ApiExplorer::new("/openapi.json"));
+    }
     app.at("/orders/shoes").post(order_shoes);
-    app.at("/*")
-    .get(RhaiDir::new("/*", "./app/").unwrap());
-    app.listen("127.0.0.1:8080").await?;
+    app.at("/graphql").all(current(live, |g| &g.graphql));
+    app.at("/js/*").all(current(live, |g| &g.js_routes));
+    app.at("/*").all(current(live, |g| &g.rhai_routes));
+    Ok(app)
+}
+
+/// Accepts the connections of `listener` on a thread of its own, until the
+/// sender answered is dropped. With a thread for every core, no acceptor
+/// waits for the others, and every one answers with its own generation.
+fn accept(
+    shard: usize,
+    mut listener: impl tide::listener::Listener<()>,
+) -> io::Result<async_std::channel::Sender<()>> {
+    use async_std::prelude::FutureExt;
+    let (stop, stopped) = async_std::channel::bounded::<()>(1);
+    std::thread::Builder::new()
+        .name(format!("rustvm-accept-{}", shard))
+        .spawn(move || {
+            let stopped = async move {
+                let _ = stopped.recv().await;
+                Ok(())
+            };
+            if let Err(e) = tide_rhai::rt::block_on(listener.accept().race(stopped)) {
+                tide::log::error!("Accepting connections failed: {}", e);
+            }
+        })?;
+    Ok(stop)
+}
+
+async fn serve(
+    args: ServeArgs,
+    permissions: Permissions,
+    profile: Option<PathBuf>,
+) -> tide::Result<()> {
+    let config = args.config()?;
+    logging::start(config.log_format, config.log_level);
+    // The generations of the shards once they started, for the watcher to
+    // leave out the edits they pick up by themselves.
+    let generations: Arc<OnceLock<Vec<Arc<reload::Swap<Generation>>>>> = Arc::default();
+    let g = generations.clone();
+    let signals = reload::signals(args.watched(&config), move |path| {
+        g.get().map_or(false, |lives| {
+            lives.iter().any(|l| l.current().picks_up_edits(path))
+        })
+    })?;
+    let profiler = profile.as_ref().map(|_| Arc::new(Profiler::new()));
+    // Kept by reloads, regressions show against the times from before.
+    let slow = Arc::new(config.slow_scripts.build());
+    // Like the addresses, kept by reloads and shared by the shards.
+    let debugger = debugger(&config)?;
+    let shards = match config.thread_per_core {
+        true => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        false => 1,
+    };
+    if shards > 1
+        && config
+            .listen
+            .iter()
+            .any(|l| l.addr.starts_with("http+unix://"))
+    {
+        let message = "Only TCP addresses can be served thread per core";
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+    }
+
+    let in_flight = reload::InFlight::default();
+    let mut lives = Vec::new();
+    let mut certs = Vec::new();
+    let mut acceptors = Vec::new();
+    for shard in 0..shards {
+        let live = Arc::new(reload::Swap::new(
+            Generation::start(
+                &config,
+                shard,
+                permissions.clone(),
+                profiler.as_ref(),
+                &slow,
+                debugger.as_ref(),
+            )
+            .await?,
+        ));
+        let app = server(&config, &live, &in_flight, &permissions)?;
+        // Every shard binds sockets of its own, the kernel balances them.
+        let reuse_port = config.reuse_port || shards > 1;
+        let (listener, shard_certs) =
+            listener(&config.listen, &config.connections, reuse_port, shards == 1)?;
+        let listener = app.bind(listener).await?;
+        if shard == 0 {
+            for info in listener.info() {
+                println!("Listening on {}", info);
+            }
+        }
+        acceptors.push(accept(shard, listener)?);
+        certs.extend(shard_certs);
+        lives.push(live);
+    }
+    if shards > 1 {
+        println!("Serving on {} threads", shards);
+    }
+    let _ = generations.set(lives.clone());
+
+    while let Ok(reload::Signal::Reload) = signals.recv().await {
+        tide::log::info!("Reloading");
+        // Addresses are kept, changing them takes a restart.
+        for c in &certs {
+            if let Err(e) = c.reload() {
+                tide::log::error!("Keeping the TLS certificate: {}", e);
+            }
+        }
+        let config = match args.config() {
+            Ok(config) => config,
+            Err(e) => {
+                tide::log::error!("Keeping the app, reloading failed: {}", e);
+                continue;
+            }
+        };
+        for (shard, live) in lives.iter().enumerate() {
+            match Generation::start(
+                &config,
+                shard,
+                permissions.clone(),
+                profiler.as_ref(),
+                &slow,
+                debugger.as_ref(),
+            )
+            .await
+            {
+                Ok(next) => {
+                    if let Err(e) = live.replace(next).stop().await {
+                        tide::log::error!("Stopping the replaced app failed: {}", e);
+                    }
+                }
+                Err(e) => tide::log::error!("Keeping the app, reloading failed: {}", e),
+            }
+        }
+    }
+
+    tide::log::info!("Stopping");
+    drop(acceptors);
+    let drain_timeout = Duration::from_secs(config.drain_timeout);
+    let left = in_flight.drain(drain_timeout).await;
+    if left > 0 {
+        tide::log::warn!("Stopping with {} requests in flight", left);
+    }
+    for live in &lives {
+        live.current().stop().await?;
+    }
+    if let (Some(profiler), Some(path)) = (profiler, profile) {
+        profiler.save(&path)?;
+        print!("{}", profiler.summary());
+        println!("Wrote the profile to {}", path.display());
+    }
     Ok(())
 }
 
+/// The middleware copying requests to the shadow of `config`.
+fn mirror(config: &config::Mirror, permissions: Permissions) -> tide::Result<tide_rhai::Mirror> {
+    match (&config.upstream, &config.dir) {
+        (Some(url), None) => Ok(tide_rhai::Mirror::upstream(url, config.percent)?),
+        (None, Some(dir)) => Ok(tide_rhai::Mirror::endpoint(
+            shadow(dir, permissions)?,
+            config.percent,
+        )),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The mirror needs either an upstream or a dir",
+        )
+        .into()),
+    }
+}
+
+/// The app of `dir` answering the copies of the mirror.
+fn shadow(dir: &Path, permissions: Permissions) -> tide::Result<tide::Server<()>> {
+    let mut shadow = tide::new();
+    shadow
+        .at("/js/*")
+        .all(JsDir::new("/js/*", dir)?.with_permissions(permissions.clone()));
+    shadow
+        .at("/*")
+        .all(RhaiDir::new("/*", dir)?.with_permissions(permissions));
+    Ok(shadow)
+}
+
+/// One listener for every address of the config, all serving the same app,
+/// and the certificates of those with TLS. A socket-activated server serves
+/// the sockets of systemd instead if `activated`; they cannot be shared by
+/// several listeners. TCP addresses, with TLS or not, are served within
+/// `connections`.
+fn listener(
+    listen: &[Listen],
+    connections: &Connections,
+    reuse_port: bool,
+    activated: bool,
+) -> io::Result<(ConcurrentListener<()>, Vec<Arc<reload::Certs>>)> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    #[cfg(not(unix))]
+    let _ = activated;
+    #[cfg(unix)]
+    if activated {
+        let sockets = activation::sockets()?;
+        if !sockets.is_empty() {
+            println!("Serving {} sockets passed by systemd", sockets.len());
+            let mut listener = ConcurrentListener::new();
+            for socket in sockets {
+                match socket {
+                    activation::Activated::Tcp(tcp) if connections.is_set() => {
+                        listener.add(limits::Limited::new(tcp, connections.clone())?)?
+                    }
+                    activation::Activated::Tcp(tcp) => listener.add(tcp)?,
+                    activation::Activated::Unix(unix) => listener.add(unix)?,
+                }
+            }
+            return Ok((listener, Vec::new()));
+        }
+    }
+    if listen.is_empty() {
+        return Err(invalid("No address to listen on".into()));
+    }
+    let mut listener = ConcurrentListener::new();
+    let mut certs = Vec::new();
+    for l in listen {
+        let unix = l.addr.starts_with("http+unix://");
+        match &l.tls {
+            None if unix || !(reuse_port || connections.is_set()) => {
+                listener.add(l.addr.as_str())?
+            }
+            None if connections.is_set() => {
+                let tcp = reload::bind_tcp(&l.addr, reuse_port, connections.backlog())?;
+                listener.add(limits::Limited::new(tcp, connections.clone())?)?
+            }
+            None => listener.add(reload::bind_tcp(&l.addr, true, connections.backlog())?)?,
+            Some(_) if unix => {
+                return Err(invalid(format!("TLS is not supported on {}", l.addr)));
+            }
+            Some(tls) if connections.is_set() => {
+                let c = reload::Certs::load(&tls.cert, &tls.key)?;
+                let tcp = reload::bind_tcp(&l.addr, reuse_port, connections.backlog())?;
+                let limited = limits::Limited::new(tcp, connections.clone())?;
+                listener.add(limited.with_tls(c.server_config()))?;
+                certs.push(c);
+            }
+            Some(tls) => {
+                let c = reload::Certs::load(&tls.cert, &tls.key)?;
+                let builder = TlsListener::build().config(c.server_config());
+                let builder = if reuse_port {
+                    let tcp = reload::bind_tcp(&l.addr, true, connections.backlog())?;
+                    builder.tcp(async_std::net::TcpListener::from(tcp))
+                } else {
+                    builder.addrs(l.addr.as_str())
+                };
+                listener.add(builder)?;
+                certs.push(c);
+            }
+        }
+    }
+    Ok((listener, certs))
+}
+
+fn repl(js: bool, permissions: Permissions) -> tide::Result<()> {
+    let config = Config::load(CONFIG)?;
+    let (dir, js_dir) = app(&config, permissions, debugger(&config)?.as_ref())?;
+    if js {
+        js_dir.repl(|repl| read_eval("js", |code| repl.eval(code)))??;
+    } else {
+        let mut repl = dir.repl();
+        read_eval("rhai", |code| repl.eval(code))?;
+    }
+    Ok(())
+}
+
+/// Prints how the tests of the app went and exits with 1 if any failed, for
+/// CI pipelines.
+fn test(permissions: Permissions) -> tide::Result<()> {
+    let config = Config::load(CONFIG)?;
+    let (dir, js) = app(&config, permissions, debugger(&config)?.as_ref())?;
+    let mut report = dir.run_tests();
+    report.merge(js.run_tests());
+    print!("{}", report);
+    if report.failed() > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Reads lines until Ctrl-D and prints what `eval` makes of them. Lines of
+/// incomplete code are collected until they are complete. The history is
+/// kept in `.rustvm_history`.
+fn read_eval(name: &str, mut eval: impl FnMut(&str) -> Evaluated) -> rustyline::Result<()> {
+    const HISTORY: &str = ".rustvm_history";
+    let mut editor = rustyline::DefaultEditor::new()?;
+    let _ = editor.load_history(HISTORY);
+    let prompt = format!("{}> ", name);
+    let mut code = String::new();
+    loop {
+        let line = match editor.readline(if code.is_empty() { &prompt } else { "... " }) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                code.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        if code.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        code.push_str(&line);
+        code.push('\n');
+        match eval(&code) {
+            Evaluated::Incomplete => continue,
+            Evaluated::Value(Some(shown)) => println!("{}", shown),
+            Evaluated::Value(None) => {}
+            Evaluated::Error(e) => eprintln!("{}", e),
+        }
+        code.clear();
+    }
+    editor.save_history(HISTORY)
+}
+
+/// Serves `endpoint` while keeping a handle on it for the lifecycle hooks.
+fn shared<E: Endpoint<()> + ?Sized>(endpoint: Arc<E>) -> impl Endpoint<()> {
+    move |req: Request<()>| {
+        let endpoint = endpoint.clone();
+        async move { endpoint.call(req).await }
+    }
+}
+
+/// The response hooks of the live generation of the app, reloaded like
+/// the endpoints of [`current`].
+struct Hooks(Arc<reload::Swap<Generation>>);
+
+#[async_trait::async_trait]
+impl tide::Middleware<()> for Hooks {
+    async fn handle(&self, req: Request<()>, next: tide::Next<'_, ()>) -> tide::Result {
+        let generation = self.0.current();
+        tide::Middleware::<()>::handle(&generation.hooks, req, next).await
+    }
+}
+
+/// Serves the endpoint `pick` takes from the live generation of the app,
+/// so a reload takes effect with the next request.
+fn current<E: Endpoint<()> + ?Sized>(
+    live: &Arc<reload::Swap<Generation>>,
+    pick: fn(&Generation) -> &Arc<E>,
+) -> impl Endpoint<()> {
+    let live = live.clone();
+    move |req: Request<()>| {
+        let generation = live.current();
+        async move { pick(&generation).call(req).await }
+    }
+}
+
 async fn order_shoes(mut req: Request<()>) -> tide::Result {
     let Animal { name, legs } = req.body_json().await?;
     Ok(format!("Hello, {}! I've put in an order for {} shoes", name, legs).into())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::io::{Read, Write};
+    use async_std::net::TcpStream;
+    use async_std::prelude::*;
+    use tide::http::{Method, Url};
+
+    async fn post(app: &tide::Server<()>, path: &str) -> tide::http::Response {
+        let url = Url::parse("http://localhost/").unwrap().join(path).unwrap();
+        let mut req = tide::http::Request::new(Method::Post, url);
+        req.set_body(Body::from_json(&json!({ "id": 7 })).unwrap());
+        app.respond(req).await.unwrap()
+    }
+
+    /// Two different ports nothing listens on.
+    fn free_ports() -> (u16, u16) {
+        let a = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let b = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        (
+            a.local_addr().unwrap().port(),
+            b.local_addr().unwrap().port(),
+        )
+    }
+
+    /// The answer to `GET /` sent over `stream`, which is closed after.
+    async fn get(mut stream: impl Read + Write + Unpin) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut answer = Vec::new();
+        // TLS servers close without a close_notify.
+        let _ = stream.read_to_end(&mut answer).await;
+        String::from_utf8_lossy(&answer).into_owned()
+    }
+
+    /// The config of an app in the temporary directory `name`, answering
+    /// `POST /orders.rhai` with its body.
+    fn config(name: &str) -> Config {
+        let root = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        std::fs::write(
+            root.join("app/orders.rhai"),
+            r#"fn post(req) { http::response(http::CREATED, req.body_json()) }"#,
+        )
+        .unwrap();
+        Config {
+            dir: root.join("app"),
+            data_dir: root.join("data"),
+            storage_dir: root.join("storage"),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn answers_every_method() {
+        let config = config("rustvm_methods");
+        let permissions = Permissions::default();
+        let slow = Arc::new(config.slow_scripts.build());
+        tide_rhai::rt::block_on(async {
+            let generation = Generation::start(&config, 0, permissions.clone(), None, &slow, None)
+                .await
+                .unwrap();
+            let live = Arc::new(reload::Swap::new(generation));
+            let in_flight = reload::InFlight::default();
+            let app = server(&config, &live, &in_flight, &permissions).unwrap();
+            let mut res = post(&app, "/orders.rhai").await;
+            assert_eq!(res.status(), StatusCode::Created);
+            assert_eq!(res.body_string().await.unwrap(), r#"{"id":7}"#);
+
+            let shadow = shadow(&config.dir, permissions.clone()).unwrap();
+            assert_eq!(
+                post(&shadow, "/orders.rhai").await.status(),
+                StatusCode::Created
+            );
+        });
+    }
+
+    #[test]
+    fn reloads_with_the_debugger() {
+        let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = free.local_addr().unwrap().port();
+        drop(free);
+        let config = Config {
+            dev: true,
+            debug_port: Some(port),
+            ..config("rustvm_debugged")
+        };
+        let debugger = debugger(&config).unwrap();
+        let start = || {
+            let slow = Arc::new(config.slow_scripts.build());
+            let debugger = debugger.clone();
+            let config = &config;
+            async move {
+                Generation::start(
+                    config,
+                    0,
+                    Permissions::default(),
+                    None,
+                    &slow,
+                    debugger.as_ref(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+        tide_rhai::rt::block_on(async {
+            let live = reload::Swap::new(start().await);
+            for _ in 0..2 {
+                live.replace(start().await).stop().await.unwrap();
+            }
+        });
+        // Still listening, on the port of the first generation.
+        assert_eq!(debugger.unwrap().addr().port(), port);
+        assert!(std::net::TcpListener::bind(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn listens_on_every_address() {
+        let (_, client) = limits::test::certificate("rustvm_listen_tls");
+        let dir = std::env::temp_dir().join("rustvm_listen_tls");
+        let (http, https) = free_ports();
+        let listen = [
+            Listen {
+                addr: format!("127.0.0.1:{}", http),
+                tls: None,
+            },
+            Listen {
+                addr: format!("127.0.0.1:{}", https),
+                tls: Some(Tls {
+                    cert: dir.join("cert.pem"),
+                    key: dir.join("key.pem"),
+                }),
+            },
+        ];
+        let (listener, certs) = listener(&listen, &Connections::default(), false, false).unwrap();
+        assert_eq!(certs.len(), 1);
+        let mut app = tide::new();
+        app.at("/").get(|_| async { Ok("ok") });
+        tide_rhai::rt::block_on(async move {
+            let mut listener = app.bind(listener).await.unwrap();
+            assert_eq!(listener.info().len(), 2);
+            tide_rhai::rt::spawn(async move { listener.accept().await });
+
+            let answer = get(TcpStream::connect(("127.0.0.1", http)).await.unwrap()).await;
+            assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+            let localhost = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+            let tcp = TcpStream::connect(("127.0.0.1", https)).await.unwrap();
+            let answer = get(client.connect(localhost, tcp).await.unwrap()).await;
+            assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+            assert!(answer.ends_with("ok"), "{}", answer);
+
+            let tcp = TcpStream::connect(("127.0.0.1", https)).await.unwrap();
+            assert!(!get(tcp).await.starts_with("HTTP"));
+        });
+    }
+
+    #[test]
+    fn shards_share_the_port() {
+        let (port, _) = free_ports();
+        let listen = [Listen {
+            addr: format!("127.0.0.1:{}", port),
+            tls: None,
+        }];
+        let mut stops = Vec::new();
+        for shard in 0..2 {
+            // Binding the port again fails without SO_REUSEPORT.
+            let (listener, _) = listener(&listen, &Connections::default(), true, false).unwrap();
+            let mut app = tide::new();
+            app.at("/")
+                .get(move |_| async move { Ok(format!("shard {}", shard)) });
+            let listener = tide_rhai::rt::block_on(app.bind(listener)).unwrap();
+            stops.push(accept(shard, listener).unwrap());
+        }
+        // The kernel picks a shard by the address of the client.
+        let mut answered = std::collections::HashSet::new();
+        for _ in 0..200 {
+            let tcp = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let answer = tide_rhai::rt::block_on(get(TcpStream::from(tcp)));
+            assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+            answered.insert(answer.rsplit(' ').next().unwrap().to_string());
+            if answered.len() == 2 {
+                break;
+            }
+        }
+        assert_eq!(answered.len(), 2, "{:?}", answered);
+        drop(stops);
+    }
+
+    #[test]
+    fn watches_what_is_compiled_at_start() {
+        let mut config = config("rustvm_watch_hooks");
+        let app_dir = config.dir.clone();
+        std::fs::create_dir_all(app_dir.join("lib")).unwrap();
+        std::fs::create_dir_all(app_dir.join("hooks")).unwrap();
+        std::fs::write(app_dir.join("lib/total.rhai"), "fn total(n) { n * 2 }").unwrap();
+        std::fs::write(
+            app_dir.join("total.rhai"),
+            r#"import "lib::total" as t; t::total(21)"#,
+        )
+        .unwrap();
+        let hook = app_dir.join("hooks/frame.rhai");
+        std::fs::write(&hook, r#"res.headers["x-frame-options"] = "DENY"; res"#).unwrap();
+        config.response_hooks = vec![hook.clone()];
+        let permissions = Permissions::default();
+        let slow = Arc::new(config.slow_scripts.build());
+        let live = tide_rhai::rt::block_on(async {
+            let generation = Generation::start(&config, 0, permissions.clone(), None, &slow, None)
+                .await
+                .unwrap();
+            let live = Arc::new(reload::Swap::new(generation));
+            let in_flight = reload::InFlight::default();
+            let app = server(&config, &live, &in_flight, &permissions).unwrap();
+            let url = Url::parse("http://localhost/total.rhai").unwrap();
+            let mut res: tide::http::Response = app
+                .respond(tide::http::Request::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert_eq!(res.body_string().await.unwrap(), "42");
+            assert_eq!(res.header("x-frame-options").unwrap().as_str(), "DENY");
+            live
+        });
+        let l = live.clone();
+        let picks_up = move |path: &Path| l.current().picks_up_edits(path);
+        let mut watch = reload::Watch::new(vec![app_dir.clone()]);
+
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(app_dir.join("lib/total.rhai"), "fn total(n) { n * 3 }").unwrap();
+        assert!(!watch.changed(&picks_up));
+        std::fs::write(
+            &hook,
+            r#"res.headers["x-frame-options"] = "SAMEORIGIN"; res"#,
+        )
+        .unwrap();
+        assert!(watch.changed(&picks_up));
+        std::fs::write(app_dir.join("orders.rhai"), "42").unwrap();
+        assert!(watch.changed(&picks_up));
+    }
+
+    #[test]
+    fn flags_replace_the_config() {
+        let root = std::env::temp_dir().join("rustvm_serve_flags");
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join(CONFIG);
+        let toml = "watch = true\ndev = true\n[connections]\nmax = 5\nbacklog = 16\n";
+        std::fs::write(&file, toml).unwrap();
+        let cli = Cli::try_parse_from([
+            "rustvm",
+            "serve",
+            "--config",
+            file.to_str().unwrap(),
+            "--watch=false",
+            "--reuse-port",
+            "--max-connections",
+            "9",
+            "--tenants-dir",
+            "tenants",
+        ])
+        .unwrap();
+        let Some(Command::Serve(args)) = cli.command else {
+            panic!("not serve");
+        };
+        let config = args.config().unwrap();
+        assert!(!config.watch);
+        assert!(config.dev);
+        assert!(config.reuse_port);
+        assert_eq!(config.connections.max, Some(9));
+        assert_eq!(config.connections.backlog, Some(16));
+        assert_eq!(config.tenants_dir, Some(PathBuf::from("tenants")));
+        assert!(args.watched(&config).is_empty());
+    }
+
+    #[test]
+    fn checks_with_the_config_of_the_app() {
+        // Tests run from the crate, not from the directory of the app.
+        let root = std::env::temp_dir().join("rustvm_check_config");
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        std::fs::create_dir_all(root.join("hooks")).unwrap();
+        std::fs::write(root.join("app/hello.rhai"), r#""hello""#).unwrap();
+        std::fs::write(root.join("hooks/frame.rhai"), "res").unwrap();
+        let toml = root.join(CONFIG);
+        std::fs::write(&toml, "response_hooks = [\"hooks/frame.rhai\"]\n").unwrap();
+        assert_eq!(app_config(&root.join("app")), toml.canonicalize().unwrap());
+        check(&root.join("app"), None).unwrap();
+
+        std::fs::write(&toml, "response_hooks = [\"hooks/gone.rhai\"]\n").unwrap();
+        assert!(check(&root.join("app"), None).is_err());
+        check(&root.join("app"), Some(root.join("app/missing.toml"))).unwrap();
+    }
+}
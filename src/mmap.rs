@@ -0,0 +1,68 @@
+//! The bytes of a file without copying them into memory first: large
+//! torrents and the files they describe are mapped where the platform
+//! supports it and read into a buffer where it does not.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+pub enum Input {
+    #[cfg(any(unix, windows))]
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(any(unix, windows))]
+            Input::Mapped(map) => map,
+            Input::Read(bytes) => bytes,
+        }
+    }
+}
+
+/// The bytes of the file at `path`, mapped if it can be, read otherwise.
+pub fn open(path: &Path) -> io::Result<Input> {
+    let file = File::open(path)?;
+    #[cfg(any(unix, windows))]
+    {
+        // Empty files cannot be mapped on every platform.
+        if file.metadata()?.len() > 0 {
+            // Safety: the map is read only, and only changes if the file is
+            // written to while it is read, which would be as wrong read any
+            // other way.
+            if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+                return Ok(Input::Mapped(map));
+            }
+        }
+    }
+    read(file)
+}
+
+fn read(mut file: File) -> io::Result<Input> {
+    let mut bytes = Vec::new();
+    io::Read::read_to_end(&mut file, &mut bytes)?;
+    Ok(Input::Read(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opens_files() {
+        let dir = std::env::temp_dir().join("rustvm-mmap");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("full"), b"d1:ai1ee").unwrap();
+        std::fs::write(dir.join("empty"), b"").unwrap();
+        assert_eq!(&*open(&dir.join("full")).unwrap(), b"d1:ai1ee");
+        assert!(open(&dir.join("empty")).unwrap().is_empty());
+        assert!(open(&dir.join("missing")).is_err());
+        let read = read(File::open(dir.join("full")).unwrap()).unwrap();
+        assert_eq!(&*read, b"d1:ai1ee");
+    }
+}
@@ -0,0 +1,310 @@
+//! Restarting without dropping traffic. SIGHUP reloads `rustvm.toml`, the
+//! scripts and the TLS certificates while the listeners keep accepting;
+//! new requests go to the reloaded app, requests in flight finish on the
+//! old one.
+//!
+//! Upgrading the binary is a handoff between two processes: with
+//! `reuse_port` the new one binds the same addresses next to the old one,
+//! which then gets SIGTERM, stops accepting and finishes the requests it has
+//! before exiting.
+
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tide::{Middleware, Next, Request};
+
+pub enum Signal {
+    /// SIGHUP, or a change of a watched file.
+    Reload,
+    /// Ctrl-C or SIGTERM.
+    Stop,
+}
+
+/// The signals the process gets, as they come, and a reload whenever a file
+/// below one of the `watched` paths changes, but for the edits the app
+/// `picks_up` by itself.
+pub fn signals(
+    watched: Vec<PathBuf>,
+    picks_up: impl Fn(&Path) -> bool + Send + 'static,
+) -> io::Result<async_std::channel::Receiver<Signal>> {
+    let mut signals = signal_hook::iterator::Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    let (send, received) = async_std::channel::unbounded();
+    if !watched.is_empty() {
+        let send = send.clone();
+        std::thread::spawn(move || {
+            let mut watch = Watch::new(watched);
+            loop {
+                std::thread::sleep(WATCH_INTERVAL);
+                if !watch.changed(&picks_up) {
+                    continue;
+                }
+                if send.try_send(Signal::Reload).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let signal = match signal {
+                SIGHUP => Signal::Reload,
+                _ => Signal::Stop,
+            };
+            if send.try_send(signal).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(received)
+}
+
+/// How often watched files are looked at.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The files and directories below some paths and when they last changed.
+/// Adding or removing a file changes its directory too.
+pub struct Watch {
+    paths: Vec<PathBuf>,
+    seen: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Watch {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let seen = modified(&paths);
+        Self { paths, seen }
+    }
+
+    /// Whether a file or directory changed, came or went since the last
+    /// look, leaving out the edits of the files the app `picks_up` by
+    /// itself, like the modules it evaluates again when they change.
+    pub fn changed(&mut self, picks_up: &dyn Fn(&Path) -> bool) -> bool {
+        let now = modified(&self.paths);
+        let changed = now.len() != self.seen.len()
+            || now.iter().any(|(path, m)| match self.seen.get(path) {
+                None => true,
+                Some(seen) => seen != m && !picks_up(path),
+            });
+        self.seen = now;
+        changed
+    }
+}
+
+/// When every file and directory below `paths` last changed.
+fn modified(paths: &[PathBuf]) -> HashMap<PathBuf, Option<SystemTime>> {
+    fn walk(path: &Path, seen: &mut HashMap<PathBuf, Option<SystemTime>>) {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return;
+        };
+        seen.insert(path.to_path_buf(), metadata.modified().ok());
+        if metadata.is_dir() {
+            for entry in std::fs::read_dir(path).into_iter().flatten().flatten() {
+                walk(&entry.path(), seen);
+            }
+        }
+    }
+    let mut seen = HashMap::new();
+    for path in paths {
+        walk(path, &mut seen);
+    }
+    seen
+}
+
+/// What is being served, replaced as a whole on reload.
+pub struct Swap<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> Swap<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(value)),
+        }
+    }
+
+    pub fn current(&self) -> Arc<T> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Puts `value` in place, answering the one it replaced.
+    pub fn replace(&self, value: T) -> Arc<T> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(value))
+    }
+}
+
+/// Counts the requests being answered, to wait for them before exiting.
+#[derive(Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Waits until no request is left or `timeout` passed, answering how
+    /// many were left.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        let started = Instant::now();
+        loop {
+            let left = self.0.load(Ordering::SeqCst);
+            if left == 0 || started.elapsed() >= timeout {
+                return left;
+            }
+            tide_rhai::rt::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for InFlight {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let _entered = Entered::new(&self.0);
+        Ok(next.run(req).await)
+    }
+}
+
+/// One request in flight, until dropped, also when its connection is.
+struct Entered<'a>(&'a AtomicUsize);
+
+impl<'a> Entered<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count)
+    }
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The certificate of a TLS listener, read again by [`Certs::reload`].
+pub struct Certs {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl Certs {
+    pub fn load(cert: &Path, key: &Path) -> io::Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+            current: RwLock::new(certified_key(cert, key)?),
+        }))
+    }
+
+    /// Reads the files again, keeping the certificate there was if they
+    /// cannot be used.
+    pub fn reload(&self) -> io::Result<()> {
+        let reloaded = certified_key(&self.cert, &self.key)?;
+        *self.current.write().unwrap() = reloaded;
+        Ok(())
+    }
+
+    /// The TLS config of a listener answering with these certificates.
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = self.clone();
+        config
+    }
+}
+
+impl ResolvesServerCert for Certs {
+    fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn certified_key(cert: &Path, key: &Path) -> io::Result<CertifiedKey> {
+    let invalid = |what: &str, path: &Path| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No {} in {}", what, path.display()),
+        )
+    };
+    let chain = pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .map_err(|_| invalid("certificate", cert))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key)?))
+        .map_err(|_| invalid("private key", key))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(key)?))
+            .map_err(|_| invalid("private key", key))?;
+    }
+    let key = keys
+        .first()
+        .and_then(|k| sign::any_supported_type(k).ok())
+        .ok_or_else(|| invalid("usable private key", key))?;
+    Ok(CertifiedKey::new(chain, Arc::new(key)))
+}
+
+/// A TCP listener on `addr` queueing up to `backlog` connections, which
+/// another process may bind too if `reuse_port`.
+pub fn bind_tcp(addr: &str, reuse_port: bool, backlog: i32) -> io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+    use std::net::ToSocketAddrs;
+    let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No address in {}", addr),
+        )
+    })?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn swaps_while_requests_keep_theirs() {
+        let swap = Swap::new(1);
+        let held = swap.current();
+        assert_eq!(*swap.replace(2), 1);
+        assert_eq!((*held, *swap.current()), (1, 2));
+    }
+
+    #[test]
+    fn sees_changed_and_removed_files() {
+        let dir = std::env::temp_dir().join("rustvm_reload_watch");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("api")).unwrap();
+        std::fs::write(dir.join("api/a.rhai"), "1").unwrap();
+        std::fs::write(dir.join("api/b.rhai"), "1").unwrap();
+        let picks_up = |path: &Path| path.ends_with("api/b.rhai");
+        let mut watch = Watch::new(vec![dir.clone()]);
+        assert!(!watch.changed(&picks_up));
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(dir.join("api/a.rhai"), "2").unwrap();
+        assert!(watch.changed(&picks_up));
+        assert!(!watch.changed(&picks_up));
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(dir.join("api/b.rhai"), "2").unwrap();
+        assert!(!watch.changed(&picks_up));
+        std::fs::remove_file(dir.join("api/b.rhai")).unwrap();
+        assert!(watch.changed(&picks_up));
+        assert!(Watch::new(vec![dir.join("missing")]).seen.is_empty());
+    }
+
+    #[test]
+    fn drains_the_requests_in_flight() {
+        let in_flight = InFlight::default();
+        tide_rhai::rt::block_on(async {
+            let entered = Entered::new(&in_flight.0);
+            assert_eq!(in_flight.drain(Duration::from_millis(60)).await, 1);
+            drop(entered);
+            assert_eq!(in_flight.drain(Duration::from_secs(5)).await, 0);
+        });
+    }
+}
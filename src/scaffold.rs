@@ -0,0 +1,133 @@
+//! `rustvm new`: a starter app with its config, handlers, tests and a README
+//! listing its routes, so new apps don't start from an empty directory.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Template {
+    /// A JSON API: a health check in rhai and todos in JavaScript
+    Api,
+    /// Pages rendered by rhai scripts sharing a layout
+    Site,
+    /// A BitTorrent tracker answering announces
+    Tracker,
+}
+
+/// Files by their path in the app, `/` separated.
+type Files = &'static [(&'static str, &'static str)];
+
+/// The files of every template.
+const COMMON: Files = &[
+    ("rustvm.toml", include_str!("templates/rustvm.toml")),
+    (".gitignore", include_str!("templates/gitignore")),
+];
+
+const API: Files = &[
+    ("README.md", include_str!("templates/api/README.md")),
+    (
+        "app/health.rhai",
+        include_str!("templates/api/app/health.rhai"),
+    ),
+    (
+        "app/health_test.rhai",
+        include_str!("templates/api/app/health_test.rhai"),
+    ),
+    (
+        "app/api/todos.js",
+        include_str!("templates/api/app/api/todos.js"),
+    ),
+    (
+        "app/api/todos.test.js",
+        include_str!("templates/api/app/api/todos.test.js"),
+    ),
+];
+
+const SITE: Files = &[
+    ("README.md", include_str!("templates/site/README.md")),
+    (
+        "app/index.rhai",
+        include_str!("templates/site/app/index.rhai"),
+    ),
+    (
+        "app/hello.rhai",
+        include_str!("templates/site/app/hello.rhai"),
+    ),
+    (
+        "app/lib/layout.rhai",
+        include_str!("templates/site/app/lib/layout.rhai"),
+    ),
+    (
+        "app/pages_test.rhai",
+        include_str!("templates/site/app/pages_test.rhai"),
+    ),
+];
+
+const TRACKER: Files = &[
+    ("README.md", include_str!("templates/tracker/README.md")),
+    (
+        "app/announce.js",
+        include_str!("templates/tracker/app/announce.js"),
+    ),
+    (
+        "app/lib/bencode.js",
+        include_str!("templates/tracker/app/lib/bencode.js"),
+    ),
+    (
+        "app/announce.test.js",
+        include_str!("templates/tracker/app/announce.test.js"),
+    ),
+];
+
+impl Template {
+    fn files(self) -> Files {
+        match self {
+            Template::Api => API,
+            Template::Site => SITE,
+            Template::Tracker => TRACKER,
+        }
+    }
+}
+
+/// Writes the app of `template` to `dir`, which must be missing or empty,
+/// and returns the files written.
+pub fn create(dir: &Path, template: Template) -> io::Result<Vec<PathBuf>> {
+    if dir
+        .read_dir()
+        .map_or(false, |mut entries| entries.next().is_some())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", dir.display()),
+        ));
+    }
+    let mut written = Vec::new();
+    for (name, content) in COMMON.iter().chain(template.files()) {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, content)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn templates_check() {
+        for template in [Template::Api, Template::Site, Template::Tracker] {
+            let dir = std::env::temp_dir().join(format!("rustvm-new-{:?}", template));
+            let _ = std::fs::remove_dir_all(&dir);
+            let written = create(&dir, template).unwrap();
+            assert!(written.iter().all(|f| f.is_file()));
+            crate::config::Config::load(dir.join("rustvm.toml")).unwrap();
+            let problems = tide_rhai::check(dir.join("app")).unwrap();
+            assert!(problems.is_empty(), "{:?}: {:?}", template, problems);
+            assert!(create(&dir, template).is_err());
+        }
+    }
+}
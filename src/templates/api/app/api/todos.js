@@ -0,0 +1,27 @@
+// GET lists the todos, POST adds one from `{"title": "..."}`. They are
+// kept in `storage`, in the storage_dir of rustvm.toml.
+
+function todos() {
+  return JSON.parse(storage.getItem("todos") ?? "[]");
+}
+
+export default {
+  async fetch(request) {
+    if (request.method === "GET") {
+      return Response.json(todos());
+    }
+    if (request.method === "POST") {
+      const { title } = await request.json().catch(() => ({}));
+      if (typeof title !== "string" || title.trim() === "") {
+        return Response.json({ error: "A todo needs a title" }, { status: 400 });
+      }
+      const all = todos();
+      const id = all.reduce((max, todo) => Math.max(max, todo.id), 0) + 1;
+      const todo = { id, title: title.trim(), done: false };
+      all.push(todo);
+      storage.setItem("todos", JSON.stringify(all));
+      return Response.json(todo, { status: 201 });
+    }
+    return new Response(null, { status: 405, headers: { allow: "GET, POST" } });
+  },
+};
@@ -0,0 +1,12 @@
+test("adds a todo", () => {
+  const res = request("/api/todos.js", { method: "POST", body: { title: " Write tests " } });
+  assert.equal(res.status, 201);
+  assert.equal(res.json().title, "Write tests");
+  const listed = request("/api/todos.js").json();
+  assert(listed.some((todo) => todo.id === res.json().id));
+});
+
+test("needs a title", () => {
+  const res = request("/api/todos.js", { method: "POST", body: {} });
+  assert.equal(res.status, 400);
+});
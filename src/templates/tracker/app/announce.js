@@ -0,0 +1,70 @@
+// The announce of BitTorrent clients: records the peer, then answers with
+// the other peers of the torrent.
+
+import { bencode } from "./lib/bencode.js";
+
+// Seconds clients wait between announces.
+const INTERVAL = 1800;
+
+// The info hash of a raw query string, in hex. Clients send its 20 bytes
+// percent-encoded, which are not UTF-8, so URLSearchParams would mangle it.
+function infoHash(search) {
+  const pair = search.slice(1).split("&").find((p) => p.startsWith("info_hash="));
+  const raw = pair?.slice("info_hash=".length) ?? "";
+  let hex = "";
+  for (let i = 0; i < raw.length; i++) {
+    if (raw[i] === "%") {
+      hex += raw.slice(i + 1, i + 3).toLowerCase();
+      i += 2;
+    } else {
+      hex += raw.charCodeAt(i).toString(16).padStart(2, "0");
+    }
+  }
+  return /^[0-9a-f]{40}$/.test(hex) ? hex : null;
+}
+
+function failure(reason) {
+  return new Response(bencode({ "failure reason": reason }), {
+    headers: { "content-type": "text/plain" },
+  });
+}
+
+export default {
+  fetch(request) {
+    const url = new URL(request.url);
+    const params = url.searchParams;
+    const hash = infoHash(url.search);
+    const port = Number(params.get("port"));
+    const forwarded = request.headers.get("x-forwarded-for")?.split(",")[0].trim();
+    const ip = params.get("ip") ?? forwarded;
+    if (!hash) return failure("missing or invalid info_hash");
+    if (!Number.isInteger(port) || port < 1 || port > 65535) return failure("invalid port");
+    if (!ip) return failure("unknown address, send ip");
+
+    const key = `peers:${hash}`;
+    const now = Date.now();
+    const peers = JSON.parse(storage.getItem(key) ?? "{}");
+    for (const [id, peer] of Object.entries(peers)) {
+      if (now - peer.seen > 2 * INTERVAL * 1000) delete peers[id];
+    }
+    const id = `${ip}:${port}`;
+    if (params.get("event") === "stopped") {
+      delete peers[id];
+    } else {
+      peers[id] = { ip, port, seen: now, seeding: params.get("left") === "0" };
+    }
+    storage.setItem(key, JSON.stringify(peers));
+
+    const all = Object.entries(peers);
+    const others = all.filter(([other]) => other !== id).slice(0, Number(params.get("numwant") ?? 50));
+    return new Response(
+      bencode({
+        interval: INTERVAL,
+        complete: all.filter(([, p]) => p.seeding).length,
+        incomplete: all.filter(([, p]) => !p.seeding).length,
+        peers: others.map(([, p]) => ({ ip: p.ip, port: p.port })),
+      }),
+      { headers: { "content-type": "text/plain" } },
+    );
+  },
+};
@@ -0,0 +1,19 @@
+const hash = "%AB".repeat(20);
+
+function announce(query) {
+  return request(`/announce.js?info_hash=${hash}&${query}`);
+}
+
+test("answers with the other peers", () => {
+  announce("ip=10.0.0.1&port=6881&left=0");
+  const res = announce("ip=10.0.0.2&port=6881&left=100");
+  assert.equal(res.status, 200);
+  assert(res.body.includes("2:ip8:10.0.0.14:porti6881e"), res.body);
+  assert(!res.body.includes("10.0.0.2"), res.body);
+  announce("ip=10.0.0.2&port=6881&event=stopped");
+});
+
+test("refuses announces without an info hash", () => {
+  const res = request("/announce.js?port=6881&ip=10.0.0.1");
+  assert(res.body.startsWith("d14:failure reason"), res.body);
+});
@@ -0,0 +1,18 @@
+// Bencoding, the format trackers answer in: byte strings, integers, lists
+// and dictionaries with sorted keys.
+
+const utf8 = new TextEncoder();
+
+export function bencode(value) {
+  if (typeof value === "number") {
+    return `i${Math.trunc(value)}e`;
+  }
+  if (typeof value === "string") {
+    return `${utf8.encode(value).length}:${value}`;
+  }
+  if (Array.isArray(value)) {
+    return `l${value.map(bencode).join("")}e`;
+  }
+  const keys = Object.keys(value).sort();
+  return `d${keys.map((k) => bencode(k) + bencode(value[k])).join("")}e`;
+}
@@ -0,0 +1,444 @@
+//! What a `.torrent` file describes, read with the [`bencode`](crate::bencode)
+//! parser: BitTorrent v1, v2 (BEP 52) and hybrid torrents.
+
+use crate::bencode::{parse_bencode, Bencode, BencodeCache};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    /// Path inside the torrent, `/` separated.
+    pub path: String,
+    pub length: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Torrent {
+    pub name: String,
+    /// SHA-1 of the info dictionary, for torrents with v1 pieces.
+    pub info_hash_v1: Option<[u8; 20]>,
+    /// SHA-256 of the info dictionary, for torrents of `meta version` 2.
+    pub info_hash_v2: Option<[u8; 32]>,
+    /// Announce URLs, by tier then in order, without repeats.
+    pub trackers: Vec<String>,
+    pub piece_length: i64,
+    /// SHA-1 of every piece, for torrents with v1 pieces.
+    pub pieces: Vec<[u8; 20]>,
+    pub files: Vec<File>,
+}
+
+/// How the files of a torrent on disk match its pieces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+    pub pieces: usize,
+    /// The pieces that do not match, in order.
+    pub failed: Vec<usize>,
+    /// The files that are not there, by their path in the torrent.
+    pub missing: Vec<String>,
+}
+
+type Dict = BTreeMap<Vec<u8>, Bencode>;
+
+fn text(value: Option<&Bencode>) -> Option<String> {
+    match value {
+        Some(Bencode::ByteString(bytes)) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        _ => None,
+    }
+}
+
+fn number(value: Option<&Bencode>) -> Option<i64> {
+    match value {
+        Some(Bencode::Number(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+fn get<'a>(dict: &'a Dict, key: &str) -> Option<&'a Bencode> {
+    dict.get(key.as_bytes())
+}
+
+/// The bytes of the value of `key` in the dictionary `bytes` starts with,
+/// as they are in the file: hashes are over them, not over a re-encoding.
+fn raw_value<'a>(bytes: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    let mut rest = bytes.strip_prefix(b"d")?;
+    while !rest.starts_with(b"e") {
+        let (after_key, k) = parse_bencode(rest).ok()?;
+        let (after_value, _) = parse_bencode(after_key).ok()?;
+        if k == Bencode::ByteString(key.to_vec()) {
+            return Some(&after_key[..after_key.len() - after_value.len()]);
+        }
+        rest = after_value;
+    }
+    None
+}
+
+/// The files of a v2 `file tree`, below `prefix`.
+fn file_tree(tree: &Dict, prefix: &str, files: &mut Vec<File>) {
+    for (name, node) in tree {
+        let Bencode::Dict(node) = node else {
+            continue;
+        };
+        let name = String::from_utf8_lossy(name);
+        // A file is a node with an empty name holding its length.
+        if name.is_empty() {
+            let length = node.get(b"length".as_slice());
+            files.push(File {
+                path: prefix.to_string(),
+                length: number(length).unwrap_or(0),
+            });
+            continue;
+        }
+        let path = if prefix.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        file_tree(node, &path, files);
+    }
+}
+
+impl Torrent {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        Self::read(bytes, &crate::bencode::decode(bytes)?)
+    }
+
+    /// Like [`Torrent::parse`], with the document from `cache`.
+    pub fn parse_cached(bytes: &[u8], cache: &BencodeCache) -> Result<Self, String> {
+        Self::read(bytes, &cache.decode(bytes)?)
+    }
+
+    /// The torrent of `document`, parsed from `bytes`.
+    fn read(bytes: &[u8], document: &Bencode) -> Result<Self, String> {
+        let Bencode::Dict(root) = document else {
+            return Err("A torrent is a dictionary".into());
+        };
+        let Some(Bencode::Dict(info)) = document.get("info") else {
+            return Err("The torrent has no info dictionary".into());
+        };
+        let raw_info = raw_value(bytes, b"info").ok_or("The torrent has no info dictionary")?;
+
+        let pieces = match get(info, "pieces") {
+            Some(Bencode::ByteString(hashes)) if hashes.len() % 20 == 0 => hashes
+                .chunks_exact(20)
+                .map(|hash| hash.try_into().unwrap())
+                .collect(),
+            Some(_) => return Err("The pieces are not a list of SHA-1 hashes".into()),
+            None => Vec::new(),
+        };
+        let v1 = get(info, "pieces").is_some();
+        let v2 = number(get(info, "meta version")) == Some(2);
+        let info_hash_v1 = v1.then(|| Sha1::digest(raw_info).into());
+        let info_hash_v2 = v2.then(|| Sha256::digest(raw_info).into());
+        if !v1 && !v2 {
+            return Err("The info dictionary has neither pieces nor meta version 2".into());
+        }
+
+        let name = match get(info, "name") {
+            Some(_) => document
+                .get_utf8_str(&[&"info", &"name"])
+                .map_err(|e| e.to_string())?
+                .to_string(),
+            None => String::new(),
+        };
+        let mut files = Vec::new();
+        match (get(info, "file tree"), get(info, "files")) {
+            (Some(Bencode::Dict(tree)), _) => {
+                // A single file is at the root of the tree, by its own name.
+                let single = tree.len() == 1
+                    && matches!(tree.get(name.as_bytes()), Some(Bencode::Dict(node)) if node.contains_key(b"".as_slice()));
+                file_tree(tree, if single { "" } else { &name }, &mut files);
+            }
+            (_, Some(Bencode::List(list))) => {
+                for (i, file) in list.iter().enumerate() {
+                    let Bencode::Dict(file) = file else {
+                        continue;
+                    };
+                    // Paths name files on disk, a lossy one would not be found.
+                    let path = match get(file, "path") {
+                        Some(Bencode::List(parts)) => (0..parts.len())
+                            .map(|j| {
+                                document
+                                    .get_utf8_str(&[&"info", &"files", &i, &"path", &j])
+                                    .map_err(|e| e.to_string())
+                            })
+                            .collect::<Result<Vec<_>, _>>()?
+                            .join("/"),
+                        _ => String::new(),
+                    };
+                    files.push(File {
+                        path: format!("{}/{}", name, path),
+                        length: number(get(file, "length")).unwrap_or(0),
+                    });
+                }
+            }
+            _ => files.push(File {
+                path: name.clone(),
+                length: number(get(info, "length")).unwrap_or(0),
+            }),
+        }
+
+        let mut trackers: Vec<String> = Vec::new();
+        let tiers = match get(root, "announce-list") {
+            Some(Bencode::List(tiers)) => tiers.as_slice(),
+            _ => &[],
+        };
+        let listed = tiers.iter().flat_map(|tier| match tier {
+            Bencode::List(urls) => urls.iter().filter_map(|u| text(Some(u))).collect(),
+            _ => Vec::new(),
+        });
+        for url in text(get(root, "announce")).into_iter().chain(listed) {
+            if !trackers.contains(&url) {
+                trackers.push(url);
+            }
+        }
+
+        Ok(Self {
+            name,
+            info_hash_v1,
+            info_hash_v2,
+            trackers,
+            piece_length: number(get(info, "piece length")).unwrap_or(0),
+            pieces,
+            files,
+        })
+    }
+
+    /// Bytes of all the files.
+    pub fn size(&self) -> i64 {
+        self.files.iter().map(|f| f.length).sum()
+    }
+
+    /// Hashes the files of the torrent below `dir` against its v1 pieces.
+    /// Files are mapped rather than read, so torrents of any size are
+    /// checked without holding their files in memory.
+    pub fn verify(&self, dir: &Path) -> io::Result<Verification> {
+        if self.info_hash_v1.is_none() || self.piece_length <= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only torrents with v1 pieces can be verified",
+            ));
+        }
+        let mut pieces = Pieces::new(&self.pieces, self.piece_length as u64);
+        let mut missing = Vec::new();
+        for file in &self.files {
+            let length = file.length.max(0) as u64;
+            // BEP 47 padding files are zeros that are never written to disk.
+            if file.path.split('/').any(|part| part == ".pad") {
+                pieces.zeros(length);
+                continue;
+            }
+            let input = match crate::mmap::open(&dir.join(&file.path)) {
+                Ok(input) => input,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    missing.push(file.path.clone());
+                    pieces.skip(length);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let present = (input.len() as u64).min(length);
+            pieces.feed(&input[..present as usize]);
+            pieces.skip(length - present);
+        }
+        Ok(Verification {
+            pieces: self.pieces.len(),
+            failed: pieces.finish(),
+            missing,
+        })
+    }
+}
+
+/// The pieces of a torrent, hashed from its files one after the other.
+struct Pieces<'a> {
+    expected: &'a [[u8; 20]],
+    length: u64,
+    hasher: Sha1,
+    /// Bytes of the current piece so far.
+    filled: u64,
+    /// Whether bytes of the current piece are missing.
+    broken: bool,
+    index: usize,
+    failed: Vec<usize>,
+}
+
+impl<'a> Pieces<'a> {
+    fn new(expected: &'a [[u8; 20]], length: u64) -> Self {
+        Self {
+            expected,
+            length,
+            hasher: Sha1::new(),
+            filled: 0,
+            broken: false,
+            index: 0,
+            failed: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let take = (self.length - self.filled).min(bytes.len() as u64) as usize;
+            self.hasher.update(&bytes[..take]);
+            bytes = &bytes[take..];
+            self.advance(take as u64);
+        }
+    }
+
+    fn zeros(&mut self, mut n: u64) {
+        let zeros = [0; 4096];
+        while n > 0 {
+            let take = n.min(zeros.len() as u64);
+            self.feed(&zeros[..take as usize]);
+            n -= take;
+        }
+    }
+
+    /// Counts `n` bytes that are not there against the pieces they are in.
+    fn skip(&mut self, mut n: u64) {
+        while n > 0 {
+            let take = (self.length - self.filled).min(n);
+            self.broken = true;
+            n -= take;
+            self.advance(take);
+        }
+    }
+
+    fn advance(&mut self, n: u64) {
+        self.filled += n;
+        if self.filled == self.length {
+            self.end_piece();
+        }
+    }
+
+    fn end_piece(&mut self) {
+        let hash: [u8; 20] = self.hasher.finalize_reset().into();
+        if self.broken || self.expected.get(self.index) != Some(&hash) {
+            self.failed.push(self.index);
+        }
+        self.index += 1;
+        self.filled = 0;
+        self.broken = false;
+    }
+
+    /// The pieces that failed, the last one being shorter than the others.
+    fn finish(mut self) -> Vec<usize> {
+        if self.filled > 0 {
+            self.end_piece();
+        }
+        // Pieces the files are too short to reach.
+        self.failed.extend(self.index..self.expected.len());
+        self.failed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn single_file_v1() {
+        let torrent = b"d8:announce14:http://t/a.php13:announce-listll14:http://t/a.phpel10:udp://t:80eee4:infod6:lengthi12e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        let t = Torrent::parse(torrent).unwrap();
+        assert_eq!(t.name, "a.txt");
+        assert_eq!(t.trackers, ["http://t/a.php", "udp://t:80"]);
+        assert_eq!(t.piece_length, 16384);
+        assert_eq!(
+            t.files,
+            [File {
+                path: "a.txt".into(),
+                length: 12
+            }]
+        );
+        let info =
+            b"d6:lengthi12e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        assert_eq!(t.info_hash_v1, Some(Sha1::digest(info).into()));
+        assert_eq!(t.info_hash_v2, None);
+    }
+
+    #[test]
+    fn multi_file_and_v2() {
+        let v1 = b"d4:infod5:filesld6:lengthi1e4:pathl1:a1:beed6:lengthi2e4:pathl1:ceee4:name1:d12:piece lengthi1e6:pieces0:ee";
+        let t = Torrent::parse(v1).unwrap();
+        let paths: Vec<_> = t.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["d/a/b", "d/c"]);
+        assert_eq!(t.size(), 3);
+
+        let v2 = b"d4:infod9:file treed1:xd0:d6:lengthi5eeee12:meta versioni2e4:name1:d12:piece lengthi1eee";
+        let t = Torrent::parse(v2).unwrap();
+        assert_eq!(t.files[0].path, "d/x");
+        assert_eq!(t.size(), 5);
+        assert!(t.info_hash_v1.is_none() && t.info_hash_v2.is_some());
+        let single = b"d4:infod9:file treed1:dd0:d6:lengthi5eeee12:meta versioni2e4:name1:d12:piece lengthi1eee";
+        assert_eq!(Torrent::parse(single).unwrap().files[0].path, "d");
+        assert!(Torrent::parse(b"d4:infodee").is_err());
+    }
+
+    #[test]
+    fn binary_paths() {
+        let binary = b"d4:infod5:filesld6:lengthi1e4:pathl1:a1:\xffeee4:name1:d12:piece lengthi1e6:pieces0:ee";
+        let e = Torrent::parse(binary).unwrap_err();
+        assert!(e.starts_with("info.files.0.path.1 is not UTF-8"), "{}", e);
+        let name = b"d4:infod6:lengthi1e4:namei1e12:piece lengthi1e6:pieces0:ee";
+        assert_eq!(
+            Torrent::parse(name).unwrap_err(),
+            "info.name is not a string"
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let string = |s: &[u8]| Bencode::ByteString(s.to_vec());
+        let file = |name: &str, length: i64| {
+            let path = Bencode::List(vec![string(name.as_bytes())]);
+            let file = [
+                (b"length".to_vec(), Bencode::Number(length)),
+                (b"path".to_vec(), path),
+            ];
+            Bencode::Dict(file.into_iter().collect())
+        };
+        let pieces: Vec<u8> = [b"abcd", b"efgh"]
+            .iter()
+            .flat_map(|p| Sha1::digest(p))
+            .collect();
+        let info = [
+            (
+                b"files".to_vec(),
+                Bencode::List(vec![file("a", 3), file("b", 5)]),
+            ),
+            (b"name".to_vec(), string(b"d")),
+            (b"piece length".to_vec(), Bencode::Number(4)),
+            (b"pieces".to_vec(), string(&pieces)),
+        ];
+        let torrent = Bencode::Dict(
+            [(b"info".to_vec(), Bencode::Dict(info.into_iter().collect()))]
+                .into_iter()
+                .collect(),
+        );
+        let t = Torrent::parse(&torrent.encode()).unwrap();
+        assert_eq!(t.pieces.len(), 2);
+
+        let dir = std::env::temp_dir().join("rustvm-verify");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("d")).unwrap();
+        std::fs::write(dir.join("d/a"), b"abc").unwrap();
+        std::fs::write(dir.join("d/b"), b"defgh").unwrap();
+        let ok = t.verify(&dir).unwrap();
+        assert_eq!(
+            ok,
+            Verification {
+                pieces: 2,
+                failed: vec![],
+                missing: vec![]
+            }
+        );
+
+        std::fs::write(dir.join("d/b"), b"dexgh").unwrap();
+        assert_eq!(t.verify(&dir).unwrap().failed, [1]);
+        std::fs::remove_file(dir.join("d/a")).unwrap();
+        let gone = t.verify(&dir).unwrap();
+        assert_eq!(gone.failed, [0, 1]);
+        assert_eq!(gone.missing, ["d/a"]);
+    }
+}
@@ -0,0 +1,174 @@
+//! Scripted apps inside of an actix-web app, with the `actix` feature: an
+//! [`ActixService`] is a handler answering with a tide server in the same
+//! process, through the handlers serving it under tide.
+//!
+//!```no_run
+//! use actix_web::{web, App};
+//! use tide_rhai::{ActixService, RhaiDir};
+//!
+//! let scripts = ActixService::new(RhaiDir::new("/*", "./app/").unwrap());
+//! let app = App::new().service(web::scope("/scripts").default_service(web::to(scripts)));
+//!```
+//!
+//! A service made [`from_server`](ActixService::from_server) with a clone of
+//! a server tide listens with shares its endpoints, and with them the pools
+//! of JavaScript contexts, the compiled scripts and the bindings. Bodies are
+//! read whole before the script runs and after it answered.
+
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{Handler, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use tide::{Endpoint, Server};
+
+/// A handler answering requests with a tide server, for `web::to`.
+pub struct ActixService<State> {
+    server: Server<State>,
+}
+
+impl<State> Clone for ActixService<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            server: self.server.clone(),
+        }
+    }
+}
+
+impl ActixService<()> {
+    /// Answers every request with `endpoint`, like a [`RhaiDir`](crate::RhaiDir)
+    /// or a [`JsDir`](crate::JsDir) with the prefix `/*`. In a scope the path
+    /// it sees is the one below the scope.
+    pub fn new(endpoint: impl Endpoint<()>) -> Self {
+        let mut server = tide::new();
+        server.at("/*").all(endpoint);
+        Self { server }
+    }
+}
+
+impl<State> ActixService<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    /// Answers with `server`, routes, middleware and state as it has them.
+    pub fn from_server(server: Server<State>) -> Self {
+        Self { server }
+    }
+
+    /// The answer of the server to `req` with `body`.
+    pub async fn respond(&self, req: HttpRequest, body: Bytes) -> HttpResponse {
+        let req = match incoming(&req, body) {
+            Ok(req) => req,
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        };
+        match self.server.respond::<_, http_types::Response>(req).await {
+            Ok(res) => outgoing(res).await,
+            Err(e) => {
+                let status = StatusCode::from_u16(u16::from(e.status()))
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                HttpResponse::build(status).body(e.to_string())
+            }
+        }
+    }
+}
+
+/// The request of actix as tide reads it, failing for what cannot be.
+fn incoming(req: &HttpRequest, body: Bytes) -> Result<http_types::Request, String> {
+    let method: http_types::Method = req
+        .method()
+        .as_str()
+        .parse()
+        .map_err(|_| format!("Unsupported method {}", req.method()))?;
+    let path = format!(
+        "/{}",
+        req.match_info().unprocessed().trim_start_matches('/')
+    );
+    let path = match req.query_string() {
+        "" => path,
+        query => format!("{}?{}", path, query),
+    };
+    let host = req.connection_info().host().to_string();
+    let url = http_types::Url::parse(&format!("http://{}{}", host, path))
+        .map_err(|e| format!("Invalid URL {}: {}", path, e))?;
+    let mut out = http_types::Request::new(method, url);
+    for (name, value) in req.headers() {
+        // tide only takes text; other values do not reach the scripts.
+        if let Ok(value) = value.to_str() {
+            out.append_header(name.as_str(), value);
+        }
+    }
+    out.set_peer_addr(req.peer_addr().map(|addr| addr.to_string()));
+    out.set_body(body.to_vec());
+    Ok(out)
+}
+
+/// The response of tide as actix sends it.
+async fn outgoing(mut res: http_types::Response) -> HttpResponse {
+    let body = match res.body_bytes().await {
+        Ok(body) => body,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Cannot read the response body: {}", e))
+        }
+    };
+    let status =
+        StatusCode::from_u16(u16::from(res.status())).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut out = HttpResponse::build(status);
+    for (name, values) in res.iter() {
+        for value in values.iter() {
+            out.append_header((name.as_str(), value.as_str()));
+        }
+    }
+    out.body(body)
+}
+
+impl<State> Handler<(HttpRequest, Bytes)> for ActixService<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    type Output = HttpResponse;
+    type Future = LocalBoxFuture<'static, HttpResponse>;
+
+    fn call(&self, (req, body): (HttpRequest, Bytes)) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { service.respond(req, body).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App};
+
+    #[test]
+    fn serves_scripts() {
+        let dir = std::env::temp_dir().join("tide_rhai_actix");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("orders.rhai"),
+            r#"fn post(req) { http::response(http::CREATED, req.body_json()).header("location", "/orders/7") }"#,
+        )
+        .unwrap();
+        let service = ActixService::new(crate::RhaiDir::new("/*", &dir).unwrap());
+        actix_web::rt::System::new().block_on(async {
+            let scope = web::scope("/scripts").default_service(web::to(service));
+            let app = init_service(App::new().service(scope)).await;
+            let req = TestRequest::post()
+                .uri("/scripts/orders.rhai?x=1")
+                .insert_header(("content-type", "application/json"))
+                .set_payload(r#"{"id":7}"#)
+                .to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            assert_eq!(res.headers()["location"], "/orders/7");
+            assert_eq!(&read_body(res).await[..], br#"{"id":7}"#);
+
+            let req = TestRequest::get().uri("/scripts/missing.rhai").to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        });
+    }
+}
@@ -0,0 +1,82 @@
+// Shows the report of `?format=json` on this route, fetched again every few
+// seconds.
+(function () {
+  const main = document.querySelector("main");
+  const el = (tag, attrs, ...children) => {
+    const e = document.createElement(tag);
+    Object.assign(e, attrs);
+    e.append(...children);
+    return e;
+  };
+  const table = (columns, rows) => {
+    const head = el("tr", {}, ...columns.map(([title]) => el("th", {}, title)));
+    const body = rows.map((row) =>
+      el("tr", {}, ...columns.map(([, cell, className]) => el("td", { className: className || "" }, cell(row)))));
+    return rows.length ? el("table", {}, head, ...body) : el("p", {}, "Nothing yet.");
+  };
+  const section = (title, ...content) => [el("h2", {}, title), ...content];
+  const flag = (on, text) => (on ? el("span", { className: "bad" }, text) : "");
+
+  const render = (report) => {
+    const dirs = report.dirs;
+    const stats = table([
+      ["Engine", (d) => d.stats.engine],
+      ["Route", (d) => d.stats.prefix, "script"],
+      ["Runs", (d) => String(d.stats.runs), "n"],
+      ["Failures", (d) => String(d.stats.failures), "n"],
+      ["Compiled", (d) => String(d.stats.compiles), "n"],
+      ["Cached", (d) => String(d.stats.cache_entries), "n"],
+      ["Pool", (d) => (d.stats.pool_size ? `${d.stats.pool_size - d.stats.pool_idle} of ${d.stats.pool_size} busy` : ""), "n"],
+      ["Cold starts", (d) => String(d.stats.cold_starts), "n"],
+      ["WebSockets", (d) => (d.stats.engine === "js" ? String(d.stats.websockets) : ""), "n"],
+    ], dirs);
+    const scripts = dirs.flatMap((d) => d.scripts.map((s) => ({ ...s, engine: d.stats.engine })));
+    const errors = dirs.flatMap((d) => d.errors).sort((a, b) => b.time.localeCompare(a.time));
+    main.replaceChildren(
+      ...section("Engines", stats),
+      ...section("Scripts", table([
+        ["Script", (s) => s.script, "script"],
+        ["Engine", (s) => s.engine],
+        ["Runs", (s) => String(s.runs), "n"],
+        ["Failures", (s) => String(s.failures), "n"],
+        ["Last run", (s) => s.last_run],
+      ], scripts)),
+      ...section("Slow scripts", table([
+        ["Script", (s) => s.script, "script"],
+        ["Phase", (s) => s.phase],
+        ["p50 ms", (s) => String(s.p50_ms), "n"],
+        ["p95 ms", (s) => String(s.p95_ms), "n"],
+        ["Usual p95 ms", (s) => (s.usual_p95_ms == null ? "" : String(s.usual_p95_ms)), "n"],
+        ["", (s) => el("span", {}, flag(s.slow, "slow "), flag(s.regressed, "regressed"))],
+      ], report.slow)),
+      ...section("Scheduled jobs", table([
+        ["Job", (j) => j.name, "script"],
+        ["Every", (j) => `${j.every_secs} s`, "n"],
+        ["Runs", (j) => String(j.runs), "n"],
+        ["Failures", (j) => String(j.failures), "n"],
+        ["Last run", (j) => j.last_run || ""],
+        ["Next run", (j) => (j.running ? "running" : `in ${j.next_in_secs} s`)],
+        ["Last error", (j) => (j.last_error ? el("span", { className: "bad" }, j.last_error) : "")],
+      ], report.jobs)),
+      ...section("Recent errors", table([
+        ["Time", (e) => e.time],
+        ["Script", (e) => e.script, "script"],
+        ["Error", (e) => (e.stack
+          ? el("details", {}, el("summary", {}, e.message), el("pre", {}, e.stack))
+          : e.message)],
+      ], errors)),
+    );
+  };
+
+  const refresh = async () => {
+    try {
+      const res = await fetch(`${location.pathname}?format=json`);
+      render(await res.json());
+      document.querySelector("h1").replaceChildren("Admin", el("small", {}, new Date().toLocaleTimeString()));
+    } catch (e) {
+      main.replaceChildren(el("p", {}, `Could not load the report: ${e}`));
+    }
+  };
+  refresh();
+  setInterval(refresh, 5000);
+})();
@@ -0,0 +1,207 @@
+//! [`Admin`], a dashboard of what the directories it watches are doing: the
+//! scripts that ran, the caches, how busy the JavaScript pool is, the last
+//! errors with where they happened, the connected WebSockets, with
+//! [`Admin::with_slow_scripts`] the times of every script and, with
+//! [`Admin::with_jobs`], how the scheduled jobs went. It is for looking into
+//! a running server without attaching a debugger.
+//!
+//! Every request needs the token of the dashboard, as a bearer token or as
+//! the password of basic authentication so a browser can ask for it. The
+//! page fetches the same route with `?format=json`, which is also the API:
+//!
+//!```text
+//! curl -H "Authorization: Bearer $ADMIN_TOKEN" 'http://localhost:8080/admin?format=json'
+//!```
+
+use crate::{DirReport, Jobs, SlowScripts};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use serde_json::json;
+use std::sync::Arc;
+use tide::http::mime;
+use tide::{Body, Endpoint, Request, Response, StatusCode};
+
+const PAGE: &str = include_str!("index.html");
+const CSS: &str = include_str!("admin.css");
+const JS: &str = include_str!("admin.js");
+
+type Source = Arc<dyn Fn() -> DirReport + Send + Sync>;
+
+/// Serves the dashboard to those with its token.
+///```
+/// use std::sync::Arc;
+/// use tide_rhai::{Admin, JsDir, RhaiDir};
+/// let rhai = Arc::new(RhaiDir::new("/*", "./examples/app/").unwrap());
+/// let js = Arc::new(JsDir::new("/js/*", "./examples/app/").unwrap());
+/// let (r, j) = (rhai.clone(), js.clone());
+/// let admin = Admin::new("s3cr3t")
+///     .watch(move || r.report())
+///     .watch(move || j.report());
+/// let mut app = tide::new();
+/// app.at("/admin").get(admin);
+///```
+#[derive(Clone)]
+pub struct Admin {
+    token: String,
+    sources: Vec<Source>,
+    slow: Option<Arc<SlowScripts>>,
+    jobs: Option<Arc<Jobs>>,
+}
+
+impl Admin {
+    /// A dashboard asking for `token`.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            sources: Vec::new(),
+            slow: None,
+            jobs: None,
+        }
+    }
+
+    /// Adds the directory `report` describes, called for every request.
+    pub fn watch(mut self, report: impl Fn() -> DirReport + Send + Sync + 'static) -> Self {
+        self.sources.push(Arc::new(report));
+        self
+    }
+
+    /// Shows the times `slow` keeps of the scripts.
+    pub fn with_slow_scripts(mut self, slow: Arc<SlowScripts>) -> Self {
+        self.slow = Some(slow);
+        self
+    }
+
+    /// Shows the runs of `jobs`.
+    pub fn with_jobs(mut self, jobs: Arc<Jobs>) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// What the page shows, as json.
+    pub fn report(&self) -> serde_json::Value {
+        let dirs: Vec<_> = self.sources.iter().map(|s| s()).collect();
+        let slow = self.slow.as_ref().map(|s| s.report()).unwrap_or_default();
+        let jobs = self.jobs.as_ref().map(|j| j.report()).unwrap_or_default();
+        json!({ "dirs": dirs, "slow": slow, "jobs": jobs })
+    }
+
+    /// Whether `authorization`, the header of a request, holds the token.
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some((scheme, credentials)) = authorization.and_then(|a| a.split_once(' ')) else {
+            return false;
+        };
+        let token = if scheme.eq_ignore_ascii_case("bearer") {
+            credentials.trim().to_string()
+        } else if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = STANDARD.decode(credentials.trim()).unwrap_or_default();
+            let decoded = String::from_utf8_lossy(&decoded);
+            match decoded.split_once(':') {
+                Some((_, password)) => password.to_string(),
+                None => return false,
+            }
+        } else {
+            return false;
+        };
+        // Compared in full, so the time taken tells nothing of the token.
+        !self.token.is_empty()
+            && token.len() == self.token.len()
+            && token
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for Admin {
+    async fn call(&self, req: Request<State>) -> tide::Result {
+        let authorization = req.header("authorization").map(|h| h.as_str());
+        if !self.authorized(authorization) {
+            return Ok(Response::builder(StatusCode::Unauthorized)
+                .header("www-authenticate", "Basic realm=\"admin\"")
+                .build());
+        }
+        let json = req
+            .url()
+            .query_pairs()
+            .any(|(k, v)| k == "format" && v == "json");
+        if json {
+            return Ok(Response::builder(StatusCode::Ok)
+                .header("cache-control", "no-store")
+                .body(Body::from_json(&self.report())?)
+                .build());
+        }
+        let page = PAGE.replace("/*CSS*/", CSS).replace("/*JS*/", JS);
+        Ok(Response::builder(StatusCode::Ok)
+            .content_type(mime::HTML)
+            .header("cache-control", "no-store")
+            .body(page)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RhaiDir;
+    use std::time::Duration;
+    use tide_testing::TideTestingExt;
+
+    #[async_std::test]
+    async fn needs_the_token() {
+        let dir = std::env::temp_dir().join("tide_rhai_admin");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken"), r#"throw "oops""#).unwrap();
+        let rhai = Arc::new(RhaiDir::new("/*", &dir).unwrap());
+        let r = rhai.clone();
+        let jobs = Arc::new(Jobs::new().job("cleanup", Duration::from_secs(60), || Ok(())));
+        let mut app = tide::new();
+        app.at("/admin").get(
+            Admin::new("s3cr3t")
+                .watch(move || r.report())
+                .with_jobs(jobs),
+        );
+        let r = rhai.clone();
+        app.at("/*").get(move |req| {
+            let r = r.clone();
+            async move { r.call(req).await }
+        });
+        app.get("/broken").await.unwrap();
+
+        let res = app.get("/admin").await.unwrap();
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+        let res = app
+            .get("/admin")
+            .header("authorization", "Bearer wrong")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+
+        let basic = format!("Basic {}", STANDARD.encode("admin:s3cr3t"));
+        let mut res = app
+            .get("/admin")
+            .header("authorization", basic)
+            .await
+            .unwrap();
+        assert_eq!(res.content_type().unwrap().essence(), "text/html");
+        assert!(!res.body_string().await.unwrap().contains("/*JS*/"));
+
+        let report: serde_json::Value = app
+            .get("/admin?format=json")
+            .header("authorization", "Bearer s3cr3t")
+            .recv_json()
+            .await
+            .unwrap();
+        let rhai = &report["dirs"][0];
+        assert_eq!(rhai["stats"]["engine"], "rhai");
+        assert_eq!(rhai["scripts"][0]["script"], "broken");
+        assert_eq!(rhai["scripts"][0]["failures"], 1);
+        assert!(rhai["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("oops"));
+        assert_eq!(report["jobs"][0]["name"], "cleanup");
+        assert_eq!(report["jobs"][0]["runs"], 0);
+    }
+}
@@ -0,0 +1,362 @@
+//! Archives in the data directory, for apps taking uploads of them:
+//! `zip_extract(archive, dir)` and `tar_extract(archive, dir)` unpack a file
+//! of the data directory or a blob into `dir`, and `zip_create(name, paths)`
+//! packs files and directories into a zip.
+//!
+//!```text
+//! fn post(req) {
+//!     let names = zip_extract(req.body_blob, "uploads/" + req.query("id"));
+//!     #{ files: names }
+//! }
+//! zip_create("exports/all.zip", ["reports", "summary.csv"]);
+//!```
+//!
+//! Extracting writes only regular files and directories, leaving links out,
+//! and refuses archives whose names lead outside of `dir`. An archive must
+//! fit in the [`DataQuota`](crate::DataQuota) as a whole before anything of
+//! it is written: no more entries than `max_archive_entries`, no entry larger
+//! than `max_file_size`, no more in all than the data directory has left.
+//! Tar archives may be gzipped. Both return the names of what they wrote,
+//! relative to the data directory, and `zip_create` the number of entries.
+
+use crate::files::Sandbox;
+use flate2::read::MultiGzDecoder;
+use rhai::{Array, Blob, Dynamic, EvalAltResult, ImmutableString};
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+/// An entry of an archive to extract.
+struct Entry {
+    /// As the archive names it.
+    name: String,
+    dir: bool,
+    size: u64,
+    /// Its position in the archive.
+    index: usize,
+}
+
+fn invalid<E: std::fmt::Display>(kind: &'static str) -> impl Fn(E) -> Box<EvalAltResult> {
+    move |e| format!("Invalid {}: {}", kind, e).into()
+}
+
+fn file_error(e: io::Error) -> Box<EvalAltResult> {
+    format!("File error: {}", e).into()
+}
+
+impl Sandbox {
+    /// The bytes of `archive`, a file of the data directory or a blob.
+    fn archive_bytes(&self, archive: Dynamic) -> Out<Vec<u8>> {
+        if archive.is::<Blob>() {
+            return Ok(archive.cast::<Blob>());
+        }
+        match archive.into_immutable_string() {
+            Ok(name) => self.read_blob(name),
+            Err(kind) => Err(format!("An archive is a file name or a blob, not {}", kind).into()),
+        }
+    }
+
+    fn check_entries(&self, count: usize) -> Out<()> {
+        let max = self.quota().max_archive_entries;
+        if count > max {
+            return Err(format!("Archive with more than {} entries", max).into());
+        }
+        Ok(())
+    }
+
+    /// Where `entries` go in `dir`, once they are known to fit in the quota.
+    fn plan(&self, dir: &str, entries: &[Entry]) -> Out<Vec<PathBuf>> {
+        self.check_entries(entries.len())?;
+        let quota = self.quota();
+        let mut total = 0;
+        let mut paths = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let name = Path::new(dir).join(&entry.name);
+            let path = self.resolve_write(&name.to_string_lossy())?;
+            if !entry.dir && entry.size > quota.max_file_size {
+                return Err(format!(
+                    "File size quota exceeded: {} is {} > {} bytes",
+                    entry.name, entry.size, quota.max_file_size
+                )
+                .into());
+            }
+            total += entry.size;
+            paths.push(path);
+        }
+        let total = self.used() + total;
+        if total > quota.max_total_size {
+            return Err(format!(
+                "Data directory quota exceeded: {} > {} bytes",
+                total, quota.max_total_size
+            )
+            .into());
+        }
+        Ok(paths)
+    }
+
+    /// `path` relative to the data directory, as scripts name it.
+    fn script_name(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(self.root()).unwrap_or(path);
+        let parts: Vec<_> = relative.iter().map(|p| p.to_string_lossy()).collect();
+        parts.join("/")
+    }
+
+    pub fn zip_extract(&self, archive: Dynamic, dir: ImmutableString) -> Out<Array> {
+        let bytes = self.archive_bytes(archive)?;
+        let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).map_err(invalid("zip"))?;
+        self.check_entries(zip.len())?;
+        let mut entries = Vec::new();
+        for index in 0..zip.len() {
+            let file = zip.by_index_raw(index).map_err(invalid("zip"))?;
+            // The type bits of unix modes, links have them as 0o120000.
+            if file.unix_mode().map_or(false, |m| m & 0o170000 == 0o120000) {
+                continue;
+            }
+            entries.push(Entry {
+                name: file.name().to_string(),
+                dir: file.is_dir(),
+                size: file.size(),
+                index,
+            });
+        }
+        let paths = self.plan(&dir, &entries)?;
+        let mut names = Array::new();
+        for (entry, path) in entries.iter().zip(&paths) {
+            if entry.dir {
+                fs::create_dir_all(path).map_err(file_error)?;
+            } else {
+                let file = zip.by_index(entry.index).map_err(invalid("zip"))?;
+                extract(path, file, entry.size)?;
+            }
+            names.push(self.script_name(path).into());
+        }
+        Ok(names)
+    }
+
+    pub fn tar_extract(&self, archive: Dynamic, dir: ImmutableString) -> Out<Array> {
+        let bytes = self.archive_bytes(archive)?;
+        let open = || -> tar::Archive<Box<dyn Read + '_>> {
+            if bytes.starts_with(&[0x1f, 0x8b]) {
+                tar::Archive::new(Box::new(MultiGzDecoder::new(&bytes[..])))
+            } else {
+                tar::Archive::new(Box::new(&bytes[..]))
+            }
+        };
+        let mut listed = open();
+        let mut entries = Vec::new();
+        for (index, entry) in listed.entries().map_err(invalid("tar"))?.enumerate() {
+            let entry = entry.map_err(invalid("tar"))?;
+            let kind = entry.header().entry_type();
+            if !kind.is_file() && !kind.is_dir() {
+                continue;
+            }
+            entries.push(Entry {
+                name: entry
+                    .path()
+                    .map_err(invalid("tar"))?
+                    .to_string_lossy()
+                    .into(),
+                dir: kind.is_dir(),
+                size: entry.size(),
+                index,
+            });
+            self.check_entries(entries.len())?;
+        }
+        let paths = self.plan(&dir, &entries)?;
+        // Tar is read once through, so a second time for the contents.
+        let mut read = open();
+        let mut planned = entries.iter().zip(&paths).peekable();
+        let mut names = Array::new();
+        for (index, entry) in read.entries().map_err(invalid("tar"))?.enumerate() {
+            let Some((planned_entry, path)) = planned.peek() else {
+                break;
+            };
+            if planned_entry.index != index {
+                continue;
+            }
+            let entry = entry.map_err(invalid("tar"))?;
+            if planned_entry.dir {
+                fs::create_dir_all(path).map_err(file_error)?;
+            } else {
+                extract(path, entry, planned_entry.size)?;
+            }
+            names.push(self.script_name(path).into());
+            planned.next();
+        }
+        Ok(names)
+    }
+
+    /// Packs `sources`, a name or an array of them, into the zip `name`,
+    /// with entries named as the data directory names them.
+    pub fn zip_create(&self, name: ImmutableString, sources: Dynamic) -> Out<i64> {
+        let sources = if sources.is_string() {
+            vec![sources]
+        } else {
+            sources
+                .into_array()
+                .map_err(|_| "The sources of a zip are a name or an array of them")?
+        };
+        let target = self.resolve_write(&name)?;
+        let mut files = Vec::new();
+        for source in sources {
+            let source = source.into_immutable_string()?;
+            let path = self.resolve_read(&source)?;
+            self.collect(&path, &mut files)?;
+        }
+        files.retain(|(path, _)| *path != target);
+        self.check_entries(files.len())?;
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, dir) in &files {
+            let entry = self.script_name(path);
+            if *dir {
+                zip.add_directory(entry, options).map_err(invalid("zip"))?;
+                continue;
+            }
+            zip.start_file(entry, options).map_err(invalid("zip"))?;
+            let mut file = fs::File::open(path).map_err(file_error)?;
+            io::copy(&mut file, &mut zip).map_err(file_error)?;
+        }
+        let zip = zip.finish().map_err(invalid("zip"))?;
+        self.write_blob(name, zip.into_inner())?;
+        Ok(files.len() as i64)
+    }
+
+    /// `path` and, for directories, what is inside, without following links.
+    fn collect(&self, path: &Path, files: &mut Vec<(PathBuf, bool)>) -> Out<()> {
+        let meta = fs::symlink_metadata(path).map_err(file_error)?;
+        if meta.is_file() {
+            files.push((path.to_path_buf(), false));
+        } else if meta.is_dir() {
+            if path != self.root() {
+                files.push((path.to_path_buf(), true));
+            }
+            let mut entries: Vec<_> = fs::read_dir(path)
+                .map_err(file_error)?
+                .flatten()
+                .map(|e| e.path())
+                .collect();
+            entries.sort();
+            for entry in entries {
+                self.check_read(&entry)?;
+                self.collect(&entry, files)?;
+            }
+        }
+        self.check_entries(files.len())
+    }
+}
+
+/// Writes the `size` bytes of `contents` to `path`, failing if there are more.
+fn extract(path: &Path, contents: impl Read, size: u64) -> Out<()> {
+    let written = (|| {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::File::create(path)?;
+        let copied = io::copy(&mut contents.take(size + 1), &mut file)?;
+        file.flush()?;
+        Ok(copied)
+    })();
+    match written {
+        Ok(copied) if copied > size => {
+            let _ = fs::remove_file(path);
+            Err(format!("{} is larger than its archive says", path.display()).into())
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(file_error(e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DataQuota;
+
+    fn sandbox(name: &str, quota: DataQuota) -> Sandbox {
+        let root = std::env::temp_dir().join(format!("tide-rhai-archive-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Sandbox::new(root.canonicalize().unwrap(), quota)
+    }
+
+    fn zip_of(entries: &[(&str, &[u8])]) -> Blob {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let sb = sandbox("zip", DataQuota::default());
+        sb.write("docs/a.txt".into(), "a".into()).unwrap();
+        sb.write("docs/b/c.txt".into(), "c".into()).unwrap();
+        let count = sb
+            .zip_create("docs.zip".into(), Dynamic::from_array(vec!["docs".into()]))
+            .unwrap();
+        assert_eq!(count, 4);
+        let names = sb.zip_extract("docs.zip".into(), "copy".into()).unwrap();
+        assert_eq!(names.len(), 4);
+        assert_eq!(sb.read("copy/docs/b/c.txt".into()).unwrap(), "c");
+    }
+
+    #[test]
+    fn slips_and_limits_rejected() {
+        let sb = sandbox(
+            "limits",
+            DataQuota {
+                max_file_size: 4,
+                max_archive_entries: 2,
+                ..DataQuota::default()
+            },
+        );
+        let slip = zip_of(&[("../evil", b"x")]);
+        assert!(sb
+            .zip_extract(Dynamic::from_blob(slip), "in".into())
+            .is_err());
+        let absolute = zip_of(&[("/etc/evil", b"x")]);
+        assert!(sb
+            .zip_extract(Dynamic::from_blob(absolute), "in".into())
+            .is_err());
+        let many = zip_of(&[("a", b"1"), ("b", b"2"), ("c", b"3")]);
+        assert!(sb
+            .zip_extract(Dynamic::from_blob(many), "in".into())
+            .is_err());
+        let large = zip_of(&[("a", b"12345")]);
+        assert!(sb
+            .zip_extract(Dynamic::from_blob(large), "in".into())
+            .is_err());
+        assert!(sb.list("".into()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tar_gzipped() {
+        let sb = sandbox("tar", DataQuota::default());
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header, "notes/hello.txt", &b"hello"[..])
+            .unwrap();
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_link_name("/etc/passwd").unwrap();
+        link.set_size(0);
+        tar.append_data(&mut link, "notes/passwd", io::empty())
+            .unwrap();
+        let tar = tar.into_inner().unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&tar).unwrap();
+        let names = sb
+            .tar_extract(Dynamic::from_blob(gz.finish().unwrap()), "".into())
+            .unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(sb.read("notes/hello.txt".into()).unwrap(), "hello");
+    }
+}
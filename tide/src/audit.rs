@@ -0,0 +1,282 @@
+//! A record of every request scripts send out, for finding out what an app
+//! talks to and refusing what it should not. Each `fetch` of a rhai or
+//! JavaScript handler, every redirect it follows and every module imported
+//! by URL is one line of json:
+//!
+//!```text
+//! {"time":"2026-10-14T09:30:12.041Z","request_id":"5f0c2a9e7d41b3c8","script":"api/orders.rhai","method":"POST","destination":"api.example.com:443","url":"https://api.example.com/orders","status":201,"duration_ms":84,"known":true}
+//!```
+//!
+//! The request id is the `x-request-id` header of the request the handler
+//! answers, or a random one if it has none, so the lines of a request can be
+//! found together. Destinations not among [`AuditLog::known`] are marked
+//! `"known":false`, and refused with [`AuditLog::deny_unknown`].
+//!
+//! Like the capabilities, the handler an outbound request is for lives on the
+//! thread running it, set by [`run`] and read by [`start`].
+
+use crate::permissions::Allow;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use url::Url;
+
+/// Longest `x-request-id` taken from a request, longer ones get a new id.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Where the outbound requests of scripts are recorded, and which of them
+/// are let through.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    known: Allow<String>,
+    deny_unknown: bool,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            file: None,
+            known: Allow::none(),
+            deny_unknown: false,
+        }
+    }
+}
+
+impl AuditLog {
+    /// Records to the `audit` target of the log, with no destination known
+    /// and none refused.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the lines to the file at `path` instead, creating it if
+    /// missing.
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        self.file = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    /// The destinations scripts are expected to reach, as `host` for any
+    /// port or `host:port` like [`Permissions::net`](crate::Permissions::net).
+    pub fn known(mut self, hosts: Allow<String>) -> Self {
+        self.known = hosts;
+        self
+    }
+
+    /// Refuses requests to destinations that are not known, recording that
+    /// they were tried.
+    ///```
+    /// use tide_rhai::{Allow, AuditLog};
+    /// let audit = AuditLog::new()
+    ///     .known(Allow::Only(vec!["api.example.com".into()]))
+    ///     .deny_unknown(true);
+    ///```
+    pub fn deny_unknown(mut self, deny: bool) -> Self {
+        self.deny_unknown = deny;
+        self
+    }
+
+    fn write(&self, entry: &Entry<'_>) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Cannot record an outbound request: {}", e);
+                return;
+            }
+        };
+        match &self.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = writeln!(file, "{}", line) {
+                    log::error!("Cannot write the audit log: {}", e);
+                }
+            }
+            None => log::info!(target: "audit", "{}", line),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: String,
+    request_id: &'a str,
+    script: &'a str,
+    method: &'a str,
+    destination: String,
+    url: &'a str,
+    status: Option<u16>,
+    duration_ms: u64,
+    known: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// The handler outbound requests are recorded for.
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+    log: Arc<AuditLog>,
+    script: String,
+    request_id: String,
+}
+
+impl Origin {
+    /// The handler `script`, answering the request with the `x-request-id`
+    /// header `request_id`.
+    pub(crate) fn new(log: Arc<AuditLog>, script: &str, request_id: Option<&str>) -> Self {
+        let request_id = match request_id.map(str::trim) {
+            Some(id) if !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN => id.to_string(),
+            _ => {
+                let mut bytes = [0; 8];
+                getrandom::getrandom(&mut bytes).expect("no random numbers");
+                bytes.iter().map(|b| format!("{:02x}", b)).collect()
+            }
+        };
+        Self {
+            log,
+            script: script.to_string(),
+            request_id,
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Origin>> = RefCell::new(None);
+}
+
+/// Runs `f` recording its outbound requests for `origin`, or not at all if
+/// `None`.
+pub(crate) fn run<T>(origin: Option<Origin>, f: impl FnOnce() -> T) -> T {
+    let outer = CURRENT.with(|c| c.replace(origin));
+    let res = f();
+    CURRENT.with(|c| c.replace(outer));
+    res
+}
+
+/// The handler running on this thread, for work it hands to another thread.
+pub(crate) fn current() -> Option<Origin> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+fn destination(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port_or_known_default() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// An outbound request on its way, recorded once it is answered.
+pub(crate) struct Call {
+    origin: Option<Origin>,
+    method: String,
+    url: Url,
+    known: bool,
+    started: Instant,
+}
+
+/// Starts recording a request of the handler running on this thread to
+/// `url`, failing if its audit log refuses the destination.
+pub(crate) fn start(method: &str, url: &Url) -> Result<Call, String> {
+    let origin = current();
+    let known = origin.as_ref().map_or(true, |o| o.log.known.host(url));
+    let call = Call {
+        origin,
+        method: method.to_uppercase(),
+        url: url.clone(),
+        known,
+        started: Instant::now(),
+    };
+    if !known && call.origin.as_ref().map_or(false, |o| o.log.deny_unknown) {
+        let e = format!(
+            "Refusing to reach {}, it is not a known destination",
+            destination(url)
+        );
+        call.record(None, Some(&e));
+        return Err(e);
+    }
+    Ok(call)
+}
+
+impl Call {
+    /// Records the status the request was answered with, or why it failed.
+    pub(crate) fn finish(self, outcome: Result<u16, String>) {
+        match outcome {
+            Ok(status) => self.record(Some(status), None),
+            Err(e) => self.record(None, Some(&e)),
+        }
+    }
+
+    fn record(&self, status: Option<u16>, error: Option<&str>) {
+        let Some(origin) = &self.origin else {
+            return;
+        };
+        let url = crate::redact(self.url.as_str());
+        let error = error.map(crate::redact);
+        origin.log.write(&Entry {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            request_id: &origin.request_id,
+            script: &origin.script,
+            method: &self.method,
+            destination: destination(&self.url),
+            url: &url,
+            status,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            known: self.known,
+            error: error.as_deref(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_and_refuses_unknown() {
+        let path = std::env::temp_dir().join("tide-rhai-audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::new()
+            .with_file(&path)
+            .unwrap()
+            .known(Allow::Only(vec!["api.example.com".into()]))
+            .deny_unknown(true);
+        let origin = Origin::new(Arc::new(log), "orders.rhai", Some("req-1"));
+        let known = Url::parse("https://api.example.com/orders").unwrap();
+        let unknown = Url::parse("http://evil.example.com/").unwrap();
+
+        assert!(start("get", &unknown).is_ok());
+        run(Some(origin), || {
+            start("post", &known).unwrap().finish(Ok(201));
+            let e = start("get", &unknown).err().unwrap();
+            assert!(e.contains("evil.example.com:80"));
+        });
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["request_id"], "req-1");
+        assert_eq!(lines[0]["script"], "orders.rhai");
+        assert_eq!(lines[0]["method"], "POST");
+        assert_eq!(lines[0]["destination"], "api.example.com:443");
+        assert_eq!(lines[0]["status"], 201);
+        assert_eq!(lines[0]["known"], true);
+        assert_eq!(lines[1]["known"], false);
+        assert_eq!(lines[1]["status"], serde_json::Value::Null);
+        assert!(lines[1]["error"].is_string());
+
+        let generated = Origin::new(Arc::new(AuditLog::new()), "a.ts", None);
+        assert_eq!(generated.request_id.len(), 16);
+    }
+}
@@ -0,0 +1,142 @@
+//! Scripted apps inside of an axum router, with the `axum` feature: the
+//! requests axum routes to an [`AxumService`] are answered by a tide server
+//! in the same process, through the handlers serving them under tide.
+//!
+//!```no_run
+//! use tide_rhai::{AxumService, JsDir, RhaiDir};
+//!
+//! let router: axum::Router = axum::Router::new()
+//!     .route("/health", axum::routing::get(|| async { "ok" }))
+//!     .nest_service("/scripts", AxumService::new(RhaiDir::new("/*", "./app/").unwrap()))
+//!     .nest_service("/js", AxumService::new(JsDir::new("/*", "./app/").unwrap()));
+//!```
+//!
+//! Bodies are read whole before the script runs and after it answered, so a
+//! streamed response reaches the client once the script finished it.
+
+use crate::hyper_server::{incoming, outgoing};
+use axum::body::{boxed, Body, Full};
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use futures::future::BoxFuture;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+use tide::{Endpoint, Server};
+
+/// A tower service answering requests with a tide server, to mount in an
+/// axum router with `nest_service` or `fallback_service`.
+pub struct AxumService<State> {
+    server: Server<State>,
+}
+
+impl<State> Clone for AxumService<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            server: self.server.clone(),
+        }
+    }
+}
+
+impl AxumService<()> {
+    /// Answers every request with `endpoint`, like a [`RhaiDir`](crate::RhaiDir)
+    /// or a [`JsDir`](crate::JsDir) with the prefix `/*`. Under `nest_service`
+    /// the path it sees is the one below where it is nested.
+    pub fn new(endpoint: impl Endpoint<()>) -> Self {
+        let mut server = tide::new();
+        server.at("/*").all(endpoint);
+        Self { server }
+    }
+}
+
+impl<State> AxumService<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    /// Answers with `server`, routes, middleware and state as it has them.
+    pub fn from_server(server: Server<State>) -> Self {
+        Self { server }
+    }
+}
+
+fn failed(status: StatusCode, message: String) -> Response {
+    let mut res = Response::new(boxed(Full::from(message)));
+    *res.status_mut() = status;
+    res
+}
+
+impl<State> tower_service::Service<Request<Body>> for AxumService<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let server = self.server.clone();
+        Box::pin(async move {
+            let peer = req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|c| c.0);
+            let mut req = match incoming(req).await {
+                Ok(req) => req,
+                Err(e) => return Ok(failed(StatusCode::BAD_REQUEST, e)),
+            };
+            req.set_peer_addr(peer.map(|addr| addr.to_string()));
+            Ok(match server.respond::<_, http_types::Response>(req).await {
+                Ok(res) => match outgoing(res).await {
+                    Ok(res) => res.map(|body| boxed(Full::from(body))),
+                    Err(e) => failed(StatusCode::INTERNAL_SERVER_ERROR, e),
+                },
+                Err(e) => failed(
+                    StatusCode::from_u16(u16::from(e.status()))
+                        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                    e.to_string(),
+                ),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tower_service::Service;
+
+    #[test]
+    fn serves_scripts() {
+        let dir = std::env::temp_dir().join("tide_rhai_axum");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("orders.rhai"),
+            r#"fn post(req) { http::response(http::CREATED, req.body_json()).header("location", "/orders/7") }"#,
+        )
+        .unwrap();
+        let mut service = AxumService::new(crate::RhaiDir::new("/*", &dir).unwrap());
+        crate::rt::block_on(async {
+            let req = Request::post("/orders.rhai?x=1")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"id":7}"#))
+                .unwrap();
+            let res = service.call(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+            assert_eq!(res.headers()["location"], "/orders/7");
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(&body[..], br#"{"id":7}"#);
+
+            let req = Request::get("/missing.rhai").body(Body::empty()).unwrap();
+            let res = service.call(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        });
+    }
+}
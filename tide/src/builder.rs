@@ -0,0 +1,198 @@
+//! [`RhaiDirBuilder`], for the settings of a [`RhaiDir`] that are fixed
+//! once it serves: the engine, its limits, the cache of compiled scripts,
+//! index files and what to answer when a script fails.
+
+use crate::{cache, validate, DataQuota, ErrorHook, Permissions, RhaiDir, Secrets};
+use rhai::{Engine, EvalAltResult};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tide::Response;
+
+/// Limits of the rhai engine, `None` keeps the default of the engine.
+///```
+/// use tide_rhai::{Limits, RhaiDir};
+/// let dir = RhaiDir::builder("/*", "./examples/app/")
+///     .limits(Limits {
+///         max_operations: Some(1_000_000),
+///         max_string_size: Some(64 * 1024),
+///         ..Limits::default()
+///     })
+///     .build()
+///     .unwrap();
+///```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Steps of the engine a script may take, see
+    /// [`RhaiDir::with_max_operations`].
+    pub max_operations: Option<u64>,
+    /// How deep functions may call each other.
+    pub max_call_levels: Option<usize>,
+    /// Longest string in bytes a script may build.
+    pub max_string_size: Option<usize>,
+    pub max_array_size: Option<usize>,
+    pub max_map_size: Option<usize>,
+    /// Largest blob in bytes `gzip_decompress` and `zstd_decompress` give,
+    /// 64 MiB when `None`.
+    pub max_decompressed_size: Option<usize>,
+    /// Most pixels of an image `image_resize` and `image_thumbnail` read or
+    /// make, 40 million when `None`.
+    pub max_image_pixels: Option<u64>,
+}
+
+impl Limits {
+    pub(crate) fn apply(&self, engine: &mut Engine) {
+        if let Some(n) = self.max_operations {
+            engine.set_max_operations(n);
+        }
+        if let Some(n) = self.max_call_levels {
+            engine.set_max_call_levels(n);
+        }
+        if let Some(n) = self.max_string_size {
+            engine.set_max_string_size(n);
+        }
+        if let Some(n) = self.max_array_size {
+            engine.set_max_array_size(n);
+        }
+        if let Some(n) = self.max_map_size {
+            engine.set_max_map_size(n);
+        }
+    }
+}
+
+/// Settings of a [`RhaiDir`], made by [`RhaiDir::builder`].
+///```
+/// use tide_rhai::RhaiDir;
+/// use tide::{Response, StatusCode};
+/// let dir = RhaiDir::builder("/*", "./examples/app/")
+///     .engine(|engine| {
+///         engine.register_fn("double", |x: i64| x * 2);
+///     })
+///     .index_files(["index.rhai"])
+///     .on_error(|_path, _e| Some(Response::new(StatusCode::ServiceUnavailable)))
+///     .build()
+///     .unwrap();
+///```
+pub struct RhaiDirBuilder {
+    prefix: String,
+    dir: PathBuf,
+    setup: Vec<Arc<dyn Fn(&mut Engine) + Send + Sync>>,
+    limits: Limits,
+    cache_scripts: bool,
+    shared_cache: bool,
+    index_files: Vec<String>,
+    error_hooks: Vec<ErrorHook>,
+    dev_mode: bool,
+}
+
+impl RhaiDir {
+    /// The settings of a directory serving the scripts of `dir` under
+    /// `prefix`, see [`RhaiDirBuilder`].
+    pub fn builder(prefix: &str, dir: impl AsRef<Path>) -> RhaiDirBuilder {
+        RhaiDirBuilder {
+            prefix: prefix.to_string(),
+            dir: dir.as_ref().to_path_buf(),
+            setup: Vec::new(),
+            limits: Limits::default(),
+            cache_scripts: true,
+            shared_cache: true,
+            index_files: Vec::new(),
+            error_hooks: Vec::new(),
+            dev_mode: false,
+        }
+    }
+}
+
+impl RhaiDirBuilder {
+    /// Runs `setup` on the engine once the bindings are registered, like
+    /// [`RhaiDir::with_engine_setup`].
+    pub fn engine(mut self, setup: impl Fn(&mut Engine) + Send + Sync + 'static) -> Self {
+        self.setup.push(Arc::new(setup));
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether compiled scripts are kept between requests, on by default.
+    /// Without it every request compiles its script again.
+    pub fn cache_scripts(mut self, cache: bool) -> Self {
+        self.cache_scripts = cache;
+        self
+    }
+
+    /// Whether compiled scripts are shared with the other directories
+    /// serving the same files, on by default.
+    pub fn shared_cache(mut self, shared: bool) -> Self {
+        self.shared_cache = shared;
+        self
+    }
+
+    /// The scripts answering requests for a directory, tried in order, like
+    /// `index.rhai` for `/orders/`. None by default.
+    pub fn index_files<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.index_files = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Runs `hook` with the script and the error when a script fails. The
+    /// first hook answering a response replaces the error page or 500.
+    /// Errors thrown as `HttpError` are answered as they are.
+    pub fn on_error(
+        mut self,
+        hook: impl Fn(&Path, &EvalAltResult) -> Option<Response> + Send + Sync + 'static,
+    ) -> Self {
+        self.error_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// See [`RhaiDir::with_dev_mode`].
+    pub fn dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    /// Fails if the directory does not exist.
+    pub fn build(self) -> io::Result<RhaiDir> {
+        let dir = self.dir.canonicalize()?;
+        let asts = if self.shared_cache {
+            cache::AstCache::for_dir(&dir)
+        } else {
+            Arc::default()
+        };
+        let routes = validate::Routes::load(&dir)?;
+        Ok(RhaiDir {
+            prefix: self.prefix,
+            dir,
+            data_dir: None,
+            data_quota: DataQuota::default(),
+            mailer: None,
+            oauth: None,
+            storage: None,
+            snapshot: None,
+            integrity: None,
+            permissions: Permissions::default(),
+            secrets: Secrets::default(),
+            audit: None,
+            routes,
+            setup: self.setup,
+            globals: Vec::new(),
+            scope_hooks: Vec::new(),
+            debugger: None,
+            profiler: None,
+            slow: None,
+            dev_mode: self.dev_mode,
+            limits: self.limits,
+            cache_scripts: self.cache_scripts,
+            index_files: self.index_files,
+            error_hooks: self.error_hooks,
+            counters: Arc::default(),
+            modules: Arc::default(),
+            asts,
+            memo: Arc::default(),
+            engine: OnceLock::new(),
+        })
+    }
+}
@@ -0,0 +1,89 @@
+//! Compiled rhai scripts, kept between requests. Every [`RhaiDir`] serving
+//! the same directory shares one cache, so mounting an app under several
+//! routes compiles each script once.
+//!
+//! Entries are keyed by the canonical path of the script and checked against
+//! a hash of its source, so edited scripts are compiled again.
+//!
+//! [`RhaiDir`]: crate::RhaiDir
+
+use rhai::AST;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+#[derive(Default)]
+pub(crate) struct AstCache {
+    scripts: Mutex<HashMap<PathBuf, (u64, Arc<AST>)>>,
+}
+
+fn hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl AstCache {
+    /// The cache of the directory `root`, shared with the other directories
+    /// serving it for as long as one of them is alive.
+    pub(crate) fn for_dir(root: &Path) -> Arc<Self> {
+        static CACHES: OnceLock<Mutex<HashMap<PathBuf, Weak<AstCache>>>> = OnceLock::new();
+        let mut caches = CACHES.get_or_init(Default::default).lock().unwrap();
+        caches.retain(|_, c| c.strong_count() > 0);
+        if let Some(cache) = caches.get(root).and_then(Weak::upgrade) {
+            return cache;
+        }
+        let cache = Arc::new(Self::default());
+        caches.insert(root.to_path_buf(), Arc::downgrade(&cache));
+        cache
+    }
+
+    /// The script at `path` as compiled from `source`, if it was.
+    pub(crate) fn get(&self, path: &Path, source: &str) -> Option<Arc<AST>> {
+        let scripts = self.scripts.lock().unwrap();
+        match scripts.get(path) {
+            Some((h, ast)) if *h == hash(source) => Some(ast.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, path: &Path, source: &str, ast: AST) -> Arc<AST> {
+        let ast = Arc::new(ast);
+        self.scripts
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (hash(source), ast.clone()));
+        ast
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.scripts.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::Engine;
+
+    #[test]
+    fn shared_by_root() {
+        let root = Path::new("/tmp/tide_rhai_ast_cache");
+        let a = AstCache::for_dir(root);
+        let b = AstCache::for_dir(root);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(
+            &a,
+            &AstCache::for_dir(Path::new("/elsewhere"))
+        ));
+
+        let engine = Engine::new();
+        let script = root.join("a.rhai");
+        a.insert(&script, "1 + 1", engine.compile("1 + 1").unwrap());
+        assert!(b.get(&script, "1 + 1").is_some());
+        assert!(b.get(&script, "1 + 2").is_none());
+        assert_eq!(b.len(), 1);
+    }
+}
@@ -0,0 +1,200 @@
+//! What a single handler may do, declared by the script itself. A comment
+//! among the first lines of a rhai or JavaScript handler, or else a sidecar
+//! file named after it with `.capabilities` appended, lists what it needs:
+//!
+//!```text
+//! // capabilities: net, env
+//! fn get(req) {
+//!     fetch(fetch_options())
+//! }
+//!```
+//!
+//! `net` is `fetch`, `WebSocket` and `send_mail`, `fs` the `file_*` and
+//! archive bindings of the data directory, `db` the `storage` of
+//! JavaScript, `storage` the `s3` bucket, and `env` the environment and
+//! `secret`. Anything
+//! else the handler, the modules it imports and the workers it starts try
+//! fails, even if the [`Permissions`](crate::Permissions) of the app allow
+//! it. `// capabilities: none` grants nothing. Handlers declaring nothing
+//! keep everything the permissions allow.
+//!
+//! The grant lives on the thread running the handler, set by [`run`] and
+//! checked by the bindings with [`require`].
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+
+/// One kind of binding a handler may be granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Capability {
+    Net,
+    Fs,
+    Db,
+    Env,
+    Storage,
+}
+
+impl Capability {
+    const ALL: [Capability; 5] = [Self::Net, Self::Fs, Self::Db, Self::Env, Self::Storage];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Net => "net",
+            Self::Fs => "fs",
+            Self::Db => "db",
+            Self::Env => "env",
+            Self::Storage => "storage",
+        }
+    }
+}
+
+/// What a handler declared, and which handler it is for the errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Grant {
+    granted: u8,
+    script: String,
+}
+
+impl fmt::Display for Grant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = Capability::ALL
+            .iter()
+            .filter(|c| self.granted & c.bit() != 0)
+            .map(|c| c.name())
+            .collect();
+        if names.is_empty() {
+            write!(f, "{}: none", self.script)
+        } else {
+            write!(f, "{}: {}", self.script, names.join(", "))
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Grant>> = RefCell::new(None);
+}
+
+/// The names of `list`, separated by commas or spaces.
+fn parse(list: &str, script: &str) -> Result<Grant, String> {
+    let mut granted = 0;
+    for name in list.split(|c: char| c == ',' || c.is_whitespace()) {
+        match name {
+            "" | "none" => {}
+            name => match Capability::ALL.iter().find(|c| c.name() == name) {
+                Some(c) => granted |= c.bit(),
+                None => {
+                    return Err(format!(
+                        "{} declares the unknown capability {:?}, not net, fs, db, env, storage or none",
+                        script, name
+                    ))
+                }
+            },
+        }
+    }
+    Ok(Grant {
+        granted,
+        script: script.to_string(),
+    })
+}
+
+/// The `capabilities:` comment among the leading comments of `source`.
+fn header(source: &str) -> Option<&str> {
+    let comments = source
+        .lines()
+        .map(str::trim)
+        .skip_while(|l| l.is_empty() || l.starts_with("#!"))
+        .map_while(|l| l.strip_prefix("//"));
+    for comment in comments {
+        let comment = comment.trim_start_matches(['/', '!']).trim();
+        if let Some(list) = comment.strip_prefix("capabilities:") {
+            return Some(list);
+        }
+    }
+    None
+}
+
+/// What the handler at `path`, named `script` in errors, declares in its
+/// `source` or its sidecar file, `None` if it declares nothing.
+pub(crate) fn declared(path: &Path, script: &str, source: &str) -> Result<Option<Grant>, String> {
+    if let Some(list) = header(source) {
+        return parse(list, script).map(Some);
+    }
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".capabilities");
+    match std::fs::read_to_string(&sidecar) {
+        Ok(text) => {
+            let list: Vec<_> = text
+                .lines()
+                .map(|l| l.split('#').next().unwrap_or_default())
+                .collect();
+            parse(&list.join(" "), script).map(Some)
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Runs `f` with only what `grant` declares, or without limits if `None`.
+pub(crate) fn run<T>(grant: Option<Grant>, f: impl FnOnce() -> T) -> T {
+    let outer = CURRENT.with(|c| c.replace(grant));
+    let res = f();
+    CURRENT.with(|c| c.replace(outer));
+    res
+}
+
+/// The grant of the handler running on this thread, for work it hands to
+/// another thread.
+pub(crate) fn current() -> Option<Grant> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Fails unless the handler running on this thread may use `capability`.
+pub(crate) fn require(capability: Capability) -> Result<(), String> {
+    CURRENT.with(|c| match &*c.borrow() {
+        Some(grant) if grant.granted & capability.bit() == 0 => Err(format!(
+            "Requires the {} capability, which {} does not declare",
+            capability.name(),
+            grant.script
+        )),
+        _ => Ok(()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn declares_and_requires() {
+        let source =
+            "#!/usr/bin/env rustvm\n// Orders.\n//! capabilities: net, env\nfn get(req) {}";
+        let grant = declared(Path::new("orders.rhai"), "orders.rhai", source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(grant.to_string(), "orders.rhai: net, env");
+        assert!(require(Capability::Fs).is_ok());
+        run(Some(grant), || {
+            assert!(require(Capability::Net).is_ok());
+            let e = require(Capability::Fs).unwrap_err();
+            assert_eq!(
+                e,
+                "Requires the fs capability, which orders.rhai does not declare"
+            );
+        });
+        assert!(require(Capability::Fs).is_ok());
+
+        let late = "fn get(req) {}\n// capabilities: none";
+        assert_eq!(declared(Path::new("a.rhai"), "a.rhai", late).unwrap(), None);
+        assert!(declared(Path::new("a.rhai"), "a.rhai", "// capabilities: disk").is_err());
+
+        let dir = std::env::temp_dir().join("tide-rhai-capabilities");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.ts.capabilities"), "db # the cart\n").unwrap();
+        let grant = declared(&dir.join("b.ts"), "b.ts", "export {}").unwrap();
+        assert_eq!(grant.unwrap().to_string(), "b.ts: db");
+    }
+}
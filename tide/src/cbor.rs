@@ -0,0 +1,212 @@
+//! CBOR, for devices speaking it: [`decode`] and [`encode`] between bytes and
+//! a [`Value`] of any shape, and [`to_vec`] and [`from_slice`] for types with
+//! serde. Scripts get the same: `cbor::decode(blob)` and `cbor::encode(value)`
+//! in rhai, with `req.body_cbor()` for request bodies, and a `cbor` global
+//! with `decode` and `encode` in JavaScript handlers running on boa.
+//!
+//!```
+//! use tide_rhai::cbor::{self, Value};
+//!
+//! let bytes = cbor::encode(&Value::Array(vec![Value::from(1), Value::from("one")]));
+//! assert_eq!(bytes, b"\x82\x01\x63one");
+//! assert_eq!(cbor::from_slice::<(i64, String)>(&bytes).unwrap(), (1, "one".into()));
+//!```
+//!
+//! Decoded values are the maps and arrays of the scripts, so a script can
+//! answer a device's readings as json, or hand them on with `bencode::encode`:
+//!
+//!```text
+//! fn post(req) {
+//!     let reading = req.body_cbor();
+//!     #{ device: reading.id, celsius: reading.t }
+//! }
+//!```
+//!
+//! In rhai, byte strings are blobs, integers past those of rhai floats, and
+//! map keys that are not text are written as they print, or as the hex of
+//! their CBOR when they are not numbers or booleans. Tagged values are maps
+//! of their `tag` and `value`, and stay maps when encoded again.
+
+use rhai::{Blob, Dynamic, EvalAltResult, Map, Module, Shared};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+pub use ciborium::value::Value;
+
+/// Parses `bytes` holding exactly one value.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let mut rest = bytes;
+    let value: Value = ciborium::de::from_reader(&mut rest)
+        .map_err(|e| format!("Invalid CBOR at byte {}: {}", bytes.len() - rest.len(), e))?;
+    match rest.len() {
+        0 => Ok(value),
+        left => Err(format!("{} bytes are left after the value", left)),
+    }
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    ciborium::ser::into_writer(value, &mut out).expect("Writing to a Vec cannot fail");
+    out
+}
+
+/// `value` in CBOR, structs as maps by field name.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    ciborium::ser::into_writer(value, &mut out).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// A Rust value read from CBOR, failing if it does not have the shape of `T`.
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    ciborium::de::from_reader(bytes).map_err(|e| e.to_string())
+}
+
+/// `value` as a rhai value, see the [module](self).
+pub(crate) fn to_dynamic(value: Value) -> Dynamic {
+    match value {
+        Value::Integer(n) => match i64::try_from(n) {
+            Ok(n) => n.into(),
+            Err(_) => (i128::from(n) as f64).into(),
+        },
+        Value::Bytes(bytes) => Dynamic::from_blob(bytes),
+        Value::Float(f) => f.into(),
+        Value::Text(s) => s.into(),
+        Value::Bool(b) => b.into(),
+        Value::Tag(tag, value) => {
+            let mut tagged = Map::new();
+            tagged.insert("tag".into(), (tag as i64).into());
+            tagged.insert("value".into(), to_dynamic(*value));
+            Dynamic::from_map(tagged)
+        }
+        Value::Array(items) => Dynamic::from_array(items.into_iter().map(to_dynamic).collect()),
+        Value::Map(entries) => Dynamic::from_map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (key(k).into(), to_dynamic(v)))
+                .collect(),
+        ),
+        // Null, and whatever later versions of CBOR add.
+        _ => Dynamic::UNIT,
+    }
+}
+
+/// A map key as a rhai map has them.
+pub(crate) fn key(key: Value) -> String {
+    match key {
+        Value::Text(s) => s,
+        Value::Integer(n) => i128::from(n).to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Bool(b) => b.to_string(),
+        other => encode(&other)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    }
+}
+
+/// The value of rhai written the way [`to_dynamic`] writes it, with
+/// characters as strings; custom types cannot be written.
+pub(crate) fn from_dynamic(value: Dynamic) -> Result<Value, String> {
+    if value.is_unit() {
+        return Ok(Value::Null);
+    }
+    if let Ok(b) = value.as_bool() {
+        return Ok(Value::Bool(b));
+    }
+    if let Ok(n) = value.as_int() {
+        return Ok(Value::from(n));
+    }
+    if let Ok(f) = value.as_float() {
+        return Ok(Value::Float(f));
+    }
+    if let Ok(c) = value.as_char() {
+        return Ok(Value::Text(c.to_string()));
+    }
+    if value.is_string() {
+        let s = value.into_immutable_string().unwrap_or_default();
+        return Ok(Value::Text(s.to_string()));
+    }
+    if value.is::<Blob>() {
+        return Ok(Value::Bytes(value.cast::<Blob>()));
+    }
+    if value.is_array() {
+        return value
+            .cast::<rhai::Array>()
+            .into_iter()
+            .map(from_dynamic)
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    if value.is_map() {
+        return value
+            .cast::<Map>()
+            .into_iter()
+            .map(|(k, v)| Ok((Value::Text(k.to_string()), from_dynamic(v)?)))
+            .collect::<Result<_, String>>()
+            .map(Value::Map);
+    }
+    Err(format!("CBOR cannot hold {}", value.type_name()))
+}
+
+/// The `cbor` module of rhai scripts, see the [module](self).
+pub(crate) fn module() -> Shared<Module> {
+    static MODULE: OnceLock<Shared<Module>> = OnceLock::new();
+    MODULE.get_or_init(|| build().into()).clone()
+}
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+fn build() -> Module {
+    let mut m = Module::new();
+    m.set_native_fn("decode", |bytes: Blob| -> Out<Dynamic> {
+        Ok(to_dynamic(decode(&bytes)?))
+    });
+    m.set_native_fn("encode", |value: Dynamic| -> Out<Blob> {
+        Ok(encode(&from_dynamic(value)?))
+    });
+    m
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        id: String,
+        t: f64,
+    }
+
+    #[test]
+    fn values_and_scripts() {
+        let reading = Reading {
+            id: "probe".into(),
+            t: 21.5,
+        };
+        let bytes = to_vec(&reading).unwrap();
+        assert_eq!(from_slice::<Reading>(&bytes).unwrap(), reading);
+        let value = decode(&bytes).unwrap();
+        assert_eq!(encode(&value), bytes);
+        assert!(decode(&[0x82, 0x01]).is_err());
+        assert!(decode(&[0x01, 0x02]).is_err());
+
+        let mut engine = rhai::Engine::new();
+        engine.register_static_module("cbor", module());
+        let mut scope = rhai::Scope::new();
+        scope.push("bytes", Dynamic::from_blob(bytes));
+        let t: f64 = engine
+            .eval_with_scope(&mut scope, "cbor::decode(bytes).t")
+            .unwrap();
+        assert_eq!(t, 21.5);
+        let encoded: Blob = engine
+            .eval_with_scope(&mut scope, r#"cbor::encode([1, (), true, "x"])"#)
+            .unwrap();
+        assert_eq!(encoded, b"\x84\x01\xf6\xf5\x61x");
+        let tagged = to_dynamic(Value::Tag(1, Box::new(Value::from(1_700_000_000))));
+        assert_eq!(tagged.cast::<Map>()["tag"].as_int(), Ok(1));
+        assert_eq!(key(Value::Array(Vec::new())), "80");
+    }
+}
@@ -0,0 +1,284 @@
+//! Everything wrong with the scripts of an app that would only show once
+//! deployed, found without running any of them: syntax errors of rhai,
+//! JavaScript, TypeScript and json files, and imports that do not resolve.
+//!
+//! Unlike [`Snapshot::build`](crate::Snapshot::build) every problem is
+//! reported, not only the first one, for `rustvm check` to gate deployments.
+
+use crate::js::graph::{self, Dependency};
+use crate::js::typescript::{self, TranspileError};
+use crate::modules::AppModules;
+use boa_engine::{module::Module, script::Script, Source};
+use rhai::Engine;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A problem of a file, at a line and column when it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Diagnostic {
+    fn new(file: &Path, line: Option<usize>, column: Option<usize>, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn syntax(file: &Path, e: TranspileError) -> Self {
+        Self::new(file, Some(e.line), Some(e.column), e.message)
+    }
+}
+
+/// The line of `source` naming `specifier` in quotes, where a dependency
+/// that does not resolve is reported.
+fn line_of(source: &str, specifier: &str) -> Option<usize> {
+    let quoted = ['"', '\'', '`'].map(|q| format!("{}{}{}", q, specifier, q));
+    source
+        .lines()
+        .position(|l| quoted.iter().any(|q| l.contains(q.as_str())))
+        .map(|i| i + 1)
+}
+
+/// The literal paths of the `import "..." as name;` statements of a rhai
+/// script, with their line and column.
+fn rhai_imports(source: &str) -> Vec<(usize, usize, &str)> {
+    let mut found = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let code = line.split("//").next().unwrap_or_default();
+        let mut from = 0;
+        while let Some(at) = code[from..].find("import").map(|at| from + at) {
+            from = at + "import".len();
+            let word = code[..at]
+                .chars()
+                .next_back()
+                .map_or(true, |c| !c.is_alphanumeric() && c != '_');
+            let rest = code[from..].trim_start();
+            if !word || rest.len() == code[from..].len() {
+                continue;
+            }
+            if let Some((path, _)) = rest.strip_prefix('"').and_then(|r| r.split_once('"')) {
+                found.push((i + 1, at + 1, path));
+            }
+        }
+    }
+    found
+}
+
+struct Checker {
+    root: PathBuf,
+    engine: Engine,
+    modules: AppModules,
+    found: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn rhai(&mut self, path: &Path, source: &str) {
+        if let Err(e) = self.engine.compile(source) {
+            let message = e.0.to_string();
+            let found = Diagnostic::new(path, e.1.line(), e.1.position(), message);
+            self.found.push(found);
+            return;
+        }
+        let importer = path.to_string_lossy();
+        for (line, column, import) in rhai_imports(source) {
+            let message = match self.modules.locate(Some(&importer), import) {
+                Some(file) if file.is_file() => continue,
+                Some(_) => format!("Cannot find module '{}'", import),
+                None => format!("Module '{}' is outside of the app directory", import),
+            };
+            let found = Diagnostic::new(path, Some(line), Some(column), message);
+            self.found.push(found);
+        }
+    }
+
+    fn js(&mut self, path: &Path, source: &str, context: &mut boa_engine::Context<'_>) {
+        let dependencies = match graph::dependencies(path, source) {
+            Ok(d) => d,
+            Err(e) => {
+                self.found.push(Diagnostic::syntax(path, e));
+                return;
+            }
+        };
+        let src = Source::from_bytes(source).with_path(path);
+        let parsed = if crate::js::is_module(path, source) {
+            Module::parse(src, None, context).err()
+        } else {
+            Script::parse(src, None, context).err()
+        };
+        if let Some(e) = parsed {
+            self.found
+                .push(Diagnostic::new(path, None, None, e.to_string()));
+            return;
+        }
+        self.dependencies(path, source, &dependencies);
+    }
+
+    fn typescript(&mut self, path: &Path, source: &str) {
+        let js = match typescript::transpile(path, source) {
+            Ok(js) => js,
+            Err(e) => {
+                self.found.push(Diagnostic::syntax(path, e));
+                return;
+            }
+        };
+        match graph::dependencies(path, &js.code) {
+            Ok(dependencies) => self.dependencies(path, source, &dependencies),
+            Err(e) => self.found.push(Diagnostic::syntax(path, e)),
+        }
+    }
+
+    /// Checks the `dependencies` of the script at `path` resolve.
+    fn dependencies(&mut self, path: &Path, source: &str, dependencies: &[Dependency]) {
+        let dir = path.parent().unwrap_or(&self.root);
+        for dependency in dependencies {
+            if let Some(Err(e)) = graph::resolve(&self.root, dir, dependency) {
+                let line = line_of(source, dependency.specifier());
+                self.found.push(Diagnostic::new(path, line, None, e));
+            }
+        }
+    }
+
+    fn json(&mut self, path: &Path, source: &str) {
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(source) {
+            let message = e.to_string();
+            let message = match message.rsplit_once(" at line ") {
+                Some((m, _)) => m.to_string(),
+                None => message,
+            };
+            let found = Diagnostic::new(path, Some(e.line()), Some(e.column()), message);
+            self.found.push(found);
+        }
+    }
+}
+
+/// Checks every script below `dir` outside of `node_modules`, and that the
+/// modules they import resolve, the way handlers would load them.
+///
+///```no_run
+/// for problem in tide_rhai::check("./app/").unwrap() {
+///     eprintln!("{}", problem);
+/// }
+///```
+pub fn check(dir: impl AsRef<Path>) -> io::Result<Vec<Diagnostic>> {
+    let root = dir.as_ref().canonicalize()?;
+    let mut paths = Vec::new();
+    crate::snapshot::collect(&root, &mut paths)?;
+    paths.retain(|p| {
+        let rel = p.strip_prefix(&root).unwrap_or(p);
+        !rel.components().any(|c| c.as_os_str() == "node_modules")
+    });
+    paths.sort();
+
+    let mut checker = Checker {
+        root: root.clone(),
+        engine: Engine::new_raw(),
+        modules: AppModules {
+            root,
+            snapshot: None,
+            integrity: None,
+            cache: Default::default(),
+        },
+        found: Vec::new(),
+    };
+    let mut context = boa_engine::Context::default();
+    for path in paths {
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            continue;
+        };
+        if !matches!(
+            ext,
+            "rhai" | "js" | "mjs" | "cjs" | "ts" | "mts" | "cts" | "json"
+        ) {
+            continue;
+        }
+        let source = match std::fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) => {
+                checker
+                    .found
+                    .push(Diagnostic::new(&path, None, None, e.to_string()));
+                continue;
+            }
+        };
+        match ext {
+            "rhai" => checker.rhai(&path, &source),
+            "ts" | "mts" | "cts" => checker.typescript(&path, &source),
+            "json" => checker.json(&path, &source),
+            _ => checker.js(&path, &source, &mut context),
+        }
+    }
+    Ok(checker.found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_every_problem() {
+        let root = std::env::temp_dir().join("tide-rhai-check");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        let write = |name: &str, source: &str| std::fs::write(root.join(name), source).unwrap();
+        write("lib/math.rhai", "fn double(n) { n * 2 }");
+        write("ok.rhai", "import \"lib::math\" as m;\nm::double(2)");
+        write("broken.rhai", "let a = 1;\nlet x = ;");
+        write(
+            "imports.rhai",
+            "// import \"gone\" as g;\nimport \"lib::gone\" as g;",
+        );
+        write(
+            "api.js",
+            "import m from \"./missing.js\";\nexport default m;",
+        );
+        write("bad.ts", "const x: number = ;");
+        write("data.json", "{\n  \"a\": 1,\n}");
+        write("node_modules/pkg/index.js", "this is not javascript");
+
+        let root = root.canonicalize().unwrap();
+        let found = check(&root).unwrap();
+        let at: Vec<_> = found
+            .iter()
+            .map(|d| {
+                let file = d.file.strip_prefix(&root).unwrap().to_str().unwrap();
+                (file, d.line)
+            })
+            .collect();
+        assert_eq!(
+            at,
+            [
+                ("api.js", Some(1)),
+                ("bad.ts", Some(1)),
+                ("broken.rhai", Some(2)),
+                ("data.json", Some(3)),
+                ("imports.rhai", Some(2)),
+            ]
+        );
+        assert!(found[0].message.contains("./missing.js"), "{}", found[0]);
+        assert!(found[4].message.contains("lib::gone"), "{}", found[4]);
+        assert!(found[2].to_string().contains("broken.rhai:2:"));
+    }
+}
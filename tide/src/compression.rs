@@ -0,0 +1,159 @@
+//! gzip and zstd for rhai scripts: `gzip_compress(data)` and
+//! `zstd_compress(data)` of blobs or strings, with an optional level, and
+//! `gzip_decompress(blob)` and `zstd_decompress(blob)`, with request bodies
+//! as blobs in `req.body_blob`.
+//!
+//!```text
+//! fn post(req) {
+//!     let upload = gzip_decompress(req.body_blob, 1024 * 1024);
+//!     http::response(http::OK, zstd_compress(upload, 19))
+//!         .header("content-encoding", "zstd")
+//! }
+//!```
+//!
+//! Decompressing stops at [`Limits::max_decompressed_size`], or at the
+//! smaller size a script passes as the last argument, failing with the
+//! `ErrorDataTooLarge` of the engine like its own limits do.
+//!
+//! [`Limits::max_decompressed_size`]: crate::Limits::max_decompressed_size
+
+use rhai::{Blob, Engine, EvalAltResult, ImmutableString, Position};
+use std::io::{self, Read, Write};
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+/// Largest blob decompressing gives when the limits don't say.
+pub(crate) const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+const GZIP_LEVEL: i64 = 6;
+const ZSTD_LEVEL: i64 = 3;
+
+fn gzip_compress(data: &[u8], level: i64) -> Out<Blob> {
+    let level = u32::try_from(level)
+        .ok()
+        .filter(|level| *level <= 9)
+        .ok_or_else(|| format!("{} is not a gzip level, from 0 to 9", level))?;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+    let compressed = encoder.write_all(data).and_then(|_| encoder.finish());
+    Ok(compressed.map_err(|e| format!("Cannot gzip: {}", e))?)
+}
+
+fn zstd_compress(data: &[u8], level: i64) -> Out<Blob> {
+    let levels = zstd::compression_level_range();
+    let level = i32::try_from(level)
+        .ok()
+        .filter(|level| levels.contains(level))
+        .ok_or_else(|| {
+            let (low, high) = (levels.start(), levels.end());
+            format!("{} is not a zstd level, from {} to {}", level, low, high)
+        })?;
+    Ok(zstd::encode_all(data, level).map_err(|e| format!("Cannot zstd: {}", e))?)
+}
+
+/// All of `decoder`, failing past `max` bytes.
+fn read_limited(decoder: impl Read, max: usize, what: &str) -> Out<Blob> {
+    let mut out = Vec::new();
+    let read = decoder.take(max as u64 + 1).read_to_end(&mut out);
+    match read {
+        Ok(n) if n > max => Err(Box::new(EvalAltResult::ErrorDataTooLarge(
+            format!("Decompressed {}", what),
+            Position::NONE,
+        ))),
+        Ok(_) => Ok(out),
+        Err(e) => Err(format!("Invalid {}: {}", what, e).into()),
+    }
+}
+
+fn gzip_decompress(data: &[u8], max: usize) -> Out<Blob> {
+    // Concatenated members are one stream, as gunzip reads them.
+    read_limited(flate2::read::MultiGzDecoder::new(data), max, "gzip")
+}
+
+fn zstd_decompress(data: &[u8], max: usize) -> Out<Blob> {
+    let decoder = zstd::stream::read::Decoder::new(data)
+        .map_err(|e: io::Error| format!("Invalid zstd: {}", e))?;
+    read_limited(decoder, max, "zstd")
+}
+
+/// The smaller of `max` and the `limit` of a script.
+fn lower(max: usize, limit: i64) -> Out<usize> {
+    let limit = usize::try_from(limit).map_err(|_| format!("{} is not a size", limit))?;
+    Ok(max.min(limit))
+}
+
+/// Registers the functions of the [module](self), decompressing at most
+/// `max` bytes.
+pub(crate) fn register(engine: &mut Engine, max: usize) {
+    engine
+        .register_result_fn("gzip_compress", |data: Blob| {
+            gzip_compress(&data, GZIP_LEVEL)
+        })
+        .register_result_fn("gzip_compress", |data: Blob, level: i64| {
+            gzip_compress(&data, level)
+        })
+        .register_result_fn("gzip_compress", |data: ImmutableString| {
+            gzip_compress(data.as_bytes(), GZIP_LEVEL)
+        })
+        .register_result_fn("gzip_compress", |data: ImmutableString, level: i64| {
+            gzip_compress(data.as_bytes(), level)
+        })
+        .register_result_fn("zstd_compress", |data: Blob| {
+            zstd_compress(&data, ZSTD_LEVEL)
+        })
+        .register_result_fn("zstd_compress", |data: Blob, level: i64| {
+            zstd_compress(&data, level)
+        })
+        .register_result_fn("zstd_compress", |data: ImmutableString| {
+            zstd_compress(data.as_bytes(), ZSTD_LEVEL)
+        })
+        .register_result_fn("zstd_compress", |data: ImmutableString, level: i64| {
+            zstd_compress(data.as_bytes(), level)
+        })
+        .register_result_fn("gzip_decompress", move |data: Blob| {
+            gzip_decompress(&data, max)
+        })
+        .register_result_fn("gzip_decompress", move |data: Blob, limit: i64| {
+            gzip_decompress(&data, lower(max, limit)?)
+        })
+        .register_result_fn("zstd_decompress", move |data: Blob| {
+            zstd_decompress(&data, max)
+        })
+        .register_result_fn("zstd_decompress", move |data: Blob, limit: i64| {
+            zstd_decompress(&data, lower(max, limit)?)
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_within_limits() {
+        let mut engine = Engine::new();
+        register(&mut engine, 1000);
+        let text: String = engine
+            .eval(r#"let b = gzip_decompress(gzip_compress("hello", 9)); b.as_string()"#)
+            .unwrap();
+        assert_eq!(text, "hello");
+        let size: i64 = engine
+            .eval(r#"zstd_decompress(zstd_compress("hello".to_blob())).len()"#)
+            .unwrap();
+        assert_eq!(size, 5);
+
+        let big =
+            engine.eval::<Blob>(r#"let b = blob(1001, 0); gzip_decompress(gzip_compress(b))"#);
+        assert!(matches!(
+            *big.unwrap_err(),
+            EvalAltResult::ErrorDataTooLarge(..)
+        ));
+        let lowered = engine.eval::<Blob>(r#"zstd_decompress(zstd_compress(blob(10, 1)), 9)"#);
+        assert!(matches!(
+            *lowered.unwrap_err(),
+            EvalAltResult::ErrorDataTooLarge(..)
+        ));
+        assert!(engine.eval::<Blob>(r#"gzip_compress("x", 10)"#).is_err());
+        assert!(engine
+            .eval::<Blob>(r#"gzip_decompress(blob(4, 1))"#)
+            .is_err());
+    }
+}
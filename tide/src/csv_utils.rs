@@ -0,0 +1,291 @@
+//! CSV for rhai scripts: `csv_parse(text)` reads rows, `csv_write(rows)`
+//! writes them into a string, and `csv_response(rows)` answers with them,
+//! written while they are sent so a large export never sits in memory twice.
+//!
+//!```text
+//! fn get(req) {
+//!     let orders = csv_parse(file_read("orders.csv"));
+//!     csv_response(orders.filter(|o| o.status == "open"), #{ columns: ["id", "total"] })
+//!         .header("content-disposition", "attachment; filename=\"open.csv\"")
+//! }
+//!```
+//!
+//! Each accepts options as a last argument: `delimiter`, a single character,
+//! `","` by default, and `headers`, on by default. With headers, parsed rows
+//! are maps of the columns named in the first line, and written maps start
+//! with a line of their `columns`, by default the keys of the first row.
+//! Without, rows are arrays. Parsed fields are strings; written ones are
+//! strings as they are, `()` empty and anything else as it prints.
+
+use crate::http_utils::ScriptResponse;
+use futures::TryStreamExt;
+use rhai::serde::from_dynamic;
+use rhai::{Array, Dynamic, EvalAltResult, ImmutableString, Map};
+use serde::Deserialize;
+use std::io;
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+/// How many rows are written at a time into a streamed body.
+const CHUNK_ROWS: usize = 256;
+
+/// The options of the CSV bindings.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct Options {
+    headers: bool,
+    delimiter: String,
+    /// The columns of written maps, in order.
+    columns: Option<Vec<String>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            headers: true,
+            delimiter: ",".into(),
+            columns: None,
+        }
+    }
+}
+
+impl Options {
+    fn from_script(opts: Dynamic) -> Out<Self> {
+        let opts: Self = from_dynamic(&opts).map_err(|e| format!("Invalid CSV options: {}", e))?;
+        opts.delimiter()?;
+        Ok(opts)
+    }
+
+    fn delimiter(&self) -> Out<u8> {
+        match self.delimiter.as_bytes() {
+            [b] if b.is_ascii() => Ok(*b),
+            _ => Err(format!("{:?} is not a CSV delimiter", self.delimiter).into()),
+        }
+    }
+}
+
+/// `csv_parse(text)`
+pub(crate) fn parse(text: ImmutableString) -> Out<Array> {
+    parse_with(text, Options::default())
+}
+
+/// `csv_parse(text, #{headers: false, delimiter: ";"})`
+pub(crate) fn parse_script(text: ImmutableString, opts: Dynamic) -> Out<Array> {
+    parse_with(text, Options::from_script(opts)?)
+}
+
+fn parse_with(text: ImmutableString, opts: Options) -> Out<Array> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(opts.headers)
+        .delimiter(opts.delimiter()?)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let invalid = |e: csv::Error| format!("Invalid CSV: {}", e);
+    let headers: Vec<ImmutableString> = match opts.headers {
+        true => reader
+            .headers()
+            .map_err(invalid)?
+            .iter()
+            .map(Into::into)
+            .collect(),
+        false => Vec::new(),
+    };
+    let mut rows = Array::new();
+    for record in reader.records() {
+        let record = record.map_err(invalid)?;
+        let fields = record.iter().map(|field| Dynamic::from(field.to_string()));
+        if opts.headers {
+            let row: Map = headers
+                .iter()
+                .map(|name| name.as_str().into())
+                .zip(fields)
+                .collect();
+            rows.push(Dynamic::from_map(row));
+        } else {
+            rows.push(Dynamic::from_array(fields.collect()));
+        }
+    }
+    Ok(rows)
+}
+
+/// `csv_write(rows)`
+pub(crate) fn write(rows: Array) -> Out<String> {
+    write_with(rows, Options::default())
+}
+
+/// `csv_write(rows, #{columns: ["id", "name"]})`
+pub(crate) fn write_script(rows: Array, opts: Dynamic) -> Out<String> {
+    write_with(rows, Options::from_script(opts)?)
+}
+
+fn write_with(rows: Array, opts: Options) -> Out<String> {
+    let mut out = Vec::new();
+    for chunk in CsvBody::new(rows, opts)? {
+        out.extend(chunk.map_err(|e| e.to_string())?);
+    }
+    // Only strings go in, so only UTF-8 comes out.
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// `csv_response(rows)`
+pub(crate) fn response(rows: Array) -> Out<ScriptResponse> {
+    response_with(rows, Options::default())
+}
+
+/// `csv_response(rows, #{delimiter: ";"})`
+pub(crate) fn response_script(rows: Array, opts: Dynamic) -> Out<ScriptResponse> {
+    response_with(rows, Options::from_script(opts)?)
+}
+
+fn response_with(rows: Array, opts: Options) -> Out<ScriptResponse> {
+    Ok(ScriptResponse {
+        status: 200,
+        headers: vec![("content-type".into(), "text/csv; charset=utf-8".into())],
+        body: Dynamic::from(CsvBody::new(rows, opts)?),
+        memo: None,
+    })
+}
+
+/// Rows written [`CHUNK_ROWS`] at a time, as the body of a `csv_response`
+/// is read.
+#[derive(Debug, Clone)]
+pub(crate) struct CsvBody {
+    rows: Array,
+    columns: Option<Vec<String>>,
+    headers: bool,
+    delimiter: u8,
+    next: usize,
+}
+
+impl CsvBody {
+    fn new(rows: Array, opts: Options) -> Out<Self> {
+        let delimiter = opts.delimiter()?;
+        let columns = match (opts.columns, rows.first()) {
+            (Some(columns), _) => Some(columns),
+            (None, Some(first)) => first
+                .read_lock::<Map>()
+                .map(|map| map.keys().map(|k| k.to_string()).collect()),
+            (None, None) => None,
+        };
+        Ok(Self {
+            rows,
+            columns,
+            headers: opts.headers,
+            delimiter,
+            next: 0,
+        })
+    }
+
+    /// Sent as it is written.
+    pub(crate) fn into_body(self) -> tide::Body {
+        let chunks = futures::stream::iter(self);
+        tide::Body::from_reader(chunks.into_async_read(), None)
+    }
+
+    /// The header line if `header`, and the next rows.
+    fn chunk(&mut self, header: bool) -> csv::Result<Vec<u8>> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .flexible(true)
+            .from_writer(Vec::new());
+        if let (true, Some(columns)) = (header, &self.columns) {
+            writer.write_record(columns)?;
+        }
+        let start = self.next;
+        let end = self.rows.len().min(start + CHUNK_ROWS);
+        for row in &self.rows[start..end] {
+            writer.write_record(fields(row, self.columns.as_deref()))?;
+        }
+        // Past the header even without rows.
+        self.next = end.max(start + 1);
+        writer.into_inner().map_err(|e| e.into_error().into())
+    }
+}
+
+impl Iterator for CsvBody {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next;
+        let header = start == 0 && self.headers && self.columns.is_some();
+        if start >= self.rows.len() && !header {
+            return None;
+        }
+        let chunk = self.chunk(header);
+        Some(chunk.map_err(|e| io::Error::new(io::ErrorKind::Other, e)))
+    }
+}
+
+/// The fields of `row`, the values of `columns` for maps.
+fn fields(row: &Dynamic, columns: Option<&[String]>) -> Vec<String> {
+    if let (Some(map), Some(columns)) = (row.read_lock::<Map>(), columns) {
+        return columns
+            .iter()
+            .map(|c| map.get(c.as_str()).map_or_else(String::new, field))
+            .collect();
+    }
+    if let Some(items) = row.read_lock::<Array>() {
+        return items.iter().map(field).collect();
+    }
+    vec![field(row)]
+}
+
+fn field(value: &Dynamic) -> String {
+    if value.is_unit() {
+        return String::new();
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::AsyncReadExt;
+
+    #[test]
+    fn parses_and_writes() {
+        let mut engine = rhai::Engine::new();
+        engine
+            .register_result_fn("csv_parse", parse)
+            .register_result_fn("csv_parse", parse_script)
+            .register_result_fn("csv_write", write)
+            .register_result_fn("csv_write", write_script);
+        let text = "id,name\n1,\"Smith, Jo\"\n2,Ann\n";
+        let name: String = engine
+            .eval(&format!("csv_parse({:?})[0].name", text))
+            .unwrap();
+        assert_eq!(name, "Smith, Jo");
+        let back: String = engine
+            .eval(&format!("csv_write(csv_parse({:?}))", text))
+            .unwrap();
+        assert_eq!(back, text);
+        let plain: String = engine
+            .eval(r#"csv_write([[1, (), "a;b"]], #{ delimiter: ";" })"#)
+            .unwrap();
+        assert_eq!(plain, "1;;\"a;b\"\n");
+        let columns: String = engine
+            .eval(r#"csv_write([#{ a: 1, b: 2 }], #{ columns: ["b"] })"#)
+            .unwrap();
+        assert_eq!(columns, "b\n2\n");
+        let rows: Array = engine
+            .eval(r#"csv_parse("1|2", #{ headers: false, delimiter: "|" })"#)
+            .unwrap();
+        assert_eq!(rows[0].clone().cast::<Array>().len(), 2);
+        assert!(engine
+            .eval::<Array>(r#"csv_parse("a", #{ delimiter: "::" })"#)
+            .is_err());
+    }
+
+    #[test]
+    fn streams_in_chunks() {
+        let rows: Array = (0..CHUNK_ROWS * 2 + 1)
+            .map(|i| Dynamic::from_array(vec![(i as i64).into()]))
+            .collect();
+        let body = CsvBody::new(rows.clone(), Options::default()).unwrap();
+        assert_eq!(body.clone().count(), 3);
+        let mut sent = String::new();
+        crate::rt::block_on(body.into_body().into_reader().read_to_string(&mut sent)).unwrap();
+        assert_eq!(sent, write(rows).unwrap());
+        assert!(sent.ends_with("512\n"), "{}", sent);
+    }
+}
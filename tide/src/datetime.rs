@@ -0,0 +1,240 @@
+use chrono::{NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use rhai::{EvalAltResult, ImmutableString};
+use std::fmt::Write;
+
+#[derive(Debug, Clone)]
+pub struct DateTime {
+    inner: chrono::DateTime<chrono::FixedOffset>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Duration {
+    inner: chrono::Duration,
+}
+
+impl DateTime {
+    fn from_utc(dt: chrono::DateTime<Utc>) -> Self {
+        Self {
+            inner: dt.with_timezone(&Utc.fix()),
+        }
+    }
+
+    // Remember &mut must be used even for getters
+    pub fn get_timestamp(&mut self) -> i64 {
+        self.inner.timestamp()
+    }
+
+    pub fn get_timestamp_ms(&mut self) -> i64 {
+        self.inner.timestamp_millis()
+    }
+
+    pub fn get_year(&mut self) -> i64 {
+        chrono::Datelike::year(&self.inner) as i64
+    }
+
+    pub fn get_month(&mut self) -> i64 {
+        chrono::Datelike::month(&self.inner) as i64
+    }
+
+    pub fn get_day(&mut self) -> i64 {
+        chrono::Datelike::day(&self.inner) as i64
+    }
+
+    pub fn get_hour(&mut self) -> i64 {
+        chrono::Timelike::hour(&self.inner) as i64
+    }
+
+    pub fn get_minute(&mut self) -> i64 {
+        chrono::Timelike::minute(&self.inner) as i64
+    }
+
+    pub fn get_second(&mut self) -> i64 {
+        chrono::Timelike::second(&self.inner) as i64
+    }
+
+    pub fn to_iso(&mut self) -> ImmutableString {
+        self.inner.to_rfc3339().into()
+    }
+
+    /// As RSS and mail headers write dates.
+    pub(crate) fn to_rfc2822(&self) -> String {
+        self.inner.to_rfc2822()
+    }
+
+    pub fn format(
+        &mut self,
+        pattern: ImmutableString,
+    ) -> Result<ImmutableString, Box<EvalAltResult>> {
+        // chrono panics on `to_string` for invalid patterns, `write!` reports it instead
+        let mut out = String::new();
+        match write!(out, "{}", self.inner.format(pattern.as_str())) {
+            Ok(_) => Ok(out.into()),
+            Err(_) => Err(format!("Invalid format pattern: {}", pattern).into()),
+        }
+    }
+
+    pub fn to_timezone(&mut self, tz: ImmutableString) -> Result<DateTime, Box<EvalAltResult>> {
+        let tz: chrono_tz::Tz = match tz.parse() {
+            Ok(v) => v,
+            Err(_) => return Err(format!("Unknown timezone: {}", tz).into()),
+        };
+        let local = self.inner.with_timezone(&tz);
+        Ok(DateTime {
+            inner: local.with_timezone(&local.offset().fix()),
+        })
+    }
+
+    pub fn add(dt: DateTime, d: Duration) -> Result<DateTime, Box<EvalAltResult>> {
+        match dt.inner.checked_add_signed(d.inner) {
+            Some(inner) => Ok(DateTime { inner }),
+            None => Err("DateTime overflow".into()),
+        }
+    }
+
+    pub fn sub(dt: DateTime, d: Duration) -> Result<DateTime, Box<EvalAltResult>> {
+        match dt.inner.checked_sub_signed(d.inner) {
+            Some(inner) => Ok(DateTime { inner }),
+            None => Err("DateTime overflow".into()),
+        }
+    }
+
+    pub fn diff(a: DateTime, b: DateTime) -> Duration {
+        Duration {
+            inner: a.inner.signed_duration_since(b.inner),
+        }
+    }
+
+    pub fn eq(a: DateTime, b: DateTime) -> bool {
+        a.inner == b.inner
+    }
+
+    pub fn lt(a: DateTime, b: DateTime) -> bool {
+        a.inner < b.inner
+    }
+
+    pub fn gt(a: DateTime, b: DateTime) -> bool {
+        a.inner > b.inner
+    }
+}
+
+impl Duration {
+    pub fn get_seconds(&mut self) -> i64 {
+        self.inner.num_seconds()
+    }
+
+    pub fn get_milliseconds(&mut self) -> i64 {
+        self.inner.num_milliseconds()
+    }
+
+    pub fn add(a: Duration, b: Duration) -> Result<Duration, Box<EvalAltResult>> {
+        match a.inner.checked_add(&b.inner) {
+            Some(inner) => Ok(Duration { inner }),
+            None => Err("Duration overflow".into()),
+        }
+    }
+
+    pub fn sub(a: Duration, b: Duration) -> Result<Duration, Box<EvalAltResult>> {
+        match a.inner.checked_sub(&b.inner) {
+            Some(inner) => Ok(Duration { inner }),
+            None => Err("Duration overflow".into()),
+        }
+    }
+}
+
+pub fn now() -> DateTime {
+    DateTime::from_utc(Utc::now())
+}
+
+pub fn now_utc_iso() -> ImmutableString {
+    Utc::now().to_rfc3339().into()
+}
+
+pub fn from_timestamp(secs: i64) -> Result<DateTime, Box<EvalAltResult>> {
+    match Utc.timestamp_opt(secs, 0).single() {
+        Some(dt) => Ok(DateTime::from_utc(dt)),
+        None => Err(format!("Invalid timestamp: {}", secs).into()),
+    }
+}
+
+/// Parses an RFC 3339 / ISO 8601 string such as `2022-11-05T10:00:00+01:00`.
+pub fn parse(s: ImmutableString) -> Result<DateTime, Box<EvalAltResult>> {
+    match chrono::DateTime::parse_from_rfc3339(s.as_str()) {
+        Ok(inner) => Ok(DateTime { inner }),
+        Err(e) => Err(format!("Invalid datetime '{}': {}", s, e).into()),
+    }
+}
+
+/// Parses `s` with a strftime-like pattern. Patterns without an offset are
+/// taken as UTC, and date-only patterns as midnight UTC.
+pub fn parse_with_format(
+    s: ImmutableString,
+    pattern: ImmutableString,
+) -> Result<DateTime, Box<EvalAltResult>> {
+    if let Ok(inner) = chrono::DateTime::parse_from_str(s.as_str(), pattern.as_str()) {
+        return Ok(DateTime { inner });
+    }
+    if let Ok(naive) = NaiveDateTime::parse_from_str(s.as_str(), pattern.as_str()) {
+        return Ok(DateTime::from_utc(Utc.from_utc_datetime(&naive)));
+    }
+    match NaiveDate::parse_from_str(s.as_str(), pattern.as_str()) {
+        Ok(date) => Ok(DateTime::from_utc(
+            Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()),
+        )),
+        Err(e) => Err(format!("Invalid datetime '{}' for '{}': {}", s, pattern, e).into()),
+    }
+}
+
+/// `n` of the unit `make` makes durations of, failing past what a
+/// duration holds instead of panicking.
+fn duration(
+    n: i64,
+    unit: &str,
+    make: fn(i64) -> Option<chrono::Duration>,
+) -> Result<Duration, Box<EvalAltResult>> {
+    match make(n) {
+        Some(inner) => Ok(Duration { inner }),
+        None => Err(format!("Duration overflow: {} {}", n, unit).into()),
+    }
+}
+
+pub fn milliseconds(n: i64) -> Result<Duration, Box<EvalAltResult>> {
+    duration(n, "milliseconds", chrono::Duration::try_milliseconds)
+}
+
+pub fn seconds(n: i64) -> Result<Duration, Box<EvalAltResult>> {
+    duration(n, "seconds", chrono::Duration::try_seconds)
+}
+
+pub fn minutes(n: i64) -> Result<Duration, Box<EvalAltResult>> {
+    duration(n, "minutes", chrono::Duration::try_minutes)
+}
+
+pub fn hours(n: i64) -> Result<Duration, Box<EvalAltResult>> {
+    duration(n, "hours", chrono::Duration::try_hours)
+}
+
+pub fn days(n: i64) -> Result<Duration, Box<EvalAltResult>> {
+    duration(n, "days", chrono::Duration::try_days)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fails_out_of_range() {
+        assert_eq!(days(2).unwrap().get_seconds(), 172_800);
+        assert_eq!(minutes(-3).unwrap().get_milliseconds(), -180_000);
+        assert!(seconds(i64::MAX).is_err());
+        assert!(days(i64::MAX / 1000).is_err());
+        assert!(hours(i64::MIN).is_err());
+
+        let sum = Duration::add(hours(1).unwrap(), seconds(30).unwrap());
+        assert_eq!(sum.unwrap().get_seconds(), 3630);
+        let most = milliseconds(i64::MAX).unwrap();
+        assert!(Duration::add(most.clone(), most.clone()).is_err());
+        let least = milliseconds(-i64::MAX).unwrap();
+        assert!(Duration::sub(least, most.clone()).is_err());
+        assert!(DateTime::add(now(), most).is_err());
+    }
+}
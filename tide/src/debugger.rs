@@ -0,0 +1,545 @@
+//! A debug adapter for rhai scripts, speaking the Debug Adapter Protocol of
+//! editors like VS Code. One client at a time connects to the port given to
+//! [`RhaiDir::with_debugger`](crate::RhaiDir::with_debugger), sets
+//! breakpoints and steps through scripts as requests run them. A
+//! [`Debugger`] can also be shared by the directories of an app, and by
+//! the apps replacing each other on reloads, with
+//! [`RhaiDir::with_shared_debugger`](crate::RhaiDir::with_shared_debugger).
+//!
+//! A paused script holds on to its request, and other scripts reaching a
+//! breakpoint meanwhile wait until it goes on. Only rhai scripts can be
+//! debugged: the JavaScript engine has no hooks to stop a running script.
+
+use rhai::debugger::DebuggerEvent;
+use rhai::{ASTNode, Dynamic, EvalContext, Position};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use tide::log;
+
+/// The only thread shown to the client, standing for every script.
+const THREAD: i64 = 1;
+
+/// The port editors attach to, listening until the process exits.
+pub struct Debugger {
+    addr: SocketAddr,
+    /// Whether a client is connected, checked before taking the lock.
+    attached: AtomicBool,
+    state: Mutex<State>,
+    /// Signalled when a paused script may go on.
+    resumed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    client: Option<TcpStream>,
+    seq: i64,
+    /// Lines to stop at, by script path.
+    breakpoints: HashMap<String, HashSet<usize>>,
+    /// Stop whichever script runs next, asked for by `pause`.
+    pause: bool,
+    /// The script being stepped through, by the thread running it.
+    stepping: Option<(ThreadId, Step)>,
+    paused: Option<Paused>,
+    resume: Option<Resume>,
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    In,
+    /// Stop at the next statement at this call level or above.
+    Over(usize),
+    /// Stop at the next statement above this call level.
+    Out(usize),
+}
+
+enum Resume {
+    Run,
+    Step(Step),
+}
+
+/// Where a script stopped and what it had in scope.
+struct Paused {
+    source: String,
+    line: usize,
+    column: usize,
+    level: usize,
+    /// The values behind each `variablesReference`, starting at 1 with the
+    /// locals. Maps and arrays get theirs when the client opens them.
+    variables: Vec<Vec<(String, Dynamic)>>,
+}
+
+impl Debugger {
+    /// Starts listening for a client on `addr`.
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Arc<Self>> {
+        let listener = TcpListener::bind(addr)?;
+        let debugger = Arc::new(Self {
+            addr: listener.local_addr()?,
+            attached: AtomicBool::new(false),
+            state: Mutex::new(State::default()),
+            resumed: Condvar::new(),
+        });
+        log::info!("Debugger listening on {}", debugger.addr);
+        let d = debugger.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => d.serve(stream),
+                    Err(e) => log::warn!("Debugger connection failed: {}", e),
+                }
+            }
+        });
+        Ok(debugger)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the script where the client wants it to, called by the engine
+    /// for every step. Scripts are told apart by the source of their AST.
+    pub(crate) fn on_event(
+        &self,
+        context: &EvalContext,
+        event: DebuggerEvent,
+        node: ASTNode,
+        source: Option<&str>,
+        pos: Position,
+    ) {
+        if !self.attached.load(Ordering::Relaxed) {
+            return;
+        }
+        let thread = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+        match event {
+            DebuggerEvent::Start | DebuggerEvent::End => {
+                if matches!(state.stepping, Some((t, _)) if t == thread) {
+                    state.stepping = None;
+                }
+                return;
+            }
+            DebuggerEvent::Step => {}
+            _ => return,
+        }
+        let (ASTNode::Stmt(_), Some(source), Some(line)) = (node, source, pos.line()) else {
+            return;
+        };
+        let level = context.call_level();
+        let reason = loop {
+            if state.client.is_none() {
+                return;
+            }
+            let Some(reason) = state.stop_reason(thread, source, line, level) else {
+                return;
+            };
+            if state.paused.is_none() {
+                break reason;
+            }
+            state = self.resumed.wait(state).unwrap();
+        };
+
+        state.pause = false;
+        state.stepping = None;
+        state.paused = Some(Paused {
+            source: source.to_string(),
+            line,
+            column: pos.position().unwrap_or(1),
+            level,
+            variables: vec![locals(context)],
+        });
+        state.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true }),
+        );
+        while state.resume.is_none() && state.client.is_some() {
+            state = self.resumed.wait(state).unwrap();
+        }
+        if let Some(Resume::Step(step)) = state.resume.take() {
+            state.stepping = Some((thread, step));
+        }
+        state.paused = None;
+        drop(state);
+        self.resumed.notify_all();
+    }
+
+    fn serve(&self, stream: TcpStream) {
+        let mut reader = match stream.try_clone() {
+            Ok(s) => BufReader::new(s),
+            Err(e) => return log::warn!("Debugger connection failed: {}", e),
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            state.client = Some(stream);
+            state.seq = 0;
+        }
+        self.attached.store(true, Ordering::Relaxed);
+        log::info!("Debugger client connected");
+        loop {
+            match read_message(&mut reader) {
+                Ok(Some(request)) => {
+                    if !self.handle(&request) {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Invalid message from the debugger client: {}", e);
+                    break;
+                }
+            }
+        }
+        self.detach();
+        log::info!("Debugger client disconnected");
+    }
+
+    /// Answers `request`, false once the client disconnected.
+    fn handle(&self, request: &Value) -> bool {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let mut state = self.state.lock().unwrap();
+        let body = match command {
+            "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+            "launch" | "attach" | "configurationDone" | "disconnect" => Ok(json!({})),
+            "setBreakpoints" => Ok(state.set_breakpoints(args)),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "scripts" }] })),
+            "stackTrace" => state.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [{ "name": "Locals", "variablesReference": 1, "expensive": false }]
+            })),
+            "variables" => state.variables(args),
+            "pause" => {
+                state.pause = true;
+                Ok(json!({}))
+            }
+            "continue" => state.resume(Resume::Run),
+            "next" => state.resume_step(Step::Over),
+            "stepIn" => state.resume(Resume::Step(Step::In)),
+            "stepOut" => state.resume_step(Step::Out),
+            _ => Err(format!("Unsupported request {}", command)),
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        state.send(response);
+        if command == "initialize" {
+            state.event("initialized", json!({}));
+        }
+        drop(state);
+        self.resumed.notify_all();
+        command != "disconnect"
+    }
+
+    /// Forgets the client and lets its paused script go on.
+    fn detach(&self) {
+        self.attached.store(false, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        state.client = None;
+        state.breakpoints.clear();
+        state.pause = false;
+        state.stepping = None;
+        if state.paused.is_some() {
+            state.resume = Some(Resume::Run);
+        }
+        drop(state);
+        self.resumed.notify_all();
+    }
+}
+
+impl State {
+    fn stop_reason(
+        &self,
+        thread: ThreadId,
+        source: &str,
+        line: usize,
+        level: usize,
+    ) -> Option<&'static str> {
+        if self.pause {
+            return Some("pause");
+        }
+        let stepped = match self.stepping {
+            Some((t, step)) if t == thread => match step {
+                Step::In => true,
+                Step::Over(l) => level <= l,
+                Step::Out(l) => level < l,
+            },
+            _ => false,
+        };
+        if stepped {
+            return Some("step");
+        }
+        let breakpoint = self
+            .breakpoints
+            .get(source)
+            .map_or(false, |lines| lines.contains(&line));
+        breakpoint.then_some("breakpoint")
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let path = args["source"]["path"].as_str().unwrap_or_default();
+        let path = std::fs::canonicalize(path)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|b| b["line"].as_u64())
+            .map(|line| line as usize)
+            .collect();
+        let breakpoints: Vec<Value> = lines
+            .iter()
+            .map(|line| json!({ "verified": true, "line": line }))
+            .collect();
+        self.breakpoints.insert(path, lines.into_iter().collect());
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let paused = self.paused.as_ref().ok_or("No script is paused")?;
+        let name = if paused.level == 0 {
+            "script"
+        } else {
+            "function"
+        };
+        Ok(json!({
+            "stackFrames": [{
+                "id": 1,
+                "name": name,
+                "source": { "path": paused.source },
+                "line": paused.line,
+                "column": paused.column,
+            }],
+            "totalFrames": 1,
+        }))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        let paused = self.paused.as_mut().ok_or("No script is paused")?;
+        let reference = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+        let values = match reference
+            .checked_sub(1)
+            .and_then(|i| paused.variables.get(i))
+        {
+            Some(values) => values.clone(),
+            None => return Err(format!("Unknown variables {}", reference)),
+        };
+        let mut variables = Vec::new();
+        for (name, value) in values {
+            let inner = children(&value);
+            let reference = if inner.is_empty() {
+                0
+            } else {
+                paused.variables.push(inner);
+                paused.variables.len()
+            };
+            variables.push(json!({
+                "name": name,
+                "value": shown(&value),
+                "type": value.type_name(),
+                "variablesReference": reference,
+            }));
+        }
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn resume(&mut self, resume: Resume) -> Result<Value, String> {
+        if self.paused.is_none() {
+            return Err("No script is paused".into());
+        }
+        self.resume = Some(resume);
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn resume_step(&mut self, step: fn(usize) -> Step) -> Result<Value, String> {
+        let level = self.paused.as_ref().map_or(0, |p| p.level);
+        self.resume(Resume::Step(step(level)))
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        if let Some(client) = &mut self.client {
+            if let Err(e) = write_message(client, &message) {
+                log::warn!("Could not write to the debugger client: {}", e);
+            }
+        }
+    }
+}
+
+/// The variables in scope, the innermost of those with the same name.
+fn locals(context: &EvalContext) -> Vec<(String, Dynamic)> {
+    let all: Vec<_> = context.scope().iter_raw().collect();
+    let mut seen = HashSet::new();
+    let mut locals: Vec<_> = all
+        .into_iter()
+        .rev()
+        .filter(|(name, _, _)| seen.insert(*name))
+        .map(|(name, _, value)| (name.to_string(), value.clone()))
+        .collect();
+    locals.reverse();
+    locals
+}
+
+fn children(value: &Dynamic) -> Vec<(String, Dynamic)> {
+    if let Some(map) = value.clone().try_cast::<rhai::Map>() {
+        map.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+    } else if let Some(array) = value.clone().try_cast::<rhai::Array>() {
+        array
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+fn shown(value: &Dynamic) -> String {
+    if value.is_string() {
+        format!("{:?}", value.to_string())
+    } else {
+        value.to_string()
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header, `None` at the end of
+/// the stream.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            length = n.trim().parse().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::debugger::DebuggerCommand;
+    use rhai::Engine;
+
+    struct Client {
+        out: TcpStream,
+        reader: BufReader<TcpStream>,
+        seq: i64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let request = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            write_message(&mut self.out, &request).unwrap();
+            let seq = self.seq;
+            self.next(|m| m["type"] == "response" && m["request_seq"] == seq)
+        }
+
+        fn next(&mut self, wanted: impl Fn(&Value) -> bool) -> Value {
+            loop {
+                let message = read_message(&mut self.reader).unwrap().unwrap();
+                if wanted(&message) {
+                    return message;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn breakpoints_and_steps() {
+        let debugger = Debugger::listen("127.0.0.1:0").unwrap();
+        let mut engine = Engine::new();
+        let d = debugger.clone();
+        engine.register_debugger(
+            |_| Dynamic::UNIT,
+            move |context, event, node, source, pos| {
+                d.on_event(&context, event, node, source, pos);
+                Ok(DebuggerCommand::StepInto)
+            },
+        );
+
+        let out = TcpStream::connect(debugger.addr()).unwrap();
+        let reader = BufReader::new(out.try_clone().unwrap());
+        let mut client = Client {
+            out,
+            reader,
+            seq: 0,
+        };
+        client.request("initialize", json!({}));
+        let set = client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": "orders.rhai" }, "breakpoints": [{ "line": 3 }] }),
+        );
+        assert_eq!(set["body"]["breakpoints"][0]["verified"], true);
+        client.request("configurationDone", json!({}));
+
+        let script = thread::spawn(move || {
+            let mut ast = engine
+                .compile("let items = [1, 2];\nlet total = 0;\nfor i in items { total += i; }\ntotal * 10")
+                .unwrap();
+            ast.set_source("orders.rhai");
+            engine.eval_ast::<i64>(&ast).unwrap()
+        });
+
+        let stopped = client.next(|m| m["event"] == "stopped");
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+        let trace = client.request("stackTrace", json!({ "threadId": THREAD }));
+        assert_eq!(trace["body"]["stackFrames"][0]["line"], 3);
+        let locals = client.request("variables", json!({ "variablesReference": 1 }));
+        let locals = locals["body"]["variables"].as_array().unwrap().clone();
+        assert_eq!(locals[0]["name"], "items");
+        assert_eq!(locals[1]["value"], "0");
+        let items = client.request(
+            "variables",
+            json!({ "variablesReference": locals[0]["variablesReference"] }),
+        );
+        assert_eq!(items["body"]["variables"][1]["value"], "2");
+
+        client.request("next", json!({ "threadId": THREAD }));
+        let stopped = client.next(|m| m["event"] == "stopped");
+        assert_eq!(stopped["body"]["reason"], "step");
+
+        client.request("disconnect", json!({}));
+        assert_eq!(script.join().unwrap(), 30);
+    }
+}
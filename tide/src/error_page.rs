@@ -0,0 +1,140 @@
+use rhai::{Dynamic, EvalAltResult, ImmutableString};
+use serde_json::{json, Value};
+use std::path::Path;
+use tide::log;
+use tide::{Response, StatusCode};
+
+/// An error a script raised on purpose with `HttpError(status, message)`,
+/// answered with its status instead of a 500.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HttpError {
+    pub status: u16,
+    pub message: String,
+    /// Replaces the default body, `{"error": message}`.
+    pub body: Option<Value>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpError {
+    /// Only client and server errors, 400 to 599, can be raised.
+    pub(crate) fn new(status: i64, message: impl Into<String>) -> Result<Self, String> {
+        let known = u16::try_from(status)
+            .ok()
+            .and_then(|s| StatusCode::try_from(s).ok());
+        let status = match known {
+            Some(s) if s.is_client_error() || s.is_server_error() => s,
+            _ => {
+                return Err(format!(
+                    "HttpError needs a client or server error status, not {}",
+                    status
+                ))
+            }
+        };
+        Ok(Self {
+            status: status as u16,
+            message: message.into(),
+            body: None,
+            headers: Vec::new(),
+        })
+    }
+
+    /// `HttpError(status, message)` of rhai scripts.
+    pub(crate) fn script(
+        status: i64,
+        message: ImmutableString,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        Ok(Self::new(status, message.as_str())?)
+    }
+
+    /// `HttpError(status, message, body)`, answering with `body` as json.
+    pub(crate) fn script_with_body(
+        status: i64,
+        message: ImmutableString,
+        body: Dynamic,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        let mut e = Self::new(status, message.as_str())?;
+        e.body = Some(crate::from_script_value(&body)?);
+        Ok(e)
+    }
+
+    pub(crate) fn body(&self) -> Value {
+        self.body
+            .clone()
+            .unwrap_or_else(|| json!({ "error": self.message }))
+    }
+
+    pub(crate) fn response(&self) -> Response {
+        log::info!("Script answered {}: {}", self.status, self.message);
+        let mut res = Response::builder(self.status).body(self.body()).build();
+        for (n, v) in &self.headers {
+            res.append_header(n.as_str(), v.as_str());
+        }
+        res
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Renders the page shown instead of a bare 500 when a script fails in dev mode.
+pub(crate) fn render(title: &str, path: &Path, detail: &str) -> Response {
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+pre {{ background: #fdf2f2; border-left: 4px solid #c0392b; padding: 1em; overflow: auto; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p><code>{path}</code></p>
+<pre>{detail}</pre>
+</body>
+</html>
+"#,
+        title = escape(title),
+        path = escape(&path.display().to_string()),
+        detail = escape(&crate::redact(detail)),
+    );
+    Response::builder(StatusCode::InternalServerError)
+        .content_type(tide::http::mime::HTML)
+        .body(body)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn http_error() {
+        let e = HttpError::new(404, "not found").unwrap();
+        assert_eq!(e.body(), json!({"error": "not found"}));
+        assert_eq!(e.response().status(), StatusCode::NotFound);
+        assert!(HttpError::new(200, "fine").is_err());
+        assert!(HttpError::new(600, "what").is_err());
+        assert!(HttpError::new(499, "unknown").is_err());
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+// Lists the operations of the OpenAPI document at SPEC and lets them be
+// tried out from the page.
+(async function () {
+  const main = document.querySelector("main");
+  const el = (tag, attrs, ...children) => {
+    const e = document.createElement(tag);
+    Object.assign(e, attrs);
+    e.append(...children);
+    return e;
+  };
+
+  let spec;
+  try {
+    const res = await fetch(SPEC);
+    spec = await res.json();
+  } catch (e) {
+    main.append(el("p", {}, `Could not load ${SPEC}: ${e}`));
+    return;
+  }
+  document.querySelector("h1").append(el("small", {}, `${spec.info.title} ${spec.info.version}`));
+
+  const operation = (path, method, op) => {
+    const params = op.parameters || [];
+    const inputs = params.map((p) => {
+      const input = el("input", { name: p.name, placeholder: p.description || "" });
+      return [p, input, el("label", {}, `${p.name} (${p.in})`, input)];
+    });
+    const body = el("textarea", { rows: 4, placeholder: "Body" });
+    const out = el("pre", {});
+    const send = el("button", { textContent: "Send" });
+    send.onclick = async () => {
+      const url = new URL(path, location.origin);
+      const headers = {};
+      for (const [p, input] of inputs) {
+        if (!input.value) continue;
+        if (p.in === "header") headers[p.name] = input.value;
+        else url.searchParams.set(p.name, input.value);
+      }
+      const init = { method: method.toUpperCase(), headers };
+      if (method !== "get" && body.value) init.body = body.value;
+      out.textContent = "…";
+      try {
+        const res = await fetch(url, init);
+        const text = await res.text();
+        out.textContent = `${res.status} ${res.statusText}\n\n${text}`;
+      } catch (e) {
+        out.textContent = String(e);
+      }
+    };
+    const responses = Object.entries(op.responses || {})
+      .map(([code, r]) => `${code} ${r.description || ""}`)
+      .join("\n");
+    return el(
+      "details",
+      {},
+      el("summary", {}, el("span", { className: `method ${method}` }, method),
+        el("span", { className: "path" }, path), el("span", {}, op.summary || "")),
+      el("div", { className: "op" },
+        el("p", {}, op.description || ""),
+        ...inputs.map(([, , label]) => label),
+        ...(method === "get" ? [] : [body]),
+        send,
+        el("pre", {}, responses),
+        out),
+    );
+  };
+
+  const tagged = new Map();
+  for (const [path, item] of Object.entries(spec.paths || {})) {
+    for (const [method, op] of Object.entries(item)) {
+      const tag = (op.tags && op.tags[0]) || "";
+      if (!tagged.has(tag)) tagged.set(tag, []);
+      tagged.get(tag).push(operation(path, method, op));
+    }
+  }
+  for (const [tag, ops] of [...tagged].sort()) {
+    if (tag) main.append(el("h2", {}, tag));
+    main.append(...ops);
+  }
+})();
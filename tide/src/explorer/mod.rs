@@ -0,0 +1,65 @@
+//! [`ApiExplorer`], a page listing the operations of an OpenAPI document,
+//! like the one of [`RhaiDir::openapi`](crate::RhaiDir::openapi), and
+//! sending requests to try them out. Its assets are part of the binary.
+
+use tide::http::mime;
+use tide::{Endpoint, Request, Response, StatusCode};
+
+const PAGE: &str = include_str!("index.html");
+const CSS: &str = include_str!("explorer.css");
+const JS: &str = include_str!("explorer.js");
+
+/// Serves the explorer of the document at `spec`.
+///```
+/// use tide_rhai::ApiExplorer;
+/// let mut app = tide::new();
+/// app.at("/docs").get(ApiExplorer::new("/openapi.json"));
+///```
+#[derive(Clone)]
+pub struct ApiExplorer {
+    page: String,
+}
+
+impl ApiExplorer {
+    /// `spec` is the URL of the document, fetched by the page.
+    pub fn new(spec: &str) -> Self {
+        // The URL ends up in a script, where `<` could close it.
+        let spec = serde_json::to_string(spec)
+            .unwrap_or_default()
+            .replace('<', "\\u003c");
+        let page = PAGE
+            .replace("/*CSS*/", CSS)
+            .replace("/*SPEC*/", &spec)
+            .replace("/*JS*/", JS);
+        Self { page }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for ApiExplorer {
+    async fn call(&self, _: Request<State>) -> tide::Result {
+        Ok(Response::builder(StatusCode::Ok)
+            .content_type(mime::HTML)
+            .body(self.page.as_str())
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide_testing::TideTestingExt;
+
+    #[async_std::test]
+    async fn page() {
+        let mut app = tide::new();
+        app.at("/docs")
+            .get(ApiExplorer::new("/openapi.json?v=</script>"));
+        let mut res = app.get("/docs").await.unwrap();
+        assert_eq!(res.content_type().unwrap().essence(), "text/html");
+        let page = res.body_string().await.unwrap();
+        assert!(page.contains(r#"const SPEC = "/openapi.json?v=\u003c/script>";"#));
+        assert!(page.contains("fetch(SPEC)"));
+        assert!(!page.contains("/*JS*/"));
+    }
+}
@@ -1,10 +1,14 @@
-use async_std::task;
+use crate::audit;
+use crate::permissions::Permissions;
+use crate::rt;
 use rhai::serde::{from_dynamic, to_dynamic};
 use rhai::{Dynamic, EvalAltResult, ImmutableString};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::str::FromStr;
-use surf::http::Method;
-use surf::{Request, StatusCode, Url};
+use surf::http::{Method, StatusCode};
+use surf::{Request, Url};
 
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -75,59 +79,141 @@ impl Response {
     }
 }
 
-pub fn fetch(opts: Options) -> Result<Response, Box<EvalAltResult>> {
-    let copts = opts.clone();
-    let th_url = copts.url;
-    let th_body = copts.body;
-    let th_headers = copts.headers;
-    let th_method = copts.method;
-
-    match task::block_on(async move {
-        let l_url = Url::parse(th_url.as_str())?;
-        let l_method = Method::from_str(th_method.as_str())?;
+/// Performs the http request behind the `fetch` bindings, returning the
+/// decoded json body and the response headers.
+pub(crate) async fn send<B, R>(
+    url: &str,
+    method: &str,
+    headers: &HashMap<String, String>,
+    body: &B,
+) -> surf::Result<(R, HashMap<String, String>)>
+where
+    B: Serialize,
+    R: DeserializeOwned,
+{
+    let l_url = Url::parse(url)?;
+    let l_method = Method::from_str(method)?;
+    let call = audit::start(method, &l_url)
+        .map_err(|e| surf::Error::from_str(StatusCode::Forbidden, e))?;
+
+    let mut l_req = Request::new(l_method, l_url);
+    if !l_method.is_safe() {
+        l_req.set_body(http_types::Body::from_json(body)?);
+    }
 
-        let mut l_req = Request::new(l_method, l_url);
-        if !l_method.is_safe() {
-            l_req.set_body(http_types::Body::from_json(&th_body)?);
+    if l_method != Method::Trace {
+        for (n, v) in headers.iter() {
+            l_req.set_header(n.as_str(), v.as_str());
         }
+    }
 
-        let mut l_headers: HashMap<String, String> = HashMap::new();
-        if th_headers.type_name() != "string" {
-            // the headers have been set to lets try and map them
-            l_headers = match from_dynamic(&th_headers) {
-                Ok(v) => v,
-                Err(e) => {
-                    log::warn!("Hashmap Parse Error {:?}", e);
-                    return Err(surf::Error::from_str(
-                        StatusCode::InternalServerError,
-                        "Hashmap error",
-                    ));
-                }
-            };
-        }
+    let l_client = surf::client();
 
-        if l_method != Method::Trace {
-            for (n, v) in l_headers.iter() {
-                l_req.set_header(n.as_str(), v.as_str());
-            }
-        }
-
-        let l_client = surf::client();
+    let sent = l_client.send(l_req).await;
+    call.finish(
+        sent.as_ref()
+            .map(|r| r.status().into())
+            .map_err(|e| e.to_string()),
+    );
+    let mut r_resp = sent?;
 
-        let mut r_resp = l_client.send(l_req).await?;
+    let r_body: R = r_resp.body_json().await?;
+    let mut r_hmap = HashMap::new();
+    for (n, v) in r_resp.iter() {
+        r_hmap.insert(String::from(n.as_str()), String::from(v.as_str()));
+    }
+    Ok((r_body, r_hmap))
+}
 
-        let r_body: Dynamic = r_resp.body_json().await?;
-        let mut r_hmap = HashMap::new();
-        for (n, v) in r_resp.iter() {
-            r_hmap.insert(String::from(n.as_str()), String::from(v.as_str()));
+/// How many redirects [`request`] follows.
+const MAX_REDIRECTS: usize = 10;
+
+/// Sends a request with a raw body and returns the response unread, for
+/// callers that stream the body themselves. Every redirect is checked
+/// against `permissions`, and recorded in the audit log of the handler,
+/// before it is followed.
+pub(crate) async fn request(
+    url: &str,
+    method: &str,
+    headers: &[(String, String)],
+    body: Option<Vec<u8>>,
+    follow_redirects: bool,
+    permissions: &Permissions,
+) -> surf::Result<surf::Response> {
+    let mut url = Url::parse(url)?;
+    let mut method = Method::from_str(method)?;
+    let mut body = body;
+    let client = surf::client();
+    for _ in 0..=MAX_REDIRECTS {
+        permissions
+            .check_net(&url)
+            .map_err(|e| surf::Error::from_str(StatusCode::Forbidden, e))?;
+        let call = audit::start(method.as_ref(), &url)
+            .map_err(|e| surf::Error::from_str(StatusCode::Forbidden, e))?;
+        let mut req = Request::new(method, url.clone());
+        for (n, v) in headers {
+            req.append_header(n.as_str(), v.as_str());
         }
-        let r_headers = to_dynamic(r_hmap).unwrap();
-        Ok::<Response, surf::Error>(Response {
-            body: r_body,
-            headers: r_headers,
-        })
-    }) {
-        Ok(v) => Ok(v),
+        if let Some(body) = &body {
+            req.set_body(body.clone());
+        }
+        let sent = client.send(req).await;
+        call.finish(
+            sent.as_ref()
+                .map(|r| r.status().into())
+                .map_err(|e| e.to_string()),
+        );
+        let res = sent?;
+        let status = res.status();
+        let location = match res.header("location") {
+            Some(l) if follow_redirects && status.is_redirection() => l.as_str().to_string(),
+            _ => return Ok(res),
+        };
+        url = url.join(&location)?;
+        // As in the fetch standard, a 303, or a 301 or 302 for a POST, turns
+        // into a GET.
+        let moved = status == StatusCode::MovedPermanently || status == StatusCode::Found;
+        if (status == StatusCode::SeeOther && method != Method::Head)
+            || (moved && method == Method::Post)
+        {
+            method = Method::Get;
+            body = None;
+        }
+    }
+    Err(surf::Error::from_str(
+        StatusCode::LoopDetected,
+        format!("More than {} redirects", MAX_REDIRECTS),
+    ))
+}
+
+pub fn fetch(permissions: &Permissions, opts: Options) -> Result<Response, Box<EvalAltResult>> {
+    match Url::parse(opts.url.as_str()) {
+        Ok(url) => permissions.check_net(&url)?,
+        Err(e) => return Err(format!("Invalid URL {}: {}", opts.url, e).into()),
+    }
+    let mut l_headers: HashMap<String, String> = HashMap::new();
+    if opts.headers.type_name() != "string" {
+        // the headers have been set to lets try and map them
+        l_headers = match from_dynamic(&opts.headers) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Hashmap Parse Error {:?}", e);
+                return Err("Hashmap error".into());
+            }
+        };
+    }
+
+    match rt::block_on(send(
+        opts.url.as_str(),
+        opts.method.as_str(),
+        &l_headers,
+        &opts.body,
+    )) {
+        Ok((body, headers)) => Ok(Response {
+            body,
+            headers: to_dynamic(headers).unwrap(),
+        }),
+        Err(e) if e.status() == StatusCode::Forbidden => Err(e.to_string().into()),
         Err(e) => {
             log::error!("Request Error: {}", e);
             Err("Surf Error".into())
@@ -0,0 +1,347 @@
+use crate::permissions::Permissions;
+use rhai::{Array, Blob, Dynamic, EvalAltResult, ImmutableString};
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Size limits applied to the data directory exposed to scripts.
+#[derive(Debug, Clone, Copy)]
+pub struct DataQuota {
+    /// Largest size in bytes a single file may grow to.
+    pub max_file_size: u64,
+    /// Largest combined size in bytes of everything in the data directory.
+    pub max_total_size: u64,
+    /// Most files and directories a script may extract from an archive or
+    /// pack into one.
+    pub max_archive_entries: usize,
+}
+
+impl Default for DataQuota {
+    fn default() -> Self {
+        Self {
+            max_file_size: 10 * 1024 * 1024,
+            max_total_size: 100 * 1024 * 1024,
+            max_archive_entries: 10_000,
+        }
+    }
+}
+
+/// File access for scripts, confined to a single directory.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    quota: DataQuota,
+    permissions: Permissions,
+}
+
+impl Sandbox {
+    pub fn new(root: PathBuf, quota: DataQuota) -> Self {
+        Self {
+            root,
+            quota,
+            permissions: Permissions::default(),
+        }
+    }
+
+    /// Further limits what can be read and written to the paths `permissions` allow.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn quota(&self) -> DataQuota {
+        self.quota
+    }
+
+    pub(crate) fn resolve_read(&self, name: &str) -> Result<PathBuf, Box<EvalAltResult>> {
+        let path = self.resolve(name)?;
+        self.permissions.check_read(&path)?;
+        Ok(path)
+    }
+
+    pub(crate) fn resolve_write(&self, name: &str) -> Result<PathBuf, Box<EvalAltResult>> {
+        let path = self.resolve(name)?;
+        self.permissions.check_write(&path)?;
+        Ok(path)
+    }
+
+    /// Maps a script supplied relative name onto a path inside the root,
+    /// refusing absolute paths, `..` and symlinks that lead outside of it,
+    /// or nowhere, as writing through a dangling one creates its target.
+    fn resolve(&self, name: &str) -> Result<PathBuf, Box<EvalAltResult>> {
+        let mut path = self.root.clone();
+        for c in Path::new(name).components() {
+            match c {
+                Component::Normal(p) => path.push(p),
+                Component::CurDir => continue,
+                _ => return Err(format!("Invalid file name: {}", name).into()),
+            }
+        }
+        // canonicalize whatever part of the path already exists
+        let mut existing = path.as_path();
+        while !existing.exists() {
+            if fs::symlink_metadata(existing).is_ok() {
+                log::warn!("Unauthorized attempt to access: {:?}", path);
+                return Err(format!("Invalid file name: {}", name).into());
+            }
+            existing = match existing.parent() {
+                Some(p) => p,
+                None => break,
+            };
+        }
+        match existing.canonicalize() {
+            Ok(p) if p.starts_with(&self.root) => Ok(path),
+            Ok(_) => {
+                log::warn!("Unauthorized attempt to access: {:?}", path);
+                Err(format!("Invalid file name: {}", name).into())
+            }
+            Err(e) => Err(format!("File error: {}", e).into()),
+        }
+    }
+
+    /// Checks that `path`, inside the root, may be read.
+    pub(crate) fn check_read(&self, path: &Path) -> Result<(), Box<EvalAltResult>> {
+        Ok(self.permissions.check_read(path)?)
+    }
+
+    /// The combined size in bytes of everything in the data directory.
+    pub(crate) fn used(&self) -> u64 {
+        Self::usage(&self.root)
+    }
+
+    fn usage(dir: &Path) -> u64 {
+        let mut total = 0;
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                match entry.metadata() {
+                    Ok(m) if m.is_dir() => total += Self::usage(&entry.path()),
+                    Ok(m) => total += m.len(),
+                    Err(_) => continue,
+                }
+            }
+        }
+        total
+    }
+
+    /// Checks that `path` may grow to `new_len` bytes, `old_len` being its current size.
+    fn check_quota(&self, new_len: u64, old_len: u64) -> Result<(), Box<EvalAltResult>> {
+        if new_len > self.quota.max_file_size {
+            return Err(format!(
+                "File size quota exceeded: {} > {} bytes",
+                new_len, self.quota.max_file_size
+            )
+            .into());
+        }
+        let total = Self::usage(&self.root).saturating_sub(old_len) + new_len;
+        if total > self.quota.max_total_size {
+            return Err(format!(
+                "Data directory quota exceeded: {} > {} bytes",
+                total, self.quota.max_total_size
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn write_file(
+        &self,
+        name: &str,
+        content: &[u8],
+        append: bool,
+    ) -> Result<(), Box<EvalAltResult>> {
+        let path = self.resolve_write(name)?;
+        let old_len = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let new_len = if append {
+            old_len + content.len() as u64
+        } else {
+            content.len() as u64
+        };
+        self.check_quota(new_len, old_len)?;
+
+        let res = (|| {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut f = fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&path)?;
+            f.write_all(content)
+        })();
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                log::error!("File write error {:?}: {}", path, e);
+                Err(format!("File error: {}", e).into())
+            }
+        }
+    }
+
+    pub fn read(&self, name: ImmutableString) -> Result<ImmutableString, Box<EvalAltResult>> {
+        let path = self.resolve_read(name.as_str())?;
+        match fs::read_to_string(&path) {
+            Ok(s) => Ok(s.into()),
+            Err(e) => Err(format!("File error: {}", e).into()),
+        }
+    }
+
+    pub fn write(
+        &self,
+        name: ImmutableString,
+        content: ImmutableString,
+    ) -> Result<(), Box<EvalAltResult>> {
+        self.write_file(name.as_str(), content.as_bytes(), false)
+    }
+
+    pub fn append(
+        &self,
+        name: ImmutableString,
+        content: ImmutableString,
+    ) -> Result<(), Box<EvalAltResult>> {
+        self.write_file(name.as_str(), content.as_bytes(), true)
+    }
+
+    /// Reads a file as bytes, for content that isn't text.
+    pub fn read_blob(&self, name: ImmutableString) -> Result<Blob, Box<EvalAltResult>> {
+        let path = self.resolve_read(name.as_str())?;
+        match fs::read(&path) {
+            Ok(b) => Ok(b),
+            Err(e) => Err(format!("File error: {}", e).into()),
+        }
+    }
+
+    pub fn write_blob(
+        &self,
+        name: ImmutableString,
+        content: Blob,
+    ) -> Result<(), Box<EvalAltResult>> {
+        self.write_file(name.as_str(), &content, false)
+    }
+
+    pub fn append_blob(
+        &self,
+        name: ImmutableString,
+        content: Blob,
+    ) -> Result<(), Box<EvalAltResult>> {
+        self.write_file(name.as_str(), &content, true)
+    }
+
+    /// Lists the entries of a directory inside the root, directories end with `/`.
+    pub fn list(&self, name: ImmutableString) -> Result<Array, Box<EvalAltResult>> {
+        let path = self.resolve_read(name.as_str())?;
+        let entries = match fs::read_dir(&path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("File error: {}", e).into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries.flatten() {
+            let mut n = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() {
+                n.push('/');
+            }
+            names.push(n);
+        }
+        names.sort();
+        Ok(names.into_iter().map(Dynamic::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sandbox(name: &str, quota: DataQuota) -> Sandbox {
+        let root = std::env::temp_dir().join(format!("tide-rhai-files-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Sandbox::new(root.canonicalize().unwrap(), quota)
+    }
+
+    #[test]
+    fn write_read_append() {
+        let sb = sandbox("rw", DataQuota::default());
+        sb.write("a/b.txt".into(), "hello".into()).unwrap();
+        sb.append("a/b.txt".into(), " world".into()).unwrap();
+        assert_eq!(sb.read("a/b.txt".into()).unwrap(), "hello world");
+        let names: Vec<String> = sb
+            .list("".into())
+            .unwrap()
+            .into_iter()
+            .map(|d| d.into_string().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a/"]);
+    }
+
+    #[test]
+    fn binary_content() {
+        let sb = sandbox("binary", DataQuota::default());
+        sb.write_blob("b.bin".into(), vec![0, 159, 146, 150])
+            .unwrap();
+        sb.append_blob("b.bin".into(), vec![255]).unwrap();
+        assert_eq!(
+            sb.read_blob("b.bin".into()).unwrap(),
+            vec![0, 159, 146, 150, 255]
+        );
+        assert!(sb.read("b.bin".into()).is_err());
+    }
+
+    #[test]
+    fn escape_rejected() {
+        let sb = sandbox("escape", DataQuota::default());
+        assert!(sb.read("../secret".into()).is_err());
+        assert!(sb.write("/etc/passwd".into(), "x".into()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_links_rejected() {
+        let sb = sandbox("dangling", DataQuota::default());
+        let outside = std::env::temp_dir().join("tide-rhai-files-dangling-target");
+        let _ = fs::remove_dir_all(&outside);
+        std::os::unix::fs::symlink(outside.join("file"), sb.root.join("link")).unwrap();
+        std::os::unix::fs::symlink(&outside, sb.root.join("dir")).unwrap();
+        assert!(sb.write("link".into(), "x".into()).is_err());
+        assert!(sb.append("link".into(), "x".into()).is_err());
+        assert!(sb.write("dir/file".into(), "x".into()).is_err());
+        assert!(!outside.exists());
+    }
+
+    #[test]
+    fn quota_enforced() {
+        let sb = sandbox(
+            "quota",
+            DataQuota {
+                max_file_size: 4,
+                max_total_size: 6,
+                ..DataQuota::default()
+            },
+        );
+        assert!(sb.write("a".into(), "12345".into()).is_err());
+        sb.write("a".into(), "1234".into()).unwrap();
+        assert!(sb.write("b".into(), "123".into()).is_err());
+        sb.write("a".into(), "12".into()).unwrap();
+        sb.write("b".into(), "1234".into()).unwrap();
+    }
+
+    #[test]
+    fn permissions_enforced() {
+        use crate::permissions::Allow;
+        let sb = sandbox("permissions", DataQuota::default());
+        sb.write("public/a.txt".into(), "a".into()).unwrap();
+        sb.write("private.txt".into(), "b".into()).unwrap();
+        let sb = sb.clone().with_permissions(Permissions {
+            read: Allow::Only(vec![sb.root.join("public")]),
+            ..Permissions::none()
+        });
+        assert_eq!(sb.read("public/a.txt".into()).unwrap(), "a");
+        let e = sb.read("private.txt".into()).unwrap_err();
+        assert!(e.to_string().contains("--allow-read"), "{}", e);
+        assert!(sb.write("public/a.txt".into(), "c".into()).is_err());
+    }
+}
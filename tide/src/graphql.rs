@@ -0,0 +1,325 @@
+//! A GraphQL endpoint whose schema is a `schema.graphql` file and whose
+//! resolvers are rhai functions, loaded once from a directory:
+//!
+//!```text
+//! # schema.graphql
+//! type Query { order(id: ID!): Order }
+//! type Mutation { cancel(id: ID!): Order }
+//! type Order { id: ID!, total: Int, lines: [Line!]! }
+//!
+//! // orders.rhai
+//! fn order(args) { #{ id: args.id, total: 40 } }
+//! fn cancel(args) { ... }
+//! fn Order_lines(order, args) { [] }
+//!```
+//!
+//! The fields of `Query` and `Mutation` call the function of their name
+//! with the arguments as a map. The field `f` of another type `T` calls
+//! `T_f(parent, args)` if a script defines it, otherwise it is the member
+//! `f` of the map its parent resolved to. In dev mode a `GET` shows
+//! GraphiQL.
+
+use crate::{from_script_value, thrown_http_error, to_script_value, RhaiDir};
+use async_graphql::dynamic::{
+    Enum, Field, FieldFuture, FieldValue, InputObject, InputValue, Object, ResolverContext, Scalar,
+    Schema, TypeRef,
+};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::Value;
+use async_graphql_parser::types::{
+    BaseType, FieldDefinition, InputValueDefinition, Type, TypeKind, TypeSystemDefinition,
+};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tide::http::mime;
+use tide::{Endpoint, Request, Response, StatusCode};
+
+/// The file of a GraphQL directory holding its schema.
+pub const SCHEMA: &str = "schema.graphql";
+
+/// Serves a schema and its resolvers, see the [module](self).
+///```no_run
+/// use tide_rhai::{GraphQl, RhaiDir};
+/// let dir = RhaiDir::new("/*", "./app/").unwrap();
+/// let mut app = tide::new();
+/// app.at("/graphql").all(GraphQl::load(&dir, "./graphql/", "/graphql").unwrap());
+///```
+pub struct GraphQl {
+    schema: Schema,
+    /// Where it is served, for GraphiQL.
+    path: String,
+    dev_mode: bool,
+}
+
+/// The resolver scripts, with the bindings of the directory they were
+/// loaded for.
+struct Resolvers {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+fn invalid(message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Resolvers {
+    fn has(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> async_graphql::Result<Value> {
+        let mut scope = self.scope.clone();
+        let out: Dynamic = self
+            .engine
+            .call_fn(&mut scope, &self.ast, name, args)
+            .map_err(|e| match thrown_http_error(&e) {
+                Some(h) => async_graphql::Error::new(h.message),
+                None => {
+                    log::error!("GraphQL resolver {} failed: {}", name, e);
+                    async_graphql::Error::new(e.to_string())
+                }
+            })?;
+        let json: serde_json::Value =
+            from_script_value(&out).map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        Ok(Value::from_json(json)?)
+    }
+}
+
+/// The arguments of a field, as a rhai map.
+fn arguments(ctx: &ResolverContext<'_>) -> async_graphql::Result<Dynamic> {
+    let mut args = Map::new();
+    for (name, value) in ctx.args.iter() {
+        let json = value.as_value().clone().into_json()?;
+        let value = to_script_value(&json).map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        args.insert(name.as_str().into(), value);
+    }
+    Ok(args.into())
+}
+
+/// Lists as lists of values, so objects in them resolve their own fields.
+fn field_value(value: Value) -> FieldValue<'static> {
+    match value {
+        Value::List(items) => FieldValue::list(items.into_iter().map(field_value)),
+        v => FieldValue::value(v),
+    }
+}
+
+fn type_ref(ty: &Type) -> TypeRef {
+    let base = match &ty.base {
+        BaseType::Named(name) => TypeRef::named(name.to_string()),
+        BaseType::List(inner) => TypeRef::List(Box::new(type_ref(inner))),
+    };
+    if ty.nullable {
+        base
+    } else {
+        TypeRef::NonNull(Box::new(base))
+    }
+}
+
+fn input_value(def: &InputValueDefinition) -> InputValue {
+    InputValue::new(def.name.node.to_string(), type_ref(&def.ty.node))
+}
+
+fn field(resolvers: &Arc<Resolvers>, type_name: &str, root: bool, def: &FieldDefinition) -> Field {
+    let name = def.name.node.to_string();
+    let function = if root {
+        Some(name.clone())
+    } else {
+        Some(format!("{}_{}", type_name, name)).filter(|f| resolvers.has(f))
+    };
+    let resolvers = resolvers.clone();
+    let member = name.clone();
+    let mut field = Field::new(name, type_ref(&def.ty.node), move |ctx| {
+        let resolvers = resolvers.clone();
+        let function = function.clone();
+        let member = member.clone();
+        FieldFuture::new(async move {
+            let parent = ctx.parent_value.as_value().cloned();
+            let value = match (&function, parent) {
+                (Some(f), _) if root => resolvers.call(f, (arguments(&ctx)?,))?,
+                (Some(f), Some(parent)) => {
+                    let parent = to_script_value(&parent.into_json()?)
+                        .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+                    resolvers.call(f, (parent, arguments(&ctx)?))?
+                }
+                (None, Some(Value::Object(mut fields))) => {
+                    fields.remove(member.as_str()).unwrap_or(Value::Null)
+                }
+                _ => Value::Null,
+            };
+            Ok(match value {
+                Value::Null => None,
+                v => Some(field_value(v)),
+            })
+        })
+    });
+    for arg in &def.arguments {
+        field = field.argument(input_value(&arg.node));
+    }
+    field
+}
+
+impl GraphQl {
+    /// Loads `schema.graphql` and the `.rhai` resolvers of `dir`, running
+    /// them with the bindings of `rhai`, to be served at `path`.
+    pub fn load(rhai: &RhaiDir, dir: impl AsRef<Path>, path: &str) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let sdl = std::fs::read_to_string(dir.join(SCHEMA))?;
+        let document = async_graphql_parser::parse_schema(&sdl).map_err(invalid)?;
+
+        let engine = rhai.build_engine();
+        let mut ast = AST::empty();
+        let mut files: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map_or(false, |e| e == "rhai"))
+            .collect();
+        files.sort();
+        for file in files {
+            let mut script = engine
+                .compile(std::fs::read_to_string(&file)?)
+                .map_err(|e| invalid(format!("{}: {}", file.display(), e)))?;
+            script.set_source(file.to_string_lossy().as_ref());
+            ast = ast.merge(&script);
+        }
+        let resolvers = Arc::new(Resolvers {
+            engine,
+            ast,
+            scope: rhai.scope(),
+        });
+
+        let (mut query, mut mutation) = ("Query".to_string(), None);
+        let mut types = Vec::new();
+        for definition in &document.definitions {
+            match definition {
+                TypeSystemDefinition::Schema(schema) => {
+                    if let Some(q) = &schema.node.query {
+                        query = q.node.to_string();
+                    }
+                    mutation = schema.node.mutation.as_ref().map(|m| m.node.to_string());
+                }
+                TypeSystemDefinition::Type(ty) => types.push(&ty.node),
+                TypeSystemDefinition::Directive(_) => {}
+            }
+        }
+        let has_mutation = types.iter().any(|t| t.name.node == "Mutation");
+        let mutation = mutation.or_else(|| has_mutation.then(|| "Mutation".to_string()));
+
+        let mut builder = Schema::build(&query, mutation.as_deref(), None);
+        for ty in types {
+            let name = ty.name.node.to_string();
+            let root = name == query || Some(&name) == mutation.as_ref();
+            builder = match &ty.kind {
+                TypeKind::Object(object) => {
+                    let mut o = Object::new(&name);
+                    for def in &object.fields {
+                        o = o.field(field(&resolvers, &name, root, &def.node));
+                    }
+                    builder.register(o)
+                }
+                TypeKind::InputObject(input) => {
+                    let mut i = InputObject::new(&name);
+                    for def in &input.fields {
+                        i = i.field(input_value(&def.node));
+                    }
+                    builder.register(i)
+                }
+                TypeKind::Enum(e) => builder.register(
+                    Enum::new(&name).items(e.values.iter().map(|v| v.node.value.node.to_string())),
+                ),
+                TypeKind::Scalar => builder.register(Scalar::new(&name)),
+                TypeKind::Interface(_) | TypeKind::Union(_) => {
+                    return Err(invalid(format!(
+                        "{} is an interface or union, which are not supported",
+                        name
+                    )))
+                }
+            };
+        }
+        let schema = builder.finish().map_err(invalid)?;
+        Ok(Self {
+            schema,
+            path: path.to_string(),
+            dev_mode: rhai.dev_mode,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<State> Endpoint<State> for GraphQl
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn call(&self, mut req: Request<State>) -> tide::Result {
+        let request: async_graphql::Request = match req.method() {
+            http_types::Method::Post => req.body_json().await?,
+            http_types::Method::Get if req.url().query().is_some() => req.query()?,
+            http_types::Method::Get if self.dev_mode => {
+                let page = GraphiQLSource::build().endpoint(&self.path).finish();
+                return Ok(Response::builder(StatusCode::Ok)
+                    .content_type(mime::HTML)
+                    .body(page)
+                    .build());
+            }
+            _ => return Ok(Response::new(StatusCode::MethodNotAllowed)),
+        };
+        let response = self.schema.execute(request).await;
+        Ok(Response::builder(StatusCode::Ok)
+            .body(serde_json::to_value(&response)?)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value as Json};
+    use tide_testing::TideTestingExt;
+
+    #[async_std::test]
+    async fn resolves() {
+        let dir = std::env::temp_dir().join("tide_rhai_graphql");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(SCHEMA),
+            "type Query { order(id: ID!): Order }\n\
+             type Mutation { cancel(id: ID!): Boolean }\n\
+             type Order { id: ID!, total: Int, lines: [Line!]! }\n\
+             type Line { sku: String! }",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("orders.rhai"),
+            "fn order(args) { #{ id: args.id, total: 40 } }\n\
+             fn cancel(args) { args.id == \"7\" }\n\
+             fn Order_lines(order, args) { [#{ sku: `sku-${order.id}` }] }",
+        )
+        .unwrap();
+        let rhai = RhaiDir::new("/*", &dir).unwrap();
+        let mut app = tide::new();
+        app.at("/graphql")
+            .all(GraphQl::load(&rhai, &dir, "/graphql").unwrap());
+
+        let got: Json = app
+            .post("/graphql")
+            .body(json!({"query": "{ order(id: \"7\") { id total lines { sku } } }"}))
+            .recv_json()
+            .await
+            .unwrap();
+        assert_eq!(
+            got["data"],
+            json!({"order": {"id": "7", "total": 40, "lines": [{"sku": "sku-7"}]}})
+        );
+        let got: Json = app
+            .post("/graphql")
+            .body(json!({"query": "mutation { cancel(id: \"7\") }"}))
+            .recv_json()
+            .await
+            .unwrap();
+        assert_eq!(got["data"], json!({"cancel": true}));
+        let res = app.get("/graphql").await.unwrap();
+        assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    }
+}
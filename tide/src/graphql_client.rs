@@ -0,0 +1,189 @@
+//! `graphql_query(endpoint, query)` for rhai scripts, with a map of
+//! variables and one of headers as optional arguments. It posts the query
+//! as GraphQL servers expect and gives the `data` of the answer, throwing
+//! the messages of its `errors` if there are any:
+//!
+//!```text
+//! fn get(req) {
+//!     let data = graphql_query(
+//!         "https://api.github.com/graphql",
+//!         "query($login: String!) { user(login: $login) { name } }",
+//!         #{ login: "octocat" },
+//!         #{ authorization: `bearer ${secret("GITHUB_TOKEN")}` }
+//!     );
+//!     data.user.name
+//! }
+//!```
+//!
+//! The endpoint is checked against the permissions and recorded in the
+//! audit log like the requests of `fetch`.
+
+use crate::fetch;
+use crate::permissions::Permissions;
+use crate::rt;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Map};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use surf::Url;
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+/// The `data` `endpoint` answers `query` with.
+fn query(
+    permissions: &Permissions,
+    endpoint: &str,
+    query: &str,
+    variables: &Dynamic,
+    headers: &Dynamic,
+) -> Out<Dynamic> {
+    let url = Url::parse(endpoint).map_err(|e| format!("Invalid URL {}: {}", endpoint, e))?;
+    permissions.check_net(&url)?;
+    let variables: Value = from_dynamic(variables)?;
+    let headers: HashMap<String, String> = match headers.is_unit() {
+        true => HashMap::new(),
+        false => from_dynamic(headers)?,
+    };
+    let body = json!({ "query": query, "variables": variables });
+    let (answer, _): (Value, _) = rt::block_on(fetch::send(endpoint, "POST", &headers, &body))
+        .map_err(|e| format!("GraphQL request to {} failed: {}", endpoint, e))?;
+    let errors = answer.get("errors").and_then(Value::as_array);
+    if let Some(errors) = errors.filter(|e| !e.is_empty()) {
+        let messages: Vec<_> = errors
+            .iter()
+            .map(|e| e["message"].as_str().unwrap_or("unknown error"))
+            .collect();
+        return Err(format!("GraphQL errors from {}: {}", endpoint, messages.join("; ")).into());
+    }
+    match answer.get("data") {
+        Some(data) if !data.is_null() => to_dynamic(data),
+        _ => Err(format!("GraphQL answer of {} has no data", endpoint).into()),
+    }
+}
+
+/// Registers `graphql_query`, reaching only what `permissions` allow.
+pub(crate) fn register(engine: &mut Engine, permissions: &Permissions) {
+    let p = permissions.clone();
+    engine.register_result_fn(
+        "graphql_query",
+        move |endpoint: ImmutableString, q: ImmutableString| {
+            query(&p, &endpoint, &q, &Dynamic::UNIT, &Dynamic::UNIT)
+        },
+    );
+    let p = permissions.clone();
+    engine.register_result_fn(
+        "graphql_query",
+        move |endpoint: ImmutableString, q: ImmutableString, variables: Map| {
+            query(&p, &endpoint, &q, &variables.into(), &Dynamic::UNIT)
+        },
+    );
+    let p = permissions.clone();
+    engine.register_result_fn(
+        "graphql_query",
+        move |endpoint: ImmutableString, q: ImmutableString, variables: Map, headers: Map| {
+            query(&p, &endpoint, &q, &variables.into(), &headers.into())
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::permissions::Allow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tide::listener::Listener;
+
+    /// The endpoint of a GraphQL server answering by the query, and how many
+    /// requests it got.
+    fn server() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let mut app = tide::with_state(requests.clone());
+        app.at("/graphql")
+            .post(|mut req: tide::Request<Arc<AtomicUsize>>| async move {
+                req.state().fetch_add(1, Ordering::Relaxed);
+                let body: Value = req.body_json().await?;
+                let token = req.header("authorization").map(|h| h.as_str().to_string());
+                let answer = match body["query"].as_str() {
+                    Some("{ broken }") => json!({
+                        "data": null,
+                        "errors": [{ "message": "no" }, { "message": "never" }],
+                    }),
+                    Some("{ empty }") => json!({ "data": null }),
+                    Some("{ nothing }") => json!({}),
+                    _ => json!({ "data": { "hello": body["variables"]["name"], "token": token } }),
+                };
+                tide::Body::from_json(&answer)
+            });
+        let mut listener = rt::block_on(app.bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("{}/graphql", listener.info()[0].connection());
+        rt::spawn(async move { listener.accept().await });
+        (endpoint, requests)
+    }
+
+    fn engine(permissions: &Permissions, endpoint: String) -> (Engine, rhai::Scope<'static>) {
+        let mut engine = Engine::new();
+        register(&mut engine, permissions);
+        let mut scope = rhai::Scope::new();
+        scope.push("endpoint", endpoint);
+        (engine, scope)
+    }
+
+    #[test]
+    fn unwraps_the_answer() {
+        let (endpoint, _) = server();
+        let (engine, mut scope) = engine(&Permissions::default(), endpoint);
+        let hello: String = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"graphql_query(endpoint, "query($name: String) { hello }", #{ name: "ann" }).hello"#,
+            )
+            .unwrap();
+        assert_eq!(hello, "ann");
+        let token: String = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"graphql_query(endpoint, "{ token }", #{}, #{ authorization: "t0k" }).token"#,
+            )
+            .unwrap();
+        assert_eq!(token, "t0k");
+    }
+
+    #[test]
+    fn throws_errors_and_missing_data() {
+        let (endpoint, _) = server();
+        let (engine, mut scope) = engine(&Permissions::default(), endpoint.clone());
+        let mut fails = |query: &str| {
+            let script = format!("graphql_query(endpoint, {:?})", query);
+            engine
+                .eval_with_scope::<Dynamic>(&mut scope, &script)
+                .unwrap_err()
+                .to_string()
+        };
+        let e = fails("{ broken }");
+        assert!(
+            e.contains(&format!("GraphQL errors from {}: no; never", endpoint)),
+            "{}",
+            e
+        );
+        for query in ["{ empty }", "{ nothing }"] {
+            let e = fails(query);
+            assert!(e.contains("has no data"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn checks_the_host_before_sending() {
+        let (endpoint, requests) = server();
+        let permissions = Permissions {
+            net: Allow::Only(vec!["example.com".into()]),
+            ..Permissions::none()
+        };
+        let (engine, mut scope) = engine(&permissions, endpoint);
+        let e = engine
+            .eval_with_scope::<Dynamic>(&mut scope, r#"graphql_query(endpoint, "{ hello }")"#)
+            .unwrap_err();
+        assert!(e.to_string().contains("--allow-net"), "{}", e);
+        assert_eq!(requests.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,114 @@
+//! Scripts answering each method with a function of its own:
+//!
+//!```text
+//! fn get(req) { #{ page: req.query("page") } }
+//! fn post(req) { http::response(http::CREATED, req.body_json()) }
+//! fn del(req) { http::response(http::NO_CONTENT) }
+//!```
+//!
+//! The top level of such a script runs first, for its imports and
+//! constants, then the function of the method is called with `req`. Methods
+//! without a function are answered with a 405 listing the others. Scripts
+//! without any of these functions are evaluated as a whole, as before.
+
+use crate::error_page::HttpError;
+use crate::request::ScriptRequest;
+use http_types::Method;
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+
+/// The function answering each method.
+const HANDLERS: [(Method, &str); 5] = [
+    (Method::Get, "get"),
+    (Method::Post, "post"),
+    (Method::Put, "put"),
+    (Method::Patch, "patch"),
+    (Method::Delete, "del"),
+];
+
+/// The methods `ast` has a handler for, in the order of [`HANDLERS`].
+pub(crate) fn handled(ast: &AST) -> Vec<(Method, &'static str)> {
+    HANDLERS
+        .into_iter()
+        .filter(|(_, name)| {
+            ast.iter_functions()
+                .any(|f| f.name == *name && f.params.len() <= 1)
+        })
+        .collect()
+}
+
+/// Runs `ast` for the request `req` of `scope`: the handler of its method
+/// if the script has handlers, the whole script if it has none.
+pub(crate) fn dispatch(
+    engine: &Engine,
+    scope: &mut Scope,
+    ast: &AST,
+) -> Result<Dynamic, Box<EvalAltResult>> {
+    let handled = handled(ast);
+    let req = scope.get_value::<ScriptRequest>("req");
+    let (Some(req), false) = (req, handled.is_empty()) else {
+        return engine.eval_ast_with_scope(scope, ast);
+    };
+    // HEAD is answered by `get`, the server drops the body.
+    let method = match req.method() {
+        Method::Head => Method::Get,
+        m => m,
+    };
+    let Some((_, name)) = handled.iter().find(|(m, _)| *m == method) else {
+        let mut allow: Vec<String> = handled.iter().map(|(m, _)| m.to_string()).collect();
+        if handled.iter().any(|(m, _)| *m == Method::Get) {
+            allow.insert(1, Method::Head.to_string());
+        }
+        let mut e = HttpError::new(405, format!("{} is not allowed", req.method()))?;
+        e.headers.push(("Allow".into(), allow.join(", ")));
+        return Ok(Dynamic::from(e));
+    };
+    let takes_req = ast
+        .iter_functions()
+        .any(|f| f.name == *name && f.params.len() == 1);
+    if takes_req {
+        engine.call_fn(scope, ast, name, (req,))
+    } else {
+        engine.call_fn(scope, ast, name, ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide::http::Url;
+
+    fn run(script: &str, method: Method) -> Dynamic {
+        let mut engine = Engine::new();
+        crate::request::register(&mut engine);
+        let ast = engine.compile(script).unwrap();
+        let url = Url::parse("http://localhost/items?page=3").unwrap();
+        let mut scope = Scope::new();
+        scope.push(
+            "req",
+            ScriptRequest::new(method, url, Vec::new(), Vec::new()),
+        );
+        dispatch(&engine, &mut scope, &ast).unwrap()
+    }
+
+    #[test]
+    fn by_method() {
+        let script = r#"
+            fn prefix() { "page " }
+            fn get(req) { prefix() + req.query("page") }
+            fn del() { "deleted" }
+        "#;
+        assert_eq!(run(script, Method::Get).cast::<String>(), "page 3");
+        assert_eq!(run(script, Method::Head).cast::<String>(), "page 3");
+        assert_eq!(run(script, Method::Delete).cast::<String>(), "deleted");
+
+        let e = run(script, Method::Post).cast::<HttpError>();
+        assert_eq!(e.status, 405);
+        assert_eq!(
+            e.headers,
+            [("Allow".to_string(), "GET, HEAD, DELETE".to_string())]
+        );
+
+        let legacy = r#"fn helper(n) { n * 2 } helper(21)"#;
+        assert_eq!(run(legacy, Method::Post).cast::<i64>(), 42);
+    }
+}
@@ -0,0 +1,338 @@
+//! The `http` module of rhai scripts: status codes as constants, a
+//! `Response` to answer with headers and a status of its own, and helpers
+//! for headers, query strings and URLs, so handlers don't build raw maps.
+//!
+//!```text
+//! if http::header(ctx.headers, "authorization") == () {
+//!     return http::redirect("/login?" + http::query_string(#{ next: "/orders" }));
+//! }
+//! http::response(http::CREATED, #{ id: 7 }).header("location", "/orders/7")
+//!```
+//!
+//! The module is built once and shared by the engines of every
+//! [`RhaiDir`](crate::RhaiDir).
+
+use crate::csv_utils::CsvBody;
+use crate::{from_script_value, to_script_value};
+use rhai::{Blob, Dynamic, EvalAltResult, FnNamespace, ImmutableString, Map, Module, Shared};
+use serde_json::Value;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tide::StatusCode;
+
+/// A response made by a script with `http::response`, answered as it is
+/// instead of as json with a 200.
+#[derive(Debug, Clone)]
+pub(crate) struct ScriptResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Strings and blobs are sent as they are, anything else as json.
+    pub body: Dynamic,
+    /// The key and for how long to serve the response again, set with
+    /// `memoize`, see [`crate::memo`].
+    pub memo: Option<(String, Duration)>,
+}
+
+impl ScriptResponse {
+    pub(crate) fn new(status: i64, body: Dynamic) -> Result<Self, Box<EvalAltResult>> {
+        let status = u16::try_from(status)
+            .ok()
+            .filter(|s| StatusCode::try_from(*s).is_ok())
+            .ok_or_else(|| format!("{} is not an HTTP status", status))?;
+        Ok(Self {
+            status,
+            headers: Vec::new(),
+            body,
+            memo: None,
+        })
+    }
+
+    /// Marks the response to be served again for `ttl` seconds, up to
+    /// [`MAX_TTL`](crate::memo::MAX_TTL).
+    pub(crate) fn memoize(
+        &mut self,
+        key: ImmutableString,
+        ttl: i64,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        let ttl = u64::try_from(ttl)
+            .ok()
+            .filter(|t| *t > 0 && *t <= crate::memo::MAX_TTL.as_secs())
+            .ok_or_else(|| format!("Cannot memoize a response for {} seconds", ttl))?;
+        self.memo = Some((key.to_string(), Duration::from_secs(ttl)));
+        Ok(self.clone())
+    }
+
+    /// Sets the header `name`, replacing earlier values.
+    pub(crate) fn header(&mut self, name: ImmutableString, value: Dynamic) -> Self {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+        self.headers.push((name.to_string(), value.to_string()));
+        self.clone()
+    }
+
+    fn get_headers(&mut self) -> Map {
+        self.headers
+            .iter()
+            .map(|(n, v)| (n.as_str().into(), v.clone().into()))
+            .collect()
+    }
+
+    pub(crate) fn into_response(self) -> Result<tide::Response, Box<EvalAltResult>> {
+        let mut res = tide::Response::new(self.status);
+        if self.body.is::<ImmutableString>() {
+            res.set_body(self.body.cast::<ImmutableString>().as_str());
+        } else if self.body.is::<Blob>() {
+            res.set_body(self.body.cast::<Blob>());
+        } else if self.body.is::<CsvBody>() {
+            res.set_body(self.body.cast::<CsvBody>().into_body());
+        } else if !self.body.is_unit() {
+            res.set_body(from_script_value::<Value>(&self.body)?);
+        }
+        for (n, v) in &self.headers {
+            res.insert_header(n.as_str(), v.as_str());
+        }
+        Ok(res)
+    }
+
+    /// The body as a script reads it back, for tests of handlers.
+    pub(crate) fn body(&self) -> Dynamic {
+        self.body.clone()
+    }
+}
+
+/// Status codes scripts answer with most, `http::NOT_FOUND` and so on.
+const STATUSES: [(&str, u16); 19] = [
+    ("OK", 200),
+    ("CREATED", 201),
+    ("ACCEPTED", 202),
+    ("NO_CONTENT", 204),
+    ("MOVED_PERMANENTLY", 301),
+    ("FOUND", 302),
+    ("SEE_OTHER", 303),
+    ("NOT_MODIFIED", 304),
+    ("TEMPORARY_REDIRECT", 307),
+    ("BAD_REQUEST", 400),
+    ("UNAUTHORIZED", 401),
+    ("FORBIDDEN", 403),
+    ("NOT_FOUND", 404),
+    ("METHOD_NOT_ALLOWED", 405),
+    ("CONFLICT", 409),
+    ("UNPROCESSABLE_ENTITY", 422),
+    ("TOO_MANY_REQUESTS", 429),
+    ("INTERNAL_SERVER_ERROR", 500),
+    ("SERVICE_UNAVAILABLE", 503),
+];
+
+/// The `http` module, see [`crate::RhaiDir`] for where it is registered.
+pub(crate) fn module() -> Shared<Module> {
+    static MODULE: OnceLock<Shared<Module>> = OnceLock::new();
+    MODULE.get_or_init(|| build().into()).clone()
+}
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+fn build() -> Module {
+    let mut m = Module::new();
+    for (name, status) in STATUSES {
+        m.set_var(name, status as i64);
+    }
+    m.set_custom_type::<ScriptResponse>("Response");
+    m.set_native_fn("response", |status: i64| -> Out<ScriptResponse> {
+        ScriptResponse::new(status, Dynamic::UNIT)
+    });
+    m.set_native_fn("response", ScriptResponse::new);
+    m.set_native_fn("redirect", |url: ImmutableString| redirect(url, 302));
+    m.set_native_fn("redirect", redirect);
+    // Global, to be called as a method of responses.
+    let hash = m.set_native_fn(
+        "header",
+        |r: &mut ScriptResponse, n: ImmutableString, v: Dynamic| -> Out<ScriptResponse> {
+            Ok(r.header(n, v))
+        },
+    );
+    m.update_fn_namespace(hash, FnNamespace::Global);
+    let hash = m.set_native_fn("memoize", ScriptResponse::memoize);
+    m.update_fn_namespace(hash, FnNamespace::Global);
+    m.set_getter_fn("status", |r: &mut ScriptResponse| -> Out<i64> {
+        Ok(r.status as i64)
+    });
+    m.set_getter_fn("headers", |r: &mut ScriptResponse| -> Out<Map> {
+        Ok(r.get_headers())
+    });
+    m.set_getter_fn("body", |r: &mut ScriptResponse| -> Out<Dynamic> {
+        Ok(r.body())
+    });
+    m.set_setter_fn("body", |r: &mut ScriptResponse, body: Dynamic| -> Out<()> {
+        r.body = body;
+        Ok(())
+    });
+    m.set_native_fn("status_text", |status: i64| -> Out<String> {
+        Ok(status_text(status))
+    });
+    m.set_native_fn(
+        "header",
+        |headers: Map, name: ImmutableString| -> Out<Dynamic> { Ok(header(&headers, &name)) },
+    );
+    m.set_native_fn("query", |url: ImmutableString| -> Out<Map> {
+        Ok(query(&url))
+    });
+    m.set_native_fn("query_string", |params: Map| -> Out<String> {
+        Ok(query_string(&params))
+    });
+    m.set_native_fn("encode", |s: ImmutableString| -> Out<String> {
+        Ok(url::form_urlencoded::byte_serialize(s.as_bytes()).collect())
+    });
+    m.set_native_fn("decode", |s: ImmutableString| -> Out<String> {
+        Ok(decode(&s))
+    });
+    m.set_native_fn("parse_url", |url: ImmutableString| parse_url(&url));
+    m.set_native_fn("join_url", join_url);
+    m.build_index();
+    m
+}
+
+fn status_text(status: i64) -> String {
+    let known = u16::try_from(status)
+        .ok()
+        .and_then(|s| StatusCode::try_from(s).ok());
+    known.map_or("", |s| s.canonical_reason()).to_string()
+}
+
+fn join_url(base: ImmutableString, path: ImmutableString) -> Out<String> {
+    let joined = url::Url::parse(&base).and_then(|b| b.join(&path));
+    let joined = joined.map_err(|e| format!("Cannot join {} to {}: {}", path, base, e))?;
+    Ok(joined.to_string())
+}
+
+pub(crate) fn redirect(url: ImmutableString, status: i64) -> Out<ScriptResponse> {
+    if !(300..400).contains(&status) {
+        return Err(format!("A redirect needs a 3xx status, not {}", status).into());
+    }
+    Ok(ScriptResponse::new(status, Dynamic::UNIT)?.header("location".into(), url.into()))
+}
+
+/// The header `name` of `headers`, whatever its case, or `()`.
+fn header(headers: &Map, name: &str) -> Dynamic {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map_or(Dynamic::UNIT, |(_, v)| v.clone())
+}
+
+/// The parameters of the query string of `url`, or of `url` itself if it is
+/// a bare query string. Repeated parameters keep their last value.
+fn query(url: &str) -> Map {
+    let query = match url.split_once('?') {
+        Some((_, q)) => q,
+        None if url.contains('=') => url,
+        None => "",
+    };
+    let query = query.split('#').next().unwrap_or_default();
+    url::form_urlencoded::parse(query.as_bytes())
+        .map(|(k, v)| (k.as_ref().into(), v.into_owned().into()))
+        .collect()
+}
+
+fn query_string(params: &Map) -> String {
+    let mut out = url::form_urlencoded::Serializer::new(String::new());
+    for (k, v) in params {
+        if !v.is_unit() {
+            out.append_pair(k, &v.to_string());
+        }
+    }
+    out.finish()
+}
+
+/// `s` with `+` and `%xx` escapes decoded, as in query strings.
+fn decode(s: &str) -> String {
+    url::form_urlencoded::parse(format!("={}", s).as_bytes())
+        .next()
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default()
+}
+
+/// `#{ scheme, host, port, path, query, fragment }` of `url`.
+fn parse_url(url: &str) -> Out<Map> {
+    let url = url::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    to_script_value(&serde_json::json!({
+        "scheme": url.scheme(),
+        "host": url.host_str(),
+        "port": url.port_or_known_default(),
+        "path": url.path(),
+        "query": url.query(),
+        "fragment": url.fragment(),
+    }))
+    .map(|v| v.cast::<Map>())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::Engine;
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.register_static_module("http", module());
+        engine
+    }
+
+    #[test]
+    fn helpers() {
+        let engine = engine();
+        let out: i64 = engine.eval("http::NOT_FOUND").unwrap();
+        assert_eq!(out, 404);
+        let out: String = engine
+            .eval(r#"http::query("/items?page=2&q=a+b")["q"] + http::query("x=1").x"#)
+            .unwrap();
+        assert_eq!(out, "a b1");
+        let out: String = engine
+            .eval(r#"http::query_string(#{ q: "a b", n: 1 })"#)
+            .unwrap();
+        assert!(out == "n=1&q=a+b" || out == "q=a+b&n=1", "{}", out);
+        let out: String = engine
+            .eval(r#"http::header(#{ "Content-Type": "text/plain" }, "content-type")"#)
+            .unwrap();
+        assert_eq!(out, "text/plain");
+        let out: String = engine
+            .eval(r#"let u = http::parse_url("https://example.com/a?b=1"); `${u.host}${u.path}${u.port}`"#)
+            .unwrap();
+        assert_eq!(out, "example.com/a443");
+        let out: String = engine
+            .eval(r#"http::join_url("https://example.com/a/b", "../c") + http::decode("a%20b")"#)
+            .unwrap();
+        assert_eq!(out, "https://example.com/ca b");
+    }
+
+    #[test]
+    fn responses() {
+        let engine = engine();
+        let res: ScriptResponse = engine
+            .eval(r#"http::response(http::CREATED, #{ id: 1 }).header("Location", "/items/1")"#)
+            .unwrap();
+        assert_eq!(res.status, 201);
+        assert_eq!(
+            res.headers,
+            [("Location".to_string(), "/items/1".to_string())]
+        );
+        let res = res.into_response().unwrap();
+        assert_eq!(res.header("location").unwrap().as_str(), "/items/1");
+
+        let res: ScriptResponse = engine.eval(r#"http::redirect("/login")"#).unwrap();
+        assert_eq!(res.status, 302);
+        assert!(engine
+            .eval::<Dynamic>(r#"http::redirect("/", 200)"#)
+            .is_err());
+        assert!(engine.eval::<Dynamic>("http::response(99)").is_err());
+
+        let res: ScriptResponse = engine
+            .eval(r#"http::response(200, "hi").memoize("greeting", 60)"#)
+            .unwrap();
+        assert_eq!(res.memo, Some(("greeting".into(), Duration::from_secs(60))));
+        assert!(engine
+            .eval::<Dynamic>(r#"http::response(200).memoize("k", 0)"#)
+            .is_err());
+        let e = engine
+            .eval::<Dynamic>(r#"http::response(200).memoize("k", 9223372036854775807)"#)
+            .unwrap_err();
+        assert!(e.to_string().contains("Cannot memoize"), "{}", e);
+    }
+}
@@ -0,0 +1,187 @@
+//! A server of its own for apps that are only script directories, with the
+//! `hyper` feature: hyper takes the connections and parses the requests in
+//! place of the listeners of tide, which only routes them to the handlers.
+//!
+//!```no_run
+//! use tide_rhai::{HyperServer, RhaiDir};
+//!
+//! # tide_rhai::rt::block_on(async {
+//! let dir = RhaiDir::new("/*", "./app/").unwrap();
+//! HyperServer::new(dir).listen(([127, 0, 0, 1], 8080).into()).await.unwrap();
+//! # });
+//!```
+//!
+//! hyper runs on tokio, which the feature turns on for [`rt`](crate::rt)
+//! too. Bodies are read whole before the script runs and after it answered.
+
+use hyper::body::{Body, Bytes};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use tide::{log, Endpoint, Server};
+
+/// Serves a tide server with hyper, see the [module](self).
+pub struct HyperServer<State> {
+    server: Server<State>,
+}
+
+impl HyperServer<()> {
+    /// Answers every request with `endpoint`, like a [`RhaiDir`](crate::RhaiDir)
+    /// or a [`JsDir`](crate::JsDir) with the prefix `/*`.
+    pub fn new(endpoint: impl Endpoint<()>) -> Self {
+        let mut server = tide::new();
+        server.at("/*").all(endpoint);
+        Self { server }
+    }
+}
+
+impl<State> HyperServer<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    /// Answers with `server`, routes, middleware and state as it has them.
+    pub fn from_server(server: Server<State>) -> Self {
+        Self { server }
+    }
+
+    /// Serves requests to `addr` until the server fails.
+    pub async fn listen(self, addr: SocketAddr) -> io::Result<()> {
+        let server = self.server;
+        let connections = make_service_fn(move |conn: &AddrStream| {
+            let server = server.clone();
+            let peer = conn.remote_addr();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(respond(&server, req, peer).await) }
+                }))
+            }
+        });
+        let bound = hyper::Server::try_bind(&addr)
+            .map_err(|e| io::Error::new(io::ErrorKind::AddrInUse, e))?;
+        log::info!("Listening on http://{}", addr);
+        bound
+            .serve(connections)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+fn failed(status: StatusCode, message: String) -> Response<Body> {
+    let mut res = Response::new(Body::from(message));
+    *res.status_mut() = status;
+    res
+}
+
+async fn respond<State>(
+    server: &Server<State>,
+    req: Request<Body>,
+    peer: SocketAddr,
+) -> Response<Body>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    let mut req = match incoming(req).await {
+        Ok(req) => req,
+        Err(e) => return failed(StatusCode::BAD_REQUEST, e),
+    };
+    req.set_peer_addr(Some(peer.to_string()));
+    match server.respond::<_, http_types::Response>(req).await {
+        Ok(res) => match outgoing(res).await {
+            Ok(res) => res.map(Body::from),
+            Err(e) => failed(StatusCode::INTERNAL_SERVER_ERROR, e),
+        },
+        Err(e) => failed(
+            StatusCode::from_u16(u16::from(e.status()))
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            e.to_string(),
+        ),
+    }
+}
+
+/// A request of hyper as tide reads it, failing for what cannot be.
+pub(crate) async fn incoming(req: Request<Body>) -> Result<http_types::Request, String> {
+    let (parts, body) = req.into_parts();
+    let method: http_types::Method = parts
+        .method
+        .as_str()
+        .parse()
+        .map_err(|_| format!("Unsupported method {}", parts.method))?;
+    let host = parts
+        .headers
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+    let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    let url = http_types::Url::parse(&format!("http://{}{}", host, path))
+        .map_err(|e| format!("Invalid URL {}: {}", path, e))?;
+    let mut out = http_types::Request::new(method, url);
+    for (name, value) in &parts.headers {
+        // tide only takes text; other values do not reach the scripts.
+        if let Ok(value) = value.to_str() {
+            out.append_header(name.as_str(), value);
+        }
+    }
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| format!("Cannot read the request body: {}", e))?;
+    out.set_body(body.to_vec());
+    Ok(out)
+}
+
+/// A response of tide as hyper sends it.
+pub(crate) async fn outgoing(mut res: http_types::Response) -> Result<Response<Bytes>, String> {
+    let body = res
+        .body_bytes()
+        .await
+        .map_err(|e| format!("Cannot read the response body: {}", e))?;
+    let mut out = Response::builder().status(u16::from(res.status()));
+    for (name, values) in res.iter() {
+        for value in values.iter() {
+            out = out.header(name.as_str(), value.as_str());
+        }
+    }
+    out.body(Bytes::from(body))
+        .map_err(|e| format!("Invalid response: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serves_scripts() {
+        let dir = std::env::temp_dir().join("tide_rhai_hyper");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("hello.rhai"), r#"#{ hello: req.query("name") }"#).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let server = HyperServer::new(crate::RhaiDir::new("/*", &dir).unwrap());
+        crate::rt::block_on(async move {
+            crate::rt::spawn(server.listen(addr));
+            let url: hyper::Uri = format!("http://{}/hello.rhai?name=hyper", addr)
+                .parse()
+                .unwrap();
+            let client = hyper::Client::new();
+            let mut res = None;
+            // Until the server is bound.
+            for _ in 0..50 {
+                match client.get(url.clone()).await {
+                    Ok(r) => {
+                        res = Some(r);
+                        break;
+                    }
+                    Err(_) => crate::rt::sleep(std::time::Duration::from_millis(20)).await,
+                }
+            }
+            let res = res.expect("the server never answered");
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(&body[..], br#"{"hello":"hyper"}"#);
+        });
+    }
+}
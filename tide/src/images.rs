@@ -0,0 +1,191 @@
+//! Images for rhai scripts: `image_resize(data, width, height)` to exactly
+//! that size and `image_thumbnail(data, width, height)` to fit in it, keeping
+//! the proportions. Both take PNG, JPEG, GIF or WebP blobs and give one in
+//! the format of the image, or in the `"png"`, `"jpeg"` or `"gif"` passed as
+//! the last argument.
+//!
+//!```text
+//! fn post(req) {
+//!     let avatar = image_thumbnail(req.body_blob, 128, 128, "jpeg");
+//!     file_write("avatar.jpg", avatar);
+//!     http::response(http::OK, avatar).header("content-type", "image/jpeg")
+//! }
+//!```
+//!
+//! Images are decoded only if they have at most [`Limits::max_image_pixels`]
+//! pixels, and made only that big, failing with the `ErrorDataTooLarge` of
+//! the engine like its own limits do, so an upload cannot take the memory of
+//! the server.
+//!
+//! [`Limits::max_image_pixels`]: crate::Limits::max_image_pixels
+
+use image::imageops::FilterType;
+use image::io::Reader;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use rhai::{Blob, Engine, EvalAltResult, ImmutableString, Position};
+use std::io::Cursor;
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+/// Most pixels of an image when the limits don't say, about 40 megapixels.
+pub(crate) const MAX_IMAGE_PIXELS: u64 = 40_000_000;
+
+const JPEG_QUALITY: u8 = 85;
+
+fn too_large(what: &str) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorDataTooLarge(
+        what.to_string(),
+        Position::NONE,
+    ))
+}
+
+/// The image of `data` and its format, if it has at most `max` pixels.
+fn decode(data: &[u8], max: u64) -> Out<(DynamicImage, ImageFormat)> {
+    let reader = || {
+        Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| format!("Invalid image: {}", e))
+    };
+    let format = reader()?
+        .format()
+        .ok_or("Invalid image: not a PNG, JPEG, GIF or WebP")?;
+    let (width, height) = reader()?
+        .into_dimensions()
+        .map_err(|e| format!("Invalid image: {}", e))?;
+    if u64::from(width) * u64::from(height) > max {
+        return Err(too_large("Image"));
+    }
+    let image = reader()?
+        .decode()
+        .map_err(|e| format!("Invalid image: {}", e))?;
+    Ok((image, format))
+}
+
+/// The format named `name`, or the one the image was in.
+fn output(name: Option<&str>, input: ImageFormat) -> Out<ImageOutputFormat> {
+    let format = match name.map(|n| n.to_ascii_lowercase()) {
+        None => input,
+        Some(n) => match n.as_str() {
+            "png" => ImageFormat::Png,
+            "jpeg" | "jpg" => ImageFormat::Jpeg,
+            "gif" => ImageFormat::Gif,
+            _ => return Err(format!("{} is not an image format: png, jpeg or gif", n).into()),
+        },
+    };
+    Ok(match format {
+        ImageFormat::Jpeg => ImageOutputFormat::Jpeg(JPEG_QUALITY),
+        ImageFormat::Gif => ImageOutputFormat::Gif,
+        // WebP is only read, PNG keeps what it had.
+        _ => ImageOutputFormat::Png,
+    })
+}
+
+/// The size `width` by `height` of a script, if it has at most `max` pixels.
+fn size(width: i64, height: i64, max: u64) -> Out<(u32, u32)> {
+    let side = |n: i64| u32::try_from(n).ok().filter(|n| *n > 0);
+    let (Some(w), Some(h)) = (side(width), side(height)) else {
+        return Err(format!("{}x{} is not an image size", width, height).into());
+    };
+    if u64::from(w) * u64::from(h) > max {
+        return Err(too_large("Resized image"));
+    }
+    Ok((w, h))
+}
+
+fn encode(image: DynamicImage, format: ImageOutputFormat) -> Out<Blob> {
+    // JPEG has no alpha channel.
+    let image = match format {
+        ImageOutputFormat::Jpeg(_) => DynamicImage::ImageRgb8(image.into_rgb8()),
+        _ => image,
+    };
+    let mut out = Cursor::new(Vec::new());
+    image
+        .write_to(&mut out, format)
+        .map_err(|e| format!("Cannot encode the image: {}", e))?;
+    Ok(out.into_inner())
+}
+
+fn resize(data: &[u8], width: i64, height: i64, format: Option<&str>, max: u64) -> Out<Blob> {
+    let (w, h) = size(width, height, max)?;
+    let (image, input) = decode(data, max)?;
+    let format = output(format, input)?;
+    encode(image.resize_exact(w, h, FilterType::Lanczos3), format)
+}
+
+fn thumbnail(data: &[u8], width: i64, height: i64, format: Option<&str>, max: u64) -> Out<Blob> {
+    let (w, h) = size(width, height, max)?;
+    let (image, input) = decode(data, max)?;
+    let format = output(format, input)?;
+    encode(image.thumbnail(w, h), format)
+}
+
+/// Registers the functions of the [module](self), for images of at most
+/// `max` pixels.
+pub(crate) fn register(engine: &mut Engine, max: u64) {
+    engine
+        .register_result_fn("image_resize", move |data: Blob, w: i64, h: i64| {
+            resize(&data, w, h, None, max)
+        })
+        .register_result_fn(
+            "image_resize",
+            move |data: Blob, w: i64, h: i64, format: ImmutableString| {
+                resize(&data, w, h, Some(format.as_str()), max)
+            },
+        )
+        .register_result_fn("image_thumbnail", move |data: Blob, w: i64, h: i64| {
+            thumbnail(&data, w, h, None, max)
+        })
+        .register_result_fn(
+            "image_thumbnail",
+            move |data: Blob, w: i64, h: i64, format: ImmutableString| {
+                thumbnail(&data, w, h, Some(format.as_str()), max)
+            },
+        );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    #[test]
+    fn resizes_within_limits() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(RgbaImage::new(40, 20))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        let mut engine = Engine::new();
+        register(&mut engine, 1000);
+        let mut scope = rhai::Scope::new();
+        scope.push("png", png.into_inner());
+
+        let thumb: Blob = engine
+            .eval_with_scope(&mut scope, r#"image_thumbnail(png, 10, 10, "jpeg")"#)
+            .unwrap();
+        let (image, format) = decode(&thumb, 1000).unwrap();
+        assert_eq!((format, image.dimensions()), (ImageFormat::Jpeg, (10, 5)));
+        let resized: Blob = engine
+            .eval_with_scope(&mut scope, "image_resize(png, 8, 8)")
+            .unwrap();
+        let (image, format) = decode(&resized, 1000).unwrap();
+        assert_eq!((format, image.dimensions()), (ImageFormat::Png, (8, 8)));
+
+        let big = engine.eval_with_scope::<Blob>(&mut scope, "image_resize(png, 100, 100)");
+        assert!(matches!(
+            *big.unwrap_err(),
+            EvalAltResult::ErrorDataTooLarge(..)
+        ));
+        register(&mut engine, 100);
+        let decoded = engine.eval_with_scope::<Blob>(&mut scope, "image_thumbnail(png, 5, 5)");
+        assert!(matches!(
+            *decoded.unwrap_err(),
+            EvalAltResult::ErrorDataTooLarge(..)
+        ));
+        assert!(engine
+            .eval_with_scope::<Blob>(&mut scope, r#"image_resize(png, 0, 5, "bmp")"#)
+            .is_err());
+        assert!(engine
+            .eval::<Blob>("image_thumbnail(blob(4, 1), 5, 5)")
+            .is_err());
+    }
+}
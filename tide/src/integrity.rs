@@ -0,0 +1,205 @@
+//! Signed hashes of the scripts of an app, for deployments where the app
+//! directory is written by something less trusted than the server, like a
+//! shared volume or a sync job.
+//!
+//! `integrity.json` in the app directory maps each script to its SHA-256,
+//! and `integrity.sig` holds an ECDSA P-256 signature of that file.
+//! [`Integrity::load`] checks the signature once, and from then on every
+//! script and module read from disk must hash to what the manifest says:
+//! edited, added or unlisted scripts are refused, never run.
+//!
+//!```text
+//! {"files": {"api/orders.rhai": "9f86d0...", "lib/util.ts": "60303a..."}}
+//!```
+//!
+//! Scripts served from a [`Snapshot`](crate::Snapshot) are not checked, the
+//! snapshot is trusted as a whole.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "integrity.json";
+const SIGNATURE: &str = "integrity.sig";
+
+/// What is hashed, the files a [`Snapshot`](crate::Snapshot) takes.
+const SCRIPTS: [&str; 8] = ["rhai", "js", "mjs", "cjs", "ts", "mts", "cts", "json"];
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    /// Hex SHA-256 of each script, by its path below the app directory.
+    files: BTreeMap<String, String>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn hash(source: &[u8]) -> String {
+    Sha256::digest(source)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The verified manifest of an app directory.
+#[derive(Debug, Clone)]
+pub struct Integrity {
+    root: PathBuf,
+    files: BTreeMap<String, String>,
+}
+
+impl Integrity {
+    /// Reads the manifest of `dir` and checks its signature against
+    /// `public_key`, the base64 of an uncompressed or compressed P-256
+    /// point. Fails if either file is missing or the signature is not the
+    /// key's.
+    pub fn load(dir: impl AsRef<Path>, public_key: &str) -> io::Result<Self> {
+        let root = dir.as_ref().canonicalize()?;
+        let key = STANDARD
+            .decode(public_key.trim())
+            .ok()
+            .and_then(|k| VerifyingKey::from_sec1_bytes(&k).ok())
+            .ok_or_else(|| invalid("not a P-256 public key".into()))?;
+        let manifest = std::fs::read(root.join(MANIFEST))?;
+        let signature = std::fs::read_to_string(root.join(SIGNATURE))?;
+        let signature = STANDARD
+            .decode(signature.trim())
+            .ok()
+            .and_then(|s| Signature::from_slice(&s).ok())
+            .ok_or_else(|| invalid(format!("{} is not a signature", SIGNATURE)))?;
+        key.verify(&manifest, &signature).map_err(|_| {
+            invalid(format!(
+                "the signature of {} does not match its key",
+                MANIFEST
+            ))
+        })?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .map_err(|e| invalid(format!("invalid {}: {}", MANIFEST, e)))?;
+        log::info!("Verified the hashes of {} scripts", manifest.files.len());
+        Ok(Self {
+            root,
+            files: manifest.files,
+        })
+    }
+
+    /// Writes the manifest of every script below `dir` and its signature
+    /// with `secret_key`, the base64 of a P-256 scalar. Returns how many
+    /// scripts it lists.
+    pub fn sign(dir: impl AsRef<Path>, secret_key: &str) -> io::Result<usize> {
+        let root = dir.as_ref().canonicalize()?;
+        let key = STANDARD
+            .decode(secret_key.trim())
+            .ok()
+            .and_then(|k| SigningKey::from_slice(&k).ok())
+            .ok_or_else(|| invalid("not a P-256 secret key".into()))?;
+        let mut paths = Vec::new();
+        crate::snapshot::collect(&root, &mut paths)?;
+        let mut files = BTreeMap::new();
+        for path in paths {
+            let script = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map_or(false, |e| SCRIPTS.contains(&e));
+            let Some(name) = crate::snapshot::key(&root, &path) else {
+                continue;
+            };
+            if script && name != MANIFEST {
+                files.insert(name, hash(&std::fs::read(&path)?));
+            }
+        }
+        let count = files.len();
+        let manifest = serde_json::to_vec_pretty(&Manifest { files })?;
+        let signature: Signature = key.sign(&manifest);
+        std::fs::write(root.join(MANIFEST), &manifest)?;
+        std::fs::write(root.join(SIGNATURE), STANDARD.encode(signature.to_bytes()))?;
+        Ok(count)
+    }
+
+    /// A new pair of keys, the secret one for [`Integrity::sign`] and the
+    /// public one for [`Integrity::load`], in base64.
+    pub fn generate_keys() -> (String, String) {
+        let mut scalar = [0; 32];
+        let key = loop {
+            getrandom::getrandom(&mut scalar).expect("no random numbers");
+            if let Ok(key) = SigningKey::from_slice(&scalar) {
+                break key;
+            }
+        };
+        let public = key.verifying_key().to_encoded_point(true);
+        (
+            STANDARD.encode(key.to_bytes()),
+            STANDARD.encode(public.as_bytes()),
+        )
+    }
+
+    /// Fails unless `source` is what the manifest lists for `path`.
+    pub(crate) fn check(&self, path: &Path, source: &[u8]) -> io::Result<()> {
+        let name = path
+            .canonicalize()
+            .ok()
+            .and_then(|p| crate::snapshot::key(&self.root, &p));
+        let refused = |why: &str| {
+            let message = format!("Refusing to run {}: {}", path.display(), why);
+            log::error!("{}", message);
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, message))
+        };
+        match name.and_then(|name| self.files.get(&name)) {
+            Some(expected) if *expected == hash(source) => Ok(()),
+            Some(_) => refused("it changed since it was signed"),
+            None => refused(&format!("it is not listed in {}", MANIFEST)),
+        }
+    }
+}
+
+/// The script at `path`, checked against `integrity` if there is one.
+pub(crate) fn read(integrity: Option<&Integrity>, path: &Path) -> io::Result<String> {
+    let source = std::fs::read_to_string(path)?;
+    if let Some(integrity) = integrity {
+        integrity.check(path, source.as_bytes())?;
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signs_and_refuses_changes() {
+        let dir = std::env::temp_dir().join("tide-rhai-integrity");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("a.rhai"), "1 + 1").unwrap();
+        std::fs::write(dir.join("lib/b.ts"), "export const b = 1;").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a script").unwrap();
+
+        let (secret, public) = Integrity::generate_keys();
+        assert_eq!(Integrity::sign(&dir, &secret).unwrap(), 2);
+        let integrity = Integrity::load(&dir, &public).unwrap();
+        assert_eq!(
+            read(Some(&integrity), &dir.join("a.rhai")).unwrap(),
+            "1 + 1"
+        );
+        assert!(read(Some(&integrity), &dir.join("lib/b.ts")).is_ok());
+
+        std::fs::write(dir.join("a.rhai"), "2 + 2").unwrap();
+        let e = read(Some(&integrity), &dir.join("a.rhai")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        std::fs::write(dir.join("c.rhai"), "3").unwrap();
+        assert!(read(Some(&integrity), &dir.join("c.rhai")).is_err());
+        assert!(read(None, &dir.join("c.rhai")).is_ok());
+
+        let (_, other) = Integrity::generate_keys();
+        assert!(Integrity::load(&dir, &other).is_err());
+        let manifest = std::fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        std::fs::write(dir.join(MANIFEST), manifest.replace("lib/b.ts", "lib/c.ts")).unwrap();
+        assert!(Integrity::load(&dir, &public).is_err());
+    }
+}
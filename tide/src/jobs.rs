@@ -0,0 +1,241 @@
+//! [`Jobs`], work run every so often besides the requests, like removing
+//! old uploads every hour, and how it went for the dashboard of
+//! [`Admin`](crate::Admin). A job runs first once its interval has passed
+//! after [`Jobs::start`], then again an interval after each run ends, so a
+//! slow run is never overtaken by the next:
+//!
+//!```text
+//! WARN job cleanup failed: Runtime error: disk full (line 3, position 5)
+//!```
+//!
+//! Jobs run one after the other on a thread of their own, until
+//! [`Jobs::stop`] or until they are dropped.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the thread of the jobs looks whether they were stopped.
+const TICK: Duration = Duration::from_millis(100);
+
+type Run = Box<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+/// How a job went so far.
+#[derive(Debug)]
+struct State {
+    next: Instant,
+    runs: u64,
+    failures: u64,
+    running: bool,
+    last_run: Option<String>,
+    last_error: Option<String>,
+}
+
+struct Job {
+    name: String,
+    every: Duration,
+    run: Run,
+    state: Mutex<State>,
+}
+
+impl Job {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs the job if it is due by `now`.
+    fn run_if_due(&self, now: Instant) {
+        {
+            let mut state = self.state();
+            if state.next > now {
+                return;
+            }
+            state.running = true;
+        }
+        let done = (self.run)();
+        let mut state = self.state();
+        state.running = false;
+        state.runs += 1;
+        state.last_run = Some(crate::stats::now());
+        if let Err(e) = done {
+            log::warn!("job {} failed: {}", self.name, e);
+            state.failures += 1;
+            state.last_error = Some(e);
+        }
+        state.next = Instant::now() + self.every;
+    }
+}
+
+/// What [`Jobs::report`] says of one job.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct JobReport {
+    pub name: String,
+    pub every_secs: f64,
+    pub runs: u64,
+    pub failures: u64,
+    pub running: bool,
+    /// When the last run ended.
+    pub last_run: Option<String>,
+    /// The error of the last failed run, even if later runs went well.
+    pub last_error: Option<String>,
+    /// Seconds until the next run, 0 while it is due or running.
+    pub next_in_secs: u64,
+}
+
+/// Jobs and the thread running them.
+///```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tide_rhai::{Admin, Jobs, RhaiDir};
+/// let dir = Arc::new(RhaiDir::new("/*", "./examples/app/").unwrap());
+/// let d = dir.clone();
+/// let jobs = Arc::new(Jobs::new().job("cleanup", Duration::from_secs(3600), move || {
+///     d.run_script("./jobs/cleanup.rhai").map_err(|e| e.to_string())
+/// }));
+/// jobs.start().unwrap();
+/// let mut app = tide::new();
+/// app.at("/admin").get(Admin::new("s3cr3t").with_jobs(jobs.clone()));
+/// jobs.stop();
+///```
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    stopped: AtomicBool,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `run` every `every`, failing with the message it answers.
+    pub fn job(
+        mut self,
+        name: impl Into<String>,
+        every: Duration,
+        run: impl Fn() -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.jobs.push(Job {
+            name: name.into(),
+            every,
+            run: Box::new(run),
+            state: Mutex::new(State {
+                next: Instant::now() + every,
+                runs: 0,
+                failures: 0,
+                running: false,
+                last_run: None,
+                last_error: None,
+            }),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Starts the thread running the jobs, their intervals counted from now.
+    pub fn start(self: &Arc<Self>) -> std::io::Result<()> {
+        if self.jobs.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        for job in &self.jobs {
+            job.state().next = now + job.every;
+        }
+        let jobs = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("tide-rhai-jobs".into())
+            .spawn(move || loop {
+                let Some(jobs) = jobs.upgrade() else {
+                    return;
+                };
+                if jobs.stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                for job in &jobs.jobs {
+                    job.run_if_due(Instant::now());
+                    if jobs.stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+                }
+                let next = jobs.jobs.iter().map(|j| j.state().next).min();
+                drop(jobs);
+                let wait = next.map_or(TICK, |n| n.saturating_duration_since(Instant::now()));
+                std::thread::sleep(wait.min(TICK));
+            })?;
+        Ok(())
+    }
+
+    /// Stops running the jobs, letting a run in progress end.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Every job and how it went, for an admin API.
+    pub fn report(&self) -> Vec<JobReport> {
+        let now = Instant::now();
+        self.jobs
+            .iter()
+            .map(|job| {
+                let state = job.state();
+                JobReport {
+                    name: job.name.clone(),
+                    every_secs: job.every.as_secs_f64(),
+                    runs: state.runs,
+                    failures: state.failures,
+                    running: state.running,
+                    last_run: state.last_run.clone(),
+                    last_error: state.last_error.clone(),
+                    next_in_secs: match state.running {
+                        true => 0,
+                        false => state.next.saturating_duration_since(now).as_secs(),
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+
+    #[test]
+    fn runs_every_interval_until_stopped() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let r = runs.clone();
+        let jobs = Arc::new(
+            Jobs::new()
+                .job("count", Duration::from_millis(20), move || {
+                    r.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                })
+                .job("broken", Duration::from_millis(20), || {
+                    Err("disk full".into())
+                })
+                .job("hourly", Duration::from_secs(3600), || Ok(())),
+        );
+        jobs.start().unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        jobs.stop();
+        std::thread::sleep(TICK * 2);
+        let stopped_at = runs.load(Ordering::Relaxed);
+        assert!(stopped_at >= 2, "{}", stopped_at);
+
+        let report = jobs.report();
+        assert_eq!(report[0].runs, stopped_at);
+        assert_eq!(report[0].last_error, None);
+        assert!(report[0].last_run.is_some());
+        assert_eq!(report[1].failures, report[1].runs);
+        assert_eq!(report[1].last_error.as_deref(), Some("disk full"));
+        assert_eq!(report[2].runs, 0);
+        assert!(report[2].next_in_secs > 3500);
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(runs.load(Ordering::Relaxed), stopped_at);
+    }
+}
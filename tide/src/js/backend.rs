@@ -0,0 +1,85 @@
+//! The engines JavaScript handlers can run on.
+
+use super::pool::Pool;
+use super::{run, Incoming, Reply, Runtime};
+use crate::rt;
+use crate::stats::EngineStats;
+use async_std::channel;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// A script to run and where its answer goes.
+pub(crate) struct Job {
+    pub path: PathBuf,
+    pub source: String,
+    pub incoming: Incoming,
+    pub reply: channel::Sender<Result<Reply, String>>,
+}
+
+/// Runs the scripts of a [`JsDir`](crate::JsDir). One is started by the
+/// first request and kept for the others.
+pub(crate) trait JsBackend: Send + Sync {
+    /// Starts running `job` and returns right away, the answer goes to
+    /// `job.reply`.
+    fn run(&self, job: Job);
+
+    /// Fills in what the backend knows about its threads.
+    fn stats(&self, _stats: &mut EngineStats) {}
+}
+
+/// Which engine runs JavaScript handlers, see [`JsDir::with_engine`](crate::JsDir::with_engine).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsEngine {
+    /// Boa, written in Rust, with the web APIs, modules and workers.
+    #[default]
+    Boa,
+    /// V8, for full spec compliance and a JIT. Only runs plain scripts and
+    /// handler functions with `ctx` and `console` so far.
+    #[cfg(feature = "v8")]
+    V8,
+}
+
+impl JsEngine {
+    pub(crate) fn start(self, runtime: Runtime, pool_size: usize) -> Box<dyn JsBackend> {
+        match self {
+            JsEngine::Boa => Box::new(Boa::new(runtime, pool_size)),
+            #[cfg(feature = "v8")]
+            JsEngine::V8 => Box::new(super::v8_backend::V8::new(runtime)),
+        }
+    }
+}
+
+/// Runs scripts in warm contexts from the pool, or in a new one when they are
+/// all busy.
+struct Boa {
+    runtime: Runtime,
+    pool: Pool,
+}
+
+impl Boa {
+    fn new(runtime: Runtime, pool_size: usize) -> Self {
+        let pool = Pool::new(runtime.clone(), pool_size);
+        Self { runtime, pool }
+    }
+}
+
+impl JsBackend for Boa {
+    fn run(&self, job: Job) {
+        if let Err(job) = self.pool.try_run(job) {
+            self.runtime.counters.cold_start();
+            let runtime = self.runtime.clone();
+            // The engine blocks while waiting on the futures of the script,
+            // so it gets a thread of its own. Not awaited, a streamed body
+            // keeps the script running after the reply.
+            rt::spawn_blocking(move || {
+                run(&runtime, &job.path, &job.source, &job.incoming, job.reply)
+            });
+        }
+    }
+
+    fn stats(&self, stats: &mut EngineStats) {
+        stats.pool_size = self.pool.size();
+        stats.pool_idle = self.pool.idle();
+    }
+}
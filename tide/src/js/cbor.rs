@@ -0,0 +1,169 @@
+//! The `cbor` global, see [`crate::cbor`]: `cbor.decode` of an `ArrayBuffer`,
+//! a view on one or a string, and `cbor.encode` of a value into a
+//! `Uint8Array`.
+//!
+//! Integers are numbers up to `Number.MAX_SAFE_INTEGER` and BigInts past it,
+//! byte strings `Uint8Array`s, and maps objects, with keys that are not text
+//! written as in rhai. Tagged values are objects of their `tag` and `value`.
+//! Maps, `null` and `undefined` encode too; functions and symbols do not.
+
+use super::web::{self, MAX_SAFE_INTEGER};
+use crate::cbor::{self, Value};
+use boa_engine::object::builtins::{JsArray, JsArrayBuffer, JsUint8Array};
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsBigInt, JsNativeError, JsResult, JsString, JsValue,
+    NativeFunction,
+};
+
+pub(super) fn register(context: &mut Context<'_>) -> JsResult<()> {
+    let decode = NativeFunction::from_fn_ptr(|_, args, context| {
+        let bytes = web::to_bytes(args.get_or_undefined(0), context)?;
+        let value = cbor::decode(&bytes).map_err(|e| JsNativeError::syntax().with_message(e))?;
+        to_js(value, context)
+    });
+    let encode = NativeFunction::from_fn_ptr(|_, args, context| {
+        let value = from_js(args.get_or_undefined(0), context)?;
+        web::from_bytes(cbor::encode(&value), context)
+    });
+    let object = ObjectInitializer::new(context)
+        .function(decode, "decode", 1)
+        .function(encode, "encode", 1)
+        .build();
+    context.register_global_property(js_string!("cbor"), object, Attribute::all())
+}
+
+fn to_js(value: Value, context: &mut Context<'_>) -> JsResult<JsValue> {
+    Ok(match value {
+        Value::Integer(n) => {
+            let n = i128::from(n);
+            if n.unsigned_abs() <= MAX_SAFE_INTEGER as u128 {
+                (n as f64).into()
+            } else if let Ok(i) = i64::try_from(n) {
+                JsBigInt::from(i).into()
+            } else if let Ok(u) = u64::try_from(n) {
+                JsBigInt::from(u).into()
+            } else {
+                // Negative integers of CBOR go down to -2^64, past a BigInt of
+                // 64 bits.
+                (n as f64).into()
+            }
+        }
+        Value::Bytes(bytes) => web::from_bytes(bytes, context)?,
+        Value::Float(f) => f.into(),
+        Value::Text(s) => JsString::from(s).into(),
+        Value::Bool(b) => b.into(),
+        Value::Tag(tag, value) => {
+            let value = to_js(*value, context)?;
+            ObjectInitializer::new(context)
+                .property(js_string!("tag"), tag as f64, Attribute::all())
+                .property(js_string!("value"), value, Attribute::all())
+                .build()
+                .into()
+        }
+        Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| to_js(item, context))
+                .collect::<JsResult<Vec<_>>>()?;
+            JsArray::from_iter(items, context).into()
+        }
+        Value::Map(entries) => {
+            let mut properties = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                properties.push((JsString::from(cbor::key(key)), to_js(value, context)?));
+            }
+            let mut object = ObjectInitializer::new(context);
+            for (key, value) in properties {
+                object.property(key, value, Attribute::all());
+            }
+            object.build().into()
+        }
+        _ => JsValue::null(),
+    })
+}
+
+fn from_js(value: &JsValue, context: &mut Context<'_>) -> JsResult<Value> {
+    if value.is_null_or_undefined() {
+        return Ok(Value::Null);
+    }
+    if let Some(b) = value.as_boolean() {
+        return Ok(Value::Bool(b));
+    }
+    if let Some(s) = value.as_string() {
+        return Ok(Value::Text(String::from_utf16_lossy(s)));
+    }
+    if let Some(n) = value.as_number() {
+        if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 {
+            return Ok(Value::from(n as i64));
+        }
+        return Ok(Value::Float(n));
+    }
+    if let Some(big) = value.as_bigint() {
+        let big = big.to_string();
+        if let Ok(i) = big.parse::<i64>() {
+            return Ok(Value::from(i));
+        }
+        return big.parse::<u64>().map(Value::from).map_err(|_| {
+            let message = format!("{} does not fit in 64 bits", big);
+            JsNativeError::range().with_message(message).into()
+        });
+    }
+    let cannot = || {
+        let message = format!("CBOR cannot hold {}", value.display());
+        JsNativeError::typ().with_message(message)
+    };
+    let object = match value.as_object() {
+        Some(object) if !object.is_callable() => object,
+        _ => return Err(cannot().into()),
+    };
+    if JsUint8Array::from_object(object.clone()).is_ok()
+        || JsArrayBuffer::from_object(object.clone()).is_ok()
+    {
+        return Ok(Value::Bytes(web::to_bytes(value, context)?));
+    }
+    if object.is_array() {
+        let list = JsArray::from_object(object.clone())?;
+        let mut items = Vec::new();
+        for i in 0..list.length(context)? {
+            items.push(from_js(&list.get(i, context)?, context)?);
+        }
+        return Ok(Value::Array(items));
+    }
+    let pairs = web::entries(object, context)?;
+    let mut map = Vec::new();
+    for i in 0..pairs.length(context)? {
+        let pair = pairs.get(i, context)?;
+        let pair = pair.as_object().ok_or_else(cannot)?;
+        let key = from_js(&pair.get(0, context)?, context)?;
+        map.push((key, from_js(&pair.get(1, context)?, context)?));
+    }
+    Ok(Value::Map(map))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use boa_engine::Source;
+
+    #[test]
+    fn round_trips() {
+        let mut context = Context::default();
+        register(&mut context).unwrap();
+        let mut eval = |code: &str| context.eval(Source::from_bytes(code));
+        let same = r#"
+            const reading = { id: "probe", t: 21.5, big: 2n ** 60n, raw: new Uint8Array([1, 2]), off: null };
+            const back = cbor.decode(cbor.encode(reading));
+            back.id === "probe" && back.t === 21.5 && back.big === 2n ** 60n && back.off === null
+                && back.raw instanceof Uint8Array && back.raw[1] === 2
+        "#;
+        assert_eq!(eval(same).unwrap(), JsValue::from(true));
+        let map = eval(r#"cbor.decode(cbor.encode(new Map([[1, "one"]])))["1"]"#).unwrap();
+        assert_eq!(map, JsValue::from(js_string!("one")));
+        let tagged = eval("cbor.decode(new Uint8Array([0xc1, 0x01])).tag === 1").unwrap();
+        assert_eq!(tagged, JsValue::from(true));
+        assert!(eval("cbor.encode(() => 1)").is_err());
+        assert!(eval("cbor.decode(new Uint8Array([0x82]))").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use super::resolve::{self, Kind, ResolveError};
+use super::typescript;
+use crate::integrity::Integrity;
+use crate::snapshot::Snapshot;
+use boa_engine::object::{FunctionObjectBuilder, JsFunction, ObjectInitializer};
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsNativeError, JsObject, JsResult, JsString, JsValue,
+    NativeFunction, Source,
+};
+use boa_gc::{Finalize, Trace};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// State captured by every `require` function: one per module, so relative
+/// specifiers resolve against the requiring file, sharing a single cache.
+#[derive(Trace, Finalize, Clone)]
+struct Require {
+    #[unsafe_ignore_trace]
+    root: PathBuf,
+    #[unsafe_ignore_trace]
+    dir: PathBuf,
+    #[unsafe_ignore_trace]
+    snapshot: Option<Arc<Snapshot>>,
+    #[unsafe_ignore_trace]
+    integrity: Option<Arc<Integrity>>,
+    /// `module` objects by canonical path, filled before a module runs so
+    /// require cycles see the partially populated `exports`.
+    cache: JsObject,
+}
+
+/// Creates the `require` function for code living in `dir`.
+pub(crate) fn require_function(
+    root: &Path,
+    dir: &Path,
+    snapshot: Option<Arc<Snapshot>>,
+    integrity: Option<Arc<Integrity>>,
+    cache: JsObject,
+    context: &mut Context<'_>,
+) -> JsFunction {
+    let captures = Require {
+        root: root.to_path_buf(),
+        dir: dir.to_path_buf(),
+        snapshot,
+        integrity,
+        cache,
+    };
+    FunctionObjectBuilder::new(
+        context.realm(),
+        NativeFunction::from_copy_closure_with_captures(require, captures),
+    )
+    .name(js_string!("require"))
+    .length(1)
+    .build()
+}
+
+/// Creates an empty `{ exports: {} }` module object.
+pub(crate) fn module_object(context: &mut Context<'_>) -> JsObject {
+    let exports = ObjectInitializer::new(context).build();
+    ObjectInitializer::new(context)
+        .property(js_string!("exports"), exports, Attribute::all())
+        .build()
+}
+
+fn not_found(specifier: &str) -> boa_engine::JsError {
+    JsNativeError::typ()
+        .with_message(format!("Cannot find module '{}'", specifier))
+        .into()
+}
+
+/// Node-style lookup, see [`resolve`](super::resolve).
+fn resolve(req: &Require, specifier: &str) -> JsResult<PathBuf> {
+    match resolve::resolve(&req.root, &req.dir, specifier, Kind::Require) {
+        Ok(path) => Ok(path),
+        Err(ResolveError::Outside(path)) => {
+            log::warn!("Unauthorized attempt to require: {:?}", path);
+            Err(not_found(specifier))
+        }
+        Err(ResolveError::Package(msg)) => Err(JsNativeError::typ()
+            .with_message(format!("Cannot find module '{}': {}", specifier, msg))
+            .into()),
+        Err(ResolveError::NotFound) => Err(not_found(specifier)),
+    }
+}
+
+fn require(
+    _: &JsValue,
+    args: &[JsValue],
+    req: &Require,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let specifier = args
+        .get_or_undefined(0)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let path = resolve(req, &specifier)?;
+    let key = JsString::from(path.to_string_lossy().as_ref());
+
+    let cached = req.cache.get(key.clone(), context)?;
+    if let Some(module) = cached.as_object() {
+        return module.get(js_string!("exports"), context);
+    }
+
+    let module = module_object(context);
+    req.cache.set(key, module.clone(), false, context)?;
+
+    let source = typescript::read_js(
+        req.snapshot.as_deref(),
+        req.integrity.as_deref(),
+        &req.root,
+        &path,
+    )?;
+    if path.extension().map_or(false, |e| e == "json") {
+        let value: serde_json::Value = serde_json::from_str(&source).map_err(|e| {
+            JsNativeError::syntax().with_message(format!("Invalid json in '{}': {}", specifier, e))
+        })?;
+        let exports = JsValue::from_json(&value, context)?;
+        module.set(js_string!("exports"), exports.clone(), false, context)?;
+        return Ok(exports);
+    }
+
+    let wrapper = format!(
+        "(function (exports, require, module, __filename, __dirname) {{{}\n}})",
+        source
+    );
+    let function = context.eval(Source::from_bytes(&wrapper))?;
+    let function = function
+        .as_callable()
+        .ok_or_else(|| JsNativeError::typ().with_message("Invalid module wrapper"))?;
+
+    let dir = path.parent().unwrap_or(&req.root);
+    let child = require_function(
+        &req.root,
+        dir,
+        req.snapshot.clone(),
+        req.integrity.clone(),
+        req.cache.clone(),
+        context,
+    );
+    let exports = module.get(js_string!("exports"), context)?;
+    function.call(
+        &JsValue::undefined(),
+        &[
+            exports,
+            child.into(),
+            module.clone().into(),
+            JsString::from(path.to_string_lossy().as_ref()).into(),
+            JsString::from(dir.to_string_lossy().as_ref()).into(),
+        ],
+        context,
+    )?;
+    module.get(js_string!("exports"), context)
+}
@@ -0,0 +1,102 @@
+use super::web::dom_exception;
+use crate::permissions::Permissions;
+use crate::secrets::Secrets;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{js_string, Context, JsArgs, JsResult, JsString, JsValue, NativeFunction};
+use boa_gc::{Finalize, Trace};
+use std::rc::Rc;
+
+#[derive(Trace, Finalize, Clone)]
+struct Env {
+    #[unsafe_ignore_trace]
+    permissions: Rc<Permissions>,
+}
+
+fn var(args: &[JsValue], env: &Env, context: &mut Context<'_>) -> JsResult<Option<String>> {
+    let name = args.get_or_undefined(0).to_string(context)?;
+    env.permissions
+        .env_var(&name.to_std_string_escaped())
+        .map_err(|e| dom_exception(&e, "NotAllowedError", context))
+}
+
+/// `env.get(name)`, the value of the variable or `undefined`.
+fn get(_: &JsValue, args: &[JsValue], env: &Env, context: &mut Context<'_>) -> JsResult<JsValue> {
+    match var(args, env, context)? {
+        Some(v) => Ok(JsString::from(v.as_str()).into()),
+        None => Ok(JsValue::undefined()),
+    }
+}
+
+fn has(_: &JsValue, args: &[JsValue], env: &Env, context: &mut Context<'_>) -> JsResult<JsValue> {
+    Ok(var(args, env, context)?.is_some().into())
+}
+
+/// `env.toObject()`, the variables the script may read.
+fn to_object(
+    _: &JsValue,
+    _: &[JsValue],
+    env: &Env,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let mut object = ObjectInitializer::new(context);
+    for (name, value) in env.permissions.env_vars() {
+        object.property(
+            JsString::from(name.as_str()),
+            JsString::from(value.as_str()),
+            Attribute::all(),
+        );
+    }
+    Ok(object.build().into())
+}
+
+/// Installs the `env` object, reading the environment variables that
+/// `permissions` allow and throwing a `NotAllowedError` for the others.
+pub(crate) fn register(permissions: &Permissions, context: &mut Context<'_>) -> JsResult<()> {
+    let env = Env {
+        permissions: Rc::new(permissions.clone()),
+    };
+    type Method = fn(&JsValue, &[JsValue], &Env, &mut Context<'_>) -> JsResult<JsValue>;
+    let methods: [(&str, usize, Method); 3] =
+        [("get", 1, get), ("has", 1, has), ("toObject", 0, to_object)];
+    let mut object = ObjectInitializer::new(context);
+    for (name, length, f) in methods {
+        object.function(
+            NativeFunction::from_copy_closure_with_captures(f, env.clone()),
+            name,
+            length,
+        );
+    }
+    let object = object.build();
+    context.register_global_property(js_string!("env"), object, Attribute::all())
+}
+
+#[derive(Trace, Finalize, Clone)]
+struct AppSecrets {
+    #[unsafe_ignore_trace]
+    secrets: Rc<Secrets>,
+}
+
+/// `secret(name)`, the value of an allowed secret or `undefined`.
+fn secret(
+    _: &JsValue,
+    args: &[JsValue],
+    app: &AppSecrets,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = args.get_or_undefined(0).to_string(context)?;
+    match app.secrets.get(&name.to_std_string_escaped()) {
+        Ok(Some(v)) => Ok(JsString::from(v).into()),
+        Ok(None) => Ok(JsValue::undefined()),
+        Err(e) => Err(dom_exception(&e, "NotAllowedError", context)),
+    }
+}
+
+/// Installs `secret`, getting the secrets `secrets` allow.
+pub(crate) fn register_secret(secrets: &Secrets, context: &mut Context<'_>) -> JsResult<()> {
+    let app = AppSecrets {
+        secrets: Rc::new(secrets.clone()),
+    };
+    let f = NativeFunction::from_copy_closure_with_captures(secret, app);
+    context.register_global_callable("secret", 1, f)
+}
@@ -0,0 +1,443 @@
+use crate::rt;
+use boa_engine::builtins::promise::PromiseState;
+use boa_engine::job::{FutureJob, JobQueue, NativeJob};
+use boa_engine::object::builtins::JsPromise;
+use boa_engine::object::FunctionObjectBuilder;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    Context, JsArgs, JsError, JsNativeError, JsObject, JsResult, JsString, JsValue, NativeFunction,
+};
+use boa_gc::{Finalize, Trace};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+struct Timer {
+    callback: JsValue,
+    args: Vec<JsValue>,
+    repeat: Option<Duration>,
+}
+
+/// Pending `setTimeout`/`setInterval` callbacks, ordered by when they are due
+/// and then by id, so timers due at the same time fire in the order they were
+/// set.
+#[derive(Default)]
+struct Timers {
+    next_id: u32,
+    queue: BTreeMap<(Instant, u32), Timer>,
+    /// The interval currently running, and whether it cleared itself.
+    running: Option<(u32, bool)>,
+}
+
+impl Timers {
+    fn set(&mut self, timer: Timer, delay: Duration) -> u32 {
+        self.next_id += 1;
+        self.queue
+            .insert((Instant::now() + delay, self.next_id), timer);
+        self.next_id
+    }
+
+    fn clear(&mut self, id: u32) {
+        self.queue.retain(|(_, i), _| *i != id);
+        if let Some((running, cleared)) = &mut self.running {
+            if *running == id {
+                *cleared = true;
+            }
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.keys().next().map(|(due, _)| *due)
+    }
+}
+
+/// Per-request event loop of a script.
+///
+/// Follows the order of the HTML event loop: after the script and after every
+/// task, all promise reactions run before anything else. Tasks are the
+/// continuations of host futures, like a finished fetch, and expired timers,
+/// taken in the order they become ready. The loop returns once no work is
+/// left, or when the deadline passes or the client goes away, after which
+/// nothing runs anymore.
+pub(crate) struct EventLoop {
+    jobs: RefCell<VecDeque<NativeJob>>,
+    futures: RefCell<FuturesUnordered<FutureJob>>,
+    timers: Rc<RefCell<Timers>>,
+    deadline: Cell<Instant>,
+    timeout: Duration,
+    timed_out: Cell<bool>,
+    /// The promise `settle_now` waits for, the loop returns once it settles.
+    awaited: RefCell<Option<JsPromise>>,
+    /// Tells whether whoever waits for the script has gone away.
+    gone: RefCell<Option<Box<dyn Fn() -> bool>>>,
+    /// Called first when the script is cancelled.
+    on_cancel: RefCell<Option<JsObject>>,
+    cancelled: Cell<bool>,
+}
+
+/// How often a watched loop checks whether the client is still there while
+/// it waits for timers or host futures.
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Trace, Finalize, Clone)]
+struct TimerHandle {
+    #[unsafe_ignore_trace]
+    timers: Rc<RefCell<Timers>>,
+}
+
+fn delay(args: &[JsValue], context: &mut Context<'_>) -> JsResult<Duration> {
+    let ms = args.get_or_undefined(1).to_number(context)?;
+    Ok(Duration::from_millis(if ms.is_finite() && ms > 0.0 {
+        ms as u64
+    } else {
+        0
+    }))
+}
+
+fn add_timer(
+    args: &[JsValue],
+    repeat: bool,
+    handle: &TimerHandle,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let callback = args.get_or_undefined(0).clone();
+    if !callback.is_callable() {
+        return Err(JsNativeError::typ()
+            .with_message("Timer callback is not a function")
+            .into());
+    }
+    let delay = delay(args, context)?;
+    let timer = Timer {
+        callback,
+        args: args.iter().skip(2).cloned().collect(),
+        repeat: if repeat { Some(delay) } else { None },
+    };
+    let id = handle.timers.borrow_mut().set(timer, delay);
+    Ok(id.into())
+}
+
+fn set_timeout(
+    _: &JsValue,
+    args: &[JsValue],
+    handle: &TimerHandle,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    add_timer(args, false, handle, context)
+}
+
+fn set_interval(
+    _: &JsValue,
+    args: &[JsValue],
+    handle: &TimerHandle,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    add_timer(args, true, handle, context)
+}
+
+fn clear_timer(
+    _: &JsValue,
+    args: &[JsValue],
+    handle: &TimerHandle,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    handle.timers.borrow_mut().clear(id);
+    Ok(JsValue::undefined())
+}
+
+fn queue_microtask(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let callback = args.get_or_undefined(0).clone();
+    if !callback.is_callable() {
+        return Err(JsNativeError::typ()
+            .with_message("queueMicrotask callback is not a function")
+            .into());
+    }
+    let job = NativeJob::new(move |context| {
+        callback
+            .as_callable()
+            .expect("checked to be callable")
+            .call(&JsValue::undefined(), &[], context)
+    });
+    context.job_queue().enqueue_promise_job(job, context);
+    Ok(JsValue::undefined())
+}
+
+impl EventLoop {
+    /// A loop that stops running the script `timeout` from now.
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            jobs: RefCell::new(VecDeque::new()),
+            futures: RefCell::new(FuturesUnordered::new()),
+            timers: Rc::new(RefCell::new(Timers::default())),
+            deadline: Cell::new(Instant::now() + timeout),
+            timeout,
+            timed_out: Cell::new(false),
+            awaited: RefCell::new(None),
+            gone: RefCell::new(None),
+            on_cancel: RefCell::new(None),
+            cancelled: Cell::new(false),
+        }
+    }
+
+    /// Moves the deadline to `timeout` from now, for a loop that was made
+    /// before its script arrived or that runs one script after another.
+    pub(crate) fn restart(&self) {
+        self.deadline.set(Instant::now() + self.timeout);
+        self.timed_out.set(false);
+    }
+
+    /// Installs `setTimeout`, `setInterval`, their `clear*` counterparts and
+    /// `queueMicrotask`.
+    pub(crate) fn register(&self, context: &mut Context<'_>) -> JsResult<()> {
+        let handle = TimerHandle {
+            timers: self.timers.clone(),
+        };
+        type Callback =
+            fn(&JsValue, &[JsValue], &TimerHandle, &mut Context<'_>) -> JsResult<JsValue>;
+        let functions: [(&str, Callback); 4] = [
+            ("setTimeout", set_timeout),
+            ("setInterval", set_interval),
+            ("clearTimeout", clear_timer),
+            ("clearInterval", clear_timer),
+        ];
+        for (name, f) in functions {
+            let function = FunctionObjectBuilder::new(
+                context.realm(),
+                NativeFunction::from_copy_closure_with_captures(f, handle.clone()),
+            )
+            .name(JsString::from(name))
+            .length(1)
+            .build();
+            context.register_global_property(JsString::from(name), function, Attribute::all())?;
+        }
+        context.register_global_callable(
+            "queueMicrotask",
+            1,
+            NativeFunction::from_fn_ptr(queue_microtask),
+        )?;
+        Ok(())
+    }
+
+    /// Cancels the script once `gone` returns true: pending timers, promise
+    /// jobs and host futures like in-flight fetches are dropped, and nothing
+    /// runs anymore. `None` stops watching.
+    pub(crate) fn watch(&self, gone: Option<Box<dyn Fn() -> bool>>) {
+        *self.gone.borrow_mut() = gone;
+    }
+
+    /// Calls `f` when the script is cancelled, before its work is dropped, so
+    /// it can abort the signal of the request.
+    pub(crate) fn on_cancel(&self, f: JsObject) {
+        *self.on_cancel.borrow_mut() = Some(f);
+    }
+
+    pub(crate) fn cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+
+    /// Whether the loop stopped for good, because the deadline passed or the
+    /// script was cancelled.
+    pub(crate) fn stopped(&self) -> bool {
+        self.timed_out.get() || self.cancelled.get()
+    }
+
+    fn is_gone(&self) -> bool {
+        self.gone.borrow().as_ref().map_or(false, |gone| gone())
+    }
+
+    fn cancel(&self, context: &mut Context<'_>) {
+        log::info!("The client went away, cancelling the script");
+        self.cancelled.set(true);
+        let on_cancel = self.on_cancel.borrow_mut().take();
+        if let Some(f) = on_cancel {
+            if let Err(e) = f.call(&JsValue::undefined(), &[], context) {
+                log::error!("Uncaught error while cancelling: {}", e);
+            }
+            self.run_microtasks(context);
+        }
+        self.jobs.borrow_mut().clear();
+        self.timers.borrow_mut().queue.clear();
+        *self.futures.borrow_mut() = FuturesUnordered::new();
+    }
+
+    fn run_microtasks(&self, context: &mut Context<'_>) {
+        loop {
+            let job = self.jobs.borrow_mut().pop_front();
+            match job {
+                Some(job) => {
+                    if let Err(e) = job.call(context) {
+                        log::error!("Uncaught error in promise job: {}", e);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Runs the first timer if it is due. Intervals are set again afterwards
+    /// unless they cleared themselves.
+    fn run_timer(&self, now: Instant, context: &mut Context<'_>) -> bool {
+        let ((_, id), timer) = {
+            let mut timers = self.timers.borrow_mut();
+            match timers.queue.first_key_value() {
+                Some(((due, _), _)) if *due <= now => {}
+                _ => return false,
+            }
+            let entry = timers.queue.pop_first().expect("checked above");
+            timers.running = Some((entry.0 .1, false));
+            entry
+        };
+        if let Some(f) = timer.callback.as_callable() {
+            if let Err(e) = f.call(&JsValue::undefined(), &timer.args, context) {
+                log::error!("Uncaught error in timer: {}", e);
+            }
+        }
+        let mut timers = self.timers.borrow_mut();
+        let cleared = matches!(timers.running.take(), Some((_, true)));
+        if let (Some(every), false) = (timer.repeat, cleared) {
+            timers.queue.insert((Instant::now() + every, id), timer);
+        }
+        true
+    }
+
+    /// Settles `value` if it is a promise, running the loop until no work is
+    /// left, and returns what it resolved to. Anything else is returned as it
+    /// is.
+    pub(crate) fn settle(&self, value: JsValue, context: &mut Context<'_>) -> JsResult<JsValue> {
+        match value.as_object().cloned().map(JsPromise::from_object) {
+            Some(Ok(promise)) => {
+                context.run_jobs();
+                self.outcome(&promise)
+            }
+            _ => Ok(value),
+        }
+    }
+
+    /// Like [`settle`](Self::settle), but returns as soon as the promise has
+    /// settled. Timers and requests still pending keep for the next time the
+    /// loop runs.
+    pub(crate) fn settle_now(
+        &self,
+        value: JsValue,
+        context: &mut Context<'_>,
+    ) -> JsResult<JsValue> {
+        match value.as_object().cloned().map(JsPromise::from_object) {
+            Some(Ok(promise)) => {
+                *self.awaited.borrow_mut() = Some(promise.clone());
+                context.run_jobs();
+                *self.awaited.borrow_mut() = None;
+                self.outcome(&promise)
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn awaited_settled(&self) -> bool {
+        match &*self.awaited.borrow() {
+            Some(p) => !matches!(p.state(), Ok(PromiseState::Pending)),
+            None => false,
+        }
+    }
+
+    fn outcome(&self, promise: &JsPromise) -> JsResult<JsValue> {
+        match promise.state()? {
+            PromiseState::Fulfilled(v) => Ok(v),
+            PromiseState::Rejected(err) => Err(JsError::from_opaque(err)),
+            PromiseState::Pending if self.cancelled.get() => Err(JsNativeError::error()
+                .with_message("The client went away before the script finished")
+                .into()),
+            PromiseState::Pending if self.timed_out.get() => Err(JsNativeError::error()
+                .with_message(format!("Script did not finish within {:?}", self.timeout))
+                .into()),
+            PromiseState::Pending => Err(JsNativeError::error()
+                .with_message("Promise returned by the script never settled")
+                .into()),
+        }
+    }
+}
+
+impl JobQueue for EventLoop {
+    fn enqueue_promise_job(&self, job: NativeJob, _: &mut Context<'_>) {
+        self.jobs.borrow_mut().push_back(job);
+    }
+
+    fn enqueue_future_job(&self, future: FutureJob, _: &mut Context<'_>) {
+        self.futures.borrow_mut().push(future);
+    }
+
+    fn run_jobs(&self, context: &mut Context<'_>) {
+        loop {
+            if self.timed_out.get() || self.cancelled.get() {
+                return;
+            }
+            self.run_microtasks(context);
+            if self.awaited_settled() {
+                return;
+            }
+            if self.is_gone() {
+                self.cancel(context);
+                return;
+            }
+
+            let now = Instant::now();
+            if now >= self.deadline.get() {
+                log::warn!("Script did not finish within {:?}", self.timeout);
+                self.timed_out.set(true);
+                return;
+            }
+            if self.run_timer(now, context) {
+                continue;
+            }
+
+            let next_timer = self.timers.borrow().next_due();
+            let pending = !self.futures.borrow().is_empty();
+            let until = match (next_timer, pending) {
+                (None, false) => return,
+                (Some(due), _) => due.min(self.deadline.get()),
+                (None, true) => self.deadline.get(),
+            };
+            let mut wait = until.saturating_duration_since(now);
+            if self.gone.borrow().is_some() {
+                wait = wait.min(CANCEL_POLL);
+            }
+            if pending {
+                let mut futures = self.futures.borrow_mut();
+                let next = rt::block_on(rt::timeout(wait, futures.next()));
+                drop(futures);
+                if let Some(Some(job)) = next {
+                    self.jobs.borrow_mut().push_back(job);
+                }
+            } else {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// Returns a promise settled from `future` once it completes. `finish` turns
+/// its output into the value, and runs with the context again.
+pub(crate) fn promise<T, Fut, F>(future: Fut, finish: F, context: &mut Context<'_>) -> JsPromise
+where
+    T: 'static,
+    Fut: Future<Output = T> + 'static,
+    F: FnOnce(T, &mut Context<'_>) -> JsResult<JsValue> + 'static,
+{
+    let (promise, resolvers) = JsPromise::new_pending(context);
+    let job = async move {
+        let output = future.await;
+        NativeJob::new(move |context| match finish(output, context) {
+            Ok(v) => resolvers.resolve.call(&JsValue::undefined(), &[v], context),
+            Err(e) => {
+                let e = e.to_opaque(context);
+                resolvers.reject.call(&JsValue::undefined(), &[e], context)
+            }
+        })
+    };
+    context
+        .job_queue()
+        .enqueue_future_job(Box::pin(job), context);
+    promise
+}
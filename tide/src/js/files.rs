@@ -0,0 +1,115 @@
+use super::web::{from_bytes, to_bytes};
+use crate::files::Sandbox;
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsError, JsNativeError, JsResult, JsString, JsValue, NativeFunction,
+};
+use boa_gc::{Finalize, Trace};
+use rhai::{EvalAltResult, ImmutableString};
+
+#[derive(Trace, Finalize, Clone)]
+struct Files {
+    #[unsafe_ignore_trace]
+    sandbox: Sandbox,
+}
+
+fn file_error(e: Box<EvalAltResult>) -> JsError {
+    JsNativeError::error().with_message(e.to_string()).into()
+}
+
+fn name_arg(args: &[JsValue], context: &mut Context<'_>) -> JsResult<ImmutableString> {
+    let name = args.get_or_undefined(0).to_string(context)?;
+    Ok(name.to_std_string_escaped().into())
+}
+
+/// `files.read(name)`, the contents of a file as a `Uint8Array`.
+fn read(
+    _: &JsValue,
+    args: &[JsValue],
+    files: &Files,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = name_arg(args, context)?;
+    let bytes = files.sandbox.read_blob(name).map_err(file_error)?;
+    from_bytes(bytes, context)
+}
+
+/// `files.readText(name)`, the contents of a UTF-8 file as a string.
+fn read_text(
+    _: &JsValue,
+    args: &[JsValue],
+    files: &Files,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = name_arg(args, context)?;
+    let text = files.sandbox.read(name).map_err(file_error)?;
+    Ok(JsString::from(text.as_str()).into())
+}
+
+/// `files.write(name, data)`, with a string, an `ArrayBuffer` or a typed array.
+fn write(
+    _: &JsValue,
+    args: &[JsValue],
+    files: &Files,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = name_arg(args, context)?;
+    let bytes = to_bytes(args.get_or_undefined(1), context)?;
+    files.sandbox.write_blob(name, bytes).map_err(file_error)?;
+    Ok(JsValue::undefined())
+}
+
+fn append(
+    _: &JsValue,
+    args: &[JsValue],
+    files: &Files,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = name_arg(args, context)?;
+    let bytes = to_bytes(args.get_or_undefined(1), context)?;
+    files.sandbox.append_blob(name, bytes).map_err(file_error)?;
+    Ok(JsValue::undefined())
+}
+
+/// `files.list(dir)`, the sorted entries of a directory, directories end with `/`.
+fn list(
+    _: &JsValue,
+    args: &[JsValue],
+    files: &Files,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let dir = match args.get_or_undefined(0) {
+        v if v.is_undefined() => ImmutableString::new(),
+        _ => name_arg(args, context)?,
+    };
+    let names = files.sandbox.list(dir).map_err(file_error)?;
+    let names = names
+        .into_iter()
+        .map(|n| JsString::from(n.into_string().unwrap_or_default().as_str()).into());
+    Ok(JsArray::from_iter(names, context).into())
+}
+
+/// Installs the `files` object, giving scripts access to the data directory.
+pub(crate) fn register(sandbox: Sandbox, context: &mut Context<'_>) -> JsResult<()> {
+    let files = Files { sandbox };
+    type Method = fn(&JsValue, &[JsValue], &Files, &mut Context<'_>) -> JsResult<JsValue>;
+    let methods: [(&str, usize, Method); 5] = [
+        ("read", 1, read),
+        ("readText", 1, read_text),
+        ("write", 2, write),
+        ("append", 2, append),
+        ("list", 1, list),
+    ];
+    let mut object = ObjectInitializer::new(context);
+    for (name, length, f) in methods {
+        object.function(
+            NativeFunction::from_copy_closure_with_captures(f, files.clone()),
+            name,
+            length,
+        );
+    }
+    let object = object.build();
+    context.register_global_property(js_string!("files"), object, Attribute::all())
+}
@@ -0,0 +1,169 @@
+//! The files a script loads, for `rustvm bundle` to follow the module graph
+//! from the handlers of an app and leave out what none of them reach.
+//!
+//! Only literal specifiers can be followed: `import` and `export ... from`,
+//! `import("...")`, `require("...")` and `new Worker("...")`. Files loaded
+//! through computed names have to be bundled as entries of their own.
+
+use super::resolve::{self, Kind};
+use super::typescript::TranspileError;
+use super::worker;
+use std::path::{Path, PathBuf};
+use swc_common::sync::Lrc;
+use swc_common::{FileName, SourceMap};
+use swc_ecma_ast::{
+    CallExpr, Callee, ExportAll, Expr, ExprOrSpread, ImportDecl, Lit, NamedExport, NewExpr,
+};
+use swc_ecma_parser::{lexer::Lexer, EsConfig, Parser, StringInput, Syntax};
+use swc_ecma_visit::{Visit, VisitWith};
+
+/// A file named by a script, and how it is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Dependency {
+    Import(String),
+    Require(String),
+    Worker(String),
+}
+
+impl Dependency {
+    /// The name the script loads the file by.
+    pub(crate) fn specifier(&self) -> &str {
+        match self {
+            Self::Import(s) | Self::Require(s) | Self::Worker(s) => s,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Collect {
+    found: Vec<Dependency>,
+}
+
+/// The string literal passed as the first argument, if it is one.
+fn literal(args: &[ExprOrSpread]) -> Option<String> {
+    match args.first() {
+        Some(ExprOrSpread { spread: None, expr }) => match &**expr {
+            Expr::Lit(Lit::Str(s)) => Some(s.value.to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Visit for Collect {
+    fn visit_import_decl(&mut self, n: &ImportDecl) {
+        if !n.type_only {
+            self.found.push(Dependency::Import(n.src.value.to_string()));
+        }
+    }
+
+    fn visit_named_export(&mut self, n: &NamedExport) {
+        if let Some(src) = &n.src {
+            self.found.push(Dependency::Import(src.value.to_string()));
+        }
+    }
+
+    fn visit_export_all(&mut self, n: &ExportAll) {
+        self.found.push(Dependency::Import(n.src.value.to_string()));
+    }
+
+    fn visit_call_expr(&mut self, n: &CallExpr) {
+        let found = match &n.callee {
+            Callee::Import(_) => literal(&n.args).map(Dependency::Import),
+            Callee::Expr(e) => match &**e {
+                Expr::Ident(i) if &*i.sym == "require" => literal(&n.args).map(Dependency::Require),
+                _ => None,
+            },
+            Callee::Super(_) => None,
+        };
+        self.found.extend(found);
+        n.visit_children_with(self);
+    }
+
+    fn visit_new_expr(&mut self, n: &NewExpr) {
+        if let (Expr::Ident(i), Some(args)) = (&*n.callee, &n.args) {
+            if &*i.sym == "Worker" {
+                self.found.extend(literal(args).map(Dependency::Worker));
+            }
+        }
+        n.visit_children_with(self);
+    }
+}
+
+/// The files `source`, the JavaScript of the file at `path`, loads by name,
+/// in the order they appear.
+pub(crate) fn dependencies(path: &Path, source: &str) -> Result<Vec<Dependency>, TranspileError> {
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(FileName::Real(path.to_path_buf()), source.into());
+    let lexer = Lexer::new(
+        Syntax::Es(EsConfig::default()),
+        Default::default(),
+        StringInput::from(&*fm),
+        None,
+    );
+    let program = Parser::new_from(lexer).parse_program().map_err(|e| {
+        let loc = cm.lookup_char_pos(e.span().lo);
+        TranspileError {
+            file: path.display().to_string(),
+            line: loc.line,
+            column: loc.col_display + 1,
+            message: e.into_kind().msg().to_string(),
+        }
+    })?;
+    let mut collect = Collect::default();
+    program.visit_with(&mut collect);
+    Ok(collect.found)
+}
+
+/// The file `dependency` of a script in `dir` is, the way the script would
+/// load it. Remote imports are not files of the app and give `None`.
+pub(crate) fn resolve(
+    root: &Path,
+    dir: &Path,
+    dependency: &Dependency,
+) -> Option<Result<PathBuf, String>> {
+    let resolved = match dependency {
+        Dependency::Import(s) if s.starts_with("http://") || s.starts_with("https://") => {
+            return None
+        }
+        Dependency::Import(s) => resolve::resolve(root, dir, s, Kind::Import)
+            .map_err(|e| format!("Cannot find module '{}': {}", s, e)),
+        Dependency::Require(s) => resolve::resolve(root, dir, s, Kind::Require)
+            .map_err(|e| format!("Cannot find module '{}': {}", s, e)),
+        Dependency::Worker(url) => worker::resolve(root, dir, url),
+    };
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn literal_specifiers() {
+        let source = r#"
+            import a from "./a.js";
+            import { b } from "b";
+            export { c } from "./c.js";
+            export * from "./d.js";
+            const e = require("./e.cjs");
+            const f = import("./f.js");
+            const g = import(name);
+            const w = new Worker("./worker.js");
+        "#;
+        let found = dependencies(Path::new("main.mjs"), source).unwrap();
+        assert_eq!(
+            found,
+            [
+                Dependency::Import("./a.js".into()),
+                Dependency::Import("b".into()),
+                Dependency::Import("./c.js".into()),
+                Dependency::Import("./d.js".into()),
+                Dependency::Require("./e.cjs".into()),
+                Dependency::Import("./f.js".into()),
+                Dependency::Worker("./worker.js".into()),
+            ]
+        );
+        assert!(dependencies(Path::new("bad.js"), "import {").is_err());
+    }
+}
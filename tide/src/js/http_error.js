@@ -0,0 +1,22 @@
+// `HttpError(status, message, options)`, thrown or returned by a handler to
+// answer with `status` and `{ error: message }` rather than a 500.
+// `options.body` replaces that body and `options.headers` are added to it.
+(function () {
+  "use strict";
+
+  function HttpError(status, message, options = {}) {
+    if (!new.target) return new HttpError(status, message, options);
+    const error = Reflect.construct(Error, [message === undefined ? "" : String(message)], new.target);
+    Object.defineProperty(error, "status", { value: Number(status), enumerable: true });
+    if (options.body !== undefined) error.body = options.body;
+    if (options.headers !== undefined) error.headers = options.headers;
+    return error;
+  }
+
+  HttpError.prototype = Object.create(Error.prototype, {
+    constructor: { value: HttpError, writable: true, configurable: true },
+    name: { value: "HttpError", writable: true, configurable: true },
+  });
+  Object.setPrototypeOf(HttpError, Error);
+  globalThis.HttpError = HttpError;
+})();
@@ -0,0 +1,2256 @@
+mod backend;
+mod cbor;
+mod commonjs;
+mod env;
+mod event_loop;
+mod files;
+pub(crate) mod graph;
+mod modules;
+mod msgpack;
+mod pool;
+mod remote;
+mod repl;
+mod resolve;
+mod source_map;
+mod storage;
+mod testing;
+pub(crate) mod typescript;
+#[cfg(feature = "v8")]
+mod v8_backend;
+mod wasm;
+mod web;
+mod worker;
+
+pub use backend::JsEngine;
+pub(crate) use modules::is_module;
+pub use remote::RemoteImports;
+pub use repl::JsRepl;
+
+use crate::audit::{self, AuditLog};
+use crate::capabilities;
+use crate::error_page::HttpError;
+use crate::files::{DataQuota, Sandbox};
+use crate::integrity::{self, Integrity};
+use crate::kv::{AppStorage, KvStore, StorageQuota};
+use crate::permissions::Permissions;
+use crate::profile::Profiler;
+use crate::rt;
+use crate::secrets::Secrets;
+use crate::slow::{Phase, SlowScripts};
+use crate::snapshot::{self, Snapshot};
+use crate::stats::{Counters, DirReport, EngineStats};
+use crate::validate;
+use crate::{error_page, logging, resolve_file};
+use async_std::channel;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::script::Script;
+use boa_engine::{
+    js_string, Context, JsNativeError, JsObject, JsResult, JsString, JsValue, NativeFunction,
+    Source,
+};
+use futures::{StreamExt, TryStreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tide::log;
+use tide::{Body, Endpoint, Request, Response, Result, StatusCode};
+
+const HTTP_ERROR: &str = include_str!("http_error.js");
+
+/// Struct that implements an [`Endpoint`] and matches requests to JavaScript files.
+///
+/// Scripts see the same `ctx` object as rhai scripts and the value of their
+/// last expression becomes the json response body.
+pub struct JsDir {
+    prefix: String,
+    dir: PathBuf,
+    dev_mode: bool,
+    snapshot: Option<Arc<Snapshot>>,
+    integrity: Option<Arc<Integrity>>,
+    timeout: Duration,
+    data_dir: Option<PathBuf>,
+    data_quota: DataQuota,
+    storage: Option<AppStorage>,
+    storage_quota: StorageQuota,
+    remote: Option<Arc<remote::Remote>>,
+    permissions: Permissions,
+    secrets: Secrets,
+    audit: Option<Arc<AuditLog>>,
+    /// The schemas of `_routes.json` requests are checked against.
+    routes: validate::Routes,
+    engine: JsEngine,
+    pool_size: usize,
+    globals: Vec<(String, Global)>,
+    scope_hooks: Vec<ScopeHook>,
+    profiler: Option<Arc<Profiler>>,
+    slow: Option<Arc<SlowScripts>>,
+    counters: Arc<Counters>,
+    /// The sources of the modules, kept between requests.
+    sources: Arc<modules::Sources>,
+    /// Started by the first request, once the settings are final.
+    backend: OnceLock<Box<dyn backend::JsBackend>>,
+}
+
+impl JsDir {
+    ///```
+    /// use tide_rhai::JsDir;
+    /// let mut app = tide::new();
+    /// app.at("/js/*")
+    /// .get(JsDir::new("/js/*", "./examples/app/").unwrap());
+    ///```
+    pub fn new(prefix: &str, dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_owned().canonicalize()?;
+        let prefix = String::from(prefix);
+        let routes = validate::Routes::load(&dir)?;
+        Ok(Self {
+            prefix,
+            dir,
+            dev_mode: false,
+            snapshot: None,
+            integrity: None,
+            timeout: Duration::from_secs(30),
+            data_dir: None,
+            data_quota: DataQuota::default(),
+            storage: None,
+            storage_quota: StorageQuota::default(),
+            remote: None,
+            permissions: Permissions::default(),
+            secrets: Secrets::default(),
+            audit: None,
+            routes,
+            pool_size: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            engine: JsEngine::default(),
+            globals: Vec::new(),
+            scope_hooks: Vec::new(),
+            profiler: None,
+            slow: None,
+            counters: Arc::default(),
+            sources: Arc::default(),
+            backend: OnceLock::new(),
+        })
+    }
+
+    /// Answers failing scripts with an error page showing the error and its
+    /// position instead of an empty 500. Not meant for production.
+    pub fn with_dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    /// Runs scripts, and the modules they load, from a precompiled snapshot of
+    /// the directory. Files missing from it are still read from disk.
+    pub fn with_snapshot(mut self, snapshot: Arc<Snapshot>) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Refuses to run scripts and modules read from disk that do not match
+    /// the signed hashes of `integrity`, like
+    /// [`RhaiDir::with_integrity`](crate::RhaiDir::with_integrity).
+    pub fn with_integrity(mut self, integrity: Arc<Integrity>) -> Self {
+        self.integrity = Some(integrity);
+        self
+    }
+
+    /// Limits how long a script, including its timers and the requests it
+    /// waits on, may run before the request fails. Defaults to 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gives scripts a `files` object to read and write, as text or bytes,
+    /// inside `dir`, which is created if missing. See
+    /// [`RhaiDir::with_data_dir`](crate::RhaiDir::with_data_dir).
+    pub fn with_data_dir(mut self, dir: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        self.data_dir = Some(dir.as_ref().to_owned().canonicalize()?);
+        Ok(self)
+    }
+
+    /// Overrides the default size limits of the data directory.
+    pub fn with_data_quota(mut self, quota: DataQuota) -> Self {
+        self.data_quota = quota;
+        self
+    }
+
+    /// Gives scripts a `storage` object, also named `localStorage`, with
+    /// `getItem`, `setItem`, `removeItem`, `clear`, `key` and `length` as in
+    /// browsers. Items are kept in `store` under the name of the app, so
+    /// several apps can share a store without seeing each other's items.
+    ///```
+    /// use tide_rhai::{JsDir, KvStore};
+    /// let store = KvStore::open(std::env::temp_dir().join("tide_rhai_doc_storage")).unwrap();
+    /// let js = JsDir::new("/js/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_storage(&store, "app")
+    ///     .unwrap();
+    ///```
+    pub fn with_storage(mut self, store: &KvStore, app: &str) -> io::Result<Self> {
+        self.storage = Some(store.app(app, self.storage_quota)?);
+        Ok(self)
+    }
+
+    /// Overrides the default size limits of the storage, 1 MiB per item and
+    /// 5 MiB in total.
+    pub fn with_storage_quota(mut self, quota: StorageQuota) -> Self {
+        self.storage_quota = quota;
+        self.storage = self.storage.map(|s| s.with_quota(quota));
+        self
+    }
+
+    /// Lets modules import other modules by `https://` URL, as in
+    /// `import x from "https://esm.sh/lodash-es"`. Downloads are cached on
+    /// disk by the hash of their contents and, with a lock file, checked
+    /// against the hash they had when first imported. Relative imports of
+    /// such modules resolve against their URL.
+    pub fn with_remote_imports(mut self, config: RemoteImports) -> io::Result<Self> {
+        self.remote = Some(Arc::new(remote::Remote::new(config)?));
+        Ok(self)
+    }
+
+    /// Limits what `fetch`, `WebSocket`, `files` and `env` may reach, like
+    /// [`RhaiDir::with_permissions`](crate::RhaiDir::with_permissions).
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Lets scripts get the allowed `secrets` with `secret(name)`, like
+    /// [`RhaiDir::with_secrets`](crate::RhaiDir::with_secrets), throwing a
+    /// `NotAllowedError` for the others.
+    pub fn with_secrets(mut self, secrets: Secrets) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Records every `fetch` of the handlers and their workers, and every
+    /// module imported by URL, in `audit`, like
+    /// [`RhaiDir::with_audit`](crate::RhaiDir::with_audit).
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Runs handlers on `engine` rather than the default, Boa.
+    pub fn with_engine(mut self, engine: JsEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Sets how many contexts are kept set up ahead of requests, one thread
+    /// each. Requests arriving while all are busy get a context of their own
+    /// as before, 0 disables the pool. Defaults to the number of CPUs.
+    pub fn with_pool_size(mut self, size: usize) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// Gives every script, and the workers it starts, the global `name`,
+    /// made by `value` for each context since values cannot move between
+    /// them. For the functions and objects of the application embedding this
+    /// crate. Boa only, V8 handlers do not get them.
+    ///```
+    /// use tide_rhai::boa_engine::object::ObjectInitializer;
+    /// use tide_rhai::boa_engine::{JsArgs, NativeFunction};
+    /// use tide_rhai::JsDir;
+    /// let dir = JsDir::new("/js/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_global("myApi", |context| {
+    ///         let double = NativeFunction::from_fn_ptr(|_, args, context| {
+    ///             Ok((args.get_or_undefined(0).to_number(context)? * 2.0).into())
+    ///         });
+    ///         let api = ObjectInitializer::new(context)
+    ///             .function(double, "double", 1)
+    ///             .build();
+    ///         Ok(api.into())
+    ///     });
+    ///```
+    pub fn with_global(
+        mut self,
+        name: &str,
+        value: impl Fn(&mut Context<'_>) -> JsResult<JsValue> + Send + Sync + 'static,
+    ) -> Self {
+        self.globals.push((name.to_string(), Arc::new(value)));
+        self
+    }
+
+    /// Runs `hook` before every script, to give it globals that depend on the
+    /// request, like the tenant or feature flags. Scripts run on threads of
+    /// their own, so values cross over as json.
+    ///```
+    /// use tide_rhai::JsDir;
+    /// let dir = JsDir::new("/js/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_scope(|req, scope| {
+    ///         let tenant = req.header("x-tenant").map(|h| h.as_str().to_string());
+    ///         scope.set("tenant", tenant).set("beta", true);
+    ///     });
+    ///```
+    pub fn with_scope(
+        mut self,
+        hook: impl Fn(&tide::http::Request, &mut JsScope) + Send + Sync + 'static,
+    ) -> Self {
+        self.scope_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Has `profiler` time every script by the path of its request. Only the
+    /// whole run is timed, the engines do not report function calls.
+    pub fn with_profiler(mut self, profiler: Arc<Profiler>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Records how long every TypeScript handler takes to transpile and every
+    /// handler to run in `slow`, like
+    /// [`RhaiDir::with_slow_scripts`](crate::RhaiDir::with_slow_scripts).
+    pub fn with_slow_scripts(mut self, slow: Arc<SlowScripts>) -> Self {
+        self.slow = Some(slow);
+        self
+    }
+
+    /// How many scripts ran, how long compiling and running them and setting
+    /// up their contexts took, and how busy the pool is.
+    pub fn stats(&self) -> EngineStats {
+        let mut stats = self.counters.stats("js", &self.prefix);
+        stats.cache_entries = wasm::cached_modules() + self.sources.len();
+        stats.websockets = web::open_sockets();
+        if let Some(backend) = self.backend.get() {
+            backend.stats(&mut stats);
+        }
+        stats
+    }
+
+    /// Whether `path` is a module the directory keeps and reads again when
+    /// it changes, so that an edit of it needs no reload.
+    pub fn picks_up_edits(&self, path: &Path) -> bool {
+        self.sources.contains(path)
+    }
+
+    /// The [`stats`](JsDir::stats), the scripts that ran and the last errors
+    /// they failed with, like [`RhaiDir::report`](crate::RhaiDir::report).
+    pub fn report(&self) -> DirReport {
+        self.counters.report(self.stats())
+    }
+
+    /// Calls `onStart` of the `_app.js` module of the directory, `.mjs` or
+    /// `.ts` also work, for work to do once before serving, like warming
+    /// caches or migrating data. Does nothing without the module or the
+    /// export. Fails if the hook throws, which should stop the server from
+    /// starting.
+    ///```no_run
+    /// # tide_rhai::rt::block_on(async {
+    /// use tide_rhai::JsDir;
+    /// let js = JsDir::new("/js/*", "./examples/app/").unwrap();
+    /// js.start().await.expect("the app could not start");
+    /// # });
+    ///```
+    pub async fn start(&self) -> io::Result<()> {
+        self.run_lifecycle("onStart").await
+    }
+
+    /// Calls `onShutdown` of the `_app.js` module once the server has
+    /// stopped. The module is loaded again for it, in a context of its own
+    /// like every script, so state to hand over goes through storage.
+    pub async fn shutdown(&self) -> io::Result<()> {
+        self.run_lifecycle("onShutdown").await
+    }
+
+    async fn run_lifecycle(&self, hook: &'static str) -> io::Result<()> {
+        let in_snapshot = |p: &Path| {
+            self.snapshot
+                .as_ref()
+                .map_or(false, |s| s.get(&self.dir, p).is_some())
+        };
+        let module = APP_MODULES.iter().find(|n| {
+            let p = self.dir.join(n);
+            p.exists() || in_snapshot(&p)
+        });
+        let Some(module) = module else {
+            return Ok(());
+        };
+        let source = format!(
+            r#"import * as app from "./{module}";
+            export default async () => {{
+                if (typeof app.{hook} === "function") await app.{hook}();
+                return null;
+            }};"#
+        );
+        // Never read, the imports of the source resolve from its directory.
+        let path = self.dir.join("_app");
+        let runtime = self.runtime();
+        let (reply, replies) = channel::bounded(1);
+        rt::spawn_blocking(move || run(&runtime, &path, &source, &Incoming::lifecycle(), reply));
+        match replies.recv().await {
+            Ok(Ok(_)) => {
+                log::info!("Ran {} of {}", hook, module);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} of {} failed: {}", hook, module, e),
+            )),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The script thread stopped",
+            )),
+        }
+    }
+
+    fn runtime(&self) -> Runtime {
+        Runtime {
+            root: self.dir.clone(),
+            snapshot: self.snapshot.clone(),
+            integrity: self.integrity.clone(),
+            timeout: self.timeout,
+            files: self.data_dir.clone().map(|d| {
+                Sandbox::new(d, self.data_quota).with_permissions(self.permissions.clone())
+            }),
+            storage: self.storage.clone(),
+            remote: self.remote.clone(),
+            permissions: self.permissions.clone(),
+            secrets: self.secrets.clone(),
+            audit: self.audit.clone(),
+            globals: self.globals.clone(),
+            counters: self.counters.clone(),
+            sources: self.sources.clone(),
+        }
+    }
+
+    fn script_error(&self, title: &str, path: &Path, detail: &str) -> Response {
+        log::error!("{}: {}", title, crate::redact(detail));
+        let script = snapshot::key(&self.dir, path).unwrap_or_default();
+        let (message, stack) = detail.split_once('\n').unwrap_or((detail, ""));
+        let stack = Some(stack.trim()).filter(|s| !s.is_empty());
+        self.counters
+            .failed(&script, &format!("{}: {}", title, message), stack);
+        if self.dev_mode {
+            error_page::render(title, path, detail)
+        } else {
+            Response::new(StatusCode::InternalServerError)
+        }
+    }
+}
+
+/// The request a script answers: the `ctx` object of scripts that evaluate to
+/// their response body, and the `Request` handed to `fetch` handlers.
+struct Incoming {
+    ctx: crate::Context,
+    http: web::HttpRequest,
+    /// Set by the hooks of [`JsDir::with_scope`].
+    globals: Vec<(String, Value)>,
+}
+
+impl Incoming {
+    async fn from_request<State>(req: &mut Request<State>) -> Self
+    where
+        State: Clone + Send + Sync + 'static,
+    {
+        let mut headers = HashMap::new();
+        let mut pairs = Vec::new();
+        for (n, v) in req.iter() {
+            headers.insert(String::from(n.as_str()), String::from(v.as_str()));
+            pairs.push((String::from(n.as_str()), String::from(v.as_str())));
+        }
+        let method = req.method();
+        let url = req.url().to_string();
+        let body = match req.body_bytes().await {
+            Ok(b) => b,
+            Err(e) => {
+                log::warn!("error reading body {:?}", e);
+                Vec::new()
+            }
+        };
+        Incoming {
+            ctx: crate::Context::from_parts(headers, method, &body),
+            http: web::HttpRequest {
+                method: method.to_string(),
+                url,
+                headers: pairs,
+                body,
+            },
+            globals: Vec::new(),
+        }
+    }
+
+    /// What lifecycle hooks run with, not being part of a request.
+    fn lifecycle() -> Self {
+        Incoming {
+            ctx: crate::Context {
+                data: Value::Null,
+                headers: HashMap::new(),
+            },
+            http: web::HttpRequest {
+                method: "GET".into(),
+                url: "http://localhost/".into(),
+                headers: Vec::new(),
+                body: Vec::new(),
+            },
+            globals: Vec::new(),
+        }
+    }
+}
+
+/// Values an application gives the script answering one request, see
+/// [`JsDir::with_scope`].
+#[derive(Debug, Default)]
+pub struct JsScope {
+    globals: Vec<(String, Value)>,
+}
+
+impl JsScope {
+    /// Makes `value`, converted to json, the global `name` of the script.
+    pub fn set(&mut self, name: &str, value: impl Serialize) -> &mut Self {
+        match serde_json::to_value(value) {
+            Ok(v) => self.globals.push((name.to_string(), v)),
+            Err(e) => log::warn!("Cannot give scripts {}: {}", name, e),
+        }
+        self
+    }
+}
+
+/// What a script answered with.
+#[derive(Debug)]
+enum Reply {
+    Json(Value),
+    /// An `HttpError` the script threw or returned.
+    Error(HttpError),
+    Http(web::HttpResponse),
+    /// A response whose body follows in chunks, while the script produces it.
+    Stream(web::HttpResponse, channel::Receiver<Vec<u8>>),
+}
+
+/// How many chunks of a streamed body may wait for the client before the
+/// script is held up.
+const STREAM_BUFFER: usize = 16;
+
+/// Whether `value` follows the handler convention of service workers, an
+/// object with a `fetch(request)` method.
+fn is_fetch_handler(value: &JsValue, context: &mut Context<'_>) -> JsResult<bool> {
+    match value.as_object() {
+        Some(o) if !o.is_callable() => Ok(o.get(js_string!("fetch"), context)?.is_callable()),
+        _ => Ok(false),
+    }
+}
+
+/// What running a script needs from its handler, moved onto the thread that
+/// runs it.
+#[derive(Clone)]
+struct Runtime {
+    root: PathBuf,
+    snapshot: Option<Arc<Snapshot>>,
+    integrity: Option<Arc<Integrity>>,
+    /// How long the script and its event loop may run.
+    timeout: Duration,
+    files: Option<Sandbox>,
+    storage: Option<AppStorage>,
+    remote: Option<Arc<remote::Remote>>,
+    permissions: Permissions,
+    secrets: Secrets,
+    audit: Option<Arc<AuditLog>>,
+    globals: Vec<(String, Global)>,
+    counters: Arc<Counters>,
+    sources: Arc<modules::Sources>,
+}
+
+/// Makes a global of the application, see [`JsDir::with_global`].
+type Global = Arc<dyn Fn(&mut Context<'_>) -> JsResult<JsValue> + Send + Sync>;
+
+type ScopeHook = Arc<dyn Fn(&tide::http::Request, &mut JsScope) + Send + Sync>;
+
+/// Where [`JsDir::start`] and [`JsDir::shutdown`] look for the lifecycle
+/// hooks. These are never served.
+pub(crate) const APP_MODULES: [&str; 3] = ["_app.js", "_app.mjs", "_app.ts"];
+
+/// Joins the arguments of a logging call the way `console.log` does.
+fn format_args(args: &[JsValue]) -> String {
+    let mut parts = Vec::with_capacity(args.len());
+    for a in args {
+        match a.as_string() {
+            Some(s) => parts.push(s.to_std_string_escaped()),
+            None => parts.push(a.display().to_string()),
+        }
+    }
+    parts.join(" ")
+}
+
+fn log(_: &JsValue, args: &[JsValue], _: &mut Context<'_>) -> JsResult<JsValue> {
+    logging::log(format_args(args));
+    Ok(JsValue::undefined())
+}
+
+fn info(_: &JsValue, args: &[JsValue], _: &mut Context<'_>) -> JsResult<JsValue> {
+    logging::info(format_args(args));
+    Ok(JsValue::undefined())
+}
+
+fn warn(_: &JsValue, args: &[JsValue], _: &mut Context<'_>) -> JsResult<JsValue> {
+    logging::warn(format_args(args));
+    Ok(JsValue::undefined())
+}
+
+fn error(_: &JsValue, args: &[JsValue], _: &mut Context<'_>) -> JsResult<JsValue> {
+    logging::error(format_args(args));
+    Ok(JsValue::undefined())
+}
+
+fn register(
+    script: &web::ScriptName,
+    permissions: &Permissions,
+    context: &mut Context<'_>,
+) -> JsResult<JsObject> {
+    context.register_global_callable("log", 0, NativeFunction::from_fn_ptr(log))?;
+    context.register_global_callable("info", 0, NativeFunction::from_fn_ptr(info))?;
+    context.register_global_callable("warn", 0, NativeFunction::from_fn_ptr(warn))?;
+    context.register_global_callable("error", 0, NativeFunction::from_fn_ptr(error))?;
+    env::register(permissions, context)?;
+    web::register(script, permissions, context)
+}
+
+/// Evaluates `source` with `ctx` in scope and answers with its result as json.
+///
+/// Plain scripts answer with their completion value, or with `module.exports`
+/// if they assign it. Files using `import`/`export` run as modules and answer
+/// with their default export, and may use top-level `await`. Either is called
+/// with `ctx` if it is a function. Promises are awaited before answering,
+/// and timers still pending after that run until the deadline.
+///
+/// An object with a `fetch` method instead gets called with a `Request` and
+/// `ctx`, and answers with the `Response` it returns. If its body is a
+/// `ReadableStream`, the head is sent right away and the chunks follow as the
+/// script keeps running.
+///
+/// The answer, or the error, is sent to `reply`.
+fn run(
+    runtime: &Runtime,
+    path: &Path,
+    source: &str,
+    incoming: &Incoming,
+    reply: channel::Sender<std::result::Result<Reply, String>>,
+) {
+    let name = script_name(&runtime.root, path);
+    let grant = match capabilities::declared(path, &name, source) {
+        Ok(grant) => grant,
+        Err(e) => {
+            let _ = reply.try_send(Err(e));
+            return;
+        }
+    };
+    let origin = runtime.audit.clone().map(|log| {
+        let id = incoming
+            .http
+            .headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("x-request-id"));
+        audit::Origin::new(log, &name, id.map(|(_, v)| v.as_str()))
+    });
+    let loader = modules::AppLoader::new(runtime.root.clone(), runtime.snapshot.clone())
+        .with_integrity(runtime.integrity.clone())
+        .with_sources(runtime.sources.clone())
+        .with_remote(runtime.remote.clone());
+    let jobs = event_loop::EventLoop::new(runtime.timeout);
+    let mut context = match Context::builder()
+        .module_loader(&loader)
+        .job_queue(&jobs)
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = reply.try_send(Err(e.to_string()));
+            return;
+        }
+    };
+    let script = web::ScriptName::new(&name);
+    let natives = match prepare(runtime, &script, &jobs, &mut context) {
+        Ok(n) => n,
+        Err(e) => {
+            let _ = reply.try_send(Err(e.to_string()));
+            return;
+        }
+    };
+    let prepared = Prepared {
+        loader: &loader,
+        jobs: &jobs,
+        natives,
+    };
+    capabilities::run(grant, || {
+        audit::run(origin, || {
+            execute(
+                runtime,
+                &prepared,
+                &mut context,
+                path,
+                source,
+                incoming,
+                reply,
+            )
+        })
+    });
+}
+
+/// Sets up a context for code that answers no request, like the REPL and
+/// tests, with `require` and workers relative to `dir`, and hands it to `f`.
+fn standalone<R>(
+    runtime: &Runtime,
+    name: &str,
+    dir: &Path,
+    f: impl FnOnce(&mut Context<'_>, &Prepared<'_>) -> JsResult<R>,
+) -> JsResult<R> {
+    let loader = modules::AppLoader::new(runtime.root.clone(), runtime.snapshot.clone())
+        .with_integrity(runtime.integrity.clone())
+        .with_sources(runtime.sources.clone())
+        .with_remote(runtime.remote.clone());
+    let jobs = event_loop::EventLoop::new(runtime.timeout);
+    let mut context = Context::builder()
+        .module_loader(&loader)
+        .job_queue(&jobs)
+        .build()?;
+    let script = web::ScriptName::new(name);
+    let natives = prepare(runtime, &script, &jobs, &mut context)?;
+    worker::register(runtime, dir, &natives, &mut context)?;
+    wasm::register(runtime, dir, &natives, &mut context)?;
+    let cache = ObjectInitializer::new(&mut context).build();
+    let require = commonjs::require_function(
+        &runtime.root,
+        dir,
+        runtime.snapshot.clone(),
+        runtime.integrity.clone(),
+        cache,
+        &mut context,
+    );
+    context.register_global_property(js_string!("require"), require, Attribute::all())?;
+    let prepared = Prepared {
+        loader: &loader,
+        jobs: &jobs,
+        natives,
+    };
+    f(&mut context, &prepared)
+}
+
+/// `path` relative to the app directory, as console output names it.
+fn script_name(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A context with everything installed that does not depend on the script.
+struct Prepared<'a> {
+    loader: &'a modules::AppLoader,
+    jobs: &'a event_loop::EventLoop,
+    /// The natives returned by [`web::register`].
+    natives: JsObject,
+}
+
+/// Installs the globals every script of the app gets: the web APIs, timers,
+/// `env`, `secret`, `msgpack`, `cbor`, `files` and `storage`. Returns the natives of
+/// the web APIs.
+fn prepare(
+    runtime: &Runtime,
+    script: &web::ScriptName,
+    jobs: &event_loop::EventLoop,
+    context: &mut Context<'_>,
+) -> JsResult<JsObject> {
+    let started = Instant::now();
+    let natives = register(script, &runtime.permissions, context)?;
+    env::register_secret(&runtime.secrets, context)?;
+    context.eval(Source::from_bytes(HTTP_ERROR))?;
+    jobs.register(context)?;
+    msgpack::register(context)?;
+    cbor::register(context)?;
+    if let Some(sandbox) = runtime.files.clone() {
+        files::register(sandbox, context)?;
+    }
+    if let Some(app) = runtime.storage.clone() {
+        storage::register(app, context)?;
+    }
+    for (name, value) in &runtime.globals {
+        let value = value(context)?;
+        context.register_global_property(JsString::from(name.as_str()), value, Attribute::all())?;
+    }
+    runtime.counters.set_up(started.elapsed());
+    Ok(natives)
+}
+
+/// Runs the script at `path` in a context set up by [`prepare`], see [`run`].
+fn execute(
+    runtime: &Runtime,
+    prepared: &Prepared<'_>,
+    context: &mut Context<'_>,
+    path: &Path,
+    source: &str,
+    incoming: &Incoming,
+    reply: channel::Sender<std::result::Result<Reply, String>>,
+) {
+    let root = runtime.root.as_path();
+    let (snapshot, integrity) = (runtime.snapshot.clone(), runtime.integrity.clone());
+    let (loader, jobs, natives) = (prepared.loader, prepared.jobs, &prepared.natives);
+    let waiting = reply.clone();
+    jobs.watch(Some(Box::new(move || waiting.is_closed())));
+    let res = (|| -> JsResult<Option<Reply>> {
+        worker::register(runtime, path.parent().unwrap_or(root), natives, context)?;
+        wasm::register(runtime, path.parent().unwrap_or(root), natives, context)?;
+        let dyn_ctx = crate::to_js_value(&incoming.ctx, context)?;
+        context.register_global_property(js_string!("ctx"), dyn_ctx.clone(), Attribute::all())?;
+        for (name, value) in &incoming.globals {
+            let value = JsValue::from_json(value, context)?;
+            context.register_global_property(
+                JsString::from(name.as_str()),
+                value,
+                Attribute::all(),
+            )?;
+        }
+
+        let cache = ObjectInitializer::new(context).build();
+        let dir = path.parent().unwrap_or(root);
+        let require = commonjs::require_function(root, dir, snapshot, integrity, cache, context);
+        context.register_global_property(js_string!("require"), require, Attribute::all())?;
+        let module = commonjs::module_object(context);
+        let exports = module.get(js_string!("exports"), context)?;
+        context.register_global_property(js_string!("module"), module.clone(), Attribute::all())?;
+        context.register_global_property(
+            js_string!("exports"),
+            exports.clone(),
+            Attribute::all(),
+        )?;
+
+        let mut result = if modules::is_module(path, source) {
+            modules::evaluate(loader, path, source, context)?
+        } else {
+            let started = Instant::now();
+            let script = Script::parse(Source::from_bytes(source), None, context)?;
+            runtime.counters.compiled(started.elapsed());
+            let result = script.evaluate(context)?;
+            context.run_jobs();
+            result
+        };
+        if result.is_undefined() {
+            let assigned = module.get(js_string!("exports"), context)?;
+            if !JsValue::same_value(&assigned, &exports) {
+                result = assigned;
+            }
+        }
+        if is_fetch_handler(&result, context)? {
+            let parts = web::serve(natives, &result, &incoming.http, dyn_ctx, context)?;
+            let cancel = natives.get(js_string!("cancelRequest"), context)?;
+            if let Some(cancel) = cancel.as_callable() {
+                jobs.on_cancel(cancel.clone());
+            }
+            let parts = jobs.settle_now(parts, context)?;
+            let head = web::response_parts(&parts, context)?;
+            match web::body_stream(&parts, context)? {
+                Some(stream) => {
+                    let (chunks, body) = channel::bounded(STREAM_BUFFER);
+                    if reply.try_send(Ok(Reply::Stream(head, body))).is_ok() {
+                        // From now on the client is there as long as it reads the body.
+                        let reading = chunks.clone();
+                        jobs.watch(Some(Box::new(move || reading.is_closed())));
+                        match pump(&stream, jobs, &chunks, context) {
+                            Err(e) if !jobs.cancelled() => {
+                                log::error!("Streaming the response of {:?} failed: {}", path, e)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                None => {
+                    let _ = reply.try_send(Ok(Reply::Http(head)));
+                }
+            }
+            jobs.watch(None);
+            // Work the handler left behind still runs until the deadline.
+            context.run_jobs();
+            return Ok(None);
+        }
+        if let Some(f) = result.as_callable().cloned() {
+            result = f.call(&JsValue::undefined(), &[dyn_ctx], context)?;
+        }
+        let result = jobs.settle(result, context)?;
+        if let Some(e) = http_error(natives, &result, context)? {
+            return Ok(Some(Reply::Error(e)));
+        }
+        web::to_json(natives, &result, context).map(|v| Some(Reply::Json(v)))
+    })();
+    let res = match res {
+        Ok(Some(r)) => Ok(r),
+        Ok(None) => return,
+        Err(e) => {
+            let thrown = e.as_opaque().map(|v| http_error(natives, v, context));
+            match thrown {
+                Some(Ok(Some(h))) => Ok(Reply::Error(h)),
+                Some(Err(e)) => Err(e.to_string()),
+                _ => Err(e.to_string()),
+            }
+        }
+    };
+    // Nobody is waiting anymore if the client went away.
+    let _ = reply.try_send(res);
+}
+
+/// The error `value` stands for if it is an `HttpError`, see http_error.js.
+fn http_error(
+    natives: &JsObject,
+    value: &JsValue,
+    context: &mut Context<'_>,
+) -> JsResult<Option<HttpError>> {
+    let Some(object) = value.as_object() else {
+        return Ok(None);
+    };
+    let name = object.get(js_string!("name"), context)?;
+    if name
+        .as_string()
+        .map(|n| n.to_std_string_escaped())
+        .as_deref()
+        != Some("HttpError")
+    {
+        return Ok(None);
+    }
+    let status = object
+        .get(js_string!("status"), context)?
+        .to_number(context)?;
+    let status = if status.fract() == 0.0 {
+        status as i64
+    } else {
+        0
+    };
+    let message = object
+        .get(js_string!("message"), context)?
+        .to_string(context)?;
+    let mut error = HttpError::new(status, message.to_std_string_escaped())
+        .map_err(|e| JsNativeError::range().with_message(e))?;
+    let body = object.get(js_string!("body"), context)?;
+    if !body.is_undefined() {
+        error.body = Some(web::to_json(natives, &body, context)?);
+    }
+    let headers = object.get(js_string!("headers"), context)?;
+    if !headers.is_undefined() {
+        let Value::Object(headers) = web::to_json(natives, &headers, context)? else {
+            return Err(JsNativeError::typ()
+                .with_message("The headers of an HttpError are an object")
+                .into());
+        };
+        for (n, v) in headers {
+            let v = match v {
+                Value::String(s) => s,
+                v => v.to_string(),
+            };
+            error.headers.push((n, v));
+        }
+    }
+    Ok(Some(error))
+}
+
+/// A response with the status and headers a script answered with.
+fn response_head(head: &web::HttpResponse) -> std::result::Result<Response, String> {
+    let status = StatusCode::try_from(head.status)
+        .map_err(|_| format!("Invalid response status {}", head.status))?;
+    let mut res = Response::new(status);
+    for (n, v) in &head.headers {
+        res.append_header(n.as_str(), v.as_str());
+    }
+    Ok(res)
+}
+
+/// Sends the chunks of a streamed body to `chunks` as the script produces
+/// them. Once the client is gone the stream is cancelled.
+fn pump(
+    stream: &web::BodyStream,
+    jobs: &event_loop::EventLoop,
+    chunks: &channel::Sender<Vec<u8>>,
+    context: &mut Context<'_>,
+) -> JsResult<()> {
+    loop {
+        let next = stream.next(context)?;
+        let chunk = jobs.settle_now(next, context)?;
+        if chunk.is_null() {
+            return Ok(());
+        }
+        let bytes = web::to_bytes(&chunk, context)?;
+        if rt::block_on(chunks.send(bytes)).is_err() {
+            let cancelled = stream.cancel(context)?;
+            jobs.settle_now(cancelled, context)?;
+            return Ok(());
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State> Endpoint<State> for JsDir
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn call(&self, mut req: Request<State>) -> Result {
+        let file_path = match resolve_file(&self.prefix, &self.dir, &req) {
+            Some(p) => p,
+            None => return Ok(Response::new(StatusCode::Forbidden)),
+        };
+        let path: &Path = file_path.as_ref();
+        if APP_MODULES.iter().any(|name| path == self.dir.join(name))
+            || path == self.dir.join(validate::ROUTES)
+            || crate::testing::is_test(path)
+        {
+            return Ok(Response::new(StatusCode::NotFound));
+        }
+        let precompiled = self.snapshot.as_ref().and_then(|s| s.get(&self.dir, path));
+        let read = match precompiled {
+            Some(entry) => Ok(entry.code.to_string()),
+            None => integrity::read(self.integrity.as_deref(), path),
+        };
+        let script = snapshot::key(&self.dir, path).unwrap_or_default();
+        match read {
+            Ok(s) => {
+                let (source, map) = if let Some(entry) = precompiled {
+                    (s, entry.map.clone())
+                } else if typescript::is_typescript(path) {
+                    let started = Instant::now();
+                    let transpiled = typescript::transpile(path, &s);
+                    let took = started.elapsed();
+                    self.counters.compiled(took);
+                    if let Some(slow) = &self.slow {
+                        slow.record(&script, Phase::Compile, took);
+                    }
+                    match transpiled {
+                        Ok(js) => (js.code.to_string(), Some(js.map)),
+                        Err(e) => {
+                            return Ok(self.script_error("TypeScript error", path, &e.to_string()))
+                        }
+                    }
+                } else {
+                    (s, None)
+                };
+                let mut scope = JsScope::default();
+                for hook in &self.scope_hooks {
+                    hook(req.as_ref(), &mut scope);
+                }
+                let mut incoming = Incoming::from_request(&mut req).await;
+                let http = &incoming.http;
+                let checked = self
+                    .routes
+                    .check(&script, &http.method, req.url(), &http.body);
+                if let Some(invalid) = checked {
+                    return Ok(invalid);
+                }
+                incoming.globals = scope.globals;
+                let started = Instant::now();
+                let (reply, replies) = channel::bounded(1);
+                let job = backend::Job {
+                    path: path.to_path_buf(),
+                    source,
+                    incoming,
+                    reply,
+                };
+                self.backend
+                    .get_or_init(|| self.engine.start(self.runtime(), self.pool_size))
+                    .run(job);
+                let res = replies
+                    .recv()
+                    .await
+                    .unwrap_or_else(|_| Err("The script thread stopped".to_string()));
+                self.counters.ran(&script, started.elapsed(), res.is_err());
+                if let Some(slow) = &self.slow {
+                    slow.record(&script, Phase::Exec, started.elapsed());
+                }
+                if let Some(profiler) = &self.profiler {
+                    profiler.record(req.url().path(), started.elapsed());
+                }
+                match res {
+                    Ok(Reply::Json(v)) => Ok(Response::builder(StatusCode::Ok).body(v).build()),
+                    Ok(Reply::Error(e)) => Ok(e.response()),
+                    Ok(Reply::Http(r)) => match response_head(&r) {
+                        Ok(mut res) => {
+                            res.set_body(r.body);
+                            Ok(res)
+                        }
+                        Err(e) => Ok(self.script_error("Script execution error", path, &e)),
+                    },
+                    Ok(Reply::Stream(r, chunks)) => match response_head(&r) {
+                        Ok(mut res) => {
+                            let reader = chunks.map(Ok::<_, io::Error>).into_async_read();
+                            res.set_body(Body::from_reader(reader, None));
+                            Ok(res)
+                        }
+                        Err(e) => Ok(self.script_error("Script execution error", path, &e)),
+                    },
+                    Err(e) => {
+                        let e = match map {
+                            Some(map) => source_map::translate(&map, path, &e),
+                            None => e,
+                        };
+                        Ok(self.script_error("Script execution error", path, &e))
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::warn!("File not found: {:?}", &file_path);
+                Ok(Response::new(StatusCode::NotFound))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn runtime(root: impl AsRef<Path>) -> Runtime {
+        Runtime {
+            root: root.as_ref().to_path_buf(),
+            snapshot: None,
+            integrity: None,
+            timeout: Duration::from_secs(5),
+            files: None,
+            storage: None,
+            remote: None,
+            permissions: Permissions::default(),
+            secrets: Secrets::default(),
+            audit: None,
+            globals: Vec::new(),
+            counters: Arc::default(),
+            sources: Arc::default(),
+        }
+    }
+
+    fn incoming() -> Incoming {
+        Incoming {
+            ctx: crate::Context {
+                data: json!({"name": "js"}),
+                headers: HashMap::new(),
+            },
+            http: web::HttpRequest {
+                method: "POST".into(),
+                url: "http://localhost/js/a.js?q=1".into(),
+                headers: vec![("x-test".into(), "yes".into())],
+                body: b"ping".to_vec(),
+            },
+            globals: Vec::new(),
+        }
+    }
+
+    /// Runs the script on a thread of its own, as `call` does.
+    fn respond(runtime: &Runtime, path: &Path, source: &str) -> std::result::Result<Reply, String> {
+        let (runtime, path, source) = (runtime.clone(), path.to_path_buf(), source.to_string());
+        let (reply, replies) = channel::bounded(1);
+        std::thread::spawn(move || run(&runtime, &path, &source, &incoming(), reply));
+        rt::block_on(replies.recv()).unwrap()
+    }
+
+    fn eval(runtime: &Runtime, path: &Path, source: &str) -> std::result::Result<Value, String> {
+        match respond(runtime, path, source)? {
+            Reply::Json(v) => Ok(v),
+            r => Err(format!("Unexpected response {:?}", r)),
+        }
+    }
+
+    #[test]
+    fn completion_value() {
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), "({hello: ctx.data.name})").unwrap(),
+            json!({"hello": "js"})
+        );
+    }
+
+    #[test]
+    fn undefined_is_null() {
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), "let a = 1;").unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn module_imports() {
+        let root = std::env::temp_dir().join("tide-rhai-js-modules");
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(
+            root.join("a.js"),
+            "import { b } from './b.js';\nexport const a = () => 'a' + b();",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("b.js"),
+            "import { a } from './a.js';\nexport const b = () => 'b';",
+        )
+        .unwrap();
+        let main =
+            "import { a } from './a.js';\nexport default (ctx) => ({ v: a(), n: ctx.data.name });";
+        assert_eq!(
+            eval(&runtime(&root), &root.join("main.js"), main).unwrap(),
+            json!({"v": "ab", "n": "js"})
+        );
+        assert!(eval(&runtime(&root), &root.join("main.js"), "import '../x.js';").is_err());
+    }
+
+    #[test]
+    fn edited_modules() {
+        let root = std::env::temp_dir().join("tide-rhai-js-edited");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(
+            root.join("math.ts"),
+            "export const times = (n: number) => n * 2;",
+        )
+        .unwrap();
+        std::fs::write(root.join("name.js"), "export const name = 'ann';").unwrap();
+        let main = "import { times } from './math.ts';\nimport { name } from './name.js';\nexport default () => `${name} ${times(21)}`;";
+        let runtime = runtime(&root);
+        assert_eq!(
+            eval(&runtime, &root.join("main.js"), main).unwrap(),
+            json!("ann 42")
+        );
+        let name = runtime.sources.files.lock().unwrap()[&root.join("name.js")]
+            .1
+            .clone();
+
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(
+            root.join("math.ts"),
+            "export const times = (n: number) => n * 3;",
+        )
+        .unwrap();
+        assert!(runtime.sources.contains(&root.join("math.ts")));
+        assert_eq!(
+            eval(&runtime, &root.join("main.js"), main).unwrap(),
+            json!("ann 63")
+        );
+        let kept = runtime.sources.files.lock().unwrap()[&root.join("name.js")]
+            .1
+            .clone();
+        assert!(Arc::ptr_eq(&kept, &name));
+    }
+
+    #[test]
+    fn require_modules() {
+        let root = std::env::temp_dir().join("tide-rhai-js-commonjs");
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(
+            root.join("lib/index.js"),
+            "const c = require('./config');\nexports.name = () => c.name;",
+        )
+        .unwrap();
+        std::fs::write(root.join("lib/config.json"), r#"{"name": "cjs"}"#).unwrap();
+        let main = "const lib = require('lib');\nmodule.exports = (ctx) => ({ v: lib.name(), same: require('/lib') === lib });";
+        assert_eq!(
+            eval(&runtime(&root), &root.join("main.js"), main).unwrap(),
+            json!({"v": "cjs", "same": true})
+        );
+        assert!(eval(&runtime(&root), &root.join("main.js"), "require('../x')").is_err());
+    }
+
+    #[test]
+    fn node_modules() {
+        let root = std::env::temp_dir().join("tide-rhai-js-node-modules");
+        let _ = std::fs::remove_dir_all(&root);
+        let package = root.join("node_modules/greet");
+        std::fs::create_dir_all(package.join("lib")).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(
+            package.join("package.json"),
+            r#"{"name": "greet", "exports": {".": {"import": "./lib/index.mjs", "require": "./lib/index.cjs"}}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            package.join("lib/index.mjs"),
+            "import { name } from './name.mjs';\nexport const greet = () => `hello ${name}`;",
+        )
+        .unwrap();
+        std::fs::write(package.join("lib/name.mjs"), "export const name = 'esm';").unwrap();
+        std::fs::write(
+            package.join("lib/index.cjs"),
+            "exports.greet = () => 'hello cjs';",
+        )
+        .unwrap();
+        let main = "import { greet } from 'greet';\nexport default () => greet();";
+        assert_eq!(
+            eval(&runtime(&root), &root.join("main.js"), main).unwrap(),
+            json!("hello esm")
+        );
+        let main = "module.exports = () => require('greet').greet();";
+        assert_eq!(
+            eval(&runtime(&root), &root.join("main.js"), main).unwrap(),
+            json!("hello cjs")
+        );
+    }
+
+    #[test]
+    fn remote_imports() {
+        let root = std::env::temp_dir().join("tide-rhai-js-remote-imports");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let remote = remote::Remote::new(RemoteImports {
+            cache_dir: root.join("cache"),
+            lock_file: Some(root.join("deps.lock")),
+            offline: true,
+        })
+        .unwrap();
+        let url = |s| url::Url::parse(s).unwrap();
+        remote
+            .store(
+                &url("https://example.com/mod.js"),
+                &url("https://example.com/v1/mod.js"),
+                b"import { name } from './name.js';\nexport const greet = () => `hello ${name}`;",
+            )
+            .unwrap();
+        remote
+            .store(
+                &url("https://example.com/v1/name.js"),
+                &url("https://example.com/v1/name.js"),
+                b"export const name = 'remote';",
+            )
+            .unwrap();
+        let mut rt = runtime(&root);
+        rt.remote = Some(Arc::new(remote));
+        let main =
+            "import { greet } from 'https://example.com/mod.js';\nexport default () => greet();";
+        assert_eq!(
+            eval(&rt, &root.join("main.js"), main).unwrap(),
+            json!("hello remote")
+        );
+        let lock = std::fs::read_to_string(root.join("deps.lock")).unwrap();
+        assert!(lock.contains("https://example.com/v1/name.js"));
+        let missing = "import x from 'https://example.com/missing.js';\nexport default x;";
+        let e = eval(&rt, &root.join("main.js"), missing).unwrap_err();
+        assert!(e.contains("not cached"), "{}", e);
+        let e = eval(&runtime(&root), &root.join("main.js"), main).unwrap_err();
+        assert!(e.contains("disabled"), "{}", e);
+    }
+
+    #[test]
+    fn awaits_promises() {
+        let handler = "(async (ctx) => ({ v: await Promise.resolve(ctx.data.name) }))";
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), handler).unwrap(),
+            json!({"v": "js"})
+        );
+        let top_level = "const v = await Promise.resolve(1);\nexport default { v };";
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.mjs"), top_level).unwrap(),
+            json!({"v": 1})
+        );
+        let rejected = "Promise.reject(new Error('nope'))";
+        assert!(eval(&runtime("."), Path::new("a.js"), rejected).is_err());
+    }
+
+    #[test]
+    fn task_order() {
+        let script = r#"
+            const order = [];
+            setTimeout(() => order.push("timeout 2"), 2);
+            setTimeout(() => order.push("timeout 0"), 0);
+            const id = setInterval(() => {
+                order.push("interval");
+                if (order.filter((o) => o == "interval").length == 2) clearInterval(id);
+            }, 1);
+            Promise.resolve().then(() => order.push("promise"));
+            queueMicrotask(() => order.push("microtask"));
+            order.push("script");
+            new Promise((resolve) => setTimeout(() => resolve(order), 20))
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!([
+                "script",
+                "promise",
+                "microtask",
+                "timeout 0",
+                "interval",
+                "timeout 2",
+                "interval"
+            ])
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        let mut rt = runtime(".");
+        rt.timeout = Duration::from_millis(50);
+        let never = "new Promise(() => setInterval(() => {}, 10))";
+        let err = eval(&rt, Path::new("a.js"), never).unwrap_err();
+        assert!(err.contains("did not finish"));
+    }
+
+    #[test]
+    fn response_objects() {
+        let script = r#"
+            (async () => {
+                const headers = new Headers({ "X-One": "1" });
+                headers.append("x-one", "2");
+                const res = Response.json({ n: 1 }, { status: 201, headers });
+                const copy = res.clone();
+                const streamed = [];
+                for await (const chunk of new Response("abc").body) streamed.push(chunk.length);
+                return {
+                    one: res.headers.get("X-ONE"),
+                    type: res.headers.get("content-type"),
+                    ok: res.ok,
+                    status: res.status,
+                    body: await res.json(),
+                    text: await copy.text(),
+                    used: res.bodyUsed,
+                    streamed,
+                };
+            })()
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({
+                "one": "1, 2",
+                "type": "application/json",
+                "ok": true,
+                "status": 201,
+                "body": {"n": 1},
+                "text": "{\"n\":1}",
+                "used": true,
+                "streamed": [3]
+            })
+        );
+    }
+
+    #[test]
+    fn text_encoding() {
+        let script = r#"
+            const bytes = new TextEncoder().encode("a€");
+            const decoder = new TextDecoder();
+            const streamed = decoder.decode(bytes.subarray(0, 2), { stream: true })
+                + decoder.decode(bytes.subarray(2));
+            let fatal = null;
+            try {
+                new TextDecoder("utf-8", { fatal: true }).decode(new Uint8Array([0xff]));
+            } catch (e) {
+                fatal = e.name;
+            }
+            const into = new Uint8Array(2);
+            ({
+                bytes: [...bytes],
+                streamed,
+                lossy: new TextDecoder().decode(new Uint8Array([0x61, 0xff]).buffer),
+                utf16: new TextDecoder("utf-16le").decode(new Uint16Array([0x68, 0x69])),
+                fatal,
+                into: new TextEncoder().encodeInto("a€", into),
+            })
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({
+                "bytes": [0x61, 0xe2, 0x82, 0xac],
+                "streamed": "a€",
+                "lossy": "a\u{fffd}",
+                "utf16": "hi",
+                "fatal": "TypeError",
+                "into": {"read": 1, "written": 1}
+            })
+        );
+    }
+
+    #[test]
+    fn web_crypto() {
+        let script = r#"
+            (async () => {
+                const hex = (b) => [...new Uint8Array(b)].map((x) => x.toString(16).padStart(2, "0")).join("");
+                const enc = new TextEncoder();
+                const key = await crypto.subtle.importKey(
+                    "raw", enc.encode("Jefe"), { name: "HMAC", hash: "SHA-256" }, false, ["sign", "verify"]
+                );
+                const data = enc.encode("what do ya want for nothing?");
+                const signature = await crypto.subtle.sign("HMAC", key, data);
+                let denied = null;
+                try {
+                    await crypto.subtle.exportKey("raw", key);
+                } catch (e) {
+                    denied = e.name;
+                }
+                return {
+                    digest: hex(await crypto.subtle.digest("SHA-1", enc.encode("abc"))),
+                    signature: hex(signature),
+                    valid: await crypto.subtle.verify("HMAC", key, signature, data),
+                    forged: await crypto.subtle.verify("HMAC", key, signature, enc.encode("x")),
+                    length: key.algorithm.length,
+                    denied,
+                    uuid: /^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$/.test(crypto.randomUUID()),
+                    random: crypto.getRandomValues(new Uint32Array(4)).length,
+                };
+            })()
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({
+                "digest": "a9993e364706816aba3e25717850c26c9cd0d89d",
+                "signature": "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+                "valid": true,
+                "forged": false,
+                "length": 32,
+                "denied": "InvalidAccessError",
+                "uuid": true,
+                "random": 4
+            })
+        );
+    }
+
+    #[test]
+    fn structured_clone() {
+        let script = r#"
+            const a = { date: new Date(0), map: new Map([[1, [2n]]]), bytes: new Uint8Array([1, 2]) };
+            a.self = a;
+            const b = structuredClone(a);
+            let refused = null;
+            try {
+                structuredClone({ f() {} });
+            } catch (e) {
+                refused = e.name;
+            }
+            ({
+                copied: b !== a && b.self === b && b.date.getTime() === 0 && b.date !== a.date,
+                map: b.map.get(1)[0] === 2n,
+                bytes: b.bytes instanceof Uint8Array && b.bytes.buffer !== a.bytes.buffer,
+                refused,
+            })
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({"copied": true, "map": true, "bytes": true, "refused": "DataCloneError"})
+        );
+    }
+
+    #[test]
+    fn transferred_values() {
+        let script = r#"
+            ({
+                map: new Map([["a", 1], [2, undefined]]),
+                set: new Set([1, 1, 2]),
+                bytes: new Uint8Array([255]),
+                big: [1n, 2n ** 64n],
+                date: new Date(0),
+                error: new RangeError("no"),
+                skipped: [undefined, () => 1, NaN],
+                url: new URL("https://example.com/a"),
+            })
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({
+                "map": {"a": 1},
+                "set": [1, 2],
+                "bytes": [255],
+                "big": [1, "18446744073709551616"],
+                "date": "1970-01-01T00:00:00.000Z",
+                "error": {"name": "RangeError", "message": "no"},
+                "skipped": [null, null, null],
+                "url": "https://example.com/a"
+            })
+        );
+        let cyclic = eval(&runtime("."), Path::new("a.js"), "const o = {}; o.o = o; o");
+        assert!(cyclic.unwrap_err().contains("cyclic"));
+    }
+
+    #[test]
+    fn data_files() {
+        let data = std::env::temp_dir().join("tide_rhai_js_files");
+        let _ = std::fs::remove_dir_all(&data);
+        std::fs::create_dir_all(&data).unwrap();
+        let mut rt = runtime(".");
+        rt.files = Some(Sandbox::new(
+            data.canonicalize().unwrap(),
+            DataQuota::default(),
+        ));
+        let script = r#"
+            files.write("a.txt", "hé");
+            files.append("a.txt", new Uint8Array([0x21]));
+            files.write("b.bin", new Uint8Array([0, 255]));
+            ({
+                text: files.readText("a.txt"),
+                bytes: [...files.read("b.bin")],
+                list: files.list(""),
+            })
+        "#;
+        assert_eq!(
+            eval(&rt, Path::new("a.js"), script).unwrap(),
+            json!({"text": "hé!", "bytes": [0, 255], "list": ["a.txt", "b.bin"]})
+        );
+    }
+
+    #[test]
+    fn urls() {
+        let script = r#"
+            const url = new URL("../b/c?x=1&y=a+b#top", "https://user@example.com:8080/a/index.html");
+            url.searchParams.append("z", "ä &");
+            url.searchParams.delete("x");
+            const origin = url.origin;
+            url.port = "443";
+            const params = new URLSearchParams({ q: "1 2" });
+            params.sort();
+            ({
+                href: url.href,
+                origin,
+                y: url.searchParams.get("y"),
+                path: url.pathname,
+                params: params.toString(),
+                invalid: URL.canParse("no scheme"),
+                cleared: (() => { url.search = ""; return url.searchParams.size; })(),
+            })
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({
+                "href": "https://user@example.com/b/c?y=a+b&z=%C3%A4+%26#top",
+                "origin": "https://example.com:8080",
+                "y": "a b",
+                "path": "/b/c",
+                "params": "q=1+2",
+                "invalid": false,
+                "cleared": 0
+            })
+        );
+    }
+
+    #[test]
+    fn aborted_fetch() {
+        let script = r#"
+            const controller = new AbortController();
+            controller.abort();
+            fetch("http://localhost:1/", { signal: controller.signal }).catch((e) => e.name)
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!("AbortError")
+        );
+    }
+
+    #[test]
+    fn fetch_handler() {
+        let handler = r#"
+            export default {
+                async fetch(request, ctx) {
+                    const body = await request.text();
+                    return new Response(`${request.method} ${body} ${ctx.data.name}`, {
+                        status: 201,
+                        headers: { "x-echo": request.headers.get("x-test") },
+                    });
+                },
+            };
+        "#;
+        match respond(&runtime("."), Path::new("a.mjs"), handler).unwrap() {
+            Reply::Http(r) => {
+                assert_eq!(r.status, 201);
+                assert!(r.headers.contains(&("x-echo".into(), "yes".into())));
+                assert_eq!(r.body, b"POST ping js");
+            }
+            r => panic!("expected a response, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn streamed_response() {
+        let handler = r#"
+            export default {
+                fetch(request) {
+                    const upper = new TransformStream({
+                        transform(chunk, controller) {
+                            controller.enqueue(new TextEncoder().encode(new TextDecoder().decode(chunk).toUpperCase()));
+                        },
+                    });
+                    let n = 0;
+                    const body = new ReadableStream({
+                        pull(controller) {
+                            return new Promise((resolve) => setTimeout(resolve, 1)).then(() => {
+                                if (n++ < 3) controller.enqueue(new TextEncoder().encode(`chunk ${n};`));
+                                else controller.close();
+                            });
+                        },
+                    });
+                    return new Response(body.pipeThrough(upper), { headers: { "content-type": "text/plain" } });
+                },
+            };
+        "#;
+        match respond(&runtime("."), Path::new("a.mjs"), handler).unwrap() {
+            Reply::Stream(r, chunks) => {
+                assert_eq!(r.status, 200);
+                let chunks: Vec<Vec<u8>> = rt::block_on(chunks.collect());
+                assert_eq!(chunks.len(), 3);
+                assert_eq!(chunks.concat(), b"CHUNK 1;CHUNK 2;CHUNK 3;");
+            }
+            r => panic!("expected a streamed response, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let script = r#"
+            (async () => {
+                const written = [];
+                const sink = new WritableStream({ write(chunk) { written.push(chunk); } });
+                await ReadableStream.from(["a", "b"]).pipeTo(sink);
+                const [left, right] = new Response("tee").body.tee();
+                const read = async (s) => new TextDecoder().decode(await new Response(s).arrayBuffer());
+                const request = new Request("http://localhost/", { method: "POST", body: "abc" });
+                const chunks = [];
+                for await (const chunk of request.body) chunks.push(chunk.length);
+                return {
+                    written,
+                    tee: [await read(left), await read(right)],
+                    chunks,
+                    used: request.bodyUsed,
+                };
+            })()
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), script).unwrap(),
+            json!({"written": ["a", "b"], "tee": ["tee", "tee"], "chunks": [3], "used": true})
+        );
+    }
+
+    #[test]
+    fn client_gone() {
+        let data = std::env::temp_dir().join("tide_rhai_js_client_gone");
+        let _ = std::fs::remove_dir_all(&data);
+        std::fs::create_dir_all(&data).unwrap();
+        let mut rt = runtime(".");
+        rt.files = Some(Sandbox::new(
+            data.canonicalize().unwrap(),
+            DataQuota::default(),
+        ));
+        let handler = r#"
+            export default {
+                async fetch(request) {
+                    request.signal.addEventListener("abort", () => files.write("aborted", request.signal.reason.name));
+                    setTimeout(() => files.write("late", "timer ran"), 1000);
+                    await new Promise((resolve) => setTimeout(resolve, 10000));
+                    return new Response("too late");
+                },
+            };
+        "#;
+        let (reply, replies) = channel::bounded(1);
+        let started = std::time::Instant::now();
+        let thread =
+            std::thread::spawn(move || run(&rt, Path::new("a.mjs"), handler, &incoming(), reply));
+        std::thread::sleep(Duration::from_millis(300));
+        drop(replies);
+        thread.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            std::fs::read_to_string(data.join("aborted")).unwrap(),
+            "AbortError"
+        );
+        assert!(!data.join("late").exists());
+    }
+
+    #[test]
+    fn pool() {
+        let pool = pool::Pool::new(runtime("."), 1);
+        let submit = |source: &str| {
+            let (reply, replies) = channel::bounded(1);
+            let mut job = backend::Job {
+                path: PathBuf::from("a.js"),
+                source: source.to_string(),
+                incoming: incoming(),
+                reply,
+            };
+            let started = std::time::Instant::now();
+            // Waits for the thread to set up its next context.
+            while let Err(j) = pool.try_run(job) {
+                assert!(started.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(5));
+                job = j;
+            }
+            match rt::block_on(replies.recv()).unwrap().unwrap() {
+                Reply::Json(v) => v,
+                r => panic!("Unexpected response {:?}", r),
+            }
+        };
+        assert_eq!(
+            submit("globalThis.leak = ctx.data.name; typeof fetch"),
+            json!("function")
+        );
+        assert_eq!(submit("typeof leak"), json!("undefined"));
+    }
+
+    #[cfg(feature = "v8")]
+    #[test]
+    fn v8_engine() {
+        let backend = JsEngine::V8.start(runtime("."), 0);
+        let submit = |source: &str| {
+            let (reply, replies) = channel::bounded(1);
+            backend.run(backend::Job {
+                path: PathBuf::from("a.js"),
+                source: source.to_string(),
+                incoming: incoming(),
+                reply,
+            });
+            rt::block_on(replies.recv()).unwrap()
+        };
+        let handler = "(async (ctx) => ({ v: ctx.data.name, big: 2n ** 64n + '' }))";
+        match submit(handler).unwrap() {
+            Reply::Json(v) => assert_eq!(v, json!({"v": "js", "big": "18446744073709551616"})),
+            r => panic!("Unexpected response {:?}", r),
+        }
+        let e = submit("throw new RangeError('nope')").unwrap_err();
+        assert!(e.contains("RangeError: nope"), "{}", e);
+    }
+
+    #[test]
+    fn permissions() {
+        use crate::permissions::Allow;
+        std::env::set_var("TIDE_RHAI_JS_APP_NAME", "demo");
+        let mut rt = runtime(".");
+        rt.permissions = Permissions {
+            net: Allow::Only(vec!["localhost:1".into()]),
+            env: Allow::Only(vec!["TIDE_RHAI_JS_APP_*".into()]),
+            ..Permissions::none()
+        };
+        let script = r#"
+            export default async () => {
+                const denied = async (f) => {
+                    try {
+                        await f();
+                        return "allowed";
+                    } catch (e) {
+                        return e.name;
+                    }
+                };
+                return {
+                    name: env.get("TIDE_RHAI_JS_APP_NAME"),
+                    has: env.has("TIDE_RHAI_JS_APP_OTHER"),
+                    all: Object.keys(env.toObject()),
+                    path: await denied(() => env.get("PATH")),
+                    fetch: await denied(() => fetch("http://example.com/")),
+                    socket: await denied(() => new WebSocket("ws://example.com/")),
+                    local: await denied(() => fetch("http://localhost:1/")),
+                };
+            };
+        "#;
+        assert_eq!(
+            eval(&rt, Path::new("a.mjs"), script).unwrap(),
+            json!({
+                "name": "demo",
+                "has": false,
+                "all": ["TIDE_RHAI_JS_APP_NAME"],
+                "path": "NotAllowedError",
+                "fetch": "NotAllowedError",
+                "socket": "NotAllowedError",
+                "local": "TypeError",
+            })
+        );
+    }
+
+    #[test]
+    fn websocket() {
+        use futures::{SinkExt, StreamExt};
+        let listener = rt::block_on(async_std::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Echoes every message, upper-casing text.
+        rt::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(message)) = ws.next().await {
+                use async_tungstenite::tungstenite::Message;
+                let echo = match message {
+                    Message::Text(t) => Message::Text(t.to_uppercase()),
+                    Message::Close(_) => continue,
+                    m => m,
+                };
+                ws.send(echo).await.unwrap();
+            }
+        });
+        let script = format!(
+            r#"
+            new Promise((resolve) => {{
+                const events = [];
+                const ws = new WebSocket("ws://127.0.0.1:{}/chat");
+                ws.onopen = () => {{
+                    events.push("open");
+                    ws.send("hello");
+                    ws.send(new Uint8Array([1, 2, 3]));
+                }};
+                ws.addEventListener("message", (e) => {{
+                    events.push(typeof e.data === "string" ? e.data : [...new Uint8Array(e.data)]);
+                    if (events.length === 3) ws.close(1000, "done");
+                }});
+                ws.onclose = (e) => resolve({{ events, code: e.code, clean: e.wasClean, state: ws.readyState }});
+            }})
+        "#,
+            port
+        );
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), &script).unwrap(),
+            json!({"events": ["open", "HELLO", [1, 2, 3]], "code": 1000, "clean": true, "state": 3})
+        );
+
+        let refused = r#"
+            new Promise((resolve) => {
+                const events = [];
+                const ws = new WebSocket("ws://localhost:1/");
+                ws.onerror = () => events.push("error");
+                ws.onclose = (e) => resolve([...events, e.code]);
+            })
+        "#;
+        assert_eq!(
+            eval(&runtime("."), Path::new("a.js"), refused).unwrap(),
+            json!(["error", 1006])
+        );
+    }
+
+    #[test]
+    fn http_errors() {
+        let rt = runtime(".");
+        let error = |source: &str| match respond(&rt, Path::new("a.mjs"), source) {
+            Ok(Reply::Error(e)) => Ok(e),
+            other => Err(format!("{:?}", other)),
+        };
+        let thrown = error(r#"throw HttpError(404, "no such order")"#).unwrap();
+        assert_eq!(
+            (thrown.status, thrown.body()),
+            (404, json!({"error": "no such order"}))
+        );
+
+        let handler = r#"
+            export default async () => {
+                await null;
+                throw new HttpError(429, "slow down", { headers: { "retry-after": 5 } });
+            };
+        "#;
+        let limited = error(handler).unwrap();
+        assert_eq!(
+            limited.headers,
+            [("retry-after".to_string(), "5".to_string())]
+        );
+
+        let returned = error(r#"HttpError(422, "bad", { body: { field: "name" } })"#).unwrap();
+        assert_eq!(
+            (returned.status, returned.body()),
+            (422, json!({"field": "name"}))
+        );
+
+        let fetch = r#"export default { fetch() { throw new HttpError(401, "who are you"); } };"#;
+        assert_eq!(error(fetch).unwrap().status, 401);
+
+        assert!(error(r#"throw new HttpError(200, "fine")"#).is_err());
+        assert_eq!(
+            eval(
+                &rt,
+                Path::new("a.js"),
+                r#"const e = new HttpError(404); [e instanceof Error, e.name, e.status]"#
+            )
+            .unwrap(),
+            json!([true, "HttpError", 404])
+        );
+    }
+
+    #[test]
+    fn scope() {
+        let mut incoming = incoming();
+        let mut scope = JsScope::default();
+        scope
+            .set("tenant", "acme")
+            .set("flags", json!({"beta": true}));
+        incoming.globals = scope.globals;
+        let (reply, replies) = channel::bounded(1);
+        let script = "({ tenant, beta: flags.beta })";
+        std::thread::spawn(move || run(&runtime("."), Path::new("a.js"), script, &incoming, reply));
+        match rt::block_on(replies.recv()).unwrap() {
+            Ok(Reply::Json(v)) => assert_eq!(v, json!({"tenant": "acme", "beta": true})),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        let root = std::env::temp_dir().join("tide_rhai_js_lifecycle");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("_app.mjs"),
+            r#"
+            export async function onStart() {
+                await null;
+                console.log("warming up");
+            }
+            export function onShutdown() {
+                throw new Error("connection already closed");
+            }
+        "#,
+        )
+        .unwrap();
+        let js = JsDir::new("/js/*", &root).unwrap();
+        rt::block_on(js.start()).unwrap();
+        let e = rt::block_on(js.shutdown()).unwrap_err();
+        assert!(
+            e.to_string().contains("onShutdown of _app.mjs failed"),
+            "{}",
+            e
+        );
+        assert!(e.to_string().contains("connection already closed"), "{}", e);
+
+        let empty = std::env::temp_dir().join("tide_rhai_js_lifecycle_none");
+        std::fs::create_dir_all(&empty).unwrap();
+        assert!(rt::block_on(JsDir::new("/js/*", &empty).unwrap().start()).is_ok());
+    }
+
+    #[test]
+    fn globals() {
+        use boa_engine::JsArgs;
+        let mut rt = runtime(".");
+        let double: Global = Arc::new(|context| {
+            let double = NativeFunction::from_fn_ptr(|_, args, context| {
+                Ok((args.get_or_undefined(0).to_number(context)? * 2.0).into())
+            });
+            let api = ObjectInitializer::new(context)
+                .function(double, "double", 1)
+                .property(js_string!("name"), js_string!("app"), Attribute::all())
+                .build();
+            Ok(api.into())
+        });
+        rt.globals = vec![("myApi".into(), double)];
+        let script = "({ doubled: myApi.double(21), name: myApi.name })";
+        assert_eq!(
+            eval(&rt, Path::new("a.js"), script).unwrap(),
+            json!({"doubled": 42, "name": "app"})
+        );
+
+        let failing: Global =
+            Arc::new(|_| Err(JsNativeError::error().with_message("no api").into()));
+        rt.globals = vec![("broken".into(), failing)];
+        assert!(eval(&rt, Path::new("a.js"), "1").is_err());
+    }
+
+    #[test]
+    fn workers() {
+        let root = std::env::temp_dir().join("tide_rhai_js_workers");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("fib.js"),
+            r#"
+            const fib = (n) => (n < 2 ? n : fib(n - 1) + fib(n - 2));
+            postMessage({ ready: true });
+            onmessage = (e) => {
+                if (e.data === "fail") throw new Error("no");
+                postMessage({ n: e.data, fib: fib(e.data) });
+            };
+        "#,
+        )
+        .unwrap();
+        let root = root.canonicalize().unwrap();
+        let script = r#"
+            new Promise((resolve) => {
+                const seen = [];
+                const worker = new Worker("./fib.js");
+                worker.onerror = (e) => seen.push(e.message.includes("no"));
+                worker.addEventListener("message", (e) => {
+                    seen.push(e.data);
+                    if (e.data.ready) {
+                        worker.postMessage(10);
+                        worker.postMessage("fail");
+                        worker.postMessage(20);
+                    }
+                    if (e.data.n === 20) resolve(seen);
+                });
+            })
+        "#;
+        assert_eq!(
+            eval(&runtime(&root), &root.join("a.js"), script).unwrap(),
+            json!([{"ready": true}, {"n": 10, "fib": 55}, true, {"n": 20, "fib": 6765}])
+        );
+
+        // An idle worker does not keep the script running.
+        let started = std::time::Instant::now();
+        let script = r#"new Worker("/fib.js"); "done""#;
+        assert_eq!(
+            eval(&runtime(&root), &root.join("a.js"), script).unwrap(),
+            json!("done")
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let outside = r#"try { new Worker("../x.js") } catch (e) { e.name }"#;
+        assert_eq!(
+            eval(&runtime(&root), &root.join("a.js"), outside).unwrap(),
+            json!("TypeError")
+        );
+    }
+
+    #[test]
+    fn wasm() {
+        let root = std::env::temp_dir().join("tide_rhai_js_wasm");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        // wasmtime takes the text format as well as binaries.
+        std::fs::write(
+            root.join("math.wasm"),
+            r#"(module
+                (import "env" "log" (func $log (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (global (export "answer") i32 (i32.const 42))
+                (func (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1)))
+                (func (export "wide") (param i64) (result i64)
+                    (i64.mul (local.get 0) (i64.const 2)))
+                (func (export "sum") (param i32 i32) (result i32) (local $i i32) (local $s i32)
+                    (block (loop
+                        (br_if 1 (i32.ge_u (local.get $i) (local.get 1)))
+                        (local.set $s (i32.add (local.get $s)
+                            (i32.load8_u (i32.add (local.get 0) (local.get $i)))))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br 0)))
+                    (local.get $s))
+                (func (export "callLog") (param i32) (result i32)
+                    (call $log (local.get 0)))
+                (func (export "spin") (loop (br 0))))"#,
+        )
+        .unwrap();
+        let root = root.canonicalize().unwrap();
+        let script = r#"
+            export default async () => {
+                const seen = [];
+                const log = (n) => { seen.push(n); if (n < 0) throw new Error("negative"); return n * 10; };
+                const { exports } = await loadWasm("./math.wasm", { env: { log } });
+                exports.memory.write(8, new Uint8Array([1, 2, 3]));
+                let thrown;
+                try { exports.callLog(-1); } catch (e) { thrown = e.message; }
+                let missing;
+                try { await loadWasm("/math.wasm"); } catch (e) { missing = e.name; }
+                let invalid;
+                try { new WebAssembly.Module("nope"); } catch (e) { invalid = e instanceof WebAssembly.CompileError; }
+                return {
+                    add: exports.add(2, 3),
+                    wide: String(exports.wide(2n ** 40n)),
+                    sum: exports.sum(8, 3),
+                    log: exports.callLog(4),
+                    thrown,
+                    seen,
+                    answer: exports.answer.value,
+                    size: exports.memory.byteLength,
+                    missing,
+                    invalid,
+                    valid: WebAssembly.validate("(module)"),
+                };
+            };
+        "#;
+        assert_eq!(
+            eval(&runtime(&root), &root.join("a.mjs"), script).unwrap(),
+            json!({
+                "add": 5,
+                "wide": "2199023255552",
+                "sum": 6,
+                "log": 40,
+                "thrown": "negative",
+                "seen": [-1, 4],
+                "answer": 42,
+                "size": 65536,
+                "missing": "LinkError",
+                "invalid": true,
+                "valid": true,
+            })
+        );
+
+        // A module running past the deadline is stopped.
+        let mut rt = runtime(&root);
+        rt.timeout = Duration::from_millis(200);
+        let spin = r#"
+            loadWasm("math.wasm", { env: { log: (n) => n } }).then(({ exports }) => {
+                try { exports.spin(); } catch (e) { return e.name; }
+            })
+        "#;
+        assert_eq!(
+            eval(&rt, &root.join("a.js"), spin).unwrap(),
+            json!("RuntimeError")
+        );
+    }
+
+    #[test]
+    fn local_storage() {
+        let dir = std::env::temp_dir().join("tide_rhai_js_storage");
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = KvStore::open(&dir).unwrap();
+        let quota = StorageQuota {
+            max_item_size: 64,
+            max_total_size: 64,
+        };
+        let mut rt = runtime(".");
+        rt.storage = Some(store.app("a", quota).unwrap());
+        let count = r#"
+            const n = Number(storage.getItem("count") || 0) + 1;
+            localStorage.setItem("count", n);
+            n
+        "#;
+        assert_eq!(eval(&rt, Path::new("a.js"), count).unwrap(), json!(1));
+        assert_eq!(eval(&rt, Path::new("a.js"), count).unwrap(), json!(2));
+        let script = r#"
+            let error;
+            try {
+                storage.setItem("big", "x".repeat(100));
+            } catch (e) {
+                error = e.name;
+            }
+            storage.setItem("other", "y");
+            const keys = [storage.key(0), storage.key(1), storage.key(2)];
+            storage.removeItem("other");
+            ({ error, keys, length: storage.length, missing: storage.getItem("big") })
+        "#;
+        assert_eq!(
+            eval(&rt, Path::new("a.js"), script).unwrap(),
+            json!({"error": "QuotaExceededError", "keys": ["count", "other", null], "length": 1, "missing": null})
+        );
+    }
+
+    #[test]
+    fn run_tests() {
+        let root = std::env::temp_dir().join("tide_rhai_js_run_tests");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("hello.js"),
+            r#"export default (ctx) => ({ hello: ctx.data.name ?? "world" });"#,
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("hello.test.js"),
+            r#"
+            test("greets", () => {
+                const res = request("/hello.js", { method: "POST", body: { name: "ann" } });
+                assert.equal(res.status, 200);
+                assert.deepEqual(res.json(), { hello: "ann" });
+            });
+            test("waits for promises", async () => {
+                await assert.rejects(Promise.reject(new Error("no")));
+                assert.equal(1, 2, "one is not two");
+            });
+        "#,
+        )
+        .unwrap();
+        std::fs::write(root.join("broken.test.js"), "throw new Error('oops');").unwrap();
+        let report = JsDir::new("/js/*", &root).unwrap().run_tests();
+        let results: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| (r.name.as_str(), r.error.clone()))
+            .collect();
+        assert_eq!(results.len(), 3, "{}", report);
+        assert_eq!(results[0].0, "(file)");
+        assert!(
+            results[0].1.as_deref().unwrap().contains("oops"),
+            "{}",
+            report
+        );
+        assert_eq!(results[1], ("greets", None));
+        assert_eq!(results[2].0, "waits for promises");
+        assert!(
+            results[2].1.as_deref().unwrap().contains("one is not two"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        assert!(eval(&runtime("."), Path::new("a.js"), "({").is_err());
+    }
+}
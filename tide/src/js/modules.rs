@@ -0,0 +1,322 @@
+use super::remote::Remote;
+use super::resolve::{self, Kind, ResolveError};
+use super::typescript;
+use crate::integrity::Integrity;
+use crate::snapshot::Snapshot;
+use boa_engine::builtins::promise::PromiseState;
+use boa_engine::module::{Module, ModuleLoader, Referrer};
+use boa_engine::{js_string, Context, JsError, JsNativeError, JsResult, JsString, JsValue, Source};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use url::Url;
+
+/// The sources of the modules of a [`JsDir`](crate::JsDir), TypeScript
+/// already stripped, kept between requests until their file changes. A
+/// changed module is read again on its own, the others stay as they are.
+#[derive(Default)]
+pub(crate) struct Sources {
+    /// When the file changed, `None` for modules of a snapshot, by file.
+    pub(super) files: Mutex<HashMap<PathBuf, (Option<SystemTime>, Arc<str>)>>,
+}
+
+impl Sources {
+    pub(crate) fn len(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    /// Whether the module at `path` is kept, so an edit of its file is read
+    /// by the next request.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files.lock().unwrap().contains_key(&path)
+    }
+
+    /// The source of `path` if its file did not change since it was read.
+    fn get(&self, path: &Path, modified: Option<SystemTime>) -> Option<Arc<str>> {
+        let mut files = self.files.lock().unwrap();
+        match files.get(path) {
+            Some((m, source)) if *m == modified => Some(source.clone()),
+            Some(_) => {
+                log::debug!("Module {} changed, reading it again", path.display());
+                files.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, path: &Path, modified: Option<SystemTime>, source: Arc<str>) {
+        let mut files = self.files.lock().unwrap();
+        files.insert(path.to_path_buf(), (modified, source));
+    }
+}
+
+/// Resolves `import` specifiers against the app directory, or against the
+/// URL of a module imported by URL.
+///
+/// Every module is parsed once per loader and handed out again for later
+/// imports, which is what lets the engine link import cycles.
+///
+/// A loader lives as long as the context of one request, and so do the
+/// modules it evaluates. Their [`Sources`] are kept between requests, so a
+/// changed module is read again while the others are not, and those
+/// importing it link the new one with the next request.
+pub(crate) struct AppLoader {
+    root: PathBuf,
+    snapshot: Option<Arc<Snapshot>>,
+    integrity: Option<Arc<Integrity>>,
+    sources: Option<Arc<Sources>>,
+    modules: RefCell<HashMap<PathBuf, Module>>,
+    remote: Option<Arc<Remote>>,
+    /// Modules imported by URL, with the URL they were served from.
+    remote_modules: RefCell<HashMap<Url, (Url, Module)>>,
+}
+
+/// What the specifiers of a module are relative to.
+enum Base {
+    Dir(PathBuf),
+    Url(Url),
+}
+
+impl AppLoader {
+    pub(crate) fn new(root: PathBuf, snapshot: Option<Arc<Snapshot>>) -> Self {
+        Self {
+            root,
+            snapshot,
+            integrity: None,
+            sources: None,
+            modules: RefCell::new(HashMap::new()),
+            remote: None,
+            remote_modules: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Refuses modules that do not match the hashes of `integrity`.
+    pub(crate) fn with_integrity(mut self, integrity: Option<Arc<Integrity>>) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// Allows imports by `http(s)://` URL, downloaded through `remote`.
+    /// Reads the modules through `sources`, kept between requests.
+    pub(crate) fn with_sources(mut self, sources: Arc<Sources>) -> Self {
+        self.sources = Some(sources);
+        self
+    }
+
+    pub(crate) fn with_remote(mut self, remote: Option<Arc<Remote>>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    pub(crate) fn insert(&self, path: PathBuf, module: Module) {
+        self.modules.borrow_mut().insert(path, module);
+    }
+
+    /// The directory or URL of the module `referrer`, or the app directory
+    /// for scripts.
+    fn base_of(&self, referrer: &Referrer) -> Base {
+        if let Referrer::Module(module) = referrer {
+            let modules = self.modules.borrow();
+            let path = modules.iter().find(|(_, m)| *m == module).map(|(p, _)| p);
+            if let Some(dir) = path.and_then(|p| p.parent()) {
+                return Base::Dir(dir.to_path_buf());
+            }
+            let remote = self.remote_modules.borrow();
+            if let Some((from, _)) = remote.values().find(|(_, m)| m == module) {
+                return Base::Url(from.clone());
+            }
+        }
+        Base::Dir(self.root.clone())
+    }
+
+    /// Maps a specifier such as `./util.js`, `/lib/util.js` or a package in
+    /// `node_modules` onto a file in the app directory, refusing anything
+    /// outside of it. Relative specifiers are relative to the importing
+    /// module.
+    fn resolve(&self, dir: &Path, specifier: &str) -> JsResult<PathBuf> {
+        match resolve::resolve(&self.root, dir, specifier, Kind::Import) {
+            Ok(path) => Ok(path),
+            Err(ResolveError::Outside(path)) => {
+                log::warn!("Unauthorized attempt to import: {:?}", path);
+                Err(JsNativeError::typ()
+                    .with_message(format!("Cannot import '{}'", specifier))
+                    .into())
+            }
+            Err(e) => Err(JsNativeError::typ()
+                .with_message(format!("Cannot find module '{}': {}", specifier, e))
+                .into()),
+        }
+    }
+
+    fn load(&self, base: &Base, specifier: &str, context: &mut Context<'_>) -> JsResult<Module> {
+        let invalid = |e: url::ParseError| {
+            JsNativeError::typ().with_message(format!("Cannot import '{}': {}", specifier, e))
+        };
+        let dir = match (Url::parse(specifier), base) {
+            (Ok(url), _) => return self.load_remote(url, context),
+            (Err(_), Base::Url(from)) => {
+                let url = from.join(specifier).map_err(invalid)?;
+                return self.load_remote(url, context);
+            }
+            (Err(_), Base::Dir(dir)) => dir,
+        };
+        let path = self.resolve(dir, specifier)?;
+        if let Some(module) = self.modules.borrow().get(&path) {
+            return Ok(module.clone());
+        }
+        let source = self.source(&path)?;
+        let module = Module::parse(
+            Source::from_bytes(source.as_bytes()).with_path(&path),
+            None,
+            context,
+        )?;
+        self.insert(path, module.clone());
+        Ok(module)
+    }
+
+    /// The source of the module at `path`, from the [`Sources`] if it did
+    /// not change.
+    fn source(&self, path: &Path) -> JsResult<Arc<str>> {
+        let read = || {
+            typescript::read_js(
+                self.snapshot.as_deref(),
+                self.integrity.as_deref(),
+                &self.root,
+                path,
+            )
+            .map(Arc::from)
+        };
+        let Some(sources) = &self.sources else {
+            return read();
+        };
+        let modified = match self.snapshot.as_ref().and_then(|s| s.get(&self.root, path)) {
+            Some(_) => None,
+            None => match std::fs::metadata(path).and_then(|m| m.modified()) {
+                Ok(modified) => Some(modified),
+                Err(_) => return read(),
+            },
+        };
+        if let Some(source) = sources.get(path, modified) {
+            return Ok(source);
+        }
+        let source = read()?;
+        sources.insert(path, modified, source.clone());
+        Ok(source)
+    }
+}
+
+impl AppLoader {
+    fn load_remote(&self, url: Url, context: &mut Context<'_>) -> JsResult<Module> {
+        if url.scheme() != "https" && url.scheme() != "http" {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Cannot import '{}': unsupported scheme", url))
+                .into());
+        }
+        let remote = self.remote.as_ref().ok_or_else(|| {
+            JsNativeError::typ().with_message(format!(
+                "Cannot import '{}': imports by URL are disabled",
+                url
+            ))
+        })?;
+        if let Some((_, module)) = self.remote_modules.borrow().get(&url) {
+            return Ok(module.clone());
+        }
+        let (from, source) = remote
+            .load(&url)
+            .map_err(|e| JsNativeError::typ().with_message(e))?;
+        let path = Path::new(from.path());
+        let source = if typescript::is_typescript(path) {
+            typescript::transpile(path, &source)
+                .map_err(|e| JsNativeError::syntax().with_message(e.to_string()))?
+                .code
+                .to_string()
+        } else {
+            source
+        };
+        let module = Module::parse(
+            Source::from_bytes(&source).with_path(Path::new(from.as_str())),
+            None,
+            context,
+        )?;
+        self.remote_modules
+            .borrow_mut()
+            .insert(url, (from, module.clone()));
+        Ok(module)
+    }
+}
+
+impl ModuleLoader for AppLoader {
+    fn load_imported_module(
+        &self,
+        referrer: Referrer,
+        specifier: JsString,
+        finish_load: Box<dyn FnOnce(JsResult<Module>, &mut Context<'_>)>,
+        context: &mut Context<'_>,
+    ) {
+        let result = match specifier.to_std_string() {
+            Ok(s) => self.load(&self.base_of(&referrer), &s, context),
+            Err(e) => Err(JsNativeError::typ().with_message(e.to_string()).into()),
+        };
+        finish_load(result, context);
+    }
+}
+
+/// Whether a handler file uses `import`/`export` and has to run as a module.
+pub(crate) fn is_module(path: &Path, source: &str) -> bool {
+    if path.extension().map_or(false, |e| e == "mjs") {
+        return true;
+    }
+    source.lines().any(|l| {
+        let l = l.trim_start();
+        l.starts_with("import ") || l.starts_with("import{") || l.starts_with("export ")
+    })
+}
+
+/// Parses, links and evaluates `source` as the module at `path` and returns
+/// its default export.
+pub(crate) fn evaluate(
+    loader: &AppLoader,
+    path: &Path,
+    source: &str,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let module = Module::parse(Source::from_bytes(source).with_path(path), None, context)?;
+    if let Ok(canonical) = path.canonicalize() {
+        loader.insert(canonical, module.clone());
+    }
+
+    let promise = module.load_link_evaluate(context)?;
+    context.run_jobs();
+    match promise.state()? {
+        PromiseState::Fulfilled(_) => {}
+        PromiseState::Rejected(err) => return Err(JsError::from_opaque(err)),
+        PromiseState::Pending => {
+            return Err(JsNativeError::error()
+                .with_message("Module evaluation did not complete")
+                .into())
+        }
+    }
+    module
+        .namespace(context)
+        .get(js_string!("default"), context)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn module_detection() {
+        assert!(is_module(Path::new("a.js"), "import x from './x.js';"));
+        assert!(is_module(
+            Path::new("a.js"),
+            "const a = 1;\nexport default a;"
+        ));
+        assert!(is_module(Path::new("a.mjs"), "1"));
+        assert!(!is_module(Path::new("a.js"), "({ important: 1 })"));
+    }
+}
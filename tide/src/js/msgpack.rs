@@ -0,0 +1,168 @@
+//! The `msgpack` global, see [`crate::msgpack`]: `msgpack.decode` of an
+//! `ArrayBuffer`, a view on one or a string, and `msgpack.encode` of a value
+//! into a `Uint8Array`.
+//!
+//! Integers are numbers up to `Number.MAX_SAFE_INTEGER` and BigInts past it,
+//! strings that are not UTF-8 and binaries `Uint8Array`s, and maps objects,
+//! with keys that are not strings written as they print. Extensions are
+//! objects of their `type` and `data`. Maps, `null` and `undefined` encode
+//! too; functions and symbols do not.
+
+use super::web::{self, MAX_SAFE_INTEGER};
+use crate::msgpack::{self, Value};
+use boa_engine::object::builtins::{JsArray, JsArrayBuffer, JsUint8Array};
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsBigInt, JsNativeError, JsResult, JsString, JsValue,
+    NativeFunction,
+};
+
+pub(super) fn register(context: &mut Context<'_>) -> JsResult<()> {
+    let decode = NativeFunction::from_fn_ptr(|_, args, context| {
+        let bytes = web::to_bytes(args.get_or_undefined(0), context)?;
+        let value = msgpack::decode(&bytes).map_err(|e| JsNativeError::syntax().with_message(e))?;
+        to_js(value, context)
+    });
+    let encode = NativeFunction::from_fn_ptr(|_, args, context| {
+        let value = from_js(args.get_or_undefined(0), context)?;
+        web::from_bytes(msgpack::encode(&value), context)
+    });
+    let object = ObjectInitializer::new(context)
+        .function(decode, "decode", 1)
+        .function(encode, "encode", 1)
+        .build();
+    context.register_global_property(js_string!("msgpack"), object, Attribute::all())
+}
+
+fn to_js(value: Value, context: &mut Context<'_>) -> JsResult<JsValue> {
+    Ok(match value {
+        Value::Nil => JsValue::null(),
+        Value::Boolean(b) => b.into(),
+        Value::Integer(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) if i.unsigned_abs() <= MAX_SAFE_INTEGER => (i as f64).into(),
+            (Some(i), _) => JsBigInt::from(i).into(),
+            (None, Some(u)) => JsBigInt::from(u).into(),
+            (None, None) => JsValue::nan(),
+        },
+        Value::F32(f) => (f as f64).into(),
+        Value::F64(f) => f.into(),
+        Value::String(s) => match String::from_utf8(s.into_bytes()) {
+            Ok(s) => JsString::from(s).into(),
+            Err(e) => web::from_bytes(e.into_bytes(), context)?,
+        },
+        Value::Binary(bytes) => web::from_bytes(bytes, context)?,
+        Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| to_js(item, context))
+                .collect::<JsResult<Vec<_>>>()?;
+            JsArray::from_iter(items, context).into()
+        }
+        Value::Map(entries) => {
+            let mut properties = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                let key = match key {
+                    Value::String(s) if s.is_str() => s.into_str().unwrap_or_default(),
+                    other => other.to_string(),
+                };
+                properties.push((JsString::from(key), to_js(value, context)?));
+            }
+            let mut object = ObjectInitializer::new(context);
+            for (key, value) in properties {
+                object.property(key, value, Attribute::all());
+            }
+            object.build().into()
+        }
+        Value::Ext(kind, data) => {
+            let data = web::from_bytes(data, context)?;
+            ObjectInitializer::new(context)
+                .property(js_string!("type"), kind as i32, Attribute::all())
+                .property(js_string!("data"), data, Attribute::all())
+                .build()
+                .into()
+        }
+    })
+}
+
+fn from_js(value: &JsValue, context: &mut Context<'_>) -> JsResult<Value> {
+    if value.is_null_or_undefined() {
+        return Ok(Value::Nil);
+    }
+    if let Some(b) = value.as_boolean() {
+        return Ok(Value::Boolean(b));
+    }
+    if let Some(s) = value.as_string() {
+        return Ok(Value::from(String::from_utf16_lossy(s)));
+    }
+    if let Some(n) = value.as_number() {
+        if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 {
+            return Ok(Value::from(n as i64));
+        }
+        return Ok(Value::F64(n));
+    }
+    if let Some(big) = value.as_bigint() {
+        let big = big.to_string();
+        if let Ok(i) = big.parse::<i64>() {
+            return Ok(Value::from(i));
+        }
+        return big.parse::<u64>().map(Value::from).map_err(|_| {
+            let message = format!("{} does not fit in 64 bits", big);
+            JsNativeError::range().with_message(message).into()
+        });
+    }
+    let cannot = || {
+        let message = format!("MessagePack cannot hold {}", value.display());
+        JsNativeError::typ().with_message(message)
+    };
+    let object = match value.as_object() {
+        Some(object) if !object.is_callable() => object,
+        _ => return Err(cannot().into()),
+    };
+    if JsUint8Array::from_object(object.clone()).is_ok()
+        || JsArrayBuffer::from_object(object.clone()).is_ok()
+    {
+        return Ok(Value::Binary(web::to_bytes(value, context)?));
+    }
+    if object.is_array() {
+        let list = JsArray::from_object(object.clone())?;
+        let mut items = Vec::new();
+        for i in 0..list.length(context)? {
+            items.push(from_js(&list.get(i, context)?, context)?);
+        }
+        return Ok(Value::Array(items));
+    }
+    let pairs = web::entries(object, context)?;
+    let mut map = Vec::new();
+    for i in 0..pairs.length(context)? {
+        let pair = pairs.get(i, context)?;
+        let pair = pair.as_object().ok_or_else(cannot)?;
+        let key = from_js(&pair.get(0, context)?, context)?;
+        map.push((key, from_js(&pair.get(1, context)?, context)?));
+    }
+    Ok(Value::Map(map))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use boa_engine::Source;
+
+    #[test]
+    fn round_trips() {
+        let mut context = Context::default();
+        register(&mut context).unwrap();
+        let mut eval = |code: &str| context.eval(Source::from_bytes(code));
+        let same = r#"
+            const order = { id: 7, big: 2n ** 60n, items: ["shoe", null, true, 1.5], raw: new Uint8Array([1, 2]) };
+            const back = msgpack.decode(msgpack.encode(order));
+            back.id === 7 && back.big === 2n ** 60n && back.items[3] === 1.5 && back.items[1] === null
+                && back.raw instanceof Uint8Array && back.raw[1] === 2
+        "#;
+        assert_eq!(eval(same).unwrap(), JsValue::from(true));
+        let map = eval(r#"msgpack.decode(msgpack.encode(new Map([[1, "one"]])))["1"]"#).unwrap();
+        assert_eq!(map, JsValue::from(js_string!("one")));
+        assert!(eval("msgpack.encode(() => 1)").is_err());
+        assert!(eval("msgpack.decode(new Uint8Array([0x92]))").is_err());
+    }
+}
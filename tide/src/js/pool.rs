@@ -0,0 +1,114 @@
+//! Warm contexts, set up ahead of the requests that use them.
+//!
+//! Setting up a context, mostly evaluating the web APIs, takes longer than
+//! most handlers run. Each thread of the pool sets one up, waits for a
+//! request, runs it and then sets up the next one. A context is never used
+//! for two requests, so nothing a script leaves behind reaches the next.
+
+use super::backend::Job;
+use super::{event_loop, execute, modules, prepare, script_name, web, Prepared, Runtime};
+use crate::rt;
+use async_std::channel;
+use boa_engine::Context;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tide::log;
+
+pub(super) struct Pool {
+    jobs: channel::Sender<Job>,
+    /// Threads with a context ready, waiting for a job.
+    idle: Arc<AtomicUsize>,
+    size: usize,
+}
+
+impl Pool {
+    /// Starts `size` threads running the scripts of `runtime`.
+    pub(super) fn new(runtime: Runtime, size: usize) -> Self {
+        let (jobs, queue) = channel::unbounded();
+        let idle = Arc::new(AtomicUsize::new(0));
+        for i in 0..size {
+            let runtime = runtime.clone();
+            let queue = queue.clone();
+            let idle = idle.clone();
+            let spawned = std::thread::Builder::new()
+                .name(format!("js-{}", i))
+                .spawn(move || serve(&runtime, &queue, &idle));
+            if let Err(e) = spawned {
+                log::error!("Cannot start a JavaScript thread: {}", e);
+            }
+        }
+        Self { jobs, idle, size }
+    }
+
+    pub(super) fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(super) fn idle(&self) -> usize {
+        self.idle.load(Ordering::SeqCst)
+    }
+
+    /// Hands `job` to a thread with a warm context, or gives it back if they
+    /// are all busy.
+    pub(super) fn try_run(&self, job: Job) -> Result<(), Job> {
+        let claimed = self
+            .idle
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if !claimed {
+            return Err(job);
+        }
+        self.jobs.try_send(job).map_err(|e| e.into_inner())
+    }
+}
+
+/// Sets up a context, waits for a job and runs it, until the pool is dropped.
+fn serve(runtime: &Runtime, queue: &channel::Receiver<Job>, idle: &AtomicUsize) {
+    loop {
+        let loader = modules::AppLoader::new(runtime.root.clone(), runtime.snapshot.clone())
+            .with_integrity(runtime.integrity.clone())
+            .with_sources(runtime.sources.clone())
+            .with_remote(runtime.remote.clone());
+        let jobs = event_loop::EventLoop::new(runtime.timeout);
+        let mut context = match Context::builder()
+            .module_loader(&loader)
+            .job_queue(&jobs)
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("Cannot create a JavaScript context: {}", e);
+                return;
+            }
+        };
+        let script = web::ScriptName::new("");
+        let natives = match prepare(runtime, &script, &jobs, &mut context) {
+            Ok(n) => n,
+            Err(e) => {
+                log::error!("Cannot set up a JavaScript context: {}", e);
+                return;
+            }
+        };
+        idle.fetch_add(1, Ordering::SeqCst);
+        let job = match rt::block_on(queue.recv()) {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        script.set(&script_name(&runtime.root, &job.path));
+        jobs.restart();
+        let prepared = Prepared {
+            loader: &loader,
+            jobs: &jobs,
+            natives,
+        };
+        execute(
+            runtime,
+            &prepared,
+            &mut context,
+            &job.path,
+            &job.source,
+            &job.incoming,
+            job.reply,
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Modules imported by URL, like `import x from "https://esm.sh/lodash-es"`.
+//!
+//! Downloads are kept in a cache directory, each under the sha256 of its
+//! contents, along with an index from the URL to the contents it served. A
+//! lock file, if configured, records that hash for every URL the first time
+//! it is imported, and any later mismatch, from the network or the cache,
+//! fails the import. Offline, only the cache is used. As in Deno, imports
+//! don't need network permissions, they are granted by listing URLs in code.
+
+use crate::fetch;
+use crate::permissions::Permissions;
+use crate::rt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use url::Url;
+
+/// How many redirects a download follows.
+const MAX_REDIRECTS: usize = 10;
+
+/// Settings of imports by URL, see [`JsDir::with_remote_imports`](crate::JsDir::with_remote_imports).
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteImports {
+    /// Where downloaded modules are kept.
+    pub cache_dir: PathBuf,
+    /// Records the hash of every imported URL, like a `package-lock.json`.
+    pub lock_file: Option<PathBuf>,
+    /// Only use modules that are already in the cache.
+    #[serde(default)]
+    pub offline: bool,
+}
+
+/// Where a URL led, in the index of the cache.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LockFile {
+    version: String,
+    remote: BTreeMap<String, String>,
+}
+
+pub(crate) struct Remote {
+    config: RemoteImports,
+    lock: Mutex<LockFile>,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Writes `path` through a temporary file, so readers never see half of it.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+impl Remote {
+    pub(crate) fn new(config: RemoteImports) -> io::Result<Self> {
+        std::fs::create_dir_all(config.cache_dir.join("urls"))?;
+        let lock = match &config.lock_file {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(path)?;
+                serde_json::from_str(&text).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid lock file {}: {}", path.display(), e),
+                    )
+                })?
+            }
+            _ => LockFile {
+                version: "1".into(),
+                remote: BTreeMap::new(),
+            },
+        };
+        Ok(Self {
+            config,
+            lock: Mutex::new(lock),
+        })
+    }
+
+    /// The source of the module at `url`, and the URL it came from after
+    /// redirects, which its own imports are relative to.
+    pub(crate) fn load(&self, url: &Url) -> Result<(Url, String), String> {
+        let (from, bytes) = match self.cached(url) {
+            Some(found) => found,
+            None if self.config.offline => {
+                return Err(format!("{} is not cached and imports are offline", url))
+            }
+            None => self.download(url)?,
+        };
+        self.check_lock(url, &sha256_hex(&bytes))?;
+        let source = String::from_utf8(bytes).map_err(|_| format!("{} is not valid UTF-8", url))?;
+        Ok((from, source))
+    }
+
+    fn index_path(&self, url: &Url) -> PathBuf {
+        let name = format!("{}.json", sha256_hex(url.as_str().as_bytes()));
+        self.config.cache_dir.join("urls").join(name)
+    }
+
+    /// What the cache holds for `url`. Contents that don't match their hash
+    /// anymore count as missing.
+    fn cached(&self, url: &Url) -> Option<(Url, Vec<u8>)> {
+        let entry = std::fs::read_to_string(self.index_path(url)).ok()?;
+        let entry: Entry = serde_json::from_str(&entry).ok()?;
+        let bytes = std::fs::read(self.config.cache_dir.join(&entry.hash)).ok()?;
+        if sha256_hex(&bytes) != entry.hash {
+            log::warn!("Cached module {} is corrupt, ignoring it", url);
+            return None;
+        }
+        Some((Url::parse(&entry.url).ok()?, bytes))
+    }
+
+    /// Adds what `url` served, from `from`, to the cache.
+    pub(crate) fn store(&self, url: &Url, from: &Url, bytes: &[u8]) -> io::Result<()> {
+        let hash = sha256_hex(bytes);
+        write_atomic(&self.config.cache_dir.join(&hash), bytes)?;
+        let entry = Entry {
+            url: from.to_string(),
+            hash,
+        };
+        let entry = serde_json::to_vec(&entry).expect("entries serialize");
+        write_atomic(&self.index_path(url), &entry)
+    }
+
+    fn download(&self, url: &Url) -> Result<(Url, Vec<u8>), String> {
+        let headers = [
+            (
+                "accept".to_string(),
+                "application/javascript, text/javascript, */*".to_string(),
+            ),
+            ("user-agent".to_string(), "rustvm".to_string()),
+        ];
+        let mut current = url.clone();
+        for _ in 0..MAX_REDIRECTS {
+            log::info!("Downloading {}", current);
+            let mut res = rt::block_on(fetch::request(
+                current.as_str(),
+                "GET",
+                &headers,
+                None,
+                false,
+                &Permissions::all(),
+            ))
+            .map_err(|e| format!("Cannot download {}: {}", current, e))?;
+            let status = res.status();
+            if status.is_redirection() {
+                let location = res
+                    .header("location")
+                    .map(|v| v.as_str().to_string())
+                    .ok_or_else(|| format!("{} redirects nowhere", current))?;
+                current = current
+                    .join(&location)
+                    .map_err(|e| format!("{} redirects to an invalid URL: {}", current, e))?;
+                continue;
+            }
+            if !status.is_success() {
+                return Err(format!("Cannot download {}: {}", current, status));
+            }
+            let bytes = rt::block_on(res.body_bytes())
+                .map_err(|e| format!("Cannot download {}: {}", current, e))?;
+            if let Err(e) = self.store(url, &current, &bytes) {
+                log::error!("Cannot cache {}: {}", url, e);
+            }
+            return Ok((current, bytes));
+        }
+        Err(format!("{} redirects too often", url))
+    }
+
+    /// Compares `hash` with the lock file, adding `url` to it if it is new.
+    fn check_lock(&self, url: &Url, hash: &str) -> Result<(), String> {
+        let path = match &self.config.lock_file {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let mut lock = self.lock.lock().unwrap();
+        match lock.remote.get(url.as_str()) {
+            Some(locked) if locked != hash => Err(format!(
+                "Integrity check failed for {}: the lock file expects sha256 {}, got {}",
+                url, locked, hash
+            )),
+            Some(_) => Ok(()),
+            None => {
+                lock.remote.insert(url.to_string(), hash.to_string());
+                let mut text = serde_json::to_string_pretty(&*lock).expect("lock files serialize");
+                text.push('\n');
+                write_atomic(path, text.as_bytes())
+                    .map_err(|e| format!("Cannot write lock file {}: {}", path.display(), e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn remote(name: &str, offline: bool) -> Remote {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        Remote::new(RemoteImports {
+            cache_dir: dir.join("cache"),
+            lock_file: Some(dir.join("deps.lock")),
+            offline,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn offline_cache() {
+        let remote = remote("tide_rhai_js_remote_offline", true);
+        let url = Url::parse("https://example.com/mod.js").unwrap();
+        let from = Url::parse("https://example.com/v1/mod.js").unwrap();
+        assert!(remote.load(&url).is_err());
+        remote.store(&url, &from, b"export default 1;").unwrap();
+        let (at, source) = remote.load(&url).unwrap();
+        assert_eq!(at, from);
+        assert_eq!(source, "export default 1;");
+        let lock = std::fs::read_to_string(remote.config.lock_file.as_ref().unwrap()).unwrap();
+        assert!(lock.contains(&sha256_hex(b"export default 1;")));
+    }
+
+    #[test]
+    fn integrity() {
+        let remote = remote("tide_rhai_js_remote_integrity", true);
+        let url = Url::parse("https://example.com/mod.js").unwrap();
+        remote.store(&url, &url, b"export default 1;").unwrap();
+        remote.load(&url).unwrap();
+        // The server now hands out something else.
+        remote.store(&url, &url, b"export default 2;").unwrap();
+        let e = remote.load(&url).unwrap_err();
+        assert!(e.contains("Integrity check failed"), "{}", e);
+        // Contents that don't match their name are not used.
+        std::fs::write(
+            remote
+                .config
+                .cache_dir
+                .join(sha256_hex(b"export default 2;")),
+            "tampered",
+        )
+        .unwrap();
+        assert!(remote.cached(&url).is_none());
+    }
+}
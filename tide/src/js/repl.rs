@@ -0,0 +1,129 @@
+//! The JavaScript side of `rustvm repl`: one context, set up like those of
+//! requests, that evaluates what is typed into it.
+
+use super::{event_loop, standalone, JsDir};
+use crate::Evaluated;
+use boa_engine::script::Script;
+use boa_engine::{js_string, Context, JsObject, JsResult, JsValue, Source};
+use std::io;
+
+/// A REPL for JavaScript, see [`JsDir::repl`].
+pub struct JsRepl<'a, 'c> {
+    context: &'a mut Context<'c>,
+    jobs: &'a event_loop::EventLoop,
+    natives: JsObject,
+}
+
+impl JsRepl<'_, '_> {
+    /// Evaluates `code`, the lines read since the last value or error. A
+    /// promise is waited for and its value shown, so `await` is not needed
+    /// for `fetch`.
+    pub fn eval(&mut self, code: &str) -> Evaluated {
+        self.jobs.restart();
+        let res = (|| {
+            let value = self.context.eval(Source::from_bytes(code))?;
+            let value = self.jobs.settle(value, self.context)?;
+            self.context.run_jobs();
+            Ok(value)
+        })();
+        match res {
+            Ok(v) if v.is_undefined() => Evaluated::Value(None),
+            Ok(v) => match self.format(&v) {
+                Ok(shown) => Evaluated::Value(Some(shown)),
+                Err(e) => Evaluated::Error(e.to_string()),
+            },
+            Err(_) if incomplete(code, self.context) => Evaluated::Incomplete,
+            Err(e) => Evaluated::Error(format!("Uncaught {}", e)),
+        }
+    }
+
+    /// `value` the way `console.log` shows it, strings quoted.
+    fn format(&mut self, value: &JsValue) -> JsResult<String> {
+        let format = self.natives.get(js_string!("format"), self.context)?;
+        let Some(format) = format.as_callable() else {
+            return Ok(value.display().to_string());
+        };
+        let shown = format.call(
+            &JsValue::undefined(),
+            &[js_string!("%o").into(), value.clone()],
+            self.context,
+        )?;
+        Ok(shown.to_string(self.context)?.to_std_string_escaped())
+    }
+}
+
+/// Whether `code` failed to parse only because it stops too early, like
+/// after the opening brace of a block.
+fn incomplete(code: &str, context: &mut Context<'_>) -> bool {
+    match Script::parse(Source::from_bytes(code), None, context) {
+        Ok(_) => false,
+        Err(e) => e.to_string().contains("abrupt end"),
+    }
+}
+
+impl JsDir {
+    /// Sets up a context the way requests get one, with `require` relative
+    /// to the directory, and hands `session` a [`JsRepl`] evaluating code in
+    /// it. The timeout of scripts applies to every evaluation.
+    ///```
+    /// use tide_rhai::{Evaluated, JsDir};
+    /// let js = JsDir::new("/js/*", "./examples/app/").unwrap();
+    /// let shown = js
+    ///     .repl(|repl| {
+    ///         repl.eval("const answer = 42;");
+    ///         repl.eval("answer")
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(shown, Evaluated::Value(Some("42".into())));
+    ///```
+    pub fn repl<R>(&self, session: impl FnOnce(&mut JsRepl<'_, '_>) -> R) -> io::Result<R> {
+        let runtime = self.runtime();
+        standalone(&runtime, "repl", &runtime.root, |context, prepared| {
+            let mut repl = JsRepl {
+                context,
+                jobs: prepared.jobs,
+                natives: prepared.natives.clone(),
+            };
+            Ok(session(&mut repl))
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evaluates_lines() {
+        let root = std::env::temp_dir().join("tide_rhai_js_repl");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("lib.js"), "exports.twice = (n) => n * 2;").unwrap();
+        let js = JsDir::new("/js/*", &root).unwrap();
+        let shown = js
+            .repl(|repl| {
+                vec![
+                    repl.eval("const { twice } = require('./lib.js');"),
+                    repl.eval("function add(a, b) {"),
+                    repl.eval("function add(a, b) {\n  return a + b;\n}"),
+                    repl.eval("twice(add(1, 2))"),
+                    repl.eval("'text'"),
+                    repl.eval("Promise.resolve({ done: true })"),
+                    repl.eval("missing()"),
+                ]
+            })
+            .unwrap();
+        assert_eq!(
+            shown[..6],
+            [
+                Evaluated::Value(None),
+                Evaluated::Incomplete,
+                Evaluated::Value(None),
+                Evaluated::Value(Some("6".into())),
+                Evaluated::Value(Some("'text'".into())),
+                Evaluated::Value(Some("{ done: true }".into())),
+            ]
+        );
+        assert!(matches!(&shown[6], Evaluated::Error(e) if e.contains("ReferenceError")));
+    }
+}
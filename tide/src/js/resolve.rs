@@ -0,0 +1,416 @@
+//! Node-style resolution of `import` and `require` specifiers, so packages
+//! installed with npm into the app directory can be used.
+//!
+//! Relative specifiers resolve against the importing file and absolute ones
+//! against the app directory. Bare ones like `lodash/fp` are looked up in the
+//! `node_modules` directories from the importing file up to the app
+//! directory, following the `exports`, `module` and `main` fields of the
+//! package's package.json, and are otherwise taken relative to the app
+//! directory as before. Nothing outside the app directory can be reached.
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Whether a specifier comes from `import` or `require`, which picks the
+/// conditions of `exports` and the package.json fields that apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Import,
+    Require,
+}
+
+impl Kind {
+    fn conditions(self) -> &'static [&'static str] {
+        match self {
+            Kind::Import => &["import", "default"],
+            Kind::Require => &["require", "default"],
+        }
+    }
+
+    /// The package.json fields naming the entry point, in order.
+    fn entry_fields(self) -> &'static [&'static str] {
+        match self {
+            Kind::Import => &["module", "main"],
+            Kind::Require => &["main"],
+        }
+    }
+}
+
+/// A json value that keeps the order of object keys, which decides between
+/// the conditions of `exports`.
+#[derive(Debug, Clone)]
+enum Json {
+    Null,
+    Other,
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct JsonVisitor;
+
+        impl<'de> Visitor<'de> for JsonVisitor {
+            type Value = Json;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("json")
+            }
+
+            fn visit_unit<E>(self) -> Result<Json, E> {
+                Ok(Json::Null)
+            }
+
+            fn visit_bool<E>(self, _: bool) -> Result<Json, E> {
+                Ok(Json::Other)
+            }
+
+            fn visit_i64<E>(self, _: i64) -> Result<Json, E> {
+                Ok(Json::Other)
+            }
+
+            fn visit_u64<E>(self, _: u64) -> Result<Json, E> {
+                Ok(Json::Other)
+            }
+
+            fn visit_f64<E>(self, _: f64) -> Result<Json, E> {
+                Ok(Json::Other)
+            }
+
+            fn visit_str<E>(self, s: &str) -> Result<Json, E> {
+                Ok(Json::String(s.to_string()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Json::Array(items))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Json::Object(entries))
+            }
+        }
+
+        deserializer.deserialize_any(JsonVisitor)
+    }
+}
+
+/// Why a specifier did not resolve.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ResolveError {
+    NotFound,
+    /// The file exists, but outside of the app directory.
+    Outside(PathBuf),
+    /// A package.json that could not be read, or that does not export the
+    /// requested subpath.
+    Package(String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NotFound => f.write_str("not found"),
+            ResolveError::Outside(_) => f.write_str("outside of the app directory"),
+            ResolveError::Package(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// Resolves `specifier`, used by a file in `dir`, to the canonical path of a
+/// file inside `root`.
+pub(crate) fn resolve(
+    root: &Path,
+    dir: &Path,
+    specifier: &str,
+    kind: Kind,
+) -> Result<PathBuf, ResolveError> {
+    if specifier.starts_with("./") || specifier.starts_with("../") {
+        return inside(root, file_or_dir(&dir.join(specifier), kind)?);
+    }
+    if let Some(rest) = specifier.strip_prefix('/') {
+        return inside(root, file_or_dir(&root.join(rest), kind)?);
+    }
+    match package(root, dir, specifier, kind) {
+        Err(ResolveError::NotFound) => inside(root, file_or_dir(&root.join(specifier), kind)?),
+        res => res,
+    }
+}
+
+fn inside(root: &Path, path: PathBuf) -> Result<PathBuf, ResolveError> {
+    let path = path.canonicalize().map_err(|_| ResolveError::NotFound)?;
+    if !path.starts_with(root) {
+        return Err(ResolveError::Outside(path));
+    }
+    Ok(path)
+}
+
+/// The file itself, then with `.js`, `.mjs`, `.cjs`, `.ts` or `.json`
+/// appended, then the entry point of the directory.
+fn file_or_dir(base: &Path, kind: Kind) -> Result<PathBuf, ResolveError> {
+    if let Some(path) = file(base) {
+        return Ok(path);
+    }
+    if base.is_dir() {
+        return dir_entry(base, kind);
+    }
+    Err(ResolveError::NotFound)
+}
+
+fn file(base: &Path) -> Option<PathBuf> {
+    if base.is_file() {
+        return Some(base.to_path_buf());
+    }
+    [".js", ".mjs", ".cjs", ".ts", ".json"]
+        .iter()
+        .find_map(|suffix| {
+            let mut s = base.as_os_str().to_os_string();
+            s.push(suffix);
+            Some(PathBuf::from(s)).filter(|p| p.is_file())
+        })
+}
+
+fn index(dir: &Path) -> Option<PathBuf> {
+    ["index.js", "index.mjs", "index.ts", "index.json"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|p| p.is_file())
+}
+
+/// The `module` or `main` file named by the package.json of `dir`, or its
+/// index file.
+fn dir_entry(dir: &Path, kind: Kind) -> Result<PathBuf, ResolveError> {
+    if let Some(manifest) = manifest(dir)? {
+        for field in kind.entry_fields() {
+            if let Some(Json::String(entry)) = manifest.get(field) {
+                let entry = dir.join(entry);
+                if let Some(path) = file(&entry).or_else(|| index(&entry)) {
+                    return Ok(path);
+                }
+            }
+        }
+    }
+    index(dir).ok_or(ResolveError::NotFound)
+}
+
+fn manifest(dir: &Path) -> Result<Option<Json>, ResolveError> {
+    let path = dir.join("package.json");
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(_) => return Ok(None),
+    };
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(|e| ResolveError::Package(format!("Invalid {}: {}", path.display(), e)))
+}
+
+/// Splits `lodash/fp` into `lodash` and `./fp`, and `@scope/pkg` into
+/// `@scope/pkg` and `.`.
+fn split_package(specifier: &str) -> Option<(&str, String)> {
+    let mut slashes = specifier.match_indices('/').map(|(i, _)| i);
+    let end = if specifier.starts_with('@') {
+        slashes.nth(1)
+    } else {
+        slashes.next()
+    };
+    let (name, rest) = match end {
+        Some(i) => (&specifier[..i], &specifier[i..]),
+        None => (specifier, ""),
+    };
+    if name.is_empty() || (name.starts_with('@') && !name.contains('/')) {
+        return None;
+    }
+    Some((name, format!(".{}", rest)))
+}
+
+/// Looks `specifier` up in the `node_modules` directories from `dir` up to
+/// `root`.
+fn package(root: &Path, dir: &Path, specifier: &str, kind: Kind) -> Result<PathBuf, ResolveError> {
+    let (name, subpath) = split_package(specifier).ok_or(ResolveError::NotFound)?;
+    let mut current = Some(dir);
+    while let Some(d) = current {
+        if !d.starts_with(root) {
+            break;
+        }
+        let package = d.join("node_modules").join(name);
+        if package.is_dir() {
+            let found = match manifest(&package)?.as_ref().and_then(|m| m.get("exports")) {
+                Some(exports) => exported(&package, exports, &subpath, kind)?,
+                None if subpath == "." => dir_entry(&package, kind)?,
+                None => file_or_dir(&package.join(&subpath), kind)?,
+            };
+            return inside(root, found);
+        }
+        current = d.parent();
+    }
+    Err(ResolveError::NotFound)
+}
+
+/// The file the `exports` of a package map `subpath` to.
+fn exported(
+    package: &Path,
+    exports: &Json,
+    subpath: &str,
+    kind: Kind,
+) -> Result<PathBuf, ResolveError> {
+    let not_exported = || {
+        ResolveError::Package(format!(
+            "Package {} does not export {}",
+            package.display(),
+            subpath
+        ))
+    };
+    let subpaths = match exports {
+        Json::Object(entries) if entries.iter().any(|(k, _)| k.starts_with('.')) => entries,
+        // A string, an array or conditions all stand for `.` alone.
+        _ if subpath == "." => {
+            return target(package, exports, None, kind).ok_or_else(not_exported);
+        }
+        _ => return Err(not_exported()),
+    };
+    if let Some((_, t)) = subpaths.iter().find(|(k, _)| k == subpath) {
+        return target(package, t, None, kind).ok_or_else(not_exported);
+    }
+    // The longest matching pattern like `./features/*.js` wins.
+    let mut best: Option<(&str, &Json, String)> = None;
+    for (key, t) in subpaths {
+        let (prefix, suffix) = match key.split_once('*') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        if subpath.len() >= prefix.len() + suffix.len()
+            && subpath.starts_with(prefix)
+            && subpath.ends_with(suffix)
+            && best.as_ref().map_or(true, |(k, _, _)| key.len() > k.len())
+        {
+            let matched = subpath[prefix.len()..subpath.len() - suffix.len()].to_string();
+            best = Some((key, t, matched));
+        }
+    }
+    match best {
+        Some((_, t, matched)) => target(package, t, Some(&matched), kind).ok_or_else(not_exported),
+        None => Err(not_exported()),
+    }
+}
+
+/// Follows a target of `exports`: a path, conditions taken in their order,
+/// or an array of fallbacks.
+fn target(package: &Path, t: &Json, matched: Option<&str>, kind: Kind) -> Option<PathBuf> {
+    match t {
+        Json::String(s) => {
+            let s = match matched {
+                Some(m) => s.replace('*', m),
+                None => s.clone(),
+            };
+            let relative = s.strip_prefix("./")?;
+            let path = package.join(relative);
+            if path.is_file() {
+                Some(path)
+            } else {
+                None
+            }
+        }
+        Json::Object(conditions) => conditions
+            .iter()
+            .filter(|(c, _)| kind.conditions().contains(&c.as_str()))
+            .find_map(|(_, t)| target(package, t, matched, kind)),
+        Json::Array(fallbacks) => fallbacks
+            .iter()
+            .find_map(|t| target(package, t, matched, kind)),
+        Json::Null | Json::Other => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn packages() {
+        let root = std::env::temp_dir().join("tide_rhai_js_resolve");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        write(&root, "node_modules/plain/index.js", "");
+        write(
+            &root,
+            "node_modules/main/package.json",
+            r#"{"main": "lib/main"}"#,
+        );
+        write(&root, "node_modules/main/lib/main.js", "");
+        write(
+            &root,
+            "node_modules/@scope/dual/package.json",
+            r#"{"exports": {".": {"import": "./esm/index.js", "require": "./cjs/index.js"}, "./util/*": "./src/util/*.js"}}"#,
+        );
+        write(&root, "node_modules/@scope/dual/esm/index.js", "");
+        write(&root, "node_modules/@scope/dual/cjs/index.js", "");
+        write(&root, "node_modules/@scope/dual/src/util/a.js", "");
+        write(&root, "lib/node_modules/plain/index.js", "");
+        write(&root, "lib/x.js", "");
+
+        let at = |dir: &str, specifier: &str, kind| {
+            resolve(&root, &root.join(dir), specifier, kind).map(|p| {
+                p.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        };
+        assert_eq!(
+            at("", "plain", Kind::Import).unwrap(),
+            "node_modules/plain/index.js"
+        );
+        assert_eq!(
+            at("lib", "plain", Kind::Import).unwrap(),
+            "lib/node_modules/plain/index.js"
+        );
+        assert_eq!(
+            at("lib", "main", Kind::Require).unwrap(),
+            "node_modules/main/lib/main.js"
+        );
+        assert_eq!(
+            at("", "@scope/dual", Kind::Import).unwrap(),
+            "node_modules/@scope/dual/esm/index.js"
+        );
+        assert_eq!(
+            at("", "@scope/dual", Kind::Require).unwrap(),
+            "node_modules/@scope/dual/cjs/index.js"
+        );
+        assert_eq!(
+            at("", "@scope/dual/util/a", Kind::Import).unwrap(),
+            "node_modules/@scope/dual/src/util/a.js"
+        );
+        assert!(matches!(
+            at("", "@scope/dual/src/util/a.js", Kind::Import),
+            Err(ResolveError::Package(_))
+        ));
+        assert_eq!(at("lib", "./x", Kind::Import).unwrap(), "lib/x.js");
+        assert_eq!(at("lib", "/lib/x.js", Kind::Import).unwrap(), "lib/x.js");
+        assert_eq!(at("", "lib/x", Kind::Require).unwrap(), "lib/x.js");
+        assert_eq!(at("", "missing", Kind::Import), Err(ResolveError::NotFound));
+    }
+}
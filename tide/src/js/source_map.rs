@@ -0,0 +1,73 @@
+use sourcemap::SourceMap;
+use std::path::Path;
+
+/// Parses the `N, col M` following a `line ` marker, returning both numbers
+/// and how many bytes they took up.
+fn parse_position(s: &str) -> Option<(u32, u32, usize)> {
+    let line_len = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+    let line = s[..line_len].parse().ok()?;
+    let rest = s[line_len..].strip_prefix(", col ")?;
+    let col_len = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    let col = rest[..col_len].parse().ok()?;
+    Some((line, col, line_len + ", col ".len() + col_len))
+}
+
+/// Rewrites the `line N, col M` positions the engine reports for generated
+/// code into positions in the original file.
+pub(crate) fn translate(map: &SourceMap, file: &Path, message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(i) = rest.find("line ") {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i + "line ".len()..];
+        if let Some((line, col, used)) = parse_position(tail) {
+            if let Some(token) = map.lookup_token(line.saturating_sub(1), col.saturating_sub(1)) {
+                out.push_str(&format!(
+                    "line {}, col {} of {}",
+                    token.get_src_line() + 1,
+                    token.get_src_col() + 1,
+                    file.display()
+                ));
+                rest = &tail[used..];
+                continue;
+            }
+        }
+        out.push_str("line ");
+        rest = tail;
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::js::typescript::transpile;
+
+    #[test]
+    fn position_parsing() {
+        assert_eq!(parse_position("12, col 3 in x"), Some((12, 3, 9)));
+        assert_eq!(parse_position("12 col 3"), None);
+    }
+
+    #[test]
+    fn maps_back_to_typescript() {
+        let ts = "type A = {\n  n: number;\n};\n\nconst a: A = { n: 1 };\nfoo();";
+        let js = transpile(Path::new("a.ts"), ts).unwrap();
+        let line = js.code.lines().position(|l| l.contains("foo()")).unwrap() + 1;
+        let message = format!("ReferenceError: foo is not defined at line {}, col 1", line);
+        assert_eq!(
+            translate(&js.map, Path::new("a.ts"), &message),
+            "ReferenceError: foo is not defined at line 6, col 1 of a.ts"
+        );
+    }
+
+    #[test]
+    fn leaves_other_text() {
+        let js = transpile(Path::new("b.ts"), "const b = 1;").unwrap();
+        assert_eq!(
+            translate(&js.map, Path::new("b.ts"), "baseline deadline"),
+            "baseline deadline"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use super::web::dom_exception;
+use crate::capabilities::{self, Capability};
+use crate::kv::{AppStorage, StorageError};
+use boa_engine::object::{FunctionObjectBuilder, ObjectInitializer};
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsError, JsNativeError, JsResult, JsString, JsValue, NativeFunction,
+};
+use boa_gc::{Finalize, Trace};
+
+#[derive(Trace, Finalize, Clone)]
+struct Storage {
+    #[unsafe_ignore_trace]
+    app: AppStorage,
+}
+
+/// A `QuotaExceededError` `DOMException`, as browsers throw it, or an `Error`
+/// for failures of the store itself.
+fn storage_error(e: StorageError, context: &mut Context<'_>) -> JsError {
+    let message = e.to_string();
+    match e {
+        StorageError::Quota(_) => dom_exception(&message, "QuotaExceededError", context),
+        StorageError::Store(_) => JsNativeError::error().with_message(message).into(),
+    }
+}
+
+fn key_arg(args: &[JsValue], context: &mut Context<'_>) -> JsResult<String> {
+    let key = args.get_or_undefined(0).to_string(context)?;
+    Ok(key.to_std_string_escaped())
+}
+
+fn string_or_null(value: Option<String>) -> JsValue {
+    match value {
+        Some(v) => JsString::from(v.as_str()).into(),
+        None => JsValue::null(),
+    }
+}
+
+/// `storage.getItem(key)`, the value stored under `key` or `null`.
+fn get_item(
+    _: &JsValue,
+    args: &[JsValue],
+    storage: &Storage,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let key = key_arg(args, context)?;
+    match storage.app.get_item(&key) {
+        Ok(v) => Ok(string_or_null(v)),
+        Err(e) => Err(storage_error(e, context)),
+    }
+}
+
+/// `storage.setItem(key, value)`, storing `value` as a string.
+fn set_item(
+    _: &JsValue,
+    args: &[JsValue],
+    storage: &Storage,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let key = key_arg(args, context)?;
+    let value = args.get_or_undefined(1).to_string(context)?;
+    match storage.app.set_item(&key, &value.to_std_string_escaped()) {
+        Ok(()) => Ok(JsValue::undefined()),
+        Err(e) => Err(storage_error(e, context)),
+    }
+}
+
+fn remove_item(
+    _: &JsValue,
+    args: &[JsValue],
+    storage: &Storage,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let key = key_arg(args, context)?;
+    match storage.app.remove_item(&key) {
+        Ok(()) => Ok(JsValue::undefined()),
+        Err(e) => Err(storage_error(e, context)),
+    }
+}
+
+fn clear(
+    _: &JsValue,
+    _: &[JsValue],
+    storage: &Storage,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    match storage.app.clear() {
+        Ok(()) => Ok(JsValue::undefined()),
+        Err(e) => Err(storage_error(e, context)),
+    }
+}
+
+/// `storage.key(index)`, the name of the `index`th item or `null`.
+fn key(
+    _: &JsValue,
+    args: &[JsValue],
+    storage: &Storage,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let index = args.get_or_undefined(0).to_length(context)?;
+    match storage.app.key(index as usize) {
+        Ok(v) => Ok(string_or_null(v)),
+        Err(e) => Err(storage_error(e, context)),
+    }
+}
+
+fn length(_: &JsValue, _: &[JsValue], storage: &Storage, _: &mut Context<'_>) -> JsResult<JsValue> {
+    Ok(storage.app.len().into())
+}
+
+type Method = fn(&JsValue, &[JsValue], &Storage, &mut Context<'_>) -> JsResult<JsValue>;
+
+/// `f`, throwing a `NotAllowedError` for handlers without the db capability.
+fn granted(
+    f: Method,
+) -> impl Fn(&JsValue, &[JsValue], &Storage, &mut Context<'_>) -> JsResult<JsValue> + Copy {
+    move |this, args, storage, context| {
+        capabilities::require(Capability::Db)
+            .map_err(|e| dom_exception(&e, "NotAllowedError", context))?;
+        f(this, args, storage, context)
+    }
+}
+
+/// Installs the `storage` object, also reachable as `localStorage`, holding
+/// the items of the app across requests.
+pub(crate) fn register(app: AppStorage, context: &mut Context<'_>) -> JsResult<()> {
+    let storage = Storage { app };
+    let methods: [(&str, usize, Method); 5] = [
+        ("getItem", 1, get_item),
+        ("setItem", 2, set_item),
+        ("removeItem", 1, remove_item),
+        ("clear", 0, clear),
+        ("key", 1, key),
+    ];
+    let getter = FunctionObjectBuilder::new(
+        context.realm(),
+        NativeFunction::from_copy_closure_with_captures(length, storage.clone()),
+    )
+    .name("get length")
+    .length(0)
+    .build();
+    let mut object = ObjectInitializer::new(context);
+    for (name, length, f) in methods {
+        object.function(
+            NativeFunction::from_copy_closure_with_captures(granted(f), storage.clone()),
+            name,
+            length,
+        );
+    }
+    let object = object
+        .accessor(
+            js_string!("length"),
+            Some(getter),
+            None,
+            Attribute::CONFIGURABLE,
+        )
+        .build();
+    context.register_global_property(js_string!("storage"), object.clone(), Attribute::all())?;
+    context.register_global_property(js_string!("localStorage"), object, Attribute::all())
+}
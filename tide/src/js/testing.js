@@ -0,0 +1,98 @@
+// `test`, `assert` and `request` of `*.test.js` files. See testing.rs for
+// what runs the tests `host.tests` collects.
+(function (host, web) {
+  "use strict";
+
+  host.tests = [];
+
+  globalThis.test = function test(name, fn) {
+    if (typeof fn !== "function") {
+      throw new TypeError(`test ${name} needs a function`);
+    }
+    host.tests.push({ name: String(name), fn });
+  };
+
+  function show(value) {
+    return web.format("%o", value);
+  }
+
+  class AssertionError extends Error {}
+  Object.defineProperty(AssertionError.prototype, "name", { value: "AssertionError" });
+
+  function fail(message, fallback) {
+    throw new AssertionError(message === undefined ? fallback : String(message));
+  }
+
+  function deepEqual(a, b) {
+    if (Object.is(a, b)) return true;
+    if (typeof a !== "object" || typeof b !== "object" || a === null || b === null) {
+      return false;
+    }
+    if (Array.isArray(a) !== Array.isArray(b)) return false;
+    const keys = Object.keys(a);
+    if (keys.length !== Object.keys(b).length) return false;
+    return keys.every((k) => Object.prototype.hasOwnProperty.call(b, k) && deepEqual(a[k], b[k]));
+  }
+
+  function assert(value, message) {
+    if (!value) fail(message, `expected ${show(value)} to be truthy`);
+  }
+  assert.AssertionError = AssertionError;
+  assert.ok = assert;
+  assert.equal = function equal(actual, expected, message) {
+    if (!Object.is(actual, expected)) {
+      fail(message, `expected ${show(actual)} to equal ${show(expected)}`);
+    }
+  };
+  assert.notEqual = function notEqual(actual, expected, message) {
+    if (Object.is(actual, expected)) {
+      fail(message, `expected ${show(actual)} not to equal ${show(expected)}`);
+    }
+  };
+  assert.deepEqual = function deepEqualAssert(actual, expected, message) {
+    if (!deepEqual(actual, expected)) {
+      fail(message, `expected ${show(actual)} to deeply equal ${show(expected)}`);
+    }
+  };
+  assert.throws = function throws(fn, message) {
+    try {
+      fn();
+    } catch (e) {
+      return e;
+    }
+    fail(message, "expected the function to throw");
+  };
+  assert.rejects = async function rejects(promise, message) {
+    try {
+      await (typeof promise === "function" ? promise() : promise);
+    } catch (e) {
+      return e;
+    }
+    fail(message, "expected the promise to reject");
+  };
+  globalThis.assert = assert;
+
+  // `request(path, { method, headers, body })` runs the handler at `path`
+  // with a made up request. Objects given as `body` are sent as json.
+  globalThis.request = function request(path, options = {}) {
+    const method = String(options.method ?? "GET").toUpperCase();
+    const headers = Object.entries(options.headers ?? {}).map(([k, v]) => [String(k), String(v)]);
+    let body = options.body;
+    if (body !== undefined && typeof body !== "string") {
+      body = JSON.stringify(body);
+      if (!headers.some(([k]) => k.toLowerCase() === "content-type")) {
+        headers.push(["content-type", "application/json"]);
+      }
+    }
+    const [status, pairs, text] = host.request(String(path), method, headers, body ?? "");
+    return {
+      status,
+      ok: status >= 200 && status < 300,
+      headers: Object.fromEntries(pairs.map(([k, v]) => [k.toLowerCase(), v])),
+      body: text,
+      json() {
+        return JSON.parse(text);
+      },
+    };
+  };
+})
@@ -0,0 +1,220 @@
+//! Runs the `*.test.js` and `*.test.ts` files of a [`JsDir`], see
+//! [`crate::testing`] and testing.js.
+
+use super::{modules, run, standalone, typescript, web, worker, Incoming, Reply, Runtime};
+use crate::rt;
+use crate::testing::{app_file, discover, is_test, timed, TestReport, FILE};
+use crate::JsDir;
+use async_std::channel;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::{
+    js_string, Context, JsArgs, JsArray, JsNativeError, JsObject, JsResult, JsValue,
+    NativeFunction, Source,
+};
+use boa_gc::{Finalize, Trace};
+use futures::StreamExt;
+use std::path::Path;
+
+const TESTING: &str = include_str!("testing.js");
+
+#[derive(Trace, Finalize, Clone)]
+struct Host {
+    #[unsafe_ignore_trace]
+    runtime: Runtime,
+}
+
+impl JsDir {
+    /// Runs the `*.test.js` and `*.test.ts` files of the directory. Every
+    /// `test(name, fn)` a file calls is a test, passing unless `fn` throws or
+    /// the promise it returns rejects, and a file without any is a test as a
+    /// whole.
+    ///
+    /// Besides the globals of handlers tests get `assert(value)` with
+    /// `assert.equal`, `notEqual`, `deepEqual`, `throws` and `rejects`, and
+    /// `request(path, { method, headers, body })`, which runs the handler at
+    /// `path` and returns `{ status, ok, headers, body, json() }`.
+    ///```
+    /// use tide_rhai::JsDir;
+    /// let js = JsDir::new("/js/*", "./examples/app/").unwrap();
+    /// let report = js.run_tests();
+    /// print!("{}", report);
+    ///```
+    pub fn run_tests(&self) -> TestReport {
+        let runtime = self.runtime();
+        let mut report = TestReport::default();
+        for file in discover(&self.dir, |n| {
+            n.ends_with(".test.js") || n.ends_with(".test.ts")
+        }) {
+            let name = file.strip_prefix(&self.dir).unwrap_or(&file);
+            let dir = file.parent().unwrap_or(&self.dir);
+            let ran = standalone(
+                &runtime,
+                &name.to_string_lossy(),
+                dir,
+                |context, prepared| {
+                    let host = Host {
+                        runtime: runtime.clone(),
+                    };
+                    let host = ObjectInitializer::new(context)
+                        .function(
+                            NativeFunction::from_copy_closure_with_captures(request, host),
+                            "request",
+                            4,
+                        )
+                        .build();
+                    worker::install("testing.js", TESTING, &host, &prepared.natives, context)?;
+                    let name = name.to_path_buf();
+                    let loaded = load(&runtime, &file, context, prepared.loader);
+                    if let Err(e) = loaded {
+                        return Ok(vec![timed(&name, FILE, || Err(e.to_string()))]);
+                    }
+                    let tests = host.get(js_string!("tests"), context)?;
+                    let tests = tests
+                        .as_object()
+                        .cloned()
+                        .map(JsArray::from_object)
+                        .transpose()?;
+                    let Some(tests) = tests.filter(|t| t.length(context).map_or(false, |n| n > 0))
+                    else {
+                        return Ok(vec![timed(&name, FILE, || Ok(()))]);
+                    };
+                    let mut results = Vec::new();
+                    for i in 0..tests.length(context)? {
+                        let test = tests.get(i, context)?;
+                        let test = test
+                            .as_object()
+                            .cloned()
+                            .unwrap_or_else(|| JsObject::with_null_proto());
+                        let title = test
+                            .get(js_string!("name"), context)?
+                            .to_string(context)?
+                            .to_std_string_escaped();
+                        let f = test.get(js_string!("fn"), context)?;
+                        prepared.jobs.restart();
+                        results.push(timed(&name, &title, || {
+                            let f = f
+                                .as_callable()
+                                .ok_or_else(|| format!("test {} has no function", title))?;
+                            f.call(&JsValue::undefined(), &[], context)
+                                .and_then(|v| prepared.jobs.settle(v, context))
+                                .map(|_| ())
+                                .map_err(|e| e.to_string())
+                        }));
+                    }
+                    Ok(results)
+                },
+            );
+            match ran {
+                Ok(results) => report.results.extend(results),
+                Err(e) => report
+                    .results
+                    .push(timed(name, FILE, || Err(e.to_string()))),
+            }
+        }
+        report
+    }
+}
+
+/// Evaluates the test file at `path`, which registers its tests.
+fn load(
+    runtime: &Runtime,
+    path: &Path,
+    context: &mut Context<'_>,
+    loader: &modules::AppLoader,
+) -> JsResult<()> {
+    let integrity = runtime.integrity.as_deref();
+    let source = typescript::read_js(runtime.snapshot.as_deref(), integrity, &runtime.root, path)?;
+    if modules::is_module(path, &source) {
+        modules::evaluate(loader, path, &source, context)?;
+    } else {
+        context.eval(Source::from_bytes(&source))?;
+    }
+    context.run_jobs();
+    Ok(())
+}
+
+/// `host.request(path, method, headers, body)`, `[status, headers, body]`
+/// of the handler at `path`. It runs in a context of its own on another
+/// thread, like the handlers of requests, which this one waits for.
+fn request(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let path = args
+        .get_or_undefined(0)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let method = args
+        .get_or_undefined(1)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let headers: Vec<(String, String)> =
+        serde_json::from_value(args.get_or_undefined(2).to_json(context)?).unwrap_or_default();
+    let body = args
+        .get_or_undefined(3)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let fail = |e: String| JsNativeError::error().with_message(e);
+
+    let runtime = &host.runtime;
+    let (route, _) = path.split_once('?').unwrap_or((&path, ""));
+    let file = app_file(&runtime.root, route)
+        .filter(|f| !is_test(f))
+        .ok_or_else(|| fail(format!("Cannot request {}", path)))?;
+    let integrity = runtime.integrity.as_deref();
+    let source = typescript::read_js(runtime.snapshot.as_deref(), integrity, &runtime.root, &file)?;
+    let method: http_types::Method = method
+        .parse()
+        .map_err(|_| fail(format!("Unknown method {}", method)))?;
+    let incoming = Incoming {
+        ctx: crate::Context::from_parts(headers.iter().cloned().collect(), method, body.as_bytes()),
+        http: web::HttpRequest {
+            method: method.to_string(),
+            url: format!("http://localhost/{}", path.trim_start_matches('/')),
+            headers,
+            body: body.into_bytes(),
+        },
+        globals: Vec::new(),
+    };
+    let (reply, replies) = channel::bounded(1);
+    let runtime = runtime.clone();
+    std::thread::spawn(move || run(&runtime, &file, &source, &incoming, reply));
+    let reply = rt::block_on(replies.recv())
+        .unwrap_or_else(|_| Err("The script thread stopped".to_string()));
+    let (status, headers, body) = match reply {
+        Ok(Reply::Json(v)) => (200, Vec::new(), v.to_string().into_bytes()),
+        Ok(Reply::Error(e)) => (
+            e.status,
+            e.headers.clone(),
+            e.body().to_string().into_bytes(),
+        ),
+        Ok(Reply::Http(r)) => (r.status, r.headers, r.body),
+        Ok(Reply::Stream(r, chunks)) => {
+            let body: Vec<Vec<u8>> = rt::block_on(chunks.collect());
+            (r.status, r.headers, body.concat())
+        }
+        Err(e) => (
+            500,
+            Vec::new(),
+            serde_json::json!({ "error": e }).to_string().into_bytes(),
+        ),
+    };
+    let headers: Vec<JsValue> = headers
+        .into_iter()
+        .map(|(k, v)| {
+            JsArray::from_iter([js_string!(k).into(), js_string!(v).into()], context).into()
+        })
+        .collect();
+    let body = String::from_utf8_lossy(&body).into_owned();
+    Ok(JsArray::from_iter(
+        [
+            status.into(),
+            JsArray::from_iter(headers, context).into(),
+            js_string!(body).into(),
+        ],
+        context,
+    )
+    .into())
+}
@@ -0,0 +1,177 @@
+use crate::integrity::{self, Integrity};
+use crate::snapshot::Snapshot;
+use boa_engine::{JsNativeError, JsResult};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use swc_common::comments::SingleThreadedComments;
+use swc_common::sync::Lrc;
+use swc_common::{FileName, Globals, Mark, SourceMap, GLOBALS};
+use swc_ecma_codegen::text_writer::JsWriter;
+use swc_ecma_codegen::{Config, Emitter};
+use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax, TsConfig};
+use swc_ecma_transforms_base::{fixer::fixer, hygiene::hygiene, resolver};
+use swc_ecma_transforms_typescript::strip;
+use swc_ecma_visit::FoldWith;
+
+/// A TypeScript or JavaScript syntax error, pointing at the offending position.
+#[derive(Debug, Clone)]
+pub(crate) struct TranspileError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for TranspileError {}
+
+/// JavaScript produced from a TypeScript file, with the map back to the original.
+#[derive(Clone)]
+pub(crate) struct Transpiled {
+    pub code: Arc<str>,
+    pub map: Arc<sourcemap::SourceMap>,
+}
+
+/// Transpiled output by hash of the TypeScript source, shared by all handlers.
+fn cache() -> &'static Mutex<HashMap<u64, Transpiled>> {
+    static CACHE: OnceLock<Mutex<HashMap<u64, Transpiled>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(crate) fn is_typescript(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |e| e == "ts" || e == "mts" || e == "cts")
+}
+
+/// Strips the types from `source`, reusing earlier output for identical sources.
+pub(crate) fn transpile(path: &Path, source: &str) -> Result<Transpiled, TranspileError> {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let key = hasher.finish();
+    if let Some(js) = cache().lock().unwrap().get(&key) {
+        return Ok(js.clone());
+    }
+
+    let js = strip_types(path, source)?;
+    cache().lock().unwrap().insert(key, js.clone());
+    Ok(js)
+}
+
+/// Reads an imported or required file, transpiling it if it is TypeScript.
+/// Files from the snapshot are used as they are, others are checked against
+/// `integrity`.
+pub(crate) fn read_js(
+    snapshot: Option<&Snapshot>,
+    integrity: Option<&Integrity>,
+    root: &Path,
+    path: &Path,
+) -> JsResult<String> {
+    if let Some(entry) = snapshot.and_then(|s| s.get(root, path)) {
+        return Ok(entry.code.to_string());
+    }
+    let source = integrity::read(integrity, path).map_err(|e| {
+        JsNativeError::typ().with_message(format!("Cannot read module {:?}: {}", path, e))
+    })?;
+    if !is_typescript(path) {
+        return Ok(source);
+    }
+    match transpile(path, &source) {
+        Ok(js) => Ok(js.code.to_string()),
+        Err(e) => Err(JsNativeError::syntax().with_message(e.to_string()).into()),
+    }
+}
+
+fn strip_types(path: &Path, source: &str) -> Result<Transpiled, TranspileError> {
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(FileName::Real(path.to_path_buf()), source.into());
+    let comments = SingleThreadedComments::default();
+    let error = |span: swc_common::Span, message: String| {
+        let loc = cm.lookup_char_pos(span.lo);
+        TranspileError {
+            file: path.display().to_string(),
+            line: loc.line,
+            column: loc.col_display + 1,
+            message,
+        }
+    };
+
+    let lexer = Lexer::new(
+        Syntax::Typescript(TsConfig::default()),
+        Default::default(),
+        StringInput::from(&*fm),
+        Some(&comments),
+    );
+    let mut parser = Parser::new_from(lexer);
+    let program = parser
+        .parse_program()
+        .map_err(|e| error(e.span(), e.into_kind().msg().to_string()))?;
+    if let Some(e) = parser.take_errors().into_iter().next() {
+        return Err(error(e.span(), e.into_kind().msg().to_string()));
+    }
+
+    let globals = Globals::default();
+    let program = GLOBALS.set(&globals, || {
+        let unresolved_mark = Mark::new();
+        let top_level_mark = Mark::new();
+        program
+            .fold_with(&mut resolver(unresolved_mark, top_level_mark, true))
+            .fold_with(&mut strip(top_level_mark))
+            .fold_with(&mut hygiene())
+            .fold_with(&mut fixer(Some(&comments)))
+    });
+
+    let mut buf = Vec::new();
+    let mut mappings = Vec::new();
+    {
+        let mut emitter = Emitter {
+            cfg: Config::default(),
+            cm: cm.clone(),
+            comments: Some(&comments),
+            wr: JsWriter::new(cm.clone(), "\n", &mut buf, Some(&mut mappings)),
+        };
+        emitter
+            .emit_program(&program)
+            .expect("writing to a Vec cannot fail");
+    }
+    let map = cm.build_source_map(&mut mappings);
+    Ok(Transpiled {
+        code: String::from_utf8_lossy(&buf).into(),
+        map: Arc::new(map),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strips_types() {
+        let js = transpile(
+            Path::new("a.ts"),
+            "interface A { n: number }\nconst a: A = { n: 1 };\nexport default a as A;",
+        )
+        .unwrap();
+        assert!(!js.code.contains("interface"));
+        assert!(js.code.contains("export default a"));
+    }
+
+    #[test]
+    fn reports_position() {
+        let err = transpile(Path::new("b.ts"), "const a: = 1;").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.file, "b.ts");
+    }
+}
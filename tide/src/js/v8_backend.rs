@@ -0,0 +1,156 @@
+//! Runs handlers on V8, through the `v8` crate, for the `v8` feature.
+//!
+//! Every request gets an isolate of its own with `ctx` and `console`. Plain
+//! scripts answer with their completion value and handler functions are
+//! called with `ctx`, promises included. The web APIs, modules and workers of
+//! the default engine are not there yet.
+
+use super::backend::{Job, JsBackend};
+use super::{script_name, Reply, Runtime};
+use crate::rt;
+use serde_json::Value;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Once;
+use tide::log;
+
+pub(super) struct V8 {
+    runtime: Runtime,
+}
+
+impl V8 {
+    pub(super) fn new(runtime: Runtime) -> Self {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let platform = v8::new_default_platform(0, false).make_shared();
+            v8::V8::initialize_platform(platform);
+            v8::V8::initialize();
+        });
+        Self { runtime }
+    }
+}
+
+impl JsBackend for V8 {
+    fn run(&self, job: Job) {
+        let runtime = self.runtime.clone();
+        rt::spawn_blocking(move || {
+            let res = evaluate(&runtime, &job);
+            let _ = job.reply.try_send(res.map(Reply::Json));
+        });
+    }
+}
+
+/// The script `console` logs for, in a slot of the isolate.
+struct ScriptPath(String);
+
+/// `console.log` and friends, the level being the data of the function.
+fn console(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, _: v8::ReturnValue) {
+    let parts: Vec<String> = (0..args.length())
+        .map(|i| args.get(i).to_rust_string_lossy(scope))
+        .collect();
+    let message = parts.join(" ");
+    let level = args.data().to_rust_string_lossy(scope);
+    let path = scope
+        .get_slot::<ScriptPath>()
+        .map(|p| p.0.clone())
+        .unwrap_or_default();
+    match level.as_str() {
+        "debug" => log::debug!("{}: {}", path, message),
+        "warn" => log::warn!("{}: {}", path, message),
+        "error" => log::error!("{}: {}", path, message),
+        _ => log::info!("{}: {}", path, message),
+    }
+}
+
+/// Runs `job` in a new isolate, stopping it once the timeout passes.
+fn evaluate(runtime: &Runtime, job: &Job) -> Result<Value, String> {
+    let isolate = &mut v8::Isolate::new(v8::CreateParams::default());
+    isolate.set_slot(ScriptPath(script_name(&runtime.root, &job.path)));
+    let handle = isolate.thread_safe_handle();
+    let (done, finished) = mpsc::channel::<()>();
+    let timeout = runtime.timeout;
+    std::thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+            log::warn!("Script did not finish within {:?}", timeout);
+            handle.terminate_execution();
+        }
+    });
+    let res = run_script(isolate, job);
+    drop(done);
+    res
+}
+
+fn exception(scope: &mut v8::TryCatch<v8::HandleScope>) -> String {
+    if scope.has_terminated() {
+        return "Script did not finish in time".to_string();
+    }
+    match scope.exception() {
+        Some(e) => e.to_rust_string_lossy(scope),
+        None => "Script failed".to_string(),
+    }
+}
+
+fn run_script(isolate: &mut v8::OwnedIsolate, job: &Job) -> Result<Value, String> {
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = v8::Context::new(scope);
+    let scope = &mut v8::ContextScope::new(scope, context);
+    let global = context.global(scope);
+
+    let console_object = v8::Object::new(scope);
+    for level in ["log", "info", "debug", "warn", "error"] {
+        let name = v8::String::new(scope, level).unwrap();
+        let f = v8::Function::builder(console)
+            .data(name.into())
+            .build(scope)
+            .unwrap();
+        console_object.set(scope, name.into(), f.into());
+    }
+    let name = v8::String::new(scope, "console").unwrap();
+    global.set(scope, name.into(), console_object.into());
+
+    let ctx = serde_json::to_string(&job.incoming.ctx).map_err(|e| e.to_string())?;
+    let ctx = v8::String::new(scope, &ctx).ok_or("ctx is too large")?;
+    let ctx = v8::json::parse(scope, ctx).ok_or("ctx is not valid json")?;
+    let name = v8::String::new(scope, "ctx").unwrap();
+    global.set(scope, name.into(), ctx);
+
+    let scope = &mut v8::TryCatch::new(scope);
+    let source = v8::String::new(scope, &job.source).ok_or("The script is too large")?;
+    let result = match v8::Script::compile(scope, source, None).and_then(|s| s.run(scope)) {
+        Some(r) => r,
+        None => return Err(exception(scope)),
+    };
+    let result = match v8::Local::<v8::Function>::try_from(result) {
+        Ok(f) => {
+            let this = v8::undefined(scope).into();
+            match f.call(scope, this, &[ctx]) {
+                Some(r) => r,
+                None => return Err(exception(scope)),
+            }
+        }
+        Err(_) => result,
+    };
+    scope.perform_microtask_checkpoint();
+    let result = match v8::Local::<v8::Promise>::try_from(result) {
+        Ok(promise) => match promise.state() {
+            v8::PromiseState::Fulfilled => promise.result(scope),
+            v8::PromiseState::Rejected => {
+                let reason = promise.result(scope);
+                return Err(reason.to_rust_string_lossy(scope));
+            }
+            v8::PromiseState::Pending => {
+                return Err("The script never settled its promise, timers and fetch \
+                            need the default engine"
+                    .to_string())
+            }
+        },
+        Err(_) => result,
+    };
+    if result.is_undefined() {
+        return Ok(Value::Null);
+    }
+    let json = match v8::json::stringify(scope, result) {
+        Some(j) => j.to_rust_string_lossy(scope),
+        None => return Err(exception(scope)),
+    };
+    serde_json::from_str(&json).map_err(|e| e.to_string())
+}
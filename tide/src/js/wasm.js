@@ -0,0 +1,153 @@
+// `WebAssembly` and `loadWasm`. See wasm.rs for what runs the modules.
+(function (host, web) {
+  "use strict";
+
+  class CompileError extends Error {}
+  class LinkError extends Error {}
+  class RuntimeError extends Error {}
+  for (const E of [CompileError, LinkError, RuntimeError]) {
+    Object.defineProperty(E.prototype, "name", { value: E.name });
+  }
+  const ERRORS = { CompileError, LinkError, RuntimeError };
+
+  // Runs `f`, throwing the errors the natives name by their class.
+  function guard(f) {
+    try {
+      return f();
+    } catch (e) {
+      const m = e instanceof Error && /^(CompileError|LinkError|RuntimeError): ([\s\S]*)$/.exec(e.message);
+      throw m ? new ERRORS[m[1]](m[2]) : e;
+    }
+  }
+
+  const ID = Symbol("wasm id");
+  const HIDDEN = Symbol("wasm internal");
+
+  class Module {
+    constructor(bytes, hidden) {
+      const id = hidden === HIDDEN ? bytes : guard(() => host.wasmCompile(bytes));
+      Object.defineProperty(this, ID, { value: id });
+    }
+
+    static imports(module) {
+      return host.wasmImports(module[ID]).map(([module, name, kind]) => ({ module, name, kind }));
+    }
+
+    static exports(module) {
+      return host.wasmExports(module[ID]).map(([name, kind]) => ({ name, kind }));
+    }
+  }
+
+  // A memory exported by an instance. It cannot be shared with the engine, so
+  // `buffer` is a copy and `read` and `write` are there to work on it.
+  class Memory {
+    constructor(instance, name, hidden) {
+      if (hidden !== HIDDEN) {
+        throw new TypeError("Memories can only be exported by instances");
+      }
+      Object.defineProperty(this, ID, { value: [instance, name] });
+    }
+
+    get buffer() {
+      return this.read(0).buffer;
+    }
+
+    grow(pages) {
+      return host.wasmMemoryGrow(...this[ID], pages);
+    }
+
+    get byteLength() {
+      return host.wasmMemorySize(...this[ID]);
+    }
+
+    read(offset = 0, length) {
+      return host.wasmMemoryRead(...this[ID], offset, length);
+    }
+
+    write(offset, bytes) {
+      host.wasmMemoryWrite(...this[ID], offset, bytes);
+    }
+  }
+
+  class Global {
+    constructor(instance, name, hidden) {
+      if (hidden !== HIDDEN) {
+        throw new TypeError("Globals can only be exported by instances");
+      }
+      Object.defineProperty(this, ID, { value: [instance, name] });
+    }
+
+    get value() {
+      return host.wasmGlobal(...this[ID]);
+    }
+
+    valueOf() {
+      return this.value;
+    }
+  }
+
+  class Instance {
+    constructor(module, importObject = {}) {
+      if (!(module instanceof Module)) {
+        throw new TypeError("WebAssembly.Instance needs a WebAssembly.Module");
+      }
+      const functions = [];
+      for (const { module: from, name, kind } of Module.imports(module)) {
+        const value = importObject?.[from]?.[name];
+        if (kind === "function" && typeof value !== "function") {
+          throw new LinkError(`Import ${from}.${name} is not a function`);
+        }
+        if (kind === "function") functions.push(value);
+      }
+      const id = guard(() => host.wasmInstantiate(module[ID]));
+      const dispatch = (index, args) => functions[index](...args);
+      const exports = Object.create(null);
+      for (const { name, kind } of Module.exports(module)) {
+        if (kind === "function") {
+          exports[name] = (...args) => guard(() => host.wasmCall(id, name, dispatch, ...args));
+        } else if (kind === "memory") {
+          exports[name] = new Memory(id, name, HIDDEN);
+        } else if (kind === "global") {
+          exports[name] = new Global(id, name, HIDDEN);
+        }
+      }
+      Object.defineProperty(this, "exports", { value: Object.freeze(exports), enumerable: true });
+    }
+  }
+
+  async function compile(bytes) {
+    return new Module(bytes);
+  }
+
+  async function instantiate(source, importObject) {
+    if (source instanceof Module) {
+      return new Instance(source, importObject);
+    }
+    const module = new Module(source);
+    return { module, instance: new Instance(module, importObject) };
+  }
+
+  function validate(bytes) {
+    return host.wasmValidate(bytes);
+  }
+
+  globalThis.WebAssembly = {
+    Module,
+    Instance,
+    Memory,
+    Global,
+    CompileError,
+    LinkError,
+    RuntimeError,
+    compile,
+    instantiate,
+    validate,
+  };
+
+  // `loadWasm(path, imports)`, an instance of the module in the app at
+  // `path`, relative to this script or to the root if it starts with `/`.
+  globalThis.loadWasm = async function loadWasm(path, importObject) {
+    const module = new Module(guard(() => host.wasmLoad(String(path))), HIDDEN);
+    return new Instance(module, importObject);
+  };
+})
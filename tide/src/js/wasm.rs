@@ -0,0 +1,620 @@
+//! `WebAssembly` and `loadWasm(path)`: modules compiled and run by wasmtime,
+//! for the compute-heavy parts of a handler, like codecs and parsers.
+//!
+//! A module only gets the imports the script hands it, functions of the
+//! script, no WASI and no access to the host. Instances stop at the deadline
+//! of the script and their memories are capped at [`MAX_MEMORY`]. Memories
+//! live in wasmtime, not in the engine, so `memory.buffer` is a copy and
+//! scripts write with `memory.write(offset, bytes)` instead.
+
+use super::{web, worker, Runtime};
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::{
+    Context, JsArgs, JsBigInt, JsError, JsNativeError, JsObject, JsResult, JsValue, NativeFunction,
+};
+use boa_gc::{Finalize, Trace};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tide::log;
+use wasmtime::{
+    Caller, Config, Engine, ExternType, Func, Module, Store, StoreLimits, StoreLimitsBuilder, Val,
+    ValType,
+};
+
+const WASM: &str = include_str!("wasm.js");
+
+/// How much memory, in bytes, each instance may grow its memories to.
+const MAX_MEMORY: usize = 256 << 20;
+
+/// How often the deadlines of instances are checked.
+const TICK: Duration = Duration::from_millis(10);
+
+/// The engine every module is compiled for, with a thread driving deadlines.
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).expect("the wasmtime configuration is valid");
+        let ticking = engine.clone();
+        let spawned = std::thread::Builder::new()
+            .name("wasm-epoch".into())
+            .spawn(move || loop {
+                std::thread::sleep(TICK);
+                ticking.increment_epoch();
+            });
+        if let Err(e) = spawned {
+            log::error!("Cannot start the WebAssembly deadline thread: {}", e);
+        }
+        engine
+    })
+}
+
+/// Modules loaded with `loadWasm`, by path, with the time the file was
+/// modified when it was compiled.
+static COMPILED: OnceLock<Mutex<HashMap<PathBuf, (SystemTime, Module)>>> = OnceLock::new();
+
+/// How many modules [`compiled`] keeps.
+pub(super) fn cached_modules() -> usize {
+    COMPILED.get().map_or(0, |c| c.lock().unwrap().len())
+}
+
+/// A module loaded with `loadWasm`, compiled again only when the file changes.
+fn compiled(path: &Path) -> Result<Module, String> {
+    let modified = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("Cannot load {}: {}", path.display(), e))?;
+    let cache = COMPILED.get_or_init(Default::default);
+    if let Some((at, module)) = cache.lock().unwrap().get(path) {
+        if *at == modified {
+            return Ok(module.clone());
+        }
+    }
+    let bytes =
+        std::fs::read(path).map_err(|e| format!("Cannot load {}: {}", path.display(), e))?;
+    let module = Module::new(engine(), bytes).map_err(|e| format!("CompileError: {}", e))?;
+    cache
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (modified, module.clone()));
+    Ok(module)
+}
+
+/// What the host calls of an instance see.
+struct State {
+    limits: StoreLimits,
+    /// Set for as long as a call into the instance runs.
+    call: Option<Call>,
+    /// What an import threw, to throw again once the call unwinds.
+    thrown: Option<JsError>,
+}
+
+/// The context of the script calling into an instance, and its function
+/// that calls the imports, `dispatch(index, args)`.
+struct Call {
+    context: *mut (),
+    dispatch: JsObject,
+}
+
+struct Instance {
+    store: Store<State>,
+    instance: wasmtime::Instance,
+}
+
+#[derive(Default)]
+struct Wasm {
+    next_id: u32,
+    modules: HashMap<u32, Module>,
+    instances: HashMap<u32, Instance>,
+}
+
+impl Wasm {
+    fn add_module(&mut self, module: Module) -> u32 {
+        self.next_id += 1;
+        self.modules.insert(self.next_id, module);
+        self.next_id
+    }
+}
+
+#[derive(Trace, Finalize, Clone)]
+struct Host {
+    #[unsafe_ignore_trace]
+    root: PathBuf,
+    /// The directory of the script, which module paths are relative to.
+    #[unsafe_ignore_trace]
+    dir: PathBuf,
+    #[unsafe_ignore_trace]
+    timeout: Duration,
+    #[unsafe_ignore_trace]
+    wasm: Rc<RefCell<Wasm>>,
+}
+
+/// An error for wasm.js to throw as `WebAssembly.<kind>`.
+fn wasm_error(kind: &str, e: impl Display) -> JsError {
+    JsNativeError::error()
+        .with_message(format!("{}: {}", kind, e))
+        .into()
+}
+
+fn kind(ty: &ExternType) -> &'static str {
+    match ty {
+        ExternType::Func(_) => "function",
+        ExternType::Global(_) => "global",
+        ExternType::Table(_) => "table",
+        ExternType::Memory(_) => "memory",
+    }
+}
+
+fn to_val(value: &JsValue, ty: &ValType, context: &mut Context<'_>) -> JsResult<Val> {
+    Ok(match ty {
+        ValType::I32 => Val::I32(value.to_i32(context)?),
+        ValType::I64 => {
+            let big = value.to_bigint(context)?.to_string();
+            let n: i128 = big.parse().map_err(|_| {
+                JsNativeError::range().with_message(format!("{} does not fit in an i64", big))
+            })?;
+            Val::I64(n as i64)
+        }
+        ValType::F32 => Val::F32((value.to_number(context)? as f32).to_bits()),
+        ValType::F64 => Val::F64(value.to_number(context)?.to_bits()),
+        ty => {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Values of type {} cannot be passed", ty))
+                .into())
+        }
+    })
+}
+
+fn from_val(val: &Val) -> JsResult<JsValue> {
+    Ok(match val {
+        Val::I32(n) => (*n).into(),
+        Val::I64(n) => JsBigInt::from(*n).into(),
+        Val::F32(bits) => (f32::from_bits(*bits) as f64).into(),
+        Val::F64(bits) => f64::from_bits(*bits).into(),
+        val => {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Values of type {} cannot be returned", val.ty()))
+                .into())
+        }
+    })
+}
+
+/// `results` as they come back from a JavaScript function: nothing, the
+/// value, or an array for several.
+fn set_results(
+    out: &JsValue,
+    results: &mut [Val],
+    types: &[ValType],
+    context: &mut Context<'_>,
+) -> JsResult<()> {
+    match results.len() {
+        0 => {}
+        1 => results[0] = to_val(out, &types[0], context)?,
+        _ => {
+            let out = out.as_object().ok_or_else(|| {
+                JsNativeError::typ().with_message("An import with several results returns an array")
+            })?;
+            for (i, ty) in types.iter().enumerate() {
+                let value = out.get(i, context)?;
+                results[i] = to_val(&value, ty, context)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Calls the import at `index` for the instance running in `caller`.
+fn call_import(
+    caller: &mut Caller<'_, State>,
+    index: usize,
+    params: &[Val],
+    results: &mut [Val],
+    types: &[ValType],
+) -> wasmtime::Result<()> {
+    let Some(call) = caller.data().call.as_ref() else {
+        return Err(wasmtime::Error::msg("Imports can only run during a call"));
+    };
+    let (context, dispatch) = (call.context, call.dispatch.clone());
+    // SAFETY: `call` is only set by `call` below, around `Func::call`, while
+    // it holds the context mutably and does nothing else with it.
+    let context = unsafe { &mut *(context as *mut Context<'_>) };
+    let res = (|| {
+        let args = params.iter().map(from_val).collect::<JsResult<Vec<_>>>()?;
+        let args = JsArray::from_iter(args, context);
+        let out = dispatch.call(&JsValue::undefined(), &[index.into(), args.into()], context)?;
+        set_results(&out, results, types, context)
+    })();
+    res.map_err(|e| {
+        caller.data_mut().thrown = Some(e);
+        wasmtime::Error::msg("An import threw")
+    })
+}
+
+fn module_id(args: &[JsValue], context: &mut Context<'_>) -> JsResult<u32> {
+    args.get_or_undefined(0).to_u32(context)
+}
+
+/// `host.wasmCompile(bytes)`, the id of the module. Bytes may also be the
+/// text format.
+fn compile(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let bytes = web::to_bytes(args.get_or_undefined(0), context)?;
+    let module = Module::new(engine(), bytes).map_err(|e| wasm_error("CompileError", e))?;
+    Ok(host.wasm.borrow_mut().add_module(module).into())
+}
+
+/// `host.wasmValidate(bytes)`.
+fn validate(
+    _: &JsValue,
+    args: &[JsValue],
+    _: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let bytes = web::to_bytes(args.get_or_undefined(0), context)?;
+    Ok(Module::validate(engine(), &bytes).is_ok().into())
+}
+
+/// `host.wasmLoad(path)`, the id of the module in the file at `path`, relative
+/// to the directory of the script, or to the root if it starts with `/`.
+fn load(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let url = args
+        .get_or_undefined(0)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let path = worker::resolve(&host.root, &host.dir, &url)
+        .map_err(|e| JsNativeError::typ().with_message(e))?;
+    let module = compiled(&path).map_err(|e| JsNativeError::error().with_message(e))?;
+    Ok(host.wasm.borrow_mut().add_module(module).into())
+}
+
+fn get_module(host: &Host, id: u32) -> JsResult<Module> {
+    host.wasm
+        .borrow()
+        .modules
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| JsNativeError::typ().with_message("Unknown module").into())
+}
+
+/// `host.wasmImports(module)`, `[module, name, kind]` for every import.
+fn imports(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let module = get_module(host, module_id(args, context)?)?;
+    let list: Vec<JsValue> = module
+        .imports()
+        .map(|i| {
+            let parts = [js_str(i.module()), js_str(i.name()), js_str(kind(&i.ty()))];
+            JsArray::from_iter(parts, context).into()
+        })
+        .collect();
+    Ok(JsArray::from_iter(list, context).into())
+}
+
+/// `host.wasmExports(module)`, `[name, kind]` for every export.
+fn exports(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let module = get_module(host, module_id(args, context)?)?;
+    let list: Vec<JsValue> = module
+        .exports()
+        .map(|e| {
+            let parts = [js_str(e.name()), js_str(kind(&e.ty()))];
+            JsArray::from_iter(parts, context).into()
+        })
+        .collect();
+    Ok(JsArray::from_iter(list, context).into())
+}
+
+fn js_str(s: &str) -> JsValue {
+    boa_engine::JsString::from(s).into()
+}
+
+/// `host.wasmInstantiate(module)`, the id of the instance. Its function
+/// imports are numbered in order and called through `dispatch` of
+/// `wasmCall`, other kinds of imports are not supported.
+fn instantiate(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let module = get_module(host, module_id(args, context)?)?;
+    let state = State {
+        limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+        call: None,
+        thrown: None,
+    };
+    let mut store = Store::new(engine(), state);
+    store.limiter(|state| &mut state.limits);
+    let ticks = host.timeout.as_millis() / TICK.as_millis() + 1;
+    store.set_epoch_deadline(ticks as u64);
+    store.epoch_deadline_trap();
+
+    if let Some(i) = module.imports().find(|i| i.ty().func().is_none()) {
+        let message = format!(
+            "{}.{} is a {} import, only functions can be imported",
+            i.module(),
+            i.name(),
+            kind(&i.ty())
+        );
+        return Err(wasm_error("LinkError", message));
+    }
+    let mut externs = Vec::new();
+    for (index, ty) in module
+        .imports()
+        .filter_map(|i| i.ty().func().cloned())
+        .enumerate()
+    {
+        let types: Vec<ValType> = ty.results().collect();
+        let f = Func::new(&mut store, ty, move |mut caller, params, results| {
+            call_import(&mut caller, index, params, results, &types)
+        });
+        externs.push(f.into());
+    }
+    let instance = wasmtime::Instance::new(&mut store, &module, &externs)
+        .map_err(|e| wasm_error("LinkError", e))?;
+
+    let mut wasm = host.wasm.borrow_mut();
+    wasm.next_id += 1;
+    let id = wasm.next_id;
+    wasm.instances.insert(id, Instance { store, instance });
+    Ok(id.into())
+}
+
+/// Runs `f` with the instance `args[0]`, taken out while it runs so that an
+/// import calling back into it fails instead of borrowing it twice.
+fn with_instance(
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+    f: impl FnOnce(&mut Instance, &mut Context<'_>) -> JsResult<JsValue>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let mut instance = host
+        .wasm
+        .borrow_mut()
+        .instances
+        .remove(&id)
+        .ok_or_else(|| wasm_error("RuntimeError", "The instance is already running"))?;
+    let res = f(&mut instance, context);
+    host.wasm.borrow_mut().instances.insert(id, instance);
+    res
+}
+
+fn export_name(args: &[JsValue], context: &mut Context<'_>) -> JsResult<String> {
+    Ok(args
+        .get_or_undefined(1)
+        .to_string(context)?
+        .to_std_string_escaped())
+}
+
+/// `host.wasmCall(instance, name, dispatch, ...args)`, the result of the
+/// exported function: `undefined`, a value or an array of them.
+fn call(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = export_name(args, context)?;
+    let dispatch = args
+        .get_or_undefined(2)
+        .as_object()
+        .cloned()
+        .ok_or_else(|| JsNativeError::typ().with_message("dispatch is not a function"))?;
+    let values = args.get(3..).unwrap_or_default();
+    with_instance(args, host, context, |instance, context| {
+        let func = instance
+            .instance
+            .get_func(&mut instance.store, &name)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message(format!("{} is not a function", name))
+            })?;
+        let ty = func.ty(&instance.store);
+        let mut params = Vec::new();
+        for (i, t) in ty.params().enumerate() {
+            params.push(to_val(values.get_or_undefined(i), &t, context)?);
+        }
+        let mut results = vec![Val::I32(0); ty.results().len()];
+
+        let pointer = context as *mut Context<'_> as *mut ();
+        instance.store.data_mut().call = Some(Call {
+            context: pointer,
+            dispatch,
+        });
+        let res = func.call(&mut instance.store, &params, &mut results);
+        let state = instance.store.data_mut();
+        state.call = None;
+        if let Some(e) = state.thrown.take() {
+            return Err(e);
+        }
+        res.map_err(|e| wasm_error("RuntimeError", e))?;
+
+        match results.len() {
+            0 => Ok(JsValue::undefined()),
+            1 => from_val(&results[0]),
+            _ => {
+                let values = results.iter().map(from_val).collect::<JsResult<Vec<_>>>()?;
+                Ok(JsArray::from_iter(values, context).into())
+            }
+        }
+    })
+}
+
+fn memory(instance: &mut Instance, name: &str) -> JsResult<wasmtime::Memory> {
+    instance
+        .instance
+        .get_memory(&mut instance.store, name)
+        .ok_or_else(|| {
+            JsNativeError::typ()
+                .with_message(format!("{} is not a memory", name))
+                .into()
+        })
+}
+
+/// The range `offset..offset + length` of a memory of `size` bytes.
+fn range(offset: u64, length: u64, size: usize) -> JsResult<std::ops::Range<usize>> {
+    match offset.checked_add(length) {
+        Some(end) if end <= size as u64 => Ok(offset as usize..end as usize),
+        _ => Err(JsNativeError::range()
+            .with_message(format!(
+                "{} bytes at {} are outside the memory of {} bytes",
+                length, offset, size
+            ))
+            .into()),
+    }
+}
+
+/// `host.wasmMemoryRead(instance, name, offset, length)`, a copy of the bytes.
+fn memory_read(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = export_name(args, context)?;
+    let offset = args.get_or_undefined(2).to_index(context)?;
+    with_instance(args, host, context, |instance, context| {
+        let memory = memory(instance, &name)?;
+        let data = memory.data(&instance.store);
+        let length = match args.get_or_undefined(3) {
+            l if l.is_undefined() => (data.len() as u64).saturating_sub(offset),
+            l => l.to_index(context)?,
+        };
+        let bytes = data[range(offset, length, data.len())?].to_vec();
+        web::from_bytes(bytes, context)
+    })
+}
+
+/// `host.wasmMemoryWrite(instance, name, offset, bytes)`.
+fn memory_write(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = export_name(args, context)?;
+    let offset = args.get_or_undefined(2).to_index(context)?;
+    let bytes = web::to_bytes(args.get_or_undefined(3), context)?;
+    with_instance(args, host, context, |instance, _| {
+        let memory = memory(instance, &name)?;
+        let data = memory.data_mut(&mut instance.store);
+        let range = range(offset, bytes.len() as u64, data.len())?;
+        data[range].copy_from_slice(&bytes);
+        Ok(JsValue::undefined())
+    })
+}
+
+/// `host.wasmMemorySize(instance, name)`, in bytes.
+fn memory_size(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = export_name(args, context)?;
+    with_instance(args, host, context, |instance, _| {
+        let memory = memory(instance, &name)?;
+        Ok((memory.data_size(&instance.store) as f64).into())
+    })
+}
+
+/// `host.wasmMemoryGrow(instance, name, pages)`, the size in pages before.
+fn memory_grow(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = export_name(args, context)?;
+    let pages = args.get_or_undefined(2).to_index(context)?;
+    with_instance(args, host, context, |instance, _| {
+        let memory = memory(instance, &name)?;
+        let before = memory
+            .grow(&mut instance.store, pages)
+            .map_err(|e| JsNativeError::range().with_message(e.to_string()))?;
+        Ok((before as f64).into())
+    })
+}
+
+/// `host.wasmGlobal(instance, name)`, the value of an exported global.
+fn global(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let name = export_name(args, context)?;
+    with_instance(args, host, context, |instance, _| {
+        let global = instance
+            .instance
+            .get_global(&mut instance.store, &name)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message(format!("{} is not a global", name))
+            })?;
+        from_val(&global.get(&mut instance.store))
+    })
+}
+
+type Native = fn(&JsValue, &[JsValue], &Host, &mut Context<'_>) -> JsResult<JsValue>;
+
+/// Installs `WebAssembly` and `loadWasm` for the script in `dir`.
+pub(crate) fn register(
+    runtime: &Runtime,
+    dir: &Path,
+    natives: &JsObject,
+    context: &mut Context<'_>,
+) -> JsResult<()> {
+    let host = Host {
+        root: runtime.root.clone(),
+        dir: dir.to_path_buf(),
+        timeout: runtime.timeout,
+        wasm: Rc::default(),
+    };
+    let functions: [(&str, usize, Native); 12] = [
+        ("wasmCompile", 1, compile),
+        ("wasmValidate", 1, validate),
+        ("wasmLoad", 1, load),
+        ("wasmImports", 1, imports),
+        ("wasmExports", 1, exports),
+        ("wasmInstantiate", 1, instantiate),
+        ("wasmCall", 3, call),
+        ("wasmMemoryRead", 4, memory_read),
+        ("wasmMemoryWrite", 4, memory_write),
+        ("wasmMemorySize", 2, memory_size),
+        ("wasmMemoryGrow", 3, memory_grow),
+        ("wasmGlobal", 2, global),
+    ];
+    let mut wasm_natives = ObjectInitializer::new(context);
+    for (name, length, f) in functions {
+        wasm_natives.function(
+            NativeFunction::from_copy_closure_with_captures(f, host.clone()),
+            name,
+            length,
+        );
+    }
+    let wasm_natives = wasm_natives.build();
+    worker::install("wasm.js", WASM, &wasm_natives, natives, context)
+}
@@ -0,0 +1,103 @@
+// AbortController, AbortSignal and the DOMException they reject with.
+(function () {
+  "use strict";
+
+  if (typeof globalThis.DOMException === "undefined") {
+    const codes = { AbortError: 20, TimeoutError: 23 };
+    class DOMException extends Error {
+      constructor(message = "", name = "Error") {
+        super(message);
+        Object.defineProperty(this, "name", { value: name, configurable: true, writable: true });
+      }
+      get code() {
+        return codes[this.name] || 0;
+      }
+    }
+    globalThis.DOMException = DOMException;
+  }
+
+  const STATE = Symbol("AbortSignal state");
+
+  class AbortSignal {
+    constructor(key) {
+      if (key !== STATE) throw new TypeError("Illegal constructor");
+      Object.defineProperty(this, STATE, {
+        value: { aborted: false, reason: undefined, listeners: [] },
+      });
+      this.onabort = null;
+    }
+
+    get aborted() {
+      return this[STATE].aborted;
+    }
+
+    get reason() {
+      return this[STATE].reason;
+    }
+
+    throwIfAborted() {
+      if (this.aborted) throw this.reason;
+    }
+
+    addEventListener(type, listener, options) {
+      if (type !== "abort" || typeof listener !== "function") return;
+      const once = typeof options === "object" && options !== null && options.once;
+      this[STATE].listeners.push({ listener, once });
+    }
+
+    removeEventListener(type, listener) {
+      if (type !== "abort") return;
+      const state = this[STATE];
+      state.listeners = state.listeners.filter((l) => l.listener !== listener);
+    }
+
+    static abort(reason) {
+      const signal = new AbortSignal(STATE);
+      abort(signal, reason);
+      return signal;
+    }
+
+    static timeout(ms) {
+      const signal = new AbortSignal(STATE);
+      setTimeout(() => abort(signal, new DOMException("The operation timed out.", "TimeoutError")), ms);
+      return signal;
+    }
+
+    static any(signals) {
+      const signal = new AbortSignal(STATE);
+      for (const s of signals) {
+        if (s.aborted) {
+          abort(signal, s.reason);
+          break;
+        }
+        s.addEventListener("abort", () => abort(signal, s.reason), { once: true });
+      }
+      return signal;
+    }
+  }
+
+  function abort(signal, reason) {
+    const state = signal[STATE];
+    if (state.aborted) return;
+    state.aborted = true;
+    state.reason = reason === undefined ? new DOMException("This operation was aborted", "AbortError") : reason;
+    const event = { type: "abort", target: signal };
+    const listeners = state.listeners;
+    state.listeners = listeners.filter((l) => !l.once);
+    if (typeof signal.onabort === "function") signal.onabort(event);
+    for (const { listener } of listeners) listener.call(signal, event);
+  }
+
+  class AbortController {
+    constructor() {
+      Object.defineProperty(this, "signal", { value: new AbortSignal(STATE), enumerable: true });
+    }
+
+    abort(reason) {
+      abort(this.signal, reason);
+    }
+  }
+
+  globalThis.AbortSignal = AbortSignal;
+  globalThis.AbortController = AbortController;
+})
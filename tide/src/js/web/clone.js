@@ -0,0 +1,157 @@
+// structuredClone, and the conversion of script values into the JSON the host
+// receives. Values going the other way, like `ctx`, are always fresh plain
+// objects and arrays.
+(function (host) {
+  "use strict";
+
+  function dataCloneError(what) {
+    return new DOMException(`${what} could not be cloned`, "DataCloneError");
+  }
+
+  const TYPED_ARRAYS = [
+    Int8Array,
+    Uint8Array,
+    Uint8ClampedArray,
+    Int16Array,
+    Uint16Array,
+    Int32Array,
+    Uint32Array,
+    Float32Array,
+    Float64Array,
+    BigInt64Array,
+    BigUint64Array,
+  ];
+
+  function typedArrayType(value) {
+    return TYPED_ARRAYS.find((type) => value instanceof type);
+  }
+
+  // The structured clone algorithm of HTML: cycles and shared references are
+  // kept, prototypes of class instances are not, and functions, symbols and
+  // host objects with internal state cannot be cloned.
+  function structuredClone(value, options = {}) {
+    const transfer = options.transfer || [];
+    for (const t of transfer) {
+      if (!(t instanceof ArrayBuffer)) throw dataCloneError("Only ArrayBuffers can be transferred, the value");
+    }
+    const memory = new Map();
+
+    function clone(v) {
+      if (typeof v === "symbol") throw dataCloneError(v.toString());
+      if (typeof v === "function") throw dataCloneError(`The function ${v.name || "(anonymous)"}`);
+      if (v === null || typeof v !== "object") return v;
+      if (memory.has(v)) return memory.get(v);
+
+      let out;
+      if (v instanceof Boolean || v instanceof Number || v instanceof String || v instanceof BigInt) {
+        out = Object(v.valueOf());
+      } else if (v instanceof Date) {
+        out = new Date(v.getTime());
+      } else if (v instanceof RegExp) {
+        out = new RegExp(v.source, v.flags);
+      } else if (v instanceof ArrayBuffer) {
+        out = v.slice(0);
+      } else if (v instanceof DataView) {
+        out = new DataView(clone(v.buffer), v.byteOffset, v.byteLength);
+      } else if (typedArrayType(v)) {
+        const Type = typedArrayType(v);
+        out = new Type(clone(v.buffer), v.byteOffset, v.length);
+      } else if (v instanceof Map) {
+        out = new Map();
+        memory.set(v, out);
+        for (const [k, x] of v) out.set(clone(k), clone(x));
+        return out;
+      } else if (v instanceof Set) {
+        out = new Set();
+        memory.set(v, out);
+        for (const x of v) out.add(clone(x));
+        return out;
+      } else if (v instanceof Error) {
+        const Type = [EvalError, RangeError, ReferenceError, SyntaxError, TypeError, URIError].find(
+          (type) => v.name === type.name
+        ) || Error;
+        out = new Type(v.message);
+        if (typeof v.stack === "string") Object.defineProperty(out, "stack", { value: v.stack, writable: true, configurable: true });
+        if ("cause" in v) {
+          memory.set(v, out);
+          out.cause = clone(v.cause);
+        }
+        return out;
+      } else if (Array.isArray(v)) {
+        out = new Array(v.length);
+        memory.set(v, out);
+        for (const k of Object.keys(v)) out[k] = clone(v[k]);
+        return out;
+      } else if (Object.getOwnPropertySymbols(v).length > 0 && Object.getPrototypeOf(v) !== Object.prototype) {
+        // Headers, URL, Request and the like keep their state behind symbols.
+        throw dataCloneError(`A ${v.constructor ? v.constructor.name : "host"} object`);
+      } else if (v instanceof Promise || v instanceof WeakMap || v instanceof WeakSet) {
+        throw dataCloneError(`A ${v.constructor.name}`);
+      } else {
+        out = {};
+        memory.set(v, out);
+        for (const k of Object.keys(v)) out[k] = clone(v[k]);
+        return out;
+      }
+      memory.set(v, out);
+      return out;
+    }
+
+    return clone(value);
+  }
+
+  // What the host receives from a script, as JSON. Mostly JSON.stringify:
+  // `toJSON` is honoured, `undefined`, functions and symbols are left out of
+  // objects and become null in arrays, and NaN and the infinities are null.
+  // Beyond it, Maps become objects keyed by the string of each key, Sets
+  // arrays, typed arrays and ArrayBuffers arrays of their numbers, BigInts
+  // strings when they don't fit a double exactly, and errors their name and
+  // message. Cycles are an error.
+  function toJson(value) {
+    const path = [];
+
+    function convert(v, inArray) {
+      if (v !== null && typeof v === "object" && typeof v.toJSON === "function") v = v.toJSON();
+      switch (typeof v) {
+        case "undefined":
+        case "function":
+        case "symbol":
+          return inArray ? null : undefined;
+        case "number":
+          return Number.isFinite(v) ? v : null;
+        case "bigint":
+          return Number.isSafeInteger(Number(v)) ? Number(v) : v.toString();
+        case "string":
+        case "boolean":
+          return v;
+      }
+      if (v === null) return null;
+      if (v instanceof Number || v instanceof String || v instanceof Boolean) return convert(v.valueOf(), inArray);
+      if (path.includes(v)) throw new TypeError("Cannot convert a cyclic value to JSON");
+      path.push(v);
+      try {
+        if (v instanceof Error) return { name: v.name, message: v.message };
+        if (v instanceof RegExp) return v.toString();
+        if (v instanceof ArrayBuffer) return [...new Uint8Array(v)];
+        if (typedArrayType(v)) return [...v].map((x) => convert(x, true));
+        if (v instanceof DataView) return [...new Uint8Array(v.buffer, v.byteOffset, v.byteLength)];
+        if (Array.isArray(v) || v instanceof Set) return [...v].map((x) => convert(x, true));
+        const out = {};
+        const entries = v instanceof Map ? [...v].map(([k, x]) => [String(k), x]) : Object.entries(v);
+        for (const [k, x] of entries) {
+          const converted = convert(x, false);
+          if (converted !== undefined) out[k] = converted;
+        }
+        return out;
+      } finally {
+        path.pop();
+      }
+    }
+
+    const result = convert(value, false);
+    return result === undefined ? null : result;
+  }
+
+  host.toJson = toJson;
+  globalThis.structuredClone = structuredClone;
+})
@@ -0,0 +1,271 @@
+// console, formatting its arguments the way Node's util.inspect does and
+// handing the lines to the server log through host.console.
+(function (host) {
+  "use strict";
+
+  const DEPTH = 2;
+  const MAX_ITEMS = 100;
+  const LINE_WIDTH = 72;
+
+  const IDENTIFIER = /^[A-Za-z_$][A-Za-z0-9_$]*$/;
+
+  function quote(s) {
+    return `'${s.replace(/\\/g, "\\\\").replace(/'/g, "\\'").replace(/\n/g, "\\n")}'`;
+  }
+
+  function key(k) {
+    if (typeof k === "symbol") return `[${k.toString()}]`;
+    return IDENTIFIER.test(k) ? k : quote(k);
+  }
+
+  function constructorName(value) {
+    const proto = Object.getPrototypeOf(value);
+    if (proto === null) return "[Object: null prototype]";
+    const ctor = proto.constructor;
+    return typeof ctor === "function" && ctor.name ? ctor.name : "Object";
+  }
+
+  // Joins the parts on one line if they fit, or else one per line.
+  function wrap(prefix, open, parts, close, indent) {
+    const line = parts.length === 0 ? `${prefix}${open}${close}` : `${prefix}${open} ${parts.join(", ")} ${close}`;
+    if (line.length <= LINE_WIDTH && !line.includes("\n")) return line;
+    const inner = indent + "  ";
+    return `${prefix}${open}\n${parts.map((p) => inner + p).join(",\n")}\n${indent}${close}`;
+  }
+
+  function ownEntries(value, seen, depth, indent, skip) {
+    const parts = [];
+    const keys = [...Object.keys(value), ...Object.getOwnPropertySymbols(value)];
+    for (const k of keys) {
+      if (skip && skip(k)) continue;
+      const desc = Object.getOwnPropertyDescriptor(value, k);
+      if (!desc || !desc.enumerable) continue;
+      let shown;
+      if (desc.get || desc.set) {
+        shown = desc.get && desc.set ? "[Getter/Setter]" : desc.get ? "[Getter]" : "[Setter]";
+      } else {
+        shown = inspect(desc.value, seen, depth + 1, indent + "  ");
+      }
+      parts.push(`${key(k)}: ${shown}`);
+    }
+    return parts;
+  }
+
+  function items(list, seen, depth, indent) {
+    const parts = [];
+    const shown = Math.min(list.length, MAX_ITEMS);
+    for (let i = 0; i < shown; i++) parts.push(inspect(list[i], seen, depth + 1, indent + "  "));
+    if (list.length > shown) {
+      const more = list.length - shown;
+      parts.push(`... ${more} more item${more > 1 ? "s" : ""}`);
+    }
+    return parts;
+  }
+
+  function inspectObject(value, seen, depth, indent) {
+    if (value instanceof Date) return isNaN(value) ? "Invalid Date" : value.toISOString();
+    if (value instanceof RegExp) return value.toString();
+    if (value instanceof Error) {
+      const stack = typeof value.stack === "string" && value.stack ? value.stack : null;
+      return stack || `${value.name}: ${value.message}`;
+    }
+    if (typeof value === "function") {
+      const source = Function.prototype.toString.call(value);
+      if (source.startsWith("class")) return `[class ${value.name || "(anonymous)"}]`;
+      return value.name ? `[Function: ${value.name}]` : "[Function (anonymous)]";
+    }
+    if (value instanceof Promise) return "Promise { <pending> }";
+    if (value instanceof ArrayBuffer) return `ArrayBuffer { byteLength: ${value.byteLength} }`;
+
+    const name = constructorName(value);
+    const array = Array.isArray(value);
+    if (depth > DEPTH) return array ? "[Array]" : `[${name}]`;
+
+    seen.push(value);
+    try {
+      if (array) {
+        const prefix = name === "Array" ? "" : `${name}(${value.length}) `;
+        const parts = items(value, seen, depth, indent);
+        parts.push(...ownEntries(value, seen, depth, indent, (k) => typeof k === "string" && /^\d+$/.test(k)));
+        return wrap(prefix, "[", parts, "]", indent);
+      }
+      if (ArrayBuffer.isView(value) && !(value instanceof DataView)) {
+        return wrap(`${name}(${value.length}) `, "[", items(value, seen, depth, indent), "]", indent);
+      }
+      if (value instanceof Map) {
+        const parts = [];
+        for (const [k, v] of value) {
+          parts.push(`${inspect(k, seen, depth + 1, indent + "  ")} => ${inspect(v, seen, depth + 1, indent + "  ")}`);
+        }
+        return wrap(`${name}(${value.size}) `, "{", parts, "}", indent);
+      }
+      if (value instanceof Set) {
+        return wrap(`${name}(${value.size}) `, "{", items([...value], seen, depth, indent), "}", indent);
+      }
+      const prefix = name === "Object" ? "" : `${name} `;
+      return wrap(prefix, "{", ownEntries(value, seen, depth, indent), "}", indent);
+    } finally {
+      seen.pop();
+    }
+  }
+
+  // A readable view of `value`, nested objects are shown `DEPTH` levels deep.
+  function inspect(value, seen = [], depth = 0, indent = "") {
+    switch (typeof value) {
+      case "string":
+        return depth === 0 ? value : quote(value);
+      case "number":
+        return Object.is(value, -0) ? "-0" : String(value);
+      case "bigint":
+        return `${value}n`;
+      case "symbol":
+        return value.toString();
+      case "undefined":
+        return "undefined";
+      case "boolean":
+        return String(value);
+    }
+    if (value === null) return "null";
+    if (seen.includes(value)) return "[Circular]";
+    return inspectObject(value, seen, depth, indent);
+  }
+
+  // Applies printf-like directives in a leading string, then appends the rest.
+  function format(...args) {
+    let out = [];
+    let rest = args;
+    if (typeof args[0] === "string" && args.length > 1) {
+      let i = 1;
+      const text = args[0].replace(/%([sdifjoOc%])/g, (match, d) => {
+        if (d === "%") return "%";
+        if (i >= args.length) return match;
+        const arg = args[i++];
+        switch (d) {
+          case "s":
+            return typeof arg === "string" ? arg : inspect(arg, [], 1);
+          case "d":
+          case "i": {
+            if (typeof arg === "bigint") return `${arg}n`;
+            const n = Number(arg);
+            return String(d === "i" ? Math.trunc(n) : n);
+          }
+          case "f":
+            return String(parseFloat(arg));
+          case "j":
+            try {
+              return JSON.stringify(arg);
+            } catch (e) {
+              return "[Circular]";
+            }
+          case "c":
+            return "";
+          default:
+            return inspect(arg, [], 0);
+        }
+      });
+      out.push(text);
+      rest = args.slice(i);
+    } else if (args.length > 0) {
+      out.push(inspect(args[0]));
+      rest = args.slice(1);
+    }
+    for (const arg of rest) out.push(inspect(arg));
+    return out.join(" ");
+  }
+
+  // Box drawing of rows, the way console.table prints them.
+  function table(data, columns) {
+    const rows = [];
+    const header = ["(index)"];
+    let values = false;
+    const entries = data instanceof Map ? [...data] : Object.entries(data);
+    for (const [index, row] of entries) {
+      const cells = new Map();
+      if (row !== null && typeof row === "object") {
+        for (const k of Object.keys(row)) {
+          if (columns && !columns.includes(k)) continue;
+          if (!header.includes(k)) header.push(k);
+          cells.set(k, inspect(row[k], [], 1));
+        }
+      } else {
+        values = true;
+        cells.set("Values", inspect(row, [], 1));
+      }
+      rows.push([String(index), cells]);
+    }
+    if (values) header.push("Values");
+    const grid = rows.map(([index, cells]) => [index, ...header.slice(1).map((h) => cells.get(h) || "")]);
+    const widths = header.map((h, c) => Math.max(h.length, ...grid.map((r) => r[c].length)) + 2);
+    const center = (s, w) => {
+      const left = Math.floor((w - s.length) / 2);
+      return " ".repeat(left) + s + " ".repeat(w - s.length - left);
+    };
+    const line = (l, m, r) => l + widths.map((w) => "─".repeat(w)).join(m) + r;
+    const row = (cells) => "│" + cells.map((s, c) => center(s, widths[c])).join("│") + "│";
+    return [line("┌", "┬", "┐"), row(header), line("├", "┼", "┤"), ...grid.map(row), line("└", "┴", "┘")].join("\n");
+  }
+
+  const counts = new Map();
+  const timers = new Map();
+  let indent = "";
+
+  function print(level, text) {
+    host.console(level, indent === "" ? text : text.replace(/^/gm, indent));
+  }
+
+  function elapsed(label) {
+    const start = timers.get(label);
+    if (start === undefined) {
+      print("warn", `No such label '${label}' for console timer`);
+      return null;
+    }
+    return `${label}: ${Date.now() - start}ms`;
+  }
+
+  const console = {
+    log: (...args) => print("info", format(...args)),
+    info: (...args) => print("info", format(...args)),
+    debug: (...args) => print("debug", format(...args)),
+    warn: (...args) => print("warn", format(...args)),
+    error: (...args) => print("error", format(...args)),
+    trace: (...args) => print("error", `Trace: ${format(...args)}\n${new Error().stack || ""}`.trimEnd()),
+    assert: (condition, ...args) => {
+      if (!condition) print("error", args.length > 0 ? `Assertion failed: ${format(...args)}` : "Assertion failed");
+    },
+    dir: (value) => print("info", inspect(value, [], 0)),
+    table: (data, columns) => {
+      if (data === null || typeof data !== "object") return console.log(data);
+      print("info", table(data, columns));
+    },
+    count: (label = "default") => {
+      const n = (counts.get(label) || 0) + 1;
+      counts.set(label, n);
+      print("info", `${label}: ${n}`);
+    },
+    countReset: (label = "default") => counts.delete(label),
+    time: (label = "default") => {
+      if (timers.has(label)) return print("warn", `Label '${label}' already exists for console.time()`);
+      timers.set(label, Date.now());
+    },
+    timeLog: (label = "default", ...args) => {
+      const text = elapsed(label);
+      if (text !== null) print("info", args.length > 0 ? `${text} ${format(...args)}` : text);
+    },
+    timeEnd: (label = "default") => {
+      const text = elapsed(label);
+      timers.delete(label);
+      if (text !== null) print("info", text);
+    },
+    group: (...args) => {
+      if (args.length > 0) print("info", format(...args));
+      indent += "  ";
+    },
+    groupEnd: () => {
+      indent = indent.slice(2);
+    },
+  };
+  console.groupCollapsed = console.group;
+
+  host.format = format;
+  globalThis.console = console;
+})
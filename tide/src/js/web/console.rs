@@ -0,0 +1,118 @@
+use boa_engine::{Context, JsArgs, JsResult, JsValue, NativeFunction};
+use boa_gc::{Finalize, Trace};
+use std::cell::RefCell;
+use std::rc::Rc;
+use tide::log;
+
+/// The script lines are logged for, relative to the app directory. It can
+/// change after the console is installed, for contexts that are set up
+/// before their script is known.
+#[derive(Trace, Finalize, Clone)]
+pub(crate) struct ScriptName {
+    #[unsafe_ignore_trace]
+    path: Rc<RefCell<Rc<str>>>,
+}
+
+impl ScriptName {
+    pub(crate) fn new(path: &str) -> Self {
+        Self {
+            path: Rc::new(RefCell::new(Rc::from(path))),
+        }
+    }
+
+    pub(crate) fn set(&self, path: &str) {
+        *self.path.borrow_mut() = Rc::from(path);
+    }
+}
+
+/// `host.console(level, message)`, one formatted console call.
+fn console(
+    _: &JsValue,
+    args: &[JsValue],
+    script: &ScriptName,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let level = args.get_or_undefined(0).to_string(context)?;
+    let message = args.get_or_undefined(1).to_string(context)?;
+    let message = message.to_std_string_escaped();
+    let path = script.path.borrow().clone();
+    match level.to_std_string_escaped().as_str() {
+        "debug" => log::debug!("{}: {}", path, message),
+        "warn" => log::warn!("{}: {}", path, message),
+        "error" => log::error!("{}: {}", path, message),
+        _ => log::info!("{}: {}", path, message),
+    }
+    Ok(JsValue::undefined())
+}
+
+pub(super) fn functions(script: &ScriptName) -> [(&'static str, usize, NativeFunction); 1] {
+    [(
+        "console",
+        2,
+        NativeFunction::from_copy_closure_with_captures(console, script.clone()),
+    )]
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::register;
+    use boa_engine::{js_string, Context, JsValue, Source};
+
+    fn format(src: &str) -> String {
+        let mut context = Context::default();
+        let permissions = crate::permissions::Permissions::default();
+        let script = super::ScriptName::new("test.js");
+        let natives = register(&script, &permissions, &mut context).unwrap();
+        let format = natives.get(js_string!("format"), &mut context).unwrap();
+        let args = context.eval(Source::from_bytes(src)).unwrap();
+        let args = args.as_object().unwrap().clone();
+        let len = args.get(js_string!("length"), &mut context).unwrap();
+        let args: Vec<JsValue> = (0..len.as_number().unwrap() as u32)
+            .map(|i| args.get(i, &mut context).unwrap())
+            .collect();
+        let out = format
+            .as_callable()
+            .unwrap()
+            .call(&JsValue::undefined(), &args, &mut context)
+            .unwrap();
+        out.as_string().unwrap().to_std_string_escaped()
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(
+            format(r#"["a", 1, -0, 2n, null, undefined]"#),
+            "a 1 -0 2n null undefined"
+        );
+        assert_eq!(format(r#"["%s is %d%%", "x", 4.5, "!"]"#), "x is 4.5% !");
+    }
+
+    #[test]
+    fn objects() {
+        assert_eq!(
+            format(r#"[{ a: [1, "b"], "c-d": { e: { f: { g: 1 } } } }]"#),
+            "{ a: [ 1, 'b' ], 'c-d': { e: { f: [Object] } } }"
+        );
+        assert_eq!(
+            format(r#"[new Map([["k", 1]]), new Set([1]), new Uint8Array(2)]"#),
+            "Map(1) { 'k' => 1 } Set(1) { 1 } Uint8Array(2) [ 0, 0 ]"
+        );
+        assert_eq!(
+            format("const o = {}; o.self = o; [o]"),
+            "{ self: [Circular] }"
+        );
+        assert_eq!(
+            format(
+                "[class A {}, function f() {}, new (class B { constructor() { this.x = 1 } })()]"
+            ),
+            "[class A] [Function: f] B { x: 1 }"
+        );
+    }
+
+    #[test]
+    fn long_objects_wrap() {
+        let out = format("[Array.from({ length: 30 }, (_, i) => i * 1000)]");
+        assert!(out.starts_with("[\n  0,\n  1000,"));
+        assert!(out.ends_with("  29000\n]"));
+    }
+}
@@ -0,0 +1,193 @@
+// crypto.getRandomValues, crypto.randomUUID and the part of crypto.subtle that
+// JWT and webhook signature libraries use: digests, HMAC, RSASSA-PKCS1-v1_5
+// and ECDSA on P-256. The natives are in crypto.rs.
+(function (host) {
+  "use strict";
+
+  const KEY = Symbol("key");
+
+  // Natives report the DOMException name as the prefix of their message.
+  function native(f, ...args) {
+    try {
+      return f(...args);
+    } catch (e) {
+      const match = /^(\w+Error): (.*)$/s.exec(e && e.message);
+      if (match) throw new DOMException(match[2], match[1]);
+      throw e;
+    }
+  }
+
+  function bytes(data) {
+    if (data instanceof ArrayBuffer) return new Uint8Array(data);
+    if (ArrayBuffer.isView(data)) return new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+    throw new TypeError("Expected an ArrayBuffer or a view on one");
+  }
+
+  const BASE64URL = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+  function base64url(b) {
+    let out = "";
+    for (let i = 0; i < b.length; i += 3) {
+      const n = (b[i] << 16) | ((b[i + 1] || 0) << 8) | (b[i + 2] || 0);
+      const chars = Math.min(4, Math.ceil(((b.length - i) * 8) / 6));
+      for (let j = 0; j < chars; j++) out += BASE64URL[(n >> (18 - 6 * j)) & 63];
+    }
+    return out;
+  }
+
+  function buffer(view) {
+    return view.buffer.slice(view.byteOffset, view.byteOffset + view.byteLength);
+  }
+
+  const NAMES = {
+    "SHA-1": "SHA-1",
+    "SHA-256": "SHA-256",
+    "SHA-384": "SHA-384",
+    "SHA-512": "SHA-512",
+    HMAC: "HMAC",
+    "RSASSA-PKCS1-V1_5": "RSASSA-PKCS1-v1_5",
+    ECDSA: "ECDSA",
+  };
+
+  function normalize(algorithm) {
+    const params = typeof algorithm === "string" ? { name: algorithm } : { ...algorithm };
+    const name = NAMES[String(params.name).toUpperCase()];
+    if (name === undefined) throw new DOMException(`Unrecognized algorithm: ${params.name}`, "NotSupportedError");
+    params.name = name;
+    if (params.hash !== undefined) params.hash = { name: normalize(params.hash).name };
+    return params;
+  }
+
+  function hashOf(algorithm) {
+    if (algorithm.hash === undefined) throw new TypeError(`${algorithm.name} needs a hash`);
+    return algorithm.hash.name;
+  }
+
+  class CryptoKey {
+    constructor(secret, type, extractable, algorithm, usages, material) {
+      if (secret !== KEY) throw new TypeError("Illegal constructor");
+      Object.defineProperties(this, {
+        type: { value: type, enumerable: true },
+        extractable: { value: Boolean(extractable), enumerable: true },
+        algorithm: { value: Object.freeze(algorithm), enumerable: true },
+        usages: { value: Object.freeze([...usages]), enumerable: true },
+        [KEY]: { value: material },
+      });
+    }
+  }
+
+  const USAGES = { secret: ["sign", "verify"], public: ["verify"], private: ["sign"] };
+
+  function makeKey(type, extractable, algorithm, usages, material) {
+    for (const usage of usages) {
+      if (!USAGES[type].includes(usage)) {
+        throw new DOMException(`A ${type} key cannot be used to ${usage}`, "SyntaxError");
+      }
+    }
+    if (algorithm.name === "HMAC") algorithm = { ...algorithm, length: material.length * 8 };
+    return new CryptoKey(KEY, type, extractable, algorithm, usages, material);
+  }
+
+  function useKey(algorithm, key, usage) {
+    if (!(key instanceof CryptoKey)) throw new TypeError("Expected a CryptoKey");
+    if (key.algorithm.name !== algorithm.name) {
+      throw new DOMException(`The key is for ${key.algorithm.name}, not ${algorithm.name}`, "InvalidAccessError");
+    }
+    if (!key.usages.includes(usage)) {
+      throw new DOMException(`The key cannot be used to ${usage}`, "InvalidAccessError");
+    }
+    // ECDSA takes the hash with each signature, the others fix it on import.
+    return algorithm.name === "ECDSA" ? hashOf(algorithm) : hashOf(key.algorithm);
+  }
+
+  const subtle = {
+    async digest(algorithm, data) {
+      const { name } = normalize(algorithm);
+      return buffer(native(host.digest, name, bytes(data)));
+    },
+
+    async importKey(format, keyData, algorithm, extractable, usages) {
+      algorithm = normalize(algorithm);
+      const params = { name: algorithm.name };
+      if (algorithm.name === "ECDSA") {
+        if (algorithm.namedCurve !== "P-256") {
+          throw new DOMException(`Unsupported curve: ${algorithm.namedCurve}`, "NotSupportedError");
+        }
+        params.namedCurve = "P-256";
+      } else {
+        params.hash = { name: hashOf(algorithm) };
+      }
+      const data = format === "jwk" ? host.encode(JSON.stringify(keyData)) : bytes(keyData);
+      const [type, material] = native(host.importKey, algorithm.name, String(format), data);
+      return makeKey(type, extractable, params, usages, material);
+    },
+
+    async exportKey(format, key) {
+      if (!(key instanceof CryptoKey)) throw new TypeError("Expected a CryptoKey");
+      if (!key.extractable) throw new DOMException("The key is not extractable", "InvalidAccessError");
+      const material = key[KEY];
+      if (format === "raw" && (key.type === "secret" || (key.algorithm.name === "ECDSA" && key.type === "public"))) {
+        return buffer(material);
+      }
+      if (format === "jwk" && key.type === "secret") {
+        const alg = `HS${key.algorithm.hash.name.slice(4)}`;
+        return { kty: "oct", k: base64url(material), alg, key_ops: [...key.usages], ext: true };
+      }
+      throw new DOMException(`Cannot export this key as ${format}`, "NotSupportedError");
+    },
+
+    async generateKey(algorithm, extractable, usages) {
+      algorithm = normalize(algorithm);
+      if (algorithm.name !== "HMAC") {
+        throw new DOMException(`Cannot generate ${algorithm.name} keys`, "NotSupportedError");
+      }
+      const hash = hashOf(algorithm);
+      const bits = algorithm.length || (hash === "SHA-384" || hash === "SHA-512" ? 1024 : 512);
+      const material = native(host.randomBytes, Math.ceil(bits / 8));
+      return makeKey("secret", extractable, { name: "HMAC", hash: { name: hash } }, usages, material);
+    },
+
+    async sign(algorithm, key, data) {
+      algorithm = normalize(algorithm);
+      const hash = useKey(algorithm, key, "sign");
+      return buffer(native(host.sign, algorithm.name, hash, key[KEY], bytes(data)));
+    },
+
+    async verify(algorithm, key, signature, data) {
+      algorithm = normalize(algorithm);
+      const hash = useKey(algorithm, key, "verify");
+      return native(host.verify, algorithm.name, hash, key[KEY], bytes(signature), bytes(data));
+    },
+  };
+
+  const INTEGER_ARRAYS = [
+    Int8Array,
+    Uint8Array,
+    Uint8ClampedArray,
+    Int16Array,
+    Uint16Array,
+    Int32Array,
+    Uint32Array,
+    BigInt64Array,
+    BigUint64Array,
+  ];
+
+  function getRandomValues(array) {
+    if (!INTEGER_ARRAYS.some((type) => array instanceof type)) {
+      throw new DOMException("Expected an integer typed array", "TypeMismatchError");
+    }
+    bytes(array).set(native(host.randomBytes, array.byteLength));
+    return array;
+  }
+
+  function randomUUID() {
+    const b = native(host.randomBytes, 16);
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    const hex = [...b].map((x) => x.toString(16).padStart(2, "0")).join("");
+    return `${hex.slice(0, 8)}-${hex.slice(8, 12)}-${hex.slice(12, 16)}-${hex.slice(16, 20)}-${hex.slice(20)}`;
+  }
+
+  globalThis.CryptoKey = CryptoKey;
+  globalThis.crypto = { getRandomValues, randomUUID, subtle };
+})
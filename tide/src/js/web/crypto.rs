@@ -0,0 +1,402 @@
+//! Natives behind `crypto` and `crypto.subtle`. Keys cross into the script in
+//! one canonical encoding per kind, so signing does not need to remember the
+//! format a key was imported from: raw bytes for HMAC secrets, SPKI and PKCS #8
+//! DER for RSA, and SEC1 points and scalars for P-256.
+
+use super::{from_bytes, to_bytes};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use boa_engine::object::builtins::JsArray;
+use boa_engine::{
+    Context, JsArgs, JsError, JsNativeError, JsResult, JsString, JsValue, NativeFunction,
+};
+use hmac::{Hmac, Mac};
+use rsa::pkcs8::{
+    AssociatedOid, DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey,
+};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// Most bytes `getRandomValues` fills at once, as the standard sets it.
+const MAX_RANDOM: usize = 65536;
+
+#[derive(Clone, Copy)]
+enum Hash {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Hash {
+    fn parse(name: &str) -> JsResult<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SHA-1" => Ok(Self::Sha1),
+            "SHA-256" => Ok(Self::Sha256),
+            "SHA-384" => Ok(Self::Sha384),
+            "SHA-512" => Ok(Self::Sha512),
+            _ => Err(not_supported(&format!("Unsupported hash: {}", name))),
+        }
+    }
+}
+
+fn not_supported(message: &str) -> JsError {
+    JsNativeError::error()
+        .with_message(format!("NotSupportedError: {}", message))
+        .into()
+}
+
+fn data_error(message: impl std::fmt::Display) -> JsError {
+    JsNativeError::error()
+        .with_message(format!("DataError: {}", message))
+        .into()
+}
+
+fn string_arg(args: &[JsValue], i: usize, context: &mut Context<'_>) -> JsResult<String> {
+    Ok(args
+        .get_or_undefined(i)
+        .to_string(context)?
+        .to_std_string_escaped())
+}
+
+fn digest(hash: Hash, data: &[u8]) -> Vec<u8> {
+    match hash {
+        Hash::Sha1 => Sha1::digest(data).to_vec(),
+        Hash::Sha256 => Sha256::digest(data).to_vec(),
+        Hash::Sha384 => Sha384::digest(data).to_vec(),
+        Hash::Sha512 => Sha512::digest(data).to_vec(),
+    }
+}
+
+fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], data: &[u8]) -> M {
+    let mut mac =
+        <M as hmac::digest::KeyInit>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac
+}
+
+fn hmac_sign(hash: Hash, key: &[u8], data: &[u8]) -> Vec<u8> {
+    match hash {
+        Hash::Sha1 => mac::<Hmac<Sha1>>(key, data)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+        Hash::Sha256 => mac::<Hmac<Sha256>>(key, data)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+        Hash::Sha384 => mac::<Hmac<Sha384>>(key, data)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+        Hash::Sha512 => mac::<Hmac<Sha512>>(key, data)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+    }
+}
+
+fn hmac_verify(hash: Hash, key: &[u8], signature: &[u8], data: &[u8]) -> bool {
+    // verify_slice compares in constant time.
+    match hash {
+        Hash::Sha1 => mac::<Hmac<Sha1>>(key, data).verify_slice(signature).is_ok(),
+        Hash::Sha256 => mac::<Hmac<Sha256>>(key, data)
+            .verify_slice(signature)
+            .is_ok(),
+        Hash::Sha384 => mac::<Hmac<Sha384>>(key, data)
+            .verify_slice(signature)
+            .is_ok(),
+        Hash::Sha512 => mac::<Hmac<Sha512>>(key, data)
+            .verify_slice(signature)
+            .is_ok(),
+    }
+}
+
+fn rsa_sign_with<D: Digest + AssociatedOid>(key: RsaPrivateKey, data: &[u8]) -> JsResult<Vec<u8>> {
+    let key = rsa::pkcs1v15::SigningKey::<D>::new(key);
+    let signature = key.try_sign(data).map_err(data_error)?;
+    Ok(signature.to_vec())
+}
+
+fn rsa_verify_with<D: Digest + AssociatedOid>(
+    key: RsaPublicKey,
+    signature: &[u8],
+    data: &[u8],
+) -> bool {
+    let key = rsa::pkcs1v15::VerifyingKey::<D>::new(key);
+    match rsa::pkcs1v15::Signature::try_from(signature) {
+        Ok(signature) => key.verify(data, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn rsa_sign(hash: Hash, key: &[u8], data: &[u8]) -> JsResult<Vec<u8>> {
+    let key = RsaPrivateKey::from_pkcs8_der(key).map_err(data_error)?;
+    match hash {
+        Hash::Sha1 => rsa_sign_with::<Sha1>(key, data),
+        Hash::Sha256 => rsa_sign_with::<Sha256>(key, data),
+        Hash::Sha384 => rsa_sign_with::<Sha384>(key, data),
+        Hash::Sha512 => rsa_sign_with::<Sha512>(key, data),
+    }
+}
+
+fn rsa_verify(hash: Hash, key: &[u8], signature: &[u8], data: &[u8]) -> JsResult<bool> {
+    let key = RsaPublicKey::from_public_key_der(key).map_err(data_error)?;
+    Ok(match hash {
+        Hash::Sha1 => rsa_verify_with::<Sha1>(key, signature, data),
+        Hash::Sha256 => rsa_verify_with::<Sha256>(key, signature, data),
+        Hash::Sha384 => rsa_verify_with::<Sha384>(key, signature, data),
+        Hash::Sha512 => rsa_verify_with::<Sha512>(key, signature, data),
+    })
+}
+
+/// ECDSA keys are P-256 only, and sign SHA-256 digests as WebCrypto's raw
+/// `r || s` signatures.
+fn ecdsa_hash(hash: Hash) -> JsResult<()> {
+    match hash {
+        Hash::Sha256 => Ok(()),
+        _ => Err(not_supported("ECDSA on P-256 only supports SHA-256")),
+    }
+}
+
+fn ecdsa_sign(hash: Hash, key: &[u8], data: &[u8]) -> JsResult<Vec<u8>> {
+    ecdsa_hash(hash)?;
+    let key = p256::ecdsa::SigningKey::from_slice(key).map_err(data_error)?;
+    let signature: p256::ecdsa::Signature = key.try_sign(data).map_err(data_error)?;
+    Ok(signature.to_bytes().to_vec())
+}
+
+fn ecdsa_verify(hash: Hash, key: &[u8], signature: &[u8], data: &[u8]) -> JsResult<bool> {
+    ecdsa_hash(hash)?;
+    let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(key).map_err(data_error)?;
+    Ok(match p256::ecdsa::Signature::from_slice(signature) {
+        Ok(signature) => key.verify(data, &signature).is_ok(),
+        Err(_) => false,
+    })
+}
+
+fn jwk_field(jwk: &Value, name: &str) -> JsResult<Vec<u8>> {
+    let field = jwk
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| data_error(format!("The JWK has no \"{}\"", name)))?;
+    URL_SAFE_NO_PAD.decode(field).map_err(data_error)
+}
+
+fn big(jwk: &Value, name: &str) -> JsResult<BigUint> {
+    Ok(BigUint::from_bytes_be(&jwk_field(jwk, name)?))
+}
+
+/// Key material in the canonical encoding, and whether it is a `"secret"`,
+/// `"public"` or `"private"` key.
+fn import(algorithm: &str, format: &str, data: &[u8]) -> JsResult<(&'static str, Vec<u8>)> {
+    let jwk = if format == "jwk" {
+        Some(serde_json::from_slice::<Value>(data).map_err(data_error)?)
+    } else {
+        None
+    };
+    let has_private = jwk.as_ref().map_or(false, |j| j.get("d").is_some());
+    match (algorithm, format) {
+        ("HMAC", "raw") => Ok(("secret", data.to_vec())),
+        ("HMAC", "jwk") => Ok(("secret", jwk_field(jwk.as_ref().unwrap(), "k")?)),
+        ("RSASSA-PKCS1-v1_5", "spki") => {
+            RsaPublicKey::from_public_key_der(data).map_err(data_error)?;
+            Ok(("public", data.to_vec()))
+        }
+        ("RSASSA-PKCS1-v1_5", "pkcs8") => {
+            RsaPrivateKey::from_pkcs8_der(data).map_err(data_error)?;
+            Ok(("private", data.to_vec()))
+        }
+        ("RSASSA-PKCS1-v1_5", "jwk") => {
+            let jwk = jwk.as_ref().unwrap();
+            let (n, e) = (big(jwk, "n")?, big(jwk, "e")?);
+            if has_private {
+                let primes = vec![big(jwk, "p")?, big(jwk, "q")?];
+                let key = RsaPrivateKey::from_components(n, e, big(jwk, "d")?, primes)
+                    .map_err(data_error)?;
+                let der = key.to_pkcs8_der().map_err(data_error)?;
+                Ok(("private", der.as_bytes().to_vec()))
+            } else {
+                let key = RsaPublicKey::new(n, e).map_err(data_error)?;
+                let der = key.to_public_key_der().map_err(data_error)?;
+                Ok(("public", der.as_bytes().to_vec()))
+            }
+        }
+        ("ECDSA", "raw") => {
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(data).map_err(data_error)?;
+            Ok(("public", data.to_vec()))
+        }
+        ("ECDSA", "spki") => {
+            let key = p256::ecdsa::VerifyingKey::from_public_key_der(data).map_err(data_error)?;
+            Ok(("public", key.to_encoded_point(false).as_bytes().to_vec()))
+        }
+        ("ECDSA", "pkcs8") => {
+            let key = p256::ecdsa::SigningKey::from_pkcs8_der(data).map_err(data_error)?;
+            Ok(("private", key.to_bytes().to_vec()))
+        }
+        ("ECDSA", "jwk") => {
+            let jwk = jwk.as_ref().unwrap();
+            if jwk.get("crv").and_then(Value::as_str) != Some("P-256") {
+                return Err(not_supported("Only the P-256 curve is supported"));
+            }
+            if has_private {
+                let d = jwk_field(jwk, "d")?;
+                p256::ecdsa::SigningKey::from_slice(&d).map_err(data_error)?;
+                return Ok(("private", d));
+            }
+            let mut point = vec![4];
+            point.extend(jwk_field(jwk, "x")?);
+            point.extend(jwk_field(jwk, "y")?);
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(&point).map_err(data_error)?;
+            Ok(("public", point))
+        }
+        _ => Err(not_supported(&format!(
+            "Cannot import {} keys as {}",
+            algorithm, format
+        ))),
+    }
+}
+
+/// `host.randomBytes(length)`, bytes from the system's secure generator.
+fn random_bytes(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let len = args.get_or_undefined(0).to_length(context)? as usize;
+    if len > MAX_RANDOM {
+        return Err(JsNativeError::error()
+            .with_message(format!(
+                "QuotaExceededError: {} bytes of randomness requested, at most {} are allowed",
+                len, MAX_RANDOM
+            ))
+            .into());
+    }
+    let mut bytes = vec![0; len];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| JsNativeError::error().with_message(e.to_string()))?;
+    from_bytes(bytes, context)
+}
+
+/// `host.digest(hash, data)`.
+fn digest_native(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let hash = Hash::parse(&string_arg(args, 0, context)?)?;
+    let data = to_bytes(args.get_or_undefined(1), context)?;
+    from_bytes(digest(hash, &data), context)
+}
+
+/// `host.importKey(algorithm, format, data)`, returning `[type, material]`.
+/// JWKs are passed as their JSON text.
+fn import_key(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let algorithm = string_arg(args, 0, context)?;
+    let format = string_arg(args, 1, context)?;
+    let data = to_bytes(args.get_or_undefined(2), context)?;
+    let (kind, material) = import(&algorithm, &format, &data)?;
+    let material = from_bytes(material, context)?;
+    Ok(JsArray::from_iter([JsString::from(kind).into(), material], context).into())
+}
+
+/// `host.sign(algorithm, hash, material, data)`.
+fn sign(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let algorithm = string_arg(args, 0, context)?;
+    let hash = Hash::parse(&string_arg(args, 1, context)?)?;
+    let key = to_bytes(args.get_or_undefined(2), context)?;
+    let data = to_bytes(args.get_or_undefined(3), context)?;
+    let signature = match algorithm.as_str() {
+        "HMAC" => hmac_sign(hash, &key, &data),
+        "RSASSA-PKCS1-v1_5" => rsa_sign(hash, &key, &data)?,
+        "ECDSA" => ecdsa_sign(hash, &key, &data)?,
+        _ => return Err(not_supported(&format!("Cannot sign with {}", algorithm))),
+    };
+    from_bytes(signature, context)
+}
+
+/// `host.verify(algorithm, hash, material, signature, data)`.
+fn verify(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let algorithm = string_arg(args, 0, context)?;
+    let hash = Hash::parse(&string_arg(args, 1, context)?)?;
+    let key = to_bytes(args.get_or_undefined(2), context)?;
+    let signature = to_bytes(args.get_or_undefined(3), context)?;
+    let data = to_bytes(args.get_or_undefined(4), context)?;
+    let valid = match algorithm.as_str() {
+        "HMAC" => hmac_verify(hash, &key, &signature, &data),
+        "RSASSA-PKCS1-v1_5" => rsa_verify(hash, &key, &signature, &data)?,
+        "ECDSA" => ecdsa_verify(hash, &key, &signature, &data)?,
+        _ => return Err(not_supported(&format!("Cannot verify with {}", algorithm))),
+    };
+    Ok(valid.into())
+}
+
+pub(super) fn functions() -> [(&'static str, usize, NativeFunction); 5] {
+    [
+        ("randomBytes", 1, NativeFunction::from_fn_ptr(random_bytes)),
+        ("digest", 2, NativeFunction::from_fn_ptr(digest_native)),
+        ("importKey", 3, NativeFunction::from_fn_ptr(import_key)),
+        ("sign", 4, NativeFunction::from_fn_ptr(sign)),
+        ("verify", 5, NativeFunction::from_fn_ptr(verify)),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn digests() {
+        assert_eq!(
+            hex(&digest(Hash::Sha256, b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&digest(Hash::Sha1, b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    fn hmac_roundtrip() {
+        // RFC 4231, test case 2.
+        let signature = hmac_sign(Hash::Sha256, b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&signature),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert!(hmac_verify(
+            Hash::Sha256,
+            b"Jefe",
+            &signature,
+            b"what do ya want for nothing?"
+        ));
+        assert!(!hmac_verify(
+            Hash::Sha256,
+            b"Jefe",
+            &signature,
+            b"something else"
+        ));
+    }
+
+    #[test]
+    fn ecdsa_jwk_roundtrip() {
+        let key = p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+            "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        });
+        let (kind, public) = import("ECDSA", "jwk", jwk.to_string().as_bytes()).unwrap();
+        assert_eq!(kind, "public");
+        let signature = ecdsa_sign(Hash::Sha256, &[7; 32], b"data").unwrap();
+        assert!(ecdsa_verify(Hash::Sha256, &public, &signature, b"data").unwrap());
+        assert!(!ecdsa_verify(Hash::Sha256, &public, &signature, b"other").unwrap());
+    }
+
+    #[test]
+    fn unsupported_import() {
+        assert!(import("AES-GCM", "raw", b"key").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// TextEncoder and TextDecoder for UTF-8 and UTF-16LE.
+(function (host) {
+  "use strict";
+
+  const STATE = Symbol("state");
+
+  const LABELS = {
+    "utf-8": "utf-8",
+    utf8: "utf-8",
+    "unicode-1-1-utf-8": "utf-8",
+    "utf-16le": "utf-16le",
+    "utf-16": "utf-16le",
+  };
+
+  function view(input) {
+    if (input === undefined) return new Uint8Array(0);
+    if (input instanceof ArrayBuffer) return new Uint8Array(input);
+    if (ArrayBuffer.isView(input)) return new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+    throw new TypeError("Expected an ArrayBuffer or a view on one");
+  }
+
+  // How many bytes at the end of `bytes` start a UTF-8 sequence that isn't complete yet.
+  function incompleteUtf8(bytes) {
+    for (let back = 1; back <= Math.min(3, bytes.length); back++) {
+      const b = bytes[bytes.length - back];
+      if ((b & 0xc0) === 0x80) continue;
+      const needed = b >= 0xf0 ? 4 : b >= 0xe0 ? 3 : b >= 0xc0 ? 2 : 1;
+      return needed > back ? back : 0;
+    }
+    return 0;
+  }
+
+  function decodeUtf16(bytes, fatal) {
+    if (bytes.length % 2 === 1 && fatal) throw new TypeError("The encoded data is not valid UTF-16");
+    const units = [];
+    for (let i = 0; i + 1 < bytes.length; i += 2) units.push(bytes[i] | (bytes[i + 1] << 8));
+    let out = "";
+    for (let i = 0; i < units.length; i += 4096) {
+      out += String.fromCharCode(...units.slice(i, i + 4096));
+    }
+    if (bytes.length % 2 === 1) out += "\uFFFD";
+    return out;
+  }
+
+  class TextEncoder {
+    get encoding() {
+      return "utf-8";
+    }
+
+    encode(input = "") {
+      return host.encode(String(input));
+    }
+
+    encodeInto(source, destination) {
+      let read = 0;
+      let written = 0;
+      for (const c of String(source)) {
+        const bytes = host.encode(c);
+        if (written + bytes.length > destination.length) break;
+        destination.set(bytes, written);
+        written += bytes.length;
+        read += c.length;
+      }
+      return { read, written };
+    }
+  }
+
+  class TextDecoder {
+    constructor(label = "utf-8", options = {}) {
+      const encoding = LABELS[String(label).trim().toLowerCase()];
+      if (encoding === undefined) throw new RangeError(`The encoding ${label} is not supported`);
+      Object.defineProperties(this, {
+        encoding: { value: encoding, enumerable: true },
+        fatal: { value: Boolean(options.fatal), enumerable: true },
+        ignoreBOM: { value: Boolean(options.ignoreBOM), enumerable: true },
+      });
+      Object.defineProperty(this, STATE, { value: { pending: new Uint8Array(0), started: false } });
+    }
+
+    decode(input, options = {}) {
+      const state = this[STATE];
+      let bytes = view(input);
+      if (state.pending.length > 0) {
+        const joined = new Uint8Array(state.pending.length + bytes.length);
+        joined.set(state.pending);
+        joined.set(bytes, state.pending.length);
+        bytes = joined;
+      }
+      const utf8 = this.encoding === "utf-8";
+      let keep = 0;
+      if (options.stream) keep = utf8 ? incompleteUtf8(bytes) : bytes.length % 2;
+      state.pending = bytes.slice(bytes.length - keep);
+      bytes = bytes.subarray(0, bytes.length - keep);
+
+      if (!state.started && !this.ignoreBOM) {
+        if (utf8 && bytes[0] === 0xef && bytes[1] === 0xbb && bytes[2] === 0xbf) bytes = bytes.subarray(3);
+        if (!utf8 && bytes[0] === 0xff && bytes[1] === 0xfe) bytes = bytes.subarray(2);
+      }
+      state.started = Boolean(options.stream) && (state.started || bytes.length > 0);
+      return utf8 ? host.decode(bytes, this.fatal) : decodeUtf16(bytes, this.fatal);
+    }
+  }
+
+  globalThis.TextEncoder = TextEncoder;
+  globalThis.TextDecoder = TextDecoder;
+})
@@ -0,0 +1,150 @@
+// Event, EventTarget and the event types of WebSocket and Worker.
+(function (host) {
+  "use strict";
+
+  const STATE = Symbol("event state");
+  const LISTENERS = Symbol("listeners");
+
+  class Event {
+    constructor(type, init = {}) {
+      if (arguments.length === 0) throw new TypeError("Event needs a type");
+      Object.defineProperty(this, STATE, {
+        value: { target: null, currentTarget: null, canceled: false, stopped: false },
+      });
+      Object.defineProperties(this, {
+        type: { value: String(type), enumerable: true },
+        bubbles: { value: Boolean(init.bubbles), enumerable: true },
+        cancelable: { value: Boolean(init.cancelable), enumerable: true },
+        timeStamp: { value: Date.now(), enumerable: true },
+      });
+    }
+
+    get target() {
+      return this[STATE].target;
+    }
+
+    get currentTarget() {
+      return this[STATE].currentTarget;
+    }
+
+    get defaultPrevented() {
+      return this[STATE].canceled;
+    }
+
+    preventDefault() {
+      if (this.cancelable) this[STATE].canceled = true;
+    }
+
+    stopPropagation() {}
+
+    stopImmediatePropagation() {
+      this[STATE].stopped = true;
+    }
+  }
+
+  class EventTarget {
+    constructor() {
+      Object.defineProperty(this, LISTENERS, { value: new Map() });
+    }
+
+    addEventListener(type, listener, options = {}) {
+      if (listener === null || listener === undefined) return;
+      if (typeof options === "boolean") options = {};
+      const { once, signal } = options;
+      if (signal && signal.aborted) return;
+      const list = this[LISTENERS].get(type) || [];
+      if (list.some((l) => l.listener === listener)) return;
+      const entry = { listener, once: Boolean(once), removed: false };
+      list.push(entry);
+      this[LISTENERS].set(type, list);
+      if (signal) signal.addEventListener("abort", () => this.removeEventListener(type, listener));
+    }
+
+    removeEventListener(type, listener) {
+      const list = this[LISTENERS].get(type);
+      if (list === undefined) return;
+      const i = list.findIndex((l) => l.listener === listener);
+      if (i < 0) return;
+      list[i].removed = true;
+      list.splice(i, 1);
+    }
+
+    dispatchEvent(event) {
+      if (!(event instanceof Event)) throw new TypeError("Expected an Event");
+      const state = event[STATE];
+      state.target = this;
+      state.currentTarget = this;
+      for (const entry of [...(this[LISTENERS].get(event.type) || [])]) {
+        if (entry.removed) continue;
+        if (entry.once) this.removeEventListener(event.type, entry.listener);
+        try {
+          if (typeof entry.listener === "function") entry.listener.call(this, event);
+          else entry.listener.handleEvent(event);
+        } catch (e) {
+          // As in browsers, a throwing listener does not stop the others.
+          console.error("Uncaught error in event listener:", e);
+        }
+        if (state.stopped) break;
+      }
+      state.currentTarget = null;
+      return !state.canceled;
+    }
+  }
+
+  class MessageEvent extends Event {
+    constructor(type, init = {}) {
+      super(type, init);
+      Object.defineProperties(this, {
+        data: { value: init.data === undefined ? null : init.data, enumerable: true },
+        origin: { value: String(init.origin || ""), enumerable: true },
+        lastEventId: { value: String(init.lastEventId || ""), enumerable: true },
+        source: { value: init.source || null, enumerable: true },
+        ports: { value: Object.freeze([...(init.ports || [])]), enumerable: true },
+      });
+    }
+  }
+
+  class CloseEvent extends Event {
+    constructor(type, init = {}) {
+      super(type, init);
+      Object.defineProperties(this, {
+        code: { value: Number(init.code || 0), enumerable: true },
+        reason: { value: String(init.reason || ""), enumerable: true },
+        wasClean: { value: Boolean(init.wasClean), enumerable: true },
+      });
+    }
+  }
+
+  class ErrorEvent extends Event {
+    constructor(type, init = {}) {
+      super(type, init);
+      Object.defineProperties(this, {
+        message: { value: String(init.message || ""), enumerable: true },
+        error: { value: init.error, enumerable: true },
+        filename: { value: String(init.filename || ""), enumerable: true },
+        lineno: { value: Number(init.lineno || 0), enumerable: true },
+        colno: { value: Number(init.colno || 0), enumerable: true },
+      });
+    }
+  }
+
+  // For websocket.js and the like: calls the `on<type>` handler of `target`,
+  // then its listeners.
+  host.fire = (target, event) => {
+    const handler = target[`on${event.type}`];
+    if (typeof handler === "function") {
+      try {
+        handler.call(target, event);
+      } catch (e) {
+        console.error(`Uncaught error in on${event.type}:`, e);
+      }
+    }
+    target.dispatchEvent(event);
+  };
+
+  globalThis.Event = Event;
+  globalThis.EventTarget = EventTarget;
+  globalThis.MessageEvent = MessageEvent;
+  globalThis.CloseEvent = CloseEvent;
+  globalThis.ErrorEvent = ErrorEvent;
+})
@@ -0,0 +1,377 @@
+// fetch() with Headers, Request and Response, over the natives in fetch.rs.
+(function (host) {
+  "use strict";
+
+  const BODY = Symbol("body");
+  const HEADERS = Symbol("headers");
+
+  const TOKEN = /^[!#$%&'*+\-.^_`|~0-9a-zA-Z]+$/;
+
+  function headerName(name) {
+    name = String(name);
+    if (!TOKEN.test(name)) throw new TypeError(`Invalid header name: ${name}`);
+    return name.toLowerCase();
+  }
+
+  function headerValue(value) {
+    return String(value).replace(/^[\t\n\r ]+|[\t\n\r ]+$/g, "");
+  }
+
+  class Headers {
+    constructor(init) {
+      Object.defineProperty(this, HEADERS, { value: new Map() });
+      if (init === undefined || init === null) return;
+      if (init instanceof Headers || Array.isArray(init)) {
+        for (const [name, value] of init) this.append(name, value);
+      } else if (typeof init === "object") {
+        for (const name of Object.keys(init)) this.append(name, init[name]);
+      } else {
+        throw new TypeError("Invalid headers");
+      }
+    }
+
+    append(name, value) {
+      name = headerName(name);
+      value = headerValue(value);
+      const map = this[HEADERS];
+      map.set(name, map.has(name) ? `${map.get(name)}, ${value}` : value);
+    }
+
+    delete(name) {
+      this[HEADERS].delete(headerName(name));
+    }
+
+    get(name) {
+      const value = this[HEADERS].get(headerName(name));
+      return value === undefined ? null : value;
+    }
+
+    has(name) {
+      return this[HEADERS].has(headerName(name));
+    }
+
+    set(name, value) {
+      this[HEADERS].set(headerName(name), headerValue(value));
+    }
+
+    forEach(callback, thisArg) {
+      for (const [name, value] of this) callback.call(thisArg, value, name, this);
+    }
+
+    *entries() {
+      const names = [...this[HEADERS].keys()].sort();
+      for (const name of names) yield [name, this[HEADERS].get(name)];
+    }
+
+    *keys() {
+      for (const [name] of this.entries()) yield name;
+    }
+
+    *values() {
+      for (const [, value] of this.entries()) yield value;
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+  }
+
+  // Bytes or a stream, and the content type implied by a body init.
+  function extractBody(body) {
+    if (body === undefined || body === null) return [null, null, null];
+    if (body instanceof ReadableStream) return [null, null, body];
+    if (typeof body === "string") return [host.encode(body), "text/plain;charset=UTF-8", null];
+    if (body instanceof Uint8Array) return [body, null, null];
+    if (body instanceof ArrayBuffer) return [new Uint8Array(body), null, null];
+    if (ArrayBuffer.isView(body)) {
+      return [new Uint8Array(body.buffer, body.byteOffset, body.byteLength), null, null];
+    }
+    if (typeof URLSearchParams !== "undefined" && body instanceof URLSearchParams) {
+      return [host.encode(body.toString()), "application/x-www-form-urlencoded;charset=UTF-8", null];
+    }
+    return [host.encode(String(body)), "text/plain;charset=UTF-8", null];
+  }
+
+  function chunkBytes(chunk) {
+    if (chunk instanceof Uint8Array) return chunk;
+    if (chunk instanceof ArrayBuffer) return new Uint8Array(chunk);
+    if (ArrayBuffer.isView(chunk)) return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+    throw new TypeError("Body chunks must be Uint8Arrays");
+  }
+
+  // What Request and Response share: a body that is read at most once,
+  // either all at once or as a ReadableStream. Bodies given as bytes only
+  // become a stream once `body` is asked for.
+  class Body {
+    constructor(bytes, stream) {
+      Object.defineProperty(this, BODY, {
+        value: { bytes, stream, used: false },
+      });
+    }
+
+    get body() {
+      const state = this[BODY];
+      if (state.stream === null && state.bytes !== null) {
+        const bytes = state.bytes;
+        state.bytes = null;
+        state.stream = new ReadableStream({
+          start(controller) {
+            if (bytes.length > 0) controller.enqueue(bytes);
+            controller.close();
+          },
+        });
+      }
+      return state.stream;
+    }
+
+    get bodyUsed() {
+      const state = this[BODY];
+      return state.used || (state.stream !== null && host.isDisturbed(state.stream));
+    }
+
+    async arrayBuffer() {
+      const bytes = await consume(this);
+      return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+    }
+
+    async bytes() {
+      return consume(this);
+    }
+
+    async text() {
+      return host.decode(await consume(this));
+    }
+
+    async json() {
+      return JSON.parse(await this.text());
+    }
+  }
+
+  function unusable(body) {
+    const state = body[BODY];
+    return body.bodyUsed || (state.stream !== null && state.stream.locked);
+  }
+
+  async function consume(body) {
+    const state = body[BODY];
+    if (unusable(body)) throw new TypeError("The body has already been consumed");
+    state.used = true;
+    if (state.stream === null) return state.bytes === null ? new Uint8Array(0) : state.bytes;
+    const reader = state.stream.getReader();
+    const chunks = [];
+    let size = 0;
+    for (;;) {
+      const { value, done } = await reader.read();
+      if (done) break;
+      const chunk = chunkBytes(value);
+      chunks.push(chunk);
+      size += chunk.length;
+    }
+    const bytes = new Uint8Array(size);
+    let offset = 0;
+    for (const chunk of chunks) {
+      bytes.set(chunk, offset);
+      offset += chunk.length;
+    }
+    return bytes;
+  }
+
+  // Streams are teed, so both bodies can still be read as they arrive.
+  function cloneBody(from, to) {
+    const state = from[BODY];
+    if (unusable(from)) throw new TypeError("A body that has been read cannot be cloned");
+    if (state.stream !== null) {
+      const [a, b] = state.stream.tee();
+      state.stream = a;
+      to[BODY].stream = b;
+    } else {
+      to[BODY].bytes = state.bytes === null ? null : state.bytes.slice();
+    }
+  }
+
+  const METHODS = ["DELETE", "GET", "HEAD", "OPTIONS", "POST", "PUT", "PATCH"];
+
+  class Request extends Body {
+    constructor(input, init = {}) {
+      const from = input instanceof Request ? input : null;
+      const hasBody = init.body !== undefined && init.body !== null;
+      const [bytes, type, stream] = extractBody(init.body);
+      super(bytes, stream);
+
+      let method = String(init.method || (from ? from.method : "GET"));
+      if (METHODS.includes(method.toUpperCase())) method = method.toUpperCase();
+      if (hasBody && (method === "GET" || method === "HEAD")) {
+        throw new TypeError(`A ${method} request cannot have a body`);
+      }
+      const headers = new Headers(init.headers || (from ? from.headers : undefined));
+      if (type !== null && !headers.has("content-type")) headers.set("content-type", type);
+
+      Object.defineProperties(this, {
+        url: { value: from ? from.url : String(input), enumerable: true },
+        method: { value: method, enumerable: true },
+        headers: { value: headers, enumerable: true },
+        redirect: { value: init.redirect || (from ? from.redirect : "follow"), enumerable: true },
+        signal: {
+          value: init.signal || (from ? from.signal : new AbortController().signal),
+          enumerable: true,
+        },
+      });
+      if (from && !hasBody) cloneBody(from, this);
+    }
+
+    clone() {
+      const request = new Request(this);
+      return request;
+    }
+  }
+
+  const NULL_BODY = [101, 204, 205, 304];
+
+  class Response extends Body {
+    constructor(body = null, init = {}) {
+      const [bytes, type, stream] = extractBody(body);
+      super(bytes, stream);
+      const status = init.status === undefined ? 200 : Number(init.status);
+      if (!(status >= 200 && status <= 599) && status !== 0) {
+        throw new RangeError(`Invalid status: ${init.status}`);
+      }
+      if ((bytes !== null || stream !== null) && NULL_BODY.includes(status)) {
+        throw new TypeError(`A response with status ${status} cannot have a body`);
+      }
+      const headers = new Headers(init.headers);
+      if (type !== null && !headers.has("content-type")) headers.set("content-type", type);
+      Object.defineProperties(this, {
+        status: { value: status, enumerable: true },
+        statusText: { value: String(init.statusText || ""), enumerable: true },
+        headers: { value: headers, enumerable: true },
+        url: { value: "", enumerable: true, configurable: true },
+        type: { value: "default", enumerable: true, configurable: true },
+        redirected: { value: false, enumerable: true },
+      });
+    }
+
+    get ok() {
+      return this.status >= 200 && this.status <= 299;
+    }
+
+    clone() {
+      const response = new Response(null, this);
+      cloneBody(this, response);
+      Object.defineProperty(response, "url", { value: this.url });
+      return response;
+    }
+
+    static json(data, init = {}) {
+      const headers = new Headers(init.headers);
+      if (!headers.has("content-type")) headers.set("content-type", "application/json");
+      return new Response(JSON.stringify(data), { ...init, headers });
+    }
+
+    static redirect(url, status = 302) {
+      if (![301, 302, 303, 307, 308].includes(status)) {
+        throw new RangeError(`Invalid redirect status: ${status}`);
+      }
+      return new Response(null, { status, headers: { location: String(url) } });
+    }
+
+    static error() {
+      const response = new Response(null, { status: 0 });
+      Object.defineProperty(response, "type", { value: "error" });
+      return response;
+    }
+  }
+
+  async function fetch(input, init) {
+    const request = new Request(input, init);
+    const signal = request.signal;
+    signal.throwIfAborted();
+
+    const state = request[BODY];
+    const body = state.bytes === null && state.stream === null ? null : await consume(request);
+    const [id, head] = host.fetch(request.method, request.url, [...request.headers], body, request.redirect);
+    let onAbort;
+    const aborted = new Promise((_, reject) => {
+      onAbort = () => {
+        host.abort(id);
+        reject(signal.reason);
+      };
+    });
+    signal.addEventListener("abort", onAbort);
+
+    let response;
+    try {
+      response = await Promise.race([head, aborted]);
+    } catch (e) {
+      signal.removeEventListener("abort", onAbort);
+      throw e;
+    }
+    const done = () => signal.removeEventListener("abort", onAbort);
+    let stream = null;
+    if (NULL_BODY.includes(response.status) || request.method === "HEAD") {
+      done();
+      host.abort(id);
+    } else {
+      stream = new ReadableStream(
+        {
+          async pull(controller) {
+            signal.throwIfAborted();
+            const chunk = await Promise.race([host.read(id), aborted]);
+            if (chunk === null) {
+              done();
+              controller.close();
+            } else {
+              controller.enqueue(chunk);
+            }
+          },
+          cancel() {
+            done();
+            host.abort(id);
+          },
+        },
+        { highWaterMark: 0 }
+      );
+    }
+    const result = new Response(stream, {
+      status: response.status,
+      statusText: response.statusText,
+      headers: response.headers,
+    });
+    Object.defineProperties(result, {
+      url: { value: response.url },
+      type: { value: "basic" },
+    });
+    return result;
+  }
+
+  // Answers a request with an `export default { fetch(request, ctx) }` handler.
+  host.serve = async (handler, method, url, headers, body, ctx) => {
+    // Aborted when the client goes away before the response is done.
+    const controller = new AbortController();
+    host.cancelRequest = () => controller.abort(new DOMException("The client went away", "AbortError"));
+    const init = { method, headers, signal: controller.signal };
+    if (body.length > 0 && method !== "GET" && method !== "HEAD") init.body = body;
+    const response = await handler.fetch(new Request(url, init), ctx);
+    if (!(response instanceof Response)) {
+      throw new TypeError("The fetch handler did not return a Response");
+    }
+    const head = { status: response.status, headers: [...response.headers] };
+    if (response[BODY].stream === null) return { ...head, body: await consume(response) };
+    // Streamed bodies are read chunk by chunk as the client receives them.
+    if (unusable(response)) throw new TypeError("The response body has already been consumed");
+    const reader = response[BODY].stream.getReader();
+    return {
+      ...head,
+      body: null,
+      next: async () => {
+        const { value, done } = await reader.read();
+        return done ? null : chunkBytes(value);
+      },
+      cancel: (reason) => reader.cancel(reason),
+    };
+  };
+
+  globalThis.Headers = Headers;
+  globalThis.Request = Request;
+  globalThis.Response = Response;
+  globalThis.fetch = fetch;
+})
@@ -0,0 +1,341 @@
+use super::{dom_exception, from_bytes, string_pairs, to_bytes};
+use crate::fetch;
+use crate::js::event_loop;
+use crate::permissions::Permissions;
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsNativeError, JsObject, JsResult, JsString, JsValue,
+    NativeFunction,
+};
+use boa_gc::{Finalize, Trace};
+use futures::future::{AbortHandle, Abortable};
+use futures::AsyncReadExt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The incoming request, as a `fetch` handler gets to see it.
+pub(crate) struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// The `Response` a `fetch` handler answered with.
+#[derive(Debug)]
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Requests of a script that are still in flight or have a body left to read.
+#[derive(Default)]
+struct Requests {
+    next_id: u32,
+    aborts: HashMap<u32, AbortHandle>,
+    bodies: HashMap<u32, surf::Response>,
+}
+
+#[derive(Trace, Finalize, Clone)]
+pub(super) struct Host {
+    #[unsafe_ignore_trace]
+    requests: Rc<RefCell<Requests>>,
+    #[unsafe_ignore_trace]
+    permissions: Rc<Permissions>,
+}
+
+impl Host {
+    pub(super) fn new(permissions: &Permissions) -> Self {
+        Self {
+            requests: Rc::new(RefCell::new(Requests::default())),
+            permissions: Rc::new(permissions.clone()),
+        }
+    }
+
+    fn abortable<F: std::future::Future>(&self, id: u32, f: F) -> Abortable<F> {
+        let (handle, registration) = AbortHandle::new_pair();
+        self.requests.borrow_mut().aborts.insert(id, handle);
+        Abortable::new(f, registration)
+    }
+}
+
+fn aborted() -> boa_engine::JsError {
+    JsNativeError::error()
+        .with_message("The operation was aborted")
+        .into()
+}
+
+/// `host.fetch(method, url, headers, body, redirect)`, returning the id of the
+/// request and a promise of the response head.
+fn start(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let method = args
+        .get_or_undefined(0)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let url = args
+        .get_or_undefined(1)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let headers = string_pairs(args.get_or_undefined(2), context)?;
+    let body = match args.get_or_undefined(3) {
+        v if v.is_null_or_undefined() => None,
+        v => Some(to_bytes(v, context)?),
+    };
+    let follow = args
+        .get_or_undefined(4)
+        .to_string(context)?
+        .to_std_string_escaped()
+        != "manual";
+    if let Ok(u) = url::Url::parse(&url) {
+        if let Err(e) = host.permissions.check_net(&u) {
+            return Err(dom_exception(&e, "NotAllowedError", context));
+        }
+    }
+
+    let id = {
+        let mut requests = host.requests.borrow_mut();
+        requests.next_id += 1;
+        requests.next_id
+    };
+    let request = {
+        let url = url.clone();
+        let permissions = host.permissions.clone();
+        async move { fetch::request(&url, &method, &headers, body, follow, &permissions).await }
+    };
+    let request = host.abortable(id, request);
+    let h = host.clone();
+    let promise = event_loop::promise(
+        request,
+        move |res, context| {
+            let mut requests = h.requests.borrow_mut();
+            requests.aborts.remove(&id);
+            let response = match res {
+                Ok(Ok(r)) => r,
+                Ok(Err(e)) => {
+                    log::error!("Request Error: {}", e);
+                    return Err(JsNativeError::typ()
+                        .with_message(format!("fetch failed: {}", e))
+                        .into());
+                }
+                Err(_) => return Err(aborted()),
+            };
+            let status = response.status();
+            let headers = JsArray::new(context);
+            for (n, v) in response.iter() {
+                let pair = JsArray::from_iter(
+                    [
+                        JsString::from(n.as_str()).into(),
+                        JsString::from(v.as_str()).into(),
+                    ],
+                    context,
+                );
+                headers.push(pair, context)?;
+            }
+            requests.bodies.insert(id, response);
+            let head = ObjectInitializer::new(context)
+                .property(js_string!("status"), u16::from(status), Attribute::all())
+                .property(
+                    js_string!("statusText"),
+                    JsString::from(status.canonical_reason()),
+                    Attribute::all(),
+                )
+                .property(js_string!("headers"), headers, Attribute::all())
+                .property(
+                    js_string!("url"),
+                    JsString::from(url.as_str()),
+                    Attribute::all(),
+                )
+                .build();
+            Ok(head.into())
+        },
+        context,
+    );
+    Ok(JsArray::from_iter([id.into(), promise.into()], context).into())
+}
+
+/// `host.read(id)`, a promise of the next chunk of the body or `null` at its end.
+fn read(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let mut response = match host.requests.borrow_mut().bodies.remove(&id) {
+        Some(r) => r,
+        None => return Err(aborted()),
+    };
+    let chunk = async move {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let n = response.read(&mut buf).await;
+        buf.truncate(*n.as_ref().unwrap_or(&0));
+        (response, n.map(|_| buf))
+    };
+    let chunk = host.abortable(id, chunk);
+    let h = host.clone();
+    let promise = event_loop::promise(
+        chunk,
+        move |res, context| {
+            let mut requests = h.requests.borrow_mut();
+            requests.aborts.remove(&id);
+            match res {
+                Ok((_, Ok(buf))) if buf.is_empty() => Ok(JsValue::null()),
+                Ok((response, Ok(buf))) => {
+                    requests.bodies.insert(id, response);
+                    from_bytes(buf, context)
+                }
+                Ok((_, Err(e))) => Err(JsNativeError::typ()
+                    .with_message(format!("Error reading body: {}", e))
+                    .into()),
+                Err(_) => Err(aborted()),
+            }
+        },
+        context,
+    );
+    Ok(promise.into())
+}
+
+/// `host.abort(id)`, cancelling the request or dropping its body.
+fn abort(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let mut requests = host.requests.borrow_mut();
+    if let Some(handle) = requests.aborts.remove(&id) {
+        handle.abort();
+    }
+    requests.bodies.remove(&id);
+    Ok(JsValue::undefined())
+}
+
+/// The natives behind `fetch`, as name, length and function.
+pub(super) fn functions(host: Host) -> [(&'static str, usize, NativeFunction); 3] {
+    [
+        (
+            "fetch",
+            5,
+            NativeFunction::from_copy_closure_with_captures(start, host.clone()),
+        ),
+        (
+            "read",
+            1,
+            NativeFunction::from_copy_closure_with_captures(read, host.clone()),
+        ),
+        (
+            "abort",
+            1,
+            NativeFunction::from_copy_closure_with_captures(abort, host),
+        ),
+    ]
+}
+
+/// Calls the `fetch` method of `handler` with a `Request` for `request` and
+/// `ctx`, returning a promise of the parts of its response.
+pub(crate) fn serve(
+    natives: &JsObject,
+    handler: &JsValue,
+    request: &HttpRequest,
+    ctx: JsValue,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let serve = natives.get(js_string!("serve"), context)?;
+    let serve = serve
+        .as_callable()
+        .ok_or_else(|| JsNativeError::typ().with_message("fetch.js did not install serve"))?;
+    let headers = JsArray::new(context);
+    for (n, v) in &request.headers {
+        let pair = JsArray::from_iter(
+            [
+                JsString::from(n.as_str()).into(),
+                JsString::from(v.as_str()).into(),
+            ],
+            context,
+        );
+        headers.push(pair, context)?;
+    }
+    let body = from_bytes(request.body.clone(), context)?;
+    serve.call(
+        &JsValue::undefined(),
+        &[
+            handler.clone(),
+            JsString::from(request.method.as_str()).into(),
+            JsString::from(request.url.as_str()).into(),
+            headers.into(),
+            body,
+            ctx,
+        ],
+        context,
+    )
+}
+
+/// Reads the `{status, headers, body}` that `serve` resolves to. The body of a
+/// streamed response is left empty, see [`body_stream`].
+pub(crate) fn response_parts(value: &JsValue, context: &mut Context<'_>) -> JsResult<HttpResponse> {
+    let parts = value
+        .as_object()
+        .ok_or_else(|| JsNativeError::typ().with_message("Invalid response"))?;
+    let status = parts.get(js_string!("status"), context)?.to_u32(context)?;
+    let status = u16::try_from(status)
+        .map_err(|_| JsNativeError::range().with_message(format!("Invalid status {}", status)))?;
+    let headers = string_pairs(&parts.get(js_string!("headers"), context)?, context)?;
+    let body = match parts.get(js_string!("body"), context)? {
+        v if v.is_null() => Vec::new(),
+        v => to_bytes(&v, context)?,
+    };
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// The body of a response that a handler answered with a `ReadableStream`.
+pub(crate) struct BodyStream {
+    next: JsObject,
+    cancel: JsObject,
+}
+
+impl BodyStream {
+    /// A promise of the next chunk, or of `null` at the end.
+    pub(crate) fn next(&self, context: &mut Context<'_>) -> JsResult<JsValue> {
+        self.next.call(&JsValue::undefined(), &[], context)
+    }
+
+    /// Cancels the stream, returning a promise of when its source is done.
+    pub(crate) fn cancel(&self, context: &mut Context<'_>) -> JsResult<JsValue> {
+        self.cancel.call(&JsValue::undefined(), &[], context)
+    }
+}
+
+/// The stream of the parts `serve` resolved to, if the body is streamed.
+pub(crate) fn body_stream(
+    value: &JsValue,
+    context: &mut Context<'_>,
+) -> JsResult<Option<BodyStream>> {
+    let parts = value
+        .as_object()
+        .ok_or_else(|| JsNativeError::typ().with_message("Invalid response"))?;
+    let next = parts.get(js_string!("next"), context)?;
+    let cancel = parts.get(js_string!("cancel"), context)?;
+    match (next.as_callable(), cancel.as_callable()) {
+        (Some(next), Some(cancel)) => Ok(Some(BodyStream {
+            next: next.clone(),
+            cancel: cancel.clone(),
+        })),
+        _ => Ok(None),
+    }
+}
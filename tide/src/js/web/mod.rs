@@ -0,0 +1,209 @@
+//! The web platform APIs scripts expect to find, written in JavaScript on top
+//! of a few natives. Each file evaluates to a function that receives the
+//! natives and installs its globals.
+
+mod console;
+mod crypto;
+mod fetch;
+mod url;
+mod websocket;
+
+pub(crate) use console::ScriptName;
+pub(crate) use fetch::{body_stream, response_parts, serve, BodyStream, HttpRequest, HttpResponse};
+pub(crate) use websocket::open_sockets;
+
+use crate::permissions::Permissions;
+use boa_engine::object::builtins::{JsArray, JsMap, JsUint8Array};
+use boa_engine::object::ObjectInitializer;
+use boa_engine::{
+    js_string, Context, JsArgs, JsError, JsNativeError, JsObject, JsResult, JsString, JsValue,
+    NativeFunction, Source,
+};
+use serde_json::Value;
+
+const PRELUDE: [(&str, &str); 10] = [
+    ("console.js", include_str!("console.js")),
+    ("events.js", include_str!("events.js")),
+    ("abort.js", include_str!("abort.js")),
+    ("encoding.js", include_str!("encoding.js")),
+    ("clone.js", include_str!("clone.js")),
+    ("crypto.js", include_str!("crypto.js")),
+    ("url.js", include_str!("url.js")),
+    ("streams.js", include_str!("streams.js")),
+    ("fetch.js", include_str!("fetch.js")),
+    ("websocket.js", include_str!("websocket.js")),
+];
+
+/// The largest integer a JavaScript number holds exactly.
+pub(crate) const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Turns a string, an `ArrayBuffer` or any view on one into bytes. Strings are
+/// encoded as UTF-8.
+pub(crate) fn to_bytes(value: &JsValue, context: &mut Context<'_>) -> JsResult<Vec<u8>> {
+    if let Some(s) = value.as_string() {
+        return Ok(String::from_utf16_lossy(s).into_bytes());
+    }
+    let object = value.as_object().ok_or_else(|| {
+        JsNativeError::typ().with_message("Expected a string, an ArrayBuffer or a typed array")
+    })?;
+    // Look at every kind of buffer as bytes, a Uint16Array counts two per element.
+    let uint8 = context
+        .global_object()
+        .get(js_string!("Uint8Array"), context)?;
+    let uint8 = uint8
+        .as_constructor()
+        .ok_or_else(|| JsNativeError::typ().with_message("Uint8Array is missing"))?;
+    let buffer = object.get(js_string!("buffer"), context)?;
+    let object = if buffer.is_object() {
+        let offset = object.get(js_string!("byteOffset"), context)?;
+        let length = object.get(js_string!("byteLength"), context)?;
+        uint8.construct(&[buffer, offset, length], None, context)?
+    } else {
+        uint8.construct(&[object.clone().into()], None, context)?
+    };
+    let len = object
+        .get(js_string!("length"), context)?
+        .to_length(context)?;
+    let mut bytes = Vec::with_capacity(len as usize);
+    for i in 0..len as usize {
+        bytes.push(object.get(i, context)?.to_uint8(context)?);
+    }
+    Ok(bytes)
+}
+
+/// Reads an array of `[name, value]` pairs, as headers and form fields are passed.
+pub(crate) fn string_pairs(
+    value: &JsValue,
+    context: &mut Context<'_>,
+) -> JsResult<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let list = match value.as_object() {
+        Some(o) => JsArray::from_object(o.clone())?,
+        None => return Ok(pairs),
+    };
+    for i in 0..list.length(context)? {
+        let pair = list.get(i, context)?;
+        let pair = pair
+            .as_object()
+            .ok_or_else(|| JsNativeError::typ().with_message("Invalid header"))?;
+        let name = pair.get(0, context)?.to_string(context)?;
+        let value = pair.get(1, context)?.to_string(context)?;
+        pairs.push((name.to_std_string_escaped(), value.to_std_string_escaped()));
+    }
+    Ok(pairs)
+}
+
+/// Converts what a script answered with into json, following the rules in
+/// clone.js: Maps become objects, Sets and typed arrays arrays, BigInts that
+/// don't fit a double strings, and cyclic values an error.
+pub(crate) fn to_json(
+    natives: &JsObject,
+    value: &JsValue,
+    context: &mut Context<'_>,
+) -> JsResult<Value> {
+    let convert = natives.get(js_string!("toJson"), context)?;
+    let convert = convert
+        .as_callable()
+        .ok_or_else(|| JsNativeError::typ().with_message("clone.js did not install toJson"))?;
+    let value = convert.call(&JsValue::undefined(), &[value.clone()], context)?;
+    value.to_json(context)
+}
+
+/// The `[key, value]` pairs of `object`, `Array.from` of a Map and
+/// `Object.entries` of anything else.
+pub(crate) fn entries(object: &JsObject, context: &mut Context<'_>) -> JsResult<JsArray> {
+    let (global, name) = match JsMap::from_object(object.clone()) {
+        Ok(_) => ("Array", "from"),
+        Err(_) => ("Object", "entries"),
+    };
+    let missing = || JsNativeError::typ().with_message(format!("{}.{} is missing", global, name));
+    let constructor = context
+        .global_object()
+        .get(JsString::from(global), context)?;
+    let method = constructor
+        .as_object()
+        .ok_or_else(missing)?
+        .get(JsString::from(name), context)?;
+    let method = method.as_callable().ok_or_else(missing)?;
+    let pairs = method.call(&constructor, &[object.clone().into()], context)?;
+    JsArray::from_object(pairs.as_object().cloned().ok_or_else(missing)?)
+}
+
+pub(crate) fn from_bytes(bytes: Vec<u8>, context: &mut Context<'_>) -> JsResult<JsValue> {
+    Ok(JsUint8Array::from_iter(bytes, context)?.into())
+}
+
+/// `host.encode(string)`, UTF-8 bytes of the string.
+fn encode(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let s = args.get_or_undefined(0).to_string(context)?;
+    from_bytes(String::from_utf16_lossy(&s).into_bytes(), context)
+}
+
+/// `host.decode(bytes, fatal)`, the string in UTF-8 bytes. Invalid sequences
+/// are replaced, or throw if `fatal` is set.
+fn decode(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let bytes = to_bytes(args.get_or_undefined(0), context)?;
+    if args.get_or_undefined(1).to_boolean() {
+        let s = std::str::from_utf8(&bytes).map_err(|e| {
+            JsNativeError::typ().with_message(format!("The encoded data is not valid UTF-8: {}", e))
+        })?;
+        return Ok(JsString::from(s).into());
+    }
+    Ok(JsString::from(String::from_utf8_lossy(&bytes).as_ref()).into())
+}
+
+/// A `DOMException` called `name`, as browsers throw them, or an `Error` if
+/// there is no `DOMException` yet.
+pub(crate) fn dom_exception(message: &str, name: &str, context: &mut Context<'_>) -> JsError {
+    let exception = context
+        .global_object()
+        .get(js_string!("DOMException"), context)
+        .ok()
+        .and_then(|c| c.as_constructor().cloned());
+    if let Some(exception) = exception {
+        let args = [JsString::from(message).into(), JsString::from(name).into()];
+        if let Ok(e) = exception.construct(&args, None, context) {
+            return JsError::from_opaque(e.into());
+        }
+    }
+    JsNativeError::error().with_message(message).into()
+}
+
+/// Installs the APIs and returns the object holding their natives. `script`
+/// is the path console output is logged under, and `permissions` what
+/// `fetch` and `WebSocket` may connect to.
+pub(crate) fn register(
+    script: &ScriptName,
+    permissions: &Permissions,
+    context: &mut Context<'_>,
+) -> JsResult<JsObject> {
+    let mut natives = ObjectInitializer::new(context);
+    natives
+        .function(NativeFunction::from_fn_ptr(encode), "encode", 1)
+        .function(NativeFunction::from_fn_ptr(decode), "decode", 1);
+    for (name, length, f) in fetch::functions(fetch::Host::new(permissions)) {
+        natives.function(f, name, length);
+    }
+    for (name, length, f) in console::functions(script) {
+        natives.function(f, name, length);
+    }
+    for (name, length, f) in crypto::functions() {
+        natives.function(f, name, length);
+    }
+    for (name, length, f) in url::functions() {
+        natives.function(f, name, length);
+    }
+    for (name, length, f) in websocket::functions(websocket::Host::new(permissions)) {
+        natives.function(f, name, length);
+    }
+    let natives = natives.build();
+
+    for (name, source) in PRELUDE {
+        let install = context.eval(Source::from_bytes(source))?;
+        let install = install.as_callable().ok_or_else(|| {
+            JsNativeError::typ().with_message(format!("{} does not evaluate to a function", name))
+        })?;
+        install.call(&JsValue::undefined(), &[natives.clone().into()], context)?;
+    }
+    Ok(natives)
+}
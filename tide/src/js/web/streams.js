@@ -0,0 +1,590 @@
+// ReadableStream, WritableStream and TransformStream, the default (not byte)
+// flavours of the Streams standard. Bodies of Request and Response are
+// ReadableStreams, see fetch.js.
+(function (host) {
+  "use strict";
+
+  const STATE = Symbol("stream state");
+  const KEY = Symbol("constructor key");
+
+  function sizeOf(strategy) {
+    return typeof strategy.size === "function" ? strategy.size : () => 1;
+  }
+
+  function highWaterMark(strategy, fallback) {
+    const hwm = strategy.highWaterMark === undefined ? fallback : Number(strategy.highWaterMark);
+    if (Number.isNaN(hwm) || hwm < 0) throw new RangeError("Invalid highWaterMark");
+    return hwm;
+  }
+
+  // ReadableStream
+
+  class ReadableStreamDefaultController {
+    constructor(key, stream) {
+      if (key !== KEY) throw new TypeError("Illegal constructor");
+      Object.defineProperty(this, STATE, { value: stream[STATE] });
+    }
+
+    get desiredSize() {
+      const state = this[STATE];
+      if (state.state === "errored") return null;
+      if (state.state === "closed") return 0;
+      return state.highWaterMark - state.queueSize;
+    }
+
+    enqueue(chunk) {
+      const state = this[STATE];
+      if (state.closeRequested || state.state !== "readable") {
+        throw new TypeError("The stream is closed or errored");
+      }
+      if (state.reads.length > 0) {
+        state.reads.shift().resolve({ value: chunk, done: false });
+      } else {
+        const size = state.size(chunk);
+        state.queue.push([chunk, size]);
+        state.queueSize += size;
+      }
+      pullIfNeeded(state);
+    }
+
+    close() {
+      const state = this[STATE];
+      if (state.closeRequested || state.state !== "readable") {
+        throw new TypeError("The stream is already closed or errored");
+      }
+      state.closeRequested = true;
+      if (state.queue.length === 0) finishClose(state);
+    }
+
+    error(e) {
+      errorReadable(this[STATE], e);
+    }
+  }
+
+  function finishClose(state) {
+    state.state = "closed";
+    for (const read of state.reads.splice(0)) read.resolve({ value: undefined, done: true });
+    if (state.reader !== null) state.reader[STATE].closed.resolve();
+  }
+
+  function errorReadable(state, e) {
+    if (state.state !== "readable") return;
+    state.state = "errored";
+    state.storedError = e;
+    state.queue = [];
+    state.queueSize = 0;
+    for (const read of state.reads.splice(0)) read.reject(e);
+    if (state.reader !== null) state.reader[STATE].closed.reject(e);
+  }
+
+  function pullIfNeeded(state) {
+    if (!state.started || state.state !== "readable" || state.closeRequested) return;
+    if (state.reads.length === 0 && state.highWaterMark - state.queueSize <= 0) return;
+    if (typeof state.source.pull !== "function") return;
+    if (state.pulling) {
+      state.pullAgain = true;
+      return;
+    }
+    state.pulling = true;
+    new Promise((resolve) => resolve(state.source.pull(state.controller))).then(
+      () => {
+        state.pulling = false;
+        if (state.pullAgain) {
+          state.pullAgain = false;
+          pullIfNeeded(state);
+        }
+      },
+      (e) => errorReadable(state, e)
+    );
+  }
+
+  function deferred() {
+    let resolve, reject;
+    const promise = new Promise((res, rej) => {
+      resolve = res;
+      reject = rej;
+    });
+    // Nobody has to look at `closed`, so its rejection is not worth reporting.
+    promise.catch(() => {});
+    return { promise, resolve, reject };
+  }
+
+  function readFrom(state) {
+    state.disturbed = true;
+    if (state.queue.length > 0) {
+      const [chunk, size] = state.queue.shift();
+      state.queueSize -= size;
+      if (state.closeRequested && state.queue.length === 0) finishClose(state);
+      else pullIfNeeded(state);
+      return Promise.resolve({ value: chunk, done: false });
+    }
+    if (state.state === "closed") return Promise.resolve({ value: undefined, done: true });
+    if (state.state === "errored") return Promise.reject(state.storedError);
+    const read = deferred();
+    state.reads.push(read);
+    pullIfNeeded(state);
+    return read.promise;
+  }
+
+  function cancelReadable(state, reason) {
+    state.disturbed = true;
+    if (state.state === "closed") return Promise.resolve();
+    if (state.state === "errored") return Promise.reject(state.storedError);
+    state.queue = [];
+    state.queueSize = 0;
+    finishClose(state);
+    const cancel = state.source.cancel;
+    return new Promise((resolve) => resolve(typeof cancel === "function" ? cancel.call(state.source, reason) : undefined)).then(() => undefined);
+  }
+
+  class ReadableStreamDefaultReader {
+    constructor(stream) {
+      if (!(stream instanceof ReadableStream)) throw new TypeError("Expected a ReadableStream");
+      const state = stream[STATE];
+      if (state.reader !== null) throw new TypeError("The stream is already locked to a reader");
+      const closed = deferred();
+      if (state.state === "closed") closed.resolve();
+      if (state.state === "errored") closed.reject(state.storedError);
+      Object.defineProperty(this, STATE, { value: { stream: state, closed } });
+      state.reader = this;
+    }
+
+    get closed() {
+      return this[STATE].closed.promise;
+    }
+
+    read() {
+      const stream = this[STATE].stream;
+      if (stream === null) return Promise.reject(new TypeError("The reader has been released"));
+      return readFrom(stream);
+    }
+
+    cancel(reason) {
+      const stream = this[STATE].stream;
+      if (stream === null) return Promise.reject(new TypeError("The reader has been released"));
+      return cancelReadable(stream, reason);
+    }
+
+    releaseLock() {
+      const state = this[STATE];
+      if (state.stream === null) return;
+      const error = new TypeError("The reader has been released");
+      for (const read of state.stream.reads.splice(0)) read.reject(error);
+      if (state.stream.state === "readable") state.closed.reject(error);
+      state.stream.reader = null;
+      state.stream = null;
+    }
+  }
+
+  class ReadableStream {
+    constructor(source = {}, strategy = {}) {
+      if (source.type === "bytes") throw new RangeError("Byte streams are not supported");
+      const state = {
+        state: "readable",
+        source,
+        queue: [],
+        queueSize: 0,
+        reads: [],
+        reader: null,
+        started: false,
+        pulling: false,
+        pullAgain: false,
+        closeRequested: false,
+        disturbed: false,
+        storedError: undefined,
+        highWaterMark: highWaterMark(strategy, 1),
+        size: sizeOf(strategy),
+        controller: null,
+      };
+      Object.defineProperty(this, STATE, { value: state });
+      state.controller = new ReadableStreamDefaultController(KEY, this);
+      const start = typeof source.start === "function" ? source.start.call(source, state.controller) : undefined;
+      Promise.resolve(start).then(
+        () => {
+          state.started = true;
+          pullIfNeeded(state);
+        },
+        (e) => errorReadable(state, e)
+      );
+    }
+
+    static from(iterable) {
+      const iterator =
+        typeof iterable[Symbol.asyncIterator] === "function"
+          ? iterable[Symbol.asyncIterator]()
+          : iterable[Symbol.iterator]();
+      return new ReadableStream(
+        {
+          async pull(controller) {
+            const { value, done } = await iterator.next();
+            if (done) controller.close();
+            else controller.enqueue(value);
+          },
+          async cancel(reason) {
+            if (typeof iterator.return === "function") await iterator.return(reason);
+          },
+        },
+        { highWaterMark: 0 }
+      );
+    }
+
+    get locked() {
+      return this[STATE].reader !== null;
+    }
+
+    cancel(reason) {
+      if (this.locked) return Promise.reject(new TypeError("The stream is locked to a reader"));
+      return cancelReadable(this[STATE], reason);
+    }
+
+    getReader(options = {}) {
+      if (options.mode !== undefined) throw new RangeError("Only default readers are supported");
+      return new ReadableStreamDefaultReader(this);
+    }
+
+    tee() {
+      const reader = this.getReader();
+      const canceled = [false, false];
+      const reasons = [];
+      let reading = false;
+      let branches;
+      const pull = () => {
+        if (reading) return;
+        reading = true;
+        return reader.read().then(
+          ({ value, done }) => {
+            reading = false;
+            branches.forEach((branch, i) => {
+              if (canceled[i]) return;
+              const controller = branch[STATE].controller;
+              if (done) controller.close();
+              else controller.enqueue(value);
+            });
+          },
+          (e) => branches.forEach((branch) => errorReadable(branch[STATE], e))
+        );
+      };
+      const cancel = (i) => (reason) => {
+        canceled[i] = true;
+        reasons[i] = reason;
+        if (canceled[0] && canceled[1]) return reader.cancel(reasons);
+      };
+      branches = [0, 1].map((i) => new ReadableStream({ pull, cancel: cancel(i) }, { highWaterMark: 0 }));
+      return branches;
+    }
+
+    async pipeTo(destination, options = {}) {
+      if (this.locked) throw new TypeError("The stream is locked to a reader");
+      if (destination.locked) throw new TypeError("The destination is locked to a writer");
+      const { preventClose, preventAbort, preventCancel, signal } = options;
+      const reader = this.getReader();
+      const writer = destination.getWriter();
+      let onAbort;
+      const aborted = new Promise((_, reject) => {
+        onAbort = () => reject(signal.reason);
+      });
+      aborted.catch(() => {});
+      if (signal) {
+        if (signal.aborted) onAbort();
+        else signal.addEventListener("abort", onAbort);
+      }
+      try {
+        for (;;) {
+          const { value, done } = await Promise.race([reader.read(), aborted]);
+          if (done) break;
+          await Promise.race([writer.ready, aborted]);
+          writer.write(value).catch(() => {});
+        }
+        if (!preventClose) await writer.close();
+      } catch (e) {
+        if (!preventCancel) await reader.cancel(e).catch(() => {});
+        if (!preventAbort) await writer.abort(e).catch(() => {});
+        throw e;
+      } finally {
+        if (signal) signal.removeEventListener("abort", onAbort);
+        reader.releaseLock();
+        writer.releaseLock();
+      }
+    }
+
+    pipeThrough(transform, options) {
+      this.pipeTo(transform.writable, options).catch(() => {});
+      return transform.readable;
+    }
+
+    async *values(options = {}) {
+      const reader = this.getReader();
+      let done = false;
+      try {
+        for (;;) {
+          const result = await reader.read();
+          if (result.done) {
+            done = true;
+            return;
+          }
+          yield result.value;
+        }
+      } finally {
+        if (!done && !options.preventCancel) await reader.cancel();
+        reader.releaseLock();
+      }
+    }
+
+    [Symbol.asyncIterator](options) {
+      return this.values(options);
+    }
+  }
+
+  // WritableStream
+
+  class WritableStreamDefaultController {
+    constructor(key, state) {
+      if (key !== KEY) throw new TypeError("Illegal constructor");
+      Object.defineProperty(this, STATE, { value: state });
+    }
+
+    error(e) {
+      errorWritable(this[STATE], e);
+    }
+  }
+
+  function desiredWriteSize(state) {
+    if (state.state === "errored") return null;
+    if (state.state === "closed") return 0;
+    return state.highWaterMark - state.queueSize;
+  }
+
+  function errorWritable(state, e) {
+    if (state.state === "errored" || state.state === "closed") return;
+    state.state = "errored";
+    state.storedError = e;
+    for (const op of state.queue.splice(0)) op.done.reject(e);
+    state.queueSize = 0;
+    for (const ready of state.ready.splice(0)) ready.resolve();
+    if (state.writer !== null) state.writer[STATE].closed.reject(e);
+  }
+
+  // Runs the queued writes and the close one after another.
+  function advance(state) {
+    if (!state.started || state.writing || state.queue.length === 0 || state.state === "errored") return;
+    const op = state.queue[0];
+    state.writing = true;
+    const sink = state.sink;
+    const run = () => {
+      if (op.close) return typeof sink.close === "function" ? sink.close.call(sink) : undefined;
+      return typeof sink.write === "function" ? sink.write.call(sink, op.chunk, state.controller) : undefined;
+    };
+    new Promise((resolve) => resolve(run())).then(
+      () => {
+        state.writing = false;
+        state.queue.shift();
+        state.queueSize -= op.size;
+        op.done.resolve();
+        if (op.close) {
+          state.state = "closed";
+          if (state.writer !== null) state.writer[STATE].closed.resolve();
+        }
+        if (desiredWriteSize(state) > 0) for (const ready of state.ready.splice(0)) ready.resolve();
+        advance(state);
+      },
+      (e) => {
+        state.writing = false;
+        errorWritable(state, e);
+      }
+    );
+  }
+
+  function enqueueWrite(state, op) {
+    if (state.state !== "writable" || state.closing) {
+      return Promise.reject(state.state === "errored" ? state.storedError : new TypeError("The stream is closing or closed"));
+    }
+    op.done = deferred();
+    if (op.close) state.closing = true;
+    state.queue.push(op);
+    state.queueSize += op.size;
+    advance(state);
+    return op.done.promise;
+  }
+
+  function abortWritable(state, reason) {
+    if (state.state === "closed" || state.state === "errored") return Promise.resolve();
+    errorWritable(state, reason);
+    const abort = state.sink.abort;
+    return new Promise((resolve) => resolve(typeof abort === "function" ? abort.call(state.sink, reason) : undefined)).then(() => undefined);
+  }
+
+  class WritableStreamDefaultWriter {
+    constructor(stream) {
+      if (!(stream instanceof WritableStream)) throw new TypeError("Expected a WritableStream");
+      const state = stream[STATE];
+      if (state.writer !== null) throw new TypeError("The stream is already locked to a writer");
+      const closed = deferred();
+      if (state.state === "closed") closed.resolve();
+      if (state.state === "errored") closed.reject(state.storedError);
+      Object.defineProperty(this, STATE, { value: { stream: state, closed } });
+      state.writer = this;
+    }
+
+    get closed() {
+      return this[STATE].closed.promise;
+    }
+
+    get desiredSize() {
+      const stream = this[STATE].stream;
+      if (stream === null) throw new TypeError("The writer has been released");
+      return desiredWriteSize(stream);
+    }
+
+    get ready() {
+      const stream = this[STATE].stream;
+      if (stream === null) return Promise.reject(new TypeError("The writer has been released"));
+      if (stream.state === "errored") return Promise.reject(stream.storedError);
+      if (desiredWriteSize(stream) > 0 || stream.state !== "writable") return Promise.resolve();
+      const ready = deferred();
+      stream.ready.push(ready);
+      return ready.promise;
+    }
+
+    write(chunk) {
+      const stream = this[STATE].stream;
+      if (stream === null) return Promise.reject(new TypeError("The writer has been released"));
+      return enqueueWrite(stream, { chunk, size: stream.size(chunk), close: false });
+    }
+
+    close() {
+      const stream = this[STATE].stream;
+      if (stream === null) return Promise.reject(new TypeError("The writer has been released"));
+      return enqueueWrite(stream, { size: 0, close: true });
+    }
+
+    abort(reason) {
+      const stream = this[STATE].stream;
+      if (stream === null) return Promise.reject(new TypeError("The writer has been released"));
+      return abortWritable(stream, reason);
+    }
+
+    releaseLock() {
+      const state = this[STATE];
+      if (state.stream === null) return;
+      state.stream.writer = null;
+      state.stream = null;
+    }
+  }
+
+  class WritableStream {
+    constructor(sink = {}, strategy = {}) {
+      const state = {
+        state: "writable",
+        sink,
+        queue: [],
+        queueSize: 0,
+        ready: [],
+        writer: null,
+        started: false,
+        writing: false,
+        closing: false,
+        storedError: undefined,
+        highWaterMark: highWaterMark(strategy, 1),
+        size: sizeOf(strategy),
+        controller: null,
+      };
+      Object.defineProperty(this, STATE, { value: state });
+      state.controller = new WritableStreamDefaultController(KEY, state);
+      const start = typeof sink.start === "function" ? sink.start.call(sink, state.controller) : undefined;
+      Promise.resolve(start).then(
+        () => {
+          state.started = true;
+          advance(state);
+        },
+        (e) => errorWritable(state, e)
+      );
+    }
+
+    get locked() {
+      return this[STATE].writer !== null;
+    }
+
+    getWriter() {
+      return new WritableStreamDefaultWriter(this);
+    }
+
+    abort(reason) {
+      if (this.locked) return Promise.reject(new TypeError("The stream is locked to a writer"));
+      return abortWritable(this[STATE], reason);
+    }
+
+    close() {
+      if (this.locked) return Promise.reject(new TypeError("The stream is locked to a writer"));
+      return enqueueWrite(this[STATE], { size: 0, close: true });
+    }
+  }
+
+  // TransformStream
+
+  class TransformStream {
+    constructor(transformer = {}, writableStrategy = {}, readableStrategy = {}) {
+      let readable;
+      let writableState;
+      const controller = {
+        get desiredSize() {
+          return readable.desiredSize;
+        },
+        enqueue: (chunk) => readable.enqueue(chunk),
+        error: (e) => {
+          readable.error(e);
+          errorWritable(writableState, e);
+        },
+        terminate: () => {
+          readable.close();
+          errorWritable(writableState, new TypeError("The transform stream was terminated"));
+        },
+      };
+      const readableStream = new ReadableStream(
+        {
+          start(c) {
+            readable = c;
+          },
+          cancel(reason) {
+            errorWritable(writableState, reason);
+          },
+        },
+        { highWaterMark: 0, ...readableStrategy }
+      );
+      const writableStream = new WritableStream(
+        {
+          start() {
+            if (typeof transformer.start === "function") return transformer.start(controller);
+          },
+          write(chunk) {
+            if (typeof transformer.transform === "function") return transformer.transform(chunk, controller);
+            controller.enqueue(chunk);
+          },
+          async close() {
+            if (typeof transformer.flush === "function") await transformer.flush(controller);
+            if (readableStream[STATE].state === "readable" && !readableStream[STATE].closeRequested) readable.close();
+          },
+          abort(reason) {
+            readable.error(reason);
+          },
+        },
+        writableStrategy
+      );
+      writableState = writableStream[STATE];
+      Object.defineProperties(this, {
+        readable: { value: readableStream, enumerable: true },
+        writable: { value: writableStream, enumerable: true },
+      });
+    }
+  }
+
+  // For fetch.js: whether a body stream has been read from or cancelled.
+  host.isDisturbed = (stream) => stream[STATE].disturbed;
+
+  globalThis.ReadableStream = ReadableStream;
+  globalThis.ReadableStreamDefaultReader = ReadableStreamDefaultReader;
+  globalThis.ReadableStreamDefaultController = ReadableStreamDefaultController;
+  globalThis.WritableStream = WritableStream;
+  globalThis.WritableStreamDefaultWriter = WritableStreamDefaultWriter;
+  globalThis.WritableStreamDefaultController = WritableStreamDefaultController;
+  globalThis.TransformStream = TransformStream;
+})
@@ -0,0 +1,177 @@
+// URL and URLSearchParams. Parsing and the component setters follow the URL
+// standard through the natives in url.rs.
+(function (host) {
+  "use strict";
+
+  const STATE = Symbol("state");
+
+  class URLSearchParams {
+    constructor(init = "") {
+      Object.defineProperty(this, STATE, { value: { list: [], url: null } });
+      const list = this[STATE].list;
+      if (init instanceof URLSearchParams) {
+        for (const [name, value] of init) list.push([name, value]);
+      } else if (typeof init === "object" && init !== null && typeof init[Symbol.iterator] === "function") {
+        for (const pair of init) {
+          const [name, value, ...rest] = [...pair];
+          if (value === undefined || rest.length > 0) {
+            throw new TypeError("Each pair must have exactly a name and a value");
+          }
+          list.push([String(name), String(value)]);
+        }
+      } else if (typeof init === "object" && init !== null) {
+        for (const name of Object.keys(init)) list.push([name, String(init[name])]);
+      } else {
+        let query = String(init);
+        if (query.startsWith("?")) query = query.slice(1);
+        list.push(...host.formParse(query));
+      }
+    }
+
+    get size() {
+      return this[STATE].list.length;
+    }
+
+    append(name, value) {
+      this[STATE].list.push([String(name), String(value)]);
+      update(this);
+    }
+
+    delete(name, value) {
+      const state = this[STATE];
+      name = String(name);
+      state.list = state.list.filter(
+        ([n, v]) => n !== name || (value !== undefined && v !== String(value))
+      );
+      update(this);
+    }
+
+    get(name) {
+      name = String(name);
+      const pair = this[STATE].list.find(([n]) => n === name);
+      return pair === undefined ? null : pair[1];
+    }
+
+    getAll(name) {
+      name = String(name);
+      return this[STATE].list.filter(([n]) => n === name).map(([, v]) => v);
+    }
+
+    has(name, value) {
+      name = String(name);
+      return this[STATE].list.some(([n, v]) => n === name && (value === undefined || v === String(value)));
+    }
+
+    set(name, value) {
+      const state = this[STATE];
+      name = String(name);
+      value = String(value);
+      const i = state.list.findIndex(([n]) => n === name);
+      if (i < 0) {
+        state.list.push([name, value]);
+      } else {
+        state.list[i][1] = value;
+        state.list = state.list.filter(([n], j) => j <= i || n !== name);
+      }
+      update(this);
+    }
+
+    sort() {
+      // Array.prototype.sort is stable, which the standard asks for.
+      this[STATE].list.sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+      update(this);
+    }
+
+    forEach(callback, thisArg) {
+      for (const [name, value] of this) callback.call(thisArg, value, name, this);
+    }
+
+    *entries() {
+      for (const [name, value] of this[STATE].list) yield [name, value];
+    }
+
+    *keys() {
+      for (const [name] of this[STATE].list) yield name;
+    }
+
+    *values() {
+      for (const [, value] of this[STATE].list) yield value;
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+
+    toString() {
+      return host.formSerialize(this[STATE].list);
+    }
+  }
+
+  // Writes the params back into the query of the URL they belong to.
+  function update(params) {
+    const url = params[STATE].url;
+    if (url === null) return;
+    // An empty query removes the `?` as well.
+    url[STATE].parts = host.urlSet(url.href, "search", params.toString());
+  }
+
+  function parse(input, base) {
+    const parts = base === undefined ? host.urlParse(String(input)) : host.urlParse(String(input), String(base));
+    if (parts === null) throw new TypeError(`Invalid URL: ${input}`);
+    return parts;
+  }
+
+  class URL {
+    constructor(url, base) {
+      const parts = parse(url, base);
+      const params = new URLSearchParams(parts.search);
+      Object.defineProperty(this, STATE, { value: { parts, params } });
+      params[STATE].url = this;
+    }
+
+    static canParse(url, base) {
+      try {
+        parse(url, base);
+        return true;
+      } catch (e) {
+        return false;
+      }
+    }
+
+    get searchParams() {
+      return this[STATE].params;
+    }
+
+    get origin() {
+      return this[STATE].parts.origin;
+    }
+
+    toString() {
+      return this.href;
+    }
+
+    toJSON() {
+      return this.href;
+    }
+  }
+
+  for (const name of ["href", "protocol", "username", "password", "host", "hostname", "port", "pathname", "search", "hash"]) {
+    Object.defineProperty(URL.prototype, name, {
+      get() {
+        return this[STATE].parts[name];
+      },
+      set(value) {
+        const state = this[STATE];
+        state.parts = host.urlSet(state.parts.href, name, String(value));
+        if (name === "href" || name === "search") {
+          state.params[STATE].list = host.formParse(state.parts.search.replace(/^\?/, ""));
+        }
+      },
+      enumerable: true,
+      configurable: true,
+    });
+  }
+
+  globalThis.URL = URL;
+  globalThis.URLSearchParams = URLSearchParams;
+})
@@ -0,0 +1,151 @@
+use super::string_pairs;
+use ::url::{quirks, Url};
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsNativeError, JsResult, JsString, JsValue, NativeFunction,
+};
+
+fn string_arg(args: &[JsValue], i: usize, context: &mut Context<'_>) -> JsResult<String> {
+    Ok(args
+        .get_or_undefined(i)
+        .to_string(context)?
+        .to_std_string_escaped())
+}
+
+/// The components of `url` as the getters of `URL` return them.
+fn components(url: &Url, context: &mut Context<'_>) -> JsValue {
+    let parts = [
+        ("href", quirks::href(url)),
+        ("protocol", quirks::protocol(url)),
+        ("username", quirks::username(url)),
+        ("password", quirks::password(url)),
+        ("host", quirks::host(url)),
+        ("hostname", quirks::hostname(url)),
+        ("port", quirks::port(url)),
+        ("pathname", quirks::pathname(url)),
+        ("search", quirks::search(url)),
+        ("hash", quirks::hash(url)),
+    ];
+    let mut object = ObjectInitializer::new(context);
+    for (name, value) in parts {
+        object.property(
+            JsString::from(name),
+            JsString::from(value),
+            Attribute::all(),
+        );
+    }
+    object.property(
+        js_string!("origin"),
+        JsString::from(quirks::origin(url).as_str()),
+        Attribute::all(),
+    );
+    object.build().into()
+}
+
+fn parse(input: &str, base: Option<&str>) -> Option<Url> {
+    match base {
+        Some(base) => Url::parse(base).ok()?.join(input).ok(),
+        None => Url::parse(input).ok(),
+    }
+}
+
+/// `host.urlParse(input, base)`, the components of the url or `null` if it is invalid.
+fn url_parse(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let input = string_arg(args, 0, context)?;
+    let base = match args.get_or_undefined(1) {
+        v if v.is_undefined() => None,
+        _ => Some(string_arg(args, 1, context)?),
+    };
+    Ok(match parse(&input, base.as_deref()) {
+        Some(url) => components(&url, context),
+        None => JsValue::null(),
+    })
+}
+
+/// `host.urlSet(href, component, value)`, the components after running the
+/// setter of `component`. Values the setter rejects leave the url as it was.
+fn url_set(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let href = string_arg(args, 0, context)?;
+    let name = string_arg(args, 1, context)?;
+    let value = string_arg(args, 2, context)?;
+    let mut url = Url::parse(&href)
+        .map_err(|e| JsNativeError::typ().with_message(format!("Invalid URL: {}", e)))?;
+    match name.as_str() {
+        "href" => {
+            quirks::set_href(&mut url, &value).map_err(|_| {
+                JsNativeError::typ().with_message(format!("Invalid URL: {}", value))
+            })?;
+        }
+        "protocol" => {
+            let _ = quirks::set_protocol(&mut url, &value);
+        }
+        "username" => {
+            let _ = quirks::set_username(&mut url, &value);
+        }
+        "password" => {
+            let _ = quirks::set_password(&mut url, &value);
+        }
+        "host" => {
+            let _ = quirks::set_host(&mut url, &value);
+        }
+        "hostname" => {
+            let _ = quirks::set_hostname(&mut url, &value);
+        }
+        "port" => {
+            let _ = quirks::set_port(&mut url, &value);
+        }
+        "pathname" => quirks::set_pathname(&mut url, &value),
+        "search" => quirks::set_search(&mut url, &value),
+        "hash" => quirks::set_hash(&mut url, &value),
+        _ => {
+            return Err(JsNativeError::typ()
+                .with_message(format!("Unknown URL component {}", name))
+                .into())
+        }
+    }
+    Ok(components(&url, context))
+}
+
+/// `host.formParse(query)`, the name/value pairs of an
+/// `application/x-www-form-urlencoded` string.
+fn form_parse(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let input = string_arg(args, 0, context)?;
+    let pairs = JsArray::new(context);
+    for (n, v) in ::url::form_urlencoded::parse(input.as_bytes()) {
+        let pair = JsArray::from_iter(
+            [
+                JsString::from(n.as_ref()).into(),
+                JsString::from(v.as_ref()).into(),
+            ],
+            context,
+        );
+        pairs.push(pair, context)?;
+    }
+    Ok(pairs.into())
+}
+
+/// `host.formSerialize(pairs)`, the reverse of `formParse`.
+fn form_serialize(_: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+    let pairs = string_pairs(args.get_or_undefined(0), context)?;
+    let mut out = ::url::form_urlencoded::Serializer::new(String::new());
+    for (n, v) in &pairs {
+        out.append_pair(n, v);
+    }
+    Ok(JsString::from(out.finish().as_str()).into())
+}
+
+/// The natives behind `URL` and `URLSearchParams`.
+pub(super) fn functions() -> [(&'static str, usize, NativeFunction); 4] {
+    [
+        ("urlParse", 2, NativeFunction::from_fn_ptr(url_parse)),
+        ("urlSet", 3, NativeFunction::from_fn_ptr(url_set)),
+        ("formParse", 1, NativeFunction::from_fn_ptr(form_parse)),
+        (
+            "formSerialize",
+            1,
+            NativeFunction::from_fn_ptr(form_serialize),
+        ),
+    ]
+}
@@ -0,0 +1,189 @@
+// WebSocket for connecting out to other services. The connection itself is
+// kept on the Rust side, see websocket.rs; received messages are pulled one
+// at a time and dispatched as events.
+(function (host) {
+  "use strict";
+
+  const STATE = Symbol("websocket state");
+  const CONNECTING = 0;
+  const OPEN = 1;
+  const CLOSING = 2;
+  const CLOSED = 3;
+
+  function parseUrl(url) {
+    let parsed;
+    try {
+      parsed = new URL(String(url));
+    } catch (e) {
+      throw new DOMException(`Invalid URL: ${url}`, "SyntaxError");
+    }
+    if (parsed.protocol === "http:") parsed.protocol = "ws:";
+    if (parsed.protocol === "https:") parsed.protocol = "wss:";
+    if (parsed.protocol !== "ws:" && parsed.protocol !== "wss:") {
+      throw new DOMException(`Unsupported protocol ${parsed.protocol}`, "SyntaxError");
+    }
+    if (parsed.hash !== "") throw new DOMException("WebSocket URLs cannot have a fragment", "SyntaxError");
+    return parsed.href;
+  }
+
+  class WebSocket extends EventTarget {
+    constructor(url, protocols = []) {
+      super();
+      const href = parseUrl(url);
+      if (typeof protocols === "string") protocols = [protocols];
+      protocols = [...protocols].map(String);
+      if (new Set(protocols).size !== protocols.length) {
+        throw new DOMException("Duplicate subprotocols", "SyntaxError");
+      }
+      const [id, opened] = host.wsConnect(href, protocols);
+      const state = {
+        id,
+        url: href,
+        readyState: CONNECTING,
+        protocol: "",
+        extensions: "",
+        bufferedAmount: 0,
+        binaryType: "arraybuffer",
+        sending: Promise.resolve(),
+      };
+      Object.defineProperty(this, STATE, { value: state });
+      this.onopen = null;
+      this.onmessage = null;
+      this.onerror = null;
+      this.onclose = null;
+
+      opened.then(
+        ({ protocol, extensions }) => {
+          if (state.readyState !== CONNECTING) return;
+          state.readyState = OPEN;
+          state.protocol = protocol;
+          state.extensions = extensions;
+          host.fire(this, new Event("open"));
+          receive(this);
+        },
+        (e) => {
+          if (state.readyState === CLOSED) return;
+          fail(this, e.message);
+        }
+      );
+    }
+
+    get url() {
+      return this[STATE].url;
+    }
+
+    get readyState() {
+      return this[STATE].readyState;
+    }
+
+    get protocol() {
+      return this[STATE].protocol;
+    }
+
+    get extensions() {
+      return this[STATE].extensions;
+    }
+
+    get bufferedAmount() {
+      return this[STATE].bufferedAmount;
+    }
+
+    // Binary messages always arrive as ArrayBuffers, there is no Blob.
+    get binaryType() {
+      return this[STATE].binaryType;
+    }
+
+    set binaryType(value) {
+      if (value === "arraybuffer" || value === "blob") this[STATE].binaryType = "arraybuffer";
+    }
+
+    send(data) {
+      const state = this[STATE];
+      if (state.readyState === CONNECTING) throw new DOMException("The socket is still connecting", "InvalidStateError");
+      if (state.readyState !== OPEN) return;
+      let payload;
+      let binary = true;
+      if (typeof data === "string") {
+        payload = data;
+        binary = false;
+      } else if (data instanceof ArrayBuffer) {
+        payload = new Uint8Array(data);
+      } else if (ArrayBuffer.isView(data)) {
+        payload = new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+      } else {
+        payload = String(data);
+        binary = false;
+      }
+      const size = binary ? payload.byteLength : host.encode(payload).length;
+      state.bufferedAmount += size;
+      // Frames go out one at a time, in the order they were sent.
+      state.sending = state.sending
+        .then(() => host.wsSend(state.id, payload, binary))
+        .then(
+          () => {
+            state.bufferedAmount -= size;
+          },
+          (e) => fail(this, e.message)
+        );
+    }
+
+    close(code, reason = "") {
+      if (code !== undefined && code !== 1000 && !(code >= 3000 && code <= 4999)) {
+        throw new DOMException(`Invalid close code ${code}`, "InvalidAccessError");
+      }
+      reason = String(reason);
+      if (host.encode(reason).length > 123) throw new DOMException("The close reason is too long", "SyntaxError");
+      const state = this[STATE];
+      if (state.readyState === CLOSING || state.readyState === CLOSED) return;
+      if (state.readyState === CONNECTING) {
+        host.wsClose(state.id);
+        fail(this, "The socket was closed before it connected");
+        return;
+      }
+      state.readyState = CLOSING;
+      state.sending = state.sending.then(() => host.wsClose(state.id, code === undefined ? 1000 : code, reason)).catch(() => {});
+    }
+  }
+
+  for (const [name, value] of Object.entries({ CONNECTING, OPEN, CLOSING, CLOSED })) {
+    Object.defineProperty(WebSocket, name, { value, enumerable: true });
+    Object.defineProperty(WebSocket.prototype, name, { value, enumerable: true });
+  }
+
+  function finish(socket, code, reason, wasClean) {
+    const state = socket[STATE];
+    if (state.readyState === CLOSED) return;
+    state.readyState = CLOSED;
+    host.wsRelease(state.id);
+    host.fire(socket, new CloseEvent("close", { code, reason, wasClean }));
+  }
+
+  function fail(socket, message) {
+    const state = socket[STATE];
+    if (state.readyState === CLOSED) return;
+    state.readyState = CLOSED;
+    host.wsRelease(state.id);
+    host.fire(socket, new ErrorEvent("error", { message }));
+    host.fire(socket, new CloseEvent("close", { code: 1006, reason: "", wasClean: false }));
+  }
+
+  async function receive(socket) {
+    const state = socket[STATE];
+    for (;;) {
+      let message;
+      try {
+        message = await host.wsReceive(state.id);
+      } catch (e) {
+        return fail(socket, e.message);
+      }
+      if (state.readyState === CLOSED) return;
+      if (message === null) return fail(socket, "The connection was lost");
+      if (message.type === "close") return finish(socket, message.code, message.reason, true);
+      if (message.type === "error") return fail(socket, message.message);
+      const data = message.type === "text" ? message.data : message.data.buffer.slice(0);
+      host.fire(socket, new MessageEvent("message", { data, origin: new URL(state.url).origin }));
+    }
+  }
+
+  globalThis.WebSocket = WebSocket;
+})
@@ -0,0 +1,375 @@
+use super::{dom_exception, from_bytes, to_bytes};
+use crate::js::event_loop;
+use crate::permissions::Permissions;
+use async_tungstenite::async_std::{connect_async, ConnectStream};
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::http::HeaderValue;
+use async_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use async_tungstenite::tungstenite::protocol::CloseFrame;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use boa_engine::object::builtins::JsArray;
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsNativeError, JsResult, JsString, JsValue, NativeFunction,
+};
+use boa_gc::{Finalize, Trace};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type Sink = SplitSink<WebSocketStream<ConnectStream>, Message>;
+type Source = SplitStream<WebSocketStream<ConnectStream>>;
+
+/// The connections of a script. While a message is being received or sent,
+/// its half of the connection is taken out of here and put back after.
+#[derive(Default)]
+struct Sockets {
+    next_id: u32,
+    sinks: HashMap<u32, Sink>,
+    sources: HashMap<u32, Source>,
+    /// Closed by the script before they were connected.
+    abandoned: HashSet<u32>,
+    /// Every connected socket, until it is released.
+    open: HashMap<u32, Open>,
+}
+
+/// Sockets connected by the scripts of every context.
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// How many sockets are connected, for the stats of the directories.
+pub(crate) fn open_sockets() -> usize {
+    OPEN.load(Ordering::Relaxed)
+}
+
+/// Counts a socket as connected while it lives.
+struct Open;
+
+impl Open {
+    fn new() -> Self {
+        OPEN.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Trace, Finalize, Clone)]
+pub(super) struct Host {
+    #[unsafe_ignore_trace]
+    sockets: Rc<RefCell<Sockets>>,
+    #[unsafe_ignore_trace]
+    permissions: Rc<Permissions>,
+}
+
+impl Host {
+    pub(super) fn new(permissions: &Permissions) -> Self {
+        Self {
+            sockets: Rc::new(RefCell::new(Sockets::default())),
+            permissions: Rc::new(permissions.clone()),
+        }
+    }
+}
+
+fn closed() -> boa_engine::JsError {
+    JsNativeError::error()
+        .with_message("The socket is closed")
+        .into()
+}
+
+/// `host.wsConnect(url, protocols)`, returning the id of the socket and a
+/// promise of `{protocol, extensions}` once the handshake is done.
+fn connect(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let url = args
+        .get_or_undefined(0)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let mut protocols = Vec::new();
+    if let Some(list) = args.get_or_undefined(1).as_object() {
+        let list = JsArray::from_object(list.clone())?;
+        for i in 0..list.length(context)? {
+            let p = list.get(i, context)?.to_string(context)?;
+            protocols.push(p.to_std_string_escaped());
+        }
+    }
+    if let Ok(u) = url::Url::parse(&url) {
+        if let Err(e) = host.permissions.check_net(&u) {
+            return Err(dom_exception(&e, "NotAllowedError", context));
+        }
+    }
+    let mut request = url.as_str().into_client_request().map_err(|e| {
+        JsNativeError::syntax().with_message(format!("Invalid WebSocket URL {}: {}", url, e))
+    })?;
+    if !protocols.is_empty() {
+        let value = HeaderValue::from_str(&protocols.join(", "))
+            .map_err(|_| JsNativeError::syntax().with_message("Invalid WebSocket subprotocol"))?;
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", value);
+    }
+
+    let id = {
+        let mut sockets = host.sockets.borrow_mut();
+        sockets.next_id += 1;
+        sockets.next_id
+    };
+    let h = host.clone();
+    let promise = event_loop::promise(
+        connect_async(request),
+        move |res, context| {
+            let mut sockets = h.sockets.borrow_mut();
+            let (stream, response) = match res {
+                Ok(r) => r,
+                Err(e) => {
+                    sockets.abandoned.remove(&id);
+                    log::error!("WebSocket Error: {}: {}", url, e);
+                    return Err(JsNativeError::typ()
+                        .with_message(format!("WebSocket connection to {} failed: {}", url, e))
+                        .into());
+                }
+            };
+            if sockets.abandoned.remove(&id) {
+                return Err(closed());
+            }
+            let header = |name: &str| {
+                let value = response
+                    .headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("");
+                JsString::from(value)
+            };
+            let opened = ObjectInitializer::new(context)
+                .property(
+                    js_string!("protocol"),
+                    header("Sec-WebSocket-Protocol"),
+                    Attribute::all(),
+                )
+                .property(
+                    js_string!("extensions"),
+                    header("Sec-WebSocket-Extensions"),
+                    Attribute::all(),
+                )
+                .build();
+            let (sink, source) = stream.split();
+            sockets.sinks.insert(id, sink);
+            sockets.sources.insert(id, source);
+            sockets.open.insert(id, Open::new());
+            Ok(opened.into())
+        },
+        context,
+    );
+    Ok(JsArray::from_iter([id.into(), promise.into()], context).into())
+}
+
+/// `host.wsReceive(id)`, a promise of the next message: `{type: "text", data}`,
+/// `{type: "binary", data}`, `{type: "close", code, reason}`,
+/// `{type: "error", message}`, or `null` when the connection dropped. Pings
+/// and pongs are answered and skipped.
+fn receive(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let mut source = match host.sockets.borrow_mut().sources.remove(&id) {
+        Some(s) => s,
+        None => return Err(closed()),
+    };
+    let next = async move {
+        loop {
+            match source.next().await {
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                message => break (source, message),
+            }
+        }
+    };
+    let h = host.clone();
+    let promise = event_loop::promise(
+        next,
+        move |(source, message), context| {
+            let object = |context: &mut Context<'_>, kind: &str, pairs: &[(&str, JsValue)]| {
+                let mut object = ObjectInitializer::new(context);
+                object.property(js_string!("type"), JsString::from(kind), Attribute::all());
+                for (name, value) in pairs {
+                    object.property(JsString::from(*name), value.clone(), Attribute::all());
+                }
+                object.build()
+            };
+            let message = match message {
+                None => return Ok(JsValue::null()),
+                Some(Ok(Message::Text(data))) => object(
+                    context,
+                    "text",
+                    &[("data", JsString::from(data.as_str()).into())],
+                ),
+                Some(Ok(Message::Binary(bytes))) => {
+                    let data = from_bytes(bytes, context)?;
+                    object(context, "binary", &[("data", data)])
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    let (code, reason) = match frame {
+                        Some(f) => (u16::from(f.code), f.reason.into_owned()),
+                        None => (1005, String::new()),
+                    };
+                    let reason = JsString::from(reason.as_str()).into();
+                    object(
+                        context,
+                        "close",
+                        &[("code", code.into()), ("reason", reason)],
+                    )
+                }
+                Some(Ok(_)) => unreachable!("control frames are skipped"),
+                Some(Err(e)) => {
+                    let message = JsString::from(e.to_string().as_str()).into();
+                    object(context, "error", &[("message", message)])
+                }
+            };
+            h.sockets.borrow_mut().sources.insert(id, source);
+            Ok(message.into())
+        },
+        context,
+    );
+    Ok(promise.into())
+}
+
+/// `host.wsSend(id, data, binary)`, a promise of when the message was written.
+fn send(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let message = if args.get_or_undefined(2).to_boolean() {
+        Message::Binary(to_bytes(args.get_or_undefined(1), context)?)
+    } else {
+        let text = args.get_or_undefined(1).to_string(context)?;
+        Message::Text(String::from_utf16_lossy(&text))
+    };
+    let mut sink = match host.sockets.borrow_mut().sinks.remove(&id) {
+        Some(s) => s,
+        None => return Err(closed()),
+    };
+    let sent = async move {
+        let res = sink.send(message).await;
+        (sink, res)
+    };
+    let h = host.clone();
+    let promise = event_loop::promise(
+        sent,
+        move |(sink, res), _| match res {
+            Ok(()) => {
+                h.sockets.borrow_mut().sinks.insert(id, sink);
+                Ok(JsValue::undefined())
+            }
+            Err(e) => Err(JsNativeError::typ()
+                .with_message(format!("Could not send the message: {}", e))
+                .into()),
+        },
+        context,
+    );
+    Ok(promise.into())
+}
+
+/// `host.wsClose(id, code, reason)`, starting the closing handshake. Without a
+/// code the socket is still connecting and is dropped once it connects.
+fn close(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    if args.get_or_undefined(1).is_undefined() {
+        host.sockets.borrow_mut().abandoned.insert(id);
+        return Ok(JsValue::undefined());
+    }
+    let code = args.get_or_undefined(1).to_u32(context)?;
+    let code = u16::try_from(code)
+        .map_err(|_| JsNativeError::range().with_message(format!("Invalid close code {}", code)))?;
+    let reason = args.get_or_undefined(2).to_string(context)?;
+    let frame = CloseFrame {
+        code: CloseCode::from(code),
+        reason: String::from_utf16_lossy(&reason).into(),
+    };
+    let mut sink = match host.sockets.borrow_mut().sinks.remove(&id) {
+        Some(s) => s,
+        None => return Err(closed()),
+    };
+    // The sink is dropped afterwards, the peer's answer still arrives through
+    // `wsReceive`.
+    let sent = async move { sink.send(Message::Close(Some(frame))).await };
+    let promise = event_loop::promise(
+        sent,
+        move |res, _| {
+            res.map(|_| JsValue::undefined()).map_err(|e| {
+                JsNativeError::typ()
+                    .with_message(format!("Could not close the socket: {}", e))
+                    .into()
+            })
+        },
+        context,
+    );
+    Ok(promise.into())
+}
+
+/// `host.wsRelease(id)`, dropping whatever is left of a closed socket.
+fn release(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let mut sockets = host.sockets.borrow_mut();
+    sockets.sinks.remove(&id);
+    sockets.sources.remove(&id);
+    sockets.open.remove(&id);
+    Ok(JsValue::undefined())
+}
+
+/// The natives behind `WebSocket`, as name, length and function.
+pub(super) fn functions(host: Host) -> [(&'static str, usize, NativeFunction); 5] {
+    [
+        (
+            "wsConnect",
+            2,
+            NativeFunction::from_copy_closure_with_captures(connect, host.clone()),
+        ),
+        (
+            "wsReceive",
+            1,
+            NativeFunction::from_copy_closure_with_captures(receive, host.clone()),
+        ),
+        (
+            "wsSend",
+            3,
+            NativeFunction::from_copy_closure_with_captures(send, host.clone()),
+        ),
+        (
+            "wsClose",
+            3,
+            NativeFunction::from_copy_closure_with_captures(close, host.clone()),
+        ),
+        (
+            "wsRelease",
+            1,
+            NativeFunction::from_copy_closure_with_captures(release, host),
+        ),
+    ]
+}
@@ -0,0 +1,64 @@
+// `Worker`, the owner's side. See worker.rs for how messages get across.
+(function (host, web) {
+  "use strict";
+
+  const STATE = Symbol("worker state");
+
+  class Worker extends EventTarget {
+    constructor(url, options = {}) {
+      super();
+      if (options.type !== undefined && options.type !== "classic" && options.type !== "module") {
+        throw new TypeError(`Unknown worker type ${options.type}`);
+      }
+      const id = host.workerStart(String(url));
+      Object.defineProperty(this, STATE, { value: { id, receiving: false, ended: false } });
+      this.onmessage = null;
+      this.onmessageerror = null;
+      this.onerror = null;
+      receive(this);
+    }
+
+    postMessage(message, transfer) {
+      const state = this[STATE];
+      if (state.ended) return;
+      // Fail like structuredClone would, rather than losing parts of the message.
+      structuredClone(message, Array.isArray(transfer) ? { transfer } : transfer);
+      if (!host.workerPost(state.id, message)) return;
+      receive(this);
+    }
+
+    terminate() {
+      const state = this[STATE];
+      state.ended = true;
+      host.workerTerminate(state.id);
+    }
+  }
+
+  // Listens to the worker until it has handled every message sent to it.
+  async function receive(worker) {
+    const state = worker[STATE];
+    if (state.receiving) return;
+    state.receiving = true;
+    try {
+      for (;;) {
+        const event = await host.workerReceive(state.id);
+        if (state.ended) return;
+        if (event === null) {
+          state.ended = true;
+          return;
+        }
+        if (event.type === "idle") {
+          if (event.done) return;
+        } else if (event.type === "message") {
+          web.fire(worker, new MessageEvent("message", { data: event.data }));
+        } else {
+          web.fire(worker, new ErrorEvent("error", { message: event.message, cancelable: true }));
+        }
+      }
+    } finally {
+      state.receiving = false;
+    }
+  }
+
+  globalThis.Worker = Worker;
+})
@@ -0,0 +1,407 @@
+//! `new Worker(path)`: a script running in an engine of its own, on a thread of
+//! the blocking pool, that exchanges messages with the script that started it.
+//!
+//! Messages cross over as json, converted like responses are, see clone.js.
+//! A worker handles one message at a time, running all the work it causes
+//! before taking the next, and tells its owner whenever it has caught up.
+//! The owner only listens while a worker has messages left to handle, so an
+//! idle worker does not keep the script that started it running. Workers end
+//! with the script that started them, when terminated, or at their deadline.
+
+use super::event_loop::{self, EventLoop};
+use super::web::ScriptName;
+use super::{modules, typescript, web, Runtime};
+use crate::audit;
+use crate::capabilities;
+use crate::rt;
+use async_std::channel::{self, Receiver, Sender};
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{
+    js_string, Context, JsArgs, JsNativeError, JsObject, JsResult, JsString, JsValue,
+    NativeFunction, Source,
+};
+use boa_gc::{Finalize, Trace};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tide::log;
+
+const WORKER: &str = include_str!("worker.js");
+const SCOPE: &str = include_str!("worker_scope.js");
+
+/// What a worker tells its owner.
+enum Event {
+    Message(Value),
+    /// An uncaught error, while loading the worker or handling a message.
+    Error(String),
+    /// The worker has run all its work, after handling this many messages.
+    Idle(u32),
+}
+
+/// The owner's end of a worker.
+struct Handle {
+    inbox: Sender<Value>,
+    events: Receiver<Event>,
+    sent: u32,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // Closing both ends lets the worker stop at its next chance, even
+        // while a receive is still pending.
+        self.inbox.close();
+        self.events.close();
+    }
+}
+
+#[derive(Default)]
+struct Workers {
+    next_id: u32,
+    handles: HashMap<u32, Handle>,
+}
+
+#[derive(Trace, Finalize, Clone)]
+struct Host {
+    #[unsafe_ignore_trace]
+    runtime: Runtime,
+    /// The directory of the script, which worker paths are relative to.
+    #[unsafe_ignore_trace]
+    dir: PathBuf,
+    #[unsafe_ignore_trace]
+    workers: Rc<RefCell<Workers>>,
+    /// The natives of web/mod.rs, to convert messages with.
+    web: JsObject,
+}
+
+/// Where the file `url` of a worker or a wasm module is: relative to the
+/// directory of the script, or to the root if it starts with `/`. It has to be
+/// inside the root.
+pub(super) fn resolve(root: &Path, dir: &Path, url: &str) -> Result<PathBuf, String> {
+    let path = match url.strip_prefix('/') {
+        Some(rest) => root.join(rest),
+        None => dir.join(url),
+    };
+    let path = path
+        .canonicalize()
+        .map_err(|e| format!("Cannot load {}: {}", url, e))?;
+    if !path.starts_with(root) {
+        return Err(format!("{} is outside the script directory", url));
+    }
+    Ok(path)
+}
+
+/// `host.workerStart(url)`, starting the worker and returning its id.
+fn start(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let url = args
+        .get_or_undefined(0)
+        .to_string(context)?
+        .to_std_string_escaped();
+    let root = host.runtime.root.as_path();
+    let path = resolve(root, &host.dir, &url).map_err(|e| JsNativeError::typ().with_message(e))?;
+    let integrity = host.runtime.integrity.as_deref();
+    let source = typescript::read_js(host.runtime.snapshot.as_deref(), integrity, root, &path)?;
+
+    let (inbox, messages) = channel::unbounded();
+    let (sender, events) = channel::unbounded();
+    let runtime = host.runtime.clone();
+    // Workers can do no more than the handler starting them.
+    let grant = capabilities::current();
+    let origin = audit::current();
+    rt::spawn_blocking(move || {
+        capabilities::run(grant, || {
+            audit::run(origin, || work(&runtime, &path, &source, messages, sender))
+        })
+    });
+
+    let mut workers = host.workers.borrow_mut();
+    workers.next_id += 1;
+    let id = workers.next_id;
+    workers.handles.insert(
+        id,
+        Handle {
+            inbox,
+            events,
+            sent: 0,
+        },
+    );
+    Ok(id.into())
+}
+
+/// `host.workerPost(id, message)`, returning whether the worker is still
+/// there to get it.
+fn post(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let message = web::to_json(&host.web, args.get_or_undefined(1), context)?;
+    let mut workers = host.workers.borrow_mut();
+    let handle = match workers.handles.get_mut(&id) {
+        Some(h) => h,
+        None => return Ok(false.into()),
+    };
+    if handle.inbox.try_send(message).is_err() {
+        return Ok(false.into());
+    }
+    handle.sent += 1;
+    Ok(true.into())
+}
+
+/// `host.workerReceive(id)`, a promise of what the worker says next:
+/// `{type: "message", data}`, `{type: "error", message}`, `{type: "idle",
+/// done}` where `done` tells whether it handled every message sent so far,
+/// or `null` once it has ended.
+fn receive(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    let events = match host.workers.borrow().handles.get(&id) {
+        Some(h) => h.events.clone(),
+        None => return Ok(JsValue::null()),
+    };
+    let h = host.clone();
+    let promise = event_loop::promise(
+        async move { events.recv().await.ok() },
+        move |event, context| {
+            let event = match event {
+                Some(e) => e,
+                None => {
+                    h.workers.borrow_mut().handles.remove(&id);
+                    return Ok(JsValue::null());
+                }
+            };
+            let (kind, name, value): (_, _, JsValue) = match event {
+                Event::Message(data) => ("message", "data", JsValue::from_json(&data, context)?),
+                Event::Error(message) => {
+                    ("error", "message", JsString::from(message.as_str()).into())
+                }
+                Event::Idle(handled) => {
+                    let done = h
+                        .workers
+                        .borrow()
+                        .handles
+                        .get(&id)
+                        .map_or(true, |h| h.sent == handled);
+                    ("idle", "done", done.into())
+                }
+            };
+            let object = ObjectInitializer::new(context)
+                .property(js_string!("type"), JsString::from(kind), Attribute::all())
+                .property(JsString::from(name), value, Attribute::all())
+                .build();
+            Ok(object.into())
+        },
+        context,
+    );
+    Ok(promise.into())
+}
+
+/// `host.workerTerminate(id)`, stopping the worker.
+fn terminate(
+    _: &JsValue,
+    args: &[JsValue],
+    host: &Host,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let id = args.get_or_undefined(0).to_u32(context)?;
+    host.workers.borrow_mut().handles.remove(&id);
+    Ok(JsValue::undefined())
+}
+
+/// Installs `Worker`. `dir` is the directory of the script and `natives` the
+/// natives returned by [`web::register`].
+pub(crate) fn register(
+    runtime: &Runtime,
+    dir: &Path,
+    natives: &JsObject,
+    context: &mut Context<'_>,
+) -> JsResult<()> {
+    let host = Host {
+        runtime: runtime.clone(),
+        dir: dir.to_path_buf(),
+        workers: Rc::new(RefCell::new(Workers::default())),
+        web: natives.clone(),
+    };
+    let functions = [
+        ("workerStart", start as Native),
+        ("workerPost", post),
+        ("workerReceive", receive),
+        ("workerTerminate", terminate),
+    ];
+    let mut worker_natives = ObjectInitializer::new(context);
+    for (name, f) in functions {
+        worker_natives.function(
+            NativeFunction::from_copy_closure_with_captures(f, host.clone()),
+            name,
+            1,
+        );
+    }
+    let worker_natives = worker_natives.build();
+    install("worker.js", WORKER, &worker_natives, natives, context)
+}
+
+type Native = fn(&JsValue, &[JsValue], &Host, &mut Context<'_>) -> JsResult<JsValue>;
+
+/// Evaluates one of the JavaScript files here with its natives and those of
+/// web/mod.rs.
+pub(super) fn install(
+    name: &str,
+    source: &str,
+    host: &JsObject,
+    web: &JsObject,
+    context: &mut Context<'_>,
+) -> JsResult<()> {
+    let install = context.eval(Source::from_bytes(source))?;
+    let install = install.as_callable().ok_or_else(|| {
+        JsNativeError::typ().with_message(format!("{} does not evaluate to a function", name))
+    })?;
+    install.call(
+        &JsValue::undefined(),
+        &[host.clone().into(), web.clone().into()],
+        context,
+    )?;
+    Ok(())
+}
+
+/// The worker's end: what its `postMessage` and `close` need.
+#[derive(Trace, Finalize, Clone)]
+struct Scope {
+    #[unsafe_ignore_trace]
+    events: Sender<Event>,
+    #[unsafe_ignore_trace]
+    closing: Rc<Cell<bool>>,
+    web: JsObject,
+}
+
+/// `host.post(message)` inside the worker.
+fn scope_post(
+    _: &JsValue,
+    args: &[JsValue],
+    scope: &Scope,
+    context: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    let message = web::to_json(&scope.web, args.get_or_undefined(0), context)?;
+    let _ = scope.events.try_send(Event::Message(message));
+    Ok(JsValue::undefined())
+}
+
+/// `host.close()` inside the worker, ending it once its current work is done.
+fn scope_close(
+    _: &JsValue,
+    _: &[JsValue],
+    scope: &Scope,
+    _: &mut Context<'_>,
+) -> JsResult<JsValue> {
+    scope.closing.set(true);
+    Ok(JsValue::undefined())
+}
+
+/// Runs the worker at `path` until it closes itself, its owner goes away or
+/// its deadline passes.
+fn work(
+    runtime: &Runtime,
+    path: &Path,
+    source: &str,
+    messages: Receiver<Value>,
+    events: Sender<Event>,
+) {
+    let root = runtime.root.as_path();
+    let loader = modules::AppLoader::new(root.to_path_buf(), runtime.snapshot.clone())
+        .with_integrity(runtime.integrity.clone())
+        .with_sources(runtime.sources.clone())
+        .with_remote(runtime.remote.clone());
+    let jobs = EventLoop::new(runtime.timeout);
+    let owner = events.clone();
+    jobs.watch(Some(Box::new(move || owner.is_closed())));
+    let mut context = match Context::builder()
+        .module_loader(&loader)
+        .job_queue(&jobs)
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = events.try_send(Event::Error(e.to_string()));
+            return;
+        }
+    };
+    let closing = Rc::new(Cell::new(false));
+    let res = (|| -> JsResult<()> {
+        let script = ScriptName::new(&super::script_name(root, path));
+        let natives = super::prepare(runtime, &script, &jobs, &mut context)?;
+        register(
+            runtime,
+            path.parent().unwrap_or(root),
+            &natives,
+            &mut context,
+        )?;
+        let scope = Scope {
+            events: events.clone(),
+            closing: closing.clone(),
+            web: natives.clone(),
+        };
+        let scope_natives = ObjectInitializer::new(&mut context)
+            .function(
+                NativeFunction::from_copy_closure_with_captures(scope_post, scope.clone()),
+                "post",
+                1,
+            )
+            .function(
+                NativeFunction::from_copy_closure_with_captures(scope_close, scope),
+                "close",
+                0,
+            )
+            .build();
+        install(
+            "worker_scope.js",
+            SCOPE,
+            &scope_natives,
+            &natives,
+            &mut context,
+        )?;
+        let dispatch = scope_natives.get(js_string!("dispatch"), &mut context)?;
+        let dispatch = dispatch.as_callable().cloned().ok_or_else(|| {
+            JsNativeError::typ().with_message("worker_scope.js did not install dispatch")
+        })?;
+
+        if modules::is_module(path, source) {
+            modules::evaluate(&loader, path, source, &mut context)?;
+        } else {
+            context.eval(Source::from_bytes(source))?;
+            context.run_jobs();
+        }
+        let mut handled = 0;
+        while !closing.get() && !jobs.stopped() {
+            if events.try_send(Event::Idle(handled)).is_err() {
+                break;
+            }
+            let message = match rt::block_on(messages.recv()) {
+                Ok(m) => m,
+                Err(_) => break,
+            };
+            handled += 1;
+            let data = JsValue::from_json(&message, &mut context)?;
+            if let Err(e) = dispatch.call(&JsValue::undefined(), &[data], &mut context) {
+                let _ = events.try_send(Event::Error(e.to_string()));
+            }
+            context.run_jobs();
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        log::error!("Worker {:?} failed: {}", path, e);
+        let _ = events.try_send(Event::Error(e.to_string()));
+    }
+}
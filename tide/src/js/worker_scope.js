@@ -0,0 +1,25 @@
+// The globals of a worker: `self`, `postMessage`, `close` and the message
+// events of its owner.
+(function (host) {
+  "use strict";
+
+  const target = new EventTarget();
+  globalThis.self = globalThis;
+  globalThis.addEventListener = target.addEventListener.bind(target);
+  globalThis.removeEventListener = target.removeEventListener.bind(target);
+  globalThis.dispatchEvent = target.dispatchEvent.bind(target);
+  globalThis.onmessage = null;
+  globalThis.postMessage = (message, transfer) => {
+    structuredClone(message, Array.isArray(transfer) ? { transfer } : transfer);
+    host.post(message);
+  };
+  globalThis.close = () => host.close();
+
+  // Called for every message of the owner. An error thrown by `onmessage` is
+  // reported to the owner as an error event.
+  host.dispatch = (data) => {
+    const event = new MessageEvent("message", { data });
+    if (typeof globalThis.onmessage === "function") globalThis.onmessage(event);
+    target.dispatchEvent(event);
+  };
+})
@@ -0,0 +1,231 @@
+use sled::transaction::{abort, TransactionError};
+use sled::Transactional;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Size limits of the storage of an app.
+#[derive(Debug, Clone, Copy)]
+pub struct StorageQuota {
+    /// Largest size in bytes of a single item, key included.
+    pub max_item_size: u64,
+    /// Largest combined size in bytes of all items of an app.
+    pub max_total_size: u64,
+}
+
+impl Default for StorageQuota {
+    fn default() -> Self {
+        Self {
+            max_item_size: 1024 * 1024,
+            max_total_size: 5 * 1024 * 1024,
+        }
+    }
+}
+
+/// The embedded key-value store that keeps the `storage` of scripts on disk.
+/// Each app gets a namespace of its own, see [`JsDir::with_storage`](crate::JsDir::with_storage).
+#[derive(Debug, Clone)]
+pub struct KvStore {
+    db: sled::Db,
+}
+
+impl KvStore {
+    /// Opens the store in `dir`, creating it if missing. Only one process can
+    /// have it open at a time.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let db = sled::open(dir)?;
+        Ok(Self { db })
+    }
+
+    /// The items of the app called `name`.
+    pub(crate) fn app(&self, name: &str, quota: StorageQuota) -> io::Result<AppStorage> {
+        Ok(AppStorage {
+            name: name.to_string(),
+            items: self.db.open_tree(format!("app:{}", name))?,
+            usage: self.db.open_tree("usage")?,
+            quota,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum StorageError {
+    /// Storing the item would exceed the quota.
+    Quota(String),
+    Store(sled::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Quota(msg) => f.write_str(msg),
+            StorageError::Store(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl From<sled::Error> for StorageError {
+    fn from(e: sled::Error) -> Self {
+        log::error!("Storage error: {}", e);
+        StorageError::Store(e)
+    }
+}
+
+impl From<TransactionError<StorageError>> for StorageError {
+    fn from(e: TransactionError<StorageError>) -> Self {
+        match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        }
+    }
+}
+
+/// The string items of one app. The bytes taken by its keys and values are
+/// kept next to them, so writes can be checked against the quota without
+/// reading everything.
+#[derive(Debug, Clone)]
+pub(crate) struct AppStorage {
+    name: String,
+    items: sled::Tree,
+    usage: sled::Tree,
+    quota: StorageQuota,
+}
+
+fn size_of(key: &[u8], value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+fn decode_usage(bytes: Option<sled::IVec>) -> u64 {
+    bytes
+        .and_then(|b| <[u8; 8]>::try_from(b.as_ref()).ok())
+        .map_or(0, u64::from_be_bytes)
+}
+
+impl AppStorage {
+    pub(crate) fn with_quota(mut self, quota: StorageQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    pub(crate) fn get_item(&self, key: &str) -> Result<Option<String>, StorageError> {
+        let value = self.items.get(key)?;
+        Ok(value.map(|v| String::from_utf8_lossy(&v).into_owned()))
+    }
+
+    pub(crate) fn set_item(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        let size = size_of(key.as_bytes(), value.as_bytes());
+        if size > self.quota.max_item_size {
+            return Err(StorageError::Quota(format!(
+                "The item {} is larger than {} bytes",
+                key, self.quota.max_item_size
+            )));
+        }
+        (&self.items, &self.usage).transaction(|(items, usage)| {
+            let used = decode_usage(usage.get(&self.name)?);
+            let old = items.get(key)?.map_or(0, |v| size_of(key.as_bytes(), &v));
+            let used = used - old.min(used) + size;
+            if used > self.quota.max_total_size {
+                return abort(StorageError::Quota(format!(
+                    "Storing {} would exceed the quota of {} bytes",
+                    key, self.quota.max_total_size
+                )));
+            }
+            items.insert(key, value)?;
+            usage.insert(self.name.as_str(), &used.to_be_bytes())?;
+            Ok(())
+        })?;
+        self.items.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn remove_item(&self, key: &str) -> Result<(), StorageError> {
+        (&self.items, &self.usage).transaction(|(items, usage)| {
+            if let Some(old) = items.remove(key)? {
+                let used = decode_usage(usage.get(&self.name)?);
+                let used = used.saturating_sub(size_of(key.as_bytes(), &old));
+                usage.insert(self.name.as_str(), &used.to_be_bytes())?;
+            }
+            Ok::<_, sled::transaction::ConflictableTransactionError<StorageError>>(())
+        })?;
+        self.items.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&self) -> Result<(), StorageError> {
+        self.items.clear()?;
+        self.usage.remove(self.name.as_str())?;
+        self.items.flush()?;
+        Ok(())
+    }
+
+    /// The name of the `index`th item, in the order of the keys.
+    pub(crate) fn key(&self, index: usize) -> Result<Option<String>, StorageError> {
+        match self.items.iter().keys().nth(index) {
+            Some(k) => Ok(Some(String::from_utf8_lossy(&k?).into_owned())),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Bytes taken by the keys and values of the items.
+    pub(crate) fn usage(&self) -> Result<u64, StorageError> {
+        Ok(decode_usage(self.usage.get(self.name.as_str())?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn store(name: &str) -> KvStore {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        KvStore::open(&dir).unwrap()
+    }
+
+    #[test]
+    fn items() {
+        let store = store("tide_rhai_kv_items");
+        let app = store.app("a", StorageQuota::default()).unwrap();
+        let other = store.app("b", StorageQuota::default()).unwrap();
+        app.set_item("name", "value").unwrap();
+        app.set_item("name", "other").unwrap();
+        app.set_item("count", "1").unwrap();
+        assert_eq!(app.get_item("name").unwrap().as_deref(), Some("other"));
+        assert_eq!(other.get_item("name").unwrap(), None);
+        assert_eq!(app.len(), 2);
+        assert_eq!(app.key(0).unwrap().as_deref(), Some("count"));
+        assert_eq!(app.usage().unwrap(), 9 + 6);
+        app.remove_item("name").unwrap();
+        assert_eq!(app.usage().unwrap(), 6);
+        app.clear().unwrap();
+        assert_eq!(app.len(), 0);
+        assert_eq!(app.usage().unwrap(), 0);
+    }
+
+    #[test]
+    fn quota() {
+        let store = store("tide_rhai_kv_quota");
+        let quota = StorageQuota {
+            max_item_size: 8,
+            max_total_size: 12,
+        };
+        let app = store.app("a", quota).unwrap();
+        assert!(matches!(
+            app.set_item("key", "too long"),
+            Err(StorageError::Quota(_))
+        ));
+        app.set_item("a", "1234567").unwrap();
+        assert!(matches!(
+            app.set_item("b", "12345"),
+            Err(StorageError::Quota(_))
+        ));
+        // Replacing an item only counts the difference.
+        app.set_item("a", "7654321").unwrap();
+        app.remove_item("a").unwrap();
+        app.set_item("b", "12345").unwrap();
+    }
+}
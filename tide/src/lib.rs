@@ -1,9 +1,68 @@
+#[cfg(feature = "actix")]
+mod actix_service;
+mod admin;
+mod archive;
+mod audit;
+#[cfg(feature = "axum")]
+mod axum_service;
+mod builder;
+mod cache;
+mod capabilities;
+pub mod cbor;
+mod check;
+mod compression;
+mod csv_utils;
+mod datetime;
+mod debugger;
+mod error_page;
+mod explorer;
 mod fetch;
+mod files;
+mod graphql;
+mod graphql_client;
+mod handlers;
+mod http_utils;
+#[cfg(feature = "hyper")]
+mod hyper_server;
+mod images;
+mod integrity;
+mod jobs;
+mod js;
+mod kv;
 mod logging;
+mod mail;
+mod markdown;
+mod memo;
+mod mirror;
+mod modules;
+pub mod msgpack;
+mod oauth;
+mod oidc;
+mod openapi;
+mod permissions;
+mod profile;
+mod quota;
+mod repl;
+mod request;
+pub mod rt;
+mod secrets;
+mod slow;
+mod snapshot;
+mod split;
+mod stats;
+mod storage;
+mod tenants;
+mod testing;
+mod trace;
+mod transform;
+mod validate;
+mod value;
+mod webhooks;
+mod xml;
 
 use async_std::path::PathBuf as AsyncPathBuf;
-use rhai::serde::{from_dynamic, to_dynamic};
-use rhai::{Dynamic, Engine, ImmutableString, Scope};
+use error_page::HttpError;
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, ImmutableString, Scope};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -11,32 +70,827 @@ use tide::log;
 use tide::{Endpoint, Request, Response, Result, StatusCode};
 
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
 use std::{ffi::OsStr, io};
 
+#[cfg(feature = "actix")]
+pub use actix_service::ActixService;
+pub use admin::Admin;
+pub use audit::AuditLog;
+#[cfg(feature = "axum")]
+pub use axum_service::AxumService;
+pub use builder::{Limits, RhaiDirBuilder};
+pub use check::{check, Diagnostic};
+pub use debugger::Debugger;
+pub use explorer::ApiExplorer;
+pub use files::DataQuota;
+pub use graphql::GraphQl;
+#[cfg(feature = "hyper")]
+pub use hyper_server::HyperServer;
+pub use integrity::Integrity;
+pub use jobs::{JobReport, Jobs};
+pub use js::{JsDir, JsEngine, JsRepl, JsScope, RemoteImports};
+pub use kv::{KvStore, StorageQuota};
+pub use mail::{MailConfig, SmtpTls};
+pub use mirror::Mirror;
+pub use oauth::OAuthProvider;
+pub use oidc::{Claims, Oidc, OidcConfig};
+pub use permissions::{Allow, Permissions};
+pub use profile::Profiler;
+pub use quota::{Quota, Throttle};
+pub use repl::{Evaluated, RhaiRepl};
+pub use secrets::{redact, Secrets};
+pub use slow::{Phase, SlowScript, SlowScripts};
+pub use snapshot::Snapshot;
+pub use split::Split;
+pub use stats::{DirReport, EngineStats, Metrics, ScriptActivity, ScriptError};
+pub use storage::S3Config;
+pub use tenants::Tenants;
+pub use testing::{TestReport, TestResult};
+pub use transform::{Outgoing, ResponseHooks};
+pub use value::{from_js_value, from_script_value, to_js_value, to_script_value};
+/// The engines, for applications adding bindings of their own with
+/// [`RhaiDir::with_engine_setup`] and [`JsDir::with_global`].
+pub use {boa_engine, rhai};
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Context {
     data: Value,
     headers: HashMap<String, String>,
 }
 
+impl Context {
+    /// The map of a request, with its body parsed as json for `PUT`, `POST`
+    /// and `PATCH`.
+    fn from_parts(
+        headers: HashMap<String, String>,
+        method: http_types::Method,
+        body: &[u8],
+    ) -> Self {
+        let data = match method {
+            http_types::Method::Put | http_types::Method::Post | http_types::Method::Patch => {
+                match serde_json::from_slice(body) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        log::warn!("error parsing value {:?}", e);
+                        Value::Object(Default::default())
+                    }
+                }
+            }
+            _ => Value::Object(Default::default()),
+        };
+        Context { data, headers }
+    }
+}
+
+/// Maps the request path below `prefix` onto a file in `dir`, `None` if the
+/// result would lie outside of `dir`.
+fn resolve_file<State>(prefix: &str, dir: &Path, req: &Request<State>) -> Option<AsyncPathBuf> {
+    let path = req.url().path();
+    let path = path.strip_prefix(&prefix.trim_end_matches('*')).unwrap();
+
+    let path = path.trim_start_matches('/');
+    let mut file_path = dir.to_path_buf();
+    for p in Path::new(path) {
+        if p == OsStr::new(".") {
+            continue;
+        } else if p == OsStr::new("..") {
+            file_path.pop();
+        } else {
+            file_path.push(&p);
+        }
+    }
+
+    log::info!("Requested file: {:?}", file_path);
+    let file_path = AsyncPathBuf::from(file_path);
+    if !file_path.starts_with(dir) {
+        log::warn!("Unauthorized attempt to read: {:?}", file_path);
+        None
+    } else {
+        Some(file_path)
+    }
+}
+
+/// The `HttpError` a script threw with `throw HttpError(404, "not found")`,
+/// from inside a function or module too.
+fn thrown_http_error(e: &EvalAltResult) -> Option<HttpError> {
+    match e {
+        EvalAltResult::ErrorRuntime(value, _) => value.clone().try_cast::<HttpError>(),
+        EvalAltResult::ErrorInFunctionCall(_, _, inner, _) => thrown_http_error(inner),
+        EvalAltResult::ErrorInModule(_, inner, _) => thrown_http_error(inner),
+        _ => None,
+    }
+}
+
+/// The calls `e` failed in, innermost first, after the statement of
+/// `location` if it is known.
+fn stack(e: &EvalAltResult, location: Option<&trace::Location>, root: &Path) -> Option<String> {
+    let mut calls = Vec::new();
+    let mut e = e;
+    loop {
+        match e {
+            EvalAltResult::ErrorInFunctionCall(name, source, inner, pos) if source.is_empty() => {
+                calls.push(format!("in call to {} at {}", name, pos));
+                e = inner;
+            }
+            EvalAltResult::ErrorInFunctionCall(name, source, inner, pos) => {
+                calls.push(format!("in call to {} of {} at {}", name, source, pos));
+                e = inner;
+            }
+            EvalAltResult::ErrorInModule(module, inner, pos) => {
+                calls.push(format!("in module {} at {}", module, pos));
+                e = inner;
+            }
+            _ => break,
+        }
+    }
+    calls.reverse();
+    if let Some(location) = location {
+        calls.insert(0, location.describe(root));
+    }
+    (!calls.is_empty()).then(|| calls.join("\n"))
+}
+
+type ScopeHook = Arc<dyn Fn(&tide::http::Request, &mut Scope<'_>) + Send + Sync>;
+type ErrorHook = Arc<dyn Fn(&Path, &EvalAltResult) -> Option<Response> + Send + Sync>;
+
+/// Run by [`RhaiDir::start`] and [`RhaiDir::shutdown`], never served.
+const INIT: &str = "_init.rhai";
+const SHUTDOWN: &str = "_shutdown.rhai";
+
 /// Struct that implements an [`Endpoint`] to and matches requests to rhai files.
 pub struct RhaiDir {
     prefix: String,
     dir: PathBuf,
+    data_dir: Option<PathBuf>,
+    data_quota: DataQuota,
+    mailer: Option<mail::Mailer>,
+    storage: Option<storage::Storage>,
+    oauth: Option<oauth::OAuth>,
+    snapshot: Option<Arc<Snapshot>>,
+    integrity: Option<Arc<Integrity>>,
+    permissions: Permissions,
+    secrets: Secrets,
+    audit: Option<Arc<AuditLog>>,
+    /// The schemas of `_routes.json` requests are checked against.
+    routes: validate::Routes,
+    /// What the application adds to the engine, run after the bindings.
+    setup: Vec<Arc<dyn Fn(&mut Engine) + Send + Sync>>,
+    /// Constants of the application in the scope of every script.
+    globals: Vec<(String, Dynamic)>,
+    scope_hooks: Vec<ScopeHook>,
+    debugger: Option<Arc<Debugger>>,
+    profiler: Option<Arc<Profiler>>,
+    slow: Option<Arc<SlowScripts>>,
+    dev_mode: bool,
+    limits: Limits,
+    cache_scripts: bool,
+    /// Tried in order for requests of a directory.
+    index_files: Vec<String>,
+    error_hooks: Vec<ErrorHook>,
+    counters: Arc<stats::Counters>,
+    modules: Arc<modules::ModuleCache>,
+    /// Shared with the other directories serving `dir`.
+    asts: Arc<cache::AstCache>,
+    memo: Arc<memo::Memo>,
+    /// Built by the first request, once the settings are final.
+    engine: OnceLock<Engine>,
 }
 
 impl RhaiDir {
+    /// A directory with the default settings, see [`RhaiDir::builder`].
     ///```
     /// use tide_rhai::RhaiDir;
     /// let mut app = tide::new();
     /// app.at("/*")
     /// .get(RhaiDir::new("/*", "./examples/app/").unwrap());
     ///```
-    #[allow(dead_code)]
     pub fn new(prefix: &str, dir: impl AsRef<Path>) -> io::Result<Self> {
-        let dir = dir.as_ref().to_owned().canonicalize()?;
-        let prefix = String::from(prefix);
-        Ok(Self { prefix, dir })
+        Self::builder(prefix, dir).build()
+    }
+
+    /// Gives scripts `file_read`, `file_write`, `file_append` and `file_list`
+    /// access to `dir`, which is created if missing. Scripts cannot reach
+    /// anything outside of it.
+    ///```
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_data_dir("./examples/data/")
+    ///     .unwrap();
+    ///```
+    pub fn with_data_dir(mut self, dir: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        self.data_dir = Some(dir.as_ref().to_owned().canonicalize()?);
+        Ok(self)
+    }
+
+    /// Overrides the default size limits of the data directory.
+    pub fn with_data_quota(mut self, quota: DataQuota) -> Self {
+        self.data_quota = quota;
+        self
+    }
+
+    /// Enables the `send_mail` binding, sending through the given SMTP server.
+    pub fn with_mail(mut self, config: MailConfig) -> io::Result<Self> {
+        let mailer = mail::Mailer::new(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.mailer = Some(mailer);
+        Ok(self)
+    }
+
+    /// Exposes the given S3-compatible bucket to scripts as `s3`.
+    pub fn with_s3(mut self, config: S3Config) -> io::Result<Self> {
+        let storage = storage::Storage::new(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.storage = Some(storage);
+        Ok(self)
+    }
+
+    /// Exposes the authorization code flow of `providers` to scripts as
+    /// `oauth`, by the names of the map.
+    pub fn with_oauth(mut self, providers: HashMap<String, OAuthProvider>) -> Self {
+        self.oauth = Some(oauth::OAuth::new(providers));
+        self
+    }
+
+    /// Serves scripts from a precompiled snapshot of the directory, see
+    /// [`Snapshot`]. Files missing from it are still read from disk.
+    pub fn with_snapshot(mut self, snapshot: Arc<Snapshot>) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Refuses to run scripts and modules read from disk that do not match
+    /// the signed hashes of `integrity`, answering them with a 500.
+    ///```no_run
+    /// use std::sync::Arc;
+    /// use tide_rhai::{Integrity, RhaiDir};
+    /// let key = std::env::var("APP_PUBLIC_KEY").unwrap();
+    /// let integrity = Integrity::load("./examples/app/", &key).unwrap();
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_integrity(Arc::new(integrity));
+    ///```
+    pub fn with_integrity(mut self, integrity: Arc<Integrity>) -> Self {
+        self.integrity = Some(integrity);
+        self
+    }
+
+    /// Limits the hosts `fetch` may reach, the paths of the data directory
+    /// the `file_*` bindings may read and write, and the variables `env` may
+    /// read. See [`Permissions`] for the defaults.
+    ///```
+    /// use tide_rhai::{Allow, Permissions, RhaiDir};
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_permissions(Permissions {
+    ///         net: Allow::Only(vec!["api.example.com".into()]),
+    ///         env: Allow::Only(vec!["APP_*".into()]),
+    ///         ..Permissions::none()
+    ///     });
+    ///```
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Lets scripts get the allowed `secrets` with `secret(name)`, `()` for
+    /// those that are not set.
+    ///```
+    /// use tide_rhai::{Allow, RhaiDir, Secrets};
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_secrets(
+    ///         Secrets::new()
+    ///             .with_env(&["APP_*".into()])
+    ///             .allow(Allow::Only(vec!["APP_TOKEN".into()])),
+    ///     );
+    ///```
+    pub fn with_secrets(mut self, secrets: Secrets) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Records every `fetch` of the handlers, and each redirect it follows,
+    /// in `audit`, refusing those it does not know if it says so.
+    ///```
+    /// use std::sync::Arc;
+    /// use tide_rhai::{Allow, AuditLog, RhaiDir};
+    /// let audit = AuditLog::new()
+    ///     .with_file(std::env::temp_dir().join("tide_rhai_doc_audit.jsonl"))
+    ///     .unwrap()
+    ///     .known(Allow::Only(vec!["api.example.com".into()]));
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_audit(Arc::new(audit));
+    ///```
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Runs `setup` on the engine once the bindings are registered, for the
+    /// functions, types and modules of the application embedding this crate.
+    /// Calling it again adds to what was set up before.
+    ///```
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_engine_setup(|engine| {
+    ///         engine.register_fn("double", |x: i64| x * 2);
+    ///     });
+    ///```
+    pub fn with_engine_setup(
+        mut self,
+        setup: impl Fn(&mut Engine) + Send + Sync + 'static,
+    ) -> Self {
+        self.setup.push(Arc::new(setup));
+        self
+    }
+
+    /// Puts `value` in the scope of every script as the constant `name`,
+    /// next to `ctx`.
+    ///```
+    /// use tide_rhai::rhai::Map;
+    /// use tide_rhai::RhaiDir;
+    /// let mut config = Map::new();
+    /// config.insert("region".into(), "eu".into());
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_global("config", config);
+    ///```
+    pub fn with_global(mut self, name: &str, value: impl rhai::Variant + Clone) -> Self {
+        self.globals.push((name.to_string(), Dynamic::from(value)));
+        self
+    }
+
+    /// Runs `hook` before every script, to put values that depend on the
+    /// request in its scope, like the tenant, feature flags or a database
+    /// handle.
+    ///```
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_scope(|req, scope| {
+    ///         let tenant = req.header("x-tenant").map_or("", |h| h.as_str());
+    ///         scope.push_constant("tenant", tenant.to_string());
+    ///     });
+    ///```
+    pub fn with_scope(
+        mut self,
+        hook: impl Fn(&tide::http::Request, &mut Scope<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.scope_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Listens on `addr` for an editor debugging the scripts over the Debug
+    /// Adapter Protocol, to set breakpoints, step through scripts and look at
+    /// their variables. Meant for development: scripts run slower, and a
+    /// script stopped at a breakpoint holds its request.
+    ///```no_run
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_debugger("127.0.0.1:9229")
+    ///     .unwrap();
+    ///```
+    pub fn with_debugger(self, addr: impl std::net::ToSocketAddrs) -> io::Result<Self> {
+        Ok(self.with_shared_debugger(Debugger::listen(addr)?))
+    }
+
+    /// [`RhaiDir::with_debugger`] with a debugger that is already
+    /// listening, so directories made again on every reload keep the port
+    /// and the client attached to it.
+    ///```no_run
+    /// use tide_rhai::{Debugger, RhaiDir};
+    /// let debugger = Debugger::listen("127.0.0.1:9229").unwrap();
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_shared_debugger(debugger.clone());
+    ///```
+    pub fn with_shared_debugger(mut self, debugger: Arc<Debugger>) -> Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Has `profiler` time every script and the functions it calls, by the
+    /// path of its request. Scripts run slower while profiled.
+    pub fn with_profiler(mut self, profiler: Arc<Profiler>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Records how long every script takes to compile and run in `slow`,
+    /// which warns of those above its threshold or slower than usual.
+    ///```
+    /// use std::sync::Arc;
+    /// use tide_rhai::{RhaiDir, SlowScripts};
+    /// let slow = Arc::new(SlowScripts::new());
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_slow_scripts(slow.clone());
+    ///```
+    pub fn with_slow_scripts(mut self, slow: Arc<SlowScripts>) -> Self {
+        self.slow = Some(slow);
+        self
+    }
+
+    /// Answers failing scripts with a page showing the error, the statement
+    /// that failed and the variables at that point, instead of a bare 500.
+    /// Scripts run slower in dev mode, their variables are copied at every
+    /// statement.
+    pub fn with_dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    /// Stops scripts after `operations` steps of the engine, so a runaway
+    /// loop cannot hold a thread. The error names the statement the script
+    /// was at.
+    ///```
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/")
+    ///     .unwrap()
+    ///     .with_max_operations(1_000_000);
+    ///```
+    pub fn with_max_operations(mut self, operations: u64) -> Self {
+        self.limits.max_operations = Some(operations);
+        self
+    }
+
+    /// How many scripts ran, how long compiling and running them took and
+    /// how many scripts and modules are kept compiled.
+    pub fn stats(&self) -> EngineStats {
+        let mut stats = self.counters.stats("rhai", &self.prefix);
+        stats.cache_entries = self.asts.len() + self.modules.len();
+        stats
+    }
+
+    /// Whether `path` is a module the directory keeps and evaluates again
+    /// when it changes, with the modules importing it, so that an edit of it
+    /// needs no reload.
+    pub fn picks_up_edits(&self, path: &Path) -> bool {
+        self.modules.contains(path)
+    }
+
+    /// The [`stats`](RhaiDir::stats), the scripts that ran and the last
+    /// errors they failed with, for the [`Admin`] dashboard.
+    pub fn report(&self) -> DirReport {
+        self.counters.report(self.stats())
+    }
+
+    /// Runs `_init.rhai` of the directory, if there is one, for work to do
+    /// once before serving, like warming caches or migrating data. Fails if
+    /// the script does, which should stop the server from starting.
+    ///```no_run
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/").unwrap();
+    /// dir.start().expect("the app could not start");
+    ///```
+    pub fn start(&self) -> io::Result<()> {
+        self.run_lifecycle(INIT)
+    }
+
+    /// Runs `_shutdown.rhai` of the directory, if there is one, once the
+    /// server has stopped.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.run_lifecycle(SHUTDOWN)
+    }
+
+    fn run_lifecycle(&self, name: &str) -> io::Result<()> {
+        let path = self.dir.join(name);
+        let source = match self.snapshot.as_ref().and_then(|s| s.get(&self.dir, &path)) {
+            Some(entry) => entry.code.to_string(),
+            None => match integrity::read(self.integrity.as_deref(), &path) {
+                Ok(s) => s,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+            },
+        };
+        self.run_source(name, &source)?;
+        log::info!("Ran {:?}", path);
+        Ok(())
+    }
+
+    /// Runs the rhai script at `path` with the bindings of the directory,
+    /// like a scheduled job of [`Jobs`]. Such scripts are best kept out of
+    /// the directory, not to be served.
+    pub fn run_script(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)?;
+        self.run_source(&path.display().to_string(), &source)
+    }
+
+    fn run_source(&self, name: &str, source: &str) -> io::Result<()> {
+        self.engine()
+            .run_with_scope(&mut self.scope(), source)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{} failed: {}", name, e)))
+    }
+
+    /// The engine with the bindings, built by its first use.
+    fn engine(&self) -> &Engine {
+        self.engine.get_or_init(|| self.build_engine())
+    }
+
+    /// The scope scripts start with, before `ctx` and the hooks of
+    /// [`RhaiDir::with_scope`].
+    fn scope(&self) -> Scope<'static> {
+        let mut scope = Scope::new();
+        if let Some(storage) = &self.storage {
+            scope.push_constant("s3", storage.clone());
+        }
+        if let Some(oauth) = &self.oauth {
+            scope.push_constant("oauth", oauth.for_request());
+        }
+        for (name, value) in &self.globals {
+            scope.push_constant_dynamic(name.as_str(), value.clone());
+        }
+        scope
+    }
+
+    /// The engine with every binding registered. It keeps no state between
+    /// scripts other than the modules they import, so it is built once and
+    /// shared by all requests.
+    fn build_engine(&self) -> Engine {
+        let mut engine = Engine::new_raw();
+        engine.set_module_resolver(modules::AppModules {
+            root: self.dir.clone(),
+            snapshot: self.snapshot.clone(),
+            integrity: self.integrity.clone(),
+            cache: self.modules.clone(),
+        });
+
+        engine.register_fn("log", logging::log::<i64>);
+        engine.register_fn("log", logging::log::<ImmutableString>);
+        engine.register_fn("log", logging::log::<bool>);
+        engine.register_fn("log", logging::log::<Dynamic>);
+        engine.register_fn("info", logging::info::<i64>);
+        engine.register_fn("info", logging::info::<ImmutableString>);
+        engine.register_fn("info", logging::info::<bool>);
+        engine.register_fn("info", logging::info::<Dynamic>);
+        engine.register_fn("warn", logging::warn::<i64>);
+        engine.register_fn("warn", logging::warn::<ImmutableString>);
+        engine.register_fn("warn", logging::warn::<bool>);
+        engine.register_fn("warn", logging::warn::<Dynamic>);
+        engine.register_fn("error", logging::error::<i64>);
+        engine.register_fn("error", logging::error::<ImmutableString>);
+        engine.register_fn("error", logging::error::<bool>);
+        engine.register_fn("error", logging::error::<Dynamic>);
+        let permissions = self.permissions.clone();
+        engine.register_result_fn("fetch", move |o: fetch::Options| {
+            fetch::fetch(&permissions, o)
+        });
+        graphql_client::register(&mut engine, &self.permissions);
+        let permissions = self.permissions.clone();
+        engine.register_result_fn("env", move |name: ImmutableString| {
+            match permissions.env_var(&name) {
+                Ok(Some(v)) => Ok(Dynamic::from(v)),
+                Ok(None) => Ok(Dynamic::UNIT),
+                Err(e) => Err(Box::<EvalAltResult>::from(e)),
+            }
+        });
+        let secrets = self.secrets.clone();
+        engine.register_result_fn("secret", move |name: ImmutableString| {
+            match secrets.get(&name) {
+                Ok(Some(v)) => Ok(Dynamic::from(v.to_string())),
+                Ok(None) => Ok(Dynamic::UNIT),
+                Err(e) => Err(Box::<EvalAltResult>::from(e)),
+            }
+        });
+        engine.register_static_module("http", http_utils::module());
+        engine.register_static_module("msgpack", msgpack::module());
+        engine.register_static_module("cbor", cbor::module());
+        engine
+            .register_fn("markdown_to_html", markdown::to_html)
+            .register_result_fn("markdown_to_html", markdown::to_html_with);
+        engine
+            .register_result_fn("csv_parse", csv_utils::parse)
+            .register_result_fn("csv_parse", csv_utils::parse_script)
+            .register_result_fn("csv_write", csv_utils::write)
+            .register_result_fn("csv_write", csv_utils::write_script)
+            .register_result_fn("csv_response", csv_utils::response)
+            .register_result_fn("csv_response", csv_utils::response_script);
+        let memo = self.memo.clone();
+        engine.register_fn("forget_memo", move |key: ImmutableString| {
+            memo.forget(&key) as i64
+        });
+        request::register(&mut engine);
+        webhooks::register(&mut engine);
+        xml::register(&mut engine);
+        let decompressed = self.limits.max_decompressed_size;
+        compression::register(
+            &mut engine,
+            decompressed.unwrap_or(compression::MAX_DECOMPRESSED_SIZE),
+        );
+        let pixels = self.limits.max_image_pixels;
+        images::register(&mut engine, pixels.unwrap_or(images::MAX_IMAGE_PIXELS));
+        engine
+            .register_type_with_name::<HttpError>("HttpError")
+            .register_get("status", |e: &mut HttpError| e.status as i64)
+            .register_get("message", |e: &mut HttpError| e.message.clone())
+            .register_result_fn("HttpError", HttpError::script)
+            .register_result_fn("HttpError", HttpError::script_with_body);
+        engine
+            .register_type::<fetch::Options>()
+            .register_get_set("url", fetch::Options::get_url, fetch::Options::set_url)
+            .register_get_set(
+                "method",
+                fetch::Options::get_method,
+                fetch::Options::set_method,
+            )
+            .register_get_set(
+                "headers",
+                fetch::Options::get_headers,
+                fetch::Options::set_headers,
+            )
+            .register_get_set("body", fetch::Options::get_body, fetch::Options::set_body)
+            .register_fn("fetch_options", fetch::Options::new);
+        engine
+            .register_type::<fetch::Response>()
+            .register_get_set(
+                "headers",
+                fetch::Response::get_headers,
+                fetch::Response::set_headers,
+            )
+            .register_get_set("body", fetch::Response::get_body, fetch::Response::set_body);
+        engine
+            .register_type_with_name::<datetime::DateTime>("DateTime")
+            .register_get("timestamp", datetime::DateTime::get_timestamp)
+            .register_get("timestamp_ms", datetime::DateTime::get_timestamp_ms)
+            .register_get("year", datetime::DateTime::get_year)
+            .register_get("month", datetime::DateTime::get_month)
+            .register_get("day", datetime::DateTime::get_day)
+            .register_get("hour", datetime::DateTime::get_hour)
+            .register_get("minute", datetime::DateTime::get_minute)
+            .register_get("second", datetime::DateTime::get_second)
+            .register_fn("to_iso", datetime::DateTime::to_iso)
+            .register_result_fn("format", datetime::DateTime::format)
+            .register_result_fn("to_timezone", datetime::DateTime::to_timezone)
+            .register_result_fn("+", datetime::DateTime::add)
+            .register_result_fn("-", datetime::DateTime::sub)
+            .register_fn("-", datetime::DateTime::diff)
+            .register_fn("==", datetime::DateTime::eq)
+            .register_fn("<", datetime::DateTime::lt)
+            .register_fn(">", datetime::DateTime::gt)
+            .register_fn("now", datetime::now)
+            .register_fn("now_utc_iso", datetime::now_utc_iso)
+            .register_result_fn("from_timestamp", datetime::from_timestamp)
+            .register_result_fn("parse_datetime", datetime::parse)
+            .register_result_fn("parse_datetime", datetime::parse_with_format);
+        engine
+            .register_type_with_name::<datetime::Duration>("Duration")
+            .register_get("seconds", datetime::Duration::get_seconds)
+            .register_get("milliseconds", datetime::Duration::get_milliseconds)
+            .register_result_fn("+", datetime::Duration::add)
+            .register_result_fn("-", datetime::Duration::sub)
+            .register_result_fn("milliseconds", datetime::milliseconds)
+            .register_result_fn("seconds", datetime::seconds)
+            .register_result_fn("minutes", datetime::minutes)
+            .register_result_fn("hours", datetime::hours)
+            .register_result_fn("days", datetime::days);
+        if let Some(data_dir) = &self.data_dir {
+            let sandbox = files::Sandbox::new(data_dir.clone(), self.data_quota)
+                .with_permissions(self.permissions.clone());
+            let sb = sandbox.clone();
+            engine.register_result_fn("file_read", move |n: ImmutableString| sb.read(n));
+            let sb = sandbox.clone();
+            engine.register_result_fn(
+                "file_write",
+                move |n: ImmutableString, c: ImmutableString| sb.write(n, c),
+            );
+            let sb = sandbox.clone();
+            engine.register_result_fn(
+                "file_append",
+                move |n: ImmutableString, c: ImmutableString| sb.append(n, c),
+            );
+            let sb = sandbox.clone();
+            engine.register_result_fn("file_read_blob", move |n: ImmutableString| sb.read_blob(n));
+            let sb = sandbox.clone();
+            engine.register_result_fn("file_write", move |n: ImmutableString, c: Blob| {
+                sb.write_blob(n, c)
+            });
+            let sb = sandbox.clone();
+            engine.register_result_fn("file_append", move |n: ImmutableString, c: Blob| {
+                sb.append_blob(n, c)
+            });
+            let sb = sandbox.clone();
+            engine.register_result_fn("file_list", move |n: ImmutableString| sb.list(n));
+            let sb = sandbox.clone();
+            engine.register_result_fn("zip_extract", move |a: Dynamic, d: ImmutableString| {
+                sb.zip_extract(a, d)
+            });
+            let sb = sandbox.clone();
+            engine.register_result_fn("tar_extract", move |a: Dynamic, d: ImmutableString| {
+                sb.tar_extract(a, d)
+            });
+            let sb = sandbox.clone();
+            engine.register_result_fn("zip_create", move |n: ImmutableString, s: Dynamic| {
+                sb.zip_create(n, s)
+            });
+            engine.register_result_fn("file_list", move || sandbox.list("".into()));
+        }
+        if let Some(mailer) = &self.mailer {
+            let m = mailer.clone();
+            engine.register_result_fn("send_mail", move |mail: Dynamic| m.send(mail));
+        }
+        if let Some(storage) = &self.storage {
+            engine
+                .register_type_with_name::<storage::Storage>("S3")
+                .register_result_fn("put", storage::Storage::put)
+                .register_result_fn("put", storage::Storage::put_typed)
+                .register_result_fn("get", storage::Storage::get)
+                .register_result_fn("get_blob", storage::Storage::get_blob)
+                .register_result_fn("delete", storage::Storage::delete)
+                .register_result_fn("presign", storage::Storage::presign)
+                .register_result_fn("presign", storage::Storage::presign_method);
+        }
+        if self.oauth.is_some() {
+            engine
+                .register_type_with_name::<oauth::OAuth>("OAuth")
+                .register_result_fn("begin", oauth::OAuth::begin)
+                .register_result_fn("callback", oauth::OAuth::callback)
+                .register_result_fn("refresh", oauth::OAuth::refresh);
+        }
+        self.limits.apply(&mut engine);
+        let traced = self.dev_mode || self.limits.max_operations.is_some();
+        if self.debugger.is_some() || self.profiler.is_some() || traced {
+            let debugger = self.debugger.clone();
+            let profiler = self.profiler.clone();
+            let variables = self.dev_mode;
+            engine.register_debugger(
+                |_| Dynamic::UNIT,
+                move |context, event, node, source, pos| {
+                    if traced {
+                        trace::step(&context, &event, &node, source, pos, variables);
+                    }
+                    if let Some(profiler) = &profiler {
+                        profiler.step(&context);
+                    }
+                    if let Some(debugger) = &debugger {
+                        debugger.on_event(&context, event, node, source, pos);
+                    }
+                    Ok(rhai::debugger::DebuggerCommand::StepInto)
+                },
+            );
+        }
+        for setup in &self.setup {
+            setup(&mut engine);
+        }
+        engine
+    }
+}
+
+impl RhaiDir {
+    /// The first index file of `dir` there is, if `dir` is a directory.
+    fn index_file(&self, dir: &Path) -> Option<PathBuf> {
+        if self.index_files.is_empty() || !dir.is_dir() {
+            return None;
+        }
+        self.index_files
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|file| {
+                file.is_file()
+                    || self
+                        .snapshot
+                        .as_ref()
+                        .map_or(false, |s| s.get(&self.dir, file).is_some())
+            })
+    }
+
+    /// The answer to a script that failed with `e` at `location`.
+    fn script_error(
+        &self,
+        path: &Path,
+        e: &EvalAltResult,
+        location: Option<trace::Location>,
+    ) -> Response {
+        let at = location.as_ref().map(|l| l.at(&self.dir));
+        let script = snapshot::key(&self.dir, path);
+        let script = script.unwrap_or_else(|| path.display().to_string());
+        let stack = stack(e, location.as_ref(), &self.dir);
+        self.counters
+            .failed(&script, &e.to_string(), stack.as_deref());
+        if let (EvalAltResult::ErrorTooManyOperations(_), Some(at)) = (e, &at) {
+            log::error!("Script ran out of operations at {}: {}", at, e);
+        } else {
+            log::error!("Script execution error: {}", redact(&format!("{:?}", e)));
+        }
+        if let Some(res) = self.error_hooks.iter().find_map(|hook| hook(path, e)) {
+            return res;
+        }
+        match (self.dev_mode, location) {
+            (true, Some(l)) => error_page::render(
+                "Script execution error",
+                path,
+                &format!("{}\n{}", e, l.describe(&self.dir)),
+            ),
+            (true, None) => error_page::render("Script execution error", path, &e.to_string()),
+            (false, _) => Response::new(StatusCode::InternalServerError),
+        }
     }
 }
 
@@ -46,143 +900,172 @@ where
     State: Clone + Send + Sync + 'static,
 {
     async fn call(&self, mut req: Request<State>) -> Result {
-        let path = req.url().path();
-        let path = path
-            .strip_prefix(&self.prefix.trim_end_matches('*'))
-            .unwrap();
-
-        let path = path.trim_start_matches('/');
-        let mut file_path = self.dir.clone();
-        for p in Path::new(path) {
-            if p == OsStr::new(".") {
-                continue;
-            } else if p == OsStr::new("..") {
-                file_path.pop();
-            } else {
-                file_path.push(&p);
+        let file_path = match resolve_file(&self.prefix, &self.dir, &req) {
+            Some(p) => p,
+            None => return Ok(Response::new(StatusCode::Forbidden)),
+        };
+        let file_path = match self.index_file(file_path.as_ref()) {
+            Some(index) => AsyncPathBuf::from(index),
+            None => file_path,
+        };
+        let path: &Path = file_path.as_ref();
+        if [INIT, SHUTDOWN, validate::ROUTES]
+            .iter()
+            .any(|name| path == self.dir.join(name))
+            || testing::is_test(path)
+        {
+            return Ok(Response::new(StatusCode::NotFound));
+        }
+        let memo_url = matches!(
+            req.method(),
+            http_types::Method::Get | http_types::Method::Head
+        )
+        .then(|| match req.url().query() {
+            Some(query) => format!("{}?{}", req.url().path(), query),
+            None => req.url().path().to_string(),
+        });
+        if let Some(memoized) = memo_url.as_deref().and_then(|url| self.memo.get(url)) {
+            if let Ok(res) = memoized.into_response() {
+                return Ok(res);
             }
         }
+        let precompiled = self
+            .snapshot
+            .as_ref()
+            .and_then(|s| s.get(&self.dir, file_path.as_ref()));
+        let read = match precompiled {
+            Some(entry) => Ok(entry.code.to_string()),
+            None => integrity::read(self.integrity.as_deref(), file_path.as_ref()),
+        };
+        let res = match read {
+            Ok(s) => {
+                let script = snapshot::key(&self.dir, path);
+                let script = script.unwrap_or_else(|| path.display().to_string());
+                let grant = match capabilities::declared(path, &script, &s) {
+                    Ok(grant) => grant,
+                    Err(e) => {
+                        log::error!("{}", e);
+                        return Ok(Response::new(StatusCode::InternalServerError));
+                    }
+                };
+                let origin = self.audit.clone().map(|log| {
+                    let id = req.header("x-request-id").map(|h| h.as_str());
+                    audit::Origin::new(log, &script, id)
+                });
+                let request = request::ScriptRequest::from_request(&mut req).await;
+                let method = request.method();
+                let checked =
+                    self.routes
+                        .check(&script, method.as_ref(), req.url(), request.body());
+                if let Some(invalid) = checked {
+                    return Ok(invalid);
+                }
+                let ctx =
+                    Context::from_parts(request.header_map(), request.method(), request.body());
 
-        log::info!("Requested file: {:?}", file_path);
-        let file_path = AsyncPathBuf::from(file_path);
-        if !file_path.starts_with(&self.dir) {
-            log::warn!("Unauthorized attempt to read: {:?}", file_path);
-            Ok(Response::new(StatusCode::Forbidden))
-        } else {
-            let res = match std::fs::read_to_string(&file_path) {
-                Ok(s) => {
-                    let mut m = HashMap::new();
-                    for (n, v) in req.iter() {
-                        m.insert(String::from(n.as_str()), String::from(v.as_str()));
+                let dyn_ctx = to_script_value(&ctx).unwrap();
+                let mut scope = self.scope();
+                scope.push("ctx", dyn_ctx);
+                scope.push("req", request);
+                let engine = self.engine();
+                if let Some(claims) = req.ext::<oidc::Claims>() {
+                    if let Ok(claims) = to_script_value(&claims.0) {
+                        scope.push_constant("claims", claims);
                     }
-                    let data: Value;
-
-                    match req.method() {
-                        http_types::Method::Put
-                        | http_types::Method::Post
-                        | http_types::Method::Patch => {
-                            data = match req.body_json().await {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    log::warn!("error parsing value {:?}", e);
-                                    let j = r#"{}"#;
-                                    let retval: Value = serde_json::from_str(j).unwrap();
-                                    retval
-                                }
-                            }
-                        }
-                        _ => {
-                            let j = r#"{}"#;
-                            data = serde_json::from_str(j).unwrap();
+                }
+                for hook in &self.scope_hooks {
+                    hook(req.as_ref(), &mut scope);
+                }
+                let started = Instant::now();
+                let cached = self.cache_scripts.then(|| self.asts.get(path, &s));
+                let compiled = match cached.flatten() {
+                    Some(ast) => Ok(ast),
+                    None => {
+                        let compiled = engine.compile(&s);
+                        let took = started.elapsed();
+                        self.counters.compiled(took);
+                        if let Some(slow) = &self.slow {
+                            slow.record(&script, Phase::Compile, took);
                         }
+                        compiled.map(|mut ast| {
+                            ast.set_source(path.to_string_lossy().as_ref());
+                            if self.cache_scripts {
+                                self.asts.insert(path, &s, ast)
+                            } else {
+                                Arc::new(ast)
+                            }
+                        })
                     }
-
-                    let ctx = Context {
-                        headers: m,
-                        data: data,
-                    };
-
-                    let dyn_ctx: Dynamic = to_dynamic(ctx).unwrap();
-                    let mut scope = Scope::new();
-                    scope.push("ctx", dyn_ctx);
-                    let mut engine = Engine::new_raw();
-
-                    engine.register_fn("log", logging::log::<i64>);
-                    engine.register_fn("log", logging::log::<ImmutableString>);
-                    engine.register_fn("log", logging::log::<bool>);
-                    engine.register_fn("log", logging::log::<Dynamic>);
-                    engine.register_fn("info", logging::info::<i64>);
-                    engine.register_fn("info", logging::info::<ImmutableString>);
-                    engine.register_fn("info", logging::info::<bool>);
-                    engine.register_fn("info", logging::info::<Dynamic>);
-                    engine.register_fn("warn", logging::warn::<i64>);
-                    engine.register_fn("warn", logging::warn::<ImmutableString>);
-                    engine.register_fn("warn", logging::warn::<bool>);
-                    engine.register_fn("warn", logging::warn::<Dynamic>);
-                    engine.register_fn("error", logging::error::<i64>);
-                    engine.register_fn("error", logging::error::<ImmutableString>);
-                    engine.register_fn("error", logging::error::<bool>);
-                    engine.register_fn("error", logging::error::<Dynamic>);
-                    engine.register_result_fn("fetch", fetch::fetch);
-                    engine
-                        .register_type::<fetch::Options>()
-                        .register_get_set("url", fetch::Options::get_url, fetch::Options::set_url)
-                        .register_get_set(
-                            "method",
-                            fetch::Options::get_method,
-                            fetch::Options::set_method,
-                        )
-                        .register_get_set(
-                            "headers",
-                            fetch::Options::get_headers,
-                            fetch::Options::set_headers,
-                        )
-                        .register_get_set(
-                            "body",
-                            fetch::Options::get_body,
-                            fetch::Options::set_body,
-                        )
-                        .register_fn("fetch_options", fetch::Options::new);
-                    engine
-                        .register_type::<fetch::Response>()
-                        .register_get_set(
-                            "headers",
-                            fetch::Response::get_headers,
-                            fetch::Response::set_headers,
-                        )
-                        .register_get_set(
-                            "body",
-                            fetch::Response::get_body,
-                            fetch::Response::set_body,
-                        );
-                    let result = match engine.eval_with_scope(&mut scope, s.as_str()) {
-                        Ok::<Dynamic, _>(o) => {
-                            let evt: Value = match from_dynamic(&o) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    log::warn!("Error parsing return value from script {:?}", e);
-                                    let j = r#"{"Error" : "Script return value error"}"#;
-                                    let retval: Value = serde_json::from_str(j).unwrap();
-                                    retval
-                                }
-                            };
-                            Ok(Response::builder(StatusCode::Ok).body(evt).build())
+                };
+                let running = Instant::now();
+                let (evaluated, location) = trace::run(|| {
+                    capabilities::run(grant, || {
+                        audit::run(origin, || {
+                            compiled
+                                .map_err(Box::<EvalAltResult>::from)
+                                .and_then(|ast| match &self.profiler {
+                                    Some(p) => p.run(req.url().path(), || {
+                                        handlers::dispatch(engine, &mut scope, &ast)
+                                    }),
+                                    None => handlers::dispatch(engine, &mut scope, &ast),
+                                })
+                        })
+                    })
+                });
+                let failed = matches!(&evaluated, Err(e) if thrown_http_error(e).is_none());
+                self.counters.ran(&script, started.elapsed(), failed);
+                if let Some(slow) = &self.slow {
+                    slow.record(&script, Phase::Exec, running.elapsed());
+                }
+                let mut result = match evaluated {
+                    Ok::<Dynamic, _>(o) if o.is::<HttpError>() => {
+                        Ok(o.cast::<HttpError>().response())
+                    }
+                    Ok::<Dynamic, _>(o) if o.is::<http_utils::ScriptResponse>() => {
+                        let response = o.cast::<http_utils::ScriptResponse>();
+                        if let (Some((key, ttl)), Some(url)) = (&response.memo, &memo_url) {
+                            self.memo.insert(url, key, *ttl, response.clone());
                         }
-                        Err(e) => {
-                            log::error!("Script execution error: {:?}", e);
-                            Ok(Response::new(StatusCode::InternalServerError))
+                        match response.into_response() {
+                            Ok(res) => Ok(res),
+                            Err(e) => {
+                                log::warn!("Error parsing response body from script {:?}", e);
+                                Ok(Response::new(StatusCode::InternalServerError))
+                            }
                         }
-                    };
-                    result
-                }
-                Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                    log::warn!("File not found: {:?}", &file_path);
-                    Ok(Response::new(StatusCode::NotFound))
+                    }
+                    Ok::<Dynamic, _>(o) => {
+                        let evt: Value = match from_script_value(&o) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                log::warn!("Error parsing return value from script {:?}", e);
+                                let j = r#"{"Error" : "Script return value error"}"#;
+                                let retval: Value = serde_json::from_str(j).unwrap();
+                                retval
+                            }
+                        };
+                        Ok(Response::builder(StatusCode::Ok).body(evt).build())
+                    }
+                    Err(e) => match thrown_http_error(&e) {
+                        Some(e) => Ok(e.response()),
+                        None => Ok(self.script_error(path, &e, location)),
+                    },
+                };
+                let oauth = scope.get_value::<oauth::OAuth>("oauth");
+                if let (Ok(res), Some(oauth)) = (&mut result, oauth) {
+                    for cookie in oauth.finished() {
+                        res.append_header("set-cookie", cookie);
+                    }
                 }
-                Err(e) => return Err(e.into()),
-            };
-            res
-        }
+                result
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::warn!("File not found: {:?}", &file_path);
+                Ok(Response::new(StatusCode::NotFound))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        res
     }
 }
 
@@ -274,4 +1157,377 @@ mod test {
 
         assert_eq!(response_body, json!({"url":"https://httpbin.org/post"}));
     }
+
+    #[async_std::test]
+    async fn http_errors() {
+        let dir = std::env::temp_dir().join("tide_rhai_http_errors");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("thrown"),
+            r#"throw HttpError(404, "no such order");"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("nested"),
+            r#"fn check(n) { if n < 0 { throw HttpError(422, "bad", #{ field: "n" }); } n }
+            check(-1)"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("returned"), r#"HttpError(403, "no")"#).unwrap();
+        std::fs::write(dir.join("invalid"), r#"throw HttpError(200, "fine");"#).unwrap();
+        let mut app = tide::new();
+        app.at("/*").all(RhaiDir::new("/*", &dir).unwrap());
+
+        use tide_testing::TideTestingExt;
+        let mut res = app.get("/thrown").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NotFound);
+        assert_eq!(
+            res.body_json::<Value>().await.unwrap(),
+            json!({"error": "no such order"})
+        );
+        let mut res = app.get("/nested").await.unwrap();
+        assert_eq!(res.status(), StatusCode::UnprocessableEntity);
+        assert_eq!(
+            res.body_json::<Value>().await.unwrap(),
+            json!({"field": "n"})
+        );
+        assert_eq!(
+            app.get("/returned").await.unwrap().status(),
+            StatusCode::Forbidden
+        );
+        assert_eq!(
+            app.get("/invalid").await.unwrap().status(),
+            StatusCode::InternalServerError
+        );
+    }
+
+    #[async_std::test]
+    async fn responses() {
+        let dir = std::env::temp_dir().join("tide_rhai_responses");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("created"),
+            r#"http::response(http::CREATED, #{ id: 7 }).header("location", "/orders/7")"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("text"), r#"http::response(200, "plain")"#).unwrap();
+        let mut app = tide::new();
+        app.at("/*").all(RhaiDir::new("/*", &dir).unwrap());
+
+        use tide_testing::TideTestingExt;
+        let mut res = app.get("/created").await.unwrap();
+        assert_eq!(res.status(), StatusCode::Created);
+        assert_eq!(res.header("location").unwrap().as_str(), "/orders/7");
+        assert_eq!(res.body_json::<Value>().await.unwrap(), json!({"id": 7}));
+        assert_eq!(app.get("/text").recv_string().await.unwrap(), "plain");
+    }
+
+    #[async_std::test]
+    async fn memoized_responses() {
+        let dir = std::env::temp_dir().join("tide_rhai_memoized");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(
+            dir.join("calls"),
+            r#"file_append("calls", "x"); http::response(200, file_read("calls")).memoize("calls", 60)"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("forget"), r#"forget_memo("calls")"#).unwrap();
+        let mut app = tide::new();
+        let rhai_dir = RhaiDir::new("/*", &dir)
+            .unwrap()
+            .with_data_dir(dir.join("data"))
+            .unwrap();
+        app.at("/*").all(rhai_dir);
+
+        use tide_testing::TideTestingExt;
+        assert_eq!(app.get("/calls").recv_string().await.unwrap(), "x");
+        assert_eq!(app.get("/calls").recv_string().await.unwrap(), "x");
+        assert_eq!(app.get("/calls?page=2").recv_string().await.unwrap(), "xx");
+        let res = app.post("/calls").await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        let forgotten: Value = app.get("/forget").recv_json().await.unwrap();
+        assert_eq!(forgotten, json!(2));
+        assert_eq!(app.get("/calls").recv_string().await.unwrap(), "xxxx");
+    }
+
+    #[async_std::test]
+    async fn handler_functions() {
+        let dir = std::env::temp_dir().join("tide_rhai_handler_functions");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("orders"),
+            "fn get(req) { #{ page: req.query(\"page\") } }\nfn post(req) { http::response(http::CREATED, req.body_json()) }",
+        )
+        .unwrap();
+        let mut app = tide::new();
+        app.at("/*").all(RhaiDir::new("/*", &dir).unwrap());
+
+        use tide_testing::TideTestingExt;
+        let got: Value = app.get("/orders?page=2").recv_json().await.unwrap();
+        assert_eq!(got, json!({"page": "2"}));
+        let res = app.post("/orders").body(json!({"id": 7})).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Created);
+        let res = app.delete("/orders").await.unwrap();
+        assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(res.header("allow").unwrap().as_str(), "GET, HEAD, POST");
+    }
+
+    #[async_std::test]
+    async fn edited_modules() {
+        let dir = std::env::temp_dir().join("tide_rhai_edited_modules");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("answer"),
+            "import \"lib::helpers\" as h;\n#{ answer: h::ANSWER }",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("lib/helpers.rhai"),
+            "import \"./math\" as math;\nexport const ANSWER = math::times(21);",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/math.rhai"), "fn times(n) { n * 2 }").unwrap();
+        let mut app = tide::new();
+        app.at("/*").all(RhaiDir::new("/*", &dir).unwrap());
+
+        use tide_testing::TideTestingExt;
+        let got: Value = app.get("/answer").recv_json().await.unwrap();
+        assert_eq!(got, json!({"answer": 42}));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(dir.join("lib/math.rhai"), "fn times(n) { n * 3 }").unwrap();
+        let got: Value = app.get("/answer").recv_json().await.unwrap();
+        assert_eq!(got, json!({"answer": 63}));
+    }
+
+    #[async_std::test]
+    async fn builder() {
+        let dir = std::env::temp_dir().join("tide_rhai_builder");
+        std::fs::create_dir_all(dir.join("orders")).unwrap();
+        std::fs::write(dir.join("orders/index.rhai"), "#{ orders: [] }").unwrap();
+        std::fs::write(dir.join("long"), "let s = \"\"; loop { s += \"more\"; }").unwrap();
+        let rhai = RhaiDir::builder("/*", &dir)
+            .limits(Limits {
+                max_string_size: Some(100),
+                ..Limits::default()
+            })
+            .cache_scripts(false)
+            .index_files(["index.rhai"])
+            .on_error(|path, e| {
+                let limited = matches!(e, EvalAltResult::ErrorDataTooLarge(..));
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                limited.then(|| {
+                    Response::builder(StatusCode::PayloadTooLarge)
+                        .body(name)
+                        .build()
+                })
+            })
+            .build()
+            .unwrap();
+        let rhai = Arc::new(rhai);
+        let mut app = tide::new();
+        let served = rhai.clone();
+        app.at("/*").all(move |req| {
+            let served = served.clone();
+            async move { served.call(req).await }
+        });
+
+        use tide_testing::TideTestingExt;
+        let got: Value = app.get("/orders/").recv_json().await.unwrap();
+        assert_eq!(got, json!({"orders": []}));
+        let got: Value = app.get("/orders").recv_json().await.unwrap();
+        assert_eq!(got, json!({"orders": []}));
+        let mut res = app.get("/long").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(res.body_string().await.unwrap(), "long");
+        assert_eq!(rhai.stats().cache_entries, 0);
+    }
+
+    #[async_std::test]
+    async fn dev_mode() {
+        let dir = std::env::temp_dir().join("tide_rhai_dev_mode");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken"), "let order = 7;\norder / 0").unwrap();
+        std::fs::write(dir.join("forever"), "let n = 0;\nloop { n += 1; }").unwrap();
+        let mut app = tide::new();
+        let rhai = RhaiDir::new("/*", &dir)
+            .unwrap()
+            .with_dev_mode(true)
+            .with_max_operations(1000);
+        app.at("/*").all(rhai);
+
+        use tide_testing::TideTestingExt;
+        let mut res = app.get("/broken").await.unwrap();
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+        let page = res.body_string().await.unwrap();
+        assert!(page.contains("at broken:2:1"), "{}", page);
+        assert!(page.contains("order = 7"), "{}", page);
+        let page = app.get("/forever").recv_string().await.unwrap();
+        assert!(page.contains("Too many operations"), "{}", page);
+        assert!(page.contains("at forever:2:"), "{}", page);
+    }
+
+    #[async_std::test]
+    async fn lifecycle() {
+        let dir = std::env::temp_dir().join("tide_rhai_lifecycle");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(dir.join("_init.rhai"), r#"file_write("started", "yes");"#).unwrap();
+        std::fs::write(dir.join("_shutdown.rhai"), r#"throw "cannot close";"#).unwrap();
+        let rhai = RhaiDir::new("/*", &dir)
+            .unwrap()
+            .with_data_dir(dir.join("data"))
+            .unwrap();
+        rhai.start().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("data/started")).unwrap(),
+            "yes"
+        );
+        let e = rhai.shutdown().unwrap_err();
+        assert!(e.to_string().contains("cannot close"), "{}", e);
+
+        let mut app = tide::new();
+        app.at("/*").all(rhai);
+        use tide_testing::TideTestingExt;
+        assert_eq!(
+            app.get("/_init.rhai").await.unwrap().status(),
+            StatusCode::NotFound
+        );
+        // Without the scripts there is nothing to do.
+        assert!(RhaiDir::new("/*", "/").unwrap().start().is_ok());
+    }
+
+    #[async_std::test]
+    async fn run_tests() {
+        let dir = std::env::temp_dir().join("tide_rhai_run_tests");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("orders")).unwrap();
+        std::fs::write(
+            dir.join("orders/get"),
+            r#"if ctx.data.id == () { throw HttpError(400, "no id"); } #{ id: ctx.data.id }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("orders/get_test.rhai"),
+            r#"fn test_found() {
+                let res = request("POST", "/orders/get", #{ id: 7 });
+                assert_eq(res.status, 200);
+                assert_eq(res.body, #{ id: 7 });
+            }
+            fn test_missing() {
+                assert_eq(request("/orders/get").status, 400);
+            }
+            fn test_broken() {
+                assert(false, "broken on purpose");
+            }
+            fn helper(x) { x }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("plain_test.rhai"), "assert_ne(1, 2);").unwrap();
+        let rhai = RhaiDir::new("/*", &dir).unwrap();
+        let report = rhai.run_tests();
+        let results: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| (r.name.as_str(), r.error.is_none()))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("test_broken", false),
+                ("test_found", true),
+                ("test_missing", true),
+                ("(file)", true)
+            ]
+        );
+        assert!(report.to_string().ends_with("3 passed, 1 failed\n"));
+        assert!(report.results[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("broken on purpose"));
+
+        let mut app = tide::new();
+        app.at("/*").all(rhai);
+        use tide_testing::TideTestingExt;
+        assert_eq!(
+            app.get("/plain_test.rhai").await.unwrap().status(),
+            StatusCode::NotFound
+        );
+    }
+
+    #[async_std::test]
+    async fn stats() {
+        let dir = std::env::temp_dir().join("tide_rhai_stats");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ok"), "40 + 2").unwrap();
+        std::fs::write(dir.join("missing"), r#"throw HttpError(404, "gone")"#).unwrap();
+        std::fs::write(dir.join("broken"), r#"throw "oops""#).unwrap();
+        let rhai = Arc::new(RhaiDir::new("/*", &dir).unwrap());
+        let mut app = tide::new();
+        let r = rhai.clone();
+        app.at("/*").get(move |req| {
+            let r = r.clone();
+            async move { r.call(req).await }
+        });
+        use tide_testing::TideTestingExt;
+        for path in ["/ok", "/missing", "/broken"] {
+            app.get(path).await.unwrap();
+        }
+        let stats = rhai.stats();
+        assert_eq!((stats.engine, stats.prefix.as_str()), ("rhai", "/*"));
+        assert_eq!((stats.runs, stats.failures, stats.compiles), (3, 1, 3));
+        assert_eq!(stats.cache_entries, 3);
+
+        // A second mount of the directory reuses what the first compiled.
+        let mut app = tide::new();
+        let again = Arc::new(RhaiDir::new("/api/*", &dir).unwrap());
+        let a = again.clone();
+        app.at("/api/*").get(move |req| {
+            let a = a.clone();
+            async move { a.call(req).await }
+        });
+        assert_eq!(app.get("/api/ok").recv_string().await.unwrap(), "42");
+        assert_eq!(again.stats().compiles, 0);
+    }
+
+    #[async_std::test]
+    async fn host_functions() {
+        let dir = std::env::temp_dir().join("tide_rhai_host_functions");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("host"),
+            r#"#{ doubled: double(21), region: config.region, tenant: tenant }"#,
+        )
+        .unwrap();
+        let mut config = rhai::Map::new();
+        config.insert("region".into(), "eu".into());
+        let mut app = tide::new();
+        app.at("/*").all(
+            RhaiDir::new("/*", &dir)
+                .unwrap()
+                .with_engine_setup(|engine| {
+                    engine.register_fn("double", |x: i64| x * 2);
+                })
+                .with_global("config", config)
+                .with_scope(|req, scope| {
+                    let tenant = req.header("x-tenant").map_or("none", |h| h.as_str());
+                    scope.push_constant("tenant", tenant.to_string());
+                }),
+        );
+
+        use tide_testing::TideTestingExt;
+        let response_body: serde_json::value::Value = app
+            .get("/host")
+            .header("x-tenant", "acme")
+            .recv_json()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response_body,
+            json!({"doubled": 42, "region": "eu", "tenant": "acme"})
+        );
+    }
 }
@@ -1,18 +1,19 @@
+use crate::redact;
 use std::fmt::Display;
 use tide::log;
 
 pub fn log<T: Display>(s: T) {
-    println!("{}", s)
+    println!("{}", redact(&s.to_string()))
 }
 
 pub fn info<T: Display>(s: T) {
-    log::info!("{}", s)
+    log::info!("{}", redact(&s.to_string()))
 }
 
 pub fn warn<T: Display>(s: T) {
-    log::warn!("{}", s)
+    log::warn!("{}", redact(&s.to_string()))
 }
 
 pub fn error<T: Display>(s: T) {
-    log::error!("{}", s)
+    log::error!("{}", redact(&s.to_string()))
 }
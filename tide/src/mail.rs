@@ -0,0 +1,230 @@
+use crate::capabilities::{self, Capability};
+use crate::rt;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message};
+use rhai::serde::from_dynamic;
+use rhai::{Dynamic, EvalAltResult};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How the connection to the SMTP server is secured.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text connection, only meant for local relays.
+    None,
+    /// Upgrade a plain connection with `STARTTLS` (usually port 587).
+    #[default]
+    StartTls,
+    /// TLS from the first byte (usually port 465).
+    Tls,
+}
+
+/// SMTP settings for the `send_mail` binding.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MailConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// Sender used when the script does not set `from`.
+    pub from: String,
+    /// Maximum number of mails scripts may send per minute.
+    #[serde(default = "default_per_minute")]
+    pub per_minute: usize,
+}
+
+fn default_per_minute() -> usize {
+    10
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Recipients {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize, Debug)]
+struct Mail {
+    to: Recipients,
+    from: Option<String>,
+    subject: String,
+    body: Option<String>,
+    html: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    config: MailConfig,
+    transport: Arc<AsyncSmtpTransport<AsyncStd1Executor>>,
+    sent: Arc<Mutex<Vec<Instant>>>,
+}
+
+impl Mailer {
+    pub fn new(config: MailConfig) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = match config.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<AsyncStd1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<AsyncStd1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<AsyncStd1Executor>::relay(&config.host)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(user), Some(pass)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(user.clone(), pass.clone()));
+        }
+        Ok(Self {
+            transport: Arc::new(builder.build()),
+            config,
+            sent: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Records a send attempt, failing once the per-minute quota is used up.
+    fn take_quota(&self) -> Result<(), Box<EvalAltResult>> {
+        let mut sent = self.sent.lock().unwrap();
+        let now = Instant::now();
+        sent.retain(|t| now.duration_since(*t) < Duration::from_secs(60));
+        if sent.len() >= self.config.per_minute {
+            log::warn!(
+                "Mail quota of {} per minute exceeded",
+                self.config.per_minute
+            );
+            return Err("Mail quota exceeded".into());
+        }
+        sent.push(now);
+        Ok(())
+    }
+
+    fn build(&self, mail: Mail) -> Result<Message, Box<EvalAltResult>> {
+        let from = mail.from.as_ref().unwrap_or(&self.config.from);
+        // A line break would end the header and start one of the script.
+        if mail.subject.contains(['\r', '\n']) {
+            return Err("Invalid mail: line break in the subject".into());
+        }
+        let mut builder = Message::builder().subject(mail.subject);
+        builder = match from.parse::<Mailbox>() {
+            Ok(v) => builder.from(v),
+            Err(e) => return Err(format!("Invalid sender '{}': {}", from, e).into()),
+        };
+        let to = match mail.to {
+            Recipients::One(v) => vec![v],
+            Recipients::Many(v) => v,
+        };
+        for t in to.iter() {
+            builder = match t.parse::<Mailbox>() {
+                Ok(v) => builder.to(v),
+                Err(e) => return Err(format!("Invalid recipient '{}': {}", t, e).into()),
+            };
+        }
+        let res = match (mail.body, mail.html) {
+            (Some(body), Some(html)) => {
+                builder.multipart(MultiPart::alternative_plain_html(body, html))
+            }
+            (None, Some(html)) => builder
+                .header(lettre::message::header::ContentType::TEXT_HTML)
+                .body(html),
+            (body, None) => builder.body(body.unwrap_or_default()),
+        };
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(format!("Invalid mail: {}", e).into()),
+        }
+    }
+
+    /// `send_mail(#{to: "a@example.com", subject: "Hi", body: "..", html: ".."})`
+    pub fn send(&self, mail: Dynamic) -> Result<(), Box<EvalAltResult>> {
+        capabilities::require(Capability::Net)?;
+        let mail: Mail = match from_dynamic(&mail) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Mail Parse Error {:?}", e);
+                return Err("Invalid mail options".into());
+            }
+        };
+        let message = self.build(mail)?;
+        self.take_quota()?;
+
+        match rt::block_on(self.transport.send(message)) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                log::error!("Mail Error: {}", e);
+                Err("Mail Error".into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn mailer() -> Mailer {
+        Mailer::new(MailConfig {
+            host: "localhost".into(),
+            port: None,
+            username: None,
+            password: None,
+            tls: SmtpTls::None,
+            from: "app@example.com".into(),
+            per_minute: 1,
+        })
+        .unwrap()
+    }
+
+    fn mail(to: &str, subject: &str) -> Mail {
+        Mail {
+            to: Recipients::One(to.into()),
+            from: None,
+            subject: subject.into(),
+            body: None,
+            html: Some("<p>Hi</p>".into()),
+        }
+    }
+
+    #[test]
+    fn builds_the_message() {
+        let mailer = mailer();
+        let message = mailer.build(mail("ann@example.com", "Hi")).unwrap();
+        let message = String::from_utf8(message.formatted()).unwrap();
+        let header = |name: &str| message.lines().find(|l| l.starts_with(name)).unwrap();
+        assert!(header("From: ").contains("app@example.com"));
+        assert!(header("To: ").contains("ann@example.com"));
+        assert_eq!(header("Subject: "), "Subject: Hi");
+        assert!(header("Content-Type: ").contains("text/html"));
+
+        let e = mailer.build(mail("not an address", "Hi")).unwrap_err();
+        assert!(e.to_string().contains("Invalid recipient"));
+        let injected = "Hi\r\nBcc: eve@example.com";
+        assert!(mailer.build(mail("ann@example.com", injected)).is_err());
+        let injected = "ann@example.com\r\nBcc: eve@example.com";
+        assert!(mailer.build(mail(injected, "Hi")).is_err());
+    }
+
+    #[test]
+    fn needs_the_net_capability() {
+        let mailer = mailer();
+        let grant =
+            capabilities::declared(Path::new("a.rhai"), "a.rhai", "// capabilities: fs").unwrap();
+        let mail: Dynamic = rhai::serde::to_dynamic(serde_json::json!({
+            "to": "ann@example.com",
+            "subject": "Hi",
+        }))
+        .unwrap();
+        let e = capabilities::run(grant, || mailer.send(mail)).unwrap_err();
+        assert!(e.to_string().contains("Requires the net capability"));
+        // Turned away before taking any of the quota.
+        assert!(mailer.take_quota().is_ok());
+        assert!(mailer.take_quota().is_err());
+    }
+}
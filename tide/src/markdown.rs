@@ -0,0 +1,163 @@
+//! `markdown_to_html(text)` and `markdown_to_html(text, opts)` for rhai
+//! scripts, rendering CommonMark with pulldown-cmark. The HTML is sanitized
+//! by default, so markdown written by users can be served as it comes: the
+//! tags markdown makes stay, scripts, styles and event handlers go, and links
+//! get `rel="nofollow noopener noreferrer"`.
+//!
+//!```text
+//! fn get(req) {
+//!     let text = file_read("posts/" + req.query("slug") + ".md");
+//!     let html = markdown_to_html(text, #{ tables: true, tasklists: true });
+//!     http::response(http::OK, "<article>" + html + "</article>")
+//!         .header("content-type", "text/html")
+//! }
+//!```
+//!
+//! `opts` turns on the `tables`, `strikethrough`, `footnotes`, `tasklists`
+//! and `smart_punctuation` extensions, and says what happens to HTML in the
+//! markdown with `html`: `"sanitize"`, `"escape"` to show it as text, or
+//! `"trusted"` to keep it and skip sanitizing, for authors' own content.
+//! Sanitized HTML may keep more tags with `tags`, and `link_rel` changes the
+//! `rel` of links, `""` leaving it out.
+
+use pulldown_cmark::{html, Event, Parser};
+use rhai::serde::from_dynamic;
+use rhai::{Dynamic, EvalAltResult, ImmutableString};
+use serde::Deserialize;
+
+/// What becomes of HTML written in the markdown.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Html {
+    /// Cleaned with the rest of the output.
+    #[default]
+    Sanitize,
+    /// Shown as text.
+    Escape,
+    /// Kept as it is, and nothing is sanitized.
+    Trusted,
+}
+
+/// The `opts` of `markdown_to_html`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct Options {
+    tables: bool,
+    strikethrough: bool,
+    footnotes: bool,
+    tasklists: bool,
+    smart_punctuation: bool,
+    html: Html,
+    /// Tags kept by the sanitizer besides its own.
+    tags: Vec<String>,
+    link_rel: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tables: false,
+            strikethrough: false,
+            footnotes: false,
+            tasklists: false,
+            smart_punctuation: false,
+            html: Html::default(),
+            tags: Vec::new(),
+            link_rel: "nofollow noopener noreferrer".into(),
+        }
+    }
+}
+
+pub(crate) fn render(text: &str, opts: &Options) -> String {
+    let mut extensions = pulldown_cmark::Options::empty();
+    for (on, extension) in [
+        (opts.tables, pulldown_cmark::Options::ENABLE_TABLES),
+        (
+            opts.strikethrough,
+            pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+        ),
+        (opts.footnotes, pulldown_cmark::Options::ENABLE_FOOTNOTES),
+        (opts.tasklists, pulldown_cmark::Options::ENABLE_TASKLISTS),
+        (
+            opts.smart_punctuation,
+            pulldown_cmark::Options::ENABLE_SMART_PUNCTUATION,
+        ),
+    ] {
+        extensions.set(extension, on);
+    }
+    let escape = opts.html == Html::Escape;
+    let events = Parser::new_ext(text, extensions).map(|event| match event {
+        Event::Html(raw) if escape => Event::Text(raw),
+        event => event,
+    });
+    let mut out = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    if opts.html == Html::Trusted {
+        return out;
+    }
+    let mut cleaner = ammonia::Builder::default();
+    cleaner
+        .add_tags(opts.tags.iter().map(String::as_str))
+        .add_tag_attributes("code", ["class"])
+        .link_rel(Some(opts.link_rel.as_str()).filter(|rel| !rel.is_empty()));
+    if opts.tasklists {
+        cleaner
+            .add_tags(["input"])
+            .add_tag_attributes("input", ["type", "checked", "disabled"]);
+    }
+    cleaner.clean(&out).to_string()
+}
+
+/// `markdown_to_html(text)`
+pub(crate) fn to_html(text: ImmutableString) -> String {
+    render(&text, &Options::default())
+}
+
+/// `markdown_to_html(text, #{tables: true, html: "escape"})`
+pub(crate) fn to_html_with(
+    text: ImmutableString,
+    opts: Dynamic,
+) -> Result<String, Box<EvalAltResult>> {
+    let opts: Options =
+        from_dynamic(&opts).map_err(|e| format!("Invalid markdown options: {}", e))?;
+    Ok(render(&text, &opts))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_and_sanitizes() {
+        let text = "# Hi\n\n<script>alert(1)</script>\n\n[home](https://example.com) ~~old~~";
+        let html = to_html(text.into());
+        assert!(html.starts_with("<h1>Hi</h1>"), "{}", html);
+        assert!(!html.contains("script"), "{}", html);
+        assert!(
+            html.contains(r#"rel="nofollow noopener noreferrer""#),
+            "{}",
+            html
+        );
+        assert!(html.contains("~~old~~"), "{}", html);
+
+        let mut engine = rhai::Engine::new();
+        engine
+            .register_fn("markdown_to_html", to_html)
+            .register_result_fn("markdown_to_html", to_html_with);
+        let escaped: String = engine
+            .eval(r#"markdown_to_html("<b>x</b> ~~y~~", #{ html: "escape", strikethrough: true })"#)
+            .unwrap();
+        assert_eq!(escaped, "<p>&lt;b&gt;x&lt;/b&gt; <del>y</del></p>\n");
+        let trusted: String = engine
+            .eval(r#"markdown_to_html("<div onclick=\"go()\"></div>", #{ html: "trusted" })"#)
+            .unwrap();
+        assert!(trusted.contains("onclick"), "{}", trusted);
+        let tasks: String = engine
+            .eval(r#"markdown_to_html("- [x] done", #{ tasklists: true })"#)
+            .unwrap();
+        assert!(tasks.contains("checkbox"), "{}", tasks);
+        assert!(engine
+            .eval::<String>(r#"markdown_to_html("x", #{ html: "maybe" })"#)
+            .is_err());
+    }
+}
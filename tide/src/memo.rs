@@ -0,0 +1,96 @@
+//! Responses scripts marked memoizable, served again without running the
+//! script until they expire:
+//!
+//!```text
+//! let todos = load_todos();
+//! http::response(200, todos).memoize("todos", 60)
+//!```
+//!
+//! Only `GET` and `HEAD` requests are memoized, by their path and query, so
+//! the response is served to anyone requesting the same URL whatever their
+//! headers: responses depending on who asks should not be memoized. The key
+//! groups the responses a script can drop early with `forget_memo("todos")`,
+//! when what they show changed.
+
+use crate::http_utils::ScriptResponse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Responses kept at most, the expired ones are dropped first.
+const CAPACITY: usize = 1024;
+
+/// How long a response may be memoized, a day.
+pub(crate) const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Memoized {
+    key: String,
+    expires: Instant,
+    response: ScriptResponse,
+}
+
+#[derive(Default)]
+pub(crate) struct Memo {
+    /// By the path and query of the request.
+    responses: Mutex<HashMap<String, Memoized>>,
+}
+
+impl Memo {
+    /// The response memoized for `url`, if it has not expired.
+    pub(crate) fn get(&self, url: &str) -> Option<ScriptResponse> {
+        let mut responses = self.responses.lock().unwrap();
+        match responses.get(url) {
+            Some(m) if m.expires > Instant::now() => Some(m.response.clone()),
+            Some(_) => {
+                responses.remove(url);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Keeps `response` for `url` for `ttl`, unless the memo is full or
+    /// `ttl` is too long for the clock.
+    pub(crate) fn insert(&self, url: &str, key: &str, ttl: Duration, response: ScriptResponse) {
+        let Some(expires) = Instant::now().checked_add(ttl) else {
+            return;
+        };
+        let mut responses = self.responses.lock().unwrap();
+        if responses.len() >= CAPACITY && !responses.contains_key(url) {
+            let now = Instant::now();
+            responses.retain(|_, m| m.expires > now);
+            if responses.len() >= CAPACITY {
+                return;
+            }
+        }
+        let memoized = Memoized {
+            key: key.to_string(),
+            expires,
+            response,
+        };
+        responses.insert(url.to_string(), memoized);
+    }
+
+    /// Drops the responses memoized with `key`, returning how many there were.
+    pub(crate) fn forget(&self, key: &str) -> usize {
+        let mut responses = self.responses.lock().unwrap();
+        let before = responses.len();
+        responses.retain(|_, m| m.key != key);
+        before - responses.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn refuses_ttls_past_the_clock() {
+        let memo = Memo::default();
+        let response = || ScriptResponse::new(200, "hi".into()).unwrap();
+        memo.insert("/hi", "hi", Duration::MAX, response());
+        assert!(memo.get("/hi").is_none());
+        memo.insert("/hi", "hi", Duration::from_secs(60), response());
+        assert_eq!(memo.get("/hi").unwrap().status, 200);
+    }
+}
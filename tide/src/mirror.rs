@@ -0,0 +1,145 @@
+//! [`Mirror`], a middleware sending a copy of some of the requests to a
+//! shadow: another server, or an endpoint like a [`RhaiDir`](crate::RhaiDir)
+//! of the next version of the app. What the shadow answers is dropped, the
+//! client only ever gets the answer of the app.
+
+use std::sync::Arc;
+use tide::http::Url;
+use tide::{Endpoint, Middleware, Next, Request};
+
+/// Copies `percent` of the requests to a shadow, in the background.
+///```
+/// use tide_rhai::{Mirror, RhaiDir};
+/// let mut app = tide::new();
+/// let next = RhaiDir::new("/*", "./examples/app/").unwrap();
+/// app.with(Mirror::endpoint(next, 10.0));
+///```
+pub struct Mirror {
+    percent: f64,
+    shadow: Shadow,
+}
+
+#[derive(Clone)]
+enum Shadow {
+    Upstream(Url),
+    Endpoint(Arc<dyn Endpoint<()>>),
+}
+
+impl Mirror {
+    /// Sends the copies to the server at `url`, under the same path.
+    pub fn upstream(url: &str, percent: f64) -> Result<Self, url::ParseError> {
+        Ok(Self {
+            percent,
+            shadow: Shadow::Upstream(Url::parse(url)?),
+        })
+    }
+
+    /// Has `endpoint` answer the copies.
+    pub fn endpoint(endpoint: impl Endpoint<()>, percent: f64) -> Self {
+        Self {
+            percent,
+            shadow: Shadow::Endpoint(Arc::new(endpoint)),
+        }
+    }
+}
+
+/// Whether a request falls in `percent` of them, picked at random.
+pub(crate) fn sampled(percent: f64) -> bool {
+    let mut bytes = [0; 4];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return false;
+    }
+    (u32::from_le_bytes(bytes) as f64) < percent / 100.0 * u32::MAX as f64
+}
+
+impl Shadow {
+    async fn send(self, req: http_types::Request) {
+        let (what, res) = match self {
+            Shadow::Upstream(base) => {
+                let mut req = req;
+                let mut url = base.clone();
+                url.set_path(req.url().path());
+                url.set_query(req.url().query());
+                *req.url_mut() = url;
+                req.remove_header("host");
+                let res = surf::client().send(req).await.map(|r| r.status());
+                (base.to_string(), res.map_err(|e| e.to_string()))
+            }
+            Shadow::Endpoint(endpoint) => {
+                let res = endpoint.call(Request::from(req)).await;
+                (
+                    "the shadow endpoint".to_string(),
+                    res.map(|r| r.status()).map_err(|e| e.to_string()),
+                )
+            }
+        };
+        match res {
+            Ok(status) => log::debug!("Mirrored to {}: {}", what, status),
+            Err(e) => log::debug!("Mirroring to {} failed: {}", what, e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Mirror {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !sampled(self.percent) {
+            return Ok(next.run(req).await);
+        }
+        let body = req.body_bytes().await?;
+        req.set_body(body.clone());
+        let mut copy = http_types::Request::new(req.method(), req.url().clone());
+        for (name, values) in req.iter() {
+            for value in values {
+                copy.append_header(name.as_str(), value.as_str());
+            }
+        }
+        copy.set_body(body);
+        crate::rt::spawn(self.shadow.clone().send(copy));
+        Ok(next.run(req).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn mirrors() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let s = seen.clone();
+        let shadow = move |mut req: Request<()>| {
+            let s = s.clone();
+            async move {
+                assert_eq!(req.body_string().await?, "order");
+                s.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, tide::Error>("shadow")
+            }
+        };
+        let mut app = tide::new();
+        app.with(Mirror::endpoint(shadow, 100.0));
+        app.at("/*").post(|mut req: Request<()>| async move {
+            Ok::<_, tide::Error>(format!("app got {}", req.body_string().await?))
+        });
+
+        use tide_testing::TideTestingExt;
+        let got = app
+            .post("/orders")
+            .body("order")
+            .recv_string()
+            .await
+            .unwrap();
+        assert_eq!(got, "app got order");
+        for _ in 0..100 {
+            if seen.load(Ordering::SeqCst) == 1 {
+                break;
+            }
+            crate::rt::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+
+        assert!(!sampled(0.0));
+    }
+}
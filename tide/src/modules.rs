@@ -0,0 +1,304 @@
+//! `import "lib::helpers" as helpers;` for rhai scripts, loading
+//! `lib/helpers.rhai` of the app directory the way the JavaScript side loads
+//! its modules: relative to the importing script when the path starts with
+//! `./` or `../`, otherwise from the app directory, and never outside of it.
+//!
+//! Modules are compiled and evaluated once and shared by every script, until
+//! their file changes. Then the module is evaluated again with those
+//! importing it, as they hold what they took from it, while the other
+//! modules are kept as they are:
+//!
+//!```text
+//! DEBUG Module /srv/app/lib/math.rhai changed, evaluating it and 1 importing it again
+//!```
+
+use crate::integrity::{self, Integrity};
+use crate::snapshot::Snapshot;
+use rhai::{Engine, EvalAltResult, Module, ModuleResolver, Position, Scope, Shared};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// A module and when its file changed, `None` for modules of a snapshot.
+type Cached = (Option<SystemTime>, Shared<Module>);
+
+/// Modules evaluated so far, by their file.
+#[derive(Default)]
+pub(crate) struct ModuleCache {
+    modules: Mutex<Modules>,
+}
+
+impl ModuleCache {
+    pub(crate) fn len(&self) -> usize {
+        self.modules.lock().unwrap().cached.len()
+    }
+
+    /// Whether the module at `path` is kept, so an edit of its file is
+    /// evaluated again, with its importers, by the next script importing it.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.modules.lock().unwrap().cached.contains_key(&path)
+    }
+}
+
+#[derive(Default)]
+struct Modules {
+    cached: HashMap<PathBuf, Cached>,
+    /// The files every script and module imported, by its file.
+    imports: HashMap<PathBuf, HashSet<PathBuf>>,
+}
+
+impl Modules {
+    /// The module of `file` if neither its file nor those of the modules it
+    /// imports changed. The changed ones are dropped along with the modules
+    /// importing them.
+    fn fresh(&mut self, file: &Path) -> Option<Shared<Module>> {
+        let mut seen = HashSet::new();
+        let mut next = vec![file.to_path_buf()];
+        let mut changed = Vec::new();
+        while let Some(f) = next.pop() {
+            if !seen.insert(f.clone()) {
+                continue;
+            }
+            let Some((modified, _)) = self.cached.get(&f) else {
+                continue;
+            };
+            let unchanged = modified.map_or(true, |m| {
+                std::fs::metadata(&f).and_then(|f| f.modified()).ok() == Some(m)
+            });
+            if !unchanged {
+                changed.push(f.clone());
+            }
+            next.extend(self.imports.get(&f).into_iter().flatten().cloned());
+        }
+        for f in changed {
+            self.invalidate(&f);
+        }
+        self.cached.get(file).map(|(_, module)| module.clone())
+    }
+
+    /// Drops the module of `file` and every module importing it, by way of
+    /// others or not.
+    fn invalidate(&mut self, file: &Path) {
+        let mut seen = HashSet::new();
+        let mut next = vec![file.to_path_buf()];
+        let mut importers = 0;
+        while let Some(f) = next.pop() {
+            if !seen.insert(f.clone()) {
+                continue;
+            }
+            if self.cached.remove(&f).is_some() && f != file {
+                importers += 1;
+            }
+            next.extend(
+                self.imports
+                    .iter()
+                    .filter(|(_, imported)| imported.contains(&f))
+                    .map(|(importer, _)| importer.clone()),
+            );
+        }
+        log::debug!(
+            "Module {} changed, evaluating it and {} importing it again",
+            file.display(),
+            importers
+        );
+    }
+}
+
+/// The resolver installed into the engine of a [`RhaiDir`](crate::RhaiDir).
+pub(crate) struct AppModules {
+    pub root: PathBuf,
+    pub snapshot: Option<Arc<Snapshot>>,
+    /// What modules read from disk must hash to.
+    pub integrity: Option<Arc<Integrity>>,
+    pub cache: Arc<ModuleCache>,
+}
+
+impl AppModules {
+    fn modules(&self) -> MutexGuard<'_, Modules> {
+        self.cache.modules.lock().unwrap()
+    }
+
+    /// The file `path` names, imported by the script `source`, or `None` if
+    /// it climbs out of the app directory.
+    pub(crate) fn locate(&self, source: Option<&str>, path: &str) -> Option<PathBuf> {
+        let relative = path.starts_with("./") || path.starts_with("../");
+        let mut file = match source.map(Path::new).and_then(|s| s.parent()) {
+            Some(dir) if relative => dir.strip_prefix(&self.root).ok()?.to_path_buf(),
+            _ => PathBuf::new(),
+        };
+        for part in path.split("::").flat_map(|p| p.split('/')) {
+            match Path::new(part).components().next() {
+                None | Some(Component::CurDir) => {}
+                Some(Component::ParentDir) => {
+                    if !file.pop() {
+                        return None;
+                    }
+                }
+                Some(Component::Normal(_)) if !part.contains('\\') => file.push(part),
+                Some(_) => return None,
+            }
+        }
+        if file.extension().map_or(true, |e| e != "rhai") {
+            let mut name = file.into_os_string();
+            name.push(".rhai");
+            file = name.into();
+        }
+        Some(self.root.join(file))
+    }
+
+    /// The source of `file` and when it last changed, `None` for files of
+    /// the snapshot.
+    fn read(&self, file: &Path) -> Result<(String, Option<SystemTime>), String> {
+        if let Some(entry) = self.snapshot.as_ref().and_then(|s| s.get(&self.root, file)) {
+            return Ok((entry.code.to_string(), None));
+        }
+        let canonical = file.canonicalize().map_err(|e| e.to_string())?;
+        if !canonical.starts_with(&self.root) {
+            log::warn!("Unauthorized attempt to import: {:?}", canonical);
+            return Err("outside of the app directory".into());
+        }
+        let modified = std::fs::metadata(&canonical)
+            .and_then(|m| m.modified())
+            .ok();
+        let source =
+            integrity::read(self.integrity.as_deref(), &canonical).map_err(|e| e.to_string())?;
+        Ok((source, modified))
+    }
+}
+
+impl ModuleResolver for AppModules {
+    fn resolve(
+        &self,
+        engine: &Engine,
+        source: Option<&str>,
+        path: &str,
+        pos: Position,
+    ) -> Result<Shared<Module>, Box<EvalAltResult>> {
+        let not_found = || Box::new(EvalAltResult::ErrorModuleNotFound(path.to_string(), pos));
+        let file = self.locate(source, path).ok_or_else(not_found)?;
+        let mut modules = self.modules();
+        if let Some(source) = source {
+            let imports = modules.imports.entry(PathBuf::from(source)).or_default();
+            imports.insert(file.clone());
+        }
+        if let Some(module) = modules.fresh(&file) {
+            return Ok(module);
+        }
+        // Recorded again as the module is evaluated, it may have new ones.
+        modules.imports.remove(&file);
+        drop(modules);
+
+        let (code, modified) = self.read(&file).map_err(|e| {
+            log::warn!("Cannot import {}: {}", path, e);
+            not_found()
+        })?;
+        let in_module = |e: Box<EvalAltResult>| {
+            Box::new(EvalAltResult::ErrorInModule(path.to_string(), e, pos))
+        };
+        let mut ast = engine.compile(code).map_err(|e| in_module(e.into()))?;
+        ast.set_source(file.to_string_lossy().as_ref());
+        let module: Shared<Module> = Module::eval_ast_as_new(Scope::new(), &ast, engine)
+            .map_err(in_module)?
+            .into();
+        self.modules()
+            .cached
+            .insert(file, (modified, module.clone()));
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn imports() {
+        let root = std::env::temp_dir().join("tide_rhai_modules");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(
+            root.join("lib/helpers.rhai"),
+            "import \"./math\" as math;\nexport const ANSWER = math::double(21);\nfn greet(n) { `hi ${n}` }",
+        )
+        .unwrap();
+        std::fs::write(root.join("lib/math.rhai"), "fn double(n) { n * 2 }").unwrap();
+        let cache = Arc::new(ModuleCache::default());
+        let mut engine = Engine::new();
+        engine.set_module_resolver(AppModules {
+            root: root.clone(),
+            snapshot: None,
+            integrity: None,
+            cache: cache.clone(),
+        });
+
+        let out: String = engine
+            .eval(r#"import "lib::helpers" as h; h::greet("ann") + " " + h::ANSWER"#)
+            .unwrap();
+        assert_eq!(out, "hi ann 42");
+        assert_eq!(cache.len(), 2);
+
+        let e = engine
+            .run(r#"import "../../etc/passwd" as p;"#)
+            .unwrap_err();
+        assert!(
+            matches!(*e, EvalAltResult::ErrorModuleNotFound(..)),
+            "{}",
+            e
+        );
+        let e = engine.run(r#"import "lib::missing" as m;"#).unwrap_err();
+        assert!(
+            matches!(*e, EvalAltResult::ErrorModuleNotFound(..)),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn evaluates_changed_modules_and_their_importers() {
+        let root = std::env::temp_dir().join("tide_rhai_modules_changed");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(
+            root.join("lib/helpers.rhai"),
+            "import \"./math\" as math;\nexport const ANSWER = math::times(21);",
+        )
+        .unwrap();
+        std::fs::write(root.join("lib/math.rhai"), "fn times(n) { n * 2 }").unwrap();
+        std::fs::write(root.join("lib/other.rhai"), "export const NAME = \"ann\";").unwrap();
+        let cache = Arc::new(ModuleCache::default());
+        let mut engine = Engine::new();
+        engine.set_module_resolver(AppModules {
+            root: root.clone(),
+            snapshot: None,
+            integrity: None,
+            cache: cache.clone(),
+        });
+        let script =
+            r#"import "lib::helpers" as h; import "lib::other" as o; `${o::NAME} ${h::ANSWER}`"#;
+        let cached = |name: &str| {
+            let modules = cache.modules.lock().unwrap();
+            modules.cached.get(&root.join(name)).map(|(_, m)| m.clone())
+        };
+
+        assert_eq!(engine.eval::<String>(script).unwrap(), "ann 42");
+        let other = cached("lib/other.rhai").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(root.join("lib/math.rhai"), "fn times(n) { n * 3 }").unwrap();
+        assert_eq!(engine.eval::<String>(script).unwrap(), "ann 63");
+        assert_eq!(cache.len(), 3);
+        assert!(Shared::ptr_eq(&cached("lib/other.rhai").unwrap(), &other));
+
+        let helpers = cached("lib/helpers.rhai").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(root.join("lib/other.rhai"), "export const NAME = \"bob\";").unwrap();
+        assert_eq!(engine.eval::<String>(script).unwrap(), "bob 63");
+        assert!(Shared::ptr_eq(
+            &cached("lib/helpers.rhai").unwrap(),
+            &helpers
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! MessagePack, for APIs speaking it: [`decode`] and [`encode`] between bytes
+//! and a [`Value`] of any shape, and [`to_vec`] and [`from_slice`] for types
+//! with serde. Scripts get the same: `msgpack::decode(blob)` and
+//! `msgpack::encode(value)` in rhai, with `req.body_msgpack()` for request
+//! bodies, and a `msgpack` global with `decode` and `encode` in JavaScript
+//! handlers running on boa.
+//!
+//!```
+//! use tide_rhai::msgpack::{self, Value};
+//!
+//! let bytes = msgpack::encode(&Value::Array(vec![Value::from(1), Value::from("one")]));
+//! assert_eq!(bytes, b"\x92\x01\xa3one");
+//! assert_eq!(msgpack::from_slice::<(i64, String)>(&bytes).unwrap(), (1, "one".into()));
+//!```
+//!
+//! In rhai, strings that are not UTF-8 and binaries are blobs, integers past
+//! those of rhai floats, and map keys that are not strings are written as
+//! they print. Extensions are maps of their `type` and `data`, and stay maps
+//! when encoded again.
+//!
+//!```text
+//! fn post(req) {
+//!     let order = req.body_msgpack();
+//!     http::response(http::CREATED, msgpack::encode(#{ id: order.id }))
+//!         .header("content-type", "application/msgpack")
+//! }
+//!```
+
+use rhai::{Blob, Dynamic, EvalAltResult, Map, Module, Shared};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+pub use rmpv::Value;
+
+/// Parses `bytes` holding exactly one value.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let mut rest = bytes;
+    let value = rmpv::decode::read_value(&mut rest).map_err(|e| {
+        format!(
+            "Invalid MessagePack at byte {}: {}",
+            bytes.len() - rest.len(),
+            e
+        )
+    })?;
+    match rest.len() {
+        0 => Ok(value),
+        left => Err(format!("{} bytes are left after the value", left)),
+    }
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    rmpv::encode::write_value(&mut out, value).expect("Writing to a Vec cannot fail");
+    out
+}
+
+/// `value` in MessagePack, structs as maps by field name.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, String> {
+    rmp_serde::to_vec_named(value).map_err(|e| e.to_string())
+}
+
+/// A Rust value read from MessagePack, failing if it does not have the shape
+/// of `T`.
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+}
+
+/// `value` as a rhai value, see the [module](self).
+pub(crate) fn to_dynamic(value: Value) -> Dynamic {
+    match value {
+        Value::Nil => Dynamic::UNIT,
+        Value::Boolean(b) => b.into(),
+        Value::Integer(n) => match n.as_i64() {
+            Some(n) => n.into(),
+            None => n.as_f64().unwrap_or(f64::NAN).into(),
+        },
+        Value::F32(f) => (f as f64).into(),
+        Value::F64(f) => f.into(),
+        Value::String(s) => match String::from_utf8(s.into_bytes()) {
+            Ok(s) => s.into(),
+            Err(e) => Dynamic::from_blob(e.into_bytes()),
+        },
+        Value::Binary(bytes) => Dynamic::from_blob(bytes),
+        Value::Array(items) => Dynamic::from_array(items.into_iter().map(to_dynamic).collect()),
+        Value::Map(entries) => Dynamic::from_map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (key(k).into(), to_dynamic(v)))
+                .collect(),
+        ),
+        Value::Ext(kind, data) => {
+            let mut ext = Map::new();
+            ext.insert("type".into(), (kind as i64).into());
+            ext.insert("data".into(), Dynamic::from_blob(data));
+            Dynamic::from_map(ext)
+        }
+    }
+}
+
+/// A map key as a rhai map has them.
+fn key(key: Value) -> String {
+    match key {
+        Value::String(s) if s.is_str() => s.into_str().unwrap_or_default(),
+        other => other.to_string(),
+    }
+}
+
+/// The value of rhai written the way [`to_dynamic`] writes it, with
+/// characters as strings; custom types cannot be written.
+pub(crate) fn from_dynamic(value: Dynamic) -> Result<Value, String> {
+    if value.is_unit() {
+        return Ok(Value::Nil);
+    }
+    if let Ok(b) = value.as_bool() {
+        return Ok(Value::Boolean(b));
+    }
+    if let Ok(n) = value.as_int() {
+        return Ok(Value::from(n));
+    }
+    if let Ok(f) = value.as_float() {
+        return Ok(Value::F64(f));
+    }
+    if let Ok(c) = value.as_char() {
+        return Ok(Value::from(c.to_string()));
+    }
+    if value.is_string() {
+        let s = value.into_immutable_string().unwrap_or_default();
+        return Ok(Value::from(s.as_str()));
+    }
+    if value.is::<Blob>() {
+        return Ok(Value::Binary(value.cast::<Blob>()));
+    }
+    if value.is_array() {
+        return value
+            .cast::<rhai::Array>()
+            .into_iter()
+            .map(from_dynamic)
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    if value.is_map() {
+        return value
+            .cast::<Map>()
+            .into_iter()
+            .map(|(k, v)| Ok((Value::from(k.as_str()), from_dynamic(v)?)))
+            .collect::<Result<_, String>>()
+            .map(Value::Map);
+    }
+    Err(format!("MessagePack cannot hold {}", value.type_name()))
+}
+
+/// The `msgpack` module of rhai scripts, see the [module](self).
+pub(crate) fn module() -> Shared<Module> {
+    static MODULE: OnceLock<Shared<Module>> = OnceLock::new();
+    MODULE.get_or_init(|| build().into()).clone()
+}
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+fn build() -> Module {
+    let mut m = Module::new();
+    m.set_native_fn("decode", |bytes: Blob| -> Out<Dynamic> {
+        Ok(to_dynamic(decode(&bytes)?))
+    });
+    m.set_native_fn("encode", |value: Dynamic| -> Out<Blob> {
+        Ok(encode(&from_dynamic(value)?))
+    });
+    m
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+    }
+
+    #[test]
+    fn values_and_scripts() {
+        let order = Order {
+            id: 7,
+            items: vec!["shoe".into()],
+        };
+        let bytes = to_vec(&order).unwrap();
+        assert_eq!(from_slice::<Order>(&bytes).unwrap(), order);
+        let value = decode(&bytes).unwrap();
+        assert_eq!(value.as_map().map(|m| m[0].1.clone()), Some(Value::from(7)));
+        assert_eq!(encode(&value), bytes);
+        assert!(decode(&[0x92, 0x01]).is_err());
+        assert!(decode(&[0x01, 0x02]).is_err());
+
+        let mut engine = rhai::Engine::new();
+        engine.register_static_module("msgpack", module());
+        let blob = Dynamic::from_blob(bytes);
+        let mut scope = rhai::Scope::new();
+        scope.push("bytes", blob);
+        let items: String = engine
+            .eval_with_scope(&mut scope, "msgpack::decode(bytes).items[0]")
+            .unwrap();
+        assert_eq!(items, "shoe");
+        let encoded: Blob = engine
+            .eval_with_scope(&mut scope, "msgpack::encode([1, 1.5, (), true, \"x\"])")
+            .unwrap();
+        assert_eq!(
+            encoded,
+            b"\x95\x01\xcb\x3f\xf8\x00\x00\x00\x00\x00\x00\xc0\xc3\xa1x"
+        );
+        let ext = to_dynamic(Value::Ext(3, vec![1]));
+        assert_eq!(ext.cast::<Map>()["type"].as_int(), Ok(3));
+    }
+}
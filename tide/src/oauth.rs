@@ -0,0 +1,393 @@
+//! `oauth`, the authorization code flow of the OAuth2 providers of the
+//! config, for scripts signing users in:
+//!
+//!```text
+//! // login.rhai
+//! oauth.begin("github")
+//!
+//! // callback.rhai, the redirect_uri of the provider
+//! let token = oauth.callback(req);
+//! // token.access_token, token.refresh_token, token.expires_in, ...
+//!
+//! // later
+//! let token = oauth.refresh("github", token.refresh_token);
+//!```
+//!
+//! `begin` redirects to the provider with a random `state` and a PKCE
+//! challenge, kept in a cookie of the browser, so the flow survives reloads
+//! and works across processes. `callback` checks the state before it
+//! exchanges the code, and the response of its script clears the cookie
+//! once it did. The cookie is `Secure` if the `redirect_uri` is https.
+
+use crate::error_page::HttpError;
+use crate::http_utils::{redirect, ScriptResponse};
+use crate::request::ScriptRequest;
+use crate::rt;
+use crate::to_script_value;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use rhai::{Dynamic, EvalAltResult, ImmutableString};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// A provider scripts sign users in with, by the name of its section.
+#[derive(Deserialize, Debug, Clone)]
+pub struct OAuthProvider {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where users are sent to grant access.
+    pub authorize_url: String,
+    /// Where codes and refresh tokens are exchanged for access tokens.
+    pub token_url: String,
+    /// Where the provider sends users back with a code, the URL of the
+    /// script calling `oauth.callback`.
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Sends a PKCE challenge along, for the providers that check it.
+    #[serde(default = "default_pkce")]
+    pub pkce: bool,
+}
+
+fn default_pkce() -> bool {
+    true
+}
+
+/// Seconds a user has to come back from the provider.
+const STATE_MAX_AGE: u32 = 600;
+
+#[derive(Debug, Clone)]
+pub(crate) struct OAuth {
+    providers: Arc<HashMap<String, OAuthProvider>>,
+    /// The `set-cookie` headers clearing the state of the flows the request
+    /// finished.
+    finished: Arc<Mutex<Vec<String>>>,
+}
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+/// `len` random bytes, as URL safe base64.
+pub(crate) fn random_token(len: usize) -> std::io::Result<String> {
+    let mut bytes = vec![0; len];
+    getrandom::getrandom(&mut bytes).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, format!("No randomness: {}", e))
+    })?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn random(len: usize) -> Out<String> {
+    random_token(len).map_err(|e| e.to_string().into())
+}
+
+fn bad_request(message: &str) -> Box<EvalAltResult> {
+    match HttpError::new(400, message) {
+        Ok(e) => EvalAltResult::ErrorRuntime(Dynamic::from(e), rhai::Position::NONE).into(),
+        Err(e) => e.into(),
+    }
+}
+
+fn cookie_name(provider: &str) -> String {
+    format!("oauth_{}", provider)
+}
+
+/// The `set-cookie` header keeping `value` as the state of the flow with
+/// the provider `name` for `max_age` seconds.
+fn set_cookie(provider: &OAuthProvider, name: &str, value: &str, max_age: u32) -> String {
+    let secure = match Url::parse(&provider.redirect_uri) {
+        Ok(url) if url.scheme() == "https" => "; Secure",
+        _ => "",
+    };
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        cookie_name(name),
+        value,
+        max_age,
+        secure
+    )
+}
+
+/// The cookie `name` of `req`.
+fn cookie(req: &ScriptRequest, name: &str) -> Option<String> {
+    req.header_value("cookie")?
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| v.to_string())
+}
+
+fn query(req: &ScriptRequest, name: &str) -> Option<String> {
+    req.url()
+        .query_pairs()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.into_owned())
+}
+
+impl OAuth {
+    pub(crate) fn new(providers: HashMap<String, OAuthProvider>) -> Self {
+        Self {
+            providers: Arc::new(providers),
+            finished: Arc::default(),
+        }
+    }
+
+    /// The `oauth` of a request, with none of its flows finished.
+    pub(crate) fn for_request(&self) -> Self {
+        Self {
+            providers: self.providers.clone(),
+            finished: Arc::default(),
+        }
+    }
+
+    /// The `set-cookie` headers for the response of the request, clearing
+    /// the state of the flows it finished.
+    pub(crate) fn finished(&self) -> Vec<String> {
+        std::mem::take(&mut *self.finished.lock().unwrap())
+    }
+
+    fn provider(&self, name: &str) -> Out<&OAuthProvider> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| format!("No OAuth provider {}", name).into())
+    }
+
+    /// `oauth.begin(provider)`, the redirect to the provider.
+    pub(crate) fn begin(&mut self, name: ImmutableString) -> Out<ScriptResponse> {
+        let provider = self.provider(&name)?;
+        let state = random(16)?;
+        let verifier = random(32)?;
+        let mut url = Url::parse(&provider.authorize_url)
+            .map_err(|e| format!("Invalid authorize_url {}: {}", provider.authorize_url, e))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &provider.client_id)
+                .append_pair("redirect_uri", &provider.redirect_uri)
+                .append_pair("state", &format!("{}.{}", name, state));
+            if !provider.scopes.is_empty() {
+                query.append_pair("scope", &provider.scopes.join(" "));
+            }
+            if provider.pkce {
+                let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+                query
+                    .append_pair("code_challenge", &challenge)
+                    .append_pair("code_challenge_method", "S256");
+            }
+        }
+        let kept = format!("{}.{}", state, verifier);
+        let cookie = set_cookie(provider, &name, &kept, STATE_MAX_AGE);
+        Ok(redirect(url.as_str().into(), 302)?.header("set-cookie".into(), cookie.into()))
+    }
+
+    /// `oauth.callback(req)`, the token of the code `req` came back with,
+    /// once its state checks out. Throws a 400 otherwise.
+    pub(crate) fn callback(&mut self, req: ScriptRequest) -> Out<Dynamic> {
+        if let Some(error) = query(&req, "error") {
+            return Err(bad_request(&format!("The provider refused: {}", error)));
+        }
+        let (Some(state), Some(code)) = (query(&req, "state"), query(&req, "code")) else {
+            return Err(bad_request("No OAuth state or code"));
+        };
+        // The state is URL safe base64, without a dot, unlike the name.
+        let Some((name, state)) = state.rsplit_once('.') else {
+            return Err(bad_request("Invalid OAuth state"));
+        };
+        let provider = self.provider(name)?;
+        let kept = cookie(&req, &cookie_name(name)).unwrap_or_default();
+        let Some((kept_state, verifier)) = kept.split_once('.') else {
+            return Err(bad_request("The OAuth flow expired, start again"));
+        };
+        if kept_state != state {
+            return Err(bad_request("Invalid OAuth state"));
+        }
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+        ];
+        if provider.pkce {
+            form.push(("code_verifier", verifier));
+        }
+        let mut token = self.token(provider, &form)?;
+        let cleared = set_cookie(provider, name, "", 0);
+        self.finished.lock().unwrap().push(cleared);
+        if let Some(map) = token.write_lock::<rhai::Map>().as_deref_mut() {
+            map.insert("provider".into(), name.into());
+        }
+        Ok(token)
+    }
+
+    /// `oauth.refresh(provider, refresh_token)`, a new token.
+    pub(crate) fn refresh(
+        &mut self,
+        name: ImmutableString,
+        token: ImmutableString,
+    ) -> Out<Dynamic> {
+        let provider = self.provider(&name)?;
+        self.token(
+            provider,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", token.as_str()),
+            ],
+        )
+    }
+
+    /// Posts `form` to the token endpoint of `provider`.
+    fn token(&self, provider: &OAuthProvider, form: &[(&str, &str)]) -> Out<Dynamic> {
+        let mut body = url::form_urlencoded::Serializer::new(String::new());
+        body.extend_pairs(form)
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("client_secret", &provider.client_secret);
+        let url = Url::parse(&provider.token_url)
+            .map_err(|e| format!("Invalid token_url {}: {}", provider.token_url, e))?;
+        let req = surf::RequestBuilder::new(http_types::Method::Post, url)
+            .header("accept", "application/json")
+            .content_type(http_types::mime::FORM)
+            .body(body.finish());
+        let answered = rt::block_on(async {
+            let mut res = req.await?;
+            let status = res.status();
+            let json: serde_json::Value = res.body_json().await?;
+            Ok::<_, surf::Error>((status, json))
+        });
+        let (status, json) = answered.map_err(|e| {
+            log::error!(
+                "OAuth token request to {} failed: {}",
+                provider.token_url,
+                e
+            );
+            "OAuth token request failed"
+        })?;
+        if !status.is_success() || json.get("error").is_some() {
+            log::warn!("OAuth token refused by {}: {}", provider.token_url, json);
+            return Err(bad_request("The provider refused the code"));
+        }
+        to_script_value(&json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thrown_http_error;
+
+    fn github() -> OAuth {
+        OAuth::new(HashMap::from([("github".to_string(), provider())]))
+    }
+
+    fn provider() -> OAuthProvider {
+        OAuthProvider {
+            client_id: "id".into(),
+            client_secret: "secret".into(),
+            authorize_url: "https://github.com/login/oauth/authorize".into(),
+            token_url: "https://github.com/login/oauth/access_token".into(),
+            redirect_uri: "http://localhost:8080/callback.rhai".into(),
+            scopes: vec!["read:user".into(), "user:email".into()],
+            pkce: true,
+        }
+    }
+
+    /// The `name=value` of the `set-cookie` of `begin`, and its attributes.
+    fn begin(oauth: &mut OAuth, name: &str) -> (String, String) {
+        let res = oauth.begin(name.into()).unwrap().into_response().unwrap();
+        let set_cookie = res.header("set-cookie").unwrap().as_str();
+        let (cookie, attributes) = set_cookie.split_once(';').unwrap();
+        (cookie.to_string(), attributes.to_string())
+    }
+
+    fn callback(url: &str, cookie: &str) -> ScriptRequest {
+        ScriptRequest::new(
+            http_types::Method::Get,
+            Url::parse(url).unwrap(),
+            vec![("cookie".into(), cookie.into())],
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn begins_and_checks_state() {
+        let mut oauth = github();
+        let res = oauth
+            .begin("github".into())
+            .unwrap()
+            .into_response()
+            .unwrap();
+        assert_eq!(res.status(), 302);
+        let location = Url::parse(res.header("location").unwrap().as_str()).unwrap();
+        let query: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], "id");
+        assert_eq!(query["scope"], "read:user user:email");
+        assert_eq!(query["code_challenge_method"], "S256");
+        let set_cookie = res.header("set-cookie").unwrap().as_str();
+        let kept = set_cookie
+            .strip_prefix("oauth_github=")
+            .and_then(|c| c.split(';').next())
+            .unwrap();
+        let (state, verifier) = kept.split_once('.').unwrap();
+        assert_eq!(query["state"], format!("github.{}", state));
+        assert_eq!(
+            query["code_challenge"],
+            URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+        );
+
+        let url = format!(
+            "http://localhost/callback.rhai?code=c&state=github.{}",
+            state
+        );
+        for cookie in ["", "oauth_github=other.v"] {
+            let e = oauth.callback(callback(&url, cookie)).unwrap_err();
+            assert_eq!(thrown_http_error(&e).unwrap().status, 400);
+        }
+        let e = oauth
+            .callback(callback(
+                "http://localhost/callback.rhai?error=access_denied",
+                "",
+            ))
+            .unwrap_err();
+        assert_eq!(thrown_http_error(&e).unwrap().status, 400);
+        assert!(oauth.begin("gitlab".into()).is_err());
+    }
+
+    #[test]
+    fn clears_the_state_once_exchanged() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut server = tide::new();
+        server.at("/token").post(|_| async {
+            Ok(serde_json::json!({ "access_token": "t", "token_type": "bearer" }))
+        });
+        rt::spawn(server.listen(listener));
+
+        let provider = OAuthProvider {
+            token_url: format!("http://127.0.0.1:{}/token", port),
+            redirect_uri: "https://app.example/callback.rhai".into(),
+            ..provider()
+        };
+        let mut oauth = OAuth::new(HashMap::from([("corp.sso".to_string(), provider)]));
+        let (cookie, attributes) = begin(&mut oauth, "corp.sso");
+        assert!(attributes.ends_with("; Secure"), "{}", attributes);
+        let (_, kept) = cookie.split_once('=').unwrap();
+        let (state, _) = kept.split_once('.').unwrap();
+        let url = format!(
+            "https://app.example/callback.rhai?code=c&state=corp.sso.{}",
+            state
+        );
+
+        let expired = oauth.callback(callback(&url, "")).unwrap_err();
+        assert_eq!(thrown_http_error(&expired).unwrap().status, 400);
+        assert!(oauth.finished().is_empty());
+        let token = oauth.callback(callback(&url, &cookie)).unwrap();
+        let token = token.cast::<rhai::Map>();
+        assert_eq!(token["access_token"].to_string(), "t");
+        assert_eq!(token["provider"].to_string(), "corp.sso");
+        assert_eq!(
+            oauth.finished(),
+            ["oauth_corp.sso=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax; Secure"]
+        );
+        assert!(oauth.for_request().finished().is_empty());
+    }
+}
@@ -0,0 +1,619 @@
+//! [`Oidc`], a middleware signing users in with an OpenID Connect provider
+//! before anything is served, as its relying party:
+//!
+//! - the endpoints of the provider come from its discovery document, and its
+//!   signing keys from its JWKS, kept for an hour and fetched again for a key
+//!   they do not have;
+//! - requests without a session are sent to the provider with a `state`, a
+//!   `nonce` and a PKCE challenge, kept in a cookie until it answers;
+//! - the provider answers at the path of `redirect_uri`, where the code is
+//!   exchanged and the ID token checked: signature (RS256, RS384, RS512 or
+//!   ES256), issuer, audience, expiry and nonce;
+//! - its claims are kept in a signed session cookie, and handed to the
+//!   handlers as the [`Claims`] of the request. Rhai scripts get them as
+//!   `claims`.
+
+use crate::oauth::random_token;
+use async_std::sync::Mutex;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use rsa::signature::Verifier;
+use rsa::{BigUint, RsaPublicKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tide::http::Url;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+/// Settings of the provider and of the sessions.
+#[derive(Deserialize, Debug, Clone)]
+pub struct OidcConfig {
+    /// The provider, whose discovery document is at
+    /// `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where the provider sends users back, served by the middleware.
+    pub redirect_uri: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// Signs the session cookies, at least 32 bytes.
+    pub session_secret: String,
+    /// Seconds a user stays signed in.
+    #[serde(default = "default_session_max_age")]
+    pub session_max_age: u64,
+    /// Path prefixes served without signing in, like `/health`.
+    #[serde(default)]
+    pub public: Vec<String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".into(), "email".into(), "profile".into()]
+}
+
+fn default_session_max_age() -> u64 {
+    8 * 3600
+}
+
+/// The claims of the ID token of the signed in user, set on the requests
+/// [`Oidc`] let through.
+#[derive(Debug, Clone)]
+pub struct Claims(pub Value);
+
+const SESSION_COOKIE: &str = "oidc_session";
+const FLOW_COOKIE: &str = "oidc_flow";
+/// Seconds a user has to come back from the provider.
+const FLOW_MAX_AGE: u64 = 600;
+const KEYS_MAX_AGE: Duration = Duration::from_secs(3600);
+/// Least time between two fetches of the keys, for tokens of unknown keys.
+const KEYS_MIN_AGE: Duration = Duration::from_secs(60);
+/// Seconds of clock difference allowed with the provider.
+const LEEWAY: u64 = 60;
+
+#[derive(Deserialize, Debug, Clone)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+enum Key {
+    Rsa(RsaPublicKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+struct Keys {
+    keys: Vec<(Option<String>, Key)>,
+    fetched: Instant,
+}
+
+/// Requires users to sign in with the provider of its config, see the
+/// [module](self).
+///```no_run
+/// use tide_rhai::{Oidc, OidcConfig};
+/// let mut app = tide::new();
+/// app.with(Oidc::new(OidcConfig {
+///     issuer: "https://accounts.google.com".into(),
+///     client_id: "...".into(),
+///     client_secret: "...".into(),
+///     redirect_uri: "https://app.example.com/oidc/callback".into(),
+///     scopes: vec!["openid".into(), "email".into()],
+///     session_secret: std::env::var("SESSION_SECRET").unwrap(),
+///     session_max_age: 8 * 3600,
+///     public: vec!["/health".into()],
+/// }).unwrap());
+///```
+pub struct Oidc {
+    config: OidcConfig,
+    callback_path: String,
+    secure: bool,
+    metadata: Mutex<Option<Arc<Metadata>>>,
+    keys: Mutex<Option<Arc<Keys>>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|e| format!("Invalid base64: {}", e))
+}
+
+fn mac(secret: &str, data: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac
+}
+
+/// The session cookie keeping `claims` until `expires`.
+fn seal(secret: &str, claims: &Value, expires: u64) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(json!({"claims": claims, "exp": expires}).to_string());
+    let signature = URL_SAFE_NO_PAD.encode(mac(secret, payload.as_bytes()).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+/// The claims of a session cookie, if it is signed with `secret` and not
+/// expired at `now`.
+fn open(secret: &str, cookie: &str, now: u64) -> Option<Value> {
+    let (payload, signature) = cookie.split_once('.')?;
+    mac(secret, payload.as_bytes())
+        .verify_slice(&decode(signature).ok()?)
+        .ok()?;
+    let mut session: Value = serde_json::from_slice(&decode(payload).ok()?).ok()?;
+    if session["exp"].as_u64()? <= now {
+        return None;
+    }
+    Some(session["claims"].take())
+}
+
+fn parse_key(jwk: &Value) -> Option<Key> {
+    let field = |name: &str| jwk.get(name).and_then(Value::as_str).map(decode)?.ok();
+    match jwk.get("kty")?.as_str()? {
+        "RSA" => {
+            let n = BigUint::from_bytes_be(&field("n")?);
+            let e = BigUint::from_bytes_be(&field("e")?);
+            RsaPublicKey::new(n, e).ok().map(Key::Rsa)
+        }
+        "EC" if jwk.get("crv")?.as_str()? == "P-256" => {
+            let mut point = vec![4];
+            point.extend(field("x")?);
+            point.extend(field("y")?);
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                .ok()
+                .map(Key::P256)
+        }
+        _ => None,
+    }
+}
+
+/// The signing keys of a JWKS, leaving out those of other kinds.
+fn parse_jwks(jwks: &Value) -> Vec<(Option<String>, Key)> {
+    let keys = jwks["keys"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    keys.iter()
+        .filter(|jwk| jwk.get("use").and_then(Value::as_str).unwrap_or("sig") == "sig")
+        .filter_map(|jwk| {
+            let kid = jwk.get("kid").and_then(Value::as_str).map(String::from);
+            Some((kid, parse_key(jwk)?))
+        })
+        .collect()
+}
+
+fn rsa_verify<D>(key: &RsaPublicKey, data: &[u8], signature: &[u8]) -> bool
+where
+    D: Digest + rsa::pkcs8::AssociatedOid,
+{
+    let key = rsa::pkcs1v15::VerifyingKey::<D>::new(key.clone());
+    rsa::pkcs1v15::Signature::try_from(signature)
+        .map_or(false, |signature| key.verify(data, &signature).is_ok())
+}
+
+/// The algorithm and key id of the header of a JWT.
+fn jwt_header(token: &str) -> Result<(String, Option<String>), String> {
+    let header = token.split('.').next().unwrap_or_default();
+    let header: Value = serde_json::from_slice(&decode(header)?)
+        .map_err(|e| format!("Invalid JWT header: {}", e))?;
+    let alg = header["alg"].as_str().ok_or("The JWT has no alg")?;
+    Ok((alg.to_string(), header["kid"].as_str().map(String::from)))
+}
+
+/// The claims of `token`, if `key` signed it with `alg`.
+fn verify_jwt(token: &str, alg: &str, key: &Key) -> Result<Value, String> {
+    let parts: Vec<_> = token.split('.').collect();
+    let [header, payload, signature] = parts[..] else {
+        return Err("A JWT has three parts".into());
+    };
+    let data = format!("{}.{}", header, payload);
+    let signature = decode(signature)?;
+    let valid = match (alg, key) {
+        ("RS256", Key::Rsa(k)) => rsa_verify::<Sha256>(k, data.as_bytes(), &signature),
+        ("RS384", Key::Rsa(k)) => rsa_verify::<Sha384>(k, data.as_bytes(), &signature),
+        ("RS512", Key::Rsa(k)) => rsa_verify::<Sha512>(k, data.as_bytes(), &signature),
+        ("ES256", Key::P256(k)) => p256::ecdsa::Signature::from_slice(&signature)
+            .map_or(false, |s| k.verify(data.as_bytes(), &s).is_ok()),
+        _ => return Err(format!("The key cannot check {} signatures", alg)),
+    };
+    if !valid {
+        return Err("Invalid JWT signature".into());
+    }
+    serde_json::from_slice(&decode(payload)?).map_err(|e| format!("Invalid JWT claims: {}", e))
+}
+
+/// Checks the claims of an ID token of `issuer` for `client_id`.
+fn check_claims(
+    claims: &Value,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: u64,
+) -> Result<(), String> {
+    if claims["iss"].as_str() != Some(issuer) {
+        return Err(format!("The ID token is not from {}", issuer));
+    }
+    let audience = match &claims["aud"] {
+        Value::String(aud) => aud == client_id,
+        Value::Array(aud) => aud.iter().any(|a| a.as_str() == Some(client_id)),
+        _ => false,
+    };
+    if !audience {
+        return Err("The ID token is for another client".into());
+    }
+    match claims["exp"].as_u64() {
+        Some(exp) if exp + LEEWAY > now => {}
+        _ => return Err("The ID token expired".into()),
+    }
+    if claims["nonce"].as_str() != Some(nonce) {
+        return Err("The ID token is for another sign in".into());
+    }
+    Ok(())
+}
+
+/// A path of this site to send users back to, nothing else.
+fn local_path(path: &str) -> &str {
+    if path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\") {
+        path
+    } else {
+        "/"
+    }
+}
+
+async fn get_json(url: &str) -> Result<Value, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    let mut res = surf::get(url.clone())
+        .await
+        .map_err(|e| format!("{}: {}", url, e))?;
+    if !res.status().is_success() {
+        return Err(format!("{} answered {}", url, res.status()));
+    }
+    res.body_json().await.map_err(|e| format!("{}: {}", url, e))
+}
+
+impl Oidc {
+    /// Fails if `redirect_uri` is not a URL. The provider is only reached
+    /// by the first request.
+    pub fn new(config: OidcConfig) -> Result<Self, url::ParseError> {
+        let redirect = Url::parse(&config.redirect_uri)?;
+        if config.session_secret.len() < 32 {
+            log::warn!("The OIDC session_secret should have at least 32 bytes");
+        }
+        Ok(Self {
+            callback_path: redirect.path().to_string(),
+            secure: redirect.scheme() == "https",
+            config,
+            metadata: Mutex::new(None),
+            keys: Mutex::new(None),
+        })
+    }
+
+    async fn metadata(&self) -> Result<Arc<Metadata>, String> {
+        let mut metadata = self.metadata.lock().await;
+        if let Some(m) = &*metadata {
+            return Ok(m.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let found: Metadata = serde_json::from_value(get_json(&url).await?)
+            .map_err(|e| format!("Invalid discovery document {}: {}", url, e))?;
+        if found.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            return Err(format!(
+                "{} is the discovery document of {}",
+                url, found.issuer
+            ));
+        }
+        let found = Arc::new(found);
+        *metadata = Some(found.clone());
+        Ok(found)
+    }
+
+    /// The keys of the provider, fetched again if they are old or, once a
+    /// minute at most, if they have no `kid`.
+    async fn keys(&self, kid: Option<&str>) -> Result<Arc<Keys>, String> {
+        let mut keys = self.keys.lock().await;
+        if let Some(k) = &*keys {
+            let age = k.fetched.elapsed();
+            let known = k.keys.iter().any(|(id, _)| id.as_deref() == kid);
+            if age < KEYS_MAX_AGE && (known || age < KEYS_MIN_AGE) {
+                return Ok(k.clone());
+            }
+        }
+        let metadata = self.metadata().await?;
+        let fetched = Arc::new(Keys {
+            keys: parse_jwks(&get_json(&metadata.jwks_uri).await?),
+            fetched: Instant::now(),
+        });
+        *keys = Some(fetched.clone());
+        Ok(fetched)
+    }
+
+    /// The claims of `token`, once it checks out.
+    async fn validate(&self, token: &str, nonce: &str) -> Result<Value, String> {
+        let (alg, kid) = jwt_header(token)?;
+        let keys = self.keys(kid.as_deref()).await?;
+        let key = keys
+            .keys
+            .iter()
+            .find(|(id, _)| kid.is_none() || id.as_deref() == kid.as_deref())
+            .map(|(_, key)| key)
+            .ok_or_else(|| format!("The provider has no key {:?}", kid))?;
+        let claims = verify_jwt(token, &alg, key)?;
+        let issuer = self.metadata().await?.issuer.clone();
+        check_claims(&claims, &issuer, &self.config.client_id, nonce, now())?;
+        Ok(claims)
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: u64) -> String {
+        let secure = if self.secure { "; Secure" } else { "" };
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+            name, value, max_age, secure
+        )
+    }
+
+    /// The redirect to the provider, coming back to where `req` was going.
+    async fn begin<State>(&self, req: &Request<State>) -> tide::Result {
+        let metadata = self.metadata().await.map_err(unavailable)?;
+        let (state, nonce, verifier) = (random_token(16)?, random_token(16)?, random_token(32)?);
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+        let back = match req.url().query() {
+            Some(query) => format!("{}?{}", req.url().path(), query),
+            None => req.url().path().to_string(),
+        };
+        let flow = [state, nonce, verifier, URL_SAFE_NO_PAD.encode(back)].join(".");
+        let mut res = Response::new(StatusCode::Found);
+        res.insert_header("location", url.as_str());
+        res.append_header("set-cookie", self.cookie(FLOW_COOKIE, &flow, FLOW_MAX_AGE));
+        Ok(res)
+    }
+
+    /// Signs in the user the provider sent back with `req`.
+    async fn callback<State>(&self, req: &Request<State>) -> tide::Result {
+        let query: std::collections::HashMap<String, String> =
+            req.url().query_pairs().into_owned().collect();
+        let flow = req.cookie(FLOW_COOKIE).map(|c| c.value().to_string());
+        let signed_in = match (flow, query.get("state"), query.get("code")) {
+            _ if query.contains_key("error") => {
+                Err(format!("The provider refused: {}", query["error"]))
+            }
+            (Some(flow), Some(state), Some(code)) => self.sign_in(&flow, state, code).await,
+            _ => Err("No sign in is going on".into()),
+        };
+        let (claims, back) = match signed_in {
+            Ok(signed_in) => signed_in,
+            Err(e) => {
+                log::warn!("OIDC sign in failed: {}", e);
+                return Ok(Response::builder(StatusCode::Unauthorized)
+                    .body("Signing in failed")
+                    .build());
+            }
+        };
+        let max_age = self.config.session_max_age;
+        let session = seal(&self.config.session_secret, &claims, now() + max_age);
+        let mut res = Response::new(StatusCode::Found);
+        res.insert_header("location", local_path(&back));
+        res.append_header("set-cookie", self.cookie(SESSION_COOKIE, &session, max_age));
+        res.append_header("set-cookie", self.cookie(FLOW_COOKIE, "", 0));
+        Ok(res)
+    }
+
+    /// The claims of the user and where they were going.
+    async fn sign_in(
+        &self,
+        flow: &str,
+        state: &str,
+        code: &str,
+    ) -> Result<(Value, String), String> {
+        let parts: Vec<_> = flow.split('.').collect();
+        let [kept_state, nonce, verifier, back] = parts[..] else {
+            return Err("Invalid flow cookie".into());
+        };
+        if kept_state != state {
+            return Err("The state does not match".into());
+        }
+        let back = String::from_utf8(decode(back)?).map_err(|e| e.to_string())?;
+        let metadata = self.metadata().await?;
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "authorization_code")
+            .append_pair("code", code)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("client_secret", &self.config.client_secret)
+            .append_pair("code_verifier", verifier);
+        let url = Url::parse(&metadata.token_endpoint).map_err(|e| e.to_string())?;
+        let mut res = surf::post(url)
+            .header("accept", "application/json")
+            .content_type(http_types::mime::FORM)
+            .body(form.finish())
+            .await
+            .map_err(|e| format!("Token request failed: {}", e))?;
+        let tokens: Value = res
+            .body_json()
+            .await
+            .map_err(|e| format!("Invalid token response: {}", e))?;
+        let id_token = tokens["id_token"]
+            .as_str()
+            .ok_or_else(|| format!("No ID token in {}", tokens))?;
+        Ok((self.validate(id_token, nonce).await?, back))
+    }
+}
+
+fn unavailable(e: String) -> tide::Error {
+    log::error!("OIDC provider unavailable: {}", e);
+    tide::Error::from_str(StatusCode::ServiceUnavailable, "Signing in is unavailable")
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Oidc {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let path = req.url().path();
+        if path == self.callback_path {
+            return self.callback(&req).await;
+        }
+        if self
+            .config
+            .public
+            .iter()
+            .any(|p| path.starts_with(p.as_str()))
+        {
+            return Ok(next.run(req).await);
+        }
+        let session = req.cookie(SESSION_COOKIE);
+        let claims = session.and_then(|c| open(&self.config.session_secret, c.value(), now()));
+        if let Some(claims) = claims {
+            req.set_ext(Claims(claims));
+            return Ok(next.run(req).await);
+        }
+        if req.method() != http_types::Method::Get {
+            return Ok(Response::new(StatusCode::Unauthorized));
+        }
+        self.begin(&req).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use tide_testing::TideTestingExt;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn config() -> OidcConfig {
+        OidcConfig {
+            issuer: "https://id.example.com".into(),
+            client_id: "app".into(),
+            client_secret: "secret".into(),
+            redirect_uri: "http://localhost/oidc/callback".into(),
+            scopes: default_scopes(),
+            session_secret: SECRET.into(),
+            session_max_age: 3600,
+            public: vec!["/health".into()],
+        }
+    }
+
+    fn signed(key: &p256::ecdsa::SigningKey, claims: &Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "ES256", "kid": "k1"}).to_string());
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let data = format!("{}.{}", header, payload);
+        let signature: p256::ecdsa::Signature = key.sign(data.as_bytes());
+        format!("{}.{}", data, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    #[test]
+    fn id_tokens() {
+        let key = p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let jwks = json!({"keys": [
+            {"kty": "oct", "k": "c2VjcmV0"},
+            {
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": "k1",
+                "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+                "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+            },
+        ]});
+        let keys = parse_jwks(&jwks);
+        assert_eq!(keys.len(), 1);
+        let claims = json!({
+            "iss": "https://id.example.com",
+            "aud": ["app"],
+            "sub": "u1",
+            "exp": 2000,
+            "nonce": "n",
+        });
+        let token = signed(&key, &claims);
+        assert_eq!(
+            jwt_header(&token).unwrap(),
+            ("ES256".to_string(), Some("k1".to_string()))
+        );
+        let got = verify_jwt(&token, "ES256", &keys[0].1).unwrap();
+        assert_eq!(got, claims);
+        assert!(verify_jwt(&token, "RS256", &keys[0].1).is_err());
+        let parts: Vec<_> = token.split('.').collect();
+        let other = URL_SAFE_NO_PAD.encode(json!({"sub": "admin"}).to_string());
+        let forged = format!("{}.{}.{}", parts[0], other, parts[2]);
+        assert!(verify_jwt(&forged, "ES256", &keys[0].1).is_err());
+
+        let issuer = "https://id.example.com";
+        assert!(check_claims(&got, issuer, "app", "n", 1000).is_ok());
+        assert!(check_claims(&got, issuer, "other", "n", 1000).is_err());
+        assert!(check_claims(&got, issuer, "app", "m", 1000).is_err());
+        assert!(check_claims(&got, issuer, "app", "n", 3000).is_err());
+        assert!(check_claims(&got, "https://evil.example", "app", "n", 1000).is_err());
+    }
+
+    #[test]
+    fn sessions() {
+        let claims = json!({"sub": "u1"});
+        let cookie = seal(SECRET, &claims, 100);
+        assert_eq!(open(SECRET, &cookie, 99), Some(claims));
+        assert_eq!(open(SECRET, &cookie, 100), None);
+        assert_eq!(open("another secret", &cookie, 99), None);
+        assert_eq!(local_path("//evil.example/"), "/");
+        assert_eq!(local_path("/orders?page=2"), "/orders?page=2");
+    }
+
+    #[async_std::test]
+    async fn requires_sign_in() {
+        let oidc = Oidc::new(config()).unwrap();
+        *oidc.metadata.lock().await = Some(Arc::new(Metadata {
+            issuer: "https://id.example.com".into(),
+            authorization_endpoint: "https://id.example.com/authorize".into(),
+            token_endpoint: "https://id.example.com/token".into(),
+            jwks_uri: "https://id.example.com/jwks".into(),
+        }));
+        let mut app = tide::new();
+        app.with(oidc);
+        app.at("/*").all(|req: Request<()>| async move {
+            let sub = req
+                .ext::<Claims>()
+                .and_then(|c| c.0["sub"].as_str().map(String::from));
+            Ok::<_, tide::Error>(sub.unwrap_or_else(|| "anonymous".into()))
+        });
+
+        let res = app.get("/orders?page=2").await.unwrap();
+        assert_eq!(res.status(), StatusCode::Found);
+        let location = Url::parse(res.header("location").unwrap().as_str()).unwrap();
+        assert_eq!(location.path(), "/authorize");
+        let flow = res.header("set-cookie").unwrap().as_str();
+        assert!(flow.starts_with("oidc_flow="));
+        let res = app.post("/orders").await.unwrap();
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+
+        let got = app.get("/health").recv_string().await.unwrap();
+        assert_eq!(got, "anonymous");
+        let session = seal(SECRET, &json!({"sub": "u1"}), now() + 60);
+        let got = app
+            .get("/orders")
+            .header("cookie", format!("{}={}", SESSION_COOKIE, session))
+            .recv_string()
+            .await
+            .unwrap();
+        assert_eq!(got, "u1");
+        let res = app.get("/oidc/callback?state=s&code=c").await.unwrap();
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+    }
+}
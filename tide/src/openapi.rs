@@ -0,0 +1,214 @@
+//! An OpenAPI 3 document of the rhai scripts of a [`RhaiDir`]: every
+//! script is a path, with the methods of its handler functions, or `get`
+//! for scripts without any. Comments describe the operations:
+//!
+//!```text
+//! //! @tag orders
+//!
+//! /// Lists the orders of the customer.
+//! /// @summary List orders
+//! /// @param page Page to show, from 1
+//! /// @header x-customer Customer id
+//! /// @response 404 No such customer
+//! fn get(req) { ... }
+//!```
+//!
+//! `//!` comments apply to every operation of the file, `///` comments to
+//! the handler below them. Lines without an `@` are the description.
+
+use crate::testing::is_test;
+use crate::{handlers, RhaiDir, INIT, SHUTDOWN};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+/// What comments say about an operation.
+#[derive(Default, Clone)]
+struct Annotations {
+    summary: Option<String>,
+    description: Vec<String>,
+    tags: Vec<String>,
+    /// Name, `query` or `header`, and description.
+    parameters: Vec<(String, &'static str, String)>,
+    responses: Vec<(String, String)>,
+}
+
+impl Annotations {
+    fn add(&mut self, line: &str) {
+        let line = line.trim();
+        let Some(annotation) = line.strip_prefix('@') else {
+            if !line.is_empty() {
+                self.description.push(line.to_string());
+            }
+            return;
+        };
+        let (name, rest) = annotation.split_once(' ').unwrap_or((annotation, ""));
+        let (first, text) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+        let (first, text) = (first.to_string(), text.trim().to_string());
+        match name {
+            "summary" => self.summary = Some(rest.trim().to_string()),
+            "tag" => self.tags.push(first),
+            "param" => self.parameters.push((first, "query", text)),
+            "header" => self.parameters.push((first, "header", text)),
+            "response" => self.responses.push((first, text)),
+            _ => log::warn!("Unknown OpenAPI annotation @{}", name),
+        }
+    }
+
+    fn operation(&self) -> Value {
+        let mut op = Map::new();
+        if let Some(summary) = &self.summary {
+            op.insert("summary".into(), summary.as_str().into());
+        }
+        if !self.description.is_empty() {
+            op.insert("description".into(), self.description.join("\n").into());
+        }
+        if !self.tags.is_empty() {
+            op.insert("tags".into(), json!(self.tags));
+        }
+        let parameters: Vec<_> = self
+            .parameters
+            .iter()
+            .map(|(name, place, description)| {
+                json!({
+                    "name": name,
+                    "in": place,
+                    "description": description,
+                    "schema": {"type": "string"},
+                })
+            })
+            .collect();
+        if !parameters.is_empty() {
+            op.insert("parameters".into(), parameters.into());
+        }
+        let mut responses = Map::new();
+        responses.insert("200".into(), json!({"description": "OK"}));
+        for (status, description) in &self.responses {
+            responses.insert(status.clone(), json!({ "description": description }));
+        }
+        op.insert("responses".into(), responses.into());
+        op.into()
+    }
+}
+
+/// The `//!` comments of `source`.
+fn file_annotations(source: &str) -> Annotations {
+    let mut annotations = Annotations::default();
+    for line in source.lines() {
+        if let Some(comment) = line.trim().strip_prefix("//!") {
+            annotations.add(comment);
+        }
+    }
+    annotations
+}
+
+/// The `///` comments right above `fn name(`.
+fn fn_annotations(source: &str, name: &str, mut annotations: Annotations) -> Annotations {
+    let lines: Vec<&str> = source.lines().collect();
+    let declaration = format!("fn {}(", name);
+    let Some(at) = lines
+        .iter()
+        .position(|l| l.trim().starts_with(&declaration))
+    else {
+        return annotations;
+    };
+    let comments: Vec<&str> = lines[..at]
+        .iter()
+        .rev()
+        .map(|l| l.trim())
+        .take_while(|l| l.starts_with("///"))
+        .collect();
+    for comment in comments.iter().rev() {
+        annotations.add(&comment[3..]);
+    }
+    annotations
+}
+
+impl RhaiDir {
+    /// The OpenAPI document of the scripts of the directory, see the
+    /// [module](self). Scripts that do not compile are left out.
+    pub fn openapi(&self, title: &str, version: &str) -> Value {
+        let mut paths = Map::new();
+        let engine = self.engine();
+        let served = |name: &str| name.ends_with(".rhai") && name != INIT && name != SHUTDOWN;
+        for file in crate::testing::discover(&self.dir, served) {
+            if is_test(&file) {
+                continue;
+            }
+            let Ok(source) = std::fs::read_to_string(&file) else {
+                continue;
+            };
+            let ast = match engine.compile(&source) {
+                Ok(ast) => ast,
+                Err(e) => {
+                    log::warn!("Leaving {:?} out of the OpenAPI document: {}", file, e);
+                    continue;
+                }
+            };
+            let common = file_annotations(&source);
+            let mut operations = Map::new();
+            let handled = handlers::handled(&ast);
+            if handled.is_empty() {
+                operations.insert("get".into(), common.operation());
+            }
+            for (method, name) in handled {
+                let annotations = fn_annotations(&source, name, common.clone());
+                operations.insert(method.to_string().to_lowercase(), annotations.operation());
+            }
+            paths.insert(self.route(&file), operations.into());
+        }
+        json!({
+            "openapi": "3.0.3",
+            "info": {"title": title, "version": version},
+            "paths": paths,
+        })
+    }
+
+    /// The path requests for `file` have.
+    fn route(&self, file: &Path) -> String {
+        let relative = file.strip_prefix(&self.dir).unwrap_or(file);
+        let prefix = self.prefix.trim_end_matches('*').trim_end_matches('/');
+        let parts: Vec<_> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        format!("{}/{}", prefix, parts.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn document() {
+        let dir = std::env::temp_dir().join("tide_rhai_openapi");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("api")).unwrap();
+        std::fs::write(
+            dir.join("api/orders.rhai"),
+            "//! @tag orders\n\n/// Lists the orders.\n/// @summary List orders\n/// @param page Page to show\n/// @response 404 No such customer\nfn get(req) { [] }\nfn post(req) { () }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("hello.rhai"), "\"hello\"").unwrap();
+        std::fs::write(dir.join("hello_test.rhai"), "fn test_it() {}").unwrap();
+        std::fs::write(dir.join(INIT), "()").unwrap();
+        let rhai = RhaiDir::new("/api/*", &dir).unwrap();
+        let doc = rhai.openapi("shop", "1.0");
+
+        let paths = doc["paths"].as_object().unwrap();
+        let mut routes: Vec<_> = paths.keys().collect();
+        routes.sort();
+        assert_eq!(routes, ["/api/api/orders.rhai", "/api/hello.rhai"]);
+        let get = &doc["paths"]["/api/api/orders.rhai"]["get"];
+        assert_eq!(get["summary"], "List orders");
+        assert_eq!(get["description"], "Lists the orders.");
+        assert_eq!(get["tags"], json!(["orders"]));
+        assert_eq!(get["parameters"][0]["name"], "page");
+        assert_eq!(get["parameters"][0]["in"], "query");
+        assert_eq!(get["responses"]["404"]["description"], "No such customer");
+        let post = &doc["paths"]["/api/api/orders.rhai"]["post"];
+        assert_eq!(post["tags"], json!(["orders"]));
+        assert!(post.get("summary").is_none());
+        assert!(doc["paths"]["/api/hello.rhai"]["get"].is_object());
+    }
+}
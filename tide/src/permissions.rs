@@ -0,0 +1,278 @@
+use crate::capabilities::{self, Capability};
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// One kind of access, granted for everything or for a list of targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Allow<T> {
+    All,
+    /// Only these, nothing if the list is empty.
+    Only(Vec<T>),
+}
+
+impl<T> Allow<T> {
+    pub fn none() -> Self {
+        Allow::Only(Vec::new())
+    }
+
+    fn any(&self, f: impl Fn(&T) -> bool) -> bool {
+        match self {
+            Allow::All => true,
+            Allow::Only(list) => list.iter().any(f),
+        }
+    }
+}
+
+impl Allow<String> {
+    /// Whether `name` is allowed, by name or by a prefix ending in `*`.
+    pub(crate) fn named(&self, name: &str) -> bool {
+        self.any(|entry| match entry.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => entry == name,
+        })
+    }
+
+    /// Whether the host of `url` is allowed, as `host` for any port or
+    /// `host:port`.
+    pub(crate) fn host(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default();
+        self.any(|entry| match split_port(entry) {
+            (h, None) => h.eq_ignore_ascii_case(host),
+            (h, p) => h.eq_ignore_ascii_case(host) && p == port,
+        })
+    }
+}
+
+/// What scripts may reach through their bindings, like the `--allow-*` flags
+/// of Deno. Every binding that talks to the network, the file system or the
+/// environment checks it, and fails with an error naming the flag that is
+/// missing.
+///
+/// The default lets scripts do what they could before permissions existed,
+/// any host and the whole data directory, but hides the environment.
+/// Handlers may narrow this further by declaring what they need in a
+/// `// capabilities: net, fs` comment.
+#[derive(Debug, Clone)]
+pub struct Permissions {
+    /// Hosts `fetch` and `WebSocket` may connect to, as `host` for any port
+    /// or `host:port`.
+    pub net: Allow<String>,
+    /// Files and directories, with everything inside them, that may be read.
+    pub read: Allow<PathBuf>,
+    /// Files and directories, with everything inside them, that may be written.
+    pub write: Allow<PathBuf>,
+    /// Environment variables that may be read, by name or by a prefix ending
+    /// in `*` like `APP_*`.
+    pub env: Allow<String>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            net: Allow::All,
+            read: Allow::All,
+            write: Allow::All,
+            env: Allow::none(),
+        }
+    }
+}
+
+/// Splits `host:port`, leaving the brackets of IPv6 addresses in place.
+fn split_port(entry: &str) -> (&str, Option<u16>) {
+    match entry.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && (!host.contains(':') || host.ends_with(']')) => {
+            match port.parse() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => (entry, None),
+            }
+        }
+        _ => (entry, None),
+    }
+}
+
+/// `path` with `..` and symlinks resolved as far as it exists, so permissions
+/// for `./data` also cover files about to be created in it.
+fn real_path(path: &Path) -> PathBuf {
+    let absolute = std::env::current_dir()
+        .map(|d| d.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let mut path = PathBuf::new();
+    for c in absolute.components() {
+        match c {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => {}
+            c => path.push(c),
+        }
+    }
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_owned());
+                existing = parent;
+            }
+            _ => return path,
+        }
+    }
+    let mut real = existing
+        .canonicalize()
+        .unwrap_or_else(|_| existing.to_path_buf());
+    real.extend(rest.iter().rev());
+    real
+}
+
+fn denied(kind: &str, target: &str, flag: &str) -> String {
+    format!(
+        "Requires {} access to {}, run with --allow-{}",
+        kind, target, flag
+    )
+}
+
+impl Permissions {
+    /// Everything, environment included.
+    pub fn all() -> Self {
+        Self {
+            env: Allow::All,
+            ..Self::default()
+        }
+    }
+
+    /// Nothing at all. Grant what scripts need by setting the fields.
+    pub fn none() -> Self {
+        Self {
+            net: Allow::none(),
+            read: Allow::none(),
+            write: Allow::none(),
+            env: Allow::none(),
+        }
+    }
+
+    pub(crate) fn check_net(&self, url: &Url) -> Result<(), String> {
+        capabilities::require(Capability::Net)?;
+        if self.net.host(url) {
+            return Ok(());
+        }
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default();
+        let target = match port {
+            Some(p) => format!("{}:{}", host, p),
+            None => host.to_string(),
+        };
+        Err(denied("net", &target, &format!("net={}", host)))
+    }
+
+    fn check_path(allow: &Allow<PathBuf>, path: &Path) -> bool {
+        if let Allow::All = allow {
+            return true;
+        }
+        let path = real_path(path);
+        allow.any(|p| path.starts_with(real_path(p)))
+    }
+
+    pub(crate) fn check_read(&self, path: &Path) -> Result<(), String> {
+        capabilities::require(Capability::Fs)?;
+        if Self::check_path(&self.read, path) {
+            return Ok(());
+        }
+        Err(denied("read", &path.display().to_string(), "read"))
+    }
+
+    pub(crate) fn check_write(&self, path: &Path) -> Result<(), String> {
+        capabilities::require(Capability::Fs)?;
+        if Self::check_path(&self.write, path) {
+            return Ok(());
+        }
+        Err(denied("write", &path.display().to_string(), "write"))
+    }
+
+    pub(crate) fn check_env(&self, name: &str) -> Result<(), String> {
+        capabilities::require(Capability::Env)?;
+        if self.env.named(name) {
+            return Ok(());
+        }
+        Err(denied("env", name, &format!("env={}", name)))
+    }
+
+    /// The variable `name`, if it may be read and is set.
+    pub(crate) fn env_var(&self, name: &str) -> Result<Option<String>, String> {
+        self.check_env(name)?;
+        Ok(std::env::var(name).ok())
+    }
+
+    /// The environment variables that may be read.
+    pub(crate) fn env_vars(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<_> = std::env::vars()
+            .filter(|(n, _)| self.check_env(n).is_ok())
+            .collect();
+        vars.sort();
+        vars
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn net() {
+        let permissions = Permissions {
+            net: Allow::Only(vec!["example.com".into(), "localhost:8080".into()]),
+            ..Permissions::none()
+        };
+        let check = |u: &str| permissions.check_net(&Url::parse(u).unwrap());
+        assert!(check("https://example.com/a").is_ok());
+        assert!(check("http://EXAMPLE.com:81/").is_ok());
+        assert!(check("ws://localhost:8080/").is_ok());
+        let e = check("http://localhost:8081/").unwrap_err();
+        assert_eq!(
+            e,
+            "Requires net access to localhost:8081, run with --allow-net=localhost"
+        );
+        assert!(check("https://example.org/").is_err());
+        assert!(Permissions::default()
+            .check_net(&Url::parse("https://example.org/").unwrap())
+            .is_ok());
+        assert_eq!(split_port("[::1]:80"), ("[::1]", Some(80)));
+        assert_eq!(split_port("::1"), ("::1", None));
+    }
+
+    #[test]
+    fn paths() {
+        let dir = std::env::temp_dir().join("tide-rhai-permissions");
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        let permissions = Permissions {
+            read: Allow::Only(vec![dir.join("data")]),
+            write: Allow::Only(vec![dir.join("data/out")]),
+            ..Permissions::none()
+        };
+        assert!(permissions.check_read(&dir.join("data/a.txt")).is_ok());
+        assert!(permissions.check_read(&dir.join("data/../b.txt")).is_err());
+        assert!(permissions.check_read(&dir.join("datab")).is_err());
+        assert!(permissions.check_write(&dir.join("data/a.txt")).is_err());
+        assert!(permissions.check_write(&dir.join("data/out/x/y")).is_ok());
+    }
+
+    #[test]
+    fn env() {
+        let permissions = Permissions {
+            env: Allow::Only(vec!["TIDE_RHAI_TEST_*".into(), "HOME".into()]),
+            ..Permissions::none()
+        };
+        std::env::set_var("TIDE_RHAI_TEST_A", "a");
+        assert_eq!(
+            permissions.env_var("TIDE_RHAI_TEST_A").unwrap().as_deref(),
+            Some("a")
+        );
+        assert_eq!(permissions.env_var("TIDE_RHAI_TEST_B").unwrap(), None);
+        assert!(permissions.env_var("PATH").is_err());
+        assert!(permissions
+            .env_vars()
+            .iter()
+            .any(|(n, _)| n == "TIDE_RHAI_TEST_A"));
+        assert!(Permissions::default().env_var("HOME").is_err());
+    }
+}
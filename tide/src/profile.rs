@@ -0,0 +1,218 @@
+//! Timings of scripts by route, for `rustvm --profile`. Rhai scripts are
+//! instrumented through the debugging interface of the engine, so each of
+//! their functions gets the time spent in it, bindings like `fetch`
+//! included. JavaScript handlers are only timed as a whole.
+//!
+//! The result is written as folded stacks, one `route;function;function
+//! microseconds` line per stack, which `flamegraph.pl` and `inferno` turn
+//! into flame graphs.
+
+use rhai::EvalContext;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Collects the time spent in scripts, see
+/// [`RhaiDir::with_profiler`](crate::RhaiDir::with_profiler) and
+/// [`JsDir::with_profiler`](crate::JsDir::with_profiler).
+///```
+/// use std::sync::Arc;
+/// use tide_rhai::{Profiler, RhaiDir};
+/// let profiler = Arc::new(Profiler::new());
+/// let dir = RhaiDir::new("/*", "./examples/app/")
+///     .unwrap()
+///     .with_profiler(profiler.clone());
+/// // ... serve requests, then
+/// print!("{}", profiler.summary());
+///```
+#[derive(Default)]
+pub struct Profiler {
+    samples: Mutex<Samples>,
+}
+
+#[derive(Default)]
+struct Samples {
+    /// Time spent in each stack itself, not in the functions it calls.
+    stacks: HashMap<String, Duration>,
+    runs: HashMap<String, u64>,
+}
+
+/// The script running on this thread, while the profiler follows it.
+struct Run {
+    /// The route, then the functions being run.
+    frames: Vec<String>,
+    since: Instant,
+}
+
+thread_local! {
+    static RUN: RefCell<Option<Run>> = RefCell::new(None);
+}
+
+impl Run {
+    fn charge(&mut self, samples: &mut Samples, now: Instant) {
+        let took = now - self.since;
+        *samples.stacks.entry(self.frames.join(";")).or_default() += took;
+        self.since = now;
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the rhai script `f` for `route`, following the functions it
+    /// calls through [`Profiler::step`].
+    pub(crate) fn run<T>(&self, route: &str, f: impl FnOnce() -> T) -> T {
+        let run = Run {
+            frames: vec![route.to_string()],
+            since: Instant::now(),
+        };
+        let outer = RUN.with(|r| r.replace(Some(run)));
+        let res = f();
+        if let Some(mut run) = RUN.with(|r| r.replace(outer)) {
+            let mut samples = self.samples.lock().unwrap();
+            run.charge(&mut samples, Instant::now());
+            *samples.runs.entry(route.to_string()).or_default() += 1;
+        }
+        res
+    }
+
+    /// Follows a step of the rhai script run by [`Profiler::run`] on this
+    /// thread, charging the time since the last change of its call stack.
+    pub(crate) fn step(&self, context: &EvalContext) {
+        RUN.with(|r| {
+            let mut r = r.borrow_mut();
+            let Some(run) = r.as_mut() else {
+                return;
+            };
+            let stack = context.global_runtime_state().debugger.call_stack();
+            let same = run.frames.len() == stack.len() + 1
+                && stack
+                    .iter()
+                    .zip(&run.frames[1..])
+                    .all(|(frame, name)| frame.fn_name.as_str() == name);
+            if same {
+                return;
+            }
+            let mut samples = self.samples.lock().unwrap();
+            run.charge(&mut samples, Instant::now());
+            run.frames.truncate(1);
+            run.frames
+                .extend(stack.iter().map(|frame| frame.fn_name.to_string()));
+        });
+    }
+
+    /// Adds `took` to the time spent in `route` by a script that is timed as
+    /// a whole.
+    pub(crate) fn record(&self, route: &str, took: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        *samples.stacks.entry(route.to_string()).or_default() += took;
+        *samples.runs.entry(route.to_string()).or_default() += 1;
+    }
+
+    /// The stacks and the microseconds spent in them, in the folded format
+    /// of flame graph tools.
+    pub fn folded(&self) -> String {
+        let samples = self.samples.lock().unwrap();
+        let mut stacks: Vec<_> = samples.stacks.iter().collect();
+        stacks.sort();
+        let mut out = String::new();
+        for (stack, took) in stacks {
+            let _ = writeln!(out, "{} {}", stack, took.as_micros());
+        }
+        out
+    }
+
+    /// Writes [`Profiler::folded`] to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.folded())
+    }
+
+    /// A table of the routes, slowest first, with the time they took on
+    /// average and their slowest functions.
+    pub fn summary(&self) -> String {
+        let samples = self.samples.lock().unwrap();
+        let mut routes: HashMap<&str, (Duration, Vec<(&str, Duration)>)> = HashMap::new();
+        for (stack, took) in &samples.stacks {
+            let (route, function) = match stack.split_once(';') {
+                Some((route, functions)) => (route, functions.rsplit(';').next()),
+                None => (stack.as_str(), None),
+            };
+            let (total, functions) = routes.entry(route).or_default();
+            *total += *took;
+            if let Some(function) = function {
+                functions.push((function, *took));
+            }
+        }
+        let mut routes: Vec<_> = routes.into_iter().collect();
+        routes.sort_by(|a, b| b.1 .0.cmp(&a.1 .0));
+        let mut out = String::new();
+        for (route, (total, mut functions)) in routes {
+            let runs = samples.runs.get(route).copied().unwrap_or(1).max(1);
+            let _ = writeln!(
+                out,
+                "{}: {} runs, {:.3} ms total, {:.3} ms each",
+                route,
+                runs,
+                total.as_secs_f64() * 1000.0,
+                total.as_secs_f64() * 1000.0 / runs as f64
+            );
+            functions.sort_by(|a, b| b.1.cmp(&a.1));
+            for (function, took) in functions.iter().take(5) {
+                let _ = writeln!(out, "  {} {:.3} ms", function, took.as_secs_f64() * 1000.0);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::debugger::DebuggerCommand;
+    use rhai::{Dynamic, Engine};
+    use std::sync::Arc;
+
+    #[test]
+    fn rhai_functions() {
+        let profiler = Arc::new(Profiler::new());
+        let mut engine = Engine::new();
+        let p = profiler.clone();
+        engine.register_debugger(
+            |_| Dynamic::UNIT,
+            move |context, _, _, _, _| {
+                p.step(&context);
+                Ok(DebuggerCommand::StepInto)
+            },
+        );
+        let ast = engine
+            .compile("fn inner(x) { x + 1 }\nfn outer(x) { inner(x) * 2 }\nouter(inner(1))")
+            .unwrap();
+        let out: i64 = profiler.run("/calc", || engine.eval_ast(&ast).unwrap());
+        assert_eq!(out, 6);
+        profiler.record("/js/hello.js", Duration::from_millis(3));
+
+        let folded = profiler.folded();
+        let stacks: Vec<_> = folded
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                "/calc",
+                "/calc;inner",
+                "/calc;outer",
+                "/calc;outer;inner",
+                "/js/hello.js"
+            ]
+        );
+        assert!(folded.ends_with("/js/hello.js 3000\n"));
+        assert!(profiler.summary().contains("/calc: 1 runs"));
+    }
+}
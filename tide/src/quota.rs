@@ -0,0 +1,229 @@
+//! [`Quota`], how much of the server an app may take, for tenants sharing
+//! one with [`Tenants`](crate::Tenants). Every minute an app may get so many
+//! requests and keep its scripts running for so long; past either, its
+//! requests are answered with `429 Too Many Requests` until the minute is
+//! over, while the other apps go on:
+//!
+//!```text
+//! WARN tenant shop throttled: 61.2 s of scripts this minute, past 60 s
+//! HTTP/1.1 429 Too Many Requests
+//! retry-after: 23
+//!
+//! tenant throttled
+//!```
+//!
+//! Script time is measured from the request reaching the app to its answer,
+//! so it includes what the scripts wait on. Memory and storage are limited
+//! by the directories: [`Limits`](crate::Limits) of the sizes rhai scripts
+//! may build, [`DataQuota`](crate::DataQuota) and
+//! [`StorageQuota`](crate::StorageQuota).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tide::{Endpoint, Request, Response, StatusCode};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// What an app took of the current minute.
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    requests: u64,
+    busy: Duration,
+    /// Whether the throttling of this minute was logged.
+    warned: bool,
+}
+
+/// The limits of an app and what it took of them, shared by the
+/// [`Throttle`]s of its directories.
+///```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tide_rhai::{Quota, RhaiDir};
+/// let quota = Arc::new(
+///     Quota::new("shop")
+///         .requests_per_minute(600)
+///         .script_time_per_minute(Duration::from_secs(30)),
+/// );
+/// let mut app = tide::new();
+/// let dir = RhaiDir::new("/*", "./examples/app/").unwrap();
+/// app.at("/*").get(quota.throttle(dir));
+///```
+#[derive(Debug)]
+pub struct Quota {
+    name: String,
+    requests_per_minute: Option<u64>,
+    script_time_per_minute: Option<Duration>,
+    window: Mutex<Window>,
+    requests: AtomicU64,
+    throttled: AtomicU64,
+    script_micros: AtomicU64,
+}
+
+impl Quota {
+    /// No limits for the app `name`, which the log and the metrics show.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            requests_per_minute: None,
+            script_time_per_minute: None,
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                requests: 0,
+                busy: Duration::ZERO,
+                warned: false,
+            }),
+            requests: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            script_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Requests the app may get every minute.
+    pub fn requests_per_minute(mut self, requests: u64) -> Self {
+        self.requests_per_minute = Some(requests);
+        self
+    }
+
+    /// How long the scripts of the app may run every minute, all together.
+    pub fn script_time_per_minute(mut self, time: Duration) -> Self {
+        self.script_time_per_minute = Some(time);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Requests answered by the app, not counting those throttled.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Requests answered with `429` because the app was past its quota.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Time the scripts of the app ran.
+    pub fn script_seconds(&self) -> f64 {
+        self.script_micros.load(Ordering::Relaxed) as f64 / 1e6
+    }
+
+    /// Serves `app` within the quota.
+    pub fn throttle<State: Clone + Send + Sync + 'static>(
+        self: &Arc<Self>,
+        app: impl Endpoint<State>,
+    ) -> Throttle<State> {
+        Throttle {
+            quota: self.clone(),
+            app: Arc::new(app),
+        }
+    }
+
+    /// Counts a request in, or tells how long until the next minute if the
+    /// app is past its quota.
+    fn admit(&self) -> Result<(), Duration> {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if now.duration_since(window.start) >= MINUTE {
+            *window = Window {
+                start: now,
+                requests: 0,
+                busy: Duration::ZERO,
+                warned: false,
+            };
+        }
+        let past = if matches!(self.requests_per_minute, Some(n) if window.requests >= n) {
+            Some(format!("{} requests this minute", window.requests))
+        } else if matches!(self.script_time_per_minute, Some(t) if window.busy >= t) {
+            Some(format!(
+                "{:.1} s of scripts this minute, past {} s",
+                window.busy.as_secs_f64(),
+                self.script_time_per_minute
+                    .unwrap_or_default()
+                    .as_secs_f64()
+            ))
+        } else {
+            None
+        };
+        if let Some(past) = past {
+            if !window.warned {
+                window.warned = true;
+                log::warn!("tenant {} throttled: {}", self.name, past);
+            }
+            return Err(MINUTE.saturating_sub(now.duration_since(window.start)));
+        }
+        window.requests += 1;
+        Ok(())
+    }
+
+    /// Adds what a request took to the minute it started in.
+    fn spent(&self, took: Duration) {
+        self.script_micros.fetch_add(
+            took.as_micros().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        window.busy += took;
+    }
+}
+
+/// Answers with an app while its [`Quota`] is not used up, made by
+/// [`Quota::throttle`].
+pub struct Throttle<State> {
+    quota: Arc<Quota>,
+    app: Arc<dyn Endpoint<State>>,
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for Throttle<State> {
+    async fn call(&self, req: Request<State>) -> tide::Result {
+        if let Err(wait) = self.quota.admit() {
+            self.quota.throttled.fetch_add(1, Ordering::Relaxed);
+            return Ok(Response::builder(StatusCode::TooManyRequests)
+                .header("retry-after", (wait.as_secs() + 1).to_string())
+                .body("tenant throttled")
+                .build());
+        }
+        self.quota.requests.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let res = self.app.call(req).await;
+        self.quota.spent(started.elapsed());
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide_testing::TideTestingExt;
+
+    #[async_std::test]
+    async fn throttles_past_the_quota() {
+        let requests = Arc::new(Quota::new("shop").requests_per_minute(2));
+        let time = Arc::new(Quota::new("blog").script_time_per_minute(Duration::from_millis(20)));
+        let mut app = tide::new();
+        app.at("/shop")
+            .get(requests.throttle(|_: Request<()>| async { Ok::<_, tide::Error>("shop") }));
+        app.at("/blog").get(time.throttle(|_: Request<()>| async {
+            async_std::task::sleep(Duration::from_millis(30)).await;
+            Ok::<_, tide::Error>("blog")
+        }));
+
+        for _ in 0..2 {
+            assert_eq!(app.get("/shop").await.unwrap().status(), StatusCode::Ok);
+        }
+        let mut res = app.get("/shop").await.unwrap();
+        assert_eq!(res.status(), StatusCode::TooManyRequests);
+        assert!(res.header("retry-after").is_some());
+        assert_eq!(res.body_string().await.unwrap(), "tenant throttled");
+        assert_eq!((requests.requests(), requests.throttled()), (2, 1));
+
+        assert_eq!(app.get("/blog").await.unwrap().status(), StatusCode::Ok);
+        let res = app.get("/blog").await.unwrap();
+        assert_eq!(res.status(), StatusCode::TooManyRequests);
+        assert!(time.script_seconds() >= 0.03);
+    }
+}
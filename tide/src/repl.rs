@@ -0,0 +1,117 @@
+//! Evaluating code a line at a time with the bindings of the scripts, for
+//! `rustvm repl`. Variables and functions stay defined for later lines.
+
+use crate::RhaiDir;
+use rhai::{Dynamic, Engine, EvalAltResult, ParseError, ParseErrorType, Scope, AST};
+
+/// What evaluating a line of a REPL came to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Evaluated {
+    /// The value of the line, formatted for a terminal, or nothing if it has
+    /// none worth showing.
+    Value(Option<String>),
+    /// The code is not complete yet, like an open block, read another line
+    /// and evaluate them together.
+    Incomplete,
+    /// Evaluating the code failed.
+    Error(String),
+}
+
+/// A REPL for rhai, see [`RhaiDir::repl`].
+pub struct RhaiRepl<'a> {
+    engine: &'a Engine,
+    scope: Scope<'static>,
+    /// The functions defined so far.
+    functions: AST,
+}
+
+impl<'a> RhaiRepl<'a> {
+    pub(crate) fn new(engine: &'a Engine, scope: Scope<'static>) -> Self {
+        Self {
+            engine,
+            scope,
+            functions: AST::empty(),
+        }
+    }
+
+    /// Evaluates `code`, the lines read since the last value or error.
+    pub fn eval(&mut self, code: &str) -> Evaluated {
+        let ast = match self.engine.compile_with_scope(&self.scope, code) {
+            Ok(ast) => ast,
+            Err(e) if incomplete(code, &e) => return Evaluated::Incomplete,
+            Err(e) => return Evaluated::Error(e.to_string()),
+        };
+        self.functions = self.functions.merge(&ast.clone_functions_only());
+        let ast = self.functions.merge(&ast);
+        match self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut self.scope, &ast)
+        {
+            Ok(v) if v.is_unit() => Evaluated::Value(None),
+            Ok(v) if v.is_string() => Evaluated::Value(Some(format!("{:?}", v.to_string()))),
+            Ok(v) => Evaluated::Value(Some(v.to_string())),
+            Err(e) => Evaluated::Error(error(&e)),
+        }
+    }
+}
+
+/// Whether `code` failed to parse only because it stops too early, like
+/// after the opening brace of a block.
+fn incomplete(code: &str, e: &ParseError) -> bool {
+    let at_end = e
+        .position()
+        .line()
+        .map_or(true, |line| line >= code.lines().count());
+    match e.err_type() {
+        ParseErrorType::UnexpectedEOF => true,
+        ParseErrorType::MissingToken(..) => at_end,
+        _ => false,
+    }
+}
+
+fn error(e: &EvalAltResult) -> String {
+    match e {
+        EvalAltResult::ErrorRuntime(value, _) => format!("Uncaught {}", value),
+        e => e.to_string(),
+    }
+}
+
+impl RhaiDir {
+    /// A REPL with the engine and scope scripts get, without `ctx`.
+    ///```
+    /// use tide_rhai::{Evaluated, RhaiDir};
+    /// let dir = RhaiDir::new("/*", "./examples/app/").unwrap();
+    /// let mut repl = dir.repl();
+    /// assert_eq!(repl.eval("let x = 40;"), Evaluated::Value(None));
+    /// assert_eq!(repl.eval("fn add(a) {"), Evaluated::Incomplete);
+    /// assert_eq!(repl.eval("fn add(a) { a + 2 }"), Evaluated::Value(None));
+    /// assert_eq!(repl.eval("add(x)"), Evaluated::Value(Some("42".into())));
+    ///```
+    pub fn repl(&self) -> RhaiRepl<'_> {
+        RhaiRepl::new(self.engine(), self.scope())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rhai() {
+        let engine = Engine::new();
+        let mut repl = RhaiRepl::new(&engine, Scope::new());
+        assert_eq!(repl.eval("let greeting = \"hi\";"), Evaluated::Value(None));
+        assert_eq!(
+            repl.eval("greeting + \"!\""),
+            Evaluated::Value(Some("\"hi!\"".into()))
+        );
+        assert_eq!(repl.eval("if true {"), Evaluated::Incomplete);
+        assert_eq!(repl.eval("fn twice(n) { n * 2 }"), Evaluated::Value(None));
+        assert_eq!(repl.eval("twice(4)"), Evaluated::Value(Some("8".into())));
+        assert_eq!(
+            repl.eval("throw \"nope\""),
+            Evaluated::Error("Uncaught nope".into())
+        );
+        assert!(matches!(repl.eval("1 +* 2"), Evaluated::Error(_)));
+    }
+}
@@ -0,0 +1,198 @@
+//! `req`, the request a rhai script answers, as a type of its own:
+//!
+//!```text
+//! if req.method == "POST" && req.header("content-type") == "application/json" {
+//!     let order = req.body_json();
+//!     return #{ id: order.id, page: req.query("page") ?? "1" };
+//! }
+//!```
+//!
+//! `ctx` keeps the request as a map for older scripts.
+
+use crate::to_script_value;
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, ImmutableString, Map};
+use serde_json::Value;
+use std::collections::HashMap;
+use tide::http::Url;
+use tide::Request;
+
+#[derive(Debug, Clone)]
+pub(crate) struct ScriptRequest {
+    method: http_types::Method,
+    url: Url,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl ScriptRequest {
+    pub(crate) fn new(
+        method: http_types::Method,
+        url: Url,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Self {
+        Self {
+            method,
+            url,
+            headers,
+            body,
+        }
+    }
+
+    /// Reads the body of `req`, which is left empty.
+    pub(crate) async fn from_request<State>(req: &mut Request<State>) -> Self
+    where
+        State: Clone + Send + Sync + 'static,
+    {
+        let headers = req
+            .iter()
+            .flat_map(|(n, values)| values.iter().map(move |v| (n.to_string(), v.to_string())))
+            .collect();
+        let body = match req.body_bytes().await {
+            Ok(b) => b,
+            Err(e) => {
+                log::warn!("error reading body {:?}", e);
+                Vec::new()
+            }
+        };
+        Self::new(req.method(), req.url().clone(), headers, body)
+    }
+
+    pub(crate) fn method(&self) -> http_types::Method {
+        self.method
+    }
+
+    pub(crate) fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub(crate) fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The headers by name, the last value of repeated ones.
+    pub(crate) fn header_map(&self) -> HashMap<String, String> {
+        self.headers.iter().cloned().collect()
+    }
+
+    /// The last value of the header `name`.
+    pub(crate) fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .rev()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn header(&mut self, name: ImmutableString) -> Dynamic {
+        self.header_value(&name)
+            .map_or(Dynamic::UNIT, |v| v.to_string().into())
+    }
+
+    fn query(&mut self, name: ImmutableString) -> Dynamic {
+        self.url
+            .query_pairs()
+            .find(|(n, _)| n == name.as_str())
+            .map_or(Dynamic::UNIT, |(_, v)| v.into_owned().into())
+    }
+
+    fn body_json(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+        if self.body.is_empty() {
+            return Ok(Dynamic::UNIT);
+        }
+        let value: Value = serde_json::from_slice(&self.body)
+            .map_err(|e| format!("The body is not json: {}", e))?;
+        to_script_value(&value)
+    }
+
+    fn body_msgpack(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+        if self.body.is_empty() {
+            return Ok(Dynamic::UNIT);
+        }
+        let value = crate::msgpack::decode(&self.body)
+            .map_err(|e| format!("The body is not MessagePack: {}", e))?;
+        Ok(crate::msgpack::to_dynamic(value))
+    }
+
+    fn body_cbor(&mut self) -> Result<Dynamic, Box<EvalAltResult>> {
+        if self.body.is_empty() {
+            return Ok(Dynamic::UNIT);
+        }
+        let value =
+            crate::cbor::decode(&self.body).map_err(|e| format!("The body is not CBOR: {}", e))?;
+        Ok(crate::cbor::to_dynamic(value))
+    }
+
+    fn get_headers(&mut self) -> Map {
+        self.headers
+            .iter()
+            .map(|(n, v)| (n.to_ascii_lowercase().into(), v.clone().into()))
+            .collect()
+    }
+
+    fn get_queries(&mut self) -> Map {
+        self.url
+            .query_pairs()
+            .map(|(n, v)| (n.as_ref().into(), v.into_owned().into()))
+            .collect()
+    }
+}
+
+/// Registers `Request` and its getters and methods.
+pub(crate) fn register(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptRequest>("Request")
+        .register_get("method", |r: &mut ScriptRequest| r.method.to_string())
+        .register_get("path", |r: &mut ScriptRequest| r.url.path().to_string())
+        .register_get("url", |r: &mut ScriptRequest| r.url.to_string())
+        .register_get("query_string", |r: &mut ScriptRequest| {
+            r.url.query().unwrap_or_default().to_string()
+        })
+        .register_get("headers", ScriptRequest::get_headers)
+        .register_get("queries", ScriptRequest::get_queries)
+        .register_get("body", |r: &mut ScriptRequest| {
+            String::from_utf8_lossy(&r.body).into_owned()
+        })
+        .register_get("body_blob", |r: &mut ScriptRequest| -> Blob {
+            r.body.clone()
+        })
+        .register_fn("header", ScriptRequest::header)
+        .register_fn("query", ScriptRequest::query)
+        .register_result_fn("body_json", ScriptRequest::body_json)
+        .register_result_fn("body_msgpack", ScriptRequest::body_msgpack)
+        .register_result_fn("body_cbor", ScriptRequest::body_cbor);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::Scope;
+
+    #[test]
+    fn getters() {
+        let mut engine = Engine::new();
+        register(&mut engine);
+        let req = ScriptRequest::new(
+            http_types::Method::Post,
+            Url::parse("http://localhost/orders?page=2&q=a+b").unwrap(),
+            vec![("Content-Type".into(), "application/json".into())],
+            br#"{"id": 7}"#.to_vec(),
+        );
+        let mut scope = Scope::new();
+        scope.push("req", req);
+        let out: String = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"`${req.method} ${req.path} ${req.query("page")} ${req.query("q")} ${req.header("content-type")} ${req.body_json().id}`"#,
+            )
+            .unwrap();
+        assert_eq!(out, "POST /orders 2 a b application/json 7");
+        let missing: bool = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"req.query("x") == () && req.header("x") == ()"#,
+            )
+            .unwrap();
+        assert!(missing);
+    }
+}
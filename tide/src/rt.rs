@@ -0,0 +1,173 @@
+//! The async runtime everything runs on: async-std, or tokio with the
+//! `tokio` feature, for applications whose other dependencies need it.
+//! Spawning, sleeping and blocking on futures all go through here, by the
+//! handlers, the engines and the binary serving them.
+//!
+//! tide accepts connections with the listeners of async-std either way:
+//! their reactor runs on a thread of its own, so they serve under tokio as
+//! they do under async-std. Channels are runtime independent and are used
+//! as they are.
+//!
+//!```
+//! # tide_rhai::rt::block_on(async {
+//! let answer = tide_rhai::rt::spawn(async { 6 * 7 });
+//! assert_eq!(answer.await, 42);
+//! # });
+//!```
+
+use std::future::Future;
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A task of [`spawn`] or [`spawn_blocking`], resolving to what it returned.
+/// Dropping it lets the task run on by itself.
+pub struct JoinHandle<T> {
+    #[cfg(not(feature = "tokio"))]
+    task: async_std::task::JoinHandle<T>,
+    #[cfg(feature = "tokio")]
+    task: tokio::task::JoinHandle<T>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    #[cfg(not(feature = "tokio"))]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.task).poll(cx)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        // A task only fails by panicking here, none is ever cancelled.
+        Pin::new(&mut self.task).poll(cx).map(|done| match done {
+            Ok(value) => value,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        })
+    }
+}
+
+/// Runs `future` in the background.
+#[cfg(not(feature = "tokio"))]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    JoinHandle {
+        task: async_std::task::spawn(future),
+    }
+}
+
+/// The runtime of the caller, or one of our own for the threads of the
+/// engines, which are outside of any.
+#[cfg(feature = "tokio")]
+fn runtime() -> tokio::runtime::Handle {
+    static OWN: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    tokio::runtime::Handle::try_current().unwrap_or_else(|_| {
+        let own = OWN.get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("tide-rhai-rt")
+                .enable_all()
+                .build()
+                .expect("Cannot start a tokio runtime")
+        });
+        own.handle().clone()
+    })
+}
+
+/// Runs `future` in the background.
+#[cfg(feature = "tokio")]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    JoinHandle {
+        task: runtime().spawn(future),
+    }
+}
+
+/// Runs `work`, which blocks, on a thread where it keeps no task waiting.
+#[cfg(not(feature = "tokio"))]
+pub fn spawn_blocking<F, T>(work: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    JoinHandle {
+        task: async_std::task::spawn_blocking(work),
+    }
+}
+
+/// Runs `work`, which blocks, on a thread where it keeps no task waiting.
+#[cfg(feature = "tokio")]
+pub fn spawn_blocking<F, T>(work: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    JoinHandle {
+        task: runtime().spawn_blocking(work),
+    }
+}
+
+/// Waits for `future` on the current thread, for the synchronous code of
+/// scripts calling into async code.
+#[cfg(not(feature = "tokio"))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+}
+
+/// Waits for `future` on the current thread, for the synchronous code of
+/// scripts calling into async code. Inside the runtime the other tasks of
+/// the thread move to another one while it waits, which takes the
+/// multi-threaded runtime `#[tokio::main]` starts by default.
+#[cfg(feature = "tokio")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current() {
+        Ok(current) if current.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| current.block_on(future))
+        }
+        // A single threaded runtime cannot wait inside of itself.
+        Ok(_) => futures::executor::block_on(future),
+        Err(_) => runtime().block_on(future),
+    }
+}
+
+pub async fn sleep(duration: Duration) {
+    #[cfg(not(feature = "tokio"))]
+    async_std::task::sleep(duration).await;
+    #[cfg(feature = "tokio")]
+    tokio::time::sleep(duration).await;
+}
+
+/// What `future` resolves to, `None` if it takes longer than `duration`.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    #[cfg(not(feature = "tokio"))]
+    let done = async_std::future::timeout(duration, future).await;
+    #[cfg(feature = "tokio")]
+    let done = tokio::time::timeout(duration, future).await;
+    done.ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tasks_and_timers() {
+        let outside = std::thread::spawn(|| block_on(spawn_blocking(|| 7)));
+        assert_eq!(outside.join().unwrap(), 7);
+        block_on(async {
+            assert_eq!(spawn(async { 6 * 7 }).await, 42);
+            let slow = timeout(Duration::from_millis(10), sleep(Duration::from_secs(5)));
+            assert_eq!(slow.await, None);
+            assert_eq!(timeout(Duration::from_secs(5), async { 1 }).await, Some(1));
+        });
+    }
+}
@@ -0,0 +1,316 @@
+//! Secrets of the app, read from a `.env` file, a file encrypted with a key
+//! and the environment, for scripts to get with `secret(name)` only if the
+//! allowlist names them.
+//!
+//!```text
+//! let options = fetch_options();
+//! options.url = "https://api.example.com/orders";
+//! options.headers = #{ "Authorization": "Bearer " + secret("API_TOKEN") };
+//! fetch(options)
+//!```
+//!
+//! Every value loaded is also replaced by `[REDACTED]` wherever this crate
+//! logs or shows an error page, see [`redact`], so a script logging a header
+//! or failing with a token in its message does not leak it.
+//!
+//! The encrypted file is the base64 of a random nonce followed by a `.env`
+//! file sealed with XChaCha20-Poly1305, as [`Secrets::seal`] writes it,
+//! under a key of 32 bytes in base64 from [`Secrets::generate_key`].
+
+use crate::capabilities::{self, Capability};
+use crate::permissions::Allow;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+const NONCE_LEN: usize = 24;
+
+/// What values of secrets are replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Values shorter than this are not redacted, they would turn up in too
+/// much else.
+const MIN_REDACTED_LEN: usize = 4;
+
+/// Every value loaded by any [`Secrets`], longest first.
+static KNOWN: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+fn remember(value: &str) {
+    if value.len() < MIN_REDACTED_LEN {
+        return;
+    }
+    let mut known = KNOWN.write().unwrap_or_else(|e| e.into_inner());
+    if let Err(at) =
+        known.binary_search_by(|k| value.len().cmp(&k.len()).then(k.as_str().cmp(value)))
+    {
+        known.insert(at, value.to_string());
+    }
+}
+
+/// `text` with the value of every secret loaded replaced by [`REDACTED`].
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let known = KNOWN.read().unwrap_or_else(|e| e.into_inner());
+    let mut text = Cow::Borrowed(text);
+    for value in known.iter().filter(|v| text.contains(v.as_str())) {
+        text = Cow::Owned(text.replace(value.as_str(), REDACTED));
+    }
+    text
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The secrets scripts may get, by name. Sources loaded later override the
+/// values of earlier ones. Nothing is allowed until [`Secrets::allow`] says.
+#[derive(Clone)]
+pub struct Secrets {
+    values: Arc<HashMap<String, String>>,
+    allow: Allow<String>,
+}
+
+impl Default for Secrets {
+    fn default() -> Self {
+        Self {
+            values: Arc::default(),
+            allow: Allow::none(),
+        }
+    }
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.values.keys().collect();
+        names.sort();
+        f.debug_struct("Secrets")
+            .field("names", &names)
+            .field("allow", &self.allow)
+            .finish()
+    }
+}
+
+impl Secrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, vars: Vec<(String, String)>) {
+        let values = Arc::make_mut(&mut self.values);
+        for (name, value) in vars {
+            remember(&value);
+            values.insert(name, value);
+        }
+    }
+
+    /// Adds the variables of the `.env` file at `path`.
+    ///```no_run
+    /// use tide_rhai::Secrets;
+    /// let secrets = Secrets::new()
+    ///     .with_env_file("./examples/app.env")
+    ///     .unwrap();
+    ///```
+    pub fn with_env_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let vars = parse_env(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        self.insert(vars);
+        Ok(self)
+    }
+
+    /// Adds the variables of the file at `path`, sealed with `key`.
+    pub fn with_encrypted_file(mut self, path: impl AsRef<Path>, key: &str) -> io::Result<Self> {
+        let path = path.as_ref();
+        let sealed = std::fs::read_to_string(path)?;
+        let vars = open(&sealed, key)
+            .and_then(|text| parse_env(&text))
+            .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        self.insert(vars);
+        Ok(self)
+    }
+
+    /// Adds the environment variables of `names`, each a name or a prefix
+    /// ending in `*` like `APP_*`.
+    pub fn with_env(mut self, names: &[String]) -> Self {
+        let names = Allow::Only(names.to_vec());
+        let vars = std::env::vars().filter(|(n, _)| names.named(n)).collect();
+        self.insert(vars);
+        self
+    }
+
+    /// Lets scripts get the secrets of `names`, each a name or a prefix
+    /// ending in `*`.
+    ///```
+    /// use tide_rhai::{Allow, Secrets};
+    /// let secrets = Secrets::new()
+    ///     .with_env(&["APP_*".into()])
+    ///     .allow(Allow::Only(vec!["APP_TOKEN".into()]));
+    ///```
+    pub fn allow(mut self, names: Allow<String>) -> Self {
+        self.allow = names;
+        self
+    }
+
+    /// The names of the secrets loaded.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.values.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The secret `name`, if it is allowed and loaded.
+    pub(crate) fn get(&self, name: &str) -> Result<Option<&str>, String> {
+        capabilities::require(Capability::Env)?;
+        if !self.allow.named(name) {
+            return Err(format!(
+                "Secret {} is not allowed, add it to the allowlist of the secrets",
+                name
+            ));
+        }
+        Ok(self.values.get(name).map(String::as_str))
+    }
+
+    /// A new random key for [`Secrets::seal`].
+    pub fn generate_key() -> String {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).expect("no random numbers");
+        STANDARD.encode(key)
+    }
+
+    /// The `.env` file `text` sealed with `key`, for
+    /// [`Secrets::with_encrypted_file`].
+    pub fn seal(text: &str, key: &str) -> io::Result<String> {
+        parse_env(text).map_err(invalid)?;
+        let cipher = cipher(key).map_err(invalid)?;
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| invalid(e.to_string()))?;
+        let sealed = cipher
+            .encrypt(XNonce::from_slice(&nonce), text.as_bytes())
+            .map_err(|_| invalid("cannot encrypt the secrets".into()))?;
+        Ok(STANDARD.encode([&nonce[..], &sealed].concat()))
+    }
+}
+
+fn cipher(key: &str) -> Result<XChaCha20Poly1305, String> {
+    let key = STANDARD
+        .decode(key.trim())
+        .map_err(|e| format!("invalid secrets key: {}", e))?;
+    XChaCha20Poly1305::new_from_slice(&key)
+        .map_err(|_| "the secrets key must be 32 bytes in base64".to_string())
+}
+
+/// The text `sealed` holds, if `key` sealed it.
+fn open(sealed: &str, key: &str) -> Result<String, String> {
+    let cipher = cipher(key)?;
+    let bytes = STANDARD
+        .decode(sealed.trim())
+        .map_err(|e| format!("not encrypted secrets: {}", e))?;
+    if bytes.len() < NONCE_LEN {
+        return Err("not encrypted secrets".into());
+    }
+    let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+    let text = cipher
+        .decrypt(XNonce::from_slice(nonce), sealed)
+        .map_err(|_| "the key does not open the secrets".to_string())?;
+    String::from_utf8(text).map_err(|_| "the secrets are not UTF-8".into())
+}
+
+/// The `NAME=value` lines of a `.env` file. Lines may start with `export`,
+/// `#` starts a comment outside of quotes, and double quoted values may
+/// hold `\n`, `\"` and `\\`.
+fn parse_env(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let bad = || format!("line {} is not NAME=value", i + 1);
+        let (name, value) = line.split_once('=').ok_or_else(bad)?;
+        let name = name.trim();
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        if name.is_empty() || !name.chars().all(valid) {
+            return Err(bad());
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('"') {
+            let mut out = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => out.push('\n'),
+                        Some(c) => out.push(c),
+                        None => return Err(format!("line {} ends inside quotes", i + 1)),
+                    },
+                    Some(c) => out.push(c),
+                    None => return Err(format!("line {} ends inside quotes", i + 1)),
+                }
+            }
+            out
+        } else if let Some(quoted) = value.strip_prefix('\'') {
+            match quoted.split_once('\'') {
+                Some((value, _)) => value.to_string(),
+                None => return Err(format!("line {} ends inside quotes", i + 1)),
+            }
+        } else {
+            let value = match value.find(" #") {
+                Some(at) => &value[..at],
+                None => value,
+            };
+            value.trim_end().to_string()
+        };
+        vars.push((name.to_string(), value));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loads_allows_and_redacts() {
+        let text =
+            "# keys\nexport API_KEY=abc123 # live\nQUOTED=\"a \\\"b\\\"\\nc\"\nRAW='x#y'\nEMPTY=\n";
+        let vars = parse_env(text).unwrap();
+        assert_eq!(vars[0], ("API_KEY".into(), "abc123".into()));
+        assert_eq!(vars[1].1, "a \"b\"\nc");
+        assert_eq!(vars[2].1, "x#y");
+        assert_eq!(vars[3].1, "");
+        assert!(parse_env("NOPE").is_err());
+        assert!(parse_env("A=\"open").is_err());
+
+        let key = Secrets::generate_key();
+        let sealed = Secrets::seal("TIDE_RHAI_SEALED=s3cr3t-value\n", &key).unwrap();
+        assert_eq!(
+            open(&sealed, &key).unwrap(),
+            "TIDE_RHAI_SEALED=s3cr3t-value\n"
+        );
+        assert!(open(&sealed, &Secrets::generate_key()).is_err());
+
+        let path = std::env::temp_dir().join("tide-rhai-secrets.enc");
+        std::fs::write(&path, sealed).unwrap();
+        let secrets = Secrets::new()
+            .with_encrypted_file(&path, &key)
+            .unwrap()
+            .allow(Allow::Only(vec!["TIDE_RHAI_*".into()]));
+        assert_eq!(
+            secrets.get("TIDE_RHAI_SEALED").unwrap(),
+            Some("s3cr3t-value")
+        );
+        assert_eq!(secrets.get("TIDE_RHAI_OTHER").unwrap(), None);
+        assert!(secrets.get("PATH").is_err());
+        assert!(!format!("{:?}", secrets).contains("s3cr3t"));
+        assert_eq!(redact("token s3cr3t-value sent"), "token [REDACTED] sent");
+        assert_eq!(redact("nothing here"), "nothing here");
+    }
+}
@@ -0,0 +1,242 @@
+//! How long each script takes to compile and to run, for finding the slow
+//! ones. [`SlowScripts`] keeps the last runs of every script and, each time
+//! a window of them is full, warns if their p95 is above the threshold or
+//! has grown past its usual value by the regression factor:
+//!
+//!```text
+//! WARN api/report.rhai is slow to exec: p95 of 1840.2 ms over 100 runs, above 1000 ms
+//! WARN api/orders.ts regressed to exec: p95 of 95.0 ms is 3.1x its usual 30.6 ms
+//!```
+//!
+//! The usual p95 moves slowly towards that of every window, so a script that
+//! stays slower stops being reported as regressed after a while, but keeps
+//! being reported as slow. [`SlowScripts::report`] lists every script, for an
+//! admin API.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How much of every window the usual p95 takes in.
+const HISTORY_WEIGHT: f64 = 0.1;
+
+/// What of a script took the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Compiling a rhai script, or transpiling a TypeScript one.
+    Compile,
+    /// Running it, from the request reaching the engine to its answer.
+    Exec,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Compile => "compile",
+            Self::Exec => "exec",
+        })
+    }
+}
+
+/// The times of one phase of a script.
+#[derive(Debug, Default)]
+struct Series {
+    /// The last runs, in microseconds.
+    recent: VecDeque<u64>,
+    runs: u64,
+    /// The usual p95 of the windows before, in microseconds.
+    usual: Option<f64>,
+    slow: bool,
+    regressed: bool,
+}
+
+/// The `p` percentile of `sorted`.
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    let at = (p * sorted.len() as f64).ceil() as usize;
+    sorted[at.clamp(1, sorted.len()) - 1]
+}
+
+fn millis(micros: f64) -> f64 {
+    (micros / 100.0).round() / 10.0
+}
+
+/// What [`SlowScripts::report`] says of one phase of a script. Times are in
+/// milliseconds, over the last runs.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SlowScript {
+    pub script: String,
+    pub phase: Phase,
+    /// Every run since the server started.
+    pub runs: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    /// The p95 the script usually has, once a window is full.
+    pub usual_p95_ms: Option<f64>,
+    /// Whether the last full window was above the threshold.
+    pub slow: bool,
+    /// Whether the last full window grew past the usual p95.
+    pub regressed: bool,
+}
+
+/// The times of the scripts of one or more directories, see the
+/// [module](self).
+#[derive(Debug)]
+pub struct SlowScripts {
+    threshold: Duration,
+    regression: f64,
+    window: usize,
+    series: Mutex<HashMap<(String, Phase), Series>>,
+}
+
+impl Default for SlowScripts {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_secs(1),
+            regression: 2.0,
+            window: 100,
+            series: Mutex::default(),
+        }
+    }
+}
+
+impl SlowScripts {
+    /// Warns of a p95 above a second, or twice the usual, every 100 runs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The p95 above which a script is slow.
+    pub fn threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// How many times its usual p95 a script must take to have regressed.
+    pub fn regression(mut self, factor: f64) -> Self {
+        self.regression = factor;
+        self
+    }
+
+    /// How many of the last runs the percentiles are of, and how often
+    /// they are checked.
+    ///```
+    /// use std::time::Duration;
+    /// use tide_rhai::SlowScripts;
+    /// let slow = SlowScripts::new()
+    ///     .threshold(Duration::from_millis(250))
+    ///     .regression(1.5)
+    ///     .window(50);
+    ///```
+    pub fn window(mut self, runs: usize) -> Self {
+        self.window = runs.max(1);
+        self
+    }
+
+    /// Adds a run of `script` that spent `took` in `phase`.
+    pub(crate) fn record(&self, script: &str, phase: Phase, took: Duration) {
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let s = series.entry((script.to_string(), phase)).or_default();
+        if s.recent.len() == self.window {
+            s.recent.pop_front();
+        }
+        s.recent
+            .push_back(took.as_micros().try_into().unwrap_or(u64::MAX));
+        s.runs += 1;
+        if s.runs % self.window as u64 != 0 {
+            return;
+        }
+        let mut sorted: Vec<_> = s.recent.iter().copied().collect();
+        sorted.sort_unstable();
+        let p95 = percentile(&sorted, 0.95) as f64;
+        let threshold = self.threshold.as_micros() as f64;
+        s.slow = p95 > threshold;
+        if s.slow {
+            log::warn!(
+                "{} is slow to {}: p95 of {} ms over {} runs, above {} ms",
+                script,
+                phase,
+                millis(p95),
+                sorted.len(),
+                millis(threshold)
+            );
+        }
+        s.regressed = s.usual.map_or(false, |usual| p95 > usual * self.regression);
+        if let (true, Some(usual)) = (s.regressed, s.usual) {
+            log::warn!(
+                "{} regressed to {}: p95 of {} ms is {:.1}x its usual {} ms",
+                script,
+                phase,
+                millis(p95),
+                p95 / usual,
+                millis(usual)
+            );
+        }
+        s.usual = Some(match s.usual {
+            Some(usual) => usual + (p95 - usual) * HISTORY_WEIGHT,
+            None => p95,
+        });
+    }
+
+    /// Every phase of every script that ran, slowest p95 first.
+    pub fn report(&self) -> Vec<SlowScript> {
+        let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report: Vec<_> = series
+            .iter()
+            .map(|((script, phase), s)| {
+                let mut sorted: Vec<_> = s.recent.iter().copied().collect();
+                sorted.sort_unstable();
+                SlowScript {
+                    script: script.clone(),
+                    phase: *phase,
+                    runs: s.runs,
+                    p50_ms: millis(percentile(&sorted, 0.5) as f64),
+                    p95_ms: millis(percentile(&sorted, 0.95) as f64),
+                    max_ms: millis(*sorted.last().unwrap_or(&0) as f64),
+                    usual_p95_ms: s.usual.map(millis),
+                    slow: s.slow,
+                    regressed: s.regressed,
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| b.p95_ms.total_cmp(&a.p95_ms));
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_slow_and_regressed() {
+        let slow = SlowScripts::new()
+            .threshold(Duration::from_millis(50))
+            .window(10);
+        let ms = Duration::from_millis;
+        for _ in 0..10 {
+            slow.record("a.rhai", Phase::Exec, ms(10));
+            slow.record("b.ts", Phase::Compile, ms(60));
+        }
+        let report = slow.report();
+        assert_eq!(report[0].script, "b.ts");
+        assert_eq!(report[0].phase, Phase::Compile);
+        assert!(report[0].slow && !report[0].regressed);
+        assert!(!report[1].slow && !report[1].regressed);
+        assert_eq!(report[1].usual_p95_ms, Some(10.0));
+
+        for i in 0..10 {
+            slow.record("a.rhai", Phase::Exec, ms(if i < 5 { 10 } else { 30 }));
+        }
+        let a = &slow.report()[1];
+        assert_eq!(
+            (a.runs, a.p50_ms, a.p95_ms, a.max_ms),
+            (20, 10.0, 30.0, 30.0)
+        );
+        assert!(a.regressed && !a.slow);
+        assert_eq!(a.usual_p95_ms, Some(12.0));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::js::{graph, typescript};
+use boa_engine::{module::Module, script::Script, Source};
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Bumped whenever the stored format changes, older snapshots are refused.
+const VERSION: u32 = 1;
+
+/// A script as kept in memory: ready to run, with the map back to the
+/// TypeScript it was produced from.
+pub(crate) struct Entry {
+    pub code: Arc<str>,
+    pub map: Option<Arc<sourcemap::SourceMap>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    map: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    version: u32,
+    files: BTreeMap<String, StoredEntry>,
+}
+
+/// The scripts of an app directory, checked and transpiled ahead of time.
+///
+/// Every rhai, JavaScript and json file is parsed once when the snapshot is
+/// built, so broken scripts are caught before deployment. TypeScript is
+/// stored already transpiled along with its source map, which saves the
+/// transpile on the first request to each file. Handlers given a snapshot
+/// take scripts from it and only read files it doesn't contain from disk.
+///
+///```no_run
+/// use tide_rhai::Snapshot;
+/// Snapshot::build("./app/").unwrap().save("./app.snapshot").unwrap();
+/// let snapshot = Snapshot::load("./app.snapshot").unwrap();
+///```
+#[derive(Default)]
+pub struct Snapshot {
+    files: BTreeMap<String, Entry>,
+}
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), e),
+    )
+}
+
+/// The key of `path` below `root`, always separated by `/`.
+pub(crate) fn key(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    let parts: Option<Vec<&str>> = rel.iter().map(|p| p.to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+pub(crate) fn collect(dir: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, out)?;
+        } else {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the script at `path` and makes its entry, `None` for files that
+/// are not scripts.
+fn compile(
+    path: &Path,
+    engine: &Engine,
+    context: &mut boa_engine::Context<'_>,
+) -> io::Result<Option<Entry>> {
+    let ext = match path.extension().and_then(|e| e.to_str()) {
+        Some(e) => e,
+        None => return Ok(None),
+    };
+    let entry = match ext {
+        "rhai" => {
+            let source = std::fs::read_to_string(path)?;
+            engine.compile(&source).map_err(|e| invalid(path, e))?;
+            Entry {
+                code: source.into(),
+                map: None,
+            }
+        }
+        "js" | "mjs" | "cjs" => {
+            let source = std::fs::read_to_string(path)?;
+            let src = Source::from_bytes(&source).with_path(path);
+            if crate::js::is_module(path, &source) {
+                Module::parse(src, None, context).map_err(|e| invalid(path, e))?;
+            } else {
+                Script::parse(src, None, context).map_err(|e| invalid(path, e))?;
+            }
+            Entry {
+                code: source.into(),
+                map: None,
+            }
+        }
+        "ts" | "mts" | "cts" => {
+            let source = std::fs::read_to_string(path)?;
+            let js = typescript::transpile(path, &source)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Entry {
+                code: js.code,
+                map: Some(js.map),
+            }
+        }
+        "json" => {
+            let source = std::fs::read_to_string(path)?;
+            serde_json::from_str::<serde_json::Value>(&source).map_err(|e| invalid(path, e))?;
+            Entry {
+                code: source.into(),
+                map: None,
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(entry))
+}
+
+impl Snapshot {
+    /// Checks and transpiles every script below `dir`. Fails on the first
+    /// file that doesn't parse, naming it and the position of the error.
+    pub fn build(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let root = dir.as_ref().canonicalize()?;
+        let mut paths = Vec::new();
+        collect(&root, &mut paths)?;
+        paths.sort();
+
+        let engine = Engine::new_raw();
+        let mut context = boa_engine::Context::default();
+        let mut files = BTreeMap::new();
+        for path in paths {
+            let Some(entry) = compile(&path, &engine, &mut context)? else {
+                continue;
+            };
+            if let Some(key) = key(&root, &path) {
+                log::info!("Precompiled {}", key);
+                files.insert(key, entry);
+            }
+        }
+        Ok(Self { files })
+    }
+
+    /// Like [`Snapshot::build`], but only with the `entries` and the files
+    /// they load, following `import`, `require` and workers from one module
+    /// to the next. Without entries every script outside of `node_modules`
+    /// is one, so the packages no handler uses are left out. The lifecycle
+    /// scripts of the app are always entries. Fails on
+    /// imports that do not resolve, which would fail once deployed.
+    ///
+    /// Handlers that load files through computed names, like
+    /// `import(name)`, need those files given as entries.
+    ///```no_run
+    /// use tide_rhai::Snapshot;
+    /// let snapshot = Snapshot::bundle("./app/", &["./app/api/orders.ts".into()]).unwrap();
+    /// snapshot.save("./app.bundle").unwrap();
+    ///```
+    pub fn bundle(dir: impl AsRef<Path>, entries: &[PathBuf]) -> io::Result<Self> {
+        let root = dir.as_ref().canonicalize()?;
+        let mut pending = Vec::new();
+        if entries.is_empty() {
+            collect(&root, &mut pending)?;
+            pending.retain(|p| {
+                let modules = p.strip_prefix(&root).map_or(false, |rel| {
+                    rel.components().any(|c| c.as_os_str() == "node_modules")
+                });
+                !modules
+            });
+        } else {
+            for entry in entries {
+                let path = entry.canonicalize().map_err(|e| invalid(entry, e))?;
+                if !path.starts_with(&root) {
+                    return Err(invalid(entry, "outside of the app directory"));
+                }
+                pending.push(path);
+            }
+            let lifecycle = [crate::INIT, crate::SHUTDOWN];
+            let lifecycle = lifecycle.iter().chain(&crate::js::APP_MODULES);
+            pending.extend(lifecycle.map(|n| root.join(n)).filter(|p| p.is_file()));
+        }
+        pending.sort();
+        pending.reverse();
+
+        let engine = Engine::new_raw();
+        let mut context = boa_engine::Context::default();
+        let mut files = BTreeMap::new();
+        while let Some(path) = pending.pop() {
+            let Some(key) = key(&root, &path) else {
+                continue;
+            };
+            if files.contains_key(&key) {
+                continue;
+            }
+            let Some(entry) = compile(&path, &engine, &mut context)? else {
+                continue;
+            };
+            if !matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("rhai" | "json")
+            ) {
+                let dir = path.parent().unwrap_or(&root);
+                let dependencies =
+                    graph::dependencies(&path, &entry.code).map_err(|e| invalid(&path, e))?;
+                for dependency in dependencies.iter().rev() {
+                    match graph::resolve(&root, dir, dependency) {
+                        Some(Ok(p)) => pending.push(p),
+                        Some(Err(e)) => return Err(invalid(&path, e)),
+                        None => {}
+                    }
+                }
+            }
+            log::info!("Bundled {}", key);
+            files.insert(key, entry);
+        }
+        Ok(Self { files })
+    }
+
+    /// Reads a snapshot written by [`Snapshot::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let stored: Stored =
+            serde_json::from_slice(&std::fs::read(path)?).map_err(|e| invalid(path, e))?;
+        if stored.version != VERSION {
+            return Err(invalid(
+                path,
+                format!(
+                    "snapshot version {} is not supported, rebuild it",
+                    stored.version
+                ),
+            ));
+        }
+        let mut files = BTreeMap::new();
+        for (key, e) in stored.files {
+            let map = match e.map {
+                Some(m) => Some(Arc::new(
+                    sourcemap::SourceMap::from_slice(m.as_bytes()).map_err(|e| invalid(path, e))?,
+                )),
+                None => None,
+            };
+            files.insert(
+                key,
+                Entry {
+                    code: e.code.into(),
+                    map,
+                },
+            );
+        }
+        Ok(Self { files })
+    }
+
+    /// Writes the snapshot to a single file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut files = BTreeMap::new();
+        for (key, e) in &self.files {
+            let map = match &e.map {
+                Some(m) => {
+                    let mut buf = Vec::new();
+                    m.to_writer(&mut buf)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    Some(String::from_utf8_lossy(&buf).into_owned())
+                }
+                None => None,
+            };
+            files.insert(
+                key.clone(),
+                StoredEntry {
+                    code: e.code.to_string(),
+                    map,
+                },
+            );
+        }
+        let stored = Stored {
+            version: VERSION,
+            files,
+        };
+        let json =
+            serde_json::to_vec(&stored).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        std::fs::write(path, json)
+    }
+
+    /// Number of scripts in the snapshot.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The precompiled script for `path`, a file below the app directory `root`.
+    pub(crate) fn get(&self, root: &Path, path: &Path) -> Option<&Entry> {
+        self.files.get(&key(root, path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn app(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        root.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let root = app("tide-rhai-snapshot");
+        std::fs::write(root.join("a.rhai"), "40 + 2").unwrap();
+        std::fs::write(root.join("lib/b.ts"), "const b: number = 1;\nb;").unwrap();
+        std::fs::write(root.join("notes.txt"), "not a script").unwrap();
+        let out = root.join("../tide-rhai-snapshot.json");
+        Snapshot::build(&root).unwrap().save(&out).unwrap();
+
+        let snapshot = Snapshot::load(&out).unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(
+            &*snapshot.get(&root, &root.join("a.rhai")).unwrap().code,
+            "40 + 2"
+        );
+        let b = snapshot.get(&root, &root.join("lib/b.ts")).unwrap();
+        assert!(!b.code.contains("number"));
+        assert!(b.map.is_some());
+        assert!(snapshot.get(&root, &root.join("notes.txt")).is_none());
+    }
+
+    #[test]
+    fn rejects_broken_scripts() {
+        let root = app("tide-rhai-snapshot-broken");
+        std::fs::write(root.join("lib/broken.rhai"), "let x = ;").unwrap();
+        let err = Snapshot::build(&root).err().unwrap();
+        assert!(err.to_string().contains("broken.rhai"));
+    }
+
+    #[test]
+    fn bundle() {
+        let root = app("tide-rhai-bundle");
+        std::fs::create_dir_all(root.join("node_modules/used")).unwrap();
+        std::fs::create_dir_all(root.join("node_modules/unused")).unwrap();
+        std::fs::write(
+            root.join("api.ts"),
+            "import { twice } from './lib/math.js';\nimport used from 'used';\nexport default () => twice(used);",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("lib/math.js"),
+            "export const twice = (n) => n * 2;",
+        )
+        .unwrap();
+        std::fs::write(root.join("lib/unused.js"), "export const x = 1;").unwrap();
+        std::fs::write(
+            root.join("node_modules/used/index.js"),
+            "export default 21;",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("node_modules/unused/index.js"),
+            "export default 0;",
+        )
+        .unwrap();
+        std::fs::write(root.join("_app.js"), "export function onStart() {}").unwrap();
+
+        let all = Snapshot::bundle(&root, &[]).unwrap();
+        let keys: Vec<&str> = all.files.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "_app.js",
+                "api.ts",
+                "lib/math.js",
+                "lib/unused.js",
+                "node_modules/used/index.js"
+            ]
+        );
+        let api = Snapshot::bundle(&root, &[root.join("api.ts")]).unwrap();
+        let keys: Vec<&str> = api.files.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "_app.js",
+                "api.ts",
+                "lib/math.js",
+                "node_modules/used/index.js"
+            ]
+        );
+
+        std::fs::write(root.join("lib/unused.js"), "import './missing.js';").unwrap();
+        let err = Snapshot::bundle(&root, &[]).err().unwrap();
+        assert!(err.to_string().contains("missing.js"), "{}", err);
+    }
+}
@@ -0,0 +1,157 @@
+//! [`Split`], an endpoint sending some of the traffic to a canary, like a
+//! [`RhaiDir`](crate::RhaiDir) of the next version of the app. Clients keep
+//! the side they were put on, through a cookie.
+
+use crate::mirror::sampled;
+use std::sync::Arc;
+use tide::http::Cookie;
+use tide::{Endpoint, Request, Response};
+
+/// Answers with `primary`, or with `canary` for `percent` of the clients and
+/// for the requests matching a header or cookie.
+///```
+/// use tide_rhai::{RhaiDir, Split};
+/// let mut app = tide::new();
+/// let stable = RhaiDir::new("/*", "./examples/app/").unwrap();
+/// let next = RhaiDir::new("/*", "./examples/app/").unwrap();
+/// app.at("/*").all(
+///     Split::new(stable, next)
+///         .percent(10.0)
+///         .when_header("x-canary", "1"),
+/// );
+///```
+pub struct Split<State> {
+    primary: Arc<dyn Endpoint<State>>,
+    canary: Arc<dyn Endpoint<State>>,
+    percent: f64,
+    header: Option<(String, String)>,
+    cookie: Option<(String, String)>,
+    sticky: String,
+}
+
+const PRIMARY: &str = "primary";
+const CANARY: &str = "canary";
+
+impl<State: Clone + Send + Sync + 'static> Split<State> {
+    /// Everything goes to `primary` until a share or a match is set.
+    pub fn new(primary: impl Endpoint<State>, canary: impl Endpoint<State>) -> Self {
+        Self {
+            primary: Arc::new(primary),
+            canary: Arc::new(canary),
+            percent: 0.0,
+            header: None,
+            cookie: None,
+            sticky: "split".into(),
+        }
+    }
+
+    /// Share of the new clients put on the canary, from 0 to 100.
+    pub fn percent(mut self, percent: f64) -> Self {
+        self.percent = percent;
+        self
+    }
+
+    /// Sends the requests with the header `name` set to `value` to the
+    /// canary, whatever side their client is on.
+    pub fn when_header(mut self, name: &str, value: &str) -> Self {
+        self.header = Some((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sends the requests with the cookie `name` set to `value` to the
+    /// canary, whatever side their client is on.
+    pub fn when_cookie(mut self, name: &str, value: &str) -> Self {
+        self.cookie = Some((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The cookie keeping the side of a client, `split` by default.
+    pub fn sticky_cookie(mut self, name: &str) -> Self {
+        self.sticky = name.to_string();
+        self
+    }
+
+    /// Whether `req` goes to the canary, and whether its client was put on
+    /// a side by this request.
+    fn canary(&self, req: &Request<State>) -> (bool, bool) {
+        let header = self.header.as_ref().map_or(false, |(name, value)| {
+            req.header(name.as_str())
+                .map_or(false, |h| h.as_str() == value)
+        });
+        let cookie = self.cookie.as_ref().map_or(false, |(name, value)| {
+            req.cookie(name).map_or(false, |c| c.value() == value)
+        });
+        if header || cookie {
+            return (true, false);
+        }
+        match req.cookie(&self.sticky).as_ref().map(|c| c.value()) {
+            Some(PRIMARY) => (false, false),
+            Some(CANARY) => (true, false),
+            _ => (sampled(self.percent), true),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for Split<State> {
+    async fn call(&self, req: Request<State>) -> tide::Result {
+        let (canary, assigned) = self.canary(&req);
+        let mut res: Response = if canary {
+            self.canary.call(req).await?
+        } else {
+            self.primary.call(req).await?
+        };
+        if assigned {
+            let side = if canary { CANARY } else { PRIMARY };
+            res.insert_cookie(Cookie::build(self.sticky.clone(), side).path("/").finish());
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide_testing::TideTestingExt;
+
+    fn serve(split: Split<()>) -> tide::Server<()> {
+        let mut app = tide::new();
+        app.at("/*").get(split);
+        app
+    }
+
+    fn sides() -> Split<()> {
+        Split::new(
+            |_: Request<()>| async { Ok::<_, tide::Error>("primary") },
+            |_: Request<()>| async { Ok::<_, tide::Error>("canary") },
+        )
+    }
+
+    #[async_std::test]
+    async fn routes() {
+        let app = serve(sides().when_header("x-canary", "1"));
+        let res = app.get("/").await.unwrap();
+        assert_eq!(
+            res.header("set-cookie").unwrap().as_str(),
+            "split=primary; Path=/"
+        );
+        let got = app.get("/").header("x-canary", "1").recv_string().await;
+        assert_eq!(got.unwrap(), "canary");
+        let got = app
+            .get("/")
+            .header("cookie", "split=canary")
+            .recv_string()
+            .await;
+        assert_eq!(got.unwrap(), "canary");
+
+        let app = serve(sides().percent(100.0));
+        let mut res = app.get("/").await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "canary");
+        let got = app
+            .get("/")
+            .header("cookie", "split=primary")
+            .recv_string()
+            .await;
+        assert_eq!(got.unwrap(), "primary");
+    }
+}
@@ -0,0 +1,439 @@
+//! Counters of the script engines, for capacity planning: how many scripts
+//! run, how long compiling and running them takes, how full the caches are
+//! and how busy the JavaScript pool is. [`Metrics`] serves them in the text
+//! format of Prometheus.
+//!
+//! Counters also keep what ran and the last errors, see [`DirReport`], for the
+//! [`Admin`](crate::Admin) dashboard.
+//!
+//! Neither engine reports the memory it holds, rhai has no heap of its own
+//! and the collector of boa keeps its numbers private, so memory is only
+//! known for the whole process.
+
+use crate::Quota;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tide::{Endpoint, Request, Response, StatusCode};
+
+/// Updated by a directory and the threads running its scripts.
+#[derive(Default)]
+pub(crate) struct Counters {
+    runs: AtomicU64,
+    failures: AtomicU64,
+    run_micros: AtomicU64,
+    compiles: AtomicU64,
+    compile_micros: AtomicU64,
+    contexts: AtomicU64,
+    context_micros: AtomicU64,
+    cold_starts: AtomicU64,
+    scripts: Mutex<BTreeMap<String, ScriptActivity>>,
+    /// The last [`RECENT_ERRORS`], oldest first.
+    errors: Mutex<VecDeque<ScriptError>>,
+}
+
+/// How many errors a directory keeps for its report.
+const RECENT_ERRORS: usize = 50;
+
+pub(crate) fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+impl Counters {
+    pub(crate) fn ran(&self, script: &str, took: Duration, failed: bool) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.run_micros.fetch_add(micros(took), Ordering::Relaxed);
+        if failed {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut scripts = self.scripts.lock().unwrap_or_else(|e| e.into_inner());
+        let activity = scripts
+            .entry(script.to_string())
+            .or_insert_with(|| ScriptActivity {
+                script: script.to_string(),
+                ..ScriptActivity::default()
+            });
+        activity.runs += 1;
+        activity.failures += u64::from(failed);
+        activity.last_run = now();
+    }
+
+    /// Keeps the error a script failed with, and where it was, redacted.
+    pub(crate) fn failed(&self, script: &str, message: &str, stack: Option<&str>) {
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(ScriptError {
+            time: now(),
+            script: script.to_string(),
+            message: crate::redact(message).into_owned(),
+            stack: stack.map(|s| crate::redact(s).into_owned()),
+        });
+    }
+
+    pub(crate) fn compiled(&self, took: Duration) {
+        self.compiles.fetch_add(1, Ordering::Relaxed);
+        self.compile_micros
+            .fetch_add(micros(took), Ordering::Relaxed);
+    }
+
+    /// A JavaScript context got its globals, see `prepare` of the js module.
+    pub(crate) fn set_up(&self, took: Duration) {
+        self.contexts.fetch_add(1, Ordering::Relaxed);
+        self.context_micros
+            .fetch_add(micros(took), Ordering::Relaxed);
+    }
+
+    /// A script found no warm context and had to wait for a new one.
+    pub(crate) fn cold_start(&self) {
+        self.cold_starts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self, engine: &'static str, prefix: &str) -> EngineStats {
+        let seconds = |c: &AtomicU64| c.load(Ordering::Relaxed) as f64 / 1e6;
+        EngineStats {
+            engine,
+            prefix: prefix.to_string(),
+            runs: self.runs.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            run_seconds: seconds(&self.run_micros),
+            compiles: self.compiles.load(Ordering::Relaxed),
+            compile_seconds: seconds(&self.compile_micros),
+            contexts: self.contexts.load(Ordering::Relaxed),
+            context_setup_seconds: seconds(&self.context_micros),
+            cold_starts: self.cold_starts.load(Ordering::Relaxed),
+            ..EngineStats::default()
+        }
+    }
+
+    /// The scripts that ran and the last errors, with `stats`.
+    pub(crate) fn report(&self, stats: EngineStats) -> DirReport {
+        let scripts = self.scripts.lock().unwrap_or_else(|e| e.into_inner());
+        let errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        DirReport {
+            stats,
+            scripts: scripts.values().cloned().collect(),
+            errors: errors.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+/// How often a script ran since the directory started serving.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScriptActivity {
+    /// The path of the script below the directory.
+    pub script: String,
+    pub runs: u64,
+    pub failures: u64,
+    /// When it last ran, in RFC 3339.
+    pub last_run: String,
+}
+
+/// An error a script failed with, as it was logged.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptError {
+    pub time: String,
+    pub script: String,
+    pub message: String,
+    /// Where the script was, the statement and the calls leading there.
+    pub stack: Option<String>,
+}
+
+/// What a [`RhaiDir`](crate::RhaiDir) or [`JsDir`](crate::JsDir) is
+/// doing, see their `report` method: its counters, the scripts that ran and
+/// the last errors, newest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct DirReport {
+    pub stats: EngineStats,
+    pub scripts: Vec<ScriptActivity>,
+    pub errors: Vec<ScriptError>,
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// The counters of a [`RhaiDir`](crate::RhaiDir) or
+/// [`JsDir`](crate::JsDir), see their `stats` method.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EngineStats {
+    /// `rhai` or `js`.
+    pub engine: &'static str,
+    /// The route of the directory.
+    pub prefix: String,
+    /// The app the directory serves on a server with
+    /// [`Tenants`](crate::Tenants), set by whoever watches it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Scripts run, and how many of those failed, not counting those that
+    /// answered with an `HttpError`.
+    pub runs: u64,
+    pub failures: u64,
+    /// Time from the request reaching the engine to its answer.
+    pub run_seconds: f64,
+    /// Scripts compiled, TypeScript transpiled included.
+    pub compiles: u64,
+    pub compile_seconds: f64,
+    /// JavaScript contexts set up with the web APIs and globals.
+    pub contexts: u64,
+    pub context_setup_seconds: f64,
+    /// Compiled scripts and modules kept in memory.
+    pub cache_entries: usize,
+    /// Threads of the JavaScript pool, and how many have a context ready.
+    pub pool_size: usize,
+    pub pool_idle: usize,
+    /// Scripts that found every pooled context busy.
+    pub cold_starts: u64,
+    /// WebSockets of JavaScript handlers that are connected, in the whole
+    /// process.
+    pub websockets: usize,
+}
+
+type Source = Arc<dyn Fn() -> EngineStats + Send + Sync>;
+
+/// An [`Endpoint`] answering with the counters of the directories it
+/// watches, and the memory of the process, for Prometheus to scrape.
+///```
+/// use std::sync::Arc;
+/// use tide_rhai::{JsDir, Metrics, RhaiDir};
+/// let rhai = Arc::new(RhaiDir::new("/*", "./examples/app/").unwrap());
+/// let js = Arc::new(JsDir::new("/js/*", "./examples/app/").unwrap());
+/// let (r, j) = (rhai.clone(), js.clone());
+/// let metrics = Metrics::new()
+///     .watch(move || r.stats())
+///     .watch(move || j.stats());
+/// let mut app = tide::new();
+/// app.at("/metrics").get(metrics);
+///```
+#[derive(Default, Clone)]
+pub struct Metrics {
+    sources: Vec<Source>,
+    quotas: Vec<Arc<Quota>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the counters `stats` returns, called for every scrape.
+    pub fn watch(mut self, stats: impl Fn() -> EngineStats + Send + Sync + 'static) -> Self {
+        self.sources.push(Arc::new(stats));
+        self
+    }
+
+    /// Adds what the tenant of `quota` took of it, and how often it was
+    /// throttled.
+    pub fn with_quota(mut self, quota: Arc<Quota>) -> Self {
+        self.quotas.push(quota);
+        self
+    }
+
+    /// The counters of every watched directory, for an admin API.
+    pub fn stats(&self) -> Vec<EngineStats> {
+        self.sources.iter().map(|s| s()).collect()
+    }
+
+    /// The counters in the text format of Prometheus.
+    pub fn render(&self) -> String {
+        let stats = self.stats();
+        let mut out = String::new();
+        let metrics: [(&str, &str, &str, fn(&EngineStats) -> f64); 12] = [
+            ("script_runs_total", "counter", "Scripts run.", |s| {
+                s.runs as f64
+            }),
+            (
+                "script_failures_total",
+                "counter",
+                "Scripts that failed.",
+                |s| s.failures as f64,
+            ),
+            (
+                "script_run_seconds_total",
+                "counter",
+                "Time spent running scripts.",
+                |s| s.run_seconds,
+            ),
+            (
+                "script_compiles_total",
+                "counter",
+                "Scripts compiled.",
+                |s| s.compiles as f64,
+            ),
+            (
+                "script_compile_seconds_total",
+                "counter",
+                "Time spent compiling scripts.",
+                |s| s.compile_seconds,
+            ),
+            (
+                "script_contexts_total",
+                "counter",
+                "JavaScript contexts set up.",
+                |s| s.contexts as f64,
+            ),
+            (
+                "script_context_setup_seconds_total",
+                "counter",
+                "Time spent setting up JavaScript contexts.",
+                |s| s.context_setup_seconds,
+            ),
+            (
+                "script_cache_entries",
+                "gauge",
+                "Compiled scripts and modules in memory.",
+                |s| s.cache_entries as f64,
+            ),
+            (
+                "script_pool_size",
+                "gauge",
+                "Threads of the JavaScript pool.",
+                |s| s.pool_size as f64,
+            ),
+            (
+                "script_pool_idle",
+                "gauge",
+                "Pooled threads with a context ready.",
+                |s| s.pool_idle as f64,
+            ),
+            (
+                "script_cold_starts_total",
+                "counter",
+                "Scripts that found no warm context.",
+                |s| s.cold_starts as f64,
+            ),
+            (
+                "script_websockets",
+                "gauge",
+                "Connected WebSockets of JavaScript handlers.",
+                |s| s.websockets as f64,
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for s in &stats {
+                let tenant = match &s.tenant {
+                    Some(tenant) => format!(",tenant=\"{}\"", label(tenant)),
+                    None => String::new(),
+                };
+                let _ = writeln!(
+                    out,
+                    "{}{{engine=\"{}\",prefix=\"{}\"{}}} {}",
+                    name,
+                    s.engine,
+                    label(&s.prefix),
+                    tenant,
+                    value(s)
+                );
+            }
+        }
+        let quotas: [(&str, &str, fn(&Quota) -> f64); 3] = [
+            (
+                "tenant_requests_total",
+                "Requests served to a tenant.",
+                |q| q.requests() as f64,
+            ),
+            (
+                "tenant_throttled_total",
+                "Requests refused to a tenant past its quota.",
+                |q| q.throttled() as f64,
+            ),
+            (
+                "tenant_script_seconds_total",
+                "Time spent running the scripts of a tenant.",
+                |q| q.script_seconds(),
+            ),
+        ];
+        for (name, help, value) in quotas {
+            if self.quotas.is_empty() {
+                break;
+            }
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for q in &self.quotas {
+                let _ = writeln!(
+                    out,
+                    "{}{{tenant=\"{}\"}} {}",
+                    name,
+                    label(q.name()),
+                    value(q)
+                );
+            }
+        }
+        if let Some(bytes) = resident_bytes() {
+            let _ = writeln!(
+                out,
+                "# HELP process_resident_memory_bytes Resident memory of the process.\n\
+                 # TYPE process_resident_memory_bytes gauge\n\
+                 process_resident_memory_bytes {}",
+                bytes
+            );
+        }
+        out
+    }
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The resident memory of the process, where `/proc` tells it.
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[async_trait::async_trait]
+impl<State> Endpoint<State> for Metrics
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn call(&self, _: Request<State>) -> tide::Result {
+        Ok(Response::builder(StatusCode::Ok)
+            .content_type("text/plain; version=0.0.4")
+            .body(self.render())
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let counters = Arc::new(Counters::default());
+        counters.ran("a.js", Duration::from_millis(1500), false);
+        counters.ran("a.js", Duration::from_millis(500), true);
+        counters.compiled(Duration::from_millis(2));
+        let c = counters.clone();
+        let metrics = Metrics::new().watch(move || EngineStats {
+            pool_size: 4,
+            ..c.stats("js", "/js/*")
+        });
+        let text = metrics.render();
+        assert!(text.contains("# TYPE script_runs_total counter\n"));
+        assert!(text.contains("script_runs_total{engine=\"js\",prefix=\"/js/*\"} 2\n"));
+        assert!(text.contains("script_failures_total{engine=\"js\",prefix=\"/js/*\"} 1\n"));
+        assert!(text.contains("script_run_seconds_total{engine=\"js\",prefix=\"/js/*\"} 2\n"));
+        assert!(text.contains("script_pool_size{engine=\"js\",prefix=\"/js/*\"} 4\n"));
+        assert_eq!(metrics.stats()[0].compile_seconds, 0.002);
+        assert!(!text.contains("tenant_"));
+        let text = metrics.with_quota(Arc::new(Quota::new("shop"))).render();
+        assert!(text.contains("tenant_throttled_total{tenant=\"shop\"} 0\n"));
+
+        counters.failed("a.js", "TypeError: x is undefined", Some("at a.js:3:7"));
+        let report = counters.report(counters.stats("js", "/js/*"));
+        assert_eq!(report.scripts[0].runs, 2);
+        assert_eq!(report.scripts[0].failures, 1);
+        assert_eq!(report.errors[0].stack.as_deref(), Some("at a.js:3:7"));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::capabilities::{self, Capability};
+use crate::rt;
+use rhai::{Blob, Dynamic, EvalAltResult, ImmutableString};
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use serde::Deserialize;
+
+/// Settings of the S3-compatible bucket exposed to scripts as `s3`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000` for minio
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Address the bucket as `endpoint/bucket` rather than `bucket.endpoint`.
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    /// Lifetime in seconds of urls returned by `s3.presign`.
+    #[serde(default = "default_presign_expiry")]
+    pub presign_expiry: u32,
+    /// Largest size in bytes of an object scripts may put.
+    #[serde(default = "default_max_object_size")]
+    pub max_object_size: u64,
+}
+
+fn default_region() -> String {
+    "us-east-1".into()
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+fn default_presign_expiry() -> u32 {
+    3600
+}
+
+fn default_max_object_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+#[derive(Debug, Clone)]
+pub struct Storage {
+    bucket: Bucket,
+    presign_expiry: u32,
+    max_object_size: u64,
+}
+
+fn s3_error(e: s3::error::S3Error) -> Box<EvalAltResult> {
+    log::error!("S3 Error: {}", e);
+    "S3 Error".into()
+}
+
+/// `key` if it names an object of the bucket. With path-style addressing a
+/// `..` segment would reach into other buckets once the url is normalized.
+fn object_key(key: &str) -> Result<&str, Box<EvalAltResult>> {
+    let relative = key
+        .split('/')
+        .any(|segment| segment == ".." || segment == ".");
+    if key.is_empty()
+        || key.starts_with('/')
+        || key.contains('\\')
+        || relative
+        || key.chars().any(char::is_control)
+    {
+        return Err(format!("Invalid S3 key '{}'", key).into());
+    }
+    Ok(key)
+}
+
+impl Storage {
+    pub fn new(config: S3Config) -> Result<Self, s3::error::S3Error> {
+        let region = Region::Custom {
+            region: config.region,
+            endpoint: config.endpoint,
+        };
+        let credentials = Credentials::new(
+            Some(&config.access_key),
+            Some(&config.secret_key),
+            None,
+            None,
+            None,
+        )?;
+        let mut bucket = Bucket::new(&config.bucket, region, credentials)?;
+        if config.path_style {
+            bucket = bucket.with_path_style();
+        }
+        Ok(Self {
+            bucket,
+            presign_expiry: config.presign_expiry,
+            max_object_size: config.max_object_size,
+        })
+    }
+
+    fn put_bytes(
+        &mut self,
+        key: &str,
+        bytes: &[u8],
+        content_type: &str,
+    ) -> Result<(), Box<EvalAltResult>> {
+        capabilities::require(Capability::Storage)?;
+        let key = object_key(key)?;
+        if bytes.len() as u64 > self.max_object_size {
+            return Err(format!(
+                "S3 object '{}' is larger than {} bytes",
+                key, self.max_object_size
+            )
+            .into());
+        }
+        let res = rt::block_on(
+            self.bucket
+                .put_object_with_content_type(key, bytes, content_type),
+        )
+        .map_err(s3_error)?;
+        match res.status_code() {
+            200..=299 => Ok(()),
+            code => Err(format!("S3 put failed with status {}", code).into()),
+        }
+    }
+
+    /// Fetches an object, `None` if it does not exist.
+    fn get_bytes(&mut self, key: &str) -> Result<Option<Vec<u8>>, Box<EvalAltResult>> {
+        capabilities::require(Capability::Storage)?;
+        let res = rt::block_on(self.bucket.get_object(object_key(key)?)).map_err(s3_error)?;
+        match res.status_code() {
+            200..=299 => Ok(Some(res.bytes().to_vec())),
+            404 => Ok(None),
+            code => Err(format!("S3 get failed with status {}", code).into()),
+        }
+    }
+
+    /// `s3.put(key, body)` where body is a string or a blob.
+    pub fn put(&mut self, key: ImmutableString, body: Dynamic) -> Result<(), Box<EvalAltResult>> {
+        self.put_typed(key, body, "application/octet-stream".into())
+    }
+
+    /// `s3.put(key, body, content_type)`
+    pub fn put_typed(
+        &mut self,
+        key: ImmutableString,
+        body: Dynamic,
+        content_type: ImmutableString,
+    ) -> Result<(), Box<EvalAltResult>> {
+        if body.is::<Blob>() {
+            let bytes = body.cast::<Blob>();
+            self.put_bytes(key.as_str(), &bytes, content_type.as_str())
+        } else if body.is::<ImmutableString>() {
+            let s = body.cast::<ImmutableString>();
+            self.put_bytes(key.as_str(), s.as_bytes(), content_type.as_str())
+        } else {
+            Err(format!("Cannot store {} in S3", body.type_name()).into())
+        }
+    }
+
+    /// `s3.get(key)` returns the object as a string, or `()` if it is missing.
+    pub fn get(&mut self, key: ImmutableString) -> Result<Dynamic, Box<EvalAltResult>> {
+        match self.get_bytes(key.as_str())? {
+            Some(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => Ok(s.into()),
+                Err(_) => Err(format!("S3 object '{}' is not valid UTF-8", key).into()),
+            },
+            None => Ok(Dynamic::UNIT),
+        }
+    }
+
+    /// `s3.get_blob(key)` returns the raw object bytes, or `()` if it is missing.
+    pub fn get_blob(&mut self, key: ImmutableString) -> Result<Dynamic, Box<EvalAltResult>> {
+        match self.get_bytes(key.as_str())? {
+            Some(bytes) => Ok(Dynamic::from_blob(bytes)),
+            None => Ok(Dynamic::UNIT),
+        }
+    }
+
+    pub fn delete(&mut self, key: ImmutableString) -> Result<(), Box<EvalAltResult>> {
+        capabilities::require(Capability::Storage)?;
+        let key = object_key(key.as_str())?;
+        let res = rt::block_on(self.bucket.delete_object(key)).map_err(s3_error)?;
+        match res.status_code() {
+            200..=299 | 404 => Ok(()),
+            code => Err(format!("S3 delete failed with status {}", code).into()),
+        }
+    }
+
+    /// `s3.presign(key)` returns a url clients can download the object from.
+    pub fn presign(&mut self, key: ImmutableString) -> Result<ImmutableString, Box<EvalAltResult>> {
+        self.presign_method(key, "GET".into())
+    }
+
+    /// `s3.presign(key, "PUT")` returns a url clients can upload the object to.
+    pub fn presign_method(
+        &mut self,
+        key: ImmutableString,
+        method: ImmutableString,
+    ) -> Result<ImmutableString, Box<EvalAltResult>> {
+        capabilities::require(Capability::Storage)?;
+        let key = object_key(key.as_str())?;
+        let url = match method.to_uppercase().as_str() {
+            "GET" => self.bucket.presign_get(key, self.presign_expiry, None),
+            "PUT" => self.bucket.presign_put(key, self.presign_expiry, None),
+            m => return Err(format!("Cannot presign {} requests", m).into()),
+        };
+        url.map(|u| u.into()).map_err(s3_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tide::listener::Listener;
+
+    type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// The `uploads` bucket of a fake S3 server keeping its objects in
+    /// memory, and those objects.
+    fn bucket(max_object_size: u64) -> (Storage, Objects) {
+        let objects = Objects::default();
+        let mut app = tide::with_state(objects.clone());
+        let key = |req: &tide::Request<Objects>| {
+            let path = req.url().path();
+            path.trim_start_matches("/uploads/").to_string()
+        };
+        app.at("/uploads/*")
+            .put(move |mut req: tide::Request<Objects>| async move {
+                let body = req.body_bytes().await?;
+                req.state().lock().unwrap().insert(key(&req), body);
+                Ok(tide::Response::builder(200).header("etag", "\"1\"").build())
+            })
+            .get(move |req: tide::Request<Objects>| async move {
+                let object = req.state().lock().unwrap().get(&key(&req)).cloned();
+                Ok(match object {
+                    Some(body) => tide::Response::builder(200).body(body).build(),
+                    None => tide::Response::new(404),
+                })
+            });
+        let mut listener = rt::block_on(app.bind("127.0.0.1:0")).unwrap();
+        let endpoint = listener.info()[0].connection().to_string();
+        rt::spawn(async move { listener.accept().await });
+        let storage = Storage::new(S3Config {
+            endpoint,
+            region: default_region(),
+            bucket: "uploads".into(),
+            access_key: "key".into(),
+            secret_key: "secret".into(),
+            path_style: true,
+            presign_expiry: default_presign_expiry(),
+            max_object_size,
+        })
+        .unwrap();
+        (storage, objects)
+    }
+
+    #[test]
+    fn puts_within_the_quota() {
+        let (mut s3, objects) = bucket(5);
+        s3.put("docs/a.txt".into(), "hello".into()).unwrap();
+        assert_eq!(objects.lock().unwrap()["docs/a.txt"], b"hello");
+        let got = s3.get("docs/a.txt".into()).unwrap();
+        assert_eq!(got.into_string().unwrap(), "hello");
+        assert!(s3.get("missing.txt".into()).unwrap().is_unit());
+    }
+
+    #[test]
+    fn rejects_objects_over_the_quota() {
+        let (mut s3, objects) = bucket(4);
+        let e = s3.put("a.txt".into(), "hello".into()).unwrap_err();
+        assert!(e.to_string().contains("larger than 4 bytes"), "{}", e);
+        let blob = Dynamic::from_blob(vec![0; 5]);
+        assert!(s3.put("a.bin".into(), blob).is_err());
+        assert!(objects.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_keys_leaving_the_bucket() {
+        let (mut s3, objects) = bucket(1024);
+        for key in [
+            "../other/a.txt",
+            "docs/../../a.txt",
+            "/a.txt",
+            "./a.txt",
+            "",
+        ] {
+            assert!(s3.put(key.into(), "x".into()).is_err(), "{}", key);
+            assert!(s3.get(key.into()).is_err(), "{}", key);
+            assert!(s3.delete(key.into()).is_err(), "{}", key);
+            assert!(s3.presign(key.into()).is_err(), "{}", key);
+        }
+        assert!(objects.lock().unwrap().is_empty());
+        assert!(s3.presign("docs/a.txt".into()).is_ok());
+    }
+
+    #[test]
+    fn requires_the_storage_capability() {
+        let (mut s3, objects) = bucket(1024);
+        let grant = |list: &str| {
+            let source = format!("// capabilities: {}", list);
+            capabilities::declared(std::path::Path::new("a.rhai"), "a.rhai", &source).unwrap()
+        };
+        capabilities::run(grant("db, fs"), || {
+            let e = s3.put("a.txt".into(), "x".into()).unwrap_err();
+            assert!(e.to_string().contains("the storage capability"), "{}", e);
+            assert!(s3.get("a.txt".into()).is_err());
+            assert!(s3.delete("a.txt".into()).is_err());
+            assert!(s3.presign("a.txt".into()).is_err());
+        });
+        assert!(objects.lock().unwrap().is_empty());
+        capabilities::run(grant("storage"), || s3.put("a.txt".into(), "x".into())).unwrap();
+        assert_eq!(objects.lock().unwrap()["a.txt"], b"x");
+    }
+}
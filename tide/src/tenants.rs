@@ -0,0 +1,153 @@
+//! [`Tenants`], an endpoint serving several apps from one server, picking
+//! the app of a request by its host. Every app is an endpoint of its own,
+//! like a [`RhaiDir`](crate::RhaiDir) of its script root with its own
+//! engines and data, so the apps share nothing but the process.
+//!
+//! Domains are matched without their port and case, either in full or, for
+//! those starting with `*.`, as any subdomain of the rest:
+//!
+//!```text
+//! shop.example.com     shop.example.com, SHOP.example.com:8080
+//! *.blog.example.com   ann.blog.example.com, not blog.example.com
+//!```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tide::{Endpoint, Request, Response, StatusCode};
+
+/// Answers every request with the app of its host, or with the fallback
+/// for the hosts of no app.
+///```
+/// use tide_rhai::{RhaiDir, Tenants};
+/// let shop = RhaiDir::new("/*", "./examples/app/").unwrap();
+/// let blog = RhaiDir::new("/*", "./examples/app/").unwrap();
+/// let mut app = tide::new();
+/// app.at("/*").get(
+///     Tenants::new()
+///         .tenant(["shop.example.com"], shop)
+///         .tenant(["blog.example.com", "*.blog.example.com"], blog),
+/// );
+///```
+pub struct Tenants<State> {
+    hosts: HashMap<String, Arc<dyn Endpoint<State>>>,
+    /// Suffixes of the wildcard domains, `.blog.example.com` for
+    /// `*.blog.example.com`, longest first.
+    wildcards: Vec<(String, Arc<dyn Endpoint<State>>)>,
+    fallback: Option<Arc<dyn Endpoint<State>>>,
+}
+
+impl<State: Clone + Send + Sync + 'static> Default for Tenants<State> {
+    fn default() -> Self {
+        Self {
+            hosts: HashMap::new(),
+            wildcards: Vec::new(),
+            fallback: None,
+        }
+    }
+}
+
+/// The host of `host`, a `Host` header, without its port and in lowercase.
+fn hostname(host: &str) -> String {
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or(v6),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl<State: Clone + Send + Sync + 'static> Tenants<State> {
+    /// Answers 404 until tenants are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the requests for `domains` with `app`.
+    pub fn tenant(
+        mut self,
+        domains: impl IntoIterator<Item = impl AsRef<str>>,
+        app: impl Endpoint<State>,
+    ) -> Self {
+        let app: Arc<dyn Endpoint<State>> = Arc::new(app);
+        for domain in domains {
+            let domain = domain.as_ref().trim().to_ascii_lowercase();
+            match domain.strip_prefix('*') {
+                Some(suffix) => self.wildcards.push((suffix.to_string(), app.clone())),
+                None => {
+                    self.hosts.insert(domain, app.clone());
+                }
+            }
+        }
+        self.wildcards.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        self
+    }
+
+    /// Serves the requests for the hosts of no tenant with `app`, like the
+    /// app of a server that has tenants besides it.
+    pub fn fallback(mut self, app: impl Endpoint<State>) -> Self {
+        self.fallback = Some(Arc::new(app));
+        self
+    }
+
+    /// The app serving `host`.
+    fn app(&self, host: Option<&str>) -> Option<&Arc<dyn Endpoint<State>>> {
+        let Some(host) = host.map(hostname) else {
+            return self.fallback.as_ref();
+        };
+        self.hosts
+            .get(&host)
+            .or_else(|| {
+                self.wildcards
+                    .iter()
+                    .find(|(suffix, _)| host.ends_with(suffix.as_str()))
+                    .map(|(_, app)| app)
+            })
+            .or(self.fallback.as_ref())
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for Tenants<State> {
+    async fn call(&self, req: Request<State>) -> tide::Result {
+        match self.app(req.host()) {
+            Some(app) => app.call(req).await,
+            None => Ok(Response::new(StatusCode::NotFound)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide_testing::TideTestingExt;
+
+    fn answer(name: &'static str) -> impl Endpoint<()> {
+        move |_: Request<()>| async move { Ok::<_, tide::Error>(name) }
+    }
+
+    #[async_std::test]
+    async fn routes_by_host() {
+        let mut app = tide::new();
+        app.at("/*").get(
+            Tenants::new()
+                .tenant(["shop.example.com"], answer("shop"))
+                .tenant(["*.example.com"], answer("any"))
+                .tenant(["*.blog.example.com"], answer("blog")),
+        );
+        let host = |host: &'static str| {
+            let app = app.clone();
+            async move { app.get("/").header("host", host).await.unwrap() }
+        };
+        let mut res = host("SHOP.example.com:8080").await;
+        assert_eq!(res.body_string().await.unwrap(), "shop");
+        let mut res = host("ann.blog.example.com").await;
+        assert_eq!(res.body_string().await.unwrap(), "blog");
+        let mut res = host("www.example.com").await;
+        assert_eq!(res.body_string().await.unwrap(), "any");
+        assert_eq!(host("example.org").await.status(), StatusCode::NotFound);
+
+        let mut app = tide::new();
+        app.at("/*").get(Tenants::new().fallback(answer("default")));
+        let got = app.get("/").header("host", "[::1]:80").recv_string().await;
+        assert_eq!(got.unwrap(), "default");
+    }
+}
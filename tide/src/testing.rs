@@ -0,0 +1,337 @@
+//! `rustvm test`: tests of an app, written as scripts next to its handlers.
+//! `*_test.rhai` files are run by [`RhaiDir::run_tests`], `*.test.js` and
+//! `*.test.ts` files by [`JsDir::run_tests`](crate::JsDir::run_tests). Test
+//! files are never served.
+//!
+//! Both get assertions and `request`, which runs a handler of the app with a
+//! made up request and returns the status and body it answered with.
+
+use crate::error_page::HttpError;
+use crate::http_utils::ScriptResponse;
+use crate::request::ScriptRequest;
+use crate::{from_script_value, thrown_http_error, to_script_value, Context, RhaiDir};
+use rhai::{Dynamic, Engine, EvalAltResult, Map, NativeCallContext, Scope};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How one test went.
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub file: PathBuf,
+    /// The test function, or the file for a file without any.
+    pub name: String,
+    /// Why it failed, `None` if it passed.
+    pub error: Option<String>,
+    pub took: Duration,
+}
+
+/// The results of the tests of a directory, shown one line per test and a
+/// count at the end.
+#[derive(Debug, Clone, Default)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.error.is_none()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    /// Adds the results of `other`, for the report of a whole app.
+    pub fn merge(&mut self, other: TestReport) {
+        self.results.extend(other.results);
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.results {
+            let ms = r.took.as_secs_f64() * 1000.0;
+            match &r.error {
+                None => writeln!(f, "ok   {} {} ({:.1} ms)", r.file.display(), r.name, ms)?,
+                Some(e) => writeln!(f, "FAIL {} {}: {}", r.file.display(), r.name, e)?,
+            }
+        }
+        writeln!(f, "{} passed, {} failed", self.passed(), self.failed())
+    }
+}
+
+/// The name of the test of a file without test functions.
+pub(crate) const FILE: &str = "(file)";
+
+/// Runs the test `name` of `file`.
+pub(crate) fn timed(
+    file: &Path,
+    name: &str,
+    test: impl FnOnce() -> Result<(), String>,
+) -> TestResult {
+    let started = Instant::now();
+    let error = test().err();
+    TestResult {
+        file: file.to_path_buf(),
+        name: name.to_string(),
+        error,
+        took: started.elapsed(),
+    }
+}
+
+/// Whether the file at `path` holds tests rather than a handler.
+pub(crate) fn is_test(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    name.ends_with("_test.rhai") || name.ends_with(".test.js") || name.ends_with(".test.ts")
+}
+
+/// The files below `dir` whose name `wanted` accepts, sorted, leaving out
+/// hidden directories.
+pub(crate) fn discover(dir: &Path, wanted: fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if path.is_dir() {
+                if !name.starts_with('.') {
+                    dirs.push(path);
+                }
+            } else if wanted(&name) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// `path` of a request as a file of the app in `dir`, `None` outside of it.
+pub(crate) fn app_file(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path.trim_start_matches('/'));
+    let outside = path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    (!outside).then(|| dir.join(path))
+}
+
+impl RhaiDir {
+    /// Runs the `*_test.rhai` files of the directory. Each function of a
+    /// file named `test_*` without parameters is a test, run by name after
+    /// the rest of the file, and a file without any is a test as a whole.
+    ///
+    /// Besides the bindings of handlers tests get `assert(cond)`,
+    /// `assert(cond, message)`, `assert_eq(left, right)`, `assert_ne` and
+    /// `request(path)`, `request(method, path, body)` or
+    /// `request(method, path, body, headers)`, which run the handler at
+    /// `path` and return `#{ status, body }`.
+    ///```
+    /// use tide_rhai::RhaiDir;
+    /// let dir = RhaiDir::new("/*", "./examples/app/").unwrap();
+    /// let report = dir.run_tests();
+    /// print!("{}", report);
+    ///```
+    pub fn run_tests(&self) -> TestReport {
+        let mut engine = self.build_engine();
+        register_assertions(&mut engine);
+        let (dir, scope, hooks) = (self.dir.clone(), self.scope(), self.scope_hooks.clone());
+        let handler = move |context: NativeCallContext,
+                            method: &str,
+                            path: &str,
+                            body: Dynamic,
+                            headers: Map| {
+            let mut scope = scope.clone();
+            mock_request(&mut scope, &hooks, method, path, &body, &headers)?;
+            request(context.engine(), &dir, &mut scope, path)
+        };
+        let h = handler.clone();
+        engine.register_result_fn("request", move |c: NativeCallContext, path: &str| {
+            h(c, "GET", path, Dynamic::UNIT, Map::new())
+        });
+        let h = handler.clone();
+        engine.register_result_fn(
+            "request",
+            move |c: NativeCallContext, method: &str, path: &str, body: Dynamic| {
+                h(c, method, path, body, Map::new())
+            },
+        );
+        engine.register_result_fn("request", handler);
+
+        let mut report = TestReport::default();
+        for file in discover(&self.dir, |n| n.ends_with("_test.rhai")) {
+            let name = file.strip_prefix(&self.dir).unwrap_or(&file);
+            let ast = std::fs::read_to_string(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|source| engine.compile(source).map_err(|e| e.to_string()));
+            let ast = match ast {
+                Ok(ast) => ast,
+                Err(e) => {
+                    report.results.push(timed(name, FILE, || Err(e)));
+                    continue;
+                }
+            };
+            let mut tests: Vec<String> = ast
+                .iter_functions()
+                .filter(|f| f.name.starts_with("test_") && f.params.is_empty())
+                .map(|f| f.name.to_string())
+                .collect();
+            tests.sort();
+            if tests.is_empty() {
+                report.results.push(timed(name, FILE, || {
+                    engine
+                        .run_ast_with_scope(&mut self.test_scope(), &ast)
+                        .map_err(|e| e.to_string())
+                }));
+            }
+            for test in &tests {
+                report.results.push(timed(name, test, || {
+                    engine
+                        .call_fn::<Dynamic>(&mut self.test_scope(), &ast, test, ())
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                }));
+            }
+        }
+        report
+    }
+
+    /// The scope of tests, with the `ctx` and `req` of a `GET /` without
+    /// headers.
+    fn test_scope(&self) -> Scope<'static> {
+        let mut scope = self.scope();
+        let ctx = Context::from_parts(HashMap::new(), http_types::Method::Get, &[]);
+        scope.push("ctx", to_script_value(&ctx).unwrap_or_default());
+        let url = tide::http::Url::parse("http://localhost/").expect("a valid url");
+        let req = ScriptRequest::new(http_types::Method::Get, url, Vec::new(), Vec::new());
+        scope.push("req", req);
+        scope
+    }
+}
+
+/// Adds the `ctx` and `req` of a made up request to `scope`, after the
+/// hooks of [`RhaiDir::with_scope`] saw it.
+fn mock_request(
+    scope: &mut Scope<'static>,
+    hooks: &[crate::ScopeHook],
+    method: &str,
+    path: &str,
+    body: &Dynamic,
+    headers: &Map,
+) -> Result<(), Box<EvalAltResult>> {
+    let method: http_types::Method = method
+        .parse()
+        .map_err(|_| format!("Unknown method {}", method))?;
+    let url = tide::http::Url::parse("http://localhost/")
+        .and_then(|base| base.join(path))
+        .map_err(|e| format!("Invalid path {}: {}", path, e))?;
+    let mut req = tide::http::Request::new(method, url.clone());
+    let mut pairs = Vec::new();
+    for (name, value) in headers {
+        let value = value.to_string();
+        req.insert_header(name.as_str(), value.as_str());
+        pairs.push((name.to_string(), value));
+    }
+    for hook in hooks {
+        hook(&req, scope);
+    }
+    let body = if body.is_unit() {
+        Vec::new()
+    } else {
+        serde_json::to_vec(&from_script_value::<Value>(body)?).map_err(|e| e.to_string())?
+    };
+    let req = ScriptRequest::new(method, url, pairs, body);
+    let ctx = Context::from_parts(req.header_map(), method, req.body());
+    scope.push("ctx", to_script_value(&ctx)?);
+    scope.push("req", req);
+    Ok(())
+}
+
+/// Runs the handler at `path`, answering `#{ status, body }` like the
+/// endpoint would.
+fn request(
+    engine: &Engine,
+    dir: &Path,
+    scope: &mut Scope<'static>,
+    path: &str,
+) -> Result<Map, Box<EvalAltResult>> {
+    let route = path.split(['?', '#']).next().unwrap_or_default();
+    let file = app_file(dir, route)
+        .filter(|f| !is_test(f))
+        .ok_or_else(|| format!("Cannot request {}", path))?;
+    let source =
+        std::fs::read_to_string(&file).map_err(|e| format!("Cannot request {}: {}", path, e))?;
+    let evaluated = engine
+        .compile(&source)
+        .map_err(Into::into)
+        .and_then(|ast| crate::handlers::dispatch(engine, scope, &ast));
+    let (status, body) = match evaluated {
+        Ok(v) if v.is::<HttpError>() => {
+            let e = v.cast::<HttpError>();
+            (e.status, to_script_value(&e.body())?)
+        }
+        Ok(v) if v.is::<ScriptResponse>() => {
+            let r = v.cast::<ScriptResponse>();
+            (r.status, r.body())
+        }
+        Ok(v) => (200, v),
+        Err(e) => match thrown_http_error(&e) {
+            Some(h) => (h.status, to_script_value(&h.body())?),
+            None => {
+                let mut body = Map::new();
+                body.insert("error".into(), e.to_string().into());
+                (500, body.into())
+            }
+        },
+    };
+    let mut res = Map::new();
+    res.insert("status".into(), (status as i64).into());
+    res.insert("body".into(), body);
+    Ok(res)
+}
+
+fn register_assertions(engine: &mut Engine) {
+    engine.register_result_fn("assert", |cond: bool| check(cond, "assertion failed"));
+    engine.register_result_fn("assert", |cond: bool, message: &str| check(cond, message));
+    engine.register_result_fn("assert_eq", |left: Dynamic, right: Dynamic| {
+        check(
+            same(&left, &right),
+            &format!("expected {:?} to equal {:?}", left, right),
+        )
+    });
+    engine.register_result_fn("assert_ne", |left: Dynamic, right: Dynamic| {
+        check(
+            !same(&left, &right),
+            &format!("expected {:?} not to equal {:?}", left, right),
+        )
+    });
+}
+
+fn check(cond: bool, message: &str) -> Result<(), Box<EvalAltResult>> {
+    if cond {
+        Ok(())
+    } else {
+        Err(message.into())
+    }
+}
+
+/// Equal as json, so maps compare by their entries, or else by how they
+/// are shown.
+fn same(left: &Dynamic, right: &Dynamic) -> bool {
+    match (
+        from_script_value::<Value>(left),
+        from_script_value::<Value>(right),
+    ) {
+        (Ok(l), Ok(r)) => l == r,
+        _ => left.type_name() == right.type_name() && left.to_string() == right.to_string(),
+    }
+}
@@ -0,0 +1,152 @@
+//! Where a rhai script was when it failed. The debugging interface of the
+//! engine tells every statement before it runs, so the last one seen on a
+//! thread is the one that failed: in dev mode it is shown on the error page
+//! with the variables at that point, and an exhausted operation budget
+//! names it in its error.
+//!
+//! Seeing the variables means copying them at every statement, which is
+//! only done in dev mode.
+
+use rhai::debugger::DebuggerEvent;
+use rhai::{ASTNode, EvalContext, Position};
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// The statement a script was running.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Location {
+    /// The file of the script or module, as set with `AST::set_source`.
+    pub source: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    /// Names and values, innermost last, only in dev mode.
+    pub variables: Vec<(String, String)>,
+}
+
+thread_local! {
+    static LAST: RefCell<Option<Location>> = RefCell::new(None);
+}
+
+/// Longest value shown for a variable.
+const MAX_VALUE: usize = 200;
+
+/// Runs `f` and returns what it returned and the last statement it ran.
+pub(crate) fn run<T>(f: impl FnOnce() -> T) -> (T, Option<Location>) {
+    let outer = LAST.with(|l| l.replace(None));
+    let res = f();
+    let last = LAST.with(|l| l.replace(outer));
+    (res, last)
+}
+
+/// Notes where the script run by [`run`] on this thread is, copying its
+/// variables if `variables` is set.
+pub(crate) fn step(
+    context: &EvalContext,
+    event: &DebuggerEvent,
+    node: &ASTNode,
+    source: Option<&str>,
+    pos: Position,
+    variables: bool,
+) {
+    let (DebuggerEvent::Step, ASTNode::Stmt(_), Some(line)) = (event, node, pos.line()) else {
+        return;
+    };
+    let variables = if variables {
+        context
+            .scope()
+            .iter()
+            .map(|(name, _, value)| {
+                let mut value = format!("{:?}", value);
+                if value.len() > MAX_VALUE {
+                    let end = (0..=MAX_VALUE)
+                        .rev()
+                        .find(|i| value.is_char_boundary(*i))
+                        .unwrap_or(0);
+                    value.truncate(end);
+                    value.push('…');
+                }
+                (name.to_string(), value)
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let location = Location {
+        source: source.map(str::to_string),
+        line,
+        column: pos.position().unwrap_or(0),
+        variables,
+    };
+    LAST.with(|l| *l.borrow_mut() = Some(location));
+}
+
+impl Location {
+    /// `file:line:column`, the file relative to `root`.
+    pub(crate) fn at(&self, root: &Path) -> String {
+        let file = self
+            .source
+            .as_deref()
+            .map(|s| Path::new(s).strip_prefix(root).unwrap_or(Path::new(s)))
+            .map_or("script".into(), |p| p.display().to_string());
+        format!("{}:{}:{}", file, self.line, self.column)
+    }
+
+    /// The line of the statement and the variables, for the error page.
+    pub(crate) fn describe(&self, root: &Path) -> String {
+        let mut out = format!("at {}", self.at(root));
+        let line = self
+            .source
+            .as_ref()
+            .and_then(|s| std::fs::read_to_string(s).ok())
+            .and_then(|s| s.lines().nth(self.line - 1).map(str::to_string));
+        if let Some(line) = line {
+            let _ = write!(out, "\n\n{}", line);
+            if self.column > 0 {
+                let _ = write!(out, "\n{}^", " ".repeat(self.column - 1));
+            }
+        }
+        if !self.variables.is_empty() {
+            out.push_str("\n\nVariables:");
+            for (name, value) in &self.variables {
+                let _ = write!(out, "\n  {} = {}", name, value);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::debugger::DebuggerCommand;
+    use rhai::{Dynamic, Engine};
+
+    #[test]
+    fn last_statement() {
+        let mut engine = Engine::new();
+        engine.register_debugger(
+            |_| Dynamic::UNIT,
+            |context, event, node, source, pos| {
+                step(&context, &event, &node, source, pos, true);
+                Ok(DebuggerCommand::StepInto)
+            },
+        );
+        let mut ast = engine
+            .compile("let total = 40;\nlet name = \"ann\";\ntotal + name.len() / 0")
+            .unwrap();
+        ast.set_source("/app/orders.rhai");
+        let (res, last) = run(|| engine.eval_ast::<i64>(&ast));
+        assert!(res.is_err());
+        let last = last.unwrap();
+        assert_eq!(last.at(Path::new("/app")), "orders.rhai:3:1");
+        assert_eq!(
+            last.variables,
+            [
+                ("total".to_string(), "40".to_string()),
+                ("name".to_string(), "\"ann\"".to_string())
+            ]
+        );
+        assert!(last.describe(Path::new("/app")).contains("name = \"ann\""));
+    }
+}
@@ -0,0 +1,245 @@
+//! [`ResponseHooks`], a middleware rewriting responses once the handler
+//! answered, like injecting an analytics snippet into HTML pages or
+//! normalizing headers. Hooks are Rust functions or rhai scripts.
+//!
+//! A script gets the request as `req` and the response as `res`, a map of
+//! `status`, `headers` and, for text, `body`, and answers the map to send:
+//!
+//!```text
+//! if res.headers["content-type"].starts_with("text/html") {
+//!     res.body.replace("</body>", `<script src="/a.js"></script></body>`);
+//! }
+//! res.headers["x-frame-options"] = "DENY";
+//! res
+//!```
+//!
+//! Compression has to come after the hooks, so its middleware has to be
+//! added to the app before this one.
+
+use crate::request::ScriptRequest;
+use crate::{from_script_value, http_utils, request};
+use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, AST};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tide::http::headers::CONTENT_TYPE;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+/// A response on its way out, handed to the hooks in turn.
+pub struct Outgoing<'a> {
+    /// The request answered, without its body.
+    pub request: &'a tide::http::Request,
+    pub response: &'a mut Response,
+    /// The body of text responses, `None` for others, which are left as
+    /// they are.
+    pub body: Option<String>,
+}
+
+type Hook = Arc<dyn Fn(&mut Outgoing<'_>) -> tide::Result<()> + Send + Sync>;
+
+/// Runs its hooks in the order they were added on every response.
+///```
+/// use tide_rhai::ResponseHooks;
+/// let mut app = tide::new();
+/// app.with(ResponseHooks::new().hook(|out| {
+///     out.response.insert_header("x-frame-options", "DENY");
+///     if let Some(body) = &mut out.body {
+///         *body = body.replace("</body>", "<script src=\"/a.js\"></script></body>");
+///     }
+///     Ok(())
+/// }));
+///```
+#[derive(Default, Clone)]
+pub struct ResponseHooks {
+    hooks: Vec<Hook>,
+}
+
+impl ResponseHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn hook(
+        mut self,
+        hook: impl Fn(&mut Outgoing<'_>) -> tide::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Adds the rhai script at `path` as a hook, see the [module](self)
+    /// for what it gets. Fails if it does not compile.
+    pub fn script(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)?;
+        let mut engine = Engine::new();
+        engine.register_static_module("http", http_utils::module());
+        request::register(&mut engine);
+        let mut ast = engine.compile(source).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+        ast.set_source(path.to_string_lossy().as_ref());
+        let script = Arc::new((engine, ast));
+        Ok(self.hook(move |out| run_script(&script.0, &script.1, out)))
+    }
+}
+
+/// Whether a response of `content_type` is text hooks can rewrite.
+fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || ["json", "xml", "javascript"]
+            .iter()
+            .any(|t| content_type.contains(t))
+}
+
+fn run_script(engine: &Engine, ast: &AST, out: &mut Outgoing<'_>) -> tide::Result<()> {
+    let req = out.request;
+    let headers = req
+        .iter()
+        .flat_map(|(n, values)| values.iter().map(move |v| (n.to_string(), v.to_string())))
+        .collect();
+    let mut res = Map::new();
+    res.insert(
+        "status".into(),
+        (u16::from(out.response.status()) as i64).into(),
+    );
+    let headers_map: Map = out
+        .response
+        .iter()
+        .map(|(n, v)| (n.as_str().into(), v.last().as_str().into()))
+        .collect();
+    res.insert("headers".into(), headers_map.into());
+    if let Some(body) = &out.body {
+        res.insert("body".into(), body.clone().into());
+    }
+    let mut scope = Scope::new();
+    scope.push(
+        "req",
+        ScriptRequest::new(req.method(), req.url().clone(), headers, Vec::new()),
+    );
+    scope.push("res", res);
+    let answered = engine
+        .eval_ast_with_scope::<Dynamic>(&mut scope, ast)
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e.to_string()))?;
+    let Some(res) = answered.try_cast::<Map>() else {
+        return Ok(());
+    };
+    if let Some(status) = res.get("status").and_then(|s| s.as_int().ok()) {
+        let status = u16::try_from(status)
+            .ok()
+            .and_then(|s| StatusCode::try_from(s).ok())
+            .ok_or_else(|| {
+                tide::Error::from_str(
+                    StatusCode::InternalServerError,
+                    format!("{} is not an HTTP status", status),
+                )
+            })?;
+        out.response.set_status(status);
+    }
+    if let Some(headers) = res.get("headers").and_then(|h| h.read_lock::<Map>()) {
+        let names: Vec<_> = out.response.header_names().cloned().collect();
+        for name in names {
+            if !headers.contains_key(name.as_str()) {
+                out.response.remove_header(name);
+            }
+        }
+        for (name, value) in headers.iter() {
+            out.response.insert_header(name.as_str(), value.to_string());
+        }
+    }
+    if out.body.is_some() {
+        if let Some(body) = res.get("body") {
+            out.body = Some(match body.clone().try_cast::<ImmutableString>() {
+                Some(s) => s.to_string(),
+                None => serde_json::to_string(&from_script_value::<serde_json::Value>(body)?)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ResponseHooks {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if self.hooks.is_empty() {
+            return Ok(next.run(req).await);
+        }
+        let mut head = tide::http::Request::new(req.method(), req.url().clone());
+        for (name, values) in req.iter() {
+            for value in values {
+                head.append_header(name.as_str(), value.as_str());
+            }
+        }
+        let mut res = next.run(req).await;
+        let content_type = res.header(CONTENT_TYPE).map(|c| c.last().to_string());
+        let body = match &content_type {
+            Some(c) if is_text(c) => Some(res.take_body().into_string().await?),
+            _ => None,
+        };
+        let mut out = Outgoing {
+            request: &head,
+            response: &mut res,
+            body,
+        };
+        for hook in &self.hooks {
+            hook(&mut out)?;
+        }
+        if let Some(body) = out.body {
+            let content_type = res.header(CONTENT_TYPE).map(|c| c.last().to_string());
+            res.set_body(body);
+            if let Some(c) = content_type {
+                res.insert_header(CONTENT_TYPE, c);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide_testing::TideTestingExt;
+
+    #[async_std::test]
+    async fn rewrites() {
+        let script = std::env::temp_dir().join("tide_rhai_response_hook.rhai");
+        std::fs::write(
+            &script,
+            r#"res.body.replace("</body>", "<script></script></body>"); res.headers["x-path"] = req.path; res"#,
+        )
+        .unwrap();
+        let hooks = ResponseHooks::new()
+            .hook(|out| {
+                out.response.insert_header("x-hooked", "1");
+                Ok(())
+            })
+            .script(&script)
+            .unwrap();
+        let mut app = tide::new();
+        app.with(hooks);
+        app.at("/page").get(|_| async {
+            Ok::<_, tide::Error>(
+                Response::builder(200)
+                    .body("<body></body>")
+                    .content_type(tide::http::mime::HTML)
+                    .build(),
+            )
+        });
+        app.at("/blob")
+            .get(|_| async { Ok::<_, tide::Error>(tide::Body::from_bytes(vec![1, 2, 3])) });
+
+        let mut res = app.get("/page").await.unwrap();
+        assert_eq!(res.header("x-hooked").unwrap().as_str(), "1");
+        assert_eq!(res.header("x-path").unwrap().as_str(), "/page");
+        assert!(res.content_type().unwrap().essence() == "text/html");
+        assert_eq!(
+            res.body_string().await.unwrap(),
+            "<body><script></script></body>"
+        );
+        let mut res = app.get("/blob").await.unwrap();
+        assert_eq!(res.body_bytes().await.unwrap(), [1, 2, 3]);
+    }
+}
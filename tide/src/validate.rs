@@ -0,0 +1,432 @@
+//! Checks of the requests a script is called with, made before it runs.
+//! `_routes.json` in the app directory attaches schemas to the scripts, by
+//! their path below it, for the query string and the json body of every
+//! method or of one of them:
+//!
+//!```text
+//! {
+//!   "api/orders.rhai": {
+//!     "query": {"properties": {"page": {"type": "integer", "minimum": 1}}},
+//!     "post": {
+//!       "body": {
+//!         "type": "object",
+//!         "required": ["item", "quantity"],
+//!         "properties": {
+//!           "item": {"type": "string", "minLength": 1},
+//!           "quantity": {"type": "integer", "minimum": 1, "maximum": 100}
+//!         },
+//!         "additionalProperties": false
+//!       }
+//!     }
+//!   }
+//! }
+//!```
+//!
+//! Schemas are a subset of JSON Schema: `type`, `enum`, `const`, `minimum`,
+//! `maximum`, `exclusiveMinimum`, `exclusiveMaximum`, `minLength`,
+//! `maxLength`, `properties`, `required`, `additionalProperties`, `items`,
+//! `minItems` and `maxItems`. The query is an object of its parameters,
+//! turned into the numbers, booleans and arrays their schemas ask for, and
+//! the body is only checked for `PUT`, `POST` and `PATCH`. Requests failing
+//! are answered with a 422 listing what is wrong:
+//!
+//!```text
+//! {"errors": [{"in": "body", "path": "/quantity", "message": "must be at least 1"}]}
+//!```
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use tide::{Response, StatusCode};
+use url::Url;
+
+/// The routing manifest in the app directory.
+pub(crate) const ROUTES: &str = "_routes.json";
+
+const KEYWORDS: [&str; 2] = ["query", "body"];
+
+/// What the requests of a method must look like.
+#[derive(Debug, Default, Clone)]
+struct Rule {
+    query: Option<Value>,
+    body: Option<Value>,
+}
+
+impl Rule {
+    fn parse(route: &Map<String, Value>, what: &str) -> Result<Self, String> {
+        let schema = |key: &str| match route.get(key) {
+            None => Ok(None),
+            Some(s) if s.is_object() || s.is_boolean() => Ok(Some(s.clone())),
+            Some(_) => Err(format!("the {} schema of {} is not an object", key, what)),
+        };
+        Ok(Self {
+            query: schema("query")?,
+            body: schema("body")?,
+        })
+    }
+}
+
+/// The rules of one script, for every method and by method.
+#[derive(Debug, Default)]
+struct Route {
+    all: Rule,
+    methods: HashMap<String, Rule>,
+}
+
+/// The schemas of `_routes.json`, by script.
+#[derive(Debug, Default)]
+pub(crate) struct Routes {
+    routes: HashMap<String, Route>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct Invalid {
+    #[serde(rename = "in")]
+    part: &'static str,
+    path: String,
+    message: String,
+}
+
+impl Routes {
+    /// The manifest of `dir`, none if it has no `_routes.json`.
+    pub(crate) fn load(dir: &Path) -> io::Result<Self> {
+        let text = match std::fs::read_to_string(dir.join(ROUTES)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        Self::parse(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", ROUTES, e)))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let manifest: Map<String, Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut routes = HashMap::new();
+        for (script, route) in manifest {
+            let route = route
+                .as_object()
+                .ok_or_else(|| format!("the route of {} is not an object", script))?;
+            let mut methods = HashMap::new();
+            for (method, rule) in route
+                .iter()
+                .filter(|(k, _)| !KEYWORDS.contains(&k.as_str()))
+            {
+                let what = format!("{} {}", method, script);
+                let rule = rule
+                    .as_object()
+                    .ok_or_else(|| format!("the route of {} is not an object", what))?;
+                methods.insert(method.to_ascii_lowercase(), Rule::parse(rule, &what)?);
+            }
+            let all = Rule::parse(route, &script)?;
+            routes.insert(script, Route { all, methods });
+        }
+        Ok(Self { routes })
+    }
+
+    /// A 422 if the request of `method` to `url` with `body` does not match
+    /// what the manifest says of `script`.
+    pub(crate) fn check(
+        &self,
+        script: &str,
+        method: &str,
+        url: &Url,
+        body: &[u8],
+    ) -> Option<Response> {
+        let route = self.routes.get(script)?;
+        let rule = route.methods.get(&method.to_ascii_lowercase());
+        let query = rule
+            .and_then(|r| r.query.as_ref())
+            .or(route.all.query.as_ref());
+        let body_schema = rule
+            .and_then(|r| r.body.as_ref())
+            .or(route.all.body.as_ref());
+        let mut errors = Vec::new();
+        if let Some(schema) = query {
+            let params = query_params(schema, url);
+            validate(schema, &params, "", &mut |path, message| {
+                errors.push(Invalid {
+                    part: "query",
+                    path,
+                    message,
+                })
+            });
+        }
+        let has_body = ["PUT", "POST", "PATCH"]
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method));
+        if let Some(schema) = body_schema.filter(|_| has_body) {
+            let mut push = |path, message| {
+                errors.push(Invalid {
+                    part: "body",
+                    path,
+                    message,
+                })
+            };
+            match serde_json::from_slice::<Value>(body) {
+                Ok(value) => validate(schema, &value, "", &mut push),
+                Err(e) => push(String::new(), format!("is not json: {}", e)),
+            }
+        }
+        if errors.is_empty() {
+            return None;
+        }
+        Some(
+            Response::builder(StatusCode::UnprocessableEntity)
+                .body(json!({ "errors": errors }))
+                .build(),
+        )
+    }
+}
+
+/// The query of `url` as an object, each parameter turned into what its
+/// schema among the `properties` of `schema` asks for. The last of repeated
+/// parameters wins, unless the schema is of an array.
+fn query_params(schema: &Value, url: &Url) -> Value {
+    let properties = schema.get("properties");
+    let mut params = Map::new();
+    for (name, raw) in url.query_pairs() {
+        let schema = properties.and_then(|p| p.get(name.as_ref()));
+        if type_of(schema) == Some("array") {
+            let items = schema.and_then(|s| s.get("items"));
+            let value = coerce(items, &raw);
+            if let Value::Array(values) =
+                params.entry(name.to_string()).or_insert_with(|| json!([]))
+            {
+                values.push(value);
+            }
+        } else {
+            params.insert(name.to_string(), coerce(schema, &raw));
+        }
+    }
+    Value::Object(params)
+}
+
+/// The single `type` of `schema`.
+fn type_of(schema: Option<&Value>) -> Option<&str> {
+    schema.and_then(|s| s.get("type")).and_then(Value::as_str)
+}
+
+/// `raw` as the type `schema` asks for, or as a string if it is not one.
+fn coerce(schema: Option<&Value>, raw: &str) -> Value {
+    let parsed = match type_of(schema) {
+        Some("integer") => raw.parse::<i64>().ok().map(Value::from),
+        Some("number") => raw
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        Some("boolean") => match raw {
+            "" | "true" | "1" => Some(Value::Bool(true)),
+            "false" | "0" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn name_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "an integer",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// What the `type` keyword `name` means, for the errors.
+fn describe(name: &str) -> &str {
+    match name {
+        "boolean" => "a boolean",
+        "integer" => "an integer",
+        "number" => "a number",
+        "string" => "a string",
+        "array" => "an array",
+        "object" => "an object",
+        name => name,
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("null", Value::Null)
+        | ("boolean", Value::Bool(_))
+        | ("number", Value::Number(_))
+        | ("string", Value::String(_))
+        | ("array", Value::Array(_))
+        | ("object", Value::Object(_)) => true,
+        ("integer", Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || n.as_f64().map_or(false, |f| f.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+/// Reports with `invalid` every way `value`, at the JSON pointer `path`,
+/// does not match `schema`.
+fn validate(schema: &Value, value: &Value, path: &str, invalid: &mut dyn FnMut(String, String)) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => return invalid(path.into(), "is not allowed".into()),
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|t| is_type(value, t)) {
+        let types: Vec<_> = types.iter().map(|t| describe(t)).collect();
+        let message = format!("must be {}, not {}", types.join(" or "), name_of(value));
+        return invalid(path.into(), message);
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            let options: Vec<_> = options.iter().map(Value::to_string).collect();
+            invalid(
+                path.into(),
+                format!("must be one of {}", options.join(", ")),
+            );
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            invalid(path.into(), format!("must be {}", expected));
+        }
+    }
+    let limit = |key: &str| schema.get(key).and_then(Value::as_f64);
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = limit("minimum").filter(|min| n < *min) {
+            invalid(path.into(), format!("must be at least {}", min));
+        }
+        if let Some(max) = limit("maximum").filter(|max| n > *max) {
+            invalid(path.into(), format!("must be at most {}", max));
+        }
+        if let Some(min) = limit("exclusiveMinimum").filter(|min| n <= *min) {
+            invalid(path.into(), format!("must be more than {}", min));
+        }
+        if let Some(max) = limit("exclusiveMaximum").filter(|max| n >= *max) {
+            invalid(path.into(), format!("must be less than {}", max));
+        }
+    }
+    let count = |key: &str| schema.get(key).and_then(Value::as_u64).map(|n| n as usize);
+    if let Some(s) = value.as_str() {
+        let len = s.chars().count();
+        if let Some(min) = count("minLength").filter(|min| len < *min) {
+            invalid(
+                path.into(),
+                format!("must have at least {} characters", min),
+            );
+        }
+        if let Some(max) = count("maxLength").filter(|max| len > *max) {
+            invalid(path.into(), format!("must have at most {} characters", max));
+        }
+    }
+    if let Some(items) = value.as_array() {
+        if let Some(min) = count("minItems").filter(|min| items.len() < *min) {
+            invalid(path.into(), format!("must have at least {} items", min));
+        }
+        if let Some(max) = count("maxItems").filter(|max| items.len() > *max) {
+            invalid(path.into(), format!("must have at most {} items", max));
+        }
+        if let Some(item) = schema.get("items") {
+            for (i, v) in items.iter().enumerate() {
+                validate(item, v, &format!("{}/{}", path, i), invalid);
+            }
+        }
+    }
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let required = schema.get("required").and_then(Value::as_array);
+        for name in required.into_iter().flatten().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                invalid(format!("{}/{}", path, name), "is required".into());
+            }
+        }
+        for (name, v) in object {
+            let at = format!("{}/{}", path, name);
+            match properties.and_then(|p| p.get(name)) {
+                Some(property) => validate(property, v, &at, invalid),
+                None => {
+                    if let Some(additional) = schema.get("additionalProperties") {
+                        validate(additional, v, &at, invalid);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validates_query_and_body() {
+        let routes = Routes::parse(
+            r#"{"orders.rhai": {
+                "query": {"properties": {"page": {"type": "integer", "minimum": 1},
+                                         "tag": {"type": "array", "items": {"type": "string"}}}},
+                "post": {"body": {
+                    "type": "object",
+                    "required": ["item", "quantity"],
+                    "properties": {"item": {"type": "string", "minLength": 1},
+                                   "quantity": {"type": "integer", "maximum": 100}},
+                    "additionalProperties": false}}}}"#,
+        )
+        .unwrap();
+        let url = |q: &str| Url::parse(&format!("http://localhost/orders.rhai?{}", q)).unwrap();
+        let body = br#"{"item": "tea", "quantity": 2}"#;
+        assert!(routes
+            .check("orders.rhai", "POST", &url("page=2"), body)
+            .is_none());
+        assert!(routes
+            .check("orders.rhai", "GET", &url("tag=a&tag=b"), b"")
+            .is_none());
+        assert!(routes
+            .check("other.rhai", "POST", &url("page=x"), b"")
+            .is_none());
+
+        let params = query_params(
+            &routes.routes["orders.rhai"].all.query.clone().unwrap(),
+            &url("page=3&tag=a&tag=b"),
+        );
+        assert_eq!(params, json!({"page": 3, "tag": ["a", "b"]}));
+
+        let mut errors = Vec::new();
+        let schema = routes.routes["orders.rhai"].methods["post"]
+            .body
+            .clone()
+            .unwrap();
+        let value = json!({"item": "", "quantity": 101.5, "note": 1});
+        validate(&schema, &value, "", &mut |path, message| {
+            errors.push((path, message))
+        });
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "/item".to_string(),
+                    "must have at least 1 characters".to_string()
+                ),
+                ("/note".into(), "is not allowed".into()),
+                (
+                    "/quantity".into(),
+                    "must be an integer, not a number".into()
+                ),
+            ]
+        );
+
+        let res = routes
+            .check("orders.rhai", "post", &url("page=0"), b"{")
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UnprocessableEntity);
+        assert!(Routes::parse(r#"{"a.rhai": {"body": 1}}"#).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Rust values to and from script values through serde, so bindings can take
+//! and return structs of their own without converting them by hand. Structs
+//! and maps become rhai maps and JavaScript objects, sequences arrays.
+//!
+//!```
+//! use serde::{Deserialize, Serialize};
+//! use tide_rhai::rhai::Dynamic;
+//! use tide_rhai::{from_script_value, to_script_value, RhaiDir};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Point {
+//!     x: i64,
+//!     y: i64,
+//! }
+//!
+//! let dir = RhaiDir::new("/*", "./examples/app/")
+//!     .unwrap()
+//!     .with_engine_setup(|engine| {
+//!         engine.register_result_fn("flip", |p: Dynamic| {
+//!             let p: Point = from_script_value(&p)?;
+//!             to_script_value(&Point { x: p.y, y: p.x })
+//!         });
+//!     });
+//!```
+
+use boa_engine::{JsNativeError, JsResult, JsValue};
+use rhai::{Dynamic, EvalAltResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// `value` as a rhai value.
+pub fn to_script_value<T: Serialize + ?Sized>(value: &T) -> Result<Dynamic, Box<EvalAltResult>> {
+    rhai::serde::to_dynamic(value)
+}
+
+/// A Rust value read from a rhai value, failing if it does not have the
+/// shape of `T`.
+pub fn from_script_value<T: DeserializeOwned>(value: &Dynamic) -> Result<T, Box<EvalAltResult>> {
+    rhai::serde::from_dynamic(value)
+}
+
+/// `value` as a JavaScript value of `context`.
+pub fn to_js_value<T: Serialize + ?Sized>(
+    value: &T,
+    context: &mut boa_engine::Context<'_>,
+) -> JsResult<JsValue> {
+    let json = serde_json::to_value(value)
+        .map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+    JsValue::from_json(&json, context)
+}
+
+/// A Rust value read from a JavaScript value, throwing a `TypeError` if it
+/// does not have the shape of `T`. `undefined` reads as `null`, so it fits
+/// an `Option`.
+pub fn from_js_value<T: DeserializeOwned>(
+    value: &JsValue,
+    context: &mut boa_engine::Context<'_>,
+) -> JsResult<T> {
+    let json = match value {
+        JsValue::Undefined => serde_json::Value::Null,
+        value => value.to_json(context)?,
+    };
+    serde_json::from_value(json)
+        .map_err(|e| JsNativeError::typ().with_message(e.to_string()).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use boa_engine::{Context, Source};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u32,
+        items: Vec<String>,
+        note: Option<String>,
+    }
+
+    fn order() -> Order {
+        Order {
+            id: 7,
+            items: vec!["tea".into(), "cake".into()],
+            note: None,
+        }
+    }
+
+    #[test]
+    fn rhai() {
+        let engine = rhai::Engine::new();
+        let mut scope = rhai::Scope::new();
+        scope.push("order", to_script_value(&order()).unwrap());
+        let out: Dynamic = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"order.items.push("jam"); order.id += 1; order"#,
+            )
+            .unwrap();
+        let out: Order = from_script_value(&out).unwrap();
+        assert_eq!(out.id, 8);
+        assert_eq!(out.items, ["tea", "cake", "jam"]);
+        assert!(from_script_value::<Order>(&Dynamic::from(1_i64)).is_err());
+    }
+
+    #[test]
+    fn js() {
+        let mut context = Context::default();
+        let value = to_js_value(&order(), &mut context).unwrap();
+        context
+            .register_global_property(
+                boa_engine::js_string!("order"),
+                value,
+                boa_engine::property::Attribute::all(),
+            )
+            .unwrap();
+        let out = context
+            .eval(Source::from_bytes(
+                r#"({ ...order, items: [...order.items, "jam"], note: "quick" })"#,
+            ))
+            .unwrap();
+        let out: Order = from_js_value(&out, &mut context).unwrap();
+        assert_eq!(out.note.as_deref(), Some("quick"));
+        assert_eq!(out.items.len(), 3);
+
+        let none: Option<Order> = from_js_value(&JsValue::undefined(), &mut context).unwrap();
+        assert_eq!(none, None);
+        let wrong = from_js_value::<Order>(&JsValue::from(1), &mut context).unwrap_err();
+        assert!(wrong.to_string().contains("TypeError"));
+    }
+}
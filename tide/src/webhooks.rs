@@ -0,0 +1,145 @@
+//! Checking the signature webhooks send along, over the raw bytes of the
+//! body as they were received, which scripts cannot get right once the body
+//! went through a string or `body_json`:
+//!
+//!```text
+//! if !verify_github_signature(req, env("GITHUB_WEBHOOK_SECRET")) {
+//!     throw HttpError(401, "Bad signature");
+//! }
+//! if !verify_hmac_signature(req, "x-signature", secret, "sha512") { ... }
+//!```
+//!
+//! Signatures are hex or base64, with or without a `sha256=` style prefix,
+//! and compared in constant time.
+
+use crate::request::ScriptRequest;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use rhai::{Engine, EvalAltResult, ImmutableString};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+
+/// The header GitHub signs deliveries in, with HMAC-SHA256.
+const GITHUB_HEADER: &str = "x-hub-signature-256";
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+/// The bytes of a signature sent as `value`.
+fn decode(value: &str, alg: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    let value = match value.split_once('=') {
+        Some((prefix, rest)) if prefix.eq_ignore_ascii_case(alg) => rest,
+        _ => value,
+    };
+    decode_hex(value).or_else(|| STANDARD.decode(value).ok())
+}
+
+fn verify<M: Mac + hmac::digest::KeyInit>(secret: &[u8], body: &[u8], signature: &[u8]) -> bool {
+    let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(secret)
+        .expect("HMAC takes keys of any length");
+    mac.update(body);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Whether the header `header` of `req` is the HMAC of its body with
+/// `secret`, computed with `alg`: `sha1`, `sha256`, `sha384` or `sha512`.
+pub(crate) fn verify_hmac_signature(
+    req: &ScriptRequest,
+    header: &str,
+    secret: &str,
+    alg: &str,
+) -> Result<bool, Box<EvalAltResult>> {
+    let alg = alg.to_ascii_lowercase();
+    let verify = match alg.as_str() {
+        "sha1" => verify::<Hmac<Sha1>>,
+        "sha256" => verify::<Hmac<Sha256>>,
+        "sha384" => verify::<Hmac<Sha384>>,
+        "sha512" => verify::<Hmac<Sha512>>,
+        _ => return Err(format!("Unknown HMAC algorithm {}", alg).into()),
+    };
+    let Some(signature) = req.header_value(header).and_then(|v| decode(v, &alg)) else {
+        return Ok(false);
+    };
+    Ok(verify(secret.as_bytes(), req.body(), &signature))
+}
+
+/// Registers `verify_hmac_signature` and `verify_github_signature`.
+pub(crate) fn register(engine: &mut Engine) {
+    engine
+        .register_result_fn(
+            "verify_hmac_signature",
+            |req: &mut ScriptRequest,
+             header: ImmutableString,
+             secret: ImmutableString,
+             alg: ImmutableString| {
+                verify_hmac_signature(req, &header, &secret, &alg)
+            },
+        )
+        .register_result_fn(
+            "verify_github_signature",
+            |req: &mut ScriptRequest, secret: ImmutableString| {
+                verify_hmac_signature(req, GITHUB_HEADER, &secret, "sha256")
+            },
+        );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rhai::Scope;
+    use tide::http::Url;
+
+    fn signed(header: &str, value: &str) -> ScriptRequest {
+        ScriptRequest::new(
+            http_types::Method::Post,
+            Url::parse("http://localhost/hook").unwrap(),
+            vec![(header.into(), value.into())],
+            b"Hello, World!".to_vec(),
+        )
+    }
+
+    #[test]
+    fn signatures() {
+        let mut engine = Engine::new();
+        crate::request::register(&mut engine);
+        register(&mut engine);
+        // The example of the GitHub documentation.
+        let github = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let check = |req: ScriptRequest, script: &str| -> bool {
+            let mut scope = Scope::new();
+            scope.push("req", req);
+            engine.eval_with_scope(&mut scope, script).unwrap()
+        };
+        let script = r#"verify_github_signature(req, "It's a Secret to Everybody")"#;
+        assert!(check(signed("X-Hub-Signature-256", github), script));
+        assert!(!check(
+            signed("X-Hub-Signature-256", &github.replace('7', "8")),
+            script
+        ));
+        assert!(!check(signed("X-Other", github), script));
+        assert!(check(
+            signed(
+                "x-signature",
+                "dXEH6g6yUJ/CESIczphLijdXC211hsIsRvQ3nIsEPhc="
+            ),
+            r#"verify_hmac_signature(req, "x-signature", "It's a Secret to Everybody", "SHA256")"#,
+        ));
+        let mut scope = Scope::new();
+        scope.push("req", signed("x-signature", github));
+        assert!(engine
+            .eval_with_scope::<bool>(
+                &mut scope,
+                r#"verify_hmac_signature(req, "x-signature", "s", "md5")"#
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,441 @@
+//! XML for rhai scripts talking to SOAP services and feeds: `xml_parse(text)`
+//! reads a document into an `XmlNode` to walk, `xml_build(node)` writes one,
+//! and `rss_feed(channel)` writes an RSS 2.0 feed.
+//!
+//!```text
+//! let doc = xml_parse(req.body);
+//! let titles = doc.find_all("channel/item").map(|item| item.child_text("title"));
+//!
+//! xml_build(#{ name: "soap:Envelope", attrs: #{ "xmlns:soap": "http://www.w3.org/2003/05/soap-envelope" },
+//!     children: [#{ name: "soap:Body", children: [#{ name: "GetPrice", children: ["shoe"] }] }] })
+//!```
+//!
+//! A node has its `name`, its `attrs` as a map and its `children`, nodes and
+//! the strings of the text between them, and `text`, all of the text inside.
+//! `attr(name)` is an attribute, `find(path)` the first element down a path
+//! of names separated by `/`, `find_all(path)` all of them and
+//! `child_text(path)` the text of the first. Names are matched as they are
+//! written, prefixes included.
+//!
+//! `xml_build` takes nodes or maps of the same `name`, `attrs` and
+//! `children`, with children a string on their own. `rss_feed` takes a map of
+//! the elements of the channel and its `items`, maps of the elements of each,
+//! named in snake case: `pub_date` for `pubDate`. Dates may be `DateTime`s.
+//!
+//!```text
+//! let feed = rss_feed(#{ title: "News", link: "https://example.com", description: "All of it",
+//!     items: posts.map(|p| #{ title: p.title, link: p.url, guid: p.url, pub_date: p.date }) });
+//! http::response(http::OK, feed).header("content-type", "application/rss+xml")
+//!```
+
+use crate::datetime::DateTime;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, ImmutableString, Map};
+
+type Out<T> = Result<T, Box<EvalAltResult>>;
+
+const DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+
+/// An element of a document.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct XmlNode {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Child>,
+}
+
+#[derive(Debug, Clone)]
+enum Child {
+    Element(XmlNode),
+    Text(String),
+}
+
+impl XmlNode {
+    fn start(e: &BytesStart<'_>) -> Result<Self, quick_xml::Error> {
+        let mut attrs = Vec::new();
+        for attr in e.attributes() {
+            let attr = attr?;
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            attrs.push((key, attr.unescape_value()?.into_owned()));
+        }
+        Ok(Self {
+            name: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+            attrs,
+            children: Vec::new(),
+        })
+    }
+
+    fn elements<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlNode> {
+        self.children.iter().filter_map(move |child| match child {
+            Child::Element(node) if node.name == name => Some(node),
+            _ => None,
+        })
+    }
+
+    /// The elements at the end of `path`, in document order.
+    fn walk(&self, path: &str) -> Vec<&XmlNode> {
+        let mut found = vec![self];
+        for step in path.split('/').filter(|step| !step.is_empty()) {
+            found = found.iter().flat_map(|node| node.elements(step)).collect();
+        }
+        found
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::new();
+        self.push_text(&mut text);
+        text
+    }
+
+    fn push_text(&self, out: &mut String) {
+        for child in &self.children {
+            match child {
+                Child::Text(s) => out.push_str(s),
+                Child::Element(node) => node.push_text(out),
+            }
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push('<');
+        out.push_str(&self.name);
+        for (key, value) in &self.attrs {
+            out.push(' ');
+            out.push_str(key);
+            out.push_str("=\"");
+            out.push_str(&escape(value));
+            out.push('"');
+        }
+        if self.children.is_empty() {
+            out.push_str("/>");
+            return;
+        }
+        out.push('>');
+        for child in &self.children {
+            match child {
+                Child::Text(s) => out.push_str(&escape(s)),
+                Child::Element(node) => node.write(out),
+            }
+        }
+        out.push_str("</");
+        out.push_str(&self.name);
+        out.push('>');
+    }
+
+    /// A node of a script, or a map of its `name`, `attrs` and `children`.
+    fn from_script(value: Dynamic) -> Out<Self> {
+        if value.is::<XmlNode>() {
+            return Ok(value.cast::<XmlNode>());
+        }
+        let Some(mut map) = value.try_cast::<Map>() else {
+            return Err("An XML element is a map of its name, attrs and children".into());
+        };
+        let name = map
+            .remove("name")
+            .and_then(|name| name.into_immutable_string().ok())
+            .filter(|name| !name.is_empty())
+            .ok_or("An XML element needs a name")?;
+        let attrs = match map.remove("attrs") {
+            Some(attrs) if attrs.is_map() => attrs
+                .cast::<Map>()
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            Some(attrs) if !attrs.is_unit() => {
+                return Err(format!("The attrs of <{}> are not a map", name).into())
+            }
+            _ => Vec::new(),
+        };
+        let children = match map.remove("children") {
+            Some(children) if children.is_array() => children.cast::<Array>(),
+            Some(child) if !child.is_unit() => vec![child],
+            _ => Vec::new(),
+        };
+        let children = children
+            .into_iter()
+            .map(|child| {
+                if child.is::<XmlNode>() || child.is_map() {
+                    Self::from_script(child).map(Child::Element)
+                } else {
+                    Ok(Child::Text(child.to_string()))
+                }
+            })
+            .collect::<Out<_>>()?;
+        Ok(Self {
+            name: name.to_string(),
+            attrs,
+            children,
+        })
+    }
+
+    /// A leaf element holding `text`.
+    fn leaf(name: String, text: String) -> Self {
+        Self {
+            name,
+            attrs: Vec::new(),
+            children: vec![Child::Text(text)],
+        }
+    }
+
+    fn get_name(&mut self) -> String {
+        self.name.clone()
+    }
+
+    fn get_attrs(&mut self) -> Map {
+        self.attrs
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), v.clone().into()))
+            .collect()
+    }
+
+    fn get_children(&mut self) -> Array {
+        self.children
+            .iter()
+            .map(|child| match child {
+                Child::Element(node) => Dynamic::from(node.clone()),
+                Child::Text(s) => s.clone().into(),
+            })
+            .collect()
+    }
+
+    fn get_text(&mut self) -> String {
+        self.text()
+    }
+
+    fn attr(&mut self, name: ImmutableString) -> Dynamic {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k.as_str() == name.as_str())
+            .map_or(Dynamic::UNIT, |(_, v)| v.clone().into())
+    }
+
+    fn find(&mut self, path: ImmutableString) -> Dynamic {
+        self.walk(&path)
+            .first()
+            .map_or(Dynamic::UNIT, |node| Dynamic::from((*node).clone()))
+    }
+
+    fn find_all(&mut self, path: ImmutableString) -> Array {
+        self.walk(&path)
+            .into_iter()
+            .map(|node| Dynamic::from(node.clone()))
+            .collect()
+    }
+
+    fn child_text(&mut self, path: ImmutableString) -> Dynamic {
+        self.walk(&path)
+            .first()
+            .map_or(Dynamic::UNIT, |node| node.text().into())
+    }
+}
+
+/// The root element of `text`.
+pub(crate) fn parse(text: &str) -> Result<XmlNode, String> {
+    let mut reader = Reader::from_str(text);
+    reader.trim_text(true);
+    let invalid = |reader: &Reader<&[u8]>, e: quick_xml::Error| {
+        format!("Invalid XML at byte {}: {}", reader.buffer_position(), e)
+    };
+    let mut open: Vec<XmlNode> = Vec::new();
+    let mut root = None;
+    loop {
+        let event = reader.read_event().map_err(|e| invalid(&reader, e))?;
+        let done = match event {
+            Event::Start(e) => {
+                open.push(XmlNode::start(&e).map_err(|e| invalid(&reader, e))?);
+                None
+            }
+            Event::Empty(e) => Some(XmlNode::start(&e).map_err(|e| invalid(&reader, e))?),
+            Event::End(_) => open.pop(),
+            Event::Text(e) => {
+                let text = e.unescape().map_err(|e| invalid(&reader, e))?;
+                if let Some(parent) = open.last_mut() {
+                    parent.children.push(Child::Text(text.into_owned()));
+                }
+                None
+            }
+            Event::CData(e) => {
+                let text = String::from_utf8_lossy(&e.into_inner()).into_owned();
+                if let Some(parent) = open.last_mut() {
+                    parent.children.push(Child::Text(text));
+                }
+                None
+            }
+            Event::Eof => break,
+            // Declarations, comments, processing instructions and doctypes.
+            _ => None,
+        };
+        if let Some(node) = done {
+            match open.last_mut() {
+                Some(parent) => parent.children.push(Child::Element(node)),
+                None if root.is_none() => root = Some(node),
+                None => return Err("XML has a single root element".into()),
+            }
+        }
+    }
+    if let Some(unclosed) = open.last() {
+        return Err(format!("<{}> is not closed", unclosed.name));
+    }
+    root.ok_or_else(|| "XML without an element".into())
+}
+
+/// `node` as a document.
+pub(crate) fn build(node: &XmlNode) -> String {
+    let mut out = String::from(DECLARATION);
+    node.write(&mut out);
+    out
+}
+
+/// The RSS 2.0 feed of `channel`, see the [module](self).
+fn rss_feed(mut channel: Map) -> Out<String> {
+    let items = match channel.remove("items") {
+        Some(items) if items.is_array() => items.cast::<Array>(),
+        Some(items) if !items.is_unit() => return Err("The items of a feed are an array".into()),
+        _ => Array::new(),
+    };
+    let mut elements = leaves(channel);
+    for item in items {
+        let Some(item) = item.try_cast::<Map>() else {
+            return Err("An item of a feed is a map".into());
+        };
+        elements.push(Child::Element(XmlNode {
+            name: "item".into(),
+            attrs: Vec::new(),
+            children: leaves(item),
+        }));
+    }
+    let rss = XmlNode {
+        name: "rss".into(),
+        attrs: vec![("version".into(), "2.0".into())],
+        children: vec![Child::Element(XmlNode {
+            name: "channel".into(),
+            attrs: Vec::new(),
+            children: elements,
+        })],
+    };
+    Ok(build(&rss))
+}
+
+/// The entries of `map` as elements holding their values, with names from
+/// snake case to camel case.
+fn leaves(map: Map) -> Vec<Child> {
+    map.into_iter()
+        .filter(|(_, value)| !value.is_unit())
+        .map(|(key, value)| {
+            let text = match value.clone().try_cast::<DateTime>() {
+                Some(date) => date.to_rfc2822(),
+                None => value.to_string(),
+            };
+            Child::Element(XmlNode::leaf(camel_case(&key), text))
+        })
+        .collect()
+}
+
+fn camel_case(name: &str) -> String {
+    let mut parts = name.split('_');
+    let mut out = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            out.extend(first.to_uppercase());
+            out.push_str(chars.as_str());
+        }
+    }
+    out
+}
+
+/// Registers `XmlNode` with its getters and methods, `xml_parse`, `xml_build`
+/// and `rss_feed`.
+pub(crate) fn register(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<XmlNode>("XmlNode")
+        .register_get("name", XmlNode::get_name)
+        .register_get("attrs", XmlNode::get_attrs)
+        .register_get("children", XmlNode::get_children)
+        .register_get("text", XmlNode::get_text)
+        .register_fn("attr", XmlNode::attr)
+        .register_fn("find", XmlNode::find)
+        .register_fn("find_all", XmlNode::find_all)
+        .register_fn("child_text", XmlNode::child_text)
+        .register_fn("to_string", |node: &mut XmlNode| {
+            let mut out = String::new();
+            node.write(&mut out);
+            out
+        })
+        .register_result_fn("xml_parse", |text: ImmutableString| -> Out<XmlNode> {
+            Ok(parse(&text)?)
+        })
+        .register_result_fn("xml_build", |node: Dynamic| -> Out<String> {
+            Ok(build(&XmlNode::from_script(node)?))
+        })
+        .register_result_fn("rss_feed", rss_feed);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+        <rss version="2.0"><channel>
+            <title>News &amp; more</title>
+            <item><title>One</title><link>https://example.com/1</link></item>
+            <item><title><![CDATA[Two <b>bold</b>]]></title><enclosure url="a.mp3"/></item>
+        </channel></rss>"#;
+
+    #[test]
+    fn parses_and_walks() {
+        let mut engine = Engine::new();
+        register(&mut engine);
+        let mut scope = rhai::Scope::new();
+        scope.push("text", FEED);
+        let titles: Array = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"xml_parse(text).find_all("channel/item").map(|i| i.child_text("title"))"#,
+            )
+            .unwrap();
+        let titles: Vec<String> = titles.into_iter().map(|t| t.cast()).collect();
+        assert_eq!(titles, ["One", "Two <b>bold</b>"]);
+        let url: String = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"xml_parse(text).find("channel/item/enclosure").attr("url")"#,
+            )
+            .unwrap();
+        assert_eq!(url, "a.mp3");
+        let title: String = engine
+            .eval_with_scope(&mut scope, r#"xml_parse(text).find("channel/title").text"#)
+            .unwrap();
+        assert_eq!(title, "News & more");
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<a/><b/>").is_err());
+    }
+
+    #[test]
+    fn builds() {
+        let mut engine = Engine::new();
+        register(&mut engine);
+        let built: String = engine
+            .eval(r#"xml_build(#{ name: "a", attrs: #{ href: "?x=1&y=2" }, children: ["<hi>", #{ name: "br" }] })"#)
+            .unwrap();
+        assert_eq!(
+            built,
+            r#"<?xml version="1.0" encoding="UTF-8"?><a href="?x=1&amp;y=2">&lt;hi&gt;<br/></a>"#
+        );
+        let again = build(&parse(&built).unwrap());
+        assert_eq!(again, built);
+        let feed: String = engine
+            .eval(r#"rss_feed(#{ title: "News", items: [#{ title: "One", pub_date: "Mon, 02 Jan 2023 00:00:00 +0000" }] })"#)
+            .unwrap();
+        assert!(
+            feed.contains("<channel><title>News</title><item><pubDate>Mon, 02 Jan 2023"),
+            "{}",
+            feed
+        );
+        assert!(engine
+            .eval::<String>(r#"xml_build(#{ attrs: #{} })"#)
+            .is_err());
+    }
+}